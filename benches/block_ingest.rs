@@ -0,0 +1,90 @@
+//! Measures how fast blocks can be stored in the database.
+//!
+//! Run with `cargo bench --bench block_ingest`.
+
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use stonenetd::{
+	common::IdType,
+	db::Database,
+	test::*,
+};
+
+
+const BLOCK_COUNT: usize = 256;
+const BLOCK_SIZE: usize = 0x40000; // 256 KiB
+
+
+fn generate_blocks() -> Vec<(IdType, Vec<u8>)> {
+	let mut rng = initialize_rng();
+	(0..BLOCK_COUNT)
+		.map(|_| {
+			let mut data = vec![0u8; BLOCK_SIZE];
+			rng.fill_bytes(&mut data);
+			(IdType::hash(&data), data)
+		})
+		.collect()
+}
+
+fn report(name: &str, bytes: usize, elapsed: Duration) {
+	let mib = bytes as f64 / (1024.0 * 1024.0);
+	println!(
+		"{:<24} {:>8.1} MiB in {:>8.3}s = {:>8.1} MiB/s",
+		name,
+		mib,
+		elapsed.as_secs_f64(),
+		mib / elapsed.as_secs_f64()
+	);
+}
+
+async fn bench_individually(db: &Database, blocks: &[(IdType, Vec<u8>)]) -> Duration {
+	// The blocks are copied beforehand, so that only storing them is measured
+	let blocks = blocks.to_vec();
+	let started = Instant::now();
+	for (hash, data) in blocks {
+		db.perform(move |mut c| c.store_block(0, &hash, &data))
+			.await
+			.unwrap();
+	}
+	started.elapsed()
+}
+
+async fn bench_batched(db: &Database, blocks: &[(IdType, Vec<u8>)]) -> Duration {
+	let batches: Vec<Vec<_>> = blocks.chunks(16).map(|b| b.to_vec()).collect();
+	let started = Instant::now();
+	for batch in batches {
+		db.perform(move |mut c| c.store_blocks(batch.iter().map(|(h, d)| (h, d.as_slice()))))
+			.await
+			.unwrap();
+	}
+	started.elapsed()
+}
+
+async fn bench_create_file(db: &Database, data: &[u8]) -> Duration {
+	let started = Instant::now();
+	let tx = db.transaction().await.unwrap();
+	// A mime type that isn't compressed, so that only the ingest itself is measured
+	tx.create_file2("video/mp4", data)
+		.await
+		.unwrap();
+	tx.commit().await.unwrap();
+	started.elapsed()
+}
+
+#[tokio::main]
+async fn main() {
+	let blocks = generate_blocks();
+	let total = BLOCK_COUNT * BLOCK_SIZE;
+
+	let db = load_database("bench-individually").await;
+	report("individual inserts", total, bench_individually(&db, &blocks).await);
+
+	let db = load_database("bench-batched").await;
+	report("batched inserts", total, bench_batched(&db, &blocks).await);
+
+	let db = load_database("bench-create-file").await;
+	let mut data = vec![0u8; total];
+	initialize_rng().fill_bytes(&mut data);
+	report("create_file", total, bench_create_file(&db, &data).await);
+}
@@ -12,6 +12,10 @@ DATA_FILES_PATH="$PREFIX/share/stonenet"
 LOGFILE="$PREFIX/var/log/stonenet.log"
 # Build & install the desktop app as well.
 #USE_DESKTOP_APP=1
+# Leave the web interface out of the build, for relay or bootstrap nodes. Such a
+# node is only configured through its config file, as it has no API either.
+# This makes the binary a lot smaller.
+#WITHOUT_WEB_INTERFACE=1
 # If your system is using Systemd, just uncomment the following line to enable
 # the Systemd service:
 #SYSTEMD_PATH=/lib/systemd/system
@@ -24,7 +28,11 @@ LOGFILE="$PREFIX/var/log/stonenet.log"
 ##########################################
 
 set -e
-cargo build --release
+if [ -n "$WITHOUT_WEB_INTERFACE" ]; then
+    cargo build --release --no-default-features
+else
+    cargo build --release
+fi
 if -n "$USE_DESKTOP_APP"; then
     cargo build -p stonenet-desktop --release
 fi
@@ -33,8 +41,11 @@ if -n "$USE_DESKTOP_APP"; then
     install target/release/stonenet-desktop "$EXECUTABLE_PATH"
 fi
 install conf/base.toml "$CONFIG_PATH/config.toml"
-install -t static "$DATA_FILES_PATH"
-install -t templates "$DATA_FILES_PATH"
+if [ -z "$WITHOUT_WEB_INTERFACE" ]; then
+    install -t static "$DATA_FILES_PATH"
+    install -t templates "$DATA_FILES_PATH"
+    install -t locales "$DATA_FILES_PATH"
+fi
 # Install systemd service by default
 if -n "$SYSTEMD_PATH"; then
     install assets/generic/systemd/stonenetd.service "$SYSTEMD_PATH"
@@ -23,6 +23,7 @@ class Stonenet < Formula
     system "mkdir", "-p", "#{prefix}/share/stonenet"
     system "cp", "-r", "static", "#{prefix}/share/stonenet"
     system "cp", "-r", "templates", "#{prefix}/share/stonenet"
+    system "cp", "-r", "locales", "#{prefix}/share/stonenet"
     system "mkdir", "-p", "#{prefix}/var/lib/stonenet"
     system "mkdir", "-p", "#{prefix}/var/log"
   end
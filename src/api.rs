@@ -1,42 +1,100 @@
 // FIXME: Remove when going stable:
 #![allow(deprecated)]
 
+mod block_list;
+pub mod bookmark;
+mod delegation;
+mod delete;
+pub mod direct_message;
+mod domain_verification;
+pub mod draft;
+mod edit;
+mod follower;
+mod idempotency;
+mod key_rotation;
+pub mod mention;
+mod mnemonic;
+mod poll;
+mod reaction;
+mod signed_message;
+mod user_archive;
+pub mod vanity;
+
+
 use std::{
 	collections::HashMap,
+	str::FromStr,
 	sync::Arc,
 	time::{SystemTime, UNIX_EPOCH},
 };
 
 use chrono::Utc;
 use log::*;
-use rand::rngs::OsRng;
-use sea_orm::{prelude::*, NotSet, Set};
+use sea_orm::{prelude::*, NotSet, QueryOrder, Set};
 use serde::Serialize;
-use tokio::{spawn, sync::mpsc};
+use tokio::{
+	spawn,
+	sync::{broadcast, mpsc},
+};
 use tokio_stream::wrappers::ReceiverStream;
 
 use super::{
 	common::*,
 	core::*,
-	db::{self, PersistenceHandle},
+	db::{
+		self,
+		backlog::QueueBacklogs,
+		block_list::BlockTarget,
+		health::DatabaseStatus,
+		journal::{self, JournalAction},
+		local_user,
+		search::SearchHit,
+		tag, PersistenceHandle, SyncDepth,
+	},
 	identity::*,
-	net::{actor::ActorNode, binserde, overlay::OverlayNode},
+	net::{
+		actor::{download::DownloadInfo, status::ActorNetworkStatus, ActorNode},
+		banlist::BanTarget,
+		binserde,
+		bucket::{BucketInfo, RoutingTableSummary},
+		event::Event,
+		load::LoadStats,
+		overlay::{
+			archiver::ArchiveStats,
+			availability::ActorAvailability,
+			telemetry::{TelemetryReport, TelemetryStats},
+			OverlayNode,
+		},
+		rate_limit::RateLimitStats,
+		sstp::{RelayStats, SessionInfo},
+		stats::NetworkStats,
+		MemoryLimits,
+	},
 };
 use crate::{
 	compression::decompress,
 	db::{decrypt_block, Database},
 	entity::*,
+	error_log::{self, LoggedError},
+	media::{self, MediaMetadata},
 	serde_limit::LimString,
+	task::TaskInfo,
+	util,
 	web::{
 		self,
 		consolidated_feed::{
 			load_next_unconsolidated_activity_pub_objects, load_next_unconsolidated_objects,
 		},
-		info::{ObjectInfo, ProfileObjectInfo},
+		info::{FeedCursor, FeedPage, ObjectInfo, ProfileObjectInfo},
 	},
 };
 
 
+/// The maximum number of downloaded blocks that are held in memory while a
+/// file is being streamed, because they came in before it was their turn.
+const STREAM_REORDER_LIMIT: usize = 16;
+
+
 #[derive(Clone)]
 pub struct Api {
 	pub node: Arc<OverlayNode>,
@@ -49,25 +107,123 @@ pub struct OtherObjectInfo {
 	pub content: String,
 }
 
+/// The traffic that has been exchanged with another node.
+#[derive(Debug, Serialize)]
+pub struct PeerTrafficInfo {
+	pub node_address: String,
+	pub bytes_in: u64,
+	pub bytes_out: u64,
+}
+
 pub enum PossibleFileStream {
 	None,
 	//Full(FileData),
 	Stream((String, CompressionType, ReceiverStream<db::Result<Vec<u8>>>)),
+	/// A part of the file, with its first and last byte, and the size of the
+	/// whole file.
+	Partial((String, (u64, u64), u64, ReceiverStream<db::Result<Vec<u8>>>)),
+	/// The range that was asked for lies outside of the file, which has the
+	/// given size.
+	Unsatisfiable(u64),
+}
+
+/// A range of bytes of a file, as it can be asked for with a `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+	/// From the first byte up to and including the last byte, or up to the end
+	/// of the file.
+	From(u64, Option<u64>),
+	/// The given number of bytes at the end of the file.
+	Suffix(u64),
+}
+
+
+impl ByteRange {
+	/// Parses the value of a `Range` header. Only a single range is supported,
+	/// so `None` is returned for multiple ranges as well.
+	pub fn parse(value: &str) -> Option<Self> {
+		let spec = value.trim().strip_prefix("bytes=")?;
+		if spec.contains(',') {
+			return None;
+		}
+		let (first, last) = spec.split_once('-')?;
+		let (first, last) = (first.trim(), last.trim());
+		if first.is_empty() {
+			Some(Self::Suffix(last.parse().ok()?))
+		} else {
+			let first = first.parse().ok()?;
+			let last = if last.is_empty() {
+				None
+			} else {
+				Some(last.parse().ok()?)
+			};
+			if matches!(last, Some(l) if l < first) {
+				return None;
+			}
+			Some(Self::From(first, last))
+		}
+	}
+
+	/// Finds the first and last byte of the range, in a file of the given size.
+	/// Returns `None` if the range lies outside of the file.
+	pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+		match *self {
+			Self::From(first, last) if first < size =>
+				Some((first, last.unwrap_or(u64::MAX).min(size - 1))),
+			Self::Suffix(length) if length > 0 && size > 0 =>
+				Some((size.saturating_sub(length), size - 1)),
+			_ => None,
+		}
+	}
 }
 
 
 impl Api {
+	/// Bans the given node or subnet, and remembers the ban in the database. If
+	/// it is banned already, the existing ban is returned.
+	pub async fn ban_peer(&self, target: &BanTarget, reason: &str) -> db::Result<peer_ban::Model> {
+		if let Some(record) = self.find_peer_ban(target).await? {
+			return Ok(record);
+		}
+
+		let (node_address, subnet) = match target {
+			BanTarget::Node(address) => (Some(address.clone()), None),
+			BanTarget::Subnet(subnet) => (None, Some(subnet.to_string())),
+		};
+		let model = peer_ban::ActiveModel {
+			id: NotSet,
+			node_address: Set(node_address),
+			subnet: Set(subnet),
+			reason: Set(reason.to_string()),
+			created: Set(Utc::now().timestamp_millis()),
+		};
+		let record = model.insert(self.db.inner()).await?;
+		journal::record(
+			&self.db,
+			JournalAction::Banned,
+			&target.to_string(),
+			None,
+			Some(reason),
+		)
+		.await?;
+
+		self.node.ban(target).await;
+		Ok(record)
+	}
+
 	pub async fn close(self) { self.node.close().await; }
 
 	fn compose_profile_object(
-		private_key: &ActorPrivateKeyV1, sequence: u64, name: &str, avatar_hash: &Option<IdType>,
-		wallpaper_hash: &Option<IdType>, description_hash: &Option<IdType>,
-	) -> (IdType, BlogchainObject) {
+		signer: &dyn Signer, sequence: u64, name: &str, avatar_hash: &Option<IdType>,
+		wallpaper_hash: &Option<IdType>, description_hash: &Option<IdType>, followers_only: bool,
+		created: u64,
+	) -> db::Result<(IdType, BlogchainObject)> {
 		let profile = ProfileObject {
 			name: name.into(),
 			avatar: avatar_hash.clone(),
 			wallpaper: wallpaper_hash.clone(),
 			description: description_hash.clone(),
+			followers_only,
 		};
 
 		// Sign the profile object and construct an object out of it
@@ -75,29 +231,64 @@ impl Api {
 		let sign_data = ObjectSignData {
 			sequence,
 			previous_hash: IdType::default(),
-			created: SystemTime::now()
-				.duration_since(UNIX_EPOCH)
-				.unwrap()
-				.as_millis() as u64,
+			created,
 			payload: &payload,
 		};
 
-		let signature = private_key.sign(&binserde::serialize(&sign_data).unwrap());
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		let signature = util::block_in_place(|| signer.sign(&raw_sign_data))?;
 		let object_hash = signature.hash();
 		let object = BlogchainObject {
 			signature,
 			previous_hash: IdType::default(),
-			sequence: 0,
+			sequence,
 			created: sign_data.created,
 			payload,
+			delegation: None,
 		};
 
-		(object_hash, object)
+		Ok((object_hash, object))
 	}
 
 	pub async fn create_identity(
 		&self, label: &str, name: &str, avatar: Option<&FileData>, wallpaper: Option<&FileData>,
 		description: Option<&FileData>,
+	) -> db::Result<(ActorAddress, ActorInfo)> {
+		let (private_key, seed) = mnemonic::generate_identity_key();
+		self.create_identity_with_signer(
+			label,
+			name,
+			avatar,
+			wallpaper,
+			description,
+			&private_key,
+			Some(&seed),
+			current_time_millis(),
+		)
+		.await
+	}
+
+	/// Creates an identity with a key that is held by the external signer.
+	pub async fn create_external_identity(
+		&self, label: &str, name: &str, public_key: &ActorPublicKeyV1,
+	) -> db::Result<(ActorAddress, ActorInfo)> {
+		let signer = self.db.keyring().external_signer(public_key)?;
+		self.create_identity_with_signer(
+			label,
+			name,
+			None,
+			None,
+			None,
+			&signer,
+			None,
+			current_time_millis(),
+		)
+		.await
+	}
+
+	async fn create_identity_with_signer(
+		&self, label: &str, name: &str, avatar: Option<&FileData>, wallpaper: Option<&FileData>,
+		description: Option<&FileData>, signer: &dyn Signer, seed: Option<&[u8]>, created: u64,
 	) -> db::Result<(ActorAddress, ActorInfo)> {
 		let tx = self.db.transaction().await?;
 		// Prepare profile files
@@ -117,15 +308,16 @@ impl Api {
 			None
 		};
 
-		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut OsRng);
 		let (object_hash, object) = Self::compose_profile_object(
-			&private_key,
+			signer,
 			0,
 			name,
 			&avatar_hash,
 			&wallpaper_hash,
 			&description_hash,
-		);
+			false,
+			created,
+		)?;
 		/*let profile = ProfileObject {
 			name: name.to_string(),
 			avatar: avatar_hash.clone(),
@@ -158,7 +350,7 @@ impl Api {
 		// Generate an actor ID with our new object hash.
 		let actor_info = ActorInfo::V1(ActorInfoV1 {
 			flags: 0,
-			public_key: private_key.public(),
+			public_key: signer.public(),
 			first_object: object_hash.clone(),
 			actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
 		});
@@ -172,9 +364,10 @@ impl Api {
 				label,
 				&actor_address,
 				&actor_info.public_key,
-				&private_key,
+				signer,
 				false,
 				&object_hash,
+				seed,
 			)
 			.await?;
 		tx.store_profile(
@@ -188,6 +381,15 @@ impl Api {
 			avatar_hash,
 			wallpaper_hash,
 			description_hash,
+			false,
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::CreatedIdentity,
+			&actor_address.to_string(),
+			Some(&object_hash),
+			Some(label),
 		)
 		.await?;
 		tx.commit().await?;
@@ -195,7 +397,7 @@ impl Api {
 	}
 
 	pub async fn create_share(
-		&self, identity: &ActorAddress, private_key: &ActorPrivateKeyV1, share: &ShareObject,
+		&self, identity: &ActorAddress, signer: &dyn Signer, share: &ShareObject,
 	) -> db::Result<(i64, IdType, BlogchainObject)> {
 		let tx = self.db.transaction().await?;
 
@@ -208,6 +410,8 @@ impl Api {
 
 		let next_object_sequence = tx.find_next_object_sequence(identity_record.id).await?;
 		let object_payload = ObjectPayload::Share(share.clone());
+		let delegation =
+			delegation::load_delegation_for(&tx, identity_record.id, &object_payload).await?;
 		let created = Utc::now().timestamp_millis();
 
 		// TODO: Create a seperate db function that merely finds the hash of the object,
@@ -224,8 +428,8 @@ impl Api {
 			&previous_hash,
 			created as _,
 			&object_payload,
-			&private_key,
-		);
+			signer,
+		)?;
 
 		// Insert the object record
 		// TODO: Move this into module `db`:
@@ -241,6 +445,7 @@ impl Api {
 			found: Set(created),
 			r#type: Set(OBJECT_TYPE_SHARE),
 			published_on_fediverse: Set(false),
+			delegation: Set(delegation.as_ref().map(|d| d.to_bytes())),
 		})
 		.exec(tx.inner())
 		.await?;
@@ -254,6 +459,14 @@ impl Api {
 		})
 		.exec(tx.inner())
 		.await?;
+		journal::record(
+			&tx,
+			JournalAction::PublishedShare,
+			&identity.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
 
 		tx.commit().await?;
 
@@ -263,17 +476,45 @@ impl Api {
 			previous_hash,
 			created: created as _,
 			payload: ObjectPayload::Share(share.clone()),
+			delegation,
 		};
 		Ok((result.last_insert_id, hash, object))
 	}
 
+	/// Estimates how many nodes currently take part in the network of the
+	/// given actor.
+	pub async fn estimate_actor_availability(&self, address: &ActorAddress) -> ActorAvailability {
+		self.node.estimate_actor_availability(address).await
+	}
+
+	/// The state of every actor network that we are in.
+	pub async fn joined_actor_networks(
+		&self,
+	) -> db::Result<Vec<(ActorAddress, ActorNetworkStatus)>> {
+		let mut networks = Vec::new();
+		for actor_node in self.node.actor_nodes().await {
+			let status = actor_node.network_status().await?;
+			networks.push((actor_node.actor_address().clone(), status));
+		}
+		Ok(networks)
+	}
+
+	/// The state of the network of the given actor, or `None` if we are not in
+	/// it.
+	pub async fn actor_network_status(
+		&self, address: &ActorAddress,
+	) -> db::Result<Option<ActorNetworkStatus>> {
+		match self.node.get_actor_node(&address.as_id()).await {
+			Some(actor_node) => Ok(Some(actor_node.network_status().await?)),
+			None => Ok(None),
+		}
+	}
+
 	pub async fn find_block(
 		&self, actor_node_opt: Option<&Arc<ActorNode>>, hash: &IdType,
 	) -> db::Result<Option<Vec<u8>>> {
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db.connect_old()?;
-			c.fetch_block(hash)
-		})?;
+		let hash2 = hash.clone();
+		let result = self.db.perform(move |c| c.fetch_block(&hash2)).await?;
 
 		Ok(match result {
 			Some(b) => Some(b),
@@ -354,25 +595,43 @@ impl Api {
 		}))
 	}
 
+	/// The label and address of the identity that is published with.
+	pub async fn active_identity(&self) -> db::Result<Option<(String, ActorAddress)>> {
+		self.db.load_active_identity().await
+	}
+
+	/// The address and private key of the identity that is published with.
+	pub async fn load_active_identity_key(
+		&self,
+	) -> db::Result<Option<(ActorAddress, Box<dyn Signer>)>> {
+		self.db.load_active_identity_key().await
+	}
+
+	/// Switches to the identity with the given label, and returns its address.
+	/// Returns None if we don't have an identity with that label.
+	pub async fn select_identity(&self, label: &str) -> db::Result<Option<ActorAddress>> {
+		self.db.set_active_identity(label).await
+	}
+
 	#[allow(unused)]
-	pub fn fetch_my_identity(
+	pub async fn fetch_my_identity(
 		&self, address: &ActorAddress,
-	) -> db::Result<Option<(String, ActorPrivateKeyV1)>> {
-		let this = self.clone();
-		tokio::task::block_in_place(|| {
-			let c = this.db.connect_old()?;
-			c.fetch_my_identity(address)
-		})
+	) -> db::Result<Option<(String, Box<dyn Signer>)>> {
+		let address = address.clone();
+		self.db
+			.perform(move |c| c.fetch_my_identity(&address))
+			.await
 	}
 
-	pub fn fetch_my_identities(
+	/// Fetches the identities of the current local user, or of the owner of the
+	/// node.
+	pub async fn fetch_my_identities(
 		&self,
-	) -> db::Result<Vec<(String, ActorAddress, IdType, String, ActorPrivateKeyV1)>> {
-		let this = self.clone();
-		tokio::task::block_in_place(|| {
-			let c = this.db.connect_old()?;
-			c.fetch_my_identities()
-		})
+	) -> db::Result<Vec<(String, ActorAddress, IdType, String, ActorPublicKeyV1)>> {
+		let user_id = local_user::current_user();
+		self.db
+			.perform(move |c| c.fetch_user_identities(user_id))
+			.await
 	}
 
 	pub async fn find_profile_info(
@@ -392,11 +651,17 @@ impl Api {
 		Ok(None)
 	}
 
-	pub async fn follow(&self, address: &ActorAddress, join_network: bool) -> db::Result<bool> {
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db.connect_old()?;
-			c.fetch_identity(address)
-		})?;
+	/// Follows an actor, synchronizing only as much of its history as the
+	/// given sync depth allows. The follow is made for the current local user,
+	/// or for the owner of the node.
+	pub async fn follow(
+		&self, address: &ActorAddress, join_network: bool, sync_depth: SyncDepth,
+	) -> db::Result<bool> {
+		let address2 = address.clone();
+		let result = self
+			.db
+			.perform(move |c| c.fetch_identity(&address2))
+			.await?;
 		let actor_info = match result {
 			Some(pk) => pk,
 			None => match self.node.find_actor(&address, 100, false).await {
@@ -405,13 +670,45 @@ impl Api {
 			},
 		};
 
-		let _private_key = tokio::task::block_in_place(|| {
-			let mut c = self.db.connect_old()?;
-			c.follow(address, &actor_info)
-		})?;
+		// The node may already follow the actor for someone else
+		let address2 = address.clone();
+		let is_followed = self
+			.db
+			.perform(move |c| c.is_following(&address2))
+			.await?;
+		if !is_followed {
+			let address2 = address.clone();
+			let actor_info2 = actor_info.clone();
+			self.db
+				.perform(move |mut c| c.follow(&address2, &actor_info2, &sync_depth))
+				.await?;
+		}
+		let actor_id = actor::Entity::find()
+			.filter(actor::Column::Address.eq(address))
+			.one(self.db.inner())
+			.await?
+			.expect("followed actor doesn't exist")
+			.id;
+		match local_user::current_user() {
+			Some(user_id) => {
+				if !is_followed {
+					self.db.set_follow_user_only(actor_id, true).await?;
+				}
+				self.db.store_user_follow(user_id, actor_id).await?;
+			}
+			None => self.db.set_follow_user_only(actor_id, false).await?,
+		}
+		journal::record(
+			&self.db,
+			JournalAction::Followed,
+			&address.to_string(),
+			None,
+			None,
+		)
+		.await?;
 
 		// Join network
-		if join_network {
+		if join_network && !is_followed {
 			let node = self.node.clone();
 			let actor_id2 = address.clone();
 			tokio::spawn(async move {
@@ -422,44 +719,391 @@ impl Api {
 		Ok(true)
 	}
 
+	/// Unfollows the actor for the current local user, or for the owner of the
+	/// node. The node keeps following it as long as anyone else does.
 	pub async fn unfollow(&self, actor_id: &ActorAddress) -> db::Result<bool> {
-		let success = tokio::task::block_in_place(|| {
-			let mut c = self.db.connect_old()?;
-			c.unfollow(actor_id)
-		})?;
+		let record = actor::Entity::find()
+			.filter(actor::Column::Address.eq(actor_id))
+			.one(self.db.inner())
+			.await?;
+		if let Some(actor) = record {
+			let still_followed = match local_user::current_user() {
+				Some(user_id) => match self.db.remove_user_follow(user_id, actor.id).await? {
+					None => return Ok(false),
+					Some(unneeded) => !unneeded,
+				},
+				None => {
+					let by_users = self.db.is_followed_by_users(actor.id).await?;
+					if by_users {
+						self.db.set_follow_user_only(actor.id, true).await?;
+					}
+					by_users
+				}
+			};
+			if still_followed {
+				journal::record(
+					&self.db,
+					JournalAction::Unfollowed,
+					&actor_id.to_string(),
+					None,
+					None,
+				)
+				.await?;
+				return Ok(true);
+			}
+		}
+
+		let actor_id2 = actor_id.clone();
+		let success = self
+			.db
+			.perform(move |mut c| c.unfollow(&actor_id2))
+			.await?;
 
 		if success {
-			self.node.drop_actor_network(&actor_id.as_id()).await;
+			journal::record(
+				&self.db,
+				JournalAction::Unfollowed,
+				&actor_id.to_string(),
+				None,
+				None,
+			)
+			.await?;
+			self.node.abandon_actor_network(actor_id).await?;
 		}
 		Ok(success)
 	}
 
-	pub fn is_following(&self, actor_id: &ActorAddress) -> db::Result<bool> {
-		tokio::task::block_in_place(|| {
-			let c = self.db.connect_old()?;
-			c.is_following(actor_id)
-		})
+	/// Lifts the ban with the given ID. Returns false if it didn't exist.
+	pub async fn unban_peer(&self, id: i64) -> db::Result<bool> {
+		let record = if let Some(r) = peer_ban::Entity::find_by_id(id).one(self.db.inner()).await? {
+			r
+		} else {
+			return Ok(false);
+		};
+		peer_ban::Entity::delete_by_id(id)
+			.exec(self.db.inner())
+			.await?;
+
+		if let Some(target) = BanTarget::from_model(&record) {
+			journal::record(
+				&self.db,
+				JournalAction::Unbanned,
+				&target.to_string(),
+				None,
+				Some(&record.reason),
+			)
+			.await?;
+			self.node.unban(&target);
+		}
+		Ok(true)
+	}
+
+	/// Reverses the action of the given journal entry by taking the opposite
+	/// action.
+	/// Returns false if the entry doesn't exist, can't be undone or has been
+	/// undone already.
+	pub async fn undo_activity(&self, id: i64) -> db::Result<bool> {
+		let entry = match self.db.find_journal_entry(id).await? {
+			Some(e) => e,
+			None => return Ok(false),
+		};
+		let action = match JournalAction::from_u8(entry.action) {
+			Some(a) => a,
+			None => return Ok(false),
+		};
+		if entry.undone.is_some() || !action.is_undoable() {
+			return Ok(false);
+		}
+
+		let success = match action {
+			JournalAction::Followed | JournalAction::Unfollowed => {
+				let address = match Address::from_str(&entry.subject) {
+					Ok(Address::Actor(a)) => a,
+					_ => return Ok(false),
+				};
+				if action == JournalAction::Followed {
+					self.unfollow(&address).await?
+				} else {
+					self.follow(&address, true, SyncDepth::default()).await?
+				}
+			}
+			JournalAction::MutedActor | JournalAction::BlockedActor => {
+				let identity = match Address::from_str(&entry.subject) {
+					Ok(Address::Actor(a)) => a,
+					_ => return Ok(false),
+				};
+				let target = match entry.detail.as_deref().map(BlockTarget::from_str) {
+					Some(Ok(t)) => t,
+					_ => return Ok(false),
+				};
+				self.unblock_actor(&identity, &target).await?
+			}
+			_ => {
+				let target = match BanTarget::from_str(&entry.subject) {
+					Ok(t) => t,
+					Err(_) => return Ok(false),
+				};
+				if action == JournalAction::Banned {
+					match self.find_peer_ban(&target).await? {
+						Some(record) => self.unban_peer(record.id).await?,
+						None => false,
+					}
+				} else {
+					let reason = entry.detail.as_deref().unwrap_or("");
+					self.ban_peer(&target, reason).await?;
+					true
+				}
+			}
+		};
+
+		if success {
+			self.db.mark_journal_entry_undone(id).await?;
+		}
+		Ok(success)
+	}
+
+	async fn find_peer_ban(&self, target: &BanTarget) -> db::Result<Option<peer_ban::Model>> {
+		let condition = match target {
+			BanTarget::Node(address) => peer_ban::Column::NodeAddress.eq(address.clone()),
+			BanTarget::Subnet(subnet) => peer_ban::Column::Subnet.eq(subnet.to_string()),
+		};
+		Ok(peer_ban::Entity::find()
+			.filter(condition)
+			.one(self.db.inner())
+			.await?)
+	}
+
+	/// Fetches the actors that the current local user follows, or that the
+	/// owner of the node follows.
+	pub async fn fetch_follow_list(&self) -> db::Result<Vec<(ActorAddress, ActorInfo)>> {
+		let user_id = local_user::current_user();
+		self.db
+			.perform(move |c| c.fetch_user_follow_list(user_id))
+			.await
+	}
+
+	pub async fn is_following(&self, actor_id: &ActorAddress) -> db::Result<bool> {
+		let actor_id = actor_id.clone();
+		let user_id = local_user::current_user();
+		self.db
+			.perform(move |c| c.is_user_following(&actor_id, user_id))
+			.await
 	}
 
 	pub async fn load_home_feed(&self, count: u64, offset: u64) -> db::Result<Vec<ObjectInfo>> {
-		// TODO: Manage tracked actors as followers with a CLI tool
-		//       Currently, because tracked actors are not stored in the DB, it
-		//       is hard to have them show up in the consolidated home feed.
-		let tracked_actors: Vec<ActorAddress> = self
-			.node
+		let tracked_actors = self.tracked_actors().await;
+		web::info::load_home_feed(&self.db, count, offset, tracked_actors.iter()).await
+	}
+
+	/// Loads the page of the home feed that comes after the cursor, or the
+	/// first page if no cursor is given.
+	pub async fn load_home_feed_page(
+		&self, count: u64, after: Option<FeedCursor>,
+	) -> db::Result<FeedPage> {
+		let tracked_actors = self.tracked_actors().await;
+		web::info::load_home_feed_page(&self.db, count, after, tracked_actors.iter()).await
+	}
+
+	// TODO: Manage tracked actors as followers with a CLI tool
+	//       Currently, because tracked actors are not stored in the DB, it
+	//       is hard to have them show up in the consolidated home feed.
+	async fn tracked_actors(&self) -> Vec<ActorAddress> {
+		self.node
 			.tracked_actors
 			.lock()
 			.await
 			.keys()
 			.map(|a| a.clone())
-			.collect();
-		web::info::load_home_feed(&self.db, count, offset, tracked_actors.iter()).await
+			.collect()
+	}
+
+	/// Searches through the text of the posts and profiles that are stored
+	/// locally, leaving out the actors that the active identity has muted or
+	/// blocked.
+	pub async fn search(
+		&self, query: &str, include_private: bool, limit: u64, offset: u64,
+	) -> db::Result<Vec<SearchHit>> {
+		let searcher = self.db.load_active_actor_id().await?;
+		self.db
+			.search(query, include_private, searcher, limit, offset)
+			.await
+	}
+
+	/// Loads the posts with the given tag that are stored locally, the newest
+	/// first.
+	pub async fn load_tagged_posts(
+		&self, tag: &str, include_private: bool, url_base: &str, limit: u64, offset: u64,
+	) -> db::Result<Vec<ObjectInfo>> {
+		let ids = self
+			.db
+			.load_tagged_posts(tag, include_private, limit, offset)
+			.await?;
+		let mut objects = Vec::with_capacity(ids.len());
+		for id in ids {
+			if let Some(object) = web::info::load_object_info2(&self.db, url_base, id).await? {
+				objects.push(object);
+			}
+		}
+		Ok(objects)
+	}
+
+	pub async fn load_peer_bans(&self) -> db::Result<Vec<peer_ban::Model>> {
+		Ok(peer_ban::Entity::find()
+			.order_by_asc(peer_ban::Column::Id)
+			.all(self.db.inner())
+			.await?)
+	}
+
+	/// Whether the database is available, and how many writes are waiting for
+	/// it if it isn't.
+	pub fn database_status(&self) -> DatabaseStatus { self.db.status() }
+
+	/// The progress of the files that are being downloaded from other nodes.
+	pub fn list_downloads(&self) -> Vec<DownloadInfo> { self.node.downloads.list() }
+
+	pub fn list_tasks(&self) -> Vec<TaskInfo> { self.node.tasks().list() }
+
+	/// How much of its quota this node uses, if it is an archive node.
+	pub fn archive_stats(&self) -> Option<ArchiveStats> { self.node.archive_stats() }
+
+	/// Whether the node is shedding load, and what it has turned down because
+	/// of it.
+	pub fn load_stats(&self) -> LoadStats { self.node.load_stats() }
+
+	/// Statistics about the lookups and exchanges this node has done on the
+	/// network.
+	pub fn network_stats(&self) -> NetworkStats { self.node.network_stats() }
+
+	/// Receives the events that happen on the node from now on.
+	pub fn subscribe_events(&self) -> broadcast::Receiver<Event> { self.node.events.subscribe() }
+
+	/// The traffic of this node per hour, over the given number of hours up
+	/// until now. Hours without any recorded traffic are left out.
+	pub async fn network_stats_history(
+		&self, hours: u32,
+	) -> db::Result<Vec<network_stats::Model>> {
+		let now = Utc::now().timestamp();
+		let since = now - now % 3600 - hours.saturating_sub(1) as i64 * 3600;
+		self.db.load_network_stats(since).await
+	}
+
+	/// The nodes with which the most traffic has been exchanged over the given
+	/// number of days up until now, the busiest first.
+	pub async fn top_peers_by_traffic(
+		&self, days: u32, limit: u64,
+	) -> db::Result<Vec<PeerTrafficInfo>> {
+		let now = Utc::now().timestamp();
+		let since = now - now % 86400 - days.saturating_sub(1) as i64 * 86400;
+		let peers = self.db.load_top_peers(since, limit).await?;
+		Ok(peers
+			.into_iter()
+			.map(|(node_address, traffic)| PeerTrafficInfo {
+				node_address: node_address.to_string(),
+				bytes_in: traffic.bytes_in,
+				bytes_out: traffic.bytes_out,
+			})
+			.collect())
+	}
+
+	pub fn rate_limit_stats(&self) -> RateLimitStats { self.node.rate_limit_stats() }
+
+	/// The limits on the caches and buffers that the node runs with.
+	pub async fn memory_limits(&self) -> MemoryLimits { self.node.memory_limits().await }
+
+	/// How much traffic this node relays for other nodes that can't reach each
+	/// other directly.
+	pub async fn relay_stats(&self) -> RelayStats { self.node.relay_stats().await }
+
+	/// The number of items that are waiting in the background queues.
+	pub async fn queue_backlogs(&self) -> db::Result<QueueBacklogs> {
+		self.db.load_queue_backlogs().await
+	}
+
+	/// The errors that have been logged most recently, the newest first.
+	pub fn recent_errors(&self, limit: usize) -> Vec<LoggedError> {
+		error_log::recent_errors(limit)
+	}
+
+	pub async fn routing_table_summary(&self) -> RoutingTableSummary {
+		self.node.routing_table_summary().await
+	}
+
+	pub async fn sessions(&self) -> Vec<SessionInfo> { self.node.sessions().await }
+
+	/// The number of bytes of block data that are stored.
+	pub async fn storage_usage(&self) -> db::Result<u64> { self.db.total_block_size().await }
+
+	pub async fn routing_table(&self) -> Vec<BucketInfo> { self.node.routing_table().await }
+
+	/// The telemetry report that this node sends, or would send if telemetry
+	/// were enabled.
+	pub async fn telemetry_preview(&self) -> TelemetryReport { self.node.telemetry_preview().await }
+
+	pub fn telemetry_stats(&self) -> Option<TelemetryStats> { self.node.telemetry_stats() }
+
+	/// Loads the MIME type and the data of the thumbnail of the image. The
+	/// thumbnail is generated if it hasn't been yet, which needs the whole image
+	/// to be loaded. Returns `None` if the image is small enough to be shown as
+	/// it is, or if it isn't an image at all.
+	pub async fn load_file_thumbnail(
+		&self, actor_address: &ActorAddress, file_hash: &IdType,
+	) -> db::Result<Option<(String, Vec<u8>)>> {
+		if let Some(thumbnail) = self.db.load_file_thumbnail(file_hash).await? {
+			return Ok(Some(thumbnail));
+		}
+		let metadata = self.db.load_file_metadata(file_hash).await?;
+		if let Some(MediaMetadata {
+			width: Some(width),
+			height: Some(height),
+			..
+		}) = &metadata
+		{
+			if *width <= media::THUMBNAIL_SIZE && *height <= media::THUMBNAIL_SIZE {
+				return Ok(None);
+			}
+		}
+
+		let actor_node = self.node.get_actor_node_or_lurker(actor_address).await;
+		match self.find_file(actor_node.as_ref(), file_hash).await? {
+			Some(file) if media::has_thumbnail(file.mime_type.as_str()) => {}
+			_ => return Ok(None),
+		}
+		let file = match self.find_file_data(actor_node.as_ref(), file_hash).await? {
+			Some(f) => f,
+			None => return Ok(None),
+		};
+		let mime_type = file.mime_type.as_str();
+
+		// The metadata of images from other nodes is extracted here as well, so
+		// that the image doesn't have to be loaded again to find its dimensions
+		let (thumbnail, new_metadata) = util::block_in_place(|| {
+			(
+				media::generate_thumbnail(mime_type, &file.data),
+				if metadata.is_none() {
+					media::extract_metadata(mime_type, &file.data)
+				} else {
+					None
+				},
+			)
+		});
+		let tx = self.db.transaction().await?;
+		if let Some(thumbnail) = &thumbnail {
+			tx.store_file_thumbnail(file_hash, thumbnail).await?;
+		}
+		if let Some(metadata) = &new_metadata {
+			tx.store_file_metadata(file_hash, mime_type, metadata)
+				.await?;
+		}
+		tx.commit().await?;
+		Ok(thumbnail.map(|t| (t.mime_type.to_string(), t.data)))
 	}
 
 	// Like `load_file`, but return an async stream that catches all the blocks that
-	// are being loaded in another thread.
+	// are being loaded in another thread. If a range is given, only the blocks that
+	// it covers are loaded. Ranges of compressed files, or of files of which the
+	// size isn't known yet, are ignored, in which case the whole file is streamed.
 	pub async fn stream_file(
-		&self, actor_address: ActorAddress, file_hash: IdType,
+		&self, actor_address: ActorAddress, file_hash: IdType, range: Option<ByteRange>,
 	) -> db::Result<PossibleFileStream> {
 		let db = self.db.clone();
 		let r: Option<(i64, File)> = db.find_file(&file_hash).await?;
@@ -477,6 +1121,26 @@ impl Api {
 				}
 			};
 
+			// The blocks to load, the number of bytes to skip in the first one, and the
+			// number of bytes to send in total
+			let mut blocks = 0..file.blocks.len();
+			let mut skip = 0;
+			let mut remaining = u64::MAX;
+			let mut partial = None;
+			if let (Some(range), CompressionType::None) = (range, compression_type) {
+				let block_count = file.blocks.len() as u32;
+				if let Some((block_size, size)) = db.find_file_size(file_id, block_count).await? {
+					let (first, last) = match range.resolve(size) {
+						Some(r) => r,
+						None => return Ok(PossibleFileStream::Unsatisfiable(size)),
+					};
+					blocks = (first / block_size) as usize..(last / block_size) as usize + 1;
+					skip = (first % block_size) as usize;
+					remaining = last - first + 1;
+					partial = Some(((first, last), size));
+				}
+			}
+
 			/*if file.compression_type != CompressionType::None as u8 {
 				// TODO: Make sure that the file meta data isn't searched over the network
 				// twice.
@@ -493,73 +1157,166 @@ impl Api {
 
 			let (tx, rx) = mpsc::channel(1);
 			// Asynchronously start loading the blocks one by one, from disk preferably,
-			// from the network otherwise
+			// from the network otherwise. Every block is verified against the block list
+			// of the file before it is sent, so that the file can be served while its
+			// blocks are still coming in.
 			let node: Arc<OverlayNode> = self.node.clone();
 			spawn(async move {
-				let mut actor_node: Option<Arc<ActorNode>> = None;
-				let mut loaded_actor_node = false;
-				for i in 0..file.blocks.len() {
+				// Download all blocks that we don't have yet at once, from multiple peers,
+				// in order so that they can be played back as they come in
+				let mut missing_blocks = Vec::new();
+				for i in blocks.clone() {
 					let block_hash = &file.blocks[i];
-					match db.perform(|c| c.fetch_block(block_hash)) {
-						Ok(block_result) => match block_result {
-							Some(mut block) => {
-								db::decrypt_block(i as _, &file.plain_hash, &mut block);
-								if let Err(_) = tx.send(Ok(block)).await {
-									error!("Unable to send block on stream-file channel.");
-								}
+					match db.has_block(block_hash).await {
+						Ok(true) => {}
+						Ok(false) => missing_blocks.push((i, block_hash.clone())),
+						Err(e) => {
+							if let Err(_) = tx.send(Err(e)).await {
+								error!("Unable to send error on stream-file channel.");
 							}
-							None => {
-								if !loaded_actor_node {
-									actor_node =
-										node.get_actor_node_or_lurker(&actor_address).await;
-									loaded_actor_node = true;
-								}
+							return;
+						}
+					}
+				}
+				let actor_node = node.get_actor_node_or_lurker(&actor_address).await;
+				let mut downloads = None;
+				if missing_blocks.len() > 0 {
+					if let Some(n) = &actor_node {
+						downloads =
+							Some(n.download_blocks(file_id, &file_hash, missing_blocks, true));
+					}
+				}
+				// The downloaded blocks that have come in before it was their turn
+				let mut downloaded = HashMap::new();
 
-								if let Some(n) = &actor_node {
-									// Find the block on the network, and store it if we have found
-									// it
-									if let Some(r) = n.find_block(block_hash).await {
-										if let Err(e) = db.perform(|mut c| {
-											c.store_block(file_id, block_hash, &r.data)
-										}) {
-											if let Err(_) = tx.send(Err(e)).await {
-												error!(
-													"Unable to send error on stream-file channel."
-												);
-											}
-										}
-										let mut block = r.data;
-										db::decrypt_block(i as _, &file.plain_hash, &mut block);
-										if let Err(_) = tx.send(Ok(block.into())).await {
-											error!("Unable to send block on stream-file channel.");
-										}
-										continue;
+				for i in blocks {
+					let block_hash = &file.blocks[i];
+					let block_result = match downloaded.remove(&i) {
+						Some(data) => Ok(Some(data)),
+						None => {
+							let block_hash = block_hash.clone();
+							db.perform(move |c| c.fetch_block(&block_hash)).await
+						}
+					};
+					let found = match block_result {
+						Ok(Some(data)) if &IdType::hash(&data) == block_hash => Some(data),
+						Ok(Some(_)) => {
+							warn!(
+								"Stored block {} of file {} is corrupt, finding it again.",
+								block_hash, &file_hash
+							);
+							match &actor_node {
+								Some(n) => n.find_block(block_hash).await.map(|r| r.data.into()),
+								None => None,
+							}
+						}
+						Ok(None) => {
+							// Wait for the block to be downloaded
+							let mut found = None;
+							if let Some(rx) = &mut downloads {
+								while let Some((index, data)) = rx.recv().await {
+									if index == i {
+										found = Some(data);
+										break;
+									}
+									// Downloaded blocks are stored as well, so the ones that don't
+									// fit are loaded from disk when it is their turn
+									if downloaded.len() < STREAM_REORDER_LIMIT {
+										downloaded.insert(index, data);
 									}
 								}
 							}
-						},
-						Err(e) =>
+							found
+						}
+						Err(e) => {
 							if let Err(_) = tx.send(Err(e)).await {
 								error!("Unable to send error on stream-file channel.");
-							},
+							}
+							return;
+						}
+					};
+
+					// Skipping a block would corrupt the rest of the file, so stop the stream
+					// instead
+					let mut block = match found {
+						Some(data) => data,
+						None => {
+							warn!("Unable to find block {} of file {}.", block_hash, &file_hash);
+							let e = db::Error::FileMissingBlock(file_id, i as _);
+							if let Err(_) = tx.send(Err(e.into())).await {
+								error!("Unable to send error on stream-file channel.");
+							}
+							return;
+						}
+					};
+					db::decrypt_block(i as _, &file.plain_hash, &mut block);
+					if skip > 0 {
+						block.drain(..skip.min(block.len()));
+						skip = 0;
+					}
+					if (block.len() as u64) > remaining {
+						block.truncate(remaining as usize);
+					}
+					remaining -= block.len() as u64;
+					if let Err(_) = tx.send(Ok(block)).await {
+						error!("Unable to send block on stream-file channel.");
+						return;
 					}
 				}
 			});
-			Ok(PossibleFileStream::Stream((
-				file.mime_type.to_string(),
-				compression_type,
-				ReceiverStream::new(rx),
-			)))
+			let mime_type = file.mime_type.to_string();
+			Ok(match partial {
+				Some((range, size)) => PossibleFileStream::Partial((
+					mime_type,
+					range,
+					size,
+					ReceiverStream::new(rx),
+				)),
+				None => PossibleFileStream::Stream((
+					mime_type,
+					compression_type,
+					ReceiverStream::new(rx),
+				)),
+			})
 		} else {
 			Ok(PossibleFileStream::None)
 		}
 	}
 
 	pub async fn publish_post(
-		&self, actor_address: &ActorAddress, private_key: &ActorPrivateKeyV1, msg_mime_type: &str,
-		message: &str, tags: Vec<String>, attachments: &[FileData],
+		&self, actor_address: &ActorAddress, signer: &dyn Signer, msg_mime_type: &str,
+		message: &str, mut tags: Vec<String>, attachments: &[FileData],
 		in_reply_to: Option<(ActorAddress, IdType)>,
 	) -> db::Result<IdType> {
+		// Other nodes don't know our petnames, so mentions need the address. The
+		// hashtags in the message are added to the tags, so that the post can be
+		// found by them on any node.
+		let mut message = message.to_string();
+		if msg_mime_type.starts_with("text/") {
+			message = self.expand_mentions(&message).await?;
+			for tag in tag::parse_hashtags(&message) {
+				if tags.len() >= tag::MAX_TAGS {
+					break;
+				}
+				if !tags.iter().any(|t| tag::normalize_tag(t).as_ref() == Some(&tag)) {
+					tags.push(tag);
+				}
+			}
+		}
+
+		// Decoding the images takes a while, so do it before the transaction starts
+		let medias: Vec<_> = util::block_in_place(|| {
+			attachments
+				.iter()
+				.map(|f| {
+					(
+						media::extract_metadata(f.mime_type.as_str(), &f.data),
+						media::generate_thumbnail(f.mime_type.as_str(), &f.data),
+					)
+				})
+				.collect()
+		});
+
 		let tx = self.db.transaction().await?;
 		let actor = actor::Entity::find()
 			.filter(actor::Column::Address.eq(actor_address))
@@ -572,8 +1329,16 @@ impl Api {
 		let mut files = Vec::with_capacity(attachments.len() + 1);
 		let (_, file_hash, _) = tx.create_file2(msg_mime_type, message.as_bytes()).await?;
 		files.push(file_hash);
-		for FileData { mime_type, data } in attachments {
+		let attachments_with_media = attachments.iter().zip(&medias);
+		for (FileData { mime_type, data }, (metadata, thumbnail)) in attachments_with_media {
 			let (_, file_hash, _) = tx.create_file2(mime_type.as_str(), data).await?;
+			if let Some(metadata) = metadata {
+				tx.store_file_metadata(&file_hash, mime_type.as_str(), metadata)
+					.await?;
+			}
+			if let Some(thumbnail) = thumbnail {
+				tx.store_file_thumbnail(&file_hash, thumbnail).await?;
+			}
 			files.push(file_hash);
 		}
 
@@ -592,6 +1357,7 @@ impl Api {
 				files: files.clone().into(),
 			}),
 		});
+		let delegation = delegation::load_delegation_for(&tx, actor_id, &object_payload).await?;
 		let created = Utc::now().timestamp_millis() as u64;
 		let current_object_sequence = next_object_sequence - 1;
 		let previous_hash = if let Some(object) = object::Entity::find()
@@ -612,8 +1378,8 @@ impl Api {
 			&previous_hash,
 			created,
 			&object_payload,
-			&private_key,
-		);
+			signer,
+		)?;
 
 		tx.store_post(
 			actor_id,
@@ -628,6 +1394,14 @@ impl Api {
 			false,
 		)
 		.await?;
+		journal::record(
+			&tx,
+			JournalAction::PublishedPost,
+			&actor_address.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
 		tx.commit().await?;
 
 		let object = BlogchainObject {
@@ -636,12 +1410,14 @@ impl Api {
 			previous_hash,
 			signature,
 			payload: object_payload,
+			delegation,
 		};
 
 		if let Some(actor_node) = self.node.get_actor_node(&actor_address.as_id()).await {
 			actor_node
-				.publish_object(&self.node, &hash, &object, &[], 0)
+				.publish_new_object(&self.node, &hash, &object)
 				.await;
+			actor_node.distribute_parity(files);
 		} else {
 			error!("Actor node not found.");
 		}
@@ -650,15 +1426,15 @@ impl Api {
 	}
 
 	pub async fn publish_share(
-		&self, identity: &ActorAddress, private_key: &ActorPrivateKeyV1, object: &ShareObject,
+		&self, identity: &ActorAddress, signer: &dyn Signer, object: &ShareObject,
 	) -> db::Result<IdType> {
 		// Store the share object
-		let (_, hash, object) = { self.create_share(identity, private_key, object).await? };
+		let (_, hash, object) = { self.create_share(identity, signer, object).await? };
 
 		// Publish the object into the network
 		if let Some(actor_node) = self.node.get_actor_node(&identity.as_id()).await {
 			actor_node
-				.publish_object(&self.node, &hash, &object, &[], 0)
+				.publish_new_object(&self.node, &hash, &object)
 				.await;
 		} else {
 			error!("Actor node not found.");
@@ -669,8 +1445,8 @@ impl Api {
 	/// Calculates the signature of the s
 	fn sign_object(
 		sequence: u64, previous_hash: &IdType, created: u64, payload: &ObjectPayload,
-		private_key: &ActorPrivateKeyV1,
-	) -> (IdType, ActorSignatureV1) {
+		signer: &dyn Signer,
+	) -> db::Result<(IdType, ActorSignatureV1)> {
 		// Prepare data to be signed
 		let sign_data = ObjectSignData {
 			previous_hash: previous_hash.clone(),
@@ -680,11 +1456,11 @@ impl Api {
 		};
 		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
 
-		// Sign it
-		let signature = private_key.sign(&raw_sign_data);
+		// Sign it, which may have to wait for the external signer
+		let signature = util::block_in_place(|| signer.sign(&raw_sign_data))?;
 		let hash = signature.hash();
 
-		(hash, signature)
+		Ok((hash, signature))
 	}
 
 	pub async fn update_consolidated_feed(&self) -> db::Result<()> {
@@ -749,28 +1525,38 @@ impl Api {
 		}
 	}
 
+	/// Publishes a new profile object for the identity, and relabels it. The
+	/// files that aren't given are kept from the current profile. Returns the
+	/// hash of the new profile object.
 	pub async fn update_profile(
-		&self, private_key: &ActorPrivateKeyV1, actor_id: i64, old_label: &str, new_label: &str,
+		&self, signer: &dyn Signer, actor_id: i64, old_label: &str, new_label: &str,
 		name: &str, avatar: Option<FileData>, wallpaper: Option<FileData>,
-		description: Option<FileData>,
-	) -> db::Result<()> {
+		description: Option<FileData>, followers_only: bool,
+	) -> db::Result<IdType> {
 		let tx = self.db.transaction().await?;
 
 		// Prepare profile files
 		let (old_avatar_hash, old_wallpaper_hash, old_description_hash) =
 			tx.find_profile_files(actor_id).await?;
+		let mut new_files = Vec::with_capacity(3);
 		let avatar_hash = if let Some(f) = avatar {
-			Some(tx.create_file(&f).await?.1)
+			let hash = tx.create_file(&f).await?.1;
+			new_files.push(hash.clone());
+			Some(hash)
 		} else {
 			old_avatar_hash
 		};
 		let wallpaper_hash = if let Some(f) = wallpaper {
-			Some(tx.create_file(&f).await?.1)
+			let hash = tx.create_file(&f).await?.1;
+			new_files.push(hash.clone());
+			Some(hash)
 		} else {
 			old_wallpaper_hash
 		};
 		let description_hash = if let Some(f) = description {
-			Some(tx.create_file(&f).await?.1)
+			let hash = tx.create_file(&f).await?.1;
+			new_files.push(hash.clone());
+			Some(hash)
 		} else {
 			old_description_hash
 		};
@@ -778,13 +1564,16 @@ impl Api {
 		// Construct the profle object & store it
 		let next_sequence = tx.find_next_object_sequence(actor_id).await?;
 		let (object_hash, object) = Self::compose_profile_object(
-			private_key,
+			signer,
 			next_sequence,
 			name,
 			&avatar_hash,
 			&wallpaper_hash,
 			&description_hash,
-		);
+			followers_only,
+			current_time_millis(),
+		)?;
+		delegation::load_delegation_for(&tx, actor_id, &object.payload).await?;
 		tx.store_profile(
 			actor_id,
 			object.created,
@@ -796,15 +1585,46 @@ impl Api {
 			avatar_hash,
 			wallpaper_hash,
 			description_hash,
+			followers_only,
 		)
 		.await?;
 		tx.update_identity_label(old_label, new_label).await?;
+		let actor = actor::Entity::find_by_id(actor_id)
+			.one(tx.inner())
+			.await?
+			.expect("identity doesn't exist");
+		journal::record(
+			&tx,
+			JournalAction::UpdatedProfile,
+			&actor.address.to_string(),
+			Some(&object_hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
 
-		tx.commit().await
+		// Let the actor network know about the new profile and its files
+		if let Some(actor_node) = self.node.get_actor_node(&actor.address.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, &object_hash, &object)
+				.await;
+			actor_node.distribute_parity(new_files);
+		} else {
+			error!("Actor node not found.");
+		}
+		Ok(object_hash)
 	}
 }
 
 
+fn current_time_millis() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_millis() as u64
+}
+
+
 #[cfg(test)]
 mod tests {
 	use rand::RngCore;
@@ -812,6 +1632,24 @@ mod tests {
 	use super::*;
 	use crate::test;
 
+	#[test]
+	fn test_byte_range() {
+		assert_eq!(ByteRange::parse("bytes=0-499"), Some(ByteRange::From(0, Some(499))));
+		assert_eq!(ByteRange::parse("bytes=500-"), Some(ByteRange::From(500, None)));
+		assert_eq!(ByteRange::parse("bytes=-200"), Some(ByteRange::Suffix(200)));
+		assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+		assert_eq!(ByteRange::parse("bytes=9-3"), None);
+		assert_eq!(ByteRange::parse("items=0-1"), None);
+
+		assert_eq!(ByteRange::From(0, Some(499)).resolve(1000), Some((0, 499)));
+		assert_eq!(ByteRange::From(500, None).resolve(1000), Some((500, 999)));
+		assert_eq!(ByteRange::From(900, Some(2000)).resolve(1000), Some((900, 999)));
+		assert_eq!(ByteRange::Suffix(200).resolve(1000), Some((800, 999)));
+		assert_eq!(ByteRange::Suffix(2000).resolve(1000), Some((0, 999)));
+		assert_eq!(ByteRange::From(1000, None).resolve(1000), None);
+		assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+	}
+
 	#[tokio::test]
 	async fn test_create_identity() {
 		let mut rng = test::initialize_rng();
@@ -905,4 +1743,85 @@ mod tests {
 		);
 		assert_eq!(profile_info.description, Some(description_data.to_string()));
 	}
+
+	#[tokio::test]
+	async fn test_update_profile() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("update_profile").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api {
+			node,
+			db: db.clone(),
+		};
+
+		let avatar = FileData {
+			mime_type: "image/png".into(),
+			data: vec![1u8; 1024],
+		};
+		let (address, _) = api
+			.create_identity("old", "Old name", Some(&avatar), None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.fetch_my_identity(&address).await.unwrap().unwrap();
+		let actor_id = identity::Entity::find_by_id("old".to_string())
+			.one(db.inner())
+			.await
+			.unwrap()
+			.expect("identity not found")
+			.actor_id;
+
+		let description = FileData {
+			mime_type: "text/markdown".into(),
+			data: b"*New* description".to_vec(),
+		};
+		let hash = api
+			.update_profile(
+				&*signer,
+				actor_id,
+				"old",
+				"new",
+				"New name",
+				None,
+				None,
+				Some(description),
+				true,
+			)
+			.await
+			.unwrap();
+		let object = object::Entity::find()
+			.filter(object::Column::Hash.eq(&hash))
+			.one(db.inner())
+			.await
+			.unwrap()
+			.expect("profile object not found");
+		assert_eq!(object.sequence, 1);
+
+		// The avatar is kept, as no new one has been given
+		let profile_info = web::info::find_profile_info(&db, "", &address)
+			.await
+			.unwrap()
+			.expect("profile info not found");
+		assert_eq!(profile_info.actor.name, "New name");
+		assert!(profile_info.actor.avatar_url.is_some());
+		assert!(profile_info.followers_only);
+		assert_eq!(profile_info.description.as_deref(), Some("*New* description"));
+	}
+
+	#[tokio::test]
+	async fn test_ban_peer_twice() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("ban_peer_twice").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api {
+			node,
+			db: db.clone(),
+		};
+
+		// Banning a node that is banned already keeps the existing ban
+		let target = BanTarget::Node(NodeAddress::V1(IdType::random(&mut rng)));
+		let record = api.ban_peer(&target, "spam").await.unwrap();
+		let again = api.ban_peer(&target, "more spam").await.unwrap();
+		assert_eq!(again, record);
+		assert_eq!(api.load_peer_bans().await.unwrap().len(), 1);
+	}
 }
@@ -0,0 +1,157 @@
+//! Muting and blocking other actors for our identities. See the `block_list`
+//! module of `db` for what either of them does.
+
+use sea_orm::prelude::*;
+
+use super::Api;
+use crate::{
+	core::ActorAddress,
+	db::{
+		self,
+		block_list::BlockTarget,
+		journal::{self, JournalAction},
+		PersistenceHandle,
+	},
+	entity::*,
+};
+
+
+impl Api {
+	/// Mutes or blocks the target for the identity. Returns false if the
+	/// identity doesn't exist.
+	pub async fn block_actor(
+		&self, identity: &ActorAddress, target: &BlockTarget, blocked: bool,
+	) -> db::Result<bool> {
+		let actor_id = match self.find_identity_actor_id(identity).await? {
+			Some(id) => id,
+			None => return Ok(false),
+		};
+		self.db.block_actor(actor_id, target, blocked).await?;
+		let action = if blocked {
+			JournalAction::BlockedActor
+		} else {
+			JournalAction::MutedActor
+		};
+		journal::record(
+			&self.db,
+			action,
+			&identity.to_string(),
+			None,
+			Some(&target.to_string()),
+		)
+		.await?;
+
+		self.reload_blocked_nodes(identity).await?;
+		Ok(true)
+	}
+
+	async fn find_identity_actor_id(&self, identity: &ActorAddress) -> db::Result<Option<i64>> {
+		Ok(identity::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(identity))
+			.one(self.db.inner())
+			.await?
+			.map(|i| i.actor_id))
+	}
+
+	/// Loads the actors that the identity has muted or blocked.
+	pub async fn load_actor_blocks(
+		&self, identity: &ActorAddress,
+	) -> db::Result<Vec<actor_block::Model>> {
+		match self.find_identity_actor_id(identity).await? {
+			Some(actor_id) => self.db.load_actor_blocks(actor_id).await,
+			None => Ok(Vec::new()),
+		}
+	}
+
+	async fn reload_blocked_nodes(&self, identity: &ActorAddress) -> db::Result<()> {
+		if let Some(actor_node) = self.node.get_actor_node(&identity.as_id()).await {
+			actor_node.reload_blocked_nodes().await?;
+		}
+		Ok(())
+	}
+
+	/// Unmutes or unblocks the target for the identity. Returns false if it
+	/// wasn't muted or blocked.
+	pub async fn unblock_actor(
+		&self, identity: &ActorAddress, target: &BlockTarget,
+	) -> db::Result<bool> {
+		let actor_id = match self.find_identity_actor_id(identity).await? {
+			Some(id) => id,
+			None => return Ok(false),
+		};
+		if !self.db.unblock_actor(actor_id, target).await? {
+			return Ok(false);
+		}
+		journal::record(
+			&self.db,
+			JournalAction::UnblockedActor,
+			&identity.to_string(),
+			None,
+			Some(&target.to_string()),
+		)
+		.await?;
+
+		self.reload_blocked_nodes(identity).await?;
+		Ok(true)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_block_actor() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("block_list").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (other, _) = api
+			.create_identity("other", "Other", None, None, None)
+			.await
+			.unwrap();
+		let actor_id = api.find_identity_actor_id(&address).await.unwrap().unwrap();
+		let url = "https://example.com/users/someone";
+		let remote = BlockTarget::ActivityPub(url.to_string());
+
+		// Muting alone doesn't block
+		assert!(api.block_actor(&address, &remote, false).await.unwrap());
+		assert!(!api
+			.db
+			.is_activity_pub_actor_blocked(actor_id, url)
+			.await
+			.unwrap());
+		assert!(api.block_actor(&address, &remote, true).await.unwrap());
+		assert!(api
+			.db
+			.is_activity_pub_actor_blocked(actor_id, url)
+			.await
+			.unwrap());
+
+		let local = BlockTarget::Actor(other.clone());
+		api.block_actor(&address, &local, true).await.unwrap();
+		assert!(api.db.is_actor_blocked(actor_id, &other).await.unwrap());
+		assert_eq!(api.load_actor_blocks(&address).await.unwrap().len(), 2);
+		// The block lists are kept per identity
+		assert!(api.load_actor_blocks(&other).await.unwrap().is_empty());
+
+		assert!(api.unblock_actor(&address, &local).await.unwrap());
+		assert!(!api.unblock_actor(&address, &local).await.unwrap());
+		assert!(!api.db.is_actor_blocked(actor_id, &other).await.unwrap());
+
+		// A block can be undone from the journal
+		api.block_actor(&address, &local, true).await.unwrap();
+		let entry = api.db.load_journal(1, 0).await.unwrap().remove(0);
+		assert!(api.undo_activity(entry.id).await.unwrap());
+		assert!(!api.db.is_actor_blocked(actor_id, &other).await.unwrap());
+		assert!(!api.undo_activity(entry.id).await.unwrap());
+	}
+}
@@ -0,0 +1,133 @@
+//! Lets the user bookmark the objects that they want to find back later, of
+//! their own identities as well as of other actors.
+
+use log::*;
+use sea_orm::prelude::*;
+use serde::Serialize;
+
+use super::Api;
+use crate::{
+	common::IdType,
+	core::ActorAddress,
+	db::{self, PersistenceHandle},
+	entity::object,
+	web::info::{find_object_info, object_url, ObjectInfo},
+};
+
+
+#[derive(Serialize)]
+pub struct BookmarkInfo {
+	pub actor_address: String,
+	pub object_hash: String,
+	pub url: String,
+	pub created: i64,
+	/// The object itself, or `None` if it hasn't been downloaded (yet).
+	pub object: Option<ObjectInfo>,
+}
+
+
+impl Api {
+	/// Bookmarks the object, which keeps it from being removed. If the object
+	/// hasn't been downloaded yet, it is collected from the network of its
+	/// actor in the background. Returns false if it was bookmarked already.
+	pub async fn bookmark_object(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> db::Result<bool> {
+		if !self.db.add_bookmark(actor_address, object_hash).await? {
+			return Ok(false);
+		}
+
+		let is_stored = object::Entity::find()
+			.filter(object::Column::Hash.eq(object_hash))
+			.count(self.db.inner())
+			.await? > 0;
+		if !is_stored {
+			let node = self.node.clone();
+			let actor_address = actor_address.clone();
+			let object_hash = object_hash.clone();
+			let name = format!("collection of bookmarked object {}", &object_hash);
+			self.node.tasks().spawn(name, async move {
+				let actor_node = match node.get_actor_node_or_lurker(&actor_address).await {
+					Some(n) => n,
+					None => {
+						warn!("Unable to reach actor network {}.", &actor_address);
+						return;
+					}
+				};
+				match actor_node.collect_object_from_network(&object_hash).await {
+					Ok(true) => {}
+					Ok(false) => warn!(
+						"Bookmarked object {} not found on actor network {}.",
+						&object_hash, &actor_address
+					),
+					Err(e) => error!("Unable to collect bookmarked object: {:?}", e),
+				}
+			});
+		}
+		Ok(true)
+	}
+
+	/// Loads the bookmarks, the latest ones first.
+	pub async fn load_bookmarks(
+		&self, url_base: &str, limit: u64, offset: u64,
+	) -> db::Result<Vec<BookmarkInfo>> {
+		let records = self.db.load_bookmarks(limit, offset).await?;
+		let mut bookmarks = Vec::with_capacity(records.len());
+		for record in records {
+			let object = find_object_info(
+				&self.db,
+				url_base,
+				&record.actor_address,
+				&record.object_hash,
+			)
+			.await?;
+			bookmarks.push(BookmarkInfo {
+				actor_address: record.actor_address.to_string(),
+				object_hash: record.object_hash.to_string(),
+				url: object_url(url_base, &record.actor_address, &record.object_hash),
+				created: record.created,
+				object,
+			});
+		}
+		Ok(bookmarks)
+	}
+
+	/// Returns false if the object wasn't bookmarked.
+	pub async fn unbookmark_object(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> db::Result<bool> {
+		self.db.remove_bookmark(actor_address, object_hash).await
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_bookmark_object() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api_bookmark").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, actor_info) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+
+		// The profile object of the identity is stored already
+		let object_hash = actor_info.first_object.clone();
+		assert!(api.bookmark_object(&address, &object_hash).await.unwrap());
+		assert!(!api.bookmark_object(&address, &object_hash).await.unwrap());
+
+		let bookmarks = api.load_bookmarks("", 10, 0).await.unwrap();
+		assert_eq!(bookmarks.len(), 1);
+		assert!(bookmarks[0].object.is_some());
+		assert_eq!(bookmarks[0].object_hash, object_hash.to_string());
+
+		assert!(api.unbookmark_object(&address, &object_hash).await.unwrap());
+		assert_eq!(api.load_bookmarks("", 10, 0).await.unwrap().len(), 0);
+	}
+}
@@ -0,0 +1,228 @@
+//! Lets our identities publish from other devices, with device keys.
+//!
+//! A device key is issued by the node that holds the private key of the
+//! identity. It comes with a delegation certificate, which is signed with the
+//! key of the identity and says which types of objects the device key may
+//! sign. The device key and its certificate are handed over to the other device
+//! as a single piece of text, with which the identity can be added over there.
+//! The objects that the other device publishes carry the certificate along, so
+//! that followers can verify them without having to know the device key.
+//!
+//! A device key can't rotate the key of the identity, nor issue device keys of
+//! its own. Rotating the key of the identity revokes all device keys that were
+//! issued before.
+
+use base58::{FromBase58, ToBase58};
+use rand::rngs::OsRng;
+use sea_orm::prelude::*;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::Api;
+use crate::{
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		keyring::ACTOR_PRIVATE_KEY_SIZE,
+		PersistenceHandle,
+	},
+	entity::*,
+	identity::ActorPrivateKeyV1,
+	net::binserde,
+	util,
+};
+
+
+/// Everything another device needs to publish as the identity.
+#[derive(Deserialize, Serialize)]
+struct DeviceKeyBundle {
+	actor_address: ActorAddress,
+	private_key: Vec<u8>,
+	certificate: DelegationCertificate,
+}
+
+
+/// Loads the delegation certificate that objects of the identity need to carry,
+/// and checks that it allows the given payload to be published with it.
+pub(super) async fn load_delegation_for(
+	tx: &db::Transaction, actor_id: i64, payload: &ObjectPayload,
+) -> db::Result<Option<DelegationCertificate>> {
+	let delegation = tx.load_identity_delegation(actor_id).await?;
+	if let Some(certificate) = &delegation {
+		if !certificate.allows(payload) {
+			Err(db::Error::NotDelegated)?;
+		}
+	}
+	Ok(delegation)
+}
+
+fn parse_bundle(text: &str) -> db::Result<(DeviceKeyBundle, ActorPrivateKeyV1)> {
+	let buffer = match text.trim().from_base58() {
+		Ok(b) => Zeroizing::new(b),
+		Err(_) => Err(db::Error::InvalidDelegation)?,
+	};
+	let bundle: DeviceKeyBundle = match binserde::deserialize(&buffer) {
+		Ok(b) => b,
+		Err(_) => Err(db::Error::InvalidDelegation)?,
+	};
+	if bundle.private_key.len() != ACTOR_PRIVATE_KEY_SIZE {
+		Err(db::Error::InvalidDelegation)?;
+	}
+	let private_key = ActorPrivateKeyV1::from_bytes(*array_ref![
+		bundle.private_key,
+		0,
+		ACTOR_PRIVATE_KEY_SIZE
+	]);
+	if bundle.certificate.device_key != private_key.public() {
+		Err(db::Error::InvalidDelegation)?;
+	}
+	Ok((bundle, private_key))
+}
+
+impl Api {
+	/// Issues a new device key for the identity, which may sign the types of
+	/// objects given by the scopes. Returns the text that the identity can be
+	/// added to the other device with, or None if there is no identity with the
+	/// label.
+	pub async fn issue_device_key(&self, label: &str, scopes: u8) -> db::Result<Option<String>> {
+		let (record, actor) = match identity::Entity::find_by_id(label.to_string())
+			.find_also_related(actor::Entity)
+			.one(self.db.inner())
+			.await?
+		{
+			Some((r, Some(a))) => (r, a),
+			_ => return Ok(None),
+		};
+		if record.delegation.is_some() {
+			Err(db::Error::NotDelegated)?;
+		}
+		let signer = self.db.keyring().load_signer(&record.private_key)?;
+
+		let device_key = ActorPrivateKeyV1::generate_with_rng(&mut OsRng);
+		let certificate = util::block_in_place(|| {
+			DelegationCertificate::new(&actor.address, device_key.public(), scopes, &*signer)
+		})?;
+		let bundle = DeviceKeyBundle {
+			actor_address: actor.address.clone(),
+			private_key: device_key.as_bytes().to_vec(),
+			certificate,
+		};
+		let buffer = Zeroizing::new(binserde::serialize(&bundle).unwrap());
+		journal::record(
+			&self.db,
+			JournalAction::IssuedDeviceKey,
+			&actor.address.to_string(),
+			None,
+			Some(label),
+		)
+		.await?;
+		Ok(Some(buffer.to_base58()))
+	}
+
+	/// Adds an identity of another node to ours, with a device key that was
+	/// issued for it over there. Returns false if the actor couldn't be found.
+	pub async fn add_device_identity(&self, label: &str, text: &str) -> db::Result<bool> {
+		let (bundle, private_key) = parse_bundle(text)?;
+		let address = &bundle.actor_address;
+
+		let actor_info = match self.db.find_actor_info(address).await? {
+			Some(i) => i,
+			None => match self.node.find_actor(address, 100, false).await {
+				Some(r) => r.0.clone(),
+				None => return Ok(false),
+			},
+		};
+
+		let tx = self.db.transaction().await?;
+		let actor_id = tx.ensure_actor_id(address, &actor_info).await?;
+		// The certificate is no good anymore if the key has been rotated since
+		match tx.load_current_public_key(actor_id).await? {
+			Some(public_key) if bundle.certificate.verify(address, &public_key) => {}
+			_ => Err(db::Error::InvalidDelegation)?,
+		}
+		if identity::Entity::find()
+			.filter(identity::Column::ActorId.eq(actor_id))
+			.one(tx.inner())
+			.await?
+			.is_some()
+		{
+			Err(db::Error::UnexpectedState(format!(
+				"actor {} is already one of our identities",
+				address
+			)))?;
+		}
+		tx.store_identity(
+			label,
+			actor_id,
+			&private_key,
+			false,
+			None,
+			Some(&bundle.certificate),
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::AddedDeviceIdentity,
+			&address.to_string(),
+			None,
+			Some(label),
+		)
+		.await?;
+		tx.commit().await?;
+
+		let node = self.node.clone();
+		let address2 = address.clone();
+		self.node.tasks().spawn("device identity joiner", async move {
+			node.join_actor_network(&address2, &actor_info).await;
+		});
+		Ok(true)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_device_key() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("delegation").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, _) = api
+			.create_identity("primary", "Primary", None, None, None)
+			.await
+			.unwrap();
+		let record = identity::Entity::find_by_id("primary".to_string())
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		let actor_key = api.db.keyring().decrypt_actor_key(&record.private_key).unwrap();
+
+		let text = api
+			.issue_device_key("primary", DELEGATION_SCOPE_POST | DELEGATION_SCOPE_SHARE)
+			.await
+			.unwrap()
+			.unwrap();
+		let (mut bundle, private_key) = parse_bundle(&text).unwrap();
+		assert_eq!(bundle.actor_address, address);
+		assert!(bundle.certificate.verify(&address, &actor_key.public()));
+		assert!(bundle.certificate.allows(&ObjectPayload::Share(ShareObject {
+			actor_address: address.clone(),
+			object_hash: Default::default(),
+		})));
+		let rotation = KeyRotationObject::new(&address, 1, &private_key);
+		assert!(!bundle
+			.certificate
+			.allows(&ObjectPayload::KeyRotation(rotation)));
+
+		// The scopes can't be widened without the key of the identity
+		bundle.certificate.scopes |= DELEGATION_SCOPE_PROFILE;
+		assert!(!bundle.certificate.verify(&address, &actor_key.public()));
+		assert!(parse_bundle(&text[1..]).is_err());
+	}
+}
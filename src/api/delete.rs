@@ -0,0 +1,176 @@
+//! Deleting posts that have been published already.
+//!
+//! Like with edits, a post can't be removed from the chain of its actor.
+//! Instead, a delete object is published that refers to the post. Every node
+//! that stores the actor then drops the content of the post, and only keeps a
+//! tombstone of it.
+
+use chrono::Utc;
+use log::*;
+use sea_orm::prelude::*;
+
+use super::{delegation, Api};
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		PersistenceHandle,
+	},
+	entity::*,
+	identity::Signer,
+};
+
+
+impl Api {
+	/// Publishes the deletion of one of the posts of the actor, and drops its
+	/// content locally. Returns the hash of the delete object, or `None` if the
+	/// actor has no such post, or if it has been deleted already.
+	pub async fn publish_delete(
+		&self, actor_address: &ActorAddress, signer: &dyn Signer, object_hash: &IdType,
+	) -> db::Result<Option<IdType>> {
+		let tx = self.db.transaction().await?;
+		let post = object::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(actor_address))
+			.filter(object::Column::Hash.eq(object_hash))
+			.one(tx.inner())
+			.await?;
+		let actor_id = match post {
+			Some(o) if o.r#type == OBJECT_TYPE_POST => o.actor_id,
+			_ => return Ok(None),
+		};
+		if self.db.is_object_deleted(actor_id, object_hash).await? {
+			return Ok(None);
+		}
+
+		// Sign the deletion
+		let sequence = tx.find_next_object_sequence(actor_id).await?;
+		let previous_hash = match object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Sequence.eq(sequence as i64 - 1))
+			.one(tx.inner())
+			.await?
+		{
+			Some(object) => object.hash,
+			None => Err(db::Error::UnexpectedState(format!(
+				"can't find object sequence {} for actor {}",
+				sequence as i64 - 1,
+				actor_id
+			)))?,
+		};
+		let payload = ObjectPayload::Delete(DeleteObject {
+			object_hash: object_hash.clone(),
+		});
+		let delegation = delegation::load_delegation_for(&tx, actor_id, &payload).await?;
+		let created = Utc::now().timestamp_millis() as u64;
+		let (hash, signature) =
+			Self::sign_object(sequence, &previous_hash, created, &payload, signer)?;
+
+		tx.store_delete(
+			actor_id,
+			created,
+			&hash,
+			&previous_hash,
+			&signature,
+			object_hash,
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::DeletedPost,
+			&actor_address.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
+		self.db.apply_deletions(actor_id).await?;
+
+		let object = BlogchainObject {
+			created,
+			sequence,
+			previous_hash,
+			signature,
+			payload,
+			delegation,
+		};
+		if let Some(actor_node) = self.node.get_actor_node(&actor_address.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, &hash, &object)
+				.await;
+		} else {
+			error!("Actor node not found.");
+		}
+		Ok(Some(hash))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{test, web::info::load_post_history};
+
+	#[tokio::test]
+	async fn test_publish_delete() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("delete").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+		let post_hash = api
+			.publish_post(&address, &*signer, "text/plain", "Oops", Vec::new(), &[], None)
+			.await
+			.unwrap();
+		api.publish_edit(&address, &*signer, &post_hash, "text/plain", "Oops!", None)
+			.await
+			.unwrap()
+			.expect("post not found");
+		let post = object::Entity::find()
+			.filter(object::Column::Hash.eq(&post_hash))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		let message_hash = post_file::Entity::find()
+			.filter(post_file::Column::ObjectId.eq(post.id))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap()
+			.hash;
+		assert!(api.db.has_file(&message_hash).await.unwrap());
+
+		let delete_hash = api
+			.publish_delete(&address, &*signer, &post_hash)
+			.await
+			.unwrap()
+			.expect("post not found");
+		// Neither the post nor the deletion itself can be deleted again
+		for hash in [&post_hash, &delete_hash] {
+			let result = api.publish_delete(&address, &*signer, hash).await.unwrap();
+			assert!(result.is_none());
+		}
+		let result = api
+			.publish_edit(&address, &*signer, &post_hash, "text/plain", "Back", None)
+			.await
+			.unwrap();
+		assert!(result.is_none());
+
+		// The content is gone, but the objects are still there
+		assert!(!api.db.has_file(&message_hash).await.unwrap());
+		assert!(api.db.is_file_dropped(&message_hash).await.unwrap());
+		let history = load_post_history(&api.db, "", &address, &post_hash)
+			.await
+			.unwrap();
+		assert!(history.is_none());
+		assert!(api.db.is_tombstone(post.id).await.unwrap());
+	}
+}
@@ -0,0 +1,397 @@
+//! Lets our identities exchange direct messages with other actors. Messages are
+//! stored right away, and delivered in the background, see the
+//! `direct_message` module of `net::actor` for how.
+
+use std::time::Duration;
+
+use chrono::DateTime;
+use log::*;
+use sea_orm::prelude::*;
+use serde::Serialize;
+
+use super::Api;
+use crate::{
+	common::current_timestamp,
+	core::{ActorAddress, DirectMessageContent, DirectMessageObject},
+	db::{self, PersistenceHandle},
+	entity::{conversation, direct_message},
+	naming,
+	net::binserde,
+	util,
+	web::info::{actor_url, find_profile_info, TargetedActorInfo},
+};
+
+
+/// How often the messages that couldn't be delivered yet are retried.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The time to wait before the first retry. Doubles with every failure.
+const RETRY_BASE_DELAY: u64 = 60_000;
+/// The longest time to wait between retries.
+const RETRY_MAX_DELAY: u64 = 6 * 60 * 60 * 1000;
+/// Messages are given up on after this many failures, which spans a few days
+/// with the delays above.
+const RETRY_LIMIT: u32 = 20;
+const RETRY_BATCH_SIZE: u64 = 50;
+
+
+#[derive(Serialize)]
+pub struct ConversationInfo {
+	pub id: i64,
+	pub identity_address: String,
+	pub peer_address: String,
+	pub peer_url: String,
+	/// The profile of the other actor, if we have it.
+	pub peer: Option<TargetedActorInfo>,
+	pub last_activity: String,
+	pub unread: i64,
+}
+
+#[derive(Serialize)]
+pub struct DirectMessageInfo {
+	pub incoming: bool,
+	pub mime_type: String,
+	pub body: String,
+	pub created: String,
+	/// Whether an outgoing message has reached its recipient. Incoming messages
+	/// are always delivered.
+	pub delivered: bool,
+	/// Whether delivery of an outgoing message has been given up on.
+	pub failed: bool,
+}
+
+
+impl Api {
+	/// Retries the outgoing messages that are due at the given unix timestamp in
+	/// milliseconds. Returns the number of messages that have been delivered.
+	pub async fn deliver_due_direct_messages(&self, now: u64) -> db::Result<usize> {
+		let mut delivered = 0;
+		let messages = self
+			.db
+			.load_due_direct_messages(now as _, RETRY_BATCH_SIZE)
+			.await?;
+		for message in messages {
+			if self.attempt_direct_message(&message, now).await? {
+				delivered += 1;
+			}
+		}
+		Ok(delivered)
+	}
+
+	/// Tries to deliver the message, and postpones it if that didn't work out.
+	/// Returns whether the message has been delivered.
+	async fn attempt_direct_message(
+		&self, message: &direct_message::Model, now: u64,
+	) -> db::Result<bool> {
+		match self.deliver_direct_message(message).await {
+			Ok(true) => return Ok(true),
+			Ok(false) => {}
+			Err(e) => warn!("Unable to deliver direct message {}: {}", message.id, e),
+		}
+
+		let failures = message.failures as u32 + 1;
+		let next_attempt = if failures >= RETRY_LIMIT {
+			None
+		} else {
+			Some((now + retry_delay(failures)) as i64)
+		};
+		self.db
+			.postpone_direct_message(message.id, failures, next_attempt)
+			.await?;
+		Ok(false)
+	}
+
+	/// Seals the message to the current message key of its recipient, and sends
+	/// it. If it has been sealed before, the same sealed message is sent again,
+	/// so that the recipient doesn't store it twice. Returns false if the
+	/// recipient couldn't be reached.
+	async fn deliver_direct_message(&self, message: &direct_message::Model) -> db::Result<bool> {
+		// Messages are delivered in the background, for all local users
+		let conversation = match conversation::Entity::find_by_id(message.conversation_id)
+			.one(self.db.inner())
+			.await?
+		{
+			Some(c) => c,
+			None => return Ok(false),
+		};
+		let identity = &conversation.identity_address;
+		let (signer, sender_info) = match (
+			self.fetch_my_identity(identity).await?,
+			self.db.find_actor_info(identity).await?,
+		) {
+			(Some((_, signer)), Some(info)) => (signer, info),
+			_ => return Ok(false),
+		};
+
+		let actor_node = match self
+			.node
+			.get_actor_node_or_lurker(&conversation.peer_address)
+			.await
+		{
+			Some(n) => n,
+			None => return Ok(false),
+		};
+		let object: DirectMessageObject = if let Some(buffer) = &message.sealed {
+			binserde::deserialize(buffer).map_err(|e| {
+				db::Error::UnexpectedState(format!("unable to parse sealed direct message: {}", e))
+			})?
+		} else {
+			let message_key = match actor_node.find_message_key().await {
+				Some(k) => k,
+				None => return Ok(false),
+			};
+			let content = DirectMessageContent {
+				mime_type: message.mime_type.as_str().into(),
+				body: message.body.clone(),
+			};
+			let object = util::block_in_place(|| {
+				DirectMessageObject::new(
+					identity.clone(),
+					conversation.peer_address.clone(),
+					&message_key,
+					&content,
+					&*signer,
+				)
+			})?;
+			// Stored before sending, as the recipient may accept it even if we
+			// don't get to hear about it
+			self.db
+				.store_sealed_direct_message(
+					message.id,
+					&object.hash(),
+					binserde::serialize(&object).unwrap(),
+				)
+				.await?;
+			object
+		};
+
+		if !actor_node.deliver_direct_message(&sender_info, &object).await {
+			return Ok(false);
+		}
+		self.db
+			.mark_direct_message_delivered(message.id, &object.hash())
+			.await?;
+		Ok(true)
+	}
+
+	/// Loads the conversation with its latest messages, oldest first, and marks
+	/// it as read.
+	pub async fn load_conversation(
+		&self, url_base: &str, id: i64, limit: u64,
+	) -> db::Result<Option<(ConversationInfo, Vec<DirectMessageInfo>)>> {
+		let record = match self.db.load_conversation(id).await? {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		let mut messages = self.db.load_direct_messages(id, limit, 0).await?;
+		messages.reverse();
+		self.db.mark_conversation_read(id).await?;
+
+		let info = self.conversation_info(url_base, record).await?;
+		let messages = messages
+			.into_iter()
+			.map(|m| DirectMessageInfo {
+				incoming: m.incoming,
+				mime_type: m.mime_type,
+				body: m.body,
+				created: format_timestamp(m.created),
+				delivered: m.delivered,
+				failed: !m.delivered && m.next_attempt.is_none(),
+			})
+			.collect();
+		Ok(Some((info, messages)))
+	}
+
+	/// Loads all conversations, the ones with the latest messages first.
+	pub async fn load_conversations(&self, url_base: &str) -> db::Result<Vec<ConversationInfo>> {
+		let records = self.db.load_conversations().await?;
+		let mut conversations = Vec::with_capacity(records.len());
+		for record in records {
+			conversations.push(self.conversation_info(url_base, record).await?);
+		}
+		Ok(conversations)
+	}
+
+	/// Stores the message, and tries to deliver it in the background. Returns
+	/// `None` if the conversation doesn't exist.
+	pub async fn send_direct_message(
+		&self, conversation_id: i64, body: &str,
+	) -> db::Result<Option<i64>> {
+		if self.db.load_conversation(conversation_id).await?.is_none() {
+			return Ok(None);
+		}
+		let content = DirectMessageContent {
+			mime_type: "text/markdown".into(),
+			body: body.to_string(),
+		};
+		// The first retry is only due after a delay, so that it doesn't happen
+		// while the first attempt is still going on
+		let next_attempt = current_timestamp() + retry_delay(0);
+		let record = self
+			.db
+			.store_outgoing_direct_message(conversation_id, &content, next_attempt as _)
+			.await?;
+
+		let id = record.id;
+		let this = self.clone();
+		self.node
+			.tasks()
+			.spawn(format!("delivery of direct message {}", id), async move {
+				if let Err(e) = this.attempt_direct_message(&record, current_timestamp()).await {
+					this.db.observe_error(&e);
+					error!("Unable to postpone direct message {}: {}", record.id, e);
+				}
+			});
+		Ok(Some(id))
+	}
+
+	/// Returns the ID of the conversation between the identity and the other
+	/// actor, and starts it if there wasn't one yet.
+	pub async fn start_conversation(
+		&self, identity: &ActorAddress, peer: &ActorAddress,
+	) -> db::Result<i64> {
+		self.db.ensure_conversation(identity, peer).await
+	}
+
+	async fn conversation_info(
+		&self, url_base: &str, record: conversation::Model,
+	) -> db::Result<ConversationInfo> {
+		let mut peer = find_profile_info(&self.db, url_base, &record.peer_address)
+			.await?
+			.map(|p| p.actor);
+		// Conversations are only ever shown to ourselves
+		if let Some(peer) = &mut peer {
+			peer.petname = naming::find_petname(&self.db, &record.peer_address).await?;
+		}
+		Ok(ConversationInfo {
+			id: record.id,
+			identity_address: record.identity_address.to_string(),
+			peer_address: record.peer_address.to_string(),
+			peer_url: actor_url(url_base, &record.peer_address),
+			peer,
+			last_activity: format_timestamp(record.last_activity),
+			unread: record.unread,
+		})
+	}
+}
+
+
+/// Keeps retrying the direct messages that couldn't be delivered yet, for as
+/// long as the node runs.
+pub async fn deliver_direct_messages(api: Api) {
+	loop {
+		if let Err(e) = api.deliver_due_direct_messages(current_timestamp()).await {
+			api.db.observe_error(&e);
+			error!("Unable to deliver direct messages: {}", e);
+		}
+
+		if !api.node.sleep_while_running(CHECK_INTERVAL).await {
+			break;
+		}
+	}
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+	match DateTime::from_timestamp_millis(timestamp) {
+		Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+		None => String::new(),
+	}
+}
+
+/// The delay before the next attempt, after the given number of failures.
+fn retry_delay(failures: u32) -> u64 {
+	RETRY_BASE_DELAY
+		.saturating_mul(1u64 << failures.min(32))
+		.min(RETRY_MAX_DELAY)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_send_direct_message() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api_direct_message").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (peer, _) = api
+			.create_identity("peer", "Peer", None, None, None)
+			.await
+			.unwrap();
+
+		let id = api.start_conversation(&address, &peer).await.unwrap();
+		assert_eq!(api.start_conversation(&address, &peer).await.unwrap(), id);
+		assert!(api.send_direct_message(id + 1, "Hi").await.unwrap().is_none());
+		api.send_direct_message(id, "Hi").await.unwrap().unwrap();
+
+		// Nobody can be reached on an empty network, so the message is retried
+		assert_eq!(
+			api.deliver_due_direct_messages(current_timestamp() + RETRY_BASE_DELAY)
+				.await
+				.unwrap(),
+			0
+		);
+		let (conversation, messages) = api.load_conversation("", id, 10).await.unwrap().unwrap();
+		assert_eq!(conversation.peer_address, peer.to_string());
+		assert_eq!(messages.len(), 1);
+		assert!(!messages[0].incoming);
+		assert!(!messages[0].delivered);
+		assert!(!messages[0].failed);
+		assert_eq!(api.load_conversations("").await.unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_postpone_direct_message() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api_direct_message_postpone").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (peer, _) = api
+			.create_identity("peer", "Peer", None, None, None)
+			.await
+			.unwrap();
+		let id = api.start_conversation(&address, &peer).await.unwrap();
+		let content = DirectMessageContent {
+			mime_type: "text/markdown".into(),
+			body: "Hi".to_string(),
+		};
+		let now = current_timestamp();
+		let mut record = api
+			.db
+			.store_outgoing_direct_message(id, &content, now as _)
+			.await
+			.unwrap();
+
+		// A failed first attempt counts towards the retries
+		assert!(!api.attempt_direct_message(&record, now).await.unwrap());
+		let reloaded = direct_message::Entity::find_by_id(record.id)
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(reloaded.failures, 1);
+		assert_eq!(reloaded.next_attempt, Some((now + retry_delay(1)) as i64));
+
+		record.failures = RETRY_LIMIT as i32 - 1;
+		assert!(!api.attempt_direct_message(&record, now).await.unwrap());
+		let (_, messages) = api.load_conversation("", id, 10).await.unwrap().unwrap();
+		assert!(messages[0].failed);
+	}
+
+	#[test]
+	fn test_retry_delay() {
+		assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+		assert_eq!(retry_delay(1), 2 * RETRY_BASE_DELAY);
+		assert_eq!(retry_delay(RETRY_LIMIT), RETRY_MAX_DELAY);
+	}
+}
@@ -0,0 +1,124 @@
+//! Proves that our identities belong to a domain, and checks the domains that
+//! other actors claim to belong to. See the [`crate::domain`] module for the
+//! document that the domain serves.
+
+use base58::ToBase58;
+
+use super::Api;
+use crate::{
+	core::ActorAddress,
+	db,
+	domain::{self, DomainDocument, DomainDocumentEntry},
+};
+
+
+const DOMAIN_SIGN_CONTEXT: &str = "stonenet domain verification v1";
+
+
+impl Api {
+	/// Composes the document that the domain has to serve, to prove that the
+	/// identity belongs to it. Returns None if there is no identity with the
+	/// label.
+	pub async fn compose_domain_document(
+		&self, label: &str, domain: &str,
+	) -> domain::Result<Option<DomainDocument>> {
+		let domain = domain::normalize_domain_name(domain)?;
+		let (address, signature) = match self
+			.sign_with_context(label, DOMAIN_SIGN_CONTEXT, domain.as_bytes())
+			.await?
+		{
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		Ok(Some(DomainDocument {
+			actors: vec![DomainDocumentEntry {
+				address: address.to_bech32(),
+				signature: signature.as_bytes().to_base58(),
+			}],
+		}))
+	}
+
+	/// Checks whether the domain vouches for the actor, and remembers the
+	/// outcome. Returns None if the actor couldn't be found.
+	pub async fn verify_domain(
+		&self, address: &ActorAddress, domain: &str,
+	) -> domain::Result<Option<bool>> {
+		let domain = domain::normalize_domain_name(domain)?;
+		let document = domain::fetch_document(&domain).await?;
+		self.check_domain_document(address, &domain, &document).await
+	}
+
+	async fn check_domain_document(
+		&self, address: &ActorAddress, domain: &str, document: &DomainDocument,
+	) -> domain::Result<Option<bool>> {
+		let is_valid = match document.find_signature(address) {
+			Some(signature) => match self
+				.verify_with_context(address, DOMAIN_SIGN_CONTEXT, domain.as_bytes(), &signature)
+				.await?
+			{
+				Some(v) => v,
+				None => return Ok(None),
+			},
+			None => false,
+		};
+
+		if is_valid {
+			domain::store_verification(&self.db, address, domain).await?;
+		} else {
+			domain::remove_verification(&self.db, address, domain).await?;
+		}
+		Ok(Some(is_valid))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_domain_document() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("domain_verification").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, actor_info) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		api.db.ensure_actor_id(&address, &actor_info).await.unwrap();
+
+		let document = api
+			.compose_domain_document("test", "Example.com.")
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(document.find_signature(&address).is_some());
+		let json = serde_json::to_string(&document).unwrap();
+		let document: DomainDocument = serde_json::from_str(&json).unwrap();
+
+		assert_eq!(
+			api.check_domain_document(&address, "example.com", &document)
+				.await
+				.unwrap(),
+			Some(true)
+		);
+		assert_eq!(
+			domain::load_verified_domains(&api.db, &address).await.unwrap(),
+			vec!["example.com".to_string()]
+		);
+
+		// The document can't be copied over to another domain
+		assert_eq!(
+			api.check_domain_document(&address, "example.org", &document)
+				.await
+				.unwrap(),
+			Some(false)
+		);
+		assert!(api
+			.compose_domain_document("test", "not a domain")
+			.await
+			.is_err());
+	}
+}
@@ -0,0 +1,337 @@
+//! Lets the user save a post before publishing it, and come back to it later.
+//! See the `draft` module of `db` for how the attachments are kept around in
+//! the meantime. Drafts that are scheduled are published by
+//! [`publish_scheduled_posts`] once they are due.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::DateTime;
+use log::*;
+use sea_orm::prelude::*;
+use serde::Serialize;
+
+use super::Api;
+use crate::{
+	common::{current_timestamp, IdType},
+	core::{ActorAddress, FileData},
+	db::{self, local_user, PersistenceHandle},
+	entity::{draft, file, scheduled_post},
+	identity::Signer,
+	web::info::object_url,
+};
+
+
+/// How often is checked whether any scheduled posts are due.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+
+#[derive(Serialize)]
+pub struct DraftInfo {
+	pub id: i64,
+	pub message: String,
+	/// The URL of the object that the draft replies to, if any.
+	pub in_reply_to: Option<String>,
+	pub created: String,
+	pub updated: String,
+	pub attachments: Vec<DraftAttachmentInfo>,
+	pub schedule: Option<DraftScheduleInfo>,
+}
+
+#[derive(Serialize)]
+pub struct DraftAttachmentInfo {
+	pub hash: String,
+	pub mime_type: String,
+}
+
+#[derive(Serialize)]
+pub struct DraftScheduleInfo {
+	pub actor_address: String,
+	pub publish_at: String,
+	/// Why the last attempt to publish the draft failed, if it did.
+	pub last_error: Option<String>,
+}
+
+
+impl Api {
+	/// Loads the draft with its attachments.
+	pub async fn load_draft(&self, url_base: &str, id: i64) -> db::Result<Option<DraftInfo>> {
+		let (record, file_hashes) = match self.db.load_draft(id).await? {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+
+		let mut attachments = Vec::with_capacity(file_hashes.len());
+		for hash in file_hashes {
+			let mime_type = file::Entity::find()
+				.filter(file::Column::Hash.eq(&hash))
+				.one(self.db.inner())
+				.await?
+				.map(|f| f.mime_type)
+				.unwrap_or_default();
+			attachments.push(DraftAttachmentInfo {
+				hash: hash.to_string(),
+				mime_type,
+			});
+		}
+		let schedule = self.db.load_draft_schedule(id).await?;
+		Ok(Some(draft_info(url_base, record, attachments, schedule)))
+	}
+
+	/// Loads all drafts, without their attachments.
+	pub async fn load_drafts(&self, url_base: &str) -> db::Result<Vec<DraftInfo>> {
+		let records = self.db.load_drafts().await?;
+		let mut schedules: HashMap<i64, scheduled_post::Model> = self
+			.db
+			.load_scheduled_posts(i64::MAX)
+			.await?
+			.into_iter()
+			.map(|s| (s.draft_id, s))
+			.collect();
+		Ok(records
+			.into_iter()
+			.map(|record| {
+				let schedule = schedules.remove(&record.id);
+				draft_info(url_base, record, Vec::new(), schedule)
+			})
+			.collect())
+	}
+
+	/// Publishes the drafts that are scheduled to be published at or before
+	/// the given unix timestamp in milliseconds. The ones that fail to be
+	/// published are tried again the next time. Returns the number of posts
+	/// that have been published.
+	pub async fn publish_due_drafts(&self, now: i64) -> db::Result<usize> {
+		let mut published = 0;
+		for scheduled in self.db.load_scheduled_posts(now).await? {
+			let address = &scheduled.actor_address;
+			// The draft is published on behalf of the local user that wrote it
+			let user_id = match draft::Entity::find_by_id(scheduled.draft_id)
+				.one(self.db.inner())
+				.await?
+			{
+				Some(d) => d.user_id,
+				None => continue,
+			};
+			let result = match self.fetch_my_identity(address).await? {
+				Some((_, signer)) =>
+					local_user::scope_user(
+						user_id,
+						self.publish_draft(scheduled.draft_id, address, &*signer),
+					)
+					.await,
+				None => Err(db::Error::UnexpectedState(format!(
+					"identity {} doesn't exist anymore",
+					address
+				))
+				.into()),
+			};
+			match result {
+				Ok(_) => published += 1,
+				Err(e) => {
+					warn!(
+						"Unable to publish scheduled draft {}: {}",
+						scheduled.draft_id, e
+					);
+					self.db
+						.set_scheduled_post_error(scheduled.id, &e.to_string())
+						.await?;
+				}
+			}
+		}
+		Ok(published)
+	}
+
+	/// Publishes the draft as a post, after which the draft is removed. Returns
+	/// the hash of the new post, or `None` if the draft doesn't exist.
+	pub async fn publish_draft(
+		&self, id: i64, identity: &ActorAddress, signer: &dyn Signer,
+	) -> db::Result<Option<IdType>> {
+		let (record, file_hashes) = match self.db.load_draft(id).await? {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+
+		let mut attachments: Vec<FileData> = Vec::with_capacity(file_hashes.len());
+		for hash in &file_hashes {
+			match self.db.load_file_data(hash).await? {
+				Some(file_data) => attachments.push(file_data),
+				None => Err(db::Error::UnexpectedState(format!(
+					"attachment {} of draft {} is missing",
+					hash, id
+				)))?,
+			}
+		}
+		let in_reply_to = match (
+			record.in_reply_to_actor_address,
+			record.in_reply_to_object_hash,
+		) {
+			(Some(actor_address), Some(object_hash)) => Some((actor_address, object_hash)),
+			_ => None,
+		};
+
+		// The attachments are stored already, so publishing only refers to them
+		let hash = self
+			.publish_post(
+				identity,
+				signer,
+				"text/markdown",
+				&record.message,
+				Vec::new(),
+				&attachments,
+				in_reply_to,
+			)
+			.await?;
+		self.db.delete_draft(id).await?;
+		Ok(Some(hash))
+	}
+
+	/// Stores the post as a draft that gets published by the given identity at
+	/// the given unix timestamp in milliseconds. Returns the ID of the draft.
+	pub async fn schedule_post(
+		&self, identity: &ActorAddress, message: &str, attachments: &[FileData],
+		in_reply_to: Option<&(ActorAddress, IdType)>, publish_at: i64,
+	) -> db::Result<i64> {
+		let id = self
+			.db
+			.create_draft(message, attachments, in_reply_to)
+			.await?;
+		self.db.schedule_draft(id, identity, publish_at).await?;
+		Ok(id)
+	}
+}
+
+/// Publishes the scheduled posts once they are due, for as long as the node
+/// runs.
+pub async fn publish_scheduled_posts(api: Api) {
+	while api.node.sleep_while_running(SCHEDULE_INTERVAL).await {
+		match api.publish_due_drafts(current_timestamp() as i64).await {
+			Ok(0) => {}
+			Ok(published) => info!("Published {} scheduled post(s).", published),
+			Err(e) => error!("Unable to publish scheduled posts: {}", e),
+		}
+	}
+}
+
+fn draft_info(
+	url_base: &str, record: draft::Model, attachments: Vec<DraftAttachmentInfo>,
+	schedule: Option<scheduled_post::Model>,
+) -> DraftInfo {
+	let in_reply_to = match (
+		&record.in_reply_to_actor_address,
+		&record.in_reply_to_object_hash,
+	) {
+		(Some(actor_address), Some(object_hash)) =>
+			Some(object_url(url_base, actor_address, object_hash)),
+		_ => None,
+	};
+	DraftInfo {
+		id: record.id,
+		message: record.message,
+		in_reply_to,
+		created: format_timestamp(record.created),
+		updated: format_timestamp(record.updated),
+		attachments,
+		schedule: schedule.map(|s| DraftScheduleInfo {
+			actor_address: s.actor_address.to_string(),
+			publish_at: format_timestamp(s.publish_at),
+			last_error: s.last_error,
+		}),
+	}
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+	match DateTime::from_timestamp_millis(timestamp) {
+		Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+		None => String::new(),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{entity::object, test};
+
+	#[tokio::test]
+	async fn test_publish_draft() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api_draft").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		api.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (address, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+
+		let attachment = FileData {
+			mime_type: "text/plain".into(),
+			data: b"Attached".to_vec(),
+		};
+		let id = api
+			.db
+			.create_draft("Not done yet", &[attachment], None)
+			.await
+			.unwrap();
+		let draft = api.load_draft("", id).await.unwrap().unwrap();
+		assert_eq!(draft.attachments.len(), 1);
+		assert_eq!(draft.attachments[0].mime_type, "text/plain");
+		assert_eq!(api.load_drafts("").await.unwrap().len(), 1);
+
+		let hash = api
+			.publish_draft(id, &address, &*signer)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(object::Entity::find()
+			.filter(object::Column::Hash.eq(&hash))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.is_some());
+		assert!(api.load_draft("", id).await.unwrap().is_none());
+		assert!(api
+			.publish_draft(id, &address, &*signer)
+			.await
+			.unwrap()
+			.is_none());
+
+		// The attachment now belongs to the post, so it is kept
+		let file_hash = IdType::from_base58(&draft.attachments[0].hash).unwrap();
+		assert!(api.db.load_file_data(&file_hash).await.unwrap().is_some());
+	}
+
+	#[tokio::test]
+	async fn test_publish_due_drafts() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api_draft_schedule").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+
+		let first = api
+			.schedule_post(&address, "First", &[], None, 1000)
+			.await
+			.unwrap();
+		let second = api
+			.schedule_post(&address, "Second", &[], None, 2000)
+			.await
+			.unwrap();
+		let draft = api.load_draft("", second).await.unwrap().unwrap();
+		assert_eq!(draft.schedule.unwrap().actor_address, address.to_string());
+
+		assert_eq!(api.publish_due_drafts(999).await.unwrap(), 0);
+		assert_eq!(api.publish_due_drafts(1500).await.unwrap(), 1);
+		assert!(api.load_draft("", first).await.unwrap().is_none());
+		assert!(api.load_draft("", second).await.unwrap().is_some());
+
+		// A draft that can't be published stays scheduled, with the reason
+		let unknown = ActorAddress::V1(IdType::random(&mut rng));
+		api.db.schedule_draft(second, &unknown, 2000).await.unwrap();
+		assert_eq!(api.publish_due_drafts(2000).await.unwrap(), 0);
+		let draft = api.load_draft("", second).await.unwrap().unwrap();
+		assert!(draft.schedule.unwrap().last_error.is_some());
+	}
+}
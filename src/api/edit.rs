@@ -0,0 +1,221 @@
+//! Editing posts that have been published already.
+//!
+//! A post itself can't be changed, because it is signed and part of the chain
+//! of objects of its actor. Instead, an edit object is published that refers
+//! to the post and carries the files of its new version. Edits are only taken
+//! into account when they are in the chain of the same actor as the post, so
+//! only its author can edit it.
+
+use chrono::Utc;
+use log::*;
+use sea_orm::{prelude::*, QueryOrder};
+
+use super::{delegation, Api};
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		PersistenceHandle,
+	},
+	entity::*,
+	identity::Signer,
+	media, util,
+};
+
+
+impl Api {
+	/// Publishes a new version of one of the posts of the actor. If no
+	/// attachments are given, those of the current version are kept. Returns the
+	/// hash of the edit object, or `None` if the actor has no such post, or if it
+	/// has been deleted.
+	pub async fn publish_edit(
+		&self, actor_address: &ActorAddress, signer: &dyn Signer, object_hash: &IdType,
+		msg_mime_type: &str, message: &str, attachments: Option<&[FileData]>,
+	) -> db::Result<Option<IdType>> {
+		let mut message = message.to_string();
+		if msg_mime_type.starts_with("text/") {
+			message = self.expand_mentions(&message).await?;
+		}
+		let medias: Vec<_> = util::block_in_place(|| {
+			attachments
+				.unwrap_or_default()
+				.iter()
+				.map(|f| {
+					(
+						media::extract_metadata(f.mime_type.as_str(), &f.data),
+						media::generate_thumbnail(f.mime_type.as_str(), &f.data),
+					)
+				})
+				.collect()
+		});
+
+		let tx = self.db.transaction().await?;
+		let post = object::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(actor_address))
+			.filter(object::Column::Hash.eq(object_hash))
+			.one(tx.inner())
+			.await?;
+		let post = match post {
+			Some(o) if o.r#type == OBJECT_TYPE_POST => o,
+			_ => return Ok(None),
+		};
+		let actor_id = post.actor_id;
+		if self.db.is_object_deleted(actor_id, object_hash).await? {
+			return Ok(None);
+		}
+
+		// Store all files of the new version
+		let mut files = Vec::with_capacity(attachments.map(|a| a.len()).unwrap_or(0) + 1);
+		let (_, file_hash, _) = tx.create_file2(msg_mime_type, message.as_bytes()).await?;
+		files.push(file_hash);
+		if attachments.is_none() {
+			files.extend(load_current_attachments(&tx, &post).await?);
+		}
+		let attachments_with_media = attachments.unwrap_or_default().iter().zip(&medias);
+		for (FileData { mime_type, data }, (metadata, thumbnail)) in attachments_with_media {
+			let (_, file_hash, _) = tx.create_file2(mime_type.as_str(), data).await?;
+			if let Some(metadata) = metadata {
+				tx.store_file_metadata(&file_hash, mime_type.as_str(), metadata)
+					.await?;
+			}
+			if let Some(thumbnail) = thumbnail {
+				tx.store_file_thumbnail(&file_hash, thumbnail).await?;
+			}
+			files.push(file_hash);
+		}
+
+		// Sign the edit
+		let sequence = tx.find_next_object_sequence(actor_id).await?;
+		let previous_hash = match object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Sequence.eq(sequence as i64 - 1))
+			.one(tx.inner())
+			.await?
+		{
+			Some(object) => object.hash,
+			None => Err(db::Error::UnexpectedState(format!(
+				"can't find object sequence {} for actor {}",
+				sequence as i64 - 1,
+				actor_id
+			)))?,
+		};
+		let payload = ObjectPayload::Edit(EditObject {
+			object_hash: object_hash.clone(),
+			files: files.clone().into(),
+		});
+		let delegation = delegation::load_delegation_for(&tx, actor_id, &payload).await?;
+		let created = Utc::now().timestamp_millis() as u64;
+		let (hash, signature) =
+			Self::sign_object(sequence, &previous_hash, created, &payload, signer)?;
+
+		tx.store_edit(
+			actor_id,
+			created,
+			&hash,
+			&previous_hash,
+			&signature,
+			object_hash,
+			&files,
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::EditedPost,
+			&actor_address.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
+
+		let object = BlogchainObject {
+			created,
+			sequence,
+			previous_hash,
+			signature,
+			payload,
+			delegation,
+		};
+		if let Some(actor_node) = self.node.get_actor_node(&actor_address.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, &hash, &object)
+				.await;
+			actor_node.distribute_parity(files);
+		} else {
+			error!("Actor node not found.");
+		}
+		Ok(Some(hash))
+	}
+}
+
+/// Loads the hashes of the attachments of the latest version of the post.
+async fn load_current_attachments(
+	tx: &db::Transaction, post: &object::Model,
+) -> db::Result<Vec<IdType>> {
+	let latest_edit = edit_object::Entity::find()
+		.inner_join(object::Entity)
+		.filter(object::Column::ActorId.eq(post.actor_id))
+		.filter(edit_object::Column::EditedObjectHash.eq(&post.hash))
+		.order_by_desc(object::Column::Sequence)
+		.one(tx.inner())
+		.await?;
+	let version_id = latest_edit.map(|e| e.object_id).unwrap_or(post.id);
+	Ok(post_file::Entity::find()
+		.filter(post_file::Column::ObjectId.eq(version_id))
+		.filter(post_file::Column::Sequence.gt(0))
+		.order_by_asc(post_file::Column::Sequence)
+		.all(tx.inner())
+		.await?
+		.into_iter()
+		.map(|r| r.hash)
+		.collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{test, web::info::load_post_history};
+
+	#[tokio::test]
+	async fn test_publish_edit() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("edit").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+		let post_hash = api
+			.publish_post(&address, &*signer, "text/plain", "Tpyo", Vec::new(), &[], None)
+			.await
+			.unwrap();
+		let edit_hash = api
+			.publish_edit(&address, &*signer, &post_hash, "text/plain", "Typo", None)
+			.await
+			.unwrap()
+			.expect("post not found");
+		// Only posts can be edited, not the edits themselves
+		let result = api
+			.publish_edit(&address, &*signer, &edit_hash, "text/plain", "Again", None)
+			.await
+			.unwrap();
+		assert!(result.is_none());
+
+		let history = load_post_history(&api.db, "", &address, &post_hash)
+			.await
+			.unwrap()
+			.unwrap();
+		let bodies: Vec<_> = history
+			.iter()
+			.map(|v| v.message.as_ref().unwrap().body.as_str())
+			.collect();
+		assert_eq!(bodies, vec!["Typo", "Tpyo"]);
+	}
+}
@@ -0,0 +1,195 @@
+//! Asking to follow actors that only hand out their objects to the followers
+//! they approved, and deciding on the requests that our identities get. See
+//! the `follower` module of `db` for how access is granted.
+
+use log::*;
+use sea_orm::prelude::*;
+
+use super::Api;
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		PersistenceHandle,
+	},
+	entity::*,
+	identity::Signer,
+};
+
+
+impl Api {
+	/// Accepts or rejects the follow request that the identity has received,
+	/// and publishes the followers that are approved after that. Returns
+	/// `None` if the request doesn't exist, or else the hash of the new
+	/// follower list.
+	pub async fn decide_follow_request(
+		&self, signer: &dyn Signer, actor_id: i64, request_id: i64, accept: bool,
+	) -> db::Result<Option<IdType>> {
+		let identity = match actor::Entity::find_by_id(actor_id)
+			.one(self.db.inner())
+			.await?
+		{
+			Some(a) => a.address,
+			None => return Ok(None),
+		};
+		let request = match self
+			.db
+			.decide_follow_request(actor_id, request_id, accept)
+			.await?
+		{
+			Some(r) => r,
+			None => return Ok(None),
+		};
+
+		let list = FollowerListObject {
+			followers: self.db.load_approved_followers(actor_id).await?.into(),
+		};
+		let tx = self.db.transaction().await?;
+		let payload = ObjectPayload::FollowerList(list.clone());
+		let (object, hash) = Self::sign_new_object(&tx, actor_id, payload, signer).await?;
+		tx.store_follower_list(
+			actor_id,
+			object.created,
+			&hash,
+			&object.previous_hash,
+			&object.signature,
+			&list,
+		)
+		.await?;
+		let action = if accept {
+			JournalAction::AcceptedFollower
+		} else {
+			JournalAction::RejectedFollower
+		};
+		journal::record(
+			&tx,
+			action,
+			&identity.to_string(),
+			Some(&hash),
+			Some(&request.requester_address.to_string()),
+		)
+		.await?;
+		tx.commit().await?;
+
+		self.publish_own_object(&identity, &hash, &object).await;
+		Ok(Some(hash))
+	}
+
+	/// Asks the actor to approve the identity as a follower, for this node.
+	/// Returns false if the node of the actor couldn't be reached.
+	pub async fn request_follow(
+		&self, identity: &ActorAddress, signer: &dyn Signer, actor_address: &ActorAddress,
+	) -> db::Result<bool> {
+		let requester_info = match self.db.find_actor_info(identity).await? {
+			Some(info) => info,
+			None => return Ok(false),
+		};
+		let request = FollowRequestObject::new(
+			identity.clone(),
+			actor_address.clone(),
+			self.node.node_id().clone(),
+			signer,
+		)?;
+		let actor_node = match self.node.get_actor_node_or_lurker(actor_address).await {
+			Some(n) => n,
+			None => {
+				warn!("Unable to reach the actor network of {}.", actor_address);
+				return Ok(false);
+			}
+		};
+		if !actor_node
+			.deliver_follow_request(&requester_info, &request)
+			.await
+		{
+			return Ok(false);
+		}
+
+		journal::record(
+			&self.db,
+			JournalAction::RequestedFollow,
+			&identity.to_string(),
+			Some(&request.hash()),
+			Some(&actor_address.to_string()),
+		)
+		.await?;
+		Ok(true)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_decide_follow_request() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("follower").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (requester, _) = api
+			.create_identity("requester", "Requester", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.fetch_my_identity(&address).await.unwrap().unwrap();
+		let (_, requester_signer) = api.fetch_my_identity(&requester).await.unwrap().unwrap();
+		let actor_id = actor::Entity::find()
+			.filter(actor::Column::Address.eq(&address))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap()
+			.id;
+
+		let node_address = api.node.node_id().clone();
+		let request = FollowRequestObject::new(
+			requester.clone(),
+			address.clone(),
+			node_address.clone(),
+			&*requester_signer,
+		)
+		.unwrap();
+		assert!(api.db.store_follow_request(actor_id, &request).await.unwrap());
+		// The same request doesn't have to be decided on again
+		assert!(!api.db.store_follow_request(actor_id, &request).await.unwrap());
+		assert!(!api
+			.db
+			.is_approved_follower_node(actor_id, &node_address)
+			.await
+			.unwrap());
+
+		let request_id = api.db.load_follow_requests(actor_id).await.unwrap()[0].id;
+		api.decide_follow_request(&*signer, actor_id, request_id, true)
+			.await
+			.unwrap()
+			.expect("follow request not found");
+		assert!(api
+			.db
+			.is_approved_follower_node(actor_id, &node_address)
+			.await
+			.unwrap());
+
+		api.decide_follow_request(&*signer, actor_id, request_id, false)
+			.await
+			.unwrap()
+			.expect("follow request not found");
+		assert!(!api
+			.db
+			.is_approved_follower_node(actor_id, &node_address)
+			.await
+			.unwrap());
+		let result = api
+			.decide_follow_request(&*signer, actor_id, request_id + 1, true)
+			.await
+			.unwrap();
+		assert!(result.is_none());
+	}
+}
@@ -0,0 +1,179 @@
+//! Lets clients safely retry mutating operations.
+//!
+//! A client may supply an idempotency key with a request that publishes
+//! something, follows an actor or changes settings. The first request with a
+//! key claims it in the database, and once the operation has been performed,
+//! its result is stored along with the key. A retry of the same request, for
+//! example after it timed out on the client side, then gets the stored result
+//! back instead of performing the operation a second time. Keys are forgotten
+//! after a day.
+
+use std::future::Future;
+
+use chrono::Utc;
+use log::*;
+use sea_orm::{prelude::*, sea_query::OnConflict, Set};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::Api;
+use crate::{
+	db::{self, PersistenceHandle},
+	entity::idempotency_key,
+	net::binserde,
+};
+
+
+const KEY_LENGTH_LIMIT: usize = 128;
+/// How long the result of an operation is remembered, in milliseconds.
+const KEY_TTL: i64 = 24 * 3600 * 1000;
+
+
+impl Api {
+	/// Performs the operation at most once for the given idempotency key. If
+	/// the operation has been performed with the key before, its result is
+	/// returned again. Without a key, the operation is simply performed.
+	pub async fn perform_idempotent<T>(
+		&self, key: Option<&str>, operation: &str, f: impl Future<Output = db::Result<T>>,
+	) -> db::Result<T>
+	where
+		T: Serialize + DeserializeOwned,
+	{
+		let key = match key {
+			None => return f.await,
+			Some(k) => k,
+		};
+		if key.is_empty() || key.len() > KEY_LENGTH_LIMIT {
+			Err(db::Error::InvalidIdempotencyKey)?;
+		}
+
+		let now = Utc::now().timestamp_millis();
+		idempotency_key::Entity::delete_many()
+			.filter(idempotency_key::Column::Created.lt(now - KEY_TTL))
+			.exec(self.db.inner())
+			.await?;
+
+		// Claim the key, unless it has been claimed before
+		let model = idempotency_key::ActiveModel {
+			key: Set(key.to_string()),
+			operation: Set(operation.to_string()),
+			result: Set(None),
+			created: Set(now),
+		};
+		let claimed = idempotency_key::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(idempotency_key::Column::Key)
+					.do_nothing()
+					.to_owned(),
+			)
+			.exec_without_returning(self.db.inner())
+			.await? > 0;
+		if !claimed {
+			return self.load_idempotent_result(key, operation).await;
+		}
+
+		match f.await {
+			Ok(result) => {
+				let buffer = binserde::serialize(&result).expect("unable to serialize result");
+				if let Err(e) = idempotency_key::Entity::update_many()
+					.col_expr(idempotency_key::Column::Result, Expr::value(buffer))
+					.filter(idempotency_key::Column::Key.eq(key))
+					.exec(self.db.inner())
+					.await
+				{
+					error!("Unable to store result for idempotency key {}: {}", key, e);
+				}
+				Ok(result)
+			}
+			Err(e) => {
+				// Release the key so that the operation can be retried with it
+				if let Err(e) = idempotency_key::Entity::delete_by_id(key)
+					.exec(self.db.inner())
+					.await
+				{
+					error!("Unable to release idempotency key {}: {}", key, e);
+				}
+				Err(e)
+			}
+		}
+	}
+
+	async fn load_idempotent_result<T>(&self, key: &str, operation: &str) -> db::Result<T>
+	where
+		T: DeserializeOwned,
+	{
+		let record = idempotency_key::Entity::find_by_id(key)
+			.one(self.db.inner())
+			.await?;
+		match record {
+			// The key may have expired in the meantime, but then the operation
+			// was performed long ago.
+			None => Err(db::Error::RequestInProgress)?,
+			Some(r) if r.operation != operation => Err(db::Error::InvalidIdempotencyKey)?,
+			Some(r) => match r.result {
+				None => Err(db::Error::RequestInProgress)?,
+				Some(buffer) => Ok(binserde::deserialize(&buffer).map_err(|e| {
+					db::Error::UnexpectedState(format!("unable to parse stored result: {}", e))
+				})?),
+			},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicU32, Ordering};
+
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[tokio::test]
+	async fn test_idempotency() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("api").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let performed = AtomicU32::new(0);
+		let perform = |key: Option<&'static str>, operation: &'static str, value: u32| {
+			let performed = &performed;
+			api.perform_idempotent(key, operation, async move {
+				performed.fetch_add(1, Ordering::Relaxed);
+				db::Result::Ok(value)
+			})
+		};
+		assert_eq!(perform(Some("a"), "test", 1).await.unwrap(), 1);
+		assert_eq!(perform(Some("a"), "test", 2).await.unwrap(), 1);
+		assert_eq!(perform(Some("b"), "test", 3).await.unwrap(), 3);
+		assert_eq!(perform(None, "test", 4).await.unwrap(), 4);
+		assert!(perform(Some("a"), "other", 5).await.is_err());
+		assert!(perform(Some(""), "test", 6).await.is_err());
+		assert_eq!(performed.load(Ordering::Relaxed), 3);
+
+		// A failed operation releases the key
+		let result = api
+			.perform_idempotent(Some("c"), "test", async {
+				db::Result::<u32>::Err(db::Error::UnexpectedState("test".into()).into())
+			})
+			.await;
+		assert!(result.is_err());
+		assert_eq!(perform(Some("c"), "test", 7).await.unwrap(), 7);
+		assert_eq!(performed.load(Ordering::Relaxed), 4);
+
+		// Hashes come back intact
+		let hash = IdType::random(&mut rng);
+		let hash2 = hash.clone();
+		let first = api
+			.perform_idempotent(Some("d"), "publish", async { db::Result::Ok(hash2) })
+			.await
+			.unwrap();
+		let second = api
+			.perform_idempotent(Some("d"), "publish", async {
+				db::Result::Ok(IdType::default())
+			})
+			.await
+			.unwrap();
+		assert_eq!(first, hash);
+		assert_eq!(second, hash);
+	}
+}
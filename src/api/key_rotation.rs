@@ -0,0 +1,161 @@
+//! Hands our identities over to new private keys.
+//!
+//! The new key is announced with a key rotation object, which is signed with
+//! the current key like any other object, so that the followers of the
+//! identity start accepting the objects of the new key from then on. The
+//! address of the identity stays the same. The new key is derived from new
+//! entropy, so the identity has to be backed up again afterwards.
+
+use chrono::Utc;
+use log::*;
+use sea_orm::{prelude::*, NotSet, Set};
+
+use super::{mnemonic, Api};
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		PersistenceHandle,
+	},
+	entity::*,
+};
+
+
+impl Api {
+	/// Replaces the private key of the identity with a new one, and publishes
+	/// the key rotation object for it. Returns the hash of that object, or None
+	/// if there is no identity with the label.
+	pub async fn rotate_identity_key(&self, label: &str) -> db::Result<Option<IdType>> {
+		let tx = self.db.transaction().await?;
+		let (record, actor) = match identity::Entity::find_by_id(label.to_string())
+			.find_also_related(actor::Entity)
+			.one(tx.inner())
+			.await?
+		{
+			Some((r, Some(a))) => (r, a),
+			_ => return Ok(None),
+		};
+		// Only the device that holds the key of the identity itself may rotate it
+		if record.delegation.is_some() {
+			Err(db::Error::NotDelegated)?;
+		}
+		let signer = self.db.keyring().load_signer(&record.private_key)?;
+		let (new_private_key, seed) = mnemonic::generate_identity_key();
+
+		let sequence = tx.find_next_object_sequence(actor.id).await?;
+		let previous_hash = match object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor.id))
+			.filter(object::Column::Sequence.eq(sequence as i64 - 1))
+			.one(tx.inner())
+			.await?
+		{
+			Some(object) => object.hash,
+			None => Err(db::Error::UnexpectedState(format!(
+				"can't find object sequence {} for actor {}",
+				sequence as i64 - 1,
+				actor.id
+			)))?,
+		};
+		let rotation = KeyRotationObject::new(&actor.address, sequence, &new_private_key);
+		let payload = ObjectPayload::KeyRotation(rotation.clone());
+		let created = Utc::now().timestamp_millis() as u64;
+		let (hash, signature) =
+			Self::sign_object(sequence, &previous_hash, created, &payload, &*signer)?;
+
+		let result = object::Entity::insert(object::ActiveModel {
+			id: NotSet,
+			actor_id: Set(actor.id),
+			hash: Set(hash.clone()),
+			signature: Set(signature.clone()),
+			sequence: Set(sequence as _),
+			previous_hash: Set(previous_hash.clone()),
+			created: Set(created as _),
+			verified_from_start: Set(true),
+			found: Set(created as _),
+			r#type: Set(OBJECT_TYPE_KEY_ROTATION),
+			published_on_fediverse: Set(false),
+			delegation: Set(None),
+		})
+		.exec(tx.inner())
+		.await?;
+		key_rotation_object::Entity::insert(key_rotation_object::ActiveModel {
+			object_id: Set(result.last_insert_id),
+			new_public_key: Set(rotation.new_public_key.to_bytes().to_vec()),
+			proof: Set(rotation.proof),
+		})
+		.exec(tx.inner())
+		.await?;
+
+		let mut model: identity::ActiveModel = record.into();
+		model.private_key = Set(self.db.keyring().encrypt(new_private_key.as_bytes())?);
+		model.seed = Set(Some(self.db.keyring().encrypt(&seed)?));
+		model.update(tx.inner()).await?;
+		journal::record(
+			&tx,
+			JournalAction::RotatedKey,
+			&actor.address.to_string(),
+			Some(&hash),
+			Some(label),
+		)
+		.await?;
+		tx.commit().await?;
+
+		let object = BlogchainObject {
+			signature,
+			sequence,
+			previous_hash,
+			created,
+			payload,
+			delegation: None,
+		};
+		if let Some(actor_node) = self.node.get_actor_node(&actor.address.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, &hash, &object)
+				.await;
+		} else {
+			error!("Actor node not found.");
+		}
+		Ok(Some(hash))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{identity::Signer, test};
+
+	#[tokio::test]
+	async fn test_rotate_identity_key() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("key_rotation").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, actor_info) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let actor_id = api.db.ensure_actor_id(&address, &actor_info).await.unwrap();
+		let hash = api.rotate_identity_key("test").await.unwrap().unwrap();
+		assert!(api.rotate_identity_key("unknown").await.unwrap().is_none());
+
+		// The identity keeps its address, but publishes with the new key
+		let (active_address, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+		assert_eq!(active_address, address);
+		assert_ne!(signer.public(), actor_info.public_key);
+		let current_key = api.db.load_current_public_key(actor_id).await.unwrap();
+		assert_eq!(current_key, Some(signer.public()));
+
+		let object = object::Entity::find()
+			.filter(object::Column::Hash.eq(&hash))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(object.r#type, OBJECT_TYPE_KEY_ROTATION);
+		assert_eq!(object.sequence, 1);
+	}
+}
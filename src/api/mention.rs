@@ -0,0 +1,94 @@
+//! Mentioning other actors in posts, with `@` followed by their address.
+//!
+//! Petnames are only known on this node, so a mention by petname is replaced
+//! by the address of the actor before the post is published.
+
+use serde::Serialize;
+
+use super::Api;
+use crate::{
+	core::ActorAddress,
+	db::{self, mention::find_mention_words},
+	naming,
+	web::info::find_profile_info,
+};
+
+
+#[derive(Serialize)]
+pub struct MentionSuggestion {
+	pub address: String,
+	pub name: Option<String>,
+	pub petname: Option<String>,
+}
+
+
+impl Api {
+	/// Replaces the mentions of petnames in the message by the addresses of the
+	/// actors they belong to.
+	pub(super) async fn expand_mentions(&self, message: &str) -> db::Result<String> {
+		let mut expanded = String::with_capacity(message.len());
+		let mut last_end = 0;
+		for (range, word) in find_mention_words(message) {
+			if let Some(address) = naming::find_by_petname(&self.db, word).await? {
+				expanded.push_str(&message[last_end..range.start]);
+				expanded.push('@');
+				expanded.push_str(&address.to_string());
+				last_end = range.end;
+			}
+		}
+		expanded.push_str(&message[last_end..]);
+		Ok(expanded)
+	}
+
+	/// Finds the actors that could be meant by a mention that starts with the
+	/// given text, among the actors that have a petname and the ones that are
+	/// followed.
+	pub async fn suggest_mentions(
+		&self, prefix: &str, limit: usize,
+	) -> db::Result<Vec<MentionSuggestion>> {
+		let prefix = prefix.to_lowercase();
+		let mut suggestions: Vec<MentionSuggestion> = Vec::new();
+		for petname in naming::load_petnames(&self.db).await? {
+			if suggestions.len() == limit {
+				return Ok(suggestions);
+			}
+			if petname.name.to_lowercase().starts_with(&prefix) {
+				suggestions.push(MentionSuggestion {
+					address: petname.actor_address.to_string(),
+					name: None,
+					petname: Some(petname.name),
+				});
+			}
+		}
+
+		for (address, _) in self.fetch_follow_list().await? {
+			if suggestions.len() == limit {
+				break;
+			}
+			let address_string = address.to_string();
+			if suggestions.iter().any(|s| s.address == address_string) {
+				continue;
+			}
+			let name = self.load_actor_name(&address).await?;
+			let matches = address_string.to_lowercase().starts_with(&prefix)
+				|| name
+					.as_ref()
+					.map(|n| n.to_lowercase().starts_with(&prefix))
+					.unwrap_or(false);
+			if matches {
+				suggestions.push(MentionSuggestion {
+					address: address_string,
+					name,
+					petname: None,
+				});
+			}
+		}
+		Ok(suggestions)
+	}
+
+	async fn load_actor_name(&self, address: &ActorAddress) -> db::Result<Option<String>> {
+		Ok(find_profile_info(&self.db, "", address)
+			.await?
+			.map(|p| p.actor.name))
+	}
+}
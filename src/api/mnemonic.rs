@@ -0,0 +1,172 @@
+//! Backs up and restores the private keys of identities with a mnemonic phrase.
+//!
+//! The private key of every new identity is derived from 256 bits of entropy,
+//! which can be written down as a BIP39 phrase of 24 words. The phrase is
+//! turned into a seed the way BIP39 prescribes, and the seed is expanded into
+//! the ed448 private key with SHAKE256. The entropy is stored along with the
+//! identity, so that the phrase can be shown again later.
+//!
+//! The phrase only holds the private key. Restoring an identity also needs its
+//! address, which is used to look up the rest of its actor info, from the
+//! database or from the network.
+//!
+//! Identities that were created before this existed, and identities with a
+//! vanity address, have a private key that wasn't derived from any entropy, so
+//! there is no phrase for them. The only
+//! backup of those is a copy of the database, which can be merged into another
+//! installation with `--import`.
+
+use bip39::Mnemonic;
+use rand::{rngs::OsRng, RngCore};
+use sea_orm::prelude::*;
+use zeroize::Zeroizing;
+
+use super::Api;
+use crate::{
+	core::ActorAddress,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		keyring::SEED_SIZE,
+		PersistenceHandle,
+	},
+	entity::identity,
+	identity::ActorPrivateKeyV1,
+};
+
+
+const WORD_COUNT: usize = 24;
+
+
+/// Generates the entropy for a new identity, and the private key that belongs
+/// to it.
+pub(super) fn generate_identity_key() -> (ActorPrivateKeyV1, Zeroizing<Vec<u8>>) {
+	let mut entropy = Zeroizing::new(vec![0u8; SEED_SIZE]);
+	OsRng.fill_bytes(&mut entropy);
+	(derive_identity_key(&entropy), entropy)
+}
+
+fn derive_identity_key(entropy: &[u8]) -> ActorPrivateKeyV1 {
+	let mnemonic = Mnemonic::from_entropy(entropy).expect("invalid entropy size");
+	ActorPrivateKeyV1::from_seed(&Zeroizing::new(mnemonic.to_seed("")))
+}
+
+fn parse_mnemonic(phrase: &str) -> db::Result<Mnemonic> {
+	let normalized = phrase
+		.split_whitespace()
+		.map(|w| w.to_lowercase())
+		.collect::<Vec<_>>()
+		.join(" ");
+	let mnemonic = match Mnemonic::parse_normalized(&normalized) {
+		Ok(m) => m,
+		Err(e) => Err(db::Error::InvalidMnemonic(e))?,
+	};
+	if mnemonic.word_count() != WORD_COUNT {
+		Err(db::Error::InvalidMnemonic(bip39::Error::BadWordCount(
+			mnemonic.word_count(),
+		)))?;
+	}
+	Ok(mnemonic)
+}
+
+impl Api {
+	/// Returns the mnemonic phrase that the private key of the identity can be
+	/// restored with. Returns None if there is no identity with the label, or
+	/// if its private key wasn't derived from one.
+	pub async fn export_identity_mnemonic(&self, label: &str) -> db::Result<Option<String>> {
+		let record = match identity::Entity::find_by_id(label.to_string())
+			.one(self.db.inner())
+			.await?
+		{
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		let seed = match &record.seed {
+			Some(s) => s,
+			None => return Ok(None),
+		};
+
+		let entropy = self.db.keyring().decrypt(seed, SEED_SIZE)?;
+		let mnemonic = Mnemonic::from_entropy(&entropy).expect("invalid entropy size");
+		Ok(Some(mnemonic.to_string()))
+	}
+
+	/// Makes the identity with the given address ours again, with the private
+	/// key that the mnemonic phrase belongs to. Returns false if the actor
+	/// couldn't be found.
+	pub async fn restore_identity_from_mnemonic(
+		&self, label: &str, address: &ActorAddress, phrase: &str,
+	) -> db::Result<bool> {
+		let mnemonic = parse_mnemonic(phrase)?;
+		let entropy = Zeroizing::new(mnemonic.to_entropy());
+		let private_key = derive_identity_key(&entropy);
+
+		let actor_info = match self.db.find_actor_info(address).await? {
+			Some(i) => i,
+			None => match self.node.find_actor(address, 100, false).await {
+				Some(r) => r.0.clone(),
+				None => return Ok(false),
+			},
+		};
+
+		let tx = self.db.transaction().await?;
+		let actor_id = tx.ensure_actor_id(address, &actor_info).await?;
+		// The identity may have rotated its key since it was created
+		if tx.load_current_public_key(actor_id).await? != Some(private_key.public()) {
+			Err(db::Error::MnemonicMismatch(address.clone()))?;
+		}
+		if identity::Entity::find()
+			.filter(identity::Column::ActorId.eq(actor_id))
+			.one(tx.inner())
+			.await?
+			.is_some()
+		{
+			Err(db::Error::UnexpectedState(format!(
+				"actor {} is already one of our identities",
+				address
+			)))?;
+		}
+		tx.store_identity(label, actor_id, &private_key, false, Some(&entropy), None)
+			.await?;
+		journal::record(
+			&tx,
+			JournalAction::RestoredIdentity,
+			&address.to_string(),
+			None,
+			Some(label),
+		)
+		.await?;
+		tx.commit().await?;
+
+		// Start storing our own objects again
+		let node = self.node.clone();
+		let address2 = address.clone();
+		self.node.tasks().spawn("restored identity joiner", async move {
+			node.join_actor_network(&address2, &actor_info).await;
+		});
+		Ok(true)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_mnemonic_key_derivation() {
+		let (private_key, entropy) = generate_identity_key();
+		let phrase = Mnemonic::from_entropy(&entropy).unwrap().to_string();
+		assert_eq!(phrase.split(' ').count(), WORD_COUNT);
+
+		// Case and spacing don't matter
+		let mnemonic = parse_mnemonic(&format!("  {}\n", phrase.to_uppercase())).unwrap();
+		let restored = derive_identity_key(&mnemonic.to_entropy());
+		assert_eq!(restored.as_bytes(), private_key.as_bytes());
+
+		// A shorter phrase, or one with a wrong checksum, is rejected
+		let words: Vec<&str> = phrase.split(' ').collect();
+		assert!(parse_mnemonic(&words[..12].join(" ")).is_err());
+		assert!(parse_mnemonic(&["abandon"; WORD_COUNT].join(" ")).is_err());
+	}
+}
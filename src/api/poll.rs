@@ -0,0 +1,251 @@
+//! Publishing polls and voting in them.
+
+use chrono::Utc;
+use log::*;
+use sea_orm::prelude::*;
+
+use super::{delegation, Api};
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		poll::{is_valid_vote, MAX_POLL_OPTIONS, MIN_POLL_OPTIONS},
+		PersistenceHandle, Transaction,
+	},
+	entity::*,
+	identity::Signer,
+	serde_limit::LimString,
+};
+
+
+impl Api {
+	/// Publishes a poll that closes at the given time, in milliseconds since
+	/// the UNIX epoch. Returns `None` if the question or the options can't be
+	/// used.
+	pub async fn publish_poll(
+		&self, identity: &ActorAddress, signer: &dyn Signer, question: &str, options: &[String],
+		multiple_choice: bool, closes: u64,
+	) -> db::Result<Option<IdType>> {
+		let options_valid = options.len() >= MIN_POLL_OPTIONS
+			&& options.len() <= MAX_POLL_OPTIONS
+			&& options.iter().all(|o| o.len() > 0 && o.len() <= 64);
+		if question.len() == 0 || question.len() > 256 || !options_valid {
+			return Ok(None);
+		}
+
+		let tx = self.db.transaction().await?;
+		let actor_id = match find_actor_id(&tx, identity).await? {
+			Some(id) => id,
+			None => return Ok(None),
+		};
+		let options: Vec<LimString<_>> = options.iter().map(|o| o.into()).collect();
+		let poll = PollObject {
+			question: question.into(),
+			options: options.into(),
+			multiple_choice,
+			closes,
+		};
+		let payload = ObjectPayload::Poll(poll.clone());
+		let (object, hash) = Self::sign_new_object(&tx, actor_id, payload, signer).await?;
+
+		tx.store_poll(
+			actor_id,
+			object.created,
+			&hash,
+			&object.previous_hash,
+			&object.signature,
+			&poll,
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::PublishedPoll,
+			&identity.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
+
+		self.publish_own_object(identity, &hash, &object).await;
+		Ok(Some(hash))
+	}
+
+	/// Publishes a vote of the identity in the poll. Returns `None` if the poll
+	/// is not known, if it has closed, or if the choices don't fit the poll.
+	pub async fn publish_vote(
+		&self, identity: &ActorAddress, signer: &dyn Signer, actor_address: &ActorAddress,
+		object_hash: &IdType, choices: &[u8],
+	) -> db::Result<Option<IdType>> {
+		let poll = match self.db.load_poll_tally(actor_address, object_hash).await? {
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		let created = Utc::now().timestamp_millis() as u64;
+		if created > poll.closes
+			|| !is_valid_vote(choices, poll.options.len(), poll.multiple_choice)
+		{
+			return Ok(None);
+		}
+
+		let tx = self.db.transaction().await?;
+		let actor_id = match find_actor_id(&tx, identity).await? {
+			Some(id) => id,
+			None => return Ok(None),
+		};
+		let vote = VoteObject {
+			actor_address: actor_address.clone(),
+			object_hash: object_hash.clone(),
+			choices: choices.to_vec().into(),
+		};
+		let payload = ObjectPayload::Vote(vote.clone());
+		let (object, hash) = Self::sign_new_object(&tx, actor_id, payload, signer).await?;
+
+		tx.store_vote(
+			actor_id,
+			object.created,
+			&hash,
+			&object.previous_hash,
+			&object.signature,
+			&vote,
+		)
+		.await?;
+		journal::record(
+			&tx,
+			JournalAction::Voted,
+			&identity.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
+
+		self.publish_own_object(identity, &hash, &object).await;
+		Ok(Some(hash))
+	}
+
+	/// Signs the payload as the next object of the actor.
+	pub(super) async fn sign_new_object(
+		tx: &Transaction, actor_id: i64, payload: ObjectPayload, signer: &dyn Signer,
+	) -> db::Result<(BlogchainObject, IdType)> {
+		let sequence = tx.find_next_object_sequence(actor_id).await?;
+		let previous_hash = match object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Sequence.eq(sequence as i64 - 1))
+			.one(tx.inner())
+			.await?
+		{
+			Some(object) => object.hash,
+			None => Err(db::Error::UnexpectedState(format!(
+				"can't find object sequence {} for actor {}",
+				sequence as i64 - 1,
+				actor_id
+			)))?,
+		};
+		let delegation = delegation::load_delegation_for(tx, actor_id, &payload).await?;
+		let created = Utc::now().timestamp_millis() as u64;
+		let (hash, signature) =
+			Self::sign_object(sequence, &previous_hash, created, &payload, signer)?;
+		let object = BlogchainObject {
+			created,
+			sequence,
+			previous_hash,
+			signature,
+			payload,
+			delegation,
+		};
+		Ok((object, hash))
+	}
+
+	pub(super) async fn publish_own_object(
+		&self, identity: &ActorAddress, hash: &IdType, object: &BlogchainObject,
+	) {
+		if let Some(actor_node) = self.node.get_actor_node(&identity.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, hash, object)
+				.await;
+		} else {
+			error!("Actor node not found.");
+		}
+	}
+}
+
+async fn find_actor_id(tx: &Transaction, address: &ActorAddress) -> db::Result<Option<i64>> {
+	Ok(actor::Entity::find()
+		.filter(actor::Column::Address.eq(address))
+		.one(tx.inner())
+		.await?
+		.map(|a| a.id))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_poll() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("poll").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+		let options = vec!["Tea".to_string(), "Coffee".to_string(), "Water".to_string()];
+		let closes = Utc::now().timestamp_millis() as u64 + 60 * 60 * 1000;
+		let poll_hash = api
+			.publish_poll(&address, &*signer, "Drinks?", &options, false, closes)
+			.await
+			.unwrap()
+			.expect("poll not published");
+		let result = api
+			.publish_poll(&address, &*signer, "Drinks?", &options[..1], false, closes)
+			.await
+			.unwrap();
+		assert!(result.is_none());
+
+		// Only the latest vote counts, and invalid votes aren't published
+		for choices in [&[0u8][..], &[2]] {
+			api.publish_vote(&address, &*signer, &address, &poll_hash, choices)
+				.await
+				.unwrap()
+				.expect("vote not published");
+		}
+		for choices in [&[0u8, 1][..], &[3], &[]] {
+			let result = api
+				.publish_vote(&address, &*signer, &address, &poll_hash, choices)
+				.await
+				.unwrap();
+			assert!(result.is_none());
+		}
+		let tally = api
+			.db
+			.load_poll_tally(&address, &poll_hash)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(tally.question, "Drinks?");
+		assert_eq!(tally.options, options);
+		assert_eq!(tally.votes, vec![0, 0, 1]);
+		assert_eq!(tally.voters, 1);
+
+		// Closed polls can't be voted in anymore
+		let closed_hash = api
+			.publish_poll(&address, &*signer, "Too late?", &options, true, 1)
+			.await
+			.unwrap()
+			.unwrap();
+		let result = api
+			.publish_vote(&address, &*signer, &address, &closed_hash, &[0])
+			.await
+			.unwrap();
+		assert!(result.is_none());
+	}
+}
@@ -0,0 +1,151 @@
+//! Reacting to posts with an emoji.
+
+use chrono::Utc;
+use log::*;
+use sea_orm::prelude::*;
+
+use super::{delegation, Api};
+use crate::{
+	common::IdType,
+	core::*,
+	db::{
+		self,
+		journal::{self, JournalAction},
+		reaction::is_valid_emoji,
+		PersistenceHandle,
+	},
+	entity::*,
+	identity::Signer,
+};
+
+
+impl Api {
+	/// Publishes a reaction of the identity to the post. Returns the hash of the
+	/// reaction object, or `None` if the emoji can't be used, or if the identity
+	/// has reacted with it to the post already.
+	pub async fn publish_reaction(
+		&self, identity: &ActorAddress, signer: &dyn Signer, actor_address: &ActorAddress,
+		object_hash: &IdType, emoji: &str,
+	) -> db::Result<Option<IdType>> {
+		if !is_valid_emoji(emoji) {
+			return Ok(None);
+		}
+
+		let tx = self.db.transaction().await?;
+		let actor_id = match actor::Entity::find()
+			.filter(actor::Column::Address.eq(identity))
+			.one(tx.inner())
+			.await?
+		{
+			Some(a) => a.id,
+			None => return Ok(None),
+		};
+		if self
+			.db
+			.has_reacted(actor_id, actor_address, object_hash, emoji)
+			.await?
+		{
+			return Ok(None);
+		}
+
+		// Sign the reaction
+		let sequence = tx.find_next_object_sequence(actor_id).await?;
+		let previous_hash = match object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Sequence.eq(sequence as i64 - 1))
+			.one(tx.inner())
+			.await?
+		{
+			Some(object) => object.hash,
+			None => Err(db::Error::UnexpectedState(format!(
+				"can't find object sequence {} for actor {}",
+				sequence as i64 - 1,
+				actor_id
+			)))?,
+		};
+		let reaction = ReactionObject {
+			actor_address: actor_address.clone(),
+			object_hash: object_hash.clone(),
+			emoji: emoji.into(),
+		};
+		let payload = ObjectPayload::Reaction(reaction.clone());
+		let delegation = delegation::load_delegation_for(&tx, actor_id, &payload).await?;
+		let created = Utc::now().timestamp_millis() as u64;
+		let (hash, signature) =
+			Self::sign_object(sequence, &previous_hash, created, &payload, signer)?;
+
+		tx.store_reaction(actor_id, created, &hash, &previous_hash, &signature, &reaction)
+			.await?;
+		journal::record(
+			&tx,
+			JournalAction::Reacted,
+			&identity.to_string(),
+			Some(&hash),
+			None,
+		)
+		.await?;
+		tx.commit().await?;
+
+		let object = BlogchainObject {
+			created,
+			sequence,
+			previous_hash,
+			signature,
+			payload,
+			delegation,
+		};
+		if let Some(actor_node) = self.node.get_actor_node(&identity.as_id()).await {
+			actor_node
+				.publish_new_object(&self.node, &hash, &object)
+				.await;
+		} else {
+			error!("Actor node not found.");
+		}
+		Ok(Some(hash))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_publish_reaction() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("reaction").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, _) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.load_active_identity_key().await.unwrap().unwrap();
+		let post_hash = api
+			.publish_post(&address, &*signer, "text/plain", "Hi", Vec::new(), &[], None)
+			.await
+			.unwrap();
+
+		for emoji in ["👍", "❤️"] {
+			api.publish_reaction(&address, &*signer, &address, &post_hash, emoji)
+				.await
+				.unwrap()
+				.expect("reaction not published");
+		}
+		// Reacting twice with the same emoji doesn't count twice
+		for emoji in ["👍", "nice"] {
+			let result = api
+				.publish_reaction(&address, &*signer, &address, &post_hash, emoji)
+				.await
+				.unwrap();
+			assert!(result.is_none());
+		}
+
+		let reactions = api.db.load_reactions(&address, &post_hash).await.unwrap();
+		assert_eq!(reactions, vec![("👍".to_string(), 1), ("❤️".to_string(), 1)]);
+		// Reactions of our own identities aren't notified of
+		assert_eq!(api.db.take_new_reactions().await.unwrap().len(), 0);
+	}
+}
@@ -0,0 +1,149 @@
+//! Signs messages with the keys of our identities, so that the owner of an
+//! actor address can prove it outside of Stonenet, like by putting a signed
+//! statement on another website.
+//!
+//! What is signed is not the message itself, but the message together with a
+//! fixed context string and the address of the actor. That way, a signed
+//! message can never pass for a signed object, and its signature can't be
+//! attributed to any other actor.
+
+use sea_orm::prelude::*;
+use serde::Serialize;
+
+use super::Api;
+use crate::{
+	core::ActorAddress,
+	db::{self, PersistenceHandle},
+	entity::*,
+	identity::ActorSignatureV1,
+	net::binserde,
+	util,
+};
+
+
+const MESSAGE_SIGN_CONTEXT: &str = "stonenet signed message v1";
+
+
+#[derive(Serialize)]
+struct MessageSignData<'a> {
+	context: &'a str,
+	actor_address: &'a ActorAddress,
+	message: &'a [u8],
+}
+
+
+fn message_sign_data(context: &str, actor_address: &ActorAddress, message: &[u8]) -> Vec<u8> {
+	binserde::serialize(&MessageSignData {
+		context,
+		actor_address,
+		message,
+	})
+	.unwrap()
+}
+
+impl Api {
+	/// Signs the message with the key of the identity. Returns the address of
+	/// the identity with the signature, or None if there is no identity with
+	/// the label.
+	pub async fn sign_message(
+		&self, label: &str, message: &[u8],
+	) -> db::Result<Option<(ActorAddress, ActorSignatureV1)>> {
+		self.sign_with_context(label, MESSAGE_SIGN_CONTEXT, message).await
+	}
+
+	/// Whether the message has been signed by the actor, with the key that it
+	/// uses at the moment. Returns None if the actor couldn't be found.
+	pub async fn verify_message(
+		&self, actor_address: &ActorAddress, message: &[u8], signature: &ActorSignatureV1,
+	) -> db::Result<Option<bool>> {
+		self.verify_with_context(actor_address, MESSAGE_SIGN_CONTEXT, message, signature).await
+	}
+
+	/// Signs the message under the given context, so that signatures made for
+	/// one purpose can't be used for another.
+	pub(super) async fn sign_with_context(
+		&self, label: &str, context: &str, message: &[u8],
+	) -> db::Result<Option<(ActorAddress, ActorSignatureV1)>> {
+		let (record, actor) = match identity::Entity::find_by_id(label.to_string())
+			.find_also_related(actor::Entity)
+			.one(self.db.inner())
+			.await?
+		{
+			Some((r, Some(a))) => (r, a),
+			_ => return Ok(None),
+		};
+		// A device key can't be verified without a certificate, which a message
+		// doesn't carry
+		if record.delegation.is_some() {
+			Err(db::Error::NotDelegated)?;
+		}
+
+		let signer = self.db.keyring().load_signer(&record.private_key)?;
+		let sign_data = message_sign_data(context, &actor.address, message);
+		let signature = util::block_in_place(|| signer.sign(&sign_data))?;
+		Ok(Some((actor.address, signature)))
+	}
+
+	pub(super) async fn verify_with_context(
+		&self, actor_address: &ActorAddress, context: &str, message: &[u8],
+		signature: &ActorSignatureV1,
+	) -> db::Result<Option<bool>> {
+		// If we know the actor, we may know that its key has been rotated
+		let public_key = match actor::Entity::find()
+			.filter(actor::Column::Address.eq(actor_address))
+			.one(self.db.inner())
+			.await?
+		{
+			Some(record) => self.db.load_current_public_key(record.id).await?,
+			None => None,
+		};
+		let public_key = match public_key {
+			Some(k) => k,
+			None => match self.node.find_actor(actor_address, 100, false).await {
+				Some(r) => r.0.public_key.clone(),
+				None => return Ok(None),
+			},
+		};
+
+		let sign_data = message_sign_data(context, actor_address, message);
+		Ok(Some(public_key.verify(&sign_data, signature)))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_signed_message() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("signed_message").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+		let (address, actor_info) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		api.db.ensure_actor_id(&address, &actor_info).await.unwrap();
+
+		let message = b"This is my Stonenet account.";
+		let (signed_by, signature) = api.sign_message("test", message).await.unwrap().unwrap();
+		assert_eq!(signed_by, address);
+		assert!(api.sign_message("unknown", message).await.unwrap().is_none());
+		assert_eq!(
+			api.verify_message(&address, message, &signature).await.unwrap(),
+			Some(true)
+		);
+		assert_eq!(
+			api.verify_message(&address, b"Something else.", &signature)
+				.await
+				.unwrap(),
+			Some(false)
+		);
+
+		// The signature is of no use for an object
+		assert!(!actor_info.public_key.verify(message, &signature));
+	}
+}
@@ -0,0 +1,621 @@
+//! Exports the user's own data to a single archive file, and restores it on
+//! another node.
+//!
+//! Unlike an import of a whole installation, the archive only contains what is
+//! the user's own: the identities, their objects with the files that are
+//! attached to them, and the list of actors that are being followed. The
+//! objects of followed actors are left out, as the new node can synchronize
+//! them again. Because the archive consists of the same structures that are
+//! sent over the network, it doesn't depend on the layout of the database.
+//!
+//! The archive starts with a header, which is followed by records that are
+//! each prefixed with their size. It ends with a record that marks its end,
+//! so that an archive that has been cut off is noticed. Whenever an archive
+//! includes the identities, it contains their private keys unencrypted, and
+//! needs to be kept safe accordingly.
+
+use std::{
+	collections::HashSet,
+	fs::File as FsFile,
+	io::{self, BufReader, BufWriter, Read, Write},
+	path::Path,
+};
+
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::Api;
+use crate::{
+	common::*,
+	core::*,
+	db::{
+		self,
+		import::ImportSummary,
+		keyring::{self, ACTOR_PRIVATE_KEY_SIZE, SEED_SIZE},
+		Connection, SyncDepth,
+	},
+	net::binserde,
+};
+
+
+const MAGIC: &[u8; 8] = b"STNARCHV";
+const VERSION: u8 = 1;
+/// The largest record that is accepted, which leaves plenty of room for a
+/// block of the maximum size.
+const MAX_RECORD_SIZE: usize = 2 * db::BLOCK_SIZE;
+
+
+#[derive(Deserialize, Serialize)]
+enum Record {
+	/// The actor of one of the identities, which precedes its identity and
+	/// objects.
+	Actor {
+		address: ActorAddress,
+		info: ActorInfo,
+	},
+	Identity {
+		label: String,
+		address: ActorAddress,
+		/// The private key in its plain form, or a reference to a key of the
+		/// external signer.
+		private_key: Vec<u8>,
+		seed: Option<Vec<u8>>,
+		is_private: bool,
+		delegation: Option<Vec<u8>>,
+	},
+	Follow {
+		address: ActorAddress,
+		info: ActorInfo,
+		object_limit: Option<u64>,
+		max_age: Option<u64>,
+	},
+	Object {
+		actor_address: ActorAddress,
+		hash: IdType,
+		object: BlogchainObject,
+		verified_from_start: bool,
+	},
+	File {
+		hash: IdType,
+		file: File,
+	},
+	Block {
+		hash: IdType,
+		data: Vec<u8>,
+	},
+	End,
+}
+
+struct ArchiveWriter {
+	inner: BufWriter<FsFile>,
+	files: HashSet<IdType>,
+}
+
+
+impl Api {
+	/// Writes the user's own data to an archive file at the given path. The
+	/// identities, with their private keys, are only included when asked for.
+	pub async fn export_archive(&self, path: &Path, include_identities: bool) -> db::Result<()> {
+		let this = self.clone();
+		let path = path.to_owned();
+		self.db
+			.perform(move |c| {
+				let mut writer = ArchiveWriter::create(&path)?;
+
+				for (label, address, ..) in c.fetch_my_identities()? {
+					let info = match c.fetch_identity(&address)? {
+						Some(i) => i,
+						None => Err(db::Error::MissingIdentity(address.clone()))?,
+					};
+					writer.write(&Record::Actor {
+						address: address.clone(),
+						info,
+					})?;
+					if include_identities {
+						writer.write(&this.export_identity(&c, label, &address)?)?;
+					}
+					writer.write_objects(&c, &address)?;
+				}
+
+				let mut stat = c.prepare(
+					r#"
+					SELECT f.sync_object_limit, f.sync_max_age
+					FROM following AS f
+					INNER JOIN actor AS a ON f.actor_id = a.id
+					WHERE a.address = ?
+				"#,
+				)?;
+				for (address, info) in c.fetch_follow_list()? {
+					let (object_limit, max_age): (Option<i64>, Option<i64>) =
+						stat.query_row(params![address], |r| Ok((r.get(0)?, r.get(1)?)))?;
+					writer.write(&Record::Follow {
+						address,
+						info,
+						object_limit: object_limit.map(|l| l as _),
+						max_age: max_age.map(|a| a as _),
+					})?;
+				}
+
+				writer.write(&Record::End)?;
+				writer.inner.flush()?;
+				writer.inner.get_ref().sync_all()?;
+				Ok(())
+			})
+			.await
+	}
+
+	fn export_identity(
+		&self, c: &Connection, label: String, address: &ActorAddress,
+	) -> db::Result<Record> {
+		let (stored_key, stored_seed, is_private, delegation): (
+			Vec<u8>,
+			Option<Vec<u8>>,
+			bool,
+			Option<Vec<u8>>,
+		) = c.query_row(
+			"SELECT private_key, seed, is_private, delegation FROM identity WHERE label = ?",
+			[&label],
+			|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+		)?;
+
+		let keyring = self.db.keyring();
+		let private_key = if keyring::is_external_key(&stored_key) {
+			stored_key
+		} else {
+			keyring
+				.decrypt(&stored_key, ACTOR_PRIVATE_KEY_SIZE)?
+				.to_vec()
+		};
+		let seed = match stored_seed {
+			Some(s) => Some(keyring.decrypt(&s, SEED_SIZE)?.to_vec()),
+			None => None,
+		};
+		Ok(Record::Identity {
+			label,
+			address: address.clone(),
+			private_key,
+			seed,
+			is_private,
+			delegation,
+		})
+	}
+
+	/// Restores the data in the archive at the given path. Whatever is in our
+	/// database already is left as it is, so importing the same archive again
+	/// does nothing, and an import that got interrupted can simply be retried.
+	pub async fn import_archive(
+		&self, path: &Path, join_networks: bool,
+	) -> db::Result<ImportSummary> {
+		let this = self.clone();
+		let path = path.to_owned();
+		let (summary, actors) = self
+			.db
+			.perform(move |mut c| -> db::Result<_> {
+				let mut reader = BufReader::new(FsFile::open(&path)?);
+				let mut header = [0u8; 9];
+				read_exact(&mut reader, &mut header)?;
+				if &header[..8] != MAGIC {
+					Err(db::Error::InvalidUserArchive("not a user archive".to_string()))?;
+				}
+				if header[8] != VERSION {
+					Err(db::Error::InvalidUserArchive(format!(
+						"unsupported version {}",
+						header[8]
+					)))?;
+				}
+
+				let mut summary = ImportSummary::default();
+				// The actors of which the networks need to be joined afterwards
+				let mut actors = Vec::new();
+				loop {
+					match read_record(&mut reader)? {
+						Record::Actor { address, info } =>
+							if c.fetch_identity(&address)?.is_none() {
+								check_actor_address(&address, &info)?;
+								c.store_identity(&address, &info.public_key, &info.first_object)?;
+								summary.actors += 1;
+							},
+						Record::Identity {
+							label,
+							address,
+							private_key,
+							seed,
+							is_private,
+							delegation,
+						} =>
+							if this.import_identity(
+								&c,
+								&label,
+								&address,
+								&private_key,
+								seed.as_deref(),
+								is_private,
+								delegation,
+							)? {
+								if let Some(info) = c.fetch_identity(&address)? {
+									actors.push((address.clone(), info));
+								}
+								summary.identities.push(address);
+							},
+						Record::Follow {
+							address,
+							info,
+							object_limit,
+							max_age,
+						} =>
+							if !c.is_following(&address)? {
+								check_actor_address(&address, &info)?;
+								let sync_depth = SyncDepth {
+									object_limit,
+									max_age,
+								};
+								c.follow(&address, &info, &sync_depth)?;
+								actors.push((address.clone(), info));
+								summary.follows.push(address);
+							},
+						Record::Object {
+							actor_address,
+							hash,
+							object,
+							verified_from_start,
+						} => {
+							if object.signature.hash() != hash {
+								Err(db::Error::InvalidUserArchive(format!(
+									"object {} doesn't match its hash",
+									hash
+								)))?;
+							}
+							if c.store_object(
+								&actor_address,
+								&hash,
+								&object,
+								verified_from_start,
+							)? {
+								summary.objects += 1;
+							} else if let Some((existing, ..)) =
+								c.fetch_object_by_sequence(&actor_address, object.sequence)?
+							{
+								if existing != hash {
+									summary.conflicts += 1;
+								}
+							}
+						}
+						Record::File { hash, file } =>
+							if !c.has_file(&hash)? {
+								c.store_file(&hash, &file)?;
+								summary.files += 1;
+							},
+						Record::Block { hash, data } => {
+							if IdType::hash(&data) != hash {
+								Err(db::Error::InvalidUserArchive(format!(
+									"block {} doesn't match its hash",
+									hash
+								)))?;
+							}
+							if !c.has_block(&hash)? {
+								c.store_block(0, &hash, &data)?;
+								summary.blocks += 1;
+							}
+						}
+						Record::End => break,
+					}
+				}
+				Ok((summary, actors))
+			})
+			.await?;
+
+		if join_networks {
+			let node = self.node.clone();
+			self.node.tasks().spawn("imported actor joiner", async move {
+				for (address, info) in actors {
+					node.join_actor_network(&address, &info).await;
+				}
+			});
+		}
+		Ok(summary)
+	}
+
+	/// Stores the identity, unless the actor has an identity already. Returns
+	/// whether it was stored.
+	fn import_identity(
+		&self, c: &Connection, label: &str, address: &ActorAddress, private_key: &[u8],
+		seed: Option<&[u8]>, is_private: bool, delegation: Option<Vec<u8>>,
+	) -> db::Result<bool> {
+		let actor_id: i64 = match c
+			.query_row(
+				"SELECT id FROM actor WHERE address = ?",
+				params![address],
+				|r| r.get(0),
+			)
+			.optional()?
+		{
+			Some(id) => id,
+			None => Err(db::Error::InvalidUserArchive(format!(
+				"identity {} comes before its actor",
+				address
+			)))?,
+		};
+		let has_identity = c
+			.query_row("SELECT 1 FROM identity WHERE actor_id = ?", [actor_id], |_| Ok(()))
+			.optional()?
+			.is_some();
+		if has_identity {
+			return Ok(false);
+		}
+
+		let is_external = keyring::is_external_key(private_key);
+		if !is_external && private_key.len() != ACTOR_PRIVATE_KEY_SIZE {
+			Err(db::Error::InvalidPrivateKey(private_key.len()))?;
+		}
+		let keyring = self.db.keyring();
+		let stored_key = if is_external {
+			private_key.to_vec()
+		} else {
+			keyring.encrypt(private_key)?
+		};
+		let stored_seed = seed.map(|s| keyring.encrypt(s)).transpose()?;
+
+		// Find a label that isn't used yet
+		let mut free_label = label.to_string();
+		let mut i = 1;
+		while c
+			.query_row("SELECT 1 FROM identity WHERE label = ?", [&free_label], |_| Ok(()))
+			.optional()?
+			.is_some()
+		{
+			i += 1;
+			free_label = format!("{} ({})", label, i);
+		}
+
+		c.execute(
+			r#"
+			INSERT INTO identity (
+				label, actor_id, private_key, is_private, seed, is_active, delegation
+			) VALUES (?, ?, ?, ?, ?, 0, ?)
+		"#,
+			params![
+				free_label,
+				actor_id,
+				stored_key,
+				is_private,
+				stored_seed,
+				delegation
+			],
+		)?;
+		Ok(true)
+	}
+}
+
+impl ArchiveWriter {
+	fn create(path: &Path) -> db::Result<Self> {
+		let mut inner = BufWriter::new(FsFile::create(path)?);
+		inner.write_all(MAGIC)?;
+		inner.write_all(&[VERSION])?;
+		Ok(Self {
+			inner,
+			files: HashSet::new(),
+		})
+	}
+
+	fn write(&mut self, record: &Record) -> db::Result<()> {
+		let buffer = binserde::serialize(record).unwrap();
+		self.inner.write_all(&(buffer.len() as u32).to_le_bytes())?;
+		self.inner.write_all(&buffer)?;
+		Ok(())
+	}
+
+	/// Writes the file, with all of its blocks, unless it has been written
+	/// already. Files that haven't been downloaded are left out.
+	fn write_file(&mut self, c: &Connection, hash: &IdType) -> db::Result<()> {
+		if !self.files.insert(hash.clone()) {
+			return Ok(());
+		}
+		let file = match c.fetch_file(hash)? {
+			Some(f) => f,
+			None => return Ok(()),
+		};
+		for block_hash in &file.blocks {
+			if let Some(data) = c.fetch_block(block_hash)? {
+				self.write(&Record::Block {
+					hash: block_hash.clone(),
+					data,
+				})?;
+			}
+		}
+		self.write(&Record::File {
+			hash: hash.clone(),
+			file,
+		})
+	}
+
+	/// Writes all objects of the actor, each preceded by the files that are
+	/// attached to it.
+	fn write_objects(&mut self, c: &Connection, address: &ActorAddress) -> db::Result<()> {
+		let mut stat = c.prepare(
+			r#"
+			SELECT o.hash FROM object AS o
+			INNER JOIN actor AS a ON o.actor_id = a.id
+			WHERE a.address = ?
+			ORDER BY o.sequence ASC
+		"#,
+		)?;
+		let hashes = stat
+			.query_map(params![address], |r| r.get::<_, IdType>(0))?
+			.collect::<rusqlite::Result<Vec<_>>>()?;
+		for hash in hashes {
+			let (object, verified_from_start) = match c.fetch_object(&hash)? {
+				Some(o) => o,
+				None => continue,
+			};
+			match &object.payload {
+				ObjectPayload::Post(post) => match &post.data {
+					PostObjectCryptedData::Plain(plain) =>
+						for file_hash in plain.files.iter() {
+							self.write_file(c, file_hash)?;
+						},
+				},
+				ObjectPayload::Profile(profile) =>
+					for file_hash in [&profile.avatar, &profile.wallpaper, &profile.description]
+						.into_iter()
+						.flatten()
+					{
+						self.write_file(c, file_hash)?;
+					},
+				ObjectPayload::Edit(edit) =>
+					for file_hash in edit.files.iter() {
+						self.write_file(c, file_hash)?;
+					},
+				ObjectPayload::Share(_)
+				| ObjectPayload::KeyRotation(_)
+				| ObjectPayload::Delete(_)
+				| ObjectPayload::Reaction(_)
+				| ObjectPayload::Poll(_)
+				| ObjectPayload::Vote(_)
+				| ObjectPayload::FollowerList(_) => {}
+			}
+			self.write(&Record::Object {
+				actor_address: address.clone(),
+				hash,
+				object,
+				verified_from_start,
+			})?;
+		}
+		Ok(())
+	}
+}
+
+
+fn check_actor_address(address: &ActorAddress, info: &ActorInfo) -> db::Result<()> {
+	if &info.generate_address() != address {
+		Err(db::Error::InvalidUserArchive(format!(
+			"actor {} doesn't match its info",
+			address
+		)))?;
+	}
+	Ok(())
+}
+
+/// Reads exactly enough bytes to fill the buffer, treating the end of the file
+/// as an archive that has been cut off.
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> db::Result<()> {
+	match reader.read_exact(buffer) {
+		Ok(()) => Ok(()),
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+			Err(db::Error::InvalidUserArchive("archive is incomplete".to_string()))?,
+		Err(e) => Err(e)?,
+	}
+}
+
+fn read_record(reader: &mut impl Read) -> db::Result<Record> {
+	let mut size_buffer = [0u8; 4];
+	read_exact(reader, &mut size_buffer)?;
+	let size = u32::from_le_bytes(size_buffer) as usize;
+	if size > MAX_RECORD_SIZE {
+		Err(db::Error::InvalidUserArchive(format!(
+			"record of {} bytes is too large",
+			size
+		)))?;
+	}
+	let mut buffer = vec![0u8; size];
+	read_exact(reader, &mut buffer)?;
+	match binserde::deserialize_owned(&buffer) {
+		Ok(r) => Ok(r),
+		Err(e) => Err(db::Error::InvalidUserArchive(format!("malformed record: {}", e)))?,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::*;
+	use crate::{db::PersistenceHandle, identity::ActorPrivateKeyV1, test};
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_user_archive() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("user-archive-source").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let source = Api { node, db };
+		let db = test::load_database("user-archive-target").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let target = Api { node, db };
+
+		let description = FileData {
+			mime_type: "text/markdown".into(),
+			data: b"Moved to a new machine".to_vec(),
+		};
+		let (address, _) = source
+			.create_identity("archive", "Archive", None, None, Some(&description))
+			.await
+			.unwrap();
+		let followed_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let followed_info = ActorInfo::V1(ActorInfoV1 {
+			flags: 0,
+			public_key: followed_key.public(),
+			first_object: IdType::random(&mut rng),
+			actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+		});
+		let followed = followed_info.generate_address();
+		let sync_depth = SyncDepth {
+			object_limit: Some(10),
+			max_age: None,
+		};
+		source
+			.db
+			.connect_old()
+			.unwrap()
+			.follow(&followed, &followed_info, &sync_depth)
+			.unwrap();
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("archive.stna");
+		source.export_archive(&path, true).await.unwrap();
+
+		let summary = target.import_archive(&path, false).await.unwrap();
+		assert_eq!(summary.identities, vec![address.clone()]);
+		assert_eq!(summary.follows, vec![followed.clone()]);
+		assert_eq!(summary.objects, 1);
+		assert_eq!(summary.files, 1);
+		assert_eq!(summary.conflicts, 0);
+		let (label, record_address, ..) = target.fetch_my_identities().await.unwrap().remove(0);
+		assert_eq!(label, "archive");
+		assert_eq!(record_address, address);
+		let profile = target.db.load_profile(&address).await.unwrap().unwrap();
+		let fetched = target
+			.db
+			.load_file_data(&profile.description.unwrap())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(fetched.data, description.data);
+		let c = target.db.connect_old().unwrap();
+		assert!(c.fetch_my_identity(&address).unwrap().is_some());
+		assert_eq!(
+			target.db.load_sync_depth(&followed).await.unwrap().unwrap().object_limit,
+			Some(10)
+		);
+
+		// Importing it again changes nothing
+		let summary = target.import_archive(&path, false).await.unwrap();
+		assert_eq!(summary.identities.len(), 0);
+		assert_eq!(summary.follows.len(), 0);
+		assert_eq!(summary.objects + summary.files + summary.blocks, 0);
+
+		// Without the identities, the objects are still there
+		let without = dir.path().join("without.stna");
+		source.export_archive(&without, false).await.unwrap();
+		let db = test::load_database("user-archive-without").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let other = Api { node, db };
+		let summary = other.import_archive(&without, false).await.unwrap();
+		assert_eq!(summary.identities.len(), 0);
+		assert_eq!(summary.objects, 1);
+
+		// An archive that has been cut off is rejected
+		let data = fs::read(&path).unwrap();
+		fs::write(&path, &data[..data.len() - 1]).unwrap();
+		assert!(other.import_archive(&path, false).await.is_err());
+	}
+}
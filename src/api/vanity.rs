@@ -0,0 +1,246 @@
+//! Creates identities with an address that starts with a prefix of the user's
+//! choosing.
+//!
+//! There is no way to pick an address, so keypairs are generated on all cores
+//! until one of them happens to result in a matching address. Every extra
+//! character of the prefix makes this take 32 times longer. The prefix is
+//! matched against the address in the checksummed format, which always starts
+//! with `stna1q`, followed by one of `q`, `p`, `z` or `r`, because the address
+//! begins with its version byte.
+//!
+//! The keypairs are generated directly, rather than from the entropy of a
+//! mnemonic phrase, because deriving a key from a phrase is deliberately slow.
+//! So, like identities that were created before the phrases existed, vanity
+//! identities can't be backed up with one.
+
+use std::{
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Mutex,
+	},
+	thread,
+	time::Instant,
+};
+
+use rand::rngs::OsRng;
+use serde::Serialize;
+
+use super::{current_time_millis, Api};
+use crate::{bech32, core::*, db, identity::ActorPrivateKeyV1, util};
+
+
+/// The characters that can follow the fixed beginning of an actor address.
+const SECOND_CHARACTERS: &str = "qpzr";
+
+
+/// Keeps track of a search for a vanity address, so that its progress can be
+/// shown, and so that it can be cancelled from elsewhere.
+pub struct VanitySearch {
+	prefix: String,
+	expected_attempts: u64,
+	attempts: AtomicU64,
+	is_cancelled: AtomicBool,
+	started: Instant,
+	found: Mutex<Option<ActorAddress>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct VanityProgress {
+	pub attempts: u64,
+	/// The number of attempts that it takes to find a match on average.
+	pub expected_attempts: u64,
+	pub attempts_per_second: u64,
+	pub is_cancelled: bool,
+	/// The address of the identity that has been created, once a match has
+	/// been found.
+	pub address: Option<String>,
+}
+
+
+impl VanitySearch {
+	/// Starts keeping track of a search for the given prefix, which has to be
+	/// one that an actor address can start with.
+	pub fn new(prefix: &str) -> db::Result<Self> {
+		let prefix = prefix.trim().to_ascii_lowercase();
+		let fixed = format!("{}1q", ACTOR_ADDRESS_PREFIX);
+		if !prefix.starts_with(&fixed) {
+			Err(db::Error::InvalidVanityPrefix(format!(
+				"it has to start with {}",
+				fixed
+			)))?;
+		}
+		let free = &prefix[fixed.len()..];
+		let mut expected_attempts = 1u64;
+		for (i, c) in free.chars().enumerate() {
+			if i == 0 {
+				if !SECOND_CHARACTERS.contains(c) {
+					Err(db::Error::InvalidVanityPrefix(format!(
+						"{} has to be followed by one of {}",
+						fixed, SECOND_CHARACTERS
+					)))?;
+				}
+				expected_attempts = SECOND_CHARACTERS.len() as u64;
+			} else {
+				if !c.is_ascii() || !bech32::CHARSET.contains(&(c as u8)) {
+					Err(db::Error::InvalidVanityPrefix(format!(
+						"an address can't contain the character {}",
+						c
+					)))?;
+				}
+				expected_attempts = expected_attempts.saturating_mul(bech32::CHARSET.len() as u64);
+			}
+		}
+
+		Ok(Self {
+			prefix,
+			expected_attempts,
+			attempts: AtomicU64::new(0),
+			is_cancelled: AtomicBool::new(false),
+			started: Instant::now(),
+			found: Mutex::new(None),
+		})
+	}
+
+	/// Stops the search. The identity won't be created.
+	pub fn cancel(&self) { self.is_cancelled.store(true, Ordering::Relaxed); }
+
+	pub fn is_cancelled(&self) -> bool { self.is_cancelled.load(Ordering::Relaxed) }
+
+	/// Whether the search has ended, either with a match or by being cancelled.
+	pub fn is_finished(&self) -> bool {
+		self.is_cancelled() || self.found.lock().unwrap().is_some()
+	}
+
+	pub fn prefix(&self) -> &str { &self.prefix }
+
+	pub fn progress(&self) -> VanityProgress {
+		let attempts = self.attempts.load(Ordering::Relaxed);
+		let elapsed = self.started.elapsed().as_secs_f64();
+		VanityProgress {
+			attempts,
+			expected_attempts: self.expected_attempts,
+			attempts_per_second: if elapsed > 0.0 {
+				(attempts as f64 / elapsed) as u64
+			} else {
+				0
+			},
+			is_cancelled: self.is_cancelled(),
+			address: self.found.lock().unwrap().as_ref().map(|a| a.to_string()),
+		}
+	}
+}
+
+impl Api {
+	/// Creates an identity of which the address starts with the prefix of the
+	/// search. This can take a long time, and uses all cores while it does.
+	/// Returns None if the search has been cancelled before a match was found.
+	pub async fn create_vanity_identity(
+		&self, label: &str, name: &str, search: &VanitySearch,
+	) -> db::Result<Option<(ActorAddress, ActorInfo)>> {
+		// The address depends on the first profile object, so it has to be exactly
+		// the same for the identity as it was during the search
+		let created = current_time_millis();
+		let found = util::block_in_place(|| grind(search, name, created));
+		let private_key = match found {
+			Some(k) => k,
+			None => return Ok(None),
+		};
+		let result = self
+			.create_identity_with_signer(label, name, None, None, None, &private_key, None, created)
+			.await?;
+		*search.found.lock().unwrap() = Some(result.0.clone());
+		Ok(Some(result))
+	}
+}
+
+
+fn generate_address(private_key: &ActorPrivateKeyV1, name: &str, created: u64) -> ActorAddress {
+	let (object_hash, _) =
+		Api::compose_profile_object(private_key, 0, name, &None, &None, &None, false, created)
+			.expect("unable to sign with private key");
+	let actor_info = ActorInfo::V1(ActorInfoV1 {
+		flags: 0,
+		public_key: private_key.public(),
+		first_object: object_hash,
+		actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+	});
+	actor_info.generate_address()
+}
+
+/// Generates keys on all cores until one of them matches, or until the search
+/// is cancelled.
+fn grind(search: &VanitySearch, name: &str, created: u64) -> Option<ActorPrivateKeyV1> {
+	let thread_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+	let found = Mutex::new(None);
+	let is_done = AtomicBool::new(false);
+	thread::scope(|scope| {
+		for _ in 0..thread_count {
+			scope.spawn(|| {
+				while !is_done.load(Ordering::Relaxed) && !search.is_cancelled() {
+					let private_key = ActorPrivateKeyV1::generate_with_rng(&mut OsRng);
+					let address = generate_address(&private_key, name, created);
+					search.attempts.fetch_add(1, Ordering::Relaxed);
+					if address.to_bech32().starts_with(&search.prefix) &&
+						!is_done.swap(true, Ordering::Relaxed)
+					{
+						*found.lock().unwrap() = Some(private_key);
+					}
+				}
+			});
+		}
+	});
+	found.into_inner().unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_vanity_identity() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("vanity").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		assert!(VanitySearch::new("alice").is_err());
+		assert!(VanitySearch::new("stna1qa").is_err());
+		assert!(VanitySearch::new("stna1qqb").is_err());
+		assert_eq!(
+			VanitySearch::new("STNA1QZQ").unwrap().progress().expected_attempts,
+			128
+		);
+
+		let search = VanitySearch::new("stna1qp").unwrap();
+		let (address, _) = api
+			.create_vanity_identity("vanity", "Vanity", &search)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(address.to_bech32().starts_with("stna1qp"));
+		assert!(search.is_finished());
+		let progress = search.progress();
+		assert!(progress.attempts > 0);
+		assert_eq!(progress.address, Some(address.to_string()));
+		let (_, record_address, ..) = api
+			.fetch_my_identities()
+			.await
+			.unwrap()
+			.into_iter()
+			.find(|i| i.0 == "vanity")
+			.unwrap();
+		assert_eq!(record_address, address);
+
+		let search = VanitySearch::new("stna1qqqqqqqqqq").unwrap();
+		assert!(!search.is_finished());
+		search.cancel();
+		assert!(search.is_finished());
+		assert!(api
+			.create_vanity_identity("cancelled", "Cancelled", &search)
+			.await
+			.unwrap()
+			.is_none());
+	}
+}
@@ -0,0 +1,195 @@
+//! Encodes data as text in the bech32m format, which is how addresses can be
+//! written down with a checksum.
+//!
+//! A bech32m string consists of a human-readable prefix that says what the data
+//! is, the separator `1`, and the data in an alphabet of 32 characters that
+//! leaves out the ones that are easily mistaken for each other. The last six
+//! characters are a checksum, which detects any error in up to four characters,
+//! so that a mistyped address is rejected instead of pointing to nothing.
+
+use std::fmt;
+
+
+pub const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const SEPARATOR: char = '1';
+const CHECKSUM_LENGTH: usize = 6;
+const BECH32M_CONSTANT: u32 = 0x2bc830a3;
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+
+#[derive(Debug, PartialEq)]
+pub enum Bech32Error {
+	MissingSeparator,
+	MixedCase,
+	InvalidCharacter(char),
+	InvalidChecksum,
+	InvalidPadding,
+}
+
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+	let mut checksum = 1u32;
+	for value in values {
+		let top = checksum >> 25;
+		checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+		for (i, generator) in GENERATOR.iter().enumerate() {
+			if (top >> i) & 1 == 1 {
+				checksum ^= generator;
+			}
+		}
+	}
+	checksum
+}
+
+fn expand_prefix(prefix: &str) -> impl Iterator<Item = u8> + '_ {
+	prefix
+		.bytes()
+		.map(|b| b >> 5)
+		.chain(Some(0))
+		.chain(prefix.bytes().map(|b| b & 0x1f))
+}
+
+/// Regroups the bits of the data into groups of a different size.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Error> {
+	let mut accumulator = 0u32;
+	let mut bits = 0u32;
+	let max_value = (1u32 << to) - 1;
+	let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+	for value in data {
+		accumulator = (accumulator << from) | *value as u32;
+		bits += from;
+		while bits >= to {
+			bits -= to;
+			result.push(((accumulator >> bits) & max_value) as u8);
+		}
+	}
+	if pad {
+		if bits > 0 {
+			result.push(((accumulator << (to - bits)) & max_value) as u8);
+		}
+	} else if bits >= from || ((accumulator << (to - bits)) & max_value) != 0 {
+		return Err(Bech32Error::InvalidPadding);
+	}
+	Ok(result)
+}
+
+pub fn encode(prefix: &str, data: &[u8]) -> String {
+	let values = convert_bits(data, 8, 5, true).unwrap();
+	let checksum = polymod(
+		expand_prefix(prefix)
+			.chain(values.iter().cloned())
+			.chain([0u8; CHECKSUM_LENGTH]),
+	) ^ BECH32M_CONSTANT;
+
+	let mut string = String::with_capacity(prefix.len() + 1 + values.len() + CHECKSUM_LENGTH);
+	string.push_str(prefix);
+	string.push(SEPARATOR);
+	for value in values {
+		string.push(CHARSET[value as usize] as char);
+	}
+	for i in 0..CHECKSUM_LENGTH {
+		let value = (checksum >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 0x1f;
+		string.push(CHARSET[value as usize] as char);
+	}
+	string
+}
+
+/// Whether the string starts with the prefix and the separator, in any case.
+pub fn has_prefix(string: &str, prefix: &str) -> bool {
+	string.len() > prefix.len() &&
+		string.is_char_boundary(prefix.len()) &&
+		string[..prefix.len()].eq_ignore_ascii_case(prefix) &&
+		string[prefix.len()..].starts_with(SEPARATOR)
+}
+
+/// Returns the prefix in lower case, and the data.
+pub fn decode(string: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+	let has_lower = string.chars().any(|c| c.is_ascii_lowercase());
+	let has_upper = string.chars().any(|c| c.is_ascii_uppercase());
+	if has_lower && has_upper {
+		return Err(Bech32Error::MixedCase);
+	}
+	let string = string.to_ascii_lowercase();
+	let separator_index = match string.rfind(SEPARATOR) {
+		Some(i) if i > 0 && string.len() - i > CHECKSUM_LENGTH => i,
+		_ => return Err(Bech32Error::MissingSeparator),
+	};
+	let (prefix, rest) = string.split_at(separator_index);
+	if let Some(c) = prefix.chars().find(|c| !c.is_ascii_graphic()) {
+		return Err(Bech32Error::InvalidCharacter(c));
+	}
+
+	let mut values = Vec::with_capacity(rest.len() - 1);
+	for c in rest[1..].chars() {
+		match CHARSET.iter().position(|x| *x as char == c) {
+			Some(value) => values.push(value as u8),
+			None => return Err(Bech32Error::InvalidCharacter(c)),
+		}
+	}
+	if polymod(expand_prefix(prefix).chain(values.iter().cloned())) != BECH32M_CONSTANT {
+		return Err(Bech32Error::InvalidChecksum);
+	}
+
+	values.truncate(values.len() - CHECKSUM_LENGTH);
+	let data = convert_bits(&values, 5, 8, false)?;
+	Ok((prefix.to_string(), data))
+}
+
+
+impl fmt::Display for Bech32Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::MissingSeparator => write!(f, "missing separator"),
+			Self::MixedCase => write!(f, "mixed upper and lower case"),
+			Self::InvalidCharacter(c) => write!(f, "invalid character {}", c),
+			Self::InvalidChecksum => write!(f, "invalid checksum, there may be a typo"),
+			Self::InvalidPadding => write!(f, "invalid padding"),
+		}
+	}
+}
+
+impl std::error::Error for Bech32Error {}
+
+
+#[cfg(test)]
+mod tests {
+	use std::str::FromStr;
+
+	use super::*;
+	use crate::{common::IdType, core::*, test};
+
+	#[test]
+	fn test_bech32m() {
+		// A test vector of BIP 350
+		let (prefix, data) = decode("A1LQFN3A").unwrap();
+		assert_eq!(prefix, "a");
+		assert!(data.is_empty());
+		assert_eq!(encode("a", &[]), "a1lqfn3a");
+
+		let data: Vec<u8> = (0..33).collect();
+		let string = encode("stna", &data);
+		assert_eq!(decode(&string).unwrap(), ("stna".to_string(), data));
+		assert_eq!(decode(&string.to_uppercase()).unwrap().0, "stna");
+
+		// A single typo is caught
+		let mut typo = string.clone().into_bytes();
+		typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+		let typo = String::from_utf8(typo).unwrap();
+		assert_eq!(decode(&typo), Err(Bech32Error::InvalidChecksum));
+		assert_eq!(decode("stna1qqqq"), Err(Bech32Error::MissingSeparator));
+		assert_eq!(decode("Stna1lqfn3a"), Err(Bech32Error::MixedCase));
+	}
+
+	#[test]
+	fn test_address() {
+		let mut rng = test::initialize_rng();
+		let address = Address::Actor(ActorAddress::V1(IdType::random(&mut rng)));
+		let string = address.to_bech32();
+		assert!(string.starts_with("stna1"));
+		assert_eq!(Address::from_str(&string).unwrap(), address);
+		assert_eq!(Address::from_str(&string.to_uppercase()).unwrap(), address);
+		// The old format is still accepted
+		assert_eq!(Address::from_str(&address.to_string()).unwrap(), address);
+		assert!(Address::from_str(&string[..string.len() - 1]).is_err());
+	}
+}
@@ -13,6 +13,12 @@ use sea_orm::{DbErr, TryGetError};
 use serde::{Deserialize, Serialize, Serializer};
 use sha3::{Digest, Sha3_256};
 
+use crate::bech32::{self, Bech32Error};
+
+
+/// The human-readable prefix of hashes in the checksummed format.
+pub const ID_PREFIX: &str = "stnid";
+
 
 #[async_trait]
 pub trait AsyncIterator {
@@ -32,6 +38,7 @@ pub struct IdType(pub(crate) [u8; 32]);
 #[derive(Debug)]
 pub enum IdFromBase58Error {
 	FromBase58Error(FromBase58Error),
+	Bech32(Bech32Error),
 	TooLong,
 	TooShort,
 }
@@ -121,6 +128,21 @@ impl IdType {
 
 	pub fn from_bytes(bytes: &[u8; 32]) -> Self { Self(bytes.clone()) }
 
+	/// Parses the hash from either the checksummed format or base58.
+	pub fn parse(string: &str) -> Result<Self, IdFromBase58Error> {
+		if !bech32::has_prefix(string, ID_PREFIX) {
+			return Self::from_base58(string);
+		}
+		let (_, buffer) = bech32::decode(string).map_err(IdFromBase58Error::Bech32)?;
+		if buffer.len() > 32 {
+			Err(IdFromBase58Error::TooLong)
+		} else if buffer.len() < 32 {
+			Err(IdFromBase58Error::TooShort)
+		} else {
+			Ok(Self(buffer.try_into().unwrap()))
+		}
+	}
+
 	pub fn from_slice(bytes: &[u8]) -> Option<Self> {
 		if bytes.len() < 32 {
 			None
@@ -145,6 +167,9 @@ impl IdType {
 		rng.fill_bytes(&mut buffer);
 		Self(buffer)
 	}
+
+	/// Formats the hash in the checksummed format.
+	pub fn to_bech32(&self) -> String { bech32::encode(ID_PREFIX, &self.0) }
 }
 
 impl ops::BitXor<&IdType> for IdType {
@@ -272,6 +297,7 @@ impl fmt::Display for IdFromBase58Error {
 					write!(f, "invalid length for a base58 string")
 				}
 			},
+			Self::Bech32(e) => write!(f, "{}", e),
 			Self::TooLong => write!(f, "string to long"),
 			Self::TooShort => write!(f, "string to short"),
 		}
@@ -291,4 +317,13 @@ mod tests {
 		let b = IdType::from_base58("6iAN6tcmd7DxXie3kXnaFFvge7U3WHCEjJLC4gB269No").unwrap();
 		assert!(a == b);
 	}
+
+	#[test]
+	fn test_parse() {
+		let id = IdType::from_base58("6iAN6tcmd7DxXie3kXnaFFvge7U3WHCEjJLC4gB269No").unwrap();
+		assert_eq!(IdType::parse(&id.to_bech32()).unwrap(), id);
+		assert_eq!(IdType::parse(&id.to_base58()).unwrap(), id);
+		assert!(IdType::parse(&id.to_bech32().to_uppercase()).is_ok());
+		assert!(IdType::parse(&id.to_bech32().replace("stnid1", "stnid1q")).is_err());
+	}
 }
@@ -13,12 +13,14 @@ pub fn compress(type_: CompressionType, data: &[u8]) -> Vec<u8> {
 	}
 }
 
-fn compress_brotli(input: &[u8]) -> Vec<u8> {
+fn compress_brotli(mut input: &[u8]) -> Vec<u8> {
 	let options = BrotliOptions::new().quality(11);
 	let mut encoder = compu::encoder::Interface::brotli_rust(options);
 	let mut output = Vec::with_capacity(max(input.len(), 1024));
 	loop {
 		let result = encoder.encode_vec(input, &mut output, compu::EncodeOp::Finish);
+		// Don't feed the encoder the part of the input it has already consumed
+		input = &input[(input.len() - result.input_remain)..];
 		match result.status {
 			EncodeStatus::Continue => {}
 			// Add 10% capacity if needed
@@ -16,6 +16,35 @@ pub const CONFIG_FILE_PATH: &str = "/etc/stonenet/config.toml";
 #[derive(Clone, Deserialize)]
 pub struct Config {
 	pub database_path: String,
+	pub database_passphrase_file: Option<String>,
+	pub database_wal: Option<bool>,
+	pub database_busy_timeout: Option<u64>,
+	pub database_max_connections: Option<u32>,
+	pub database_acquire_timeout: Option<u64>,
+	pub encrypt_database: Option<bool>,
+	pub archive_after_days: Option<u32>,
+	pub archive_path: Option<String>,
+	pub key_idle_lock: Option<u64>,
+	pub key_passphrase_file: Option<String>,
+	pub external_signer_socket: Option<String>,
+	pub archive_node: Option<bool>,
+	pub archive_node_discover: Option<bool>,
+	pub archive_node_max_actors: Option<usize>,
+	pub archive_node_quota: Option<u64>,
+	pub maintenance_interval: Option<u32>,
+	pub max_storage: Option<u64>,
+	pub network_stats_retention: Option<u32>,
+	pub parity_blocks: Option<u8>,
+	pub purge_unfollowed_after_days: Option<u32>,
+	pub retention_max_age: Option<u32>,
+	pub retention_max_size: Option<u64>,
+	pub retention_override: Option<Vec<RetentionOverride>>,
+	pub seeding_policy: Option<String>,
+	pub upload_rate_per_file: Option<u64>,
+	pub upload_slots: Option<usize>,
+	pub vacuum_interval: Option<u32>,
+	pub vacuum_pages_per_step: Option<u32>,
+	pub vacuum_step_delay: Option<u64>,
 
 	pub ipv4_address: Option<String>,
 	pub ipv6_address: Option<String>,
@@ -27,6 +56,7 @@ pub struct Config {
 	pub ipv4_tcp_openness: Option<String>,
 	pub ipv6_udp_openness: Option<String>,
 	pub ipv6_tcp_openness: Option<String>,
+	pub simulate_openness: Option<bool>,
 
 	pub attached_nodes_limit: Option<usize>,
 	pub bootstrap_nodes: Vec<String>,
@@ -34,10 +64,38 @@ pub struct Config {
 	pub web_interface_port: Option<u16>,
 	pub load_user_interface: Option<bool>,
 	pub user_interface_port: Option<u16>,
+	pub interface_password_file: Option<String>,
+	pub interface_session_timeout: Option<u64>,
 	pub node_ping_interval: Option<u64>,
 	pub bucket_size: Option<usize>,
 	pub relay_node: Option<bool>,
+	pub relay_election: Option<bool>,
+	pub relay_bandwidth: Option<u64>,
+	pub relay_min_bandwidth: Option<u64>,
+	pub relay_min_uptime: Option<u64>,
+	pub relay_require_bidirectional: Option<bool>,
 	pub leak_first_request: Option<bool>,
+	pub low_memory: Option<bool>,
+	pub load_shedding: Option<bool>,
+	pub load_shedding_db_latency: Option<u64>,
+	pub load_shedding_memory_limit: Option<u64>,
+	pub load_shedding_session_limit: Option<usize>,
+	pub auto_ban_threshold: Option<u32>,
+	pub auto_ban_duration: Option<u64>,
+	pub request_rate_limit: Option<u32>,
+	pub request_rate_burst: Option<u32>,
+	pub resolve_dns_names: Option<bool>,
+	pub shutdown_grace_period: Option<u64>,
+	pub telemetry: Option<bool>,
+	pub telemetry_collector: Option<bool>,
+	pub telemetry_node: Option<String>,
+	pub theme: Option<String>,
+	pub runtime_current_thread: Option<bool>,
+	pub runtime_max_blocking_threads: Option<usize>,
+	pub runtime_worker_threads: Option<usize>,
+	pub web_rate_burst: Option<u32>,
+	pub web_rate_limit: Option<u32>,
+	pub web_request_size_limit: Option<u64>,
 	pub web_url_base: Option<String>,
 	pub trusted_nodes: Option<Vec<String>>,
 
@@ -57,6 +115,14 @@ pub struct Config {
 	pub federation_server_name: Option<String>,
 }
 
+/// Replaces the retention policy for a single actor.
+#[derive(Clone, Deserialize)]
+pub struct RetentionOverride {
+	pub actor: String,
+	pub max_age: Option<u32>,
+	pub max_size: Option<u64>,
+}
+
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Settings {
 	pub default_space_allocation: u32,
@@ -85,6 +151,20 @@ impl Config {
 		}
 		addrs
 	}
+
+	pub fn parse_telemetry_node(&self) -> Option<NodeAddress> {
+		let string = self.telemetry_node.as_ref()?;
+		match Address::from_str(string) {
+			Ok(Address::Node(address)) => Some(address),
+			_ => {
+				error!(
+					"Invalid node address in telemetry_node config parameter: {}",
+					string
+				);
+				None
+			}
+		}
+	}
 }
 
 impl Default for Config {
@@ -96,15 +176,32 @@ impl Default for Config {
 			activity_pub_send_queue_capacity: None,
 			activity_pub_inbox_actor: None,
 			activity_pub_inbox_server: None,
+			archive_after_days: None,
+			archive_node: None,
+			archive_node_discover: None,
+			archive_node_max_actors: None,
+			archive_node_quota: None,
+			archive_path: None,
 			attached_nodes_limit: None,
+			auto_ban_duration: None,
+			auto_ban_threshold: None,
 			bootstrap_nodes: vec![],
 			bucket_size: Some(4),
+			database_acquire_timeout: None,
+			database_busy_timeout: None,
+			database_max_connections: None,
+			database_passphrase_file: None,
 			database_path: String::default(),
+			database_wal: None,
+			encrypt_database: None,
+			external_signer_socket: None,
 			federation_domain: None,
 			federation_contact_info: None,
 			federation_organization: None,
 			federation_server_account: None,
 			federation_server_name: None,
+			interface_password_file: None,
+			interface_session_timeout: None,
 			ipv4_address: None,
 			ipv6_address: None,
 			ipv4_udp_port: None,
@@ -115,15 +212,56 @@ impl Default for Config {
 			ipv4_tcp_openness: None,
 			ipv6_udp_openness: None,
 			ipv6_tcp_openness: None,
+			key_idle_lock: None,
+			key_passphrase_file: None,
 			leak_first_request: None,
+			load_shedding: None,
+			load_shedding_db_latency: None,
+			load_shedding_memory_limit: None,
+			load_shedding_session_limit: None,
 			load_user_interface: None,
 			load_web_interface: None,
+			low_memory: None,
+			maintenance_interval: None,
+			max_storage: None,
+			network_stats_retention: None,
 			node_ping_interval: None,
+			parity_blocks: None,
+			purge_unfollowed_after_days: None,
+			relay_election: None,
+			relay_bandwidth: None,
+			relay_min_bandwidth: None,
+			relay_min_uptime: None,
 			relay_node: None,
+			relay_require_bidirectional: None,
+			request_rate_burst: None,
+			request_rate_limit: None,
+			resolve_dns_names: None,
+			retention_max_age: None,
+			retention_max_size: None,
+			retention_override: None,
+			runtime_current_thread: None,
+			runtime_max_blocking_threads: None,
+			runtime_worker_threads: None,
+			seeding_policy: None,
+			shutdown_grace_period: None,
+			simulate_openness: None,
+			telemetry: None,
+			telemetry_collector: None,
+			telemetry_node: None,
+			theme: None,
 			track: None,
 			trusted_nodes: None,
+			upload_rate_per_file: None,
+			upload_slots: None,
 			user_interface_port: None,
+			vacuum_interval: None,
+			vacuum_pages_per_step: None,
+			vacuum_step_delay: None,
 			web_interface_port: None,
+			web_rate_burst: None,
+			web_rate_limit: None,
+			web_request_size_limit: None,
 			web_url_base: None,
 		}
 	}
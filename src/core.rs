@@ -11,11 +11,19 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::{common::*, identity::*};
-use crate::{net::binserde, serde_limit::*};
+use crate::{
+	bech32::{self, Bech32Error},
+	net::binserde,
+	serde_limit::*,
+};
 
 
 pub const ACTOR_TYPE_BLOGCHAIN: &str = "blogchain";
 
+/// The human-readable prefixes of addresses in the checksummed format.
+pub const ACTOR_ADDRESS_PREFIX: &str = "stna";
+pub const NODE_ADDRESS_PREFIX: &str = "stnn";
+
 
 #[derive(Clone, Debug, Deserialize, Hash, Eq, PartialEq, Serialize)]
 pub enum ActorAddress {
@@ -62,6 +70,142 @@ pub struct ShareObject {
 	pub object_hash: IdType,
 }
 
+/// Replaces the message and the attachments of an earlier post of the same
+/// actor. The post itself stays as it is, so that every version of it can
+/// still be verified.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EditObject {
+	/// The hash of the post that is edited, which is always the original post
+	/// rather than an earlier edit of it.
+	pub object_hash: IdType,
+	/// The files of the new version, of which the first is the message.
+	pub files: LimVec<IdType, Limit64>,
+}
+
+/// Deletes an earlier post of the same actor, together with its edits. Nodes
+/// drop the files of the post when they learn about it, and only keep the post
+/// itself as a tombstone, so that the chain of objects can still be verified.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteObject {
+	/// The hash of the post that is deleted.
+	pub object_hash: IdType,
+}
+
+/// A reaction to a post of any actor, in the form of an emoji. It is meant as
+/// a quick sign of appreciation, which doesn't need to be shared around like a
+/// share object.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReactionObject {
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+	pub emoji: LimString<Limit32>,
+}
+
+/// A question with a number of options to choose from, until the poll closes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PollObject {
+	pub question: LimString<Limit256>,
+	pub options: LimVec<LimString<Limit64>, Limit32>,
+	/// Whether more than one option may be chosen.
+	pub multiple_choice: bool,
+	/// When the poll closes, in milliseconds since the UNIX epoch. Votes that
+	/// are created after it don't count.
+	pub closes: u64,
+}
+
+/// A vote in a poll of any actor. An actor can vote again to change its
+/// choices, in which case only its latest vote counts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VoteObject {
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+	/// The indexes of the chosen options.
+	pub choices: LimVec<u8, Limit32>,
+}
+
+/// The followers that the actor has approved, which replaces any list that came
+/// before it. Only the nodes on the latest list are served the objects of an
+/// actor that has set `followers_only` on its profile.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FollowerListObject {
+	pub followers: LimVec<ApprovedFollower, Limit10K>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApprovedFollower {
+	pub actor_address: ActorAddress,
+	/// The node that follows on behalf of the actor.
+	pub node_address: NodeAddress,
+}
+
+/// Asks an actor to approve a follower. It doesn't become part of the
+/// blogchain of either actor, and is only accepted by the node of the actor
+/// that is asked.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FollowRequestObject {
+	pub requester: ActorAddress,
+	pub actor_address: ActorAddress,
+	/// The node that will follow the actor on behalf of the requester.
+	pub node_address: NodeAddress,
+	pub created: u64,
+	/// Signature of the requester on [`FollowRequestSignData`].
+	pub signature: ActorSignatureV1,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FollowRequestSignData<'a> {
+	pub requester: &'a ActorAddress,
+	pub actor_address: &'a ActorAddress,
+	pub node_address: &'a NodeAddress,
+	pub created: u64,
+}
+
+/// Hands the actor over to a new keypair. The object itself is still signed
+/// with the key that was in effect before it, and every object that comes
+/// after it has to be signed with the new key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KeyRotationObject {
+	pub new_public_key: ActorPublicKeyV1,
+	/// Signature of the new key on [`KeyRotationSignData`], which proves that
+	/// the new key was actually meant to take over this actor.
+	pub proof: ActorSignatureV1,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyRotationSignData<'a> {
+	pub actor_address: &'a ActorAddress,
+	pub sequence: u64,
+}
+
+/// A message that is sent to one actor only. It doesn't become part of the
+/// blogchain of either actor, and only its recipient is able to open it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DirectMessageObject {
+	pub sender: ActorAddress,
+	pub recipient: ActorAddress,
+	pub created: u64,
+	/// The serialized [`DirectMessageContent`], sealed to the message key of the
+	/// recipient.
+	pub sealed: message_key::SealedMessage,
+	/// Signature of the sender on [`DirectMessageSignData`].
+	pub signature: ActorSignatureV1,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DirectMessageSignData<'a> {
+	pub sender: &'a ActorAddress,
+	pub recipient: &'a ActorAddress,
+	pub created: u64,
+	pub sealed: &'a message_key::SealedMessage,
+}
+
+/// What a direct message consists of once it has been opened.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DirectMessageContent {
+	pub mime_type: LimString<LimitMimeType>,
+	pub body: String,
+}
+
 #[derive(Default)]
 pub struct FileData {
 	pub mime_type: LimString<LimitMimeType>,
@@ -99,6 +243,10 @@ pub enum ParseAddressError {
 	FromBytes(#[from] FromBytesAddressError),
 	#[error("unknown prefix: {0}")]
 	InvalidPrefix(char),
+	#[error("{0}")]
+	Bech32(#[from] Bech32Error),
+	#[error("unknown prefix: {0}")]
+	InvalidBech32Prefix(String),
 }
 
 #[derive(Debug, Error)]
@@ -151,6 +299,9 @@ pub struct ProfileObject {
 	pub avatar: Option<IdType>,
 	pub wallpaper: Option<IdType>,
 	pub description: Option<IdType>,
+	/// Whether the objects of the actor are only handed out to the followers
+	/// that it has approved.
+	pub followers_only: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -160,6 +311,32 @@ pub struct BlogchainObject {
 	pub previous_hash: IdType,
 	pub created: u64,
 	pub payload: ObjectPayload,
+	/// Only set if the object is signed by a device key instead of the key of
+	/// the actor itself.
+	/// This field is part of the encoding since actor protocol version 1, so
+	/// objects are only exchanged with nodes that speak at least that version.
+	pub delegation: Option<DelegationCertificate>,
+}
+
+/// Lets a device key sign objects on behalf of an actor, so that the actor can
+/// be used on more than one device without copying its private key around.
+/// Every object that the device key signs carries the certificate along.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DelegationCertificate {
+	pub device_key: ActorPublicKeyV1,
+	/// The types of objects that the device key may sign, as a combination of
+	/// the `DELEGATION_SCOPE_*` flags. A device key can never rotate the key
+	/// of the actor.
+	pub scopes: u8,
+	/// Signature of the key of the actor on [`DelegationSignData`].
+	pub signature: ActorSignatureV1,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DelegationSignData<'a> {
+	pub actor_address: &'a ActorAddress,
+	pub device_key: &'a ActorPublicKeyV1,
+	pub scopes: u8,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -173,12 +350,30 @@ pub struct ObjectSignData<'a> {
 pub const OBJECT_TYPE_PROFILE: u8 = 0;
 pub const OBJECT_TYPE_POST: u8 = 1;
 pub const OBJECT_TYPE_SHARE: u8 = 2;
+pub const OBJECT_TYPE_KEY_ROTATION: u8 = 3;
+pub const OBJECT_TYPE_EDIT: u8 = 4;
+pub const OBJECT_TYPE_DELETE: u8 = 5;
+pub const OBJECT_TYPE_REACTION: u8 = 6;
+pub const OBJECT_TYPE_POLL: u8 = 7;
+pub const OBJECT_TYPE_VOTE: u8 = 8;
+pub const OBJECT_TYPE_FOLLOWER_LIST: u8 = 9;
+
+pub const DELEGATION_SCOPE_POST: u8 = 0x01;
+pub const DELEGATION_SCOPE_SHARE: u8 = 0x02;
+pub const DELEGATION_SCOPE_PROFILE: u8 = 0x04;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ObjectPayload {
 	Profile(ProfileObject),
 	Post(PostObject),
 	Share(ShareObject),
+	KeyRotation(KeyRotationObject),
+	Edit(EditObject),
+	Delete(DeleteObject),
+	Reaction(ReactionObject),
+	Poll(PollObject),
+	Vote(VoteObject),
+	FollowerList(FollowerListObject),
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -226,6 +421,10 @@ impl ActorAddress {
 		}
 	}
 
+	/// Formats the address in the checksummed format, which is meant to be
+	/// shared with others.
+	pub fn to_bech32(&self) -> String { bech32::encode(ACTOR_ADDRESS_PREFIX, &self.to_bytes()) }
+
 	#[allow(dead_code)]
 	pub fn to_id(self) -> IdType {
 		match self {
@@ -310,6 +509,21 @@ impl Address {
 		}
 	}
 
+	/// Parses an address in the checksummed format. The prefix tells the type
+	/// of address, and the data starts with its version.
+	pub fn parse_bech32(string: &str) -> Result<Self, ParseAddressError> {
+		let (prefix, buffer) = bech32::decode(string)?;
+		if buffer.len() == 0 {
+			return Err(FromBytesAddressError::Empty.into());
+		}
+		let address = match prefix.as_str() {
+			ACTOR_ADDRESS_PREFIX => Address::Actor(ActorAddress::from_bytes(&buffer)?),
+			NODE_ADDRESS_PREFIX => Address::Node(NodeAddress::from_bytes(&buffer)?),
+			_ => return Err(ParseAddressError::InvalidBech32Prefix(prefix)),
+		};
+		Ok(address)
+	}
+
 	pub fn parse_new(string: &str) -> Result<Self, ParseAddressError> {
 		if string.len() == 0 {
 			return Err(ParseAddressError::Empty);
@@ -324,6 +538,13 @@ impl Address {
 		Ok(address)
 	}
 
+	pub fn to_bech32(&self) -> String {
+		match self {
+			Self::Actor(address) => address.to_bech32(),
+			Self::Node(address) => bech32::encode(NODE_ADDRESS_PREFIX, &address.to_bytes()),
+		}
+	}
+
 	#[allow(dead_code)]
 	pub fn to_bytes(&self) -> Vec<u8> {
 		match self {
@@ -358,6 +579,13 @@ impl FromStr for Address {
 			return Err(ParseAddressError::Empty);
 		}
 
+		// Checksummed addresses
+		if bech32::has_prefix(string, ACTOR_ADDRESS_PREFIX) ||
+			bech32::has_prefix(string, NODE_ADDRESS_PREFIX)
+		{
+			return Self::parse_bech32(string);
+		}
+
 		// Old-style addresses
 		let first_char = string.chars().next().unwrap();
 		if first_char == '1' || first_char == '2' {
@@ -500,10 +728,173 @@ impl ObjectPayload {
 			Self::Profile(_) => OBJECT_TYPE_PROFILE,
 			Self::Post(_) => OBJECT_TYPE_POST,
 			Self::Share(_) => OBJECT_TYPE_SHARE,
+			Self::KeyRotation(_) => OBJECT_TYPE_KEY_ROTATION,
+			Self::Edit(_) => OBJECT_TYPE_EDIT,
+			Self::Delete(_) => OBJECT_TYPE_DELETE,
+			Self::Reaction(_) => OBJECT_TYPE_REACTION,
+			Self::Poll(_) => OBJECT_TYPE_POLL,
+			Self::Vote(_) => OBJECT_TYPE_VOTE,
+			Self::FollowerList(_) => OBJECT_TYPE_FOLLOWER_LIST,
 		}
 	}
 }
 
+impl DelegationCertificate {
+	pub fn new(
+		actor_address: &ActorAddress, device_key: ActorPublicKeyV1, scopes: u8, signer: &dyn Signer,
+	) -> Result<Self, SignerError> {
+		let sign_data = DelegationSignData {
+			actor_address,
+			device_key: &device_key,
+			scopes,
+		};
+		let signature = signer.sign(&binserde::serialize(&sign_data).unwrap())?;
+		Ok(Self {
+			device_key,
+			scopes,
+			signature,
+		})
+	}
+
+	/// Whether the object may be signed by the device key.
+	pub fn allows(&self, payload: &ObjectPayload) -> bool {
+		let scope = match payload {
+			ObjectPayload::Post(_)
+			| ObjectPayload::Edit(_)
+			| ObjectPayload::Delete(_)
+			| ObjectPayload::Poll(_) => DELEGATION_SCOPE_POST,
+			ObjectPayload::Share(_) | ObjectPayload::Reaction(_) | ObjectPayload::Vote(_) =>
+				DELEGATION_SCOPE_SHARE,
+			ObjectPayload::Profile(_) | ObjectPayload::FollowerList(_) => DELEGATION_SCOPE_PROFILE,
+			ObjectPayload::KeyRotation(_) => return false,
+		};
+		self.scopes & scope != 0
+	}
+
+	pub fn from_bytes(buffer: &[u8]) -> Option<Self> { binserde::deserialize(buffer).ok() }
+
+	pub fn to_bytes(&self) -> Vec<u8> { binserde::serialize(self).unwrap() }
+
+	/// Whether the certificate was issued by the given key of the actor.
+	pub fn verify(&self, actor_address: &ActorAddress, public_key: &ActorPublicKeyV1) -> bool {
+		let sign_data = DelegationSignData {
+			actor_address,
+			device_key: &self.device_key,
+			scopes: self.scopes,
+		};
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		public_key.verify(&raw_sign_data, &self.signature)
+	}
+}
+
+impl KeyRotationObject {
+	pub fn new(
+		actor_address: &ActorAddress, sequence: u64, new_private_key: &ActorPrivateKeyV1,
+	) -> Self {
+		let sign_data = KeyRotationSignData {
+			actor_address,
+			sequence,
+		};
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		Self {
+			new_public_key: new_private_key.public(),
+			proof: new_private_key.sign(&raw_sign_data),
+		}
+	}
+
+	/// Whether the new key agreed to take over the actor at the given sequence.
+	pub fn verify_proof(&self, actor_address: &ActorAddress, sequence: u64) -> bool {
+		let sign_data = KeyRotationSignData {
+			actor_address,
+			sequence,
+		};
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		self.new_public_key.verify(&raw_sign_data, &self.proof)
+	}
+}
+
+impl DirectMessageObject {
+	/// Seals the content to the message key of the recipient, and signs it.
+	pub fn new(
+		sender: ActorAddress, recipient: ActorAddress, recipient_key: &message_key::MessageKey,
+		content: &DirectMessageContent, signer: &dyn Signer,
+	) -> Result<Self, SignerError> {
+		let created = current_timestamp();
+		let sealed = recipient_key.seal(&binserde::serialize(content).unwrap());
+		let sign_data = DirectMessageSignData {
+			sender: &sender,
+			recipient: &recipient,
+			created,
+			sealed: &sealed,
+		};
+		let signature = signer.sign(&binserde::serialize(&sign_data).unwrap())?;
+		Ok(Self {
+			sender,
+			recipient,
+			created,
+			sealed,
+			signature,
+		})
+	}
+
+	pub fn hash(&self) -> IdType { self.signature.hash() }
+
+	/// Opens the message with the message key of the recipient.
+	pub fn open(&self, secret: &message_key::MessageSecret) -> Option<DirectMessageContent> {
+		let plaintext = secret.open(&self.sealed)?;
+		binserde::deserialize_owned(&plaintext).ok()
+	}
+
+	/// Whether the message has been signed with the given key of the sender.
+	pub fn verify(&self, public_key: &ActorPublicKeyV1) -> bool {
+		let sign_data = DirectMessageSignData {
+			sender: &self.sender,
+			recipient: &self.recipient,
+			created: self.created,
+			sealed: &self.sealed,
+		};
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		public_key.verify(&raw_sign_data, &self.signature)
+	}
+}
+
+impl FollowRequestObject {
+	pub fn new(
+		requester: ActorAddress, actor_address: ActorAddress, node_address: NodeAddress,
+		signer: &dyn Signer,
+	) -> Result<Self, SignerError> {
+		let created = current_timestamp();
+		let sign_data = FollowRequestSignData {
+			requester: &requester,
+			actor_address: &actor_address,
+			node_address: &node_address,
+			created,
+		};
+		let signature = signer.sign(&binserde::serialize(&sign_data).unwrap())?;
+		Ok(Self {
+			requester,
+			actor_address,
+			node_address,
+			created,
+			signature,
+		})
+	}
+
+	pub fn hash(&self) -> IdType { self.signature.hash() }
+
+	/// Whether the request has been signed with the given key of the requester.
+	pub fn verify(&self, public_key: &ActorPublicKeyV1) -> bool {
+		let sign_data = FollowRequestSignData {
+			requester: &self.requester,
+			actor_address: &self.actor_address,
+			node_address: &self.node_address,
+			created: self.created,
+		};
+		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+		public_key.verify(&raw_sign_data, &self.signature)
+	}
+}
+
 impl From<FromBase58Error> for ParseAddressError {
 	fn from(other: FromBase58Error) -> Self { Self::FromBase58(other) }
 }
@@ -1,9 +1,44 @@
 // FIXME: Remove when going stable:
 #![allow(deprecated)]
 
+mod active_identity;
+mod archive;
+pub mod backlog;
+pub mod block_list;
+pub mod block_store;
+mod bookmark;
+mod conversation;
+mod deletion;
+mod delivery;
+mod draft;
+pub mod encryption;
+mod eviction;
+mod file_metadata;
+mod file_thumbnail;
+mod follower;
+pub mod health;
+pub mod import;
 mod install;
-
-use std::{cmp::min, fmt, net::SocketAddr, ops::*, path::*, str, time::Duration};
+pub mod journal;
+pub mod keyring;
+pub mod local_user;
+pub mod maintenance;
+pub mod mention;
+mod network_stats;
+mod parity;
+pub mod poll;
+pub mod prune;
+mod purge;
+pub mod reaction;
+pub mod search;
+pub mod tag;
+pub mod upload;
+pub mod vacuum;
+
+use std::{
+	borrow::Cow, cmp::min, fmt, io, net::SocketAddr, ops::*, panic, path::*, str, sync::Arc,
+	thread, time::Duration,
+};
 
 use async_trait::async_trait;
 use chacha20::{
@@ -20,8 +55,10 @@ use rusqlite::{
 };
 use sea_orm::{prelude::*, sea_query::*, *};
 use thiserror::Error;
+use tokio::task::spawn_blocking;
 use unsafe_send_sync::UnsafeSendSync;
 
+use self::{block_store::BlockStore, encryption::DatabaseKey, keyring::Keyring};
 use crate::{
 	common::*,
 	compression::{compress, decompress, mime_type_use_compression},
@@ -35,11 +72,36 @@ use crate::{
 
 
 pub(crate) const BLOCK_SIZE: usize = 0x100000; // 1 MiB
+/// The number of records that are inserted with a single statement, which
+/// keeps the number of bound parameters well below what SQLite allows.
+const INSERT_CHUNK_SIZE: usize = 100;
 
 #[derive(Clone)]
 pub struct Database {
 	path: PathBuf,
 	orm: DatabaseConnection,
+	health: Arc<health::Health>,
+	keyring: Arc<Keyring>,
+	block_store: Arc<BlockStore>,
+	options: DatabaseOptions,
+}
+
+/// How the connections to the database are set up.
+#[derive(Clone, Debug)]
+pub struct DatabaseOptions {
+	/// Whether to use write-ahead logging, which lets readers carry on while
+	/// another connection writes.
+	pub wal: bool,
+	/// How long a connection waits for a lock held by another connection,
+	/// before it gives up with `database is locked`.
+	pub busy_timeout: Duration,
+	/// The number of connections in the async connection pool.
+	pub max_connections: u32,
+	/// How long to wait for a connection of the pool to become available.
+	pub acquire_timeout: Duration,
+	/// The directory to keep the archive in, or `None` for a directory named
+	/// `archive` next to the database file.
+	pub archive_dir: Option<PathBuf>,
 }
 
 #[deprecated]
@@ -48,10 +110,26 @@ pub struct Connection {
 	// not need a mutex, that it is already thread-safe. For some reason it was
 	// not marked as Send and Sync.
 	old: UnsafeSendSync<rusqlite::Connection>,
+	keyring: Arc<Keyring>,
+	block_store: Arc<BlockStore>,
 }
 
 // TODO: Make the sea_orm::DatabaseTransaction inside private
-pub struct Transaction(pub(crate) sea_orm::DatabaseTransaction);
+pub struct Transaction(
+	pub(crate) sea_orm::DatabaseTransaction,
+	Arc<Keyring>,
+	Arc<BlockStore>,
+);
+
+/// How much of the history of a followed actor is synchronized. Without any
+/// limit, the whole blogchain is synchronized.
+#[derive(Clone, Debug, Default)]
+pub struct SyncDepth {
+	/// Only the latest number of objects.
+	pub object_limit: Option<u64>,
+	/// Only the objects that were created within the last number of days.
+	pub max_age: Option<u64>,
+}
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -67,15 +145,43 @@ pub enum Error {
 	InvalidHash(IdFromBase58Error),
 	InvalidSignature(NodeSignatureError),
 	InvalidPrivateKey(usize),
+	/// The private keys are encrypted, and haven't been unlocked.
+	KeysLocked,
+	InvalidMnemonic(bip39::Error),
+	/// The mnemonic phrase doesn't belong to the given identity.
+	MnemonicMismatch(ActorAddress),
+	/// A device key or its delegation certificate is malformed, or wasn't
+	/// issued by the identity.
+	InvalidDelegation,
+	/// The identity only has a device key, which isn't allowed to do this.
+	NotDelegated,
+	/// The external signer that holds the private key couldn't sign.
+	Signer(SignerError),
+	/// No actor address can start with the given vanity prefix.
+	InvalidVanityPrefix(String),
 	InvalidPublicKey(Option<NodePublicKeyError>),
 	/// The data that is stored for a block is corrupt
 	BlockDataCorrupt(i64),
 	PostMissingFiles(i64),
 	FileMissingBlock(i64, u32),
+	/// Unable to read from or write to the archive
+	Io(io::Error),
+	/// The user archive is malformed, or has been cut off.
+	InvalidUserArchive(String),
 
 	MissingIdentity(ActorAddress),
+	/// The database has been migrated by a newer version, in a way that can't
+	/// be undone.
+	DatabaseTooNew(String),
 	/// Something in the database is not how it is expected to be.
 	UnexpectedState(String),
+	/// The database can't be used at the moment, and the operation couldn't be
+	/// postponed.
+	Unavailable,
+	/// The idempotency key is malformed, or was used for another operation.
+	InvalidIdempotencyKey,
+	/// An operation with the same idempotency key hasn't finished yet.
+	RequestInProgress,
 }
 
 pub trait DerefConnection: Deref<Target = rusqlite::Connection> {}
@@ -91,6 +197,9 @@ pub trait PersistenceHandle {
 
 	fn inner(&self) -> &Self::Inner;
 
+	/// Where the data of the blocks is kept.
+	fn block_store(&self) -> &BlockStore;
+
 	fn backend(&self) -> DatabaseBackend { self.inner().get_database_backend() }
 
 
@@ -192,6 +301,49 @@ pub trait PersistenceHandle {
 			.is_some())
 	}
 
+	async fn has_identity(&self, actor_id: i64) -> Result<bool> {
+		Ok(identity::Entity::find()
+			.filter(identity::Column::ActorId.eq(actor_id))
+			.one(self.inner())
+			.await?
+			.is_some())
+	}
+
+	async fn is_followed(&self, actor_id: i64) -> Result<bool> {
+		Ok(following::Entity::find_by_id(actor_id)
+			.one(self.inner())
+			.await?
+			.is_some())
+	}
+
+	/// Finds the hash of a file that the block belongs to, together with the
+	/// size of the block.
+	async fn find_block_file(&self, block_hash: &IdType) -> Result<Option<(IdType, u32)>> {
+		let result = file_block::Entity::find()
+			.select_only()
+			.column(file::Column::Hash)
+			.column(block::Column::Size)
+			.join(
+				JoinType::InnerJoin,
+				file_block::Entity::belongs_to(file::Entity)
+					.from(file_block::Column::FileId)
+					.to(file::Column::Id)
+					.into(),
+			)
+			.join(
+				JoinType::InnerJoin,
+				file_block::Entity::belongs_to(block::Entity)
+					.from(file_block::Column::BlockHash)
+					.to(block::Column::Hash)
+					.into(),
+			)
+			.filter(file_block::Column::BlockHash.eq(block_hash))
+			.into_tuple::<(IdType, u32)>()
+			.one(self.inner())
+			.await?;
+		Ok(result)
+	}
+
 	async fn has_object(&self, hash: &IdType) -> Result<bool> {
 		Ok(object::Entity::find()
 			.filter(object::Column::Hash.eq(hash))
@@ -220,6 +372,66 @@ pub trait PersistenceHandle {
 		Ok(servers)
 	}
 
+	/// Loads the data of an archived block, if it has been archived.
+	async fn load_archived_block(&self, block_id: i64) -> Result<Option<Vec<u8>>> {
+		if let Some(record) = archived_block::Entity::find()
+			.filter(archived_block::Column::BlockId.eq(block_id))
+			.one(self.inner())
+			.await?
+		{
+			Ok(Some(archive::read_block(
+				self.block_store().archive_dir(),
+				&record.segment,
+				record.offset,
+				record.length,
+				record.compression_type,
+			)?))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Loads the data of a block that isn't kept in the database itself, from
+	/// either the block store or the archive.
+	async fn load_external_block(&self, block_id: i64, hash: &IdType) -> Result<Option<Vec<u8>>> {
+		if let Some(data) = self.block_store().load(hash)? {
+			return Ok(Some(data));
+		}
+		self.load_archived_block(block_id).await
+	}
+
+	/// Loads the data of a block, wherever it is kept.
+	async fn load_block(&self, hash: &IdType) -> Result<Option<Vec<u8>>> {
+		let record = if let Some(r) = block::Entity::find()
+			.filter(block::Column::Hash.eq(hash))
+			.one(self.inner())
+			.await?
+		{
+			r
+		} else {
+			return Ok(None);
+		};
+
+		if archive::needs_touch(record.last_access) {
+			block::Entity::update_many()
+				.col_expr(
+					block::Column::LastAccess,
+					Expr::value(Utc::now().timestamp_millis()),
+				)
+				.filter(block::Column::Id.eq(record.id))
+				.exec(self.inner())
+				.await?;
+		}
+
+		if record.data.len() == 0 && record.size > 0 {
+			if let Some(data) = self.load_external_block(record.id, &record.hash).await? {
+				return Ok(Some(data));
+			}
+			Err(Error::BlockDataCorrupt(record.id))?;
+		}
+		Ok(Some(record.data))
+	}
+
 	async fn load_file_blocks(&self, file_id: i64, block_count: u32) -> Result<Vec<IdType>> {
 		let results = file_block::Entity::find()
 			.filter(file_block::Column::FileId.eq(file_id))
@@ -312,6 +524,7 @@ pub trait PersistenceHandle {
 			if sequence != i {
 				Err(Error::FileMissingBlock(file_id, sequence))?;
 			}
+			let block_hash: IdType = row.try_get_by_index(0)?;
 			let block_id: Option<i64> = row.try_get_by_index(2)?;
 			let size2: Option<i64> = row.try_get_by_index(3)?;
 			let data2: Option<Vec<u8>> = row.try_get_by_index(4)?;
@@ -321,6 +534,14 @@ pub trait PersistenceHandle {
 			}
 			let size = size2.unwrap() as usize;
 			let mut data = data2.unwrap();
+			if data.len() == 0 && size > 0 {
+				if let Some(external_data) = self
+					.load_external_block(block_id.unwrap(), &block_hash)
+					.await?
+				{
+					data = external_data;
+				}
+			}
 			data.resize(size, 0);
 
 			if data.len() < size {
@@ -364,33 +585,6 @@ pub trait PersistenceHandle {
 		Ok(is_following)
 	}
 
-	async fn load_node_identity(&self) -> Result<(NodeAddress, NodePrivateKey)> {
-		let result = match node_identity::Entity::find().one(self.inner()).await? {
-			Some(m) => {
-				let key_len = m.private_key.len();
-				match m.private_key.try_into() {
-					Ok(buffer) => (m.address, NodePrivateKey::from_bytes(buffer)),
-					Err(_) => Err(Error::InvalidPrivateKey(key_len))?,
-				}
-			}
-			None => {
-				let private_key = NodePrivateKey::generate();
-				let address = NodeAddress::V1(IdType::hash(&private_key.public().to_bytes()));
-
-				let record = node_identity::ActiveModel {
-					id: NotSet,
-					address: Set(address.clone()),
-					private_key: Set(private_key.as_bytes().to_vec()),
-				};
-				node_identity::Entity::insert(record)
-					.exec(self.inner())
-					.await?;
-				(address, private_key)
-			}
-		};
-		Ok(result)
-	}
-
 	async fn load_post_object_payload(&self, object_id: i64) -> Result<Option<PostObject>> {
 		let result = post_object::Entity::find_by_id(object_id)
 			.one(self.inner())
@@ -472,9 +666,75 @@ pub trait PersistenceHandle {
 			avatar: r.avatar_file_hash,
 			wallpaper: r.wallpaper_file_hash,
 			description: r.description_file_hash,
+			followers_only: r.followers_only,
 		}))
 	}
 
+	async fn load_key_rotation_object_payload(
+		&self, object_id: i64,
+	) -> Result<Option<KeyRotationObject>> {
+		let result = key_rotation_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(match result {
+			None => None,
+			Some(r) => Some(KeyRotationObject {
+				new_public_key: parse_actor_public_key(r.new_public_key)?,
+				proof: r.proof,
+			}),
+		})
+	}
+
+	/// Loads the keys that the actor has rotated to, along with the sequence
+	/// numbers of the objects that rotated to them, in order.
+	async fn load_key_rotations(&self, actor_id: i64) -> Result<Vec<(u64, ActorPublicKeyV1)>> {
+		let results = key_rotation_object::Entity::find()
+			.find_also_related(object::Entity)
+			.filter(object::Column::ActorId.eq(actor_id))
+			.order_by_asc(object::Column::Sequence)
+			.all(self.inner())
+			.await?;
+		let mut rotations = Vec::with_capacity(results.len());
+		for (record, object) in results {
+			if let Some(object) = object {
+				rotations.push((
+					object.sequence as u64,
+					parse_actor_public_key(record.new_public_key)?,
+				));
+			}
+		}
+		Ok(rotations)
+	}
+
+	/// Loads the delegation certificate of our identity, if it only has a device
+	/// key.
+	async fn load_identity_delegation(
+		&self, actor_id: i64,
+	) -> Result<Option<DelegationCertificate>> {
+		let result = identity::Entity::find()
+			.filter(identity::Column::ActorId.eq(actor_id))
+			.one(self.inner())
+			.await?;
+		match result.and_then(|r| r.delegation) {
+			None => Ok(None),
+			Some(buffer) => match DelegationCertificate::from_bytes(&buffer) {
+				Some(certificate) => Ok(Some(certificate)),
+				None => Err(Error::InvalidDelegation)?,
+			},
+		}
+	}
+
+	/// Loads the key that the newest objects of the actor are signed with.
+	async fn load_current_public_key(&self, actor_id: i64) -> Result<Option<ActorPublicKeyV1>> {
+		if let Some((_, key)) = self.load_key_rotations(actor_id).await?.pop() {
+			return Ok(Some(key));
+		}
+		match actor::Entity::find_by_id(actor_id).one(self.inner()).await? {
+			Some(r) => Ok(Some(parse_actor_public_key(r.public_key)?)),
+			None => Ok(None),
+		}
+	}
+
 	async fn load_share_object_payload(&self, object_id: i64) -> Result<Option<ShareObject>> {
 		let result = share_object::Entity::find_by_id(object_id)
 			.one(self.inner())
@@ -485,6 +745,104 @@ pub trait PersistenceHandle {
 		}))
 	}
 
+	async fn load_reaction_object_payload(
+		&self, object_id: i64,
+	) -> Result<Option<ReactionObject>> {
+		let result = reaction_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|r| ReactionObject {
+			actor_address: r.actor_address,
+			object_hash: r.object_hash,
+			emoji: r.emoji.into(),
+		}))
+	}
+
+	async fn load_poll_object_payload(&self, object_id: i64) -> Result<Option<PollObject>> {
+		let poll = match poll_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?
+		{
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		let options = poll_option::Entity::find()
+			.filter(poll_option::Column::ObjectId.eq(object_id))
+			.order_by_asc(poll_option::Column::Sequence)
+			.all(self.inner())
+			.await?;
+		let options: Vec<LimString<_>> = options.into_iter().map(|o| o.text.into()).collect();
+		Ok(Some(PollObject {
+			question: poll.question.into(),
+			options: options.into(),
+			multiple_choice: poll.multiple_choice,
+			closes: poll.closes as _,
+		}))
+	}
+
+	async fn load_vote_object_payload(&self, object_id: i64) -> Result<Option<VoteObject>> {
+		let result = vote_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|r| VoteObject {
+			actor_address: r.actor_address,
+			object_hash: r.object_hash,
+			choices: r.choices.into(),
+		}))
+	}
+
+	async fn load_follower_list_object_payload(
+		&self, object_id: i64,
+	) -> Result<FollowerListObject> {
+		let followers: Vec<_> = follower_list_entry::Entity::find()
+			.filter(follower_list_entry::Column::ObjectId.eq(object_id))
+			.order_by_asc(follower_list_entry::Column::Sequence)
+			.all(self.inner())
+			.await?
+			.into_iter()
+			.map(|r| ApprovedFollower {
+				actor_address: r.actor_address,
+				node_address: r.node_address,
+			})
+			.collect();
+		Ok(FollowerListObject {
+			followers: followers.into(),
+		})
+	}
+
+	async fn load_edit_object_payload(&self, object_id: i64) -> Result<Option<EditObject>> {
+		let result = edit_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(match result {
+			None => None,
+			Some(r) => Some(EditObject {
+				object_hash: r.edited_object_hash,
+				files: self.load_post_files(object_id).await?.into(),
+			}),
+		})
+	}
+
+	async fn load_delete_object_payload(&self, object_id: i64) -> Result<Option<DeleteObject>> {
+		let result = delete_object::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|r| DeleteObject {
+			object_hash: r.deleted_object_hash,
+		}))
+	}
+
+	async fn load_sync_depth(&self, actor_address: &ActorAddress) -> Result<Option<SyncDepth>> {
+		let result = following::Entity::find()
+			.filter(following::Column::ActorId.in_subquery(query_actor_id(actor_address)))
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|r| SyncDepth {
+			object_limit: r.sync_object_limit.map(|l| l as _),
+			max_age: r.sync_max_age.map(|a| a as _),
+		}))
+	}
+
 	async fn load_object_payload(
 		&self, object_id: i64, object_type: u8,
 	) -> Result<Option<ObjectPayload>> {
@@ -501,6 +859,34 @@ pub trait PersistenceHandle {
 				.load_profile_object_payload(object_id)
 				.await?
 				.map(|p| ObjectPayload::Profile(p)),
+			OBJECT_TYPE_KEY_ROTATION => self
+				.load_key_rotation_object_payload(object_id)
+				.await?
+				.map(|k| ObjectPayload::KeyRotation(k)),
+			OBJECT_TYPE_EDIT => self
+				.load_edit_object_payload(object_id)
+				.await?
+				.map(|e| ObjectPayload::Edit(e)),
+			OBJECT_TYPE_DELETE => self
+				.load_delete_object_payload(object_id)
+				.await?
+				.map(|d| ObjectPayload::Delete(d)),
+			OBJECT_TYPE_REACTION => self
+				.load_reaction_object_payload(object_id)
+				.await?
+				.map(|r| ObjectPayload::Reaction(r)),
+			OBJECT_TYPE_POLL => self
+				.load_poll_object_payload(object_id)
+				.await?
+				.map(|p| ObjectPayload::Poll(p)),
+			OBJECT_TYPE_VOTE => self
+				.load_vote_object_payload(object_id)
+				.await?
+				.map(|v| ObjectPayload::Vote(v)),
+			// A follower list can be empty, so it always exists
+			OBJECT_TYPE_FOLLOWER_LIST => Some(ObjectPayload::FollowerList(
+				self.load_follower_list_object_payload(object_id).await?,
+			)),
 			_ => None,
 		})
 	}
@@ -544,6 +930,74 @@ pub trait PersistenceHandle {
 		Ok(result)
 	}
 
+	/// Finds out which of the blocks of the file we have, in order.
+	/// Returns `None` if the file isn't known.
+	async fn find_held_blocks(&self, file_hash: &IdType) -> Result<Option<Vec<bool>>> {
+		let file = match file::Entity::find()
+			.filter(file::Column::Hash.eq(file_hash))
+			.one(self.inner())
+			.await?
+		{
+			Some(f) => f,
+			None => return Ok(None),
+		};
+
+		let sequences = file_block::Entity::find()
+			.select_only()
+			.column(file_block::Column::Sequence)
+			.join(
+				JoinType::InnerJoin,
+				file_block::Entity::belongs_to(block::Entity)
+					.from(file_block::Column::BlockHash)
+					.to(block::Column::Hash)
+					.into(),
+			)
+			.filter(file_block::Column::FileId.eq(file.id))
+			.into_tuple::<u32>()
+			.all(self.inner())
+			.await?;
+		let mut held = vec![false; file.block_count as usize];
+		for sequence in sequences {
+			if let Some(h) = held.get_mut(sequence as usize) {
+				*h = true;
+			}
+		}
+		Ok(Some(held))
+	}
+
+	/// Finds the size of the blocks of the file, and the size of the whole
+	/// file. Only the last block can be smaller than the others, so this is
+	/// known as soon as the first and the last block are stored.
+	async fn find_file_size(&self, file_id: i64, block_count: u32) -> Result<Option<(u64, u64)>> {
+		if block_count == 0 {
+			return Ok(Some((0, 0)));
+		}
+		let sizes = file_block::Entity::find()
+			.select_only()
+			.column(file_block::Column::Sequence)
+			.column(block::Column::Size)
+			.join(
+				JoinType::InnerJoin,
+				file_block::Entity::belongs_to(block::Entity)
+					.from(file_block::Column::BlockHash)
+					.to(block::Column::Hash)
+					.into(),
+			)
+			.filter(file_block::Column::FileId.eq(file_id))
+			.filter(file_block::Column::Sequence.is_in([0, block_count - 1]))
+			.into_tuple::<(u32, u32)>()
+			.all(self.inner())
+			.await?;
+		let find = |sequence| sizes.iter().find(|(s, _)| *s == sequence).map(|(_, size)| *size);
+		Ok(match (find(0), find(block_count - 1)) {
+			(Some(block_size), Some(last_size)) => Some((
+				block_size as u64,
+				(block_count - 1) as u64 * block_size as u64 + last_size as u64,
+			)),
+			_ => None,
+		})
+	}
+
 	async fn find_file_data(
 		&self, file_id: i64, plain_hash: &IdType, block_count: u32,
 	) -> Result<Option<Vec<u8>>> {
@@ -581,8 +1035,16 @@ pub trait PersistenceHandle {
 			}
 			let block_id_opt: Option<i64> = r.try_get_by("block_id")?;
 			if let Some(block_id) = block_id_opt {
+				let block_hash: IdType = r.try_get_by(file_block::Column::BlockHash.as_str())?;
 				let size: i64 = r.try_get_by(block::Column::Size.as_str())?;
 				let mut data: Vec<u8> = r.try_get_by(block::Column::Data.as_str())?;
+				if data.len() == 0 && size > 0 {
+					if let Some(external_data) =
+						self.load_external_block(block_id, &block_hash).await?
+					{
+						data = external_data;
+					}
+				}
 
 				data.resize(size as _, 0);
 				if (data.len() as i64) < size {
@@ -757,6 +1219,59 @@ pub trait PersistenceHandle {
 		}
 	}
 
+	/// The number of bytes of block data that we have of the files that the
+	/// objects of the actor use.
+	async fn actor_block_size(&self, actor_id: i64) -> Result<u64> {
+		let sql = r#"
+			WITH actor_object AS (
+				SELECT id FROM object WHERE actor_id = ?
+			), actor_file AS (
+				SELECT hash FROM post_file WHERE object_id IN (SELECT id FROM actor_object)
+				UNION SELECT avatar_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM actor_object)
+				UNION SELECT wallpaper_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM actor_object)
+				UNION SELECT description_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM actor_object)
+			)
+			SELECT SUM(size) FROM block WHERE hash IN (
+				SELECT fb.block_hash FROM file_block AS fb
+				INNER JOIN file AS f ON f.id = fb.file_id
+				WHERE f.hash IN (SELECT hash FROM actor_file)
+			)
+		"#;
+		let stat = Statement::from_sql_and_values(self.backend(), sql, [actor_id.into()]);
+		if let Some(result) = self.inner().query_one(stat).await? {
+			let sum: Option<i64> = result.try_get_by_index(0)?;
+			Ok(sum.unwrap_or(0) as u64)
+		} else {
+			Ok(0)
+		}
+	}
+
+	async fn count_actor_objects(&self, actor_id: i64) -> Result<u64> {
+		Ok(object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.count(self.inner())
+			.await?)
+	}
+
+	/// The number of bytes of block data that we have, including the blocks
+	/// that have been archived and the parity blocks that we keep for others.
+	async fn total_block_size(&self) -> Result<u64> {
+		let stat = block::Entity::find()
+			.select_only()
+			.column_as(block::Column::Size.sum(), "sum")
+			.build(self.backend());
+		let block_size = if let Some(result) = self.inner().query_one(stat).await? {
+			let sum: Option<i64> = result.try_get_by_index(0)?;
+			sum.unwrap_or(0) as u64
+		} else {
+			0
+		};
+		Ok(block_size + self.total_parity_size().await?)
+	}
+
 	async fn update_identity_label(&self, old_label: &str, new_label: &str) -> Result<()> {
 		let mut model = <identity::ActiveModel as std::default::Default>::default();
 		model.label = Set(new_label.to_string());
@@ -767,9 +1282,43 @@ pub trait PersistenceHandle {
 			.await?;
 		Ok(())
 	}
+
+	/// Marks the identity as private or not. The objects of a private identity
+	/// are only shown on an exposed web interface with a share link.
+	async fn set_identity_private(&self, actor_id: i64, is_private: bool) -> Result<()> {
+		let mut model = <identity::ActiveModel as std::default::Default>::default();
+		model.is_private = Set(is_private);
+		identity::Entity::update_many()
+			.set(model)
+			.filter(identity::Column::ActorId.eq(actor_id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
 }
 
 
+impl SyncDepth {
+	pub fn is_limited(&self) -> bool { self.object_limit.is_some() || self.max_age.is_some() }
+
+	/// The timestamp before which objects are not synchronized, in
+	/// milliseconds.
+	pub fn min_created(&self) -> u64 {
+		match self.max_age {
+			None => 0,
+			Some(days) => current_timestamp().saturating_sub(days * 24 * 3600 * 1000),
+		}
+	}
+}
+
+
+fn parse_actor_public_key(buffer: Vec<u8>) -> Result<ActorPublicKeyV1> {
+	match buffer.try_into() {
+		Ok(bytes) => Ok(ActorPublicKeyV1::from_bytes(bytes).unwrap()),
+		Err(_) => Err(Error::InvalidPublicKey(None))?,
+	}
+}
+
 #[allow(dead_code)]
 fn query_actor_id(address: &ActorAddress) -> SelectStatement {
 	Query::select()
@@ -802,6 +1351,29 @@ impl ToSql for ActorAddress {
 	}
 }
 
+impl FromSql for NodeAddress {
+	fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+		match value {
+			ValueRef::Blob(blob) =>
+				if blob.len() != 33 {
+					Err(FromSqlError::InvalidBlobSize {
+						expected_size: 33,
+						blob_size: blob.len(),
+					})
+				} else {
+					Ok(Self::from_bytes(blob).map_err(|e| FromSqlError::Other(Box::new(e)))?)
+				},
+			_ => Err(FromSqlError::InvalidType),
+		}
+	}
+}
+
+impl ToSql for NodeAddress {
+	fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+		Ok(ToSqlOutput::Owned(Value::Blob(self.to_bytes())))
+	}
+}
+
 impl ToSql for IdType {
 	fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
 		Ok(ToSqlOutput::Owned(Value::Text(self.to_string())))
@@ -891,24 +1463,100 @@ impl FromSql for NodePublicKey {
 	}
 }
 
-impl Database {
-	pub fn connect_old(&self) -> self::Result<Connection> { Ok(Connection::open_old(&self.path)?) }
-
-	/// Runs the given closure, which pauzes the task that runs it, but doesn't
-	/// block the runtime.
-	pub fn perform<T>(&self, task: impl FnOnce(Connection) -> Result<T>) -> Result<T> {
-		tokio::task::block_in_place(move || {
-			let connection = self.connect_old()?;
-			task(connection)
-		})
+impl Default for DatabaseOptions {
+	fn default() -> Self {
+		let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1) as u32;
+		Self {
+			wal: true,
+			busy_timeout: Duration::from_secs(5),
+			max_connections: (2 * cores).clamp(4, 32),
+			acquire_timeout: Duration::from_secs(10),
+			archive_dir: None,
+		}
 	}
+}
 
-	fn install(conn: &Connection) -> Result<()> { Ok(conn.execute_batch(install::QUERY)?) }
+/// Runs the closure on the thread pool that is meant for blocking work. A
+/// panic in the closure is passed on to the task that awaits it.
+async fn run_blocking<T>(f: impl FnOnce() -> T + Send + 'static) -> Result<T>
+where
+	T: Send + 'static,
+{
+	match spawn_blocking(f).await {
+		Ok(result) => Ok(result),
+		Err(e) => match e.try_into_panic() {
+			Ok(payload) => panic::resume_unwind(payload),
+			Err(e) => Err(Error::UnexpectedState(format!("blocking task failed: {}", e)))?,
+		},
+	}
+}
 
-	pub async fn load(path: PathBuf) -> Result<Self> {
-		let connection = Connection::open_old(&path).map_err(|e| Error::SqliteError(e))?;
+impl Database {
+	pub fn connect_old(&self) -> self::Result<Connection> {
+		Ok(Connection::open_old(
+			&self.path,
+			self.keyring.clone(),
+			self.block_store.clone(),
+			self.options.busy_timeout,
+		)?)
+	}
 
-		match connection.prepare("SELECT major, minor FROM version") {
+	/// Runs the given closure with a connection of the old kind, which does
+	/// blocking calls. It is run on the thread pool that is meant for blocking
+	/// work, so that neither the runtime nor the task that awaits it is held
+	/// up, not even on the current-thread runtime.
+	pub async fn perform<T, F>(&self, task: F) -> Result<T>
+	where
+		T: Send + 'static,
+		F: FnOnce(Connection) -> Result<T> + Send + 'static,
+	{
+		let this = self.clone();
+		run_blocking(move || task(this.connect_old()?)).await?
+	}
+
+	/// The directory that the data of the blocks is kept in, which is named
+	/// after the database file, so that `db.sqlite` keeps them in `db.blocks`.
+	pub fn block_store_dir(path: &Path) -> PathBuf { path.with_extension("blocks") }
+
+	/// The directory for files that are derived from the data in the database,
+	/// and that can be generated again when they are lost.
+	pub fn derived_files_dir(&self) -> PathBuf {
+		let mut dir = self.path.clone();
+		dir.pop();
+		dir.push("derived");
+		dir
+	}
+
+	fn install(conn: &Connection) -> Result<()> {
+		// Needs to be set before any table is created
+		conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+		Ok(conn.execute_batch(install::QUERY)?)
+	}
+
+	pub async fn load(path: PathBuf) -> Result<Self> {
+		Self::load_with_key(path, None, DatabaseOptions::default()).await
+	}
+
+	/// Loads the database, which is encrypted with the given key if there is
+	/// one.
+	pub async fn load_with_key(
+		path: PathBuf, key: Option<DatabaseKey>, options: DatabaseOptions,
+	) -> Result<Self> {
+		let keyring = Arc::new(Keyring::new(key));
+		let archive_dir = options
+			.archive_dir
+			.clone()
+			.unwrap_or_else(|| path.with_file_name("archive"));
+		let block_store = Arc::new(BlockStore::new(Self::block_store_dir(&path), archive_dir));
+		let connection = Connection::open_old(
+			&path,
+			keyring.clone(),
+			block_store.clone(),
+			options.busy_timeout,
+		)
+		.map_err(|e| Error::SqliteError(e))?;
+
+		match connection.prepare("SELECT major, minor FROM version") {
 			Ok(mut stat) => {
 				let mut rows = stat.query([])?;
 				let _row = rows.next()?.expect("missing version data");
@@ -926,26 +1574,59 @@ impl Database {
 				_ => Err(e)?,
 			},
 		}
+		// The journal mode is stored in the database file, so it only has to be
+		// set once for all connections
+		let journal_mode = if options.wal { "WAL" } else { "DELETE" };
+		connection.query_row(&format!("PRAGMA journal_mode = {}", journal_mode), [], |_| {
+			Ok(())
+		})?;
+		drop(connection);
 
 		let mut opts = ConnectOptions::new(format!("sqlite://{}?mode=rwc", path.display()));
+		opts.max_connections(options.max_connections);
 		opts.idle_timeout(Duration::from_secs(10));
-		opts.acquire_timeout(Duration::from_secs(1));
+		opts.acquire_timeout(options.acquire_timeout);
 		opts.sqlx_logging_level(log::LevelFilter::Trace);
+		// The key has to be given to every connection in the pool, before
+		// anything else
+		let key_value = keyring
+			.database_key()
+			.map(|key| format!("\"{}\"", key.pragma_value()));
+		let busy_timeout = options.busy_timeout;
+		opts.map_sqlx_sqlite_opts(move |mut o| {
+			if let Some(value) = &key_value {
+				o = o.pragma("key", Cow::Owned(value.clone()));
+			}
+			o.pragma("journal_mode", journal_mode).busy_timeout(busy_timeout)
+		});
 		let orm = sea_orm::Database::connect(opts)
 			.await
 			.map_err(|e| self::Error::OrmError(e))?;
 
-		Ok(Self { path, orm })
+		Ok(Self {
+			path,
+			orm,
+			health: Arc::new(health::Health::default()),
+			keyring,
+			block_store,
+			options,
+		})
 	}
 
 	pub async fn transaction(&self) -> Result<Transaction> {
 		let tx = self.orm.begin().await?;
-		Ok(Transaction(tx))
+		Ok(Transaction(
+			tx,
+			self.keyring.clone(),
+			self.block_store.clone(),
+		))
 	}
 }
 
 impl Connection {
-	fn _fetch_block_data<C>(this: &C, id: &IdType) -> Result<Option<Vec<u8>>>
+	fn _fetch_block_data<C>(
+		this: &C, block_store: &BlockStore, id: &IdType,
+	) -> Result<Option<Vec<u8>>>
 	where
 		C: DerefConnection,
 	{
@@ -958,7 +1639,16 @@ impl Connection {
 		if let Some(row) = rows.next()? {
 			let rowid: i64 = row.get(0)?;
 			let size: usize = row.get(1)?;
-			let data: Vec<u8> = row.get(2)?;
+			let mut data: Vec<u8> = row.get(2)?;
+			if data.len() == 0 && size > 0 {
+				if let Some(external_data) = block_store.load(id)? {
+					data = external_data;
+				} else if let Some(archived_data) =
+					Self::_fetch_archived_block_data(this, block_store, rowid)?
+				{
+					data = archived_data;
+				}
+			}
 
 			if data.len() < size {
 				Err(Error::BlockDataCorrupt(rowid))?
@@ -978,6 +1668,32 @@ impl Connection {
 		}
 	}
 
+	fn _fetch_archived_block_data(
+		this: &impl DerefConnection, block_store: &BlockStore, block_id: i64,
+	) -> Result<Option<Vec<u8>>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT segment, offset, length, compression_type FROM archived_block WHERE block_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([block_id])?;
+		if let Some(row) = rows.next()? {
+			let segment: String = row.get(0)?;
+			let offset: i64 = row.get(1)?;
+			let length: i64 = row.get(2)?;
+			let compression_type: u8 = row.get(3)?;
+			Ok(Some(archive::read_block(
+				block_store.archive_dir(),
+				&segment,
+				offset,
+				length,
+				compression_type,
+			)?))
+		} else {
+			Ok(None)
+		}
+	}
+
 	pub(super) fn _fetch_file_block_hash(
 		this: &impl DerefConnection, file_id: i64, sequence: u64,
 	) -> Result<Option<IdType>> {
@@ -1019,6 +1735,7 @@ impl Connection {
 			FROM object AS o
 			LEFT JOIN actor AS i ON o.actor_id = i.id
 			WHERE i.address = ? AND o.sequence = ?
+			ORDER BY o.created ASC, o.hash ASC
 		"#,
 		)?;
 		let mut rows = stat.query(params![actor_id, sequence])?;
@@ -1035,6 +1752,7 @@ impl Connection {
 			r#"
 			SELECT o.hash FROM object AS o LEFT JOIN actor AS i ON o.actor_id = i.id
 			WHERE i.address = ? AND o.sequence = ?
+			ORDER BY o.created ASC, o.hash ASC
 		"#,
 			params![actor_address, sequence],
 			|r| r.get(0),
@@ -1051,6 +1769,7 @@ impl Connection {
 		let mut stat = this.prepare(
 			r#"
 			SELECT id FROM object WHERE actor_id = ? AND sequence = ?
+			ORDER BY created ASC, hash ASC
 		"#,
 		)?;
 		let mut rows = stat.query(params![actor_id, sequence])?;
@@ -1075,7 +1794,7 @@ impl Connection {
 			FROM object AS o
 			LEFT JOIN actor AS i ON o.actor_id = i.id
 			WHERE i.address = ?
-			ORDER BY sequence DESC LIMIT 1
+			ORDER BY o.sequence DESC, o.created ASC, o.hash ASC LIMIT 1
 		"#,
 		)?;
 		let mut rows = stat.query(params![actor_id])?;
@@ -1101,6 +1820,88 @@ impl Connection {
 		Self::_parse_object(tx, &mut rows)
 	}
 
+	fn _fetch_object_delegation(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<DelegationCertificate>> {
+		let buffer: Option<Vec<u8>> = this.query_row(
+			r#"
+			SELECT delegation FROM object WHERE id = ?
+		"#,
+			[object_id],
+			|r| r.get(0),
+		)?;
+		match buffer {
+			None => Ok(None),
+			Some(b) => match DelegationCertificate::from_bytes(&b) {
+				Some(certificate) => Ok(Some(certificate)),
+				None => Err(Error::InvalidDelegation)?,
+			},
+		}
+	}
+
+	fn _fetch_edit_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<EditObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT edited_object_hash
+			FROM edit_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		if let Some(row) = rows.next()? {
+			let files = Self::_fetch_post_files(this, object_id)?;
+			Ok(Some(EditObject {
+				object_hash: row.get(0)?,
+				files: files.into(),
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn _fetch_delete_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<DeleteObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT deleted_object_hash
+			FROM delete_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		if let Some(row) = rows.next()? {
+			Ok(Some(DeleteObject {
+				object_hash: row.get(0)?,
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn _fetch_key_rotation_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<KeyRotationObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT new_public_key, proof
+			FROM key_rotation_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		if let Some(row) = rows.next()? {
+			Ok(Some(KeyRotationObject {
+				new_public_key: row.get(0)?,
+				proof: row.get(1)?,
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
 	pub fn _fetch_share_object<C>(this: &C, object_id: i64) -> Result<Option<ShareObject>>
 	where
 		C: DerefConnection,
@@ -1126,6 +1927,114 @@ impl Connection {
 		}
 	}
 
+	fn _fetch_reaction_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<ReactionObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT actor_address, object_hash, emoji
+			FROM reaction_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		if let Some(row) = rows.next()? {
+			let emoji: String = row.get(2)?;
+			Ok(Some(ReactionObject {
+				actor_address: row.get(0)?,
+				object_hash: row.get(1)?,
+				emoji: emoji.into(),
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn _fetch_poll_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<PollObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT question, multiple_choice, closes
+			FROM poll_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		let (question, multiple_choice, closes) = if let Some(row) = rows.next()? {
+			let question: String = row.get(0)?;
+			let closes: i64 = row.get(2)?;
+			(question, row.get(1)?, closes)
+		} else {
+			return Ok(None);
+		};
+
+		let mut stat = this.prepare(
+			r#"
+			SELECT text FROM poll_option WHERE object_id = ? ORDER BY sequence ASC
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		let mut options = Vec::new();
+		while let Some(row) = rows.next()? {
+			let text: String = row.get(0)?;
+			options.push(LimString::from(text));
+		}
+		Ok(Some(PollObject {
+			question: question.into(),
+			options: options.into(),
+			multiple_choice,
+			closes: closes as _,
+		}))
+	}
+
+	fn _fetch_follower_list_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<FollowerListObject> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT actor_address, node_address
+			FROM follower_list_entry
+			WHERE object_id = ?
+			ORDER BY sequence ASC
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		let mut followers = Vec::new();
+		while let Some(row) = rows.next()? {
+			followers.push(ApprovedFollower {
+				actor_address: row.get(0)?,
+				node_address: row.get(1)?,
+			});
+		}
+		Ok(FollowerListObject {
+			followers: followers.into(),
+		})
+	}
+
+	fn _fetch_vote_object(
+		this: &impl DerefConnection, object_id: i64,
+	) -> Result<Option<VoteObject>> {
+		let mut stat = this.prepare(
+			r#"
+			SELECT actor_address, object_hash, choices
+			FROM vote_object
+			WHERE object_id = ?
+		"#,
+		)?;
+		let mut rows = stat.query([object_id])?;
+		if let Some(row) = rows.next()? {
+			let choices: Vec<u8> = row.get(2)?;
+			Ok(Some(VoteObject {
+				actor_address: row.get(0)?,
+				object_hash: row.get(1)?,
+				choices: choices.into(),
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
 	fn _fetch_post_files(this: &impl DerefConnection, object_id: i64) -> Result<Vec<IdType>> {
 		// Collect the files
 		let mut files = Vec::new();
@@ -1188,7 +2097,8 @@ impl Connection {
 	{
 		let mut stat = this.prepare(
 			r#"
-			SELECT name, avatar_file_hash, wallpaper_file_hash, description_file_hash
+			SELECT name, avatar_file_hash, wallpaper_file_hash, description_file_hash,
+				followers_only
 			FROM profile_object
 			WHERE object_id = ?
 		"#,
@@ -1207,6 +2117,7 @@ impl Connection {
 				avatar: avatar_id,
 				wallpaper: wallpaper_id,
 				description: description_hash,
+				followers_only: row.get(4)?,
 			}))
 		} else {
 			Ok(None)
@@ -1363,6 +2274,7 @@ impl Connection {
 			let object_type = row.get(5)?;
 			let previous_hash: Option<IdType> = row.get(6)?;
 			let verified_from_start: bool = row.get(7)?;
+			let delegation = Self::_fetch_object_delegation(tx, object_id)?;
 
 			let payload = match object_type {
 				OBJECT_TYPE_POST => Self::_fetch_post_object(tx, object_id)
@@ -1371,6 +2283,20 @@ impl Connection {
 					.map(|o| o.map(|b| ObjectPayload::Share(b))),
 				OBJECT_TYPE_PROFILE => Self::_fetch_profile_object(tx, object_id)
 					.map(|o| o.map(|p| ObjectPayload::Profile(p))),
+				OBJECT_TYPE_KEY_ROTATION => Self::_fetch_key_rotation_object(tx, object_id)
+					.map(|o| o.map(|k| ObjectPayload::KeyRotation(k))),
+				OBJECT_TYPE_EDIT => Self::_fetch_edit_object(tx, object_id)
+					.map(|o| o.map(|e| ObjectPayload::Edit(e))),
+				OBJECT_TYPE_DELETE => Self::_fetch_delete_object(tx, object_id)
+					.map(|o| o.map(|d| ObjectPayload::Delete(d))),
+				OBJECT_TYPE_REACTION => Self::_fetch_reaction_object(tx, object_id)
+					.map(|o| o.map(|r| ObjectPayload::Reaction(r))),
+				OBJECT_TYPE_POLL => Self::_fetch_poll_object(tx, object_id)
+					.map(|o| o.map(|p| ObjectPayload::Poll(p))),
+				OBJECT_TYPE_VOTE => Self::_fetch_vote_object(tx, object_id)
+					.map(|o| o.map(|v| ObjectPayload::Vote(v))),
+				OBJECT_TYPE_FOLLOWER_LIST => Self::_fetch_follower_list_object(tx, object_id)
+					.map(|l| Some(ObjectPayload::FollowerList(l))),
 				other => Err(Error::InvalidObjectType(other))?,
 			};
 			payload.map(|o| {
@@ -1383,6 +2309,7 @@ impl Connection {
 							created,
 							signature,
 							payload: p,
+							delegation,
 						},
 						verified_from_start,
 					)
@@ -1393,26 +2320,30 @@ impl Connection {
 		}
 	}
 
+	/// Stores the block, unless a block with the same hash exists already.
+	/// Returns whether the block was new. The data itself goes into the block
+	/// store.
 	pub fn _store_block(
-		tx: &impl DerefConnection, _file_id: i64, hash: &IdType, data: &[u8],
-	) -> Result<()> {
-		let mut stat = tx.prepare(
+		tx: &impl DerefConnection, block_store: &BlockStore, _file_id: i64, hash: &IdType,
+		data: &[u8],
+	) -> Result<bool> {
+		// The record goes first, which takes the write lock of the database before
+		// the data is written. See `Database::remove_block_data` for why.
+		// The statement is cached, so that storing many blocks on the same connection
+		// doesn't need to parse it again every time.
+		let mut stat = tx.prepare_cached(
 			r#"
-			INSERT INTO block (hash, size, data) VALUES (?,?,?)
+			INSERT OR IGNORE INTO block (hash, size, data, last_access) VALUES (?,?,?,?)
 		"#,
 		)?;
-		if let Err(e) = stat.insert(params![hash, data.len(), data]) {
-			match e {
-				rusqlite::Error::SqliteFailure(error, _) => {
-					// If the hash already exists, do nothing, it is fine...
-					if error.code != rusqlite::ErrorCode::ConstraintViolation {
-						Err(e)?
-					}
-				}
-				_ => Err(e)?,
-			}
-		}
-		Ok(())
+		let inserted = stat.execute(params![
+			hash,
+			data.len(),
+			Vec::<u8>::new(),
+			Utc::now().timestamp_millis()
+		])?;
+		block_store.store(hash, data)?;
+		Ok(inserted > 0)
 	}
 
 	pub(crate) fn _store_file(
@@ -1448,9 +2379,10 @@ impl Connection {
 	}
 
 	fn _store_file_block(
-		tx: &impl DerefConnection, file_id: i64, sequence: u64, hash: &IdType, data: &[u8],
+		tx: &impl DerefConnection, block_store: &BlockStore, file_id: i64, sequence: u64,
+		hash: &IdType, data: &[u8],
 	) -> Result<()> {
-		Self::_store_block(tx, file_id, hash, data)?;
+		Self::_store_block(tx, block_store, file_id, hash, data)?;
 		tx.execute(
 			r#"
 			INSERT INTO file_block (file_id, block_hash, sequence) VALUES (?,?,?)
@@ -1559,8 +2491,8 @@ impl Connection {
 				r#"
 			INSERT INTO object (
 				actor_id, sequence, hash, signature, created, found, type, previous_hash,
-				verified_from_start
-			) VALUES(?,?,?,?,?,?,?,?,?)
+				verified_from_start, delegation
+			) VALUES(?,?,?,?,?,?,?,?,?,?)
 			"#,
 			)?;
 			let object_id = stat.insert(params![
@@ -1573,8 +2505,14 @@ impl Connection {
 				object.payload.type_id(),
 				object.previous_hash.to_string(),
 				verified_from_start,
+				object.delegation.as_ref().map(|d| d.to_bytes()),
 			])?;
 			Self::_store_object_payload(tx, actor_rowid, object_id, &object.payload)?;
+			if let ObjectPayload::Post(_) | ObjectPayload::Profile(_) | ObjectPayload::Edit(_) =
+				&object.payload
+			{
+				search::queue_object_old(tx, object_id)?;
+			}
 			Ok(object_id)
 		} else {
 			Err(Error::MissingIdentity(actor_address.clone()))?
@@ -1620,7 +2558,7 @@ impl Connection {
 		stat.insert(params![object_id, files.len(), a, o])?;
 
 		// Store all tags & files
-		Self::_store_post_tags(tx, object_id, tags)?;
+		tag::store_tags_old(tx, object_id, tags)?;
 		Self::_store_post_files(tx, actor_id, object_id, files)?;
 		Ok(())
 	}
@@ -1662,25 +2600,13 @@ impl Connection {
 				])?;
 
 				let tags2: Vec<_> = plain.tags.iter().map(|t| t.clone().to_string()).collect();
-				Self::_store_post_tags(tx, object_id, &tags2)?;
+				tag::store_tags_old(tx, object_id, &tags2)?;
 				Self::_store_post_files(tx, actor_id, object_id, &plain.files)?;
 				Ok(())
 			}
 		}
 	}
 
-	fn _store_post_tags(tx: &impl DerefConnection, object_id: i64, tags: &[String]) -> Result<()> {
-		for tag in tags {
-			tx.execute(
-				r#"
-				INSERT INTO post_tag (object_id, tag) VALUES (?, ?)
-			"#,
-				params![object_id, tag],
-			)?;
-		}
-		Ok(())
-	}
-
 	fn _store_object_payload(
 		tx: &impl DerefConnection, actor_id: i64, object_id: i64, payload: &ObjectPayload,
 	) -> Result<()> {
@@ -1689,6 +2615,16 @@ impl Connection {
 				Self::_store_post_object_payload(tx, actor_id, object_id, &po),
 			ObjectPayload::Share(po) => Self::_store_boost_object_payload(tx, object_id, &po),
 			ObjectPayload::Profile(po) => Self::_store_profile_object_payload(tx, object_id, &po),
+			ObjectPayload::KeyRotation(ko) =>
+				Self::_store_key_rotation_object_payload(tx, object_id, &ko),
+			ObjectPayload::Edit(eo) =>
+				Self::_store_edit_object_payload(tx, actor_id, object_id, &eo),
+			ObjectPayload::Delete(dobj) => Self::_store_delete_object_payload(tx, object_id, &dobj),
+			ObjectPayload::Reaction(ro) => Self::_store_reaction_object_payload(tx, object_id, &ro),
+			ObjectPayload::Poll(po) => Self::_store_poll_object_payload(tx, object_id, &po),
+			ObjectPayload::Vote(vo) => Self::_store_vote_object_payload(tx, object_id, &vo),
+			ObjectPayload::FollowerList(fo) =>
+				Self::_store_follower_list_object_payload(tx, object_id, &fo),
 		}
 	}
 
@@ -1748,18 +2684,142 @@ impl Connection {
 		Ok(())
 	}
 
+	fn _store_reaction_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &ReactionObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO reaction_object (object_id, actor_address, object_hash, emoji)
+			VALUES (?,?,?,?)
+		"#,
+			params![
+				object_id,
+				&payload.actor_address,
+				&payload.object_hash,
+				payload.emoji.as_str()
+			],
+		)?;
+		Ok(())
+	}
+
+	fn _store_poll_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &PollObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO poll_object (object_id, question, multiple_choice, closes)
+			VALUES (?,?,?,?)
+		"#,
+			params![
+				object_id,
+				payload.question.as_str(),
+				payload.multiple_choice,
+				payload.closes as i64
+			],
+		)?;
+		for (i, option) in payload.options.iter().enumerate() {
+			tx.execute(
+				r#"
+				INSERT INTO poll_option (object_id, sequence, text)
+				VALUES (?,?,?)
+			"#,
+				params![object_id, i as i32, option.as_str()],
+			)?;
+		}
+		Ok(())
+	}
+
+	fn _store_follower_list_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &FollowerListObject,
+	) -> Result<()> {
+		for (i, follower) in payload.followers.iter().enumerate() {
+			tx.execute(
+				r#"
+				INSERT INTO follower_list_entry (object_id, sequence, actor_address, node_address)
+				VALUES (?,?,?,?)
+			"#,
+				params![
+					object_id,
+					i as i32,
+					&follower.actor_address,
+					&follower.node_address
+				],
+			)?;
+		}
+		Ok(())
+	}
+
+	fn _store_vote_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &VoteObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO vote_object (object_id, actor_address, object_hash, choices)
+			VALUES (?,?,?,?)
+		"#,
+			params![
+				object_id,
+				&payload.actor_address,
+				&payload.object_hash,
+				&*payload.choices
+			],
+		)?;
+		Ok(())
+	}
+
+	fn _store_key_rotation_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &KeyRotationObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO key_rotation_object (object_id, new_public_key, proof)
+			VALUES (?,?,?)
+		"#,
+			params![object_id, &payload.new_public_key, &payload.proof],
+		)?;
+		Ok(())
+	}
+
+	fn _store_edit_object_payload(
+		tx: &impl DerefConnection, actor_id: i64, object_id: i64, payload: &EditObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO edit_object (object_id, edited_object_hash, file_count)
+			VALUES (?,?,?)
+		"#,
+			params![object_id, &payload.object_hash, payload.files.len()],
+		)?;
+		Self::_store_post_files(tx, actor_id, object_id, &payload.files)?;
+		Ok(())
+	}
+
+	fn _store_delete_object_payload(
+		tx: &impl DerefConnection, object_id: i64, payload: &DeleteObject,
+	) -> Result<()> {
+		tx.execute(
+			r#"
+			INSERT INTO delete_object (object_id, deleted_object_hash)
+			VALUES (?,?)
+		"#,
+			params![object_id, &payload.object_hash],
+		)?;
+		Ok(())
+	}
+
 	fn _store_profile_object_payload(
 		tx: &impl DerefConnection, object_id: i64, payload: &ProfileObject,
 	) -> Result<()> {
 		tx.execute(r#"
-			INSERT INTO profile_object (object_id, name, avatar_file_hash, wallpaper_file_hash, description_file_hash)
-			VALUES (?,?,?,?,?)
+			INSERT INTO profile_object (object_id, name, avatar_file_hash, wallpaper_file_hash, description_file_hash, followers_only)
+			VALUES (?,?,?,?,?,?)
 		"#, params![
 			object_id,
 			payload.name.as_str(),
 			&payload.avatar,
 			&payload.wallpaper,
 			&payload.description,
+			payload.followers_only,
 		])?;
 		Ok(())
 	}
@@ -1781,6 +2841,12 @@ impl Connection {
 		"#,
 			[object_id],
 		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM post_mention WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
 		self.old.execute(
 			r#"
 			DELETE FROM post_tag WHERE object_id = ?
@@ -1801,11 +2867,66 @@ impl Connection {
 		)?;
 		self.old.execute(
 			r#"
-			DELETE FROM profile_object WHERE object_id = ?
+			DELETE FROM key_rotation_object WHERE object_id = ?
 		"#,
 			[object_id],
 		)?;
-
+		self.old.execute(
+			r#"
+			DELETE FROM edit_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM delete_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM tombstone WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM reaction_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM poll_option WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM poll_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM vote_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM follower_list_entry WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		self.old.execute(
+			r#"
+			DELETE FROM profile_object WHERE object_id = ?
+		"#,
+			[object_id],
+		)?;
+		search::remove_object_old(self, object_id)?;
+
 		let affected = self.old.execute(
 			r#"
 			DELETE FROM object WHERE id = ?
@@ -1842,7 +2963,7 @@ impl Connection {
 	pub fn fetch_block(&self, id: &IdType) -> Result<Option<Vec<u8>>> {
 		let mut stat = self.prepare(
 			r#"
-			SELECT b.id, b.size, b.data
+			SELECT b.id, b.size, b.data, b.last_access
 			FROM block AS b
 			WHERE b.hash = ?
 		"#,
@@ -1851,7 +2972,23 @@ impl Connection {
 		if let Some(row) = rows.next()? {
 			let block_id = row.get(0)?;
 			let size: usize = row.get(1)?;
-			let data: Vec<u8> = row.get(2)?;
+			let mut data: Vec<u8> = row.get(2)?;
+			let last_access: i64 = row.get(3)?;
+			if archive::needs_touch(last_access) {
+				self.execute(
+					"UPDATE block SET last_access = ? WHERE id = ?",
+					params![Utc::now().timestamp_millis(), block_id],
+				)?;
+			}
+			if data.len() == 0 && size > 0 {
+				if let Some(external_data) = self.block_store.load(id)? {
+					data = external_data;
+				} else if let Some(archived_data) =
+					Self::_fetch_archived_block_data(self, &self.block_store, block_id)?
+				{
+					data = archived_data;
+				}
+			}
 			if data.len() != size {
 				Err(Error::BlockDataCorrupt(block_id))?;
 			}
@@ -1919,8 +3056,30 @@ impl Connection {
 			LEFT JOIN actor AS i ON f.actor_id = i.id
 		"#,
 		)?;
-		let mut rows = stat.query([])?;
+		let rows = stat.query([])?;
+		Self::parse_follow_list(rows)
+	}
 
+	/// Fetches the actors that the local user follows, or that the owner of the
+	/// node follows if `None`.
+	pub fn fetch_user_follow_list(
+		&self, user_id: Option<i64>,
+	) -> Result<Vec<(ActorAddress, ActorInfo)>> {
+		let mut stat = self.prepare(
+			r#"
+			SELECT i.address, i.public_key, i.first_object, i.type
+			FROM following AS f
+			LEFT JOIN actor AS i ON f.actor_id = i.id
+			WHERE (?1 IS NULL AND NOT f.user_only) OR f.actor_id IN (
+				SELECT actor_id FROM local_user_following WHERE user_id = ?1
+			)
+		"#,
+		)?;
+		let rows = stat.query(params![user_id])?;
+		Self::parse_follow_list(rows)
+	}
+
+	fn parse_follow_list(mut rows: rusqlite::Rows<'_>) -> Result<Vec<(ActorAddress, ActorInfo)>> {
 		let mut list = Vec::new();
 		while let Some(row) = rows.next()? {
 			let address: ActorAddress = row.get(0)?;
@@ -1999,6 +3158,73 @@ impl Connection {
 		}
 	}
 
+	/// Returns the keys that the actor has rotated to, along with the sequence
+	/// numbers of the objects that rotated to them, in order.
+	pub fn fetch_key_rotations(
+		&self, actor_address: &ActorAddress,
+	) -> Result<Vec<(u64, ActorPublicKeyV1)>> {
+		let mut stat = self.prepare(
+			r#"
+			SELECT o.sequence, kro.new_public_key
+			FROM key_rotation_object AS kro
+			INNER JOIN object AS o ON kro.object_id = o.id
+			INNER JOIN actor AS i ON o.actor_id = i.id
+			WHERE i.address = ?
+			ORDER BY o.sequence ASC
+		"#,
+		)?;
+		let mut rows = stat.query(params![actor_address])?;
+		let mut rotations = Vec::new();
+		while let Some(row) = rows.next()? {
+			rotations.push((row.get(0)?, row.get(1)?));
+		}
+		Ok(rotations)
+	}
+
+	/// The sequence numbers at which more than one object of the actor is
+	/// known.
+	pub fn fetch_forked_sequences(&self, actor_address: &ActorAddress) -> Result<Vec<u64>> {
+		let mut stat = self.prepare(
+			r#"
+			SELECT o.sequence
+			FROM object AS o
+			INNER JOIN actor AS i ON o.actor_id = i.id
+			WHERE i.address = ?
+			GROUP BY o.sequence
+			HAVING COUNT(*) > 1
+			ORDER BY o.sequence ASC
+		"#,
+		)?;
+		let mut rows = stat.query(params![actor_address])?;
+		let mut sequences = Vec::new();
+		while let Some(row) = rows.next()? {
+			sequences.push(row.get(0)?);
+		}
+		Ok(sequences)
+	}
+
+	/// All objects of the actor with the given sequence number, the one that
+	/// wins the fork first.
+	pub fn fetch_objects_by_sequence(
+		&self, actor_address: &ActorAddress, sequence: u64,
+	) -> Result<Vec<(IdType, BlogchainObject)>> {
+		let mut stat = self.prepare(
+			r#"
+			SELECT o.id, o.sequence, o.created, o.signature, o.hash, o.type, o.previous_hash, o.verified_from_start
+			FROM object AS o
+			LEFT JOIN actor AS i ON o.actor_id = i.id
+			WHERE i.address = ? AND o.sequence = ?
+			ORDER BY o.created ASC, o.hash ASC
+		"#,
+		)?;
+		let mut rows = stat.query(params![actor_address, sequence])?;
+		let mut objects = Vec::new();
+		while let Some((hash, object, _)) = Self::_parse_object(self, &mut rows)? {
+			objects.push((hash, object));
+		}
+		Ok(objects)
+	}
+
 	pub fn fetch_identity(&self, address: &ActorAddress) -> Result<Option<ActorInfo>> {
 		let mut stat = self.prepare(
 			r#"
@@ -2045,10 +3271,11 @@ impl Connection {
 
 	pub fn fetch_my_identity(
 		&self, address: &ActorAddress,
-	) -> Result<Option<(String, ActorPrivateKeyV1)>> {
+	) -> Result<Option<(String, Box<dyn Signer>)>> {
 		let mut stat = self.old.prepare(
 			r#"
-			SELECT label, private_key FROM identity AS mi LEFT JOIN actor AS i
+			SELECT label, private_key FROM identity AS mi
+			INNER JOIN actor AS i ON mi.actor_id = i.id
 			WHERE i.address = ?
 		"#,
 		)?;
@@ -2057,31 +3284,54 @@ impl Connection {
 			None => Ok(None),
 			Some(row) => {
 				let label = row.get(0)?;
-				let private_key: ActorPrivateKeyV1 = row.get(1)?;
-				Ok(Some((label, private_key)))
+				let stored_key: Vec<u8> = row.get(1)?;
+				let signer = self.keyring.load_signer(&stored_key)?;
+				Ok(Some((label, signer)))
 			}
 		}
 	}
 
 	pub fn fetch_my_identities(
 		&self,
-	) -> Result<Vec<(String, ActorAddress, IdType, String, ActorPrivateKeyV1)>> {
+	) -> Result<Vec<(String, ActorAddress, IdType, String, ActorPublicKeyV1)>> {
 		let mut stat = self.old.prepare(
 			r#"
-			SELECT label, i.address, i.first_object, i.type, mi.private_key
+			SELECT label, i.address, i.first_object, i.type, i.public_key
 			FROM identity AS mi
 			LEFT JOIN actor AS i ON mi.actor_id = i.id
 		"#,
 		)?;
-		let mut rows = stat.query([])?;
+		let rows = stat.query([])?;
+		Self::parse_my_identities(rows)
+	}
 
+	/// Fetches the identities of the local user, or of the owner of the node if
+	/// `None`.
+	pub fn fetch_user_identities(
+		&self, user_id: Option<i64>,
+	) -> Result<Vec<(String, ActorAddress, IdType, String, ActorPublicKeyV1)>> {
+		let mut stat = self.old.prepare(
+			r#"
+			SELECT label, i.address, i.first_object, i.type, i.public_key
+			FROM identity AS mi
+			LEFT JOIN actor AS i ON mi.actor_id = i.id
+			WHERE mi.user_id IS ?
+		"#,
+		)?;
+		let rows = stat.query(params![user_id])?;
+		Self::parse_my_identities(rows)
+	}
+
+	fn parse_my_identities(
+		mut rows: rusqlite::Rows<'_>,
+	) -> Result<Vec<(String, ActorAddress, IdType, String, ActorPublicKeyV1)>> {
 		let mut ids = Vec::new();
 		while let Some(row) = rows.next()? {
 			let address: ActorAddress = row.get(1)?;
 			let first_object: IdType = row.get(2)?;
 			let actor_type: String = row.get(3)?;
-			let private_key: ActorPrivateKeyV1 = row.get(4)?;
-			ids.push((row.get(0)?, address, first_object, actor_type, private_key));
+			let public_key: ActorPublicKeyV1 = row.get(4)?;
+			ids.push((row.get(0)?, address, first_object, actor_type, public_key));
 		}
 		Ok(ids)
 	}
@@ -2107,7 +3357,9 @@ impl Connection {
 		}
 	}
 
-	pub fn follow(&mut self, actor_id: &ActorAddress, actor_info: &ActorInfo) -> Result<()> {
+	pub fn follow(
+		&mut self, actor_id: &ActorAddress, actor_info: &ActorInfo, sync_depth: &SyncDepth,
+	) -> Result<()> {
 		let tx = self.old.transaction()?;
 
 		let actor_id = {
@@ -2123,7 +3375,7 @@ impl Connection {
 				drop(rows);
 				let mut stat = tx.prepare(
 					r#"
-					INSERT INTO actor (address, public_key, first_object, type) VALUES (?,?,?,?,?)
+					INSERT INTO actor (address, public_key, first_object, type) VALUES (?,?,?,?)
 				"#,
 				)?;
 				stat.insert(params![
@@ -2138,9 +3390,13 @@ impl Connection {
 
 		tx.execute(
 			r#"
-			INSERT INTO following (actor_id) VALUES (?)
+			INSERT INTO following (actor_id, sync_object_limit, sync_max_age) VALUES (?,?,?)
 		"#,
-			params![actor_id],
+			params![
+				actor_id,
+				sync_depth.object_limit.map(|l| l as i64),
+				sync_depth.max_age.map(|a| a as i64)
+			],
 		)?;
 
 		tx.commit()?;
@@ -2212,26 +3468,69 @@ impl Connection {
 		Ok(rows.next()?.is_some())
 	}
 
+	/// Whether the local user follows the actor, or the owner of the node if
+	/// `None`.
+	pub fn is_user_following(&self, actor_id: &ActorAddress, user_id: Option<i64>) -> Result<bool> {
+		let mut stat = self.prepare(
+			r#"
+			SELECT 1
+			FROM following AS f
+			LEFT JOIN actor AS i ON f.actor_id = i.id
+			WHERE i.address = ?1 AND ((?2 IS NULL AND NOT f.user_only) OR f.actor_id IN (
+				SELECT actor_id FROM local_user_following WHERE user_id = ?2
+			))
+		"#,
+		)?;
+		let mut rows = stat.query(params![actor_id, user_id])?;
+		Ok(rows.next()?.is_some())
+	}
+
 	pub fn old(&self) -> &rusqlite::Connection { &self.old.0 }
 
 	pub fn old_mut(&mut self) -> &mut rusqlite::Connection { &mut self.old.0 }
 
-	pub fn open_old(path: &Path) -> rusqlite::Result<Self> {
+	pub fn open_old(
+		path: &Path, keyring: Arc<Keyring>, block_store: Arc<BlockStore>, busy_timeout: Duration,
+	) -> rusqlite::Result<Self> {
 		let c = rusqlite::Connection::open(&path)?;
+		// The key has to come before anything else is done with the database
+		if let Some(key) = keyring.database_key() {
+			c.pragma_update(None, "key", key.pragma_value())?;
+		}
+		c.busy_timeout(busy_timeout)?;
 		// For some reason foreign key checks are not working properly on windows, so
 		// disable it for now.
 		#[cfg(target_family = "windows")]
 		c.pragma_update(None, "foreign_keys", false)?;
 		Ok(Self {
 			old: UnsafeSendSync::new(c),
+			keyring,
+			block_store,
 		})
 	}
 
 	pub fn store_block(&mut self, file_id: i64, hash: &IdType, data: &[u8]) -> Result<()> {
-		Self::_store_block(self, file_id, hash, data)?;
+		Self::_store_block(self, &self.block_store, file_id, hash, data)?;
 		Ok(())
 	}
 
+	/// Stores all given blocks in one transaction, which is a lot faster than
+	/// storing them one by one. Blocks that exist already are skipped.
+	/// Returns the number of blocks that were new.
+	pub fn store_blocks<'a>(
+		&mut self, blocks: impl IntoIterator<Item = (&'a IdType, &'a [u8])>,
+	) -> Result<usize> {
+		let tx = self.old.transaction()?;
+		let mut stored = 0;
+		for (hash, data) in blocks {
+			if Self::_store_block(&tx, &self.block_store, 0, hash, data)? {
+				stored += 1;
+			}
+		}
+		tx.commit()?;
+		Ok(stored)
+	}
+
 	pub fn store_file(&mut self, id: &IdType, file: &File) -> Result<i64> {
 		Self::_store_file(
 			self,
@@ -2318,12 +3617,16 @@ impl PersistenceHandle for Database {
 	type Inner = sea_orm::DatabaseConnection;
 
 	fn inner(&self) -> &Self::Inner { &self.orm }
+
+	fn block_store(&self) -> &BlockStore { &self.block_store }
 }
 
 impl PersistenceHandle for Transaction {
 	type Inner = sea_orm::DatabaseTransaction;
 
 	fn inner(&self) -> &Self::Inner { &self.0 }
+
+	fn block_store(&self) -> &BlockStore { &self.2 }
 }
 
 impl Deref for Connection {
@@ -2359,12 +3662,32 @@ impl fmt::Display for Error {
 				write!(f, "file {} missing block sequence {}", file_id, sequence)
 			}
 			Self::InvalidPrivateKey(len) => write!(f, "invalid private key (size={})", len),
+			Self::KeysLocked => write!(f, "the private keys are locked"),
+			Self::InvalidMnemonic(e) => write!(f, "invalid mnemonic phrase: {}", e),
+			Self::MnemonicMismatch(address) =>
+				write!(f, "the mnemonic phrase doesn't belong to identity {}", address),
+			Self::InvalidDelegation => write!(f, "invalid device key"),
+			Self::NotDelegated => write!(f, "a device key isn't allowed to do this"),
+			Self::Signer(e) => write!(f, "{}", e),
+			Self::InvalidVanityPrefix(reason) => write!(f, "invalid vanity prefix: {}", reason),
 			Self::InvalidPublicKey(oe) => match oe {
 				Some(e) => write!(f, "invalid public key: {}", e),
 				None => write!(f, "invalid public key size"),
 			},
+			Self::Io(e) => write!(f, "I/O error: {}", e),
+			Self::InvalidUserArchive(reason) => write!(f, "invalid user archive: {}", reason),
 			Self::MissingIdentity(hash) => write!(f, "identity {:?} is missing", &hash),
+			Self::DatabaseTooNew(version) => write!(
+				f,
+				"the database has been upgraded to {} by a newer version of Stonenet, which \
+				 this version can't undo; run the newer version again, or restore a backup",
+				version
+			),
 			Self::UnexpectedState(msg) => write!(f, "unexpected database state: {}", msg),
+			Self::Unavailable => write!(f, "database is temporarily unavailable"),
+			Self::InvalidIdempotencyKey => write!(f, "invalid idempotency key"),
+			Self::RequestInProgress =>
+				write!(f, "a request with the same idempotency key is still in progress"),
 		}
 	}
 }
@@ -2381,6 +3704,22 @@ impl From<FromBytesAddressError> for Traced<Error> {
 	fn from(other: FromBytesAddressError) -> Self { Error::ActorAddress(other).trace() }
 }
 
+impl From<io::Error> for Error {
+	fn from(other: io::Error) -> Self { Self::Io(other) }
+}
+
+impl From<io::Error> for Traced<Error> {
+	fn from(other: io::Error) -> Self { Error::Io(other).trace() }
+}
+
+impl From<SignerError> for Error {
+	fn from(other: SignerError) -> Self { Self::Signer(other) }
+}
+
+impl From<SignerError> for Traced<Error> {
+	fn from(other: SignerError) -> Self { Error::Signer(other).trace() }
+}
+
 impl From<rusqlite::Error> for Error {
 	fn from(other: rusqlite::Error) -> Self { Self::SqliteError(other) }
 }
@@ -2489,7 +3828,7 @@ impl Transaction {
 			bs
 		};
 		let mut block_hashes = Vec::with_capacity(block_count);
-		let mut block_ids = Vec::with_capacity(block_count);
+		let mut block_records = Vec::with_capacity(block_count);
 
 		// Devide data into blocks, and store them if they don't yet exist
 		let plain_hash = IdType::hash(data);
@@ -2502,30 +3841,15 @@ impl Transaction {
 			encrypt_block(block_index, &plain_hash, &mut block);
 			let block_hash = IdType::hash(&block);
 			block_hashes.push(block_hash.clone());
+			self.block_store().store(&block_hash, &block)?;
 
-			// Store block with an invalid file_id
-			// When PostgreSQL & MySQL are available, use a unique temporary file_id because
-			// there may be multiple files stored at once. For SQLite it should not be an
-			// issue.
-			let block_id = if let Some(record) = block::Entity::find()
-				.filter(block::Column::Hash.eq(&block_hash))
-				.one(self.inner())
-				.await?
-			{
-				record.id
-			} else {
-				let insert = block::ActiveModel {
-					id: NotSet,
-					hash: Set(block_hash),
-					size: Set(actual_block_size as _),
-					data: Set(block),
-				};
-				block::Entity::insert(insert)
-					.exec(self.inner())
-					.await?
-					.last_insert_id
-			};
-			block_ids.push(block_id);
+			block_records.push(block::ActiveModel {
+				id: NotSet,
+				hash: Set(block_hash),
+				size: Set(actual_block_size as _),
+				data: Set(Vec::new()),
+				last_access: Set(Utc::now().timestamp_millis()),
+			});
 
 			block_index += 1;
 			i += block_size;
@@ -2534,6 +3858,21 @@ impl Transaction {
 			}
 		}
 
+		// Store the blocks in bulk, skipping the ones that exist already
+		while block_records.len() > 0 {
+			let chunk: Vec<_> = block_records
+				.drain(..min(INSERT_CHUNK_SIZE, block_records.len()))
+				.collect();
+			block::Entity::insert_many(chunk)
+				.on_conflict(
+					OnConflict::column(block::Column::Hash)
+						.do_nothing()
+						.to_owned(),
+				)
+				.exec_without_returning(self.inner())
+				.await?;
+		}
+
 		// Calculate the file hash
 		let file_hash = IdType::hash(
 			&binserde::serialize(&File {
@@ -2567,18 +3906,20 @@ impl Transaction {
 				.last_insert_id;
 
 			// Create the file_block records
-			let mut seq = 0;
-			for block_hash in &block_hashes {
-				let insert = file_block::ActiveModel {
+			let records: Vec<_> = block_hashes
+				.iter()
+				.enumerate()
+				.map(|(seq, block_hash)| file_block::ActiveModel {
 					id: NotSet,
 					file_id: Set(file_id),
 					block_hash: Set(block_hash.clone()),
-					sequence: Set(seq),
-				};
-				file_block::Entity::insert(insert)
-					.exec(self.inner())
+					sequence: Set(seq as _),
+				})
+				.collect();
+			for chunk in records.chunks(INSERT_CHUNK_SIZE) {
+				file_block::Entity::insert_many(chunk.to_vec())
+					.exec_without_returning(self.inner())
 					.await?;
-				seq += 1;
 			}
 			file_id
 		};
@@ -2588,7 +3929,7 @@ impl Transaction {
 
 	pub async fn create_identity(
 		&self, label: &str, address: &ActorAddress, public_key: &ActorPublicKeyV1,
-		private_key: &ActorPrivateKeyV1, is_private: bool, first_object_hash: &IdType,
+		signer: &dyn Signer, is_private: bool, first_object_hash: &IdType, seed: Option<&[u8]>,
 	) -> Result<i64> {
 		let model = actor::ActiveModel {
 			id: NotSet,
@@ -2601,14 +3942,35 @@ impl Transaction {
 			.exec(self.inner())
 			.await?
 			.last_insert_id;
+		self.store_identity(label, actor_id, signer, is_private, seed, None)
+			.await?;
+		Ok(actor_id)
+	}
+
+	/// Makes the actor one of our identities.
+	pub async fn store_identity(
+		&self, label: &str, actor_id: i64, signer: &dyn Signer, is_private: bool,
+		seed: Option<&[u8]>, delegation: Option<&DelegationCertificate>,
+	) -> Result<()> {
+		// The first identity of the user becomes the active one
+		let has_active = identity::Entity::find()
+			.filter(identity::Column::IsActive.eq(true))
+			.filter(local_user::identity_condition())
+			.one(self.inner())
+			.await?
+			.is_some();
 		let model = identity::ActiveModel {
 			label: Set(label.to_string()),
 			actor_id: Set(actor_id),
-			private_key: Set(private_key.as_bytes().to_vec()),
+			private_key: Set(self.1.store_signer(signer)?),
 			is_private: Set(is_private),
+			seed: Set(seed.map(|s| self.1.encrypt(s)).transpose()?),
+			is_active: Set(!has_active),
+			delegation: Set(delegation.map(|d| d.to_bytes())),
+			user_id: Set(local_user::current_user()),
 		};
 		identity::Entity::insert(model).exec(self.inner()).await?;
-		Ok(actor_id)
+		Ok(())
 	}
 
 	async fn store_object(
@@ -2616,6 +3978,8 @@ impl Transaction {
 		signature: &ActorSignatureV1, verified_from_start: bool, published_on_fediverse: bool,
 	) -> Result<i64> {
 		let next_sequence = self.find_next_object_sequence(actor_id).await?;
+		// Objects that are signed with a device key carry its certificate along
+		let delegation = self.load_identity_delegation(actor_id).await?;
 		let record = object::ActiveModel {
 			id: NotSet,
 			actor_id: Set(actor_id),
@@ -2628,6 +3992,7 @@ impl Transaction {
 			signature: Set(signature.clone()),
 			verified_from_start: Set(verified_from_start),
 			published_on_fediverse: Set(published_on_fediverse),
+			delegation: Set(delegation.map(|d| d.to_bytes())),
 		};
 		Ok(object::Entity::insert(record)
 			.exec(self.inner())
@@ -2668,8 +4033,201 @@ impl Transaction {
 			.await?;
 
 		// Store all tags & files
-		self.store_post_tags(object_id, tags).await?;
+		tag::store_tags(self, object_id, tags).await?;
 		self.store_post_files(object_id, files).await?;
+		search::queue_object(self, object_id).await?;
+		Ok(())
+	}
+
+	/// Stores an edit of one of the posts of the actor. The files of the new
+	/// version are stored like those of a post.
+	pub async fn store_edit(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, edited_object_hash: &IdType, files: &[IdType],
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_EDIT,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		let record = edit_object::ActiveModel {
+			object_id: Set(object_id),
+			edited_object_hash: Set(edited_object_hash.clone()),
+			file_count: Set(files.len() as _),
+		};
+		edit_object::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		self.store_post_files(object_id, files).await?;
+		search::queue_object(self, object_id).await?;
+		Ok(())
+	}
+
+	/// Stores the deletion of one of the posts of the actor. The content of the
+	/// post is only dropped by [`Database::apply_deletions`].
+	pub async fn store_delete(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, deleted_object_hash: &IdType,
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_DELETE,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		let record = delete_object::ActiveModel {
+			object_id: Set(object_id),
+			deleted_object_hash: Set(deleted_object_hash.clone()),
+		};
+		delete_object::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	pub async fn store_poll(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, poll: &PollObject,
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_POLL,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		let record = poll_object::ActiveModel {
+			object_id: Set(object_id),
+			question: Set(poll.question.to_string()),
+			multiple_choice: Set(poll.multiple_choice),
+			closes: Set(poll.closes as _),
+		};
+		poll_object::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		for (i, option) in poll.options.iter().enumerate() {
+			let record = poll_option::ActiveModel {
+				id: NotSet,
+				object_id: Set(object_id),
+				sequence: Set(i as _),
+				text: Set(option.to_string()),
+			};
+			poll_option::Entity::insert(record)
+				.exec(self.inner())
+				.await?;
+		}
+		Ok(())
+	}
+
+	pub async fn store_follower_list(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, list: &FollowerListObject,
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_FOLLOWER_LIST,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		for (i, follower) in list.followers.iter().enumerate() {
+			let record = follower_list_entry::ActiveModel {
+				id: NotSet,
+				object_id: Set(object_id),
+				sequence: Set(i as _),
+				actor_address: Set(follower.actor_address.clone()),
+				node_address: Set(follower.node_address.clone()),
+			};
+			follower_list_entry::Entity::insert(record)
+				.exec(self.inner())
+				.await?;
+		}
+		Ok(())
+	}
+
+	pub async fn store_vote(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, vote: &VoteObject,
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_VOTE,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		let record = vote_object::ActiveModel {
+			object_id: Set(object_id),
+			actor_address: Set(vote.actor_address.clone()),
+			object_hash: Set(vote.object_hash.clone()),
+			choices: Set(vote.choices.to_vec()),
+		};
+		vote_object::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	pub async fn store_reaction(
+		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
+		signature: &ActorSignatureV1, reaction: &ReactionObject,
+	) -> Result<()> {
+		let object_id = self
+			.store_object(
+				actor_id,
+				created,
+				hash,
+				previous_hash,
+				OBJECT_TYPE_REACTION,
+				signature,
+				true,
+				false,
+			)
+			.await?;
+
+		let record = reaction_object::ActiveModel {
+			object_id: Set(object_id),
+			actor_address: Set(reaction.actor_address.clone()),
+			object_hash: Set(reaction.object_hash.clone()),
+			emoji: Set(reaction.emoji.to_string()),
+			notified: Set(false),
+		};
+		reaction_object::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
 		Ok(())
 	}
 
@@ -2677,7 +4235,7 @@ impl Transaction {
 		&self, actor_id: i64, created: u64, hash: &IdType, previous_hash: &IdType,
 		signature: &ActorSignatureV1, verified_from_start: bool, name: &str,
 		avatar_hash: Option<IdType>, wallpaper_hash: Option<IdType>,
-		description_hash: Option<IdType>,
+		description_hash: Option<IdType>, followers_only: bool,
 	) -> Result<()> {
 		let object_id = self
 			.store_object(
@@ -2698,22 +4256,12 @@ impl Transaction {
 			avatar_file_hash: Set(avatar_hash),
 			wallpaper_file_hash: Set(wallpaper_hash),
 			description_file_hash: Set(description_hash),
+			followers_only: Set(followers_only),
 		};
 		profile_object::Entity::insert(record)
 			.exec(self.inner())
 			.await?;
-		Ok(())
-	}
-
-	async fn store_post_tags(&self, object_id: i64, tags: &[String]) -> Result<()> {
-		for tag in tags {
-			let record = post_tag::ActiveModel {
-				id: NotSet,
-				object_id: Set(object_id),
-				tag: Set(tag.clone()),
-			};
-			post_tag::Entity::insert(record).exec(self.inner()).await?;
-		}
+		search::queue_object(self, object_id).await?;
 		Ok(())
 	}
 
@@ -2740,6 +4288,25 @@ mod tests {
 
 	use super::*;
 	use crate::test;
+
+	#[tokio::test]
+	async fn test_database_options() {
+		let db = test::load_database("options").await;
+
+		// Both kinds of connections write ahead and wait for each other
+		let c = db.connect_old().unwrap();
+		let mode: String = c.query_row("PRAGMA journal_mode", [], |r| r.get(0)).unwrap();
+		assert_eq!(mode, "wal");
+		let result = db
+			.inner()
+			.query_one(Statement::from_string(db.backend(), "PRAGMA busy_timeout"))
+			.await
+			.unwrap()
+			.unwrap();
+		let timeout: i64 = result.try_get_by_index(0).unwrap();
+		assert_eq!(timeout, 5000);
+	}
+
 	#[tokio::test]
 	async fn test_file_data() {
 		let db = test::load_database("db").await;
@@ -2774,4 +4341,53 @@ mod tests {
 		);
 		assert_eq!(fetched_file2.data, file_data2.data, "corrupted file data");
 	}
+
+	#[tokio::test]
+	async fn test_perform() {
+		let db = test::load_database("perform").await;
+
+		let mode: String = db
+			.perform(|c| Ok(c.query_row("PRAGMA journal_mode", [], |r| r.get(0))?))
+			.await
+			.unwrap();
+		assert_eq!(mode, "wal");
+		let identities = db.perform(|c| c.fetch_my_identities()).await.unwrap();
+		assert_eq!(identities.len(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_sync_depth() {
+		let db = test::load_database("db").await;
+		let mut rng = test::initialize_rng();
+		let mut follow = |sync_depth: SyncDepth| {
+			let address = ActorAddress::V1(IdType::random(&mut rng));
+			let actor_info = ActorInfo::V1(ActorInfoV1 {
+				flags: 0,
+				public_key: ActorPrivateKeyV1::generate_with_rng(&mut rng).public(),
+				first_object: IdType::random(&mut rng),
+				actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+			});
+			let mut c = db.connect_old().unwrap();
+			c.follow(&address, &actor_info, &sync_depth).unwrap();
+			address
+		};
+
+		let unlimited = follow(SyncDepth::default());
+		let limited = follow(SyncDepth {
+			object_limit: Some(50),
+			max_age: Some(30),
+		});
+
+		let sync_depth = db.load_sync_depth(&unlimited).await.unwrap().unwrap();
+		assert!(!sync_depth.is_limited());
+		assert_eq!(sync_depth.min_created(), 0);
+		let sync_depth = db.load_sync_depth(&limited).await.unwrap().unwrap();
+		assert_eq!(sync_depth.object_limit, Some(50));
+		assert_eq!(sync_depth.max_age, Some(30));
+		let min_created = current_timestamp() - 30 * 24 * 3600 * 1000;
+		assert!(sync_depth.min_created().abs_diff(min_created) < 1000);
+
+		let not_followed = ActorAddress::V1(IdType::random(&mut rng));
+		assert!(db.load_sync_depth(&not_followed).await.unwrap().is_none());
+	}
 }
@@ -0,0 +1,127 @@
+//! Keeps track of which of our identities is the active one, which is the one
+//! that the user interface publishes with. Each local user has its own active
+//! identity.
+
+use sea_orm::{prelude::*, sea_query::Expr, QueryOrder};
+
+use super::{local_user, Database, PersistenceHandle, Result};
+use crate::{core::ActorAddress, entity::*, identity::Signer};
+
+
+impl Database {
+	async fn find_active_identity(&self) -> Result<Option<(identity::Model, actor::Model)>> {
+		// If none is marked as active, the oldest identity is used
+		let result = identity::Entity::find()
+			.find_also_related(actor::Entity)
+			.filter(local_user::identity_condition())
+			.order_by_desc(identity::Column::IsActive)
+			.order_by_asc(identity::Column::ActorId)
+			.one(self.inner())
+			.await?;
+		Ok(result.and_then(|(identity, actor)| actor.map(|a| (identity, a))))
+	}
+
+	/// Loads the label and address of the active identity, if we have any
+	/// identity.
+	pub async fn load_active_identity(&self) -> Result<Option<(String, ActorAddress)>> {
+		Ok(self
+			.find_active_identity()
+			.await?
+			.map(|(identity, actor)| (identity.label, actor.address)))
+	}
+
+	/// Loads the ID of the actor of the active identity, if we have any
+	/// identity.
+	pub async fn load_active_actor_id(&self) -> Result<Option<i64>> {
+		Ok(self.find_active_identity().await?.map(|(_, actor)| actor.id))
+	}
+
+	/// Loads the address of the active identity and the signer of its private
+	/// key, if we have any identity.
+	pub async fn load_active_identity_key(
+		&self,
+	) -> Result<Option<(ActorAddress, Box<dyn Signer>)>> {
+		match self.find_active_identity().await? {
+			None => Ok(None),
+			Some((identity, actor)) => {
+				let signer = self.keyring().load_signer(&identity.private_key)?;
+				Ok(Some((actor.address, signer)))
+			}
+		}
+	}
+
+	/// Makes the identity with the given label the active one. Returns its
+	/// address, or None if we don't have an identity with that label.
+	pub async fn set_active_identity(&self, label: &str) -> Result<Option<ActorAddress>> {
+		let tx = self.transaction().await?;
+		let result = identity::Entity::find_by_id(label.to_string())
+			.find_also_related(actor::Entity)
+			.filter(local_user::identity_condition())
+			.one(tx.inner())
+			.await?;
+		let address = match result {
+			Some((_, Some(actor))) => actor.address,
+			_ => return Ok(None),
+		};
+
+		identity::Entity::update_many()
+			.col_expr(
+				identity::Column::IsActive,
+				Expr::col(identity::Column::Label).eq(label),
+			)
+			.filter(local_user::identity_condition())
+			.exec(tx.inner())
+			.await?;
+		tx.commit().await?;
+		Ok(Some(address))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, identity::ActorPrivateKeyV1, test};
+
+	#[tokio::test]
+	async fn test_active_identity() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("active_identity").await;
+		assert!(db.load_active_identity().await.unwrap().is_none());
+
+		let mut addresses = Vec::new();
+		let tx = db.transaction().await.unwrap();
+		for label in ["first", "second"] {
+			let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+			let address = ActorAddress::V1(IdType::random(&mut rng));
+			tx.create_identity(
+				label,
+				&address,
+				&private_key.public(),
+				&private_key,
+				false,
+				&IdType::random(&mut rng),
+				None,
+			)
+			.await
+			.unwrap();
+			addresses.push(address);
+		}
+		tx.commit().await.unwrap();
+
+		// The first identity becomes the active one by itself
+		let (label, address) = db.load_active_identity().await.unwrap().unwrap();
+		assert_eq!(label, "first");
+		assert_eq!(address, addresses[0]);
+
+		assert_eq!(
+			db.set_active_identity("second").await.unwrap(),
+			Some(addresses[1].clone())
+		);
+		let (address, _) = db.load_active_identity_key().await.unwrap().unwrap();
+		assert_eq!(address, addresses[1]);
+		assert!(db.set_active_identity("third").await.unwrap().is_none());
+		let (label, _) = db.load_active_identity().await.unwrap().unwrap();
+		assert_eq!(label, "second");
+	}
+}
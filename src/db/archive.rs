@@ -0,0 +1,291 @@
+//! The archive tier for block data.
+//!
+//! Blocks that haven't been accessed for a while are moved out of the block
+//! store into segment files on disk, where they are compressed when possible.
+//! The block records stay in the database, so that they are still known to
+//! exist. Loading an archived block costs an extra file read, but keeps the
+//! block store compact on long-lived nodes. Blocks that are still kept in the
+//! database itself, from before the block store existed, are archived as well.
+//!
+//! The segments are recorded by their file name only, so that the archive
+//! directory can be moved elsewhere, as long as the configuration is updated
+//! along with it.
+
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{prelude::*, SeekFrom},
+	path::Path,
+};
+
+use chrono::Utc;
+use sea_orm::{prelude::*, NotSet, QuerySelect, Set};
+
+use super::{Database, Error, PersistenceHandle, Result};
+use crate::{
+	compression::{compress, decompress},
+	core::CompressionType,
+	entity::*,
+	util,
+};
+
+
+/// The last access time of a block is only updated if it is older than this
+/// many milliseconds, so that reading blocks doesn't cause a write every time.
+pub(crate) const LAST_ACCESS_RESOLUTION: i64 = 24 * 3600 * 1000;
+/// The number of blocks that are archived per transaction.
+const ARCHIVE_BATCH_SIZE: u64 = 10;
+/// The amount of bytes to test the compression ratio on.
+const COMPRESSION_SAMPLE_SIZE: usize = 0x1000;
+/// A new segment file is started once the current one exceeds this size.
+const SEGMENT_SIZE_LIMIT: u64 = 0x4000000; // 64 MiB
+
+
+struct Segment {
+	index: u32,
+	file: File,
+	len: u64,
+}
+
+
+impl Database {
+	/// Moves the data of all blocks that have not been accessed since the given
+	/// timestamp into the archive segments in the archive directory.
+	/// Returns the number of blocks that have been archived.
+	///
+	/// The file I/O and compression are done in place, so that they don't hold
+	/// up the other tasks on the runtime.
+	pub async fn archive_blocks(&self, accessed_before: i64) -> Result<usize> {
+		let dir = self.block_store().archive_dir();
+		let mut segment = util::block_in_place(|| {
+			fs::create_dir_all(dir)?;
+			Segment::open_current(dir)
+		})?;
+
+		let mut archived = 0;
+		loop {
+			let records = block::Entity::find()
+				.filter(block::Column::LastAccess.lt(accessed_before))
+				.filter(block::Column::Size.gt(0))
+				.filter(Expr::cust(
+					"\"id\" NOT IN (SELECT \"block_id\" FROM \"archived_block\")",
+				))
+				.limit(ARCHIVE_BATCH_SIZE)
+				.all(self.inner())
+				.await?;
+			if records.len() == 0 {
+				break;
+			}
+
+			let tx = self.transaction().await?;
+			let mut moved = Vec::with_capacity(records.len());
+			for record in records {
+				let (compression_type, length, offset) = util::block_in_place(|| {
+					let block_data = if record.data.len() > 0 {
+						record.data.clone()
+					} else if let Some(d) = self.block_store().load(&record.hash)? {
+						d
+					} else {
+						Err(Error::BlockDataCorrupt(record.id))?
+					};
+					let (compression_type, data) = compress_block(&block_data);
+					let offset = segment.append(&data)?;
+					Result::Ok((compression_type, data.len(), offset))
+				})?;
+				let model = archived_block::ActiveModel {
+					id: NotSet,
+					block_id: Set(record.id),
+					segment: Set(Segment::file_name(segment.index)),
+					offset: Set(offset as _),
+					length: Set(length as _),
+					compression_type: Set(compression_type as _),
+				};
+				archived_block::Entity::insert(model)
+					.exec(tx.inner())
+					.await?;
+
+				moved.push(record.hash.clone());
+				let mut model: block::ActiveModel = record.into();
+				model.data = Set(Vec::new());
+				model.update(tx.inner()).await?;
+				archived += 1;
+
+				if segment.len >= SEGMENT_SIZE_LIMIT {
+					segment = util::block_in_place(|| {
+						segment.file.sync_data()?;
+						Segment::open(dir, segment.index + 1)
+					})?;
+				}
+			}
+			// Only commit after the data has actually been written to disk.
+			util::block_in_place(|| segment.file.sync_data())?;
+			tx.commit().await?;
+			util::block_in_place(|| {
+				moved
+					.iter()
+					.try_for_each(|hash| self.block_store().remove(hash))
+			})?;
+		}
+		Ok(archived)
+	}
+}
+
+impl Segment {
+	fn file_name(index: u32) -> String { format!("segment-{:06}.bin", index) }
+
+	fn open(dir: &Path, index: u32) -> Result<Self> {
+		let path = dir.join(Self::file_name(index));
+		let file = OpenOptions::new().create(true).append(true).open(&path)?;
+		let len = file.metadata()?.len();
+		Ok(Self { index, file, len })
+	}
+
+	/// Opens the segment with the highest index, or a new one if that one is
+	/// full already.
+	fn open_current(dir: &Path) -> Result<Self> {
+		let mut index = 0;
+		for entry in fs::read_dir(dir)? {
+			let entry = entry?;
+			if let Some(i) = entry.file_name().to_str().and_then(parse_segment_index) {
+				if i > index {
+					index = i;
+				}
+			}
+		}
+
+		let segment = Self::open(dir, index)?;
+		if segment.len >= SEGMENT_SIZE_LIMIT {
+			Self::open(dir, index + 1)
+		} else {
+			Ok(segment)
+		}
+	}
+
+	/// Appends the data to the segment, and returns the offset it was written
+	/// at.
+	fn append(&mut self, data: &[u8]) -> Result<u64> {
+		let offset = self.len;
+		self.file.write_all(data)?;
+		self.len += data.len() as u64;
+		Ok(offset)
+	}
+}
+
+
+/// Compresses the block data, but only if it looks like it is worth it. Block
+/// data is encrypted, so usually it isn't.
+fn compress_block(data: &[u8]) -> (CompressionType, Vec<u8>) {
+	let sample = &data[..data.len().min(COMPRESSION_SAMPLE_SIZE)];
+	if compress(CompressionType::Brotli, sample).len() <= (sample.len() as f32 * 0.95) as usize {
+		let compressed = compress(CompressionType::Brotli, data);
+		if compressed.len() < data.len() {
+			return (CompressionType::Brotli, compressed);
+		}
+	}
+	(CompressionType::None, data.to_vec())
+}
+
+fn parse_segment_index(file_name: &str) -> Option<u32> {
+	file_name
+		.strip_prefix("segment-")?
+		.strip_suffix(".bin")?
+		.parse()
+		.ok()
+}
+
+/// Reads the data of an archived block from its segment file in the archive
+/// directory. Blocks that have been archived by older versions have the whole
+/// path of the segment recorded, which is used as it is.
+pub(super) fn read_block(
+	dir: &Path, segment: &str, offset: i64, length: i64, compression_type: u8,
+) -> Result<Vec<u8>> {
+	let mut file = File::open(dir.join(segment))?;
+	file.seek(SeekFrom::Start(offset as _))?;
+	let mut buffer = vec![0u8; length as usize];
+	file.read_exact(&mut buffer)?;
+
+	match CompressionType::from_u8(compression_type) {
+		Some(CompressionType::None) => Ok(buffer),
+		Some(t) => Ok(decompress(t, &buffer).map_err(|e| Error::from(e))?),
+		None => Err(Error::InvalidCompressionType(compression_type))?,
+	}
+}
+
+/// Returns whether the last access time of a block should be updated.
+pub(super) fn needs_touch(last_access: i64) -> bool {
+	last_access < Utc::now().timestamp_millis() - LAST_ACCESS_RESOLUTION
+}
+
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+	use tempfile::TempDir;
+
+	use super::*;
+	use crate::{core::FileData, db::DatabaseOptions, migration::Migrations, test};
+
+	async fn load_database(dir: &Path, archive_dir: &Path) -> Database {
+		let options = DatabaseOptions {
+			archive_dir: Some(archive_dir.to_path_buf()),
+			..DatabaseOptions::default()
+		};
+		let db = Database::load_with_key(dir.join("db.sqlite"), None, options)
+			.await
+			.unwrap();
+		Migrations::load().run(&db).await.unwrap();
+		db
+	}
+
+	#[tokio::test]
+	async fn test_archive_blocks() {
+		let dir = TempDir::new().unwrap();
+		let archive_dir = dir.path().join("archive");
+		let db = load_database(dir.path(), &archive_dir).await;
+		let mut rng = test::initialize_rng();
+
+		let mut file_data = FileData {
+			mime_type: "image/png".into(),
+			data: vec![0u8; 10000],
+		};
+		rng.fill_bytes(&mut file_data.data);
+		let tx = db.transaction().await.unwrap();
+		let (_, file_hash, block_hashes) = tx.create_file(&file_data).await.unwrap();
+		tx.commit().await.unwrap();
+
+		// Nothing has been accessed before the start of time
+		let archived = db.archive_blocks(0).await.unwrap();
+		assert_eq!(archived, 0);
+
+		let archived = db.archive_blocks(i64::MAX).await.unwrap();
+		assert_eq!(archived, block_hashes.len());
+		let record = block::Entity::find()
+			.filter(block::Column::Hash.eq(&block_hashes[0]))
+			.one(db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(record.data.len(), 0, "block data not moved out of database");
+		assert!(
+			!db.block_store().contains(&block_hashes[0]),
+			"block data not moved out of block store"
+		);
+		let archived_record = archived_block::Entity::find()
+			.filter(archived_block::Column::BlockId.eq(record.id))
+			.one(db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(archived_record.segment, Segment::file_name(0));
+
+		let fetched = db.load_file_data(&file_hash).await.unwrap().unwrap();
+		assert_eq!(fetched.data, file_data.data, "corrupted archived file data");
+
+		// The archive can still be found after it has been moved
+		drop(db);
+		let moved_dir = dir.path().join("moved");
+		fs::rename(&archive_dir, &moved_dir).unwrap();
+		let db = load_database(dir.path(), &moved_dir).await;
+		let fetched = db.load_file_data(&file_hash).await.unwrap().unwrap();
+		assert_eq!(fetched.data, file_data.data, "archive not found after moving it");
+	}
+}
@@ -0,0 +1,61 @@
+//! Counts what is waiting in the queues that are worked through in the
+//! background, to see whether the node keeps up with them.
+
+use sea_orm::prelude::*;
+use serde::Serialize;
+
+use super::{Database, PersistenceHandle, Result};
+use crate::entity::{activity_pub_send_queue, delivery_queue, direct_message};
+
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QueueBacklogs {
+	/// The object announcements for followers that were offline.
+	pub object_deliveries: u64,
+	/// The direct messages that haven't reached their recipient yet.
+	pub direct_messages: u64,
+	/// The activities that still have to be sent to other ActivityPub servers.
+	pub activity_pub_activities: u64,
+}
+
+
+impl Database {
+	pub async fn load_queue_backlogs(&self) -> Result<QueueBacklogs> {
+		Ok(QueueBacklogs {
+			object_deliveries: delivery_queue::Entity::find().count(self.inner()).await?,
+			direct_messages: direct_message::Entity::find()
+				.filter(direct_message::Column::Delivered.eq(false))
+				.count(self.inner())
+				.await?,
+			activity_pub_activities: activity_pub_send_queue::Entity::find()
+				.count(self.inner())
+				.await?,
+		})
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[tokio::test]
+	async fn test_queue_backlogs() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("backlog").await;
+		let backlogs = db.load_queue_backlogs().await.unwrap();
+		assert_eq!(backlogs.object_deliveries, 0);
+		assert_eq!(backlogs.direct_messages, 0);
+		assert_eq!(backlogs.activity_pub_activities, 0);
+
+		let object_hash = IdType::random(&mut rng);
+		db.queue_delivery(1, &object_hash, "follower", vec![1], 100)
+			.await
+			.unwrap();
+		db.queue_delivery(1, &object_hash, "other follower", vec![2], 100)
+			.await
+			.unwrap();
+		assert_eq!(db.load_queue_backlogs().await.unwrap().object_deliveries, 2);
+	}
+}
@@ -0,0 +1,228 @@
+//! The actors that our identities have muted or blocked.
+//!
+//! The objects of muted actors are left out of the home feed while the identity
+//! is the active one. Blocked actors are muted as well, but are also kept out
+//! of the actor network of the identity: the nodes that are known to belong to
+//! them are refused, and their ActivityPub activities are dropped at the inbox.
+//! Which nodes belong to an actor is only known from the follow requests that
+//! it has sent, and from the follower lists that it is on.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use sea_orm::{prelude::*, sea_query::SimpleExpr, NotSet, QueryOrder, Set, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::current_timestamp,
+	core::{ActorAddress, Address, NodeAddress},
+	entity::*,
+};
+
+
+/// The condition on `consolidated_object` that leaves out the objects of the
+/// actors that have been muted or blocked by the actor given as the parameter,
+/// which is given twice.
+const UNMUTED_OBJECTS: &str = r#"
+	NOT (
+		(type = 0 AND object_id IN (
+			SELECT o.id
+			FROM object AS o
+			INNER JOIN actor AS a ON a.id = o.actor_id
+			INNER JOIN actor_block AS b ON b.actor_address = a.address
+			WHERE b.actor_id = ?
+		)) OR (type = 1 AND object_id IN (
+			SELECT apo.id
+			FROM activity_pub_object AS apo
+			INNER JOIN activity_pub_actor AS apa ON apa.id = apo.actor_id
+			INNER JOIN actor_block AS b
+				ON b.activity_pub_actor_url = 'https://' || apa.host || apa.path
+			WHERE b.actor_id = ?
+		))
+	)
+"#;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockTarget {
+	Actor(ActorAddress),
+	/// The URL of an actor on the fediverse.
+	ActivityPub(String),
+}
+
+
+impl BlockTarget {
+	pub fn from_model(model: &actor_block::Model) -> Option<Self> {
+		if let Some(address) = &model.actor_address {
+			Some(Self::Actor(address.clone()))
+		} else {
+			model
+				.activity_pub_actor_url
+				.as_ref()
+				.map(|url| Self::ActivityPub(url.clone()))
+		}
+	}
+
+	fn condition(&self) -> SimpleExpr {
+		match self {
+			Self::Actor(address) => actor_block::Column::ActorAddress.eq(address),
+			Self::ActivityPub(url) => actor_block::Column::ActivityPubActorUrl.eq(url),
+		}
+	}
+}
+
+impl fmt::Display for BlockTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Actor(address) => address.fmt(f),
+			Self::ActivityPub(url) => url.fmt(f),
+		}
+	}
+}
+
+impl FromStr for BlockTarget {
+	type Err = String;
+
+	/// Parses either an actor address, or the URL of an ActivityPub actor.
+	fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+		if string.starts_with("https://") {
+			return Ok(Self::ActivityPub(string.to_string()));
+		}
+		match Address::from_str(string) {
+			Ok(Address::Actor(address)) => Ok(Self::Actor(address)),
+			Ok(_) => Err(format!("not an actor address: {}", string)),
+			Err(e) => Err(format!("invalid actor address or URL \"{}\": {}", string, e)),
+		}
+	}
+}
+
+
+impl Database {
+	/// Mutes or blocks the target for the actor. An actor that has been muted
+	/// or blocked already is changed to be one or the other.
+	pub async fn block_actor(
+		&self, actor_id: i64, target: &BlockTarget, blocked: bool,
+	) -> Result<()> {
+		let existing = actor_block::Entity::find()
+			.filter(actor_block::Column::ActorId.eq(actor_id))
+			.filter(target.condition())
+			.one(self.inner())
+			.await?;
+		if let Some(record) = existing {
+			let mut record: actor_block::ActiveModel = record.into();
+			record.blocked = Set(blocked);
+			record.update(self.inner()).await?;
+			return Ok(());
+		}
+
+		let (actor_address, activity_pub_actor_url) = match target {
+			BlockTarget::Actor(address) => (Some(address.clone()), None),
+			BlockTarget::ActivityPub(url) => (None, Some(url.clone())),
+		};
+		let record = actor_block::ActiveModel {
+			id: NotSet,
+			actor_id: Set(actor_id),
+			actor_address: Set(actor_address),
+			activity_pub_actor_url: Set(activity_pub_actor_url),
+			blocked: Set(blocked),
+			created: Set(current_timestamp() as _),
+		};
+		actor_block::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Whether the actor has blocked the ActivityPub actor with the given URL.
+	pub async fn is_activity_pub_actor_blocked(&self, actor_id: i64, url: &str) -> Result<bool> {
+		let target = BlockTarget::ActivityPub(url.to_string());
+		self.is_blocked(actor_id, &target).await
+	}
+
+	/// Whether the actor has blocked the other actor.
+	pub async fn is_actor_blocked(&self, actor_id: i64, address: &ActorAddress) -> Result<bool> {
+		self.is_blocked(actor_id, &BlockTarget::Actor(address.clone()))
+			.await
+	}
+
+	async fn is_blocked(&self, actor_id: i64, target: &BlockTarget) -> Result<bool> {
+		let result = actor_block::Entity::find()
+			.filter(actor_block::Column::ActorId.eq(actor_id))
+			.filter(actor_block::Column::Blocked.eq(true))
+			.filter(target.condition())
+			.one(self.inner())
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Loads the actors that the actor has muted or blocked, the latest ones
+	/// first.
+	pub async fn load_actor_blocks(&self, actor_id: i64) -> Result<Vec<actor_block::Model>> {
+		Ok(actor_block::Entity::find()
+			.filter(actor_block::Column::ActorId.eq(actor_id))
+			.order_by_desc(actor_block::Column::Id)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Loads the addresses of the nodes that are known to belong to the actors
+	/// that the actor has blocked.
+	pub async fn load_blocked_nodes(&self, actor_id: i64) -> Result<HashSet<NodeAddress>> {
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT fr.node_address
+				FROM follow_request AS fr
+				INNER JOIN actor_block AS b ON b.actor_address = fr.requester_address
+				WHERE b.actor_id = ? AND b.blocked
+				UNION
+				SELECT fle.node_address
+				FROM follower_list_entry AS fle
+				INNER JOIN actor_block AS b ON b.actor_address = fle.actor_address
+				WHERE b.actor_id = ? AND b.blocked
+			"#,
+				[actor_id.into(), actor_id.into()],
+			))
+			.await?;
+		let mut nodes = HashSet::with_capacity(results.len());
+		for result in results {
+			nodes.insert(result.try_get_by_index(0)?);
+		}
+		Ok(nodes)
+	}
+
+	/// Unmutes or unblocks the target for the actor. Returns false if it
+	/// wasn't muted or blocked.
+	pub async fn unblock_actor(&self, actor_id: i64, target: &BlockTarget) -> Result<bool> {
+		let result = actor_block::Entity::delete_many()
+			.filter(actor_block::Column::ActorId.eq(actor_id))
+			.filter(target.condition())
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected > 0)
+	}
+}
+
+/// The condition on `consolidated_object` that leaves out the objects of the
+/// actors that the actor has muted or blocked.
+pub fn unmuted_condition(actor_id: i64) -> SimpleExpr {
+	Expr::cust_with_values(UNMUTED_OBJECTS, [actor_id, actor_id])
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_block_target() {
+		let url = "https://example.com/users/someone";
+		assert_eq!(
+			BlockTarget::from_str(url),
+			Ok(BlockTarget::ActivityPub(url.to_string()))
+		);
+		assert!(BlockTarget::from_str("http://example.com/users/someone").is_err());
+		assert!(BlockTarget::from_str("nonsense").is_err());
+	}
+}
@@ -0,0 +1,165 @@
+//! Keeps the data of blocks in files on disk, named after their hash.
+//!
+//! Blocks make up most of the size of the database, and keeping them in it
+//! makes backing it up and vacuuming it very slow. Only their metadata stays in
+//! the `block` table, with an empty data column. Because the files are content
+//! addressed, a block that is stored twice simply ends up in the same file.
+//! The files are spread over subdirectories named after the first byte of the
+//! hash, so that no single directory grows too large. Blocks that are moved
+//! into the archive are kept in the segment files of the archive directory
+//! instead, see the `archive` module.
+//!
+//! The data of a block is only removed after its record has been removed, and
+//! only if no record of the block has been stored again since. That check is
+//! done while holding the write lock of the database, and a block is always
+//! recorded before its data is written, so a block that is stored at the same
+//! time as it is removed either keeps its file or has it written anew.
+
+use std::{
+	fmt::Write as _,
+	fs::{self, File},
+	io::{self, Write},
+	path::{Path, PathBuf},
+};
+
+use rusqlite::TransactionBehavior;
+
+use super::{Database, Result};
+use crate::common::IdType;
+
+
+pub struct BlockStore {
+	dir: PathBuf,
+	archive_dir: PathBuf,
+}
+
+
+impl BlockStore {
+	pub fn new(dir: PathBuf, archive_dir: PathBuf) -> Self { Self { dir, archive_dir } }
+
+	pub fn dir(&self) -> &Path { &self.dir }
+
+	/// The directory with the segment files of the archive.
+	pub fn archive_dir(&self) -> &Path { &self.archive_dir }
+
+	pub fn contains(&self, hash: &IdType) -> bool { self.path(hash).exists() }
+
+	/// Loads the data of the block, or returns None if it isn't in the store.
+	pub fn load(&self, hash: &IdType) -> Result<Option<Vec<u8>>> {
+		match fs::read(self.path(hash)) {
+			Ok(data) => Ok(Some(data)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e)?,
+		}
+	}
+
+	/// The file name is in hexadecimal rather than base58, so that it stays
+	/// unique on file systems that ignore case.
+	fn path(&self, hash: &IdType) -> PathBuf {
+		let mut name = String::with_capacity(64);
+		for byte in hash.as_bytes() {
+			write!(name, "{:02x}", byte).unwrap();
+		}
+		self.dir.join(&name[..2]).join(name)
+	}
+
+	/// Removes the data of the block, if it is in the store.
+	pub fn remove(&self, hash: &IdType) -> Result<()> {
+		match fs::remove_file(self.path(hash)) {
+			Ok(()) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e)?,
+		}
+	}
+
+	/// Writes the data of the block to disk, unless it is there already. The
+	/// data is written to a temporary file first, so that a block file is never
+	/// left half-written.
+	pub fn store(&self, hash: &IdType, data: &[u8]) -> Result<()> {
+		let path = self.path(hash);
+		if path.exists() {
+			return Ok(());
+		}
+		fs::create_dir_all(path.parent().unwrap())?;
+
+		let temp_path = path.with_extension("tmp");
+		let mut file = File::create(&temp_path)?;
+		file.write_all(data)?;
+		file.sync_data()?;
+		drop(file);
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+}
+
+impl Database {
+	/// Removes the data of the blocks of which the records have been removed,
+	/// except of the ones that have been stored again in the meantime.
+	pub(super) async fn remove_block_data(&self, hashes: Vec<IdType>) -> Result<()> {
+		if hashes.len() == 0 {
+			return Ok(());
+		}
+		let block_store = self.block_store.clone();
+		self.perform(move |mut c| {
+			// Waits for any transaction that is storing a block to finish
+			let tx = c.transaction_with_behavior(TransactionBehavior::Immediate)?;
+			{
+				let mut stat = tx.prepare("SELECT 1 FROM block WHERE hash = ?")?;
+				for hash in &hashes {
+					if !stat.exists([hash])? {
+						block_store.remove(hash)?;
+					}
+				}
+			}
+			tx.commit()?;
+			Ok(())
+		})
+		.await
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use tempfile::TempDir;
+
+	use super::*;
+	use crate::{db::PersistenceHandle, test};
+
+	#[test]
+	fn test_block_store() {
+		let dir = TempDir::new().unwrap();
+		let store = BlockStore::new(dir.path().join("blocks"), dir.path().join("archive"));
+		let mut rng = test::initialize_rng();
+		let hash = IdType::random(&mut rng);
+
+		assert!(!store.contains(&hash));
+		assert_eq!(store.load(&hash).unwrap(), None);
+		store.store(&hash, b"block data").unwrap();
+		assert!(store.contains(&hash));
+		// Storing the same block again leaves it as it is
+		store.store(&hash, b"block data").unwrap();
+		assert_eq!(store.load(&hash).unwrap(), Some(b"block data".to_vec()));
+
+		store.remove(&hash).unwrap();
+		assert_eq!(store.load(&hash).unwrap(), None);
+		store.remove(&hash).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_remove_block_data() {
+		let db = test::load_database("block_store").await;
+		let mut rng = test::initialize_rng();
+		let (removed, stored_again) = (IdType::random(&mut rng), IdType::random(&mut rng));
+
+		// One of the blocks has been stored again after its record was removed
+		db.block_store().store(&removed, b"removed").unwrap();
+		let mut c = db.connect_old().unwrap();
+		c.store_block(0, &stored_again, b"stored again").unwrap();
+		db.remove_block_data(vec![removed.clone(), stored_again.clone()])
+			.await
+			.unwrap();
+		assert!(!db.block_store().contains(&removed));
+		assert!(db.block_store().contains(&stored_again));
+	}
+}
@@ -0,0 +1,105 @@
+//! The objects that the user has bookmarked.
+//!
+//! Bookmarks refer to objects by their actor and hash rather than by their
+//! row, so that objects that haven't been downloaded yet can be bookmarked as
+//! well. Bookmarked objects are exempt from the retention policies, from the
+//! purging of unfollowed actors and from the eviction of blocks. Every local
+//! user has their own bookmarks.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set};
+
+use super::{local_user, Database, PersistenceHandle, Result};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::ActorAddress,
+	entity::bookmark,
+};
+
+
+impl Database {
+	/// Bookmarks the object. Returns false if it was bookmarked already.
+	pub async fn add_bookmark(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> Result<bool> {
+		if self.is_bookmarked(actor_address, object_hash).await? {
+			return Ok(false);
+		}
+		let model = bookmark::ActiveModel {
+			id: NotSet,
+			actor_address: Set(actor_address.clone()),
+			object_hash: Set(object_hash.clone()),
+			created: Set(current_timestamp() as i64),
+			user_id: Set(local_user::current_user()),
+		};
+		bookmark::Entity::insert(model)
+			.exec_without_returning(self.inner())
+			.await?;
+		Ok(true)
+	}
+
+	pub async fn is_bookmarked(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> Result<bool> {
+		let count = bookmark::Entity::find()
+			.filter(bookmark::Column::ActorAddress.eq(actor_address))
+			.filter(bookmark::Column::ObjectHash.eq(object_hash))
+			.filter(local_user::user_condition(bookmark::Column::UserId))
+			.count(self.inner())
+			.await?;
+		Ok(count > 0)
+	}
+
+	/// Loads the bookmarks, the latest ones first.
+	pub async fn load_bookmarks(&self, limit: u64, offset: u64) -> Result<Vec<bookmark::Model>> {
+		Ok(bookmark::Entity::find()
+			.filter(local_user::user_condition(bookmark::Column::UserId))
+			.order_by_desc(bookmark::Column::Id)
+			.limit(limit)
+			.offset(offset)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Returns false if the object wasn't bookmarked.
+	pub async fn remove_bookmark(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> Result<bool> {
+		let result = bookmark::Entity::delete_many()
+			.filter(bookmark::Column::ActorAddress.eq(actor_address))
+			.filter(bookmark::Column::ObjectHash.eq(object_hash))
+			.filter(local_user::user_condition(bookmark::Column::UserId))
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected > 0)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_bookmarks() {
+		let db = test::load_database("bookmark").await;
+		let mut rng = test::initialize_rng();
+
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let hash1 = IdType::random(&mut rng);
+		let hash2 = IdType::random(&mut rng);
+		assert!(db.add_bookmark(&address, &hash1).await.unwrap());
+		assert!(!db.add_bookmark(&address, &hash1).await.unwrap());
+		assert!(db.add_bookmark(&address, &hash2).await.unwrap());
+		assert!(db.is_bookmarked(&address, &hash1).await.unwrap());
+
+		let bookmarks = db.load_bookmarks(10, 0).await.unwrap();
+		assert_eq!(bookmarks.len(), 2);
+		assert_eq!(bookmarks[0].object_hash, hash2);
+		assert_eq!(db.load_bookmarks(10, 1).await.unwrap().len(), 1);
+
+		assert!(db.remove_bookmark(&address, &hash1).await.unwrap());
+		assert!(!db.remove_bookmark(&address, &hash1).await.unwrap());
+		assert!(!db.is_bookmarked(&address, &hash1).await.unwrap());
+	}
+}
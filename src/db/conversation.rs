@@ -0,0 +1,316 @@
+//! Keeps the direct messages that our identities have sent and received,
+//! grouped per actor that they have been exchanged with.
+//!
+//! Outgoing messages are stored before they are sent, and are only sealed
+//! when they are first sent. Until the recipient has accepted them, the same
+//! sealed message is retried with a backoff, the same way as announcements in
+//! the delivery queue are.
+//!
+//! Every local user only gets to see the conversations of their own
+//! identities.
+
+use sea_orm::{prelude::*, sea_query::OnConflict, NotSet, QueryOrder, QuerySelect, Set};
+
+use super::{local_user, Database, PersistenceHandle, Result};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::{ActorAddress, DirectMessageContent},
+	entity::{conversation, direct_message},
+};
+
+
+impl Database {
+	/// Returns the ID of the conversation between the identity and the other
+	/// actor, and starts it if there wasn't one yet.
+	pub async fn ensure_conversation(
+		&self, identity: &ActorAddress, peer: &ActorAddress,
+	) -> Result<i64> {
+		let model = conversation::ActiveModel {
+			id: NotSet,
+			identity_address: Set(identity.clone()),
+			peer_address: Set(peer.clone()),
+			last_activity: Set(current_timestamp() as i64),
+			unread: Set(0),
+		};
+		conversation::Entity::insert(model)
+			.on_conflict(
+				OnConflict::columns([
+					conversation::Column::IdentityAddress,
+					conversation::Column::PeerAddress,
+				])
+				.do_nothing()
+				.to_owned(),
+			)
+			.exec_without_returning(self.inner())
+			.await?;
+
+		let record = conversation::Entity::find()
+			.filter(conversation::Column::IdentityAddress.eq(identity))
+			.filter(conversation::Column::PeerAddress.eq(peer))
+			.one(self.inner())
+			.await?
+			.expect("conversation has just been stored");
+		Ok(record.id)
+	}
+
+	/// Loads the conversation, if it is one of an identity of the current user.
+	pub async fn load_conversation(&self, id: i64) -> Result<Option<conversation::Model>> {
+		Ok(conversation::Entity::find_by_id(id)
+			.filter(local_user::identity_address_condition(
+				conversation::Column::IdentityAddress,
+			))
+			.one(self.inner())
+			.await?)
+	}
+
+	/// Loads the conversations of the identities of the current user, the ones
+	/// with the latest messages first.
+	pub async fn load_conversations(&self) -> Result<Vec<conversation::Model>> {
+		Ok(conversation::Entity::find()
+			.filter(local_user::identity_address_condition(
+				conversation::Column::IdentityAddress,
+			))
+			.order_by_desc(conversation::Column::LastActivity)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Loads the latest messages of the conversation, the latest ones first.
+	pub async fn load_direct_messages(
+		&self, conversation_id: i64, limit: u64, offset: u64,
+	) -> Result<Vec<direct_message::Model>> {
+		Ok(direct_message::Entity::find()
+			.filter(direct_message::Column::ConversationId.eq(conversation_id))
+			.order_by_desc(direct_message::Column::Created)
+			.order_by_desc(direct_message::Column::Id)
+			.limit(limit)
+			.offset(offset)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Loads the outgoing messages that are due to be sent at the given time,
+	/// the ones that are due the longest first.
+	pub async fn load_due_direct_messages(
+		&self, now: i64, limit: u64,
+	) -> Result<Vec<direct_message::Model>> {
+		Ok(direct_message::Entity::find()
+			.filter(direct_message::Column::Delivered.eq(false))
+			.filter(direct_message::Column::NextAttempt.lte(now))
+			.order_by_asc(direct_message::Column::NextAttempt)
+			.limit(limit)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Marks all messages of the conversation as read.
+	pub async fn mark_conversation_read(&self, id: i64) -> Result<()> {
+		conversation::Entity::update_many()
+			.col_expr(conversation::Column::Unread, Expr::value(0))
+			.filter(conversation::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Marks the outgoing message as accepted by its recipient.
+	pub async fn mark_direct_message_delivered(&self, id: i64, hash: &IdType) -> Result<()> {
+		direct_message::Entity::update_many()
+			.col_expr(direct_message::Column::Hash, Expr::value(hash))
+			.col_expr(direct_message::Column::Delivered, Expr::value(true))
+			.col_expr(
+				direct_message::Column::NextAttempt,
+				Expr::value(Option::<i64>::None),
+			)
+			.filter(direct_message::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Remembers the sealed outgoing message, so that it is sent again as is
+	/// when it needs to be retried.
+	pub async fn store_sealed_direct_message(
+		&self, id: i64, hash: &IdType, sealed: Vec<u8>,
+	) -> Result<()> {
+		direct_message::Entity::update_many()
+			.col_expr(direct_message::Column::Hash, Expr::value(hash))
+			.col_expr(direct_message::Column::Sealed, Expr::value(sealed))
+			.filter(direct_message::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Gives up on delivering the outgoing message, if `next_attempt` is
+	/// `None`, or otherwise tries again at that time.
+	pub async fn postpone_direct_message(
+		&self, id: i64, failures: u32, next_attempt: Option<i64>,
+	) -> Result<()> {
+		direct_message::Entity::update_many()
+			.col_expr(direct_message::Column::Failures, Expr::value(failures))
+			.col_expr(direct_message::Column::NextAttempt, Expr::value(next_attempt))
+			.filter(direct_message::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Stores a message that has been received by one of our identities.
+	/// Returns false if the message had been received before.
+	pub async fn store_incoming_direct_message(
+		&self, identity: &ActorAddress, sender: &ActorAddress, hash: &IdType, created: i64,
+		content: &DirectMessageContent,
+	) -> Result<bool> {
+		let conversation_id = self.ensure_conversation(identity, sender).await?;
+		let tx = self.transaction().await?;
+		let model = direct_message::ActiveModel {
+			id: NotSet,
+			conversation_id: Set(conversation_id),
+			hash: Set(Some(hash.clone())),
+			incoming: Set(true),
+			mime_type: Set(content.mime_type.to_string()),
+			body: Set(content.body.clone()),
+			created: Set(created),
+			delivered: Set(true),
+			failures: Set(0),
+			next_attempt: Set(None),
+			sealed: Set(None),
+		};
+		let inserted = direct_message::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(direct_message::Column::Hash)
+					.do_nothing()
+					.to_owned(),
+			)
+			.exec_without_returning(tx.inner())
+			.await?;
+		if inserted > 0 {
+			touch_conversation(&tx, conversation_id, true).await?;
+		}
+		tx.commit().await?;
+		Ok(inserted > 0)
+	}
+
+	/// Stores a message that is to be sent to the other actor of the
+	/// conversation, which is first retried at the given time.
+	pub async fn store_outgoing_direct_message(
+		&self, conversation_id: i64, content: &DirectMessageContent, next_attempt: i64,
+	) -> Result<direct_message::Model> {
+		let now = current_timestamp() as i64;
+		let tx = self.transaction().await?;
+		let model = direct_message::ActiveModel {
+			id: NotSet,
+			conversation_id: Set(conversation_id),
+			hash: Set(None),
+			incoming: Set(false),
+			mime_type: Set(content.mime_type.to_string()),
+			body: Set(content.body.clone()),
+			created: Set(now),
+			delivered: Set(false),
+			failures: Set(0),
+			next_attempt: Set(Some(next_attempt)),
+			sealed: Set(None),
+		};
+		let record = model.insert(tx.inner()).await?;
+		touch_conversation(&tx, conversation_id, false).await?;
+		tx.commit().await?;
+		Ok(record)
+	}
+}
+
+/// Moves the conversation to the top, and counts the message as unread if it
+/// is incoming.
+async fn touch_conversation(
+	db: &impl PersistenceHandle, conversation_id: i64, unread: bool,
+) -> Result<()> {
+	let mut update = conversation::Entity::update_many().col_expr(
+		conversation::Column::LastActivity,
+		Expr::value(current_timestamp() as i64),
+	);
+	if unread {
+		update = update.col_expr(
+			conversation::Column::Unread,
+			Expr::col(conversation::Column::Unread).add(1),
+		);
+	}
+	update
+		.filter(conversation::Column::Id.eq(conversation_id))
+		.exec(db.inner())
+		.await?;
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, test};
+
+	#[tokio::test]
+	async fn test_direct_messages() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("conversation").await;
+		let identity = ActorAddress::V1(IdType::random(&mut rng));
+		let peer = ActorAddress::V1(IdType::random(&mut rng));
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let tx = db.transaction().await.unwrap();
+		tx.create_identity(
+			"test",
+			&identity,
+			&private_key.public(),
+			&private_key,
+			false,
+			&IdType::random(&mut rng),
+			None,
+		)
+		.await
+		.unwrap();
+		tx.commit().await.unwrap();
+
+		let id = db.ensure_conversation(&identity, &peer).await.unwrap();
+		assert_eq!(db.ensure_conversation(&identity, &peer).await.unwrap(), id);
+
+		let content = DirectMessageContent {
+			mime_type: "text/markdown".into(),
+			body: "Hi there".to_string(),
+		};
+		let outgoing = db
+			.store_outgoing_direct_message(id, &content, 1000)
+			.await
+			.unwrap()
+			.id;
+		assert_eq!(db.load_due_direct_messages(999, 10).await.unwrap().len(), 0);
+		let due = db.load_due_direct_messages(1000, 10).await.unwrap();
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].id, outgoing);
+		db.postpone_direct_message(outgoing, 1, Some(2000))
+			.await
+			.unwrap();
+		assert_eq!(db.load_due_direct_messages(1000, 10).await.unwrap().len(), 0);
+		let hash = IdType::random(&mut rng);
+		db.mark_direct_message_delivered(outgoing, &hash)
+			.await
+			.unwrap();
+		assert_eq!(
+			db.load_due_direct_messages(i64::MAX, 10).await.unwrap().len(),
+			0
+		);
+
+		// Receiving the same message twice only stores it once
+		let hash = IdType::random(&mut rng);
+		assert!(db
+			.store_incoming_direct_message(&identity, &peer, &hash, 1, &content)
+			.await
+			.unwrap());
+		assert!(!db
+			.store_incoming_direct_message(&identity, &peer, &hash, 1, &content)
+			.await
+			.unwrap());
+		let conversation = db.load_conversation(id).await.unwrap().unwrap();
+		assert_eq!(conversation.unread, 1);
+		db.mark_conversation_read(id).await.unwrap();
+		assert_eq!(db.load_conversations().await.unwrap()[0].unread, 0);
+		assert_eq!(db.load_direct_messages(id, 10, 0).await.unwrap().len(), 2);
+	}
+}
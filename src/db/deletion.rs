@@ -0,0 +1,107 @@
+//! Posts that have been deleted by their author.
+//!
+//! A deletion is published as a delete object in the chain of the actor. The
+//! objects of the post can't be removed, because the chain wouldn't verify
+//! anymore without them. Instead, they are turned into tombstones: everything
+//! that was derived from them is removed, and their files are no longer kept.
+
+use sea_orm::{prelude::*, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{common::IdType, core::OBJECT_TYPE_POST, entity::*};
+
+
+/// The condition that matches the hashes of the posts that have been deleted by
+/// the actor given as the parameter.
+const DELETED_HASHES: &str = r#"
+	SELECT d.deleted_object_hash
+	FROM delete_object AS d
+	INNER JOIN object AS od ON od.id = d.object_id
+	WHERE od.actor_id = ?
+"#;
+
+
+impl Database {
+	/// Turns the posts of the actor that have been deleted, and the edits
+	/// made to them, into tombstones, and removes the files that are no longer
+	/// needed because of that. Returns the number of new tombstones.
+	pub async fn apply_deletions(&self, actor_id: i64) -> Result<u64> {
+		let tx = self.transaction().await?;
+		let result = tx
+			.inner()
+			.execute(Statement::from_sql_and_values(
+				tx.backend(),
+				&format!(
+					r#"
+				INSERT OR IGNORE INTO tombstone (object_id)
+				SELECT o.id
+				FROM object AS o
+				LEFT JOIN edit_object AS eo ON eo.object_id = o.id
+				WHERE o.actor_id = ? AND (
+					(o.type = {1} AND o.hash IN ({0})) OR eo.edited_object_hash IN ({0})
+				)
+			"#,
+					DELETED_HASHES, OBJECT_TYPE_POST
+				),
+				[actor_id.into(), actor_id.into(), actor_id.into()],
+			))
+			.await?;
+		if result.rows_affected() == 0 {
+			return Ok(0);
+		}
+
+		// Nothing should be found anymore through tags, mentions or searches
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			DELETE FROM post_tag WHERE object_id IN (SELECT object_id FROM tombstone);
+			DELETE FROM post_mention WHERE object_id IN (SELECT object_id FROM tombstone);
+			DELETE FROM search_queue WHERE object_id IN (SELECT object_id FROM tombstone);
+			DELETE FROM search_index WHERE rowid IN (SELECT object_id FROM tombstone);
+		"#,
+			)
+			.await?;
+		let orphaned_blocks = tx.delete_orphans().await?;
+		tx.commit().await?;
+
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(result.rows_affected())
+	}
+
+	/// Whether the actor has published a deletion of the object.
+	pub async fn is_object_deleted(&self, actor_id: i64, object_hash: &IdType) -> Result<bool> {
+		let result = delete_object::Entity::find()
+			.inner_join(object::Entity)
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(delete_object::Column::DeletedObjectHash.eq(object_hash))
+			.one(self.inner())
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Whether the content of the object has been dropped.
+	pub async fn is_tombstone(&self, object_id: i64) -> Result<bool> {
+		let result = tombstone::Entity::find_by_id(object_id)
+			.one(self.inner())
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Whether the file only belongs to posts that have been deleted, in which
+	/// case it doesn't need to be stored anymore.
+	pub async fn is_file_dropped(&self, file_hash: &IdType) -> Result<bool> {
+		let total = post_file::Entity::find()
+			.filter(post_file::Column::Hash.eq(file_hash))
+			.count(self.inner())
+			.await?;
+		if total == 0 {
+			return Ok(false);
+		}
+		let alive = post_file::Entity::find()
+			.filter(post_file::Column::Hash.eq(file_hash))
+			.filter(Expr::cust("object_id NOT IN (SELECT object_id FROM tombstone)"))
+			.count(self.inner())
+			.await?;
+		Ok(alive == 0)
+	}
+}
@@ -0,0 +1,113 @@
+//! Keeps the object announcements that are waiting to be delivered to
+//! followers that were offline when the objects were published.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{common::*, entity::delivery_queue};
+
+
+impl Database {
+	/// Loads the deliveries of the actor that are due at the given time, the
+	/// ones that are due the longest first.
+	pub async fn load_due_deliveries(
+		&self, actor_id: i64, now: i64, limit: u64,
+	) -> Result<Vec<delivery_queue::Model>> {
+		Ok(delivery_queue::Entity::find()
+			.filter(delivery_queue::Column::ActorId.eq(actor_id))
+			.filter(delivery_queue::Column::NextAttempt.lte(now))
+			.order_by_asc(delivery_queue::Column::NextAttempt)
+			.limit(limit)
+			.all(self.inner())
+			.await?)
+	}
+
+	pub async fn postpone_delivery(&self, id: i64, failures: u32, next_attempt: i64) -> Result<()> {
+		delivery_queue::Entity::update_many()
+			.col_expr(delivery_queue::Column::Failures, Expr::value(failures))
+			.col_expr(delivery_queue::Column::NextAttempt, Expr::value(next_attempt))
+			.filter(delivery_queue::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Queues the announcement of an object for a follower, unless it has been
+	/// queued already.
+	pub async fn queue_delivery(
+		&self, actor_id: i64, object_hash: &IdType, recipient_address: &str,
+		recipient_contact: Vec<u8>, next_attempt: i64,
+	) -> Result<()> {
+		let existing = delivery_queue::Entity::find()
+			.filter(delivery_queue::Column::ActorId.eq(actor_id))
+			.filter(delivery_queue::Column::ObjectHash.eq(object_hash))
+			.filter(delivery_queue::Column::RecipientAddress.eq(recipient_address))
+			.one(self.inner())
+			.await?;
+		if existing.is_some() {
+			return Ok(());
+		}
+
+		let model = delivery_queue::ActiveModel {
+			id: NotSet,
+			actor_id: Set(actor_id),
+			object_hash: Set(object_hash.clone()),
+			recipient_address: Set(recipient_address.to_string()),
+			recipient_contact: Set(recipient_contact),
+			failures: Set(0),
+			next_attempt: Set(next_attempt),
+			created: Set(current_timestamp() as i64),
+		};
+		delivery_queue::Entity::insert(model)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	pub async fn remove_delivery(&self, id: i64) -> Result<()> {
+		delivery_queue::Entity::delete_by_id(id)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_delivery_queue() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("delivery").await;
+		let object_hash = IdType::random(&mut rng);
+
+		db.queue_delivery(1, &object_hash, "follower", vec![1], 100)
+			.await
+			.unwrap();
+		// Queueing the same announcement again does nothing
+		db.queue_delivery(1, &object_hash, "follower", vec![1], 50)
+			.await
+			.unwrap();
+		db.queue_delivery(1, &object_hash, "other follower", vec![2], 200)
+			.await
+			.unwrap();
+
+		let due = db.load_due_deliveries(1, 150, 10).await.unwrap();
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].recipient_address, "follower");
+		assert_eq!(db.load_due_deliveries(2, 150, 10).await.unwrap().len(), 0);
+
+		db.postpone_delivery(due[0].id, 1, 300).await.unwrap();
+		let due = db.load_due_deliveries(1, 250, 10).await.unwrap();
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].recipient_address, "other follower");
+
+		db.remove_delivery(due[0].id).await.unwrap();
+		let due = db.load_due_deliveries(1, 1000, 10).await.unwrap();
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].failures, 1);
+	}
+}
@@ -0,0 +1,315 @@
+//! Posts that are still being written.
+//!
+//! The attachments of a draft are stored as files right away, so that they
+//! don't have to be uploaded again every time the draft is saved. Because no
+//! object refers to them yet, nothing about them is announced to the network
+//! until the draft is published. They are kept from being removed as orphans
+//! for as long as the draft exists.
+//!
+//! A draft can also be scheduled, so that it is published automatically at a
+//! later time.
+//!
+//! Every local user only gets to see and change their own drafts.
+
+use sea_orm::{prelude::*, sea_query::OnConflict, NotSet, QueryOrder, Set};
+
+use super::{local_user, Database, PersistenceHandle, Result, Transaction};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::{ActorAddress, FileData},
+	entity::{draft, draft_file, scheduled_post},
+};
+
+
+impl Database {
+	/// Stores a new draft, together with its attachments. Returns its ID.
+	pub async fn create_draft(
+		&self, message: &str, attachments: &[FileData],
+		in_reply_to: Option<&(ActorAddress, IdType)>,
+	) -> Result<i64> {
+		let now = current_timestamp() as i64;
+		let tx = self.transaction().await?;
+		let model = draft::ActiveModel {
+			id: NotSet,
+			message: Set(message.to_string()),
+			in_reply_to_actor_address: Set(in_reply_to.map(|r| r.0.clone())),
+			in_reply_to_object_hash: Set(in_reply_to.map(|r| r.1.clone())),
+			created: Set(now),
+			updated: Set(now),
+			user_id: Set(local_user::current_user()),
+		};
+		let id = draft::Entity::insert(model)
+			.exec(tx.inner())
+			.await?
+			.last_insert_id;
+		tx.stage_draft_files(id, 0, attachments).await?;
+		tx.commit().await?;
+		Ok(id)
+	}
+
+	/// Removes the draft, and its attachments unless they are used elsewhere.
+	/// Returns false if the draft doesn't exist.
+	pub async fn delete_draft(&self, id: i64) -> Result<bool> {
+		let tx = self.transaction().await?;
+		if find_own_draft(&tx, id).await?.is_none() {
+			return Ok(false);
+		}
+		scheduled_post::Entity::delete_many()
+			.filter(scheduled_post::Column::DraftId.eq(id))
+			.exec(tx.inner())
+			.await?;
+		draft_file::Entity::delete_many()
+			.filter(draft_file::Column::DraftId.eq(id))
+			.exec(tx.inner())
+			.await?;
+		let result = draft::Entity::delete_by_id(id).exec(tx.inner()).await?;
+		let orphaned_blocks = tx.delete_orphans().await?;
+		tx.commit().await?;
+
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(result.rows_affected > 0)
+	}
+
+	/// Loads the draft, together with the hashes of its attachments in order.
+	pub async fn load_draft(&self, id: i64) -> Result<Option<(draft::Model, Vec<IdType>)>> {
+		let record = match find_own_draft(self, id).await? {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		let files = draft_file::Entity::find()
+			.filter(draft_file::Column::DraftId.eq(id))
+			.order_by_asc(draft_file::Column::Sequence)
+			.all(self.inner())
+			.await?;
+		Ok(Some((record, files.into_iter().map(|f| f.file_hash).collect())))
+	}
+
+	/// Loads when the draft is going to be published, if it is scheduled.
+	pub async fn load_draft_schedule(&self, id: i64) -> Result<Option<scheduled_post::Model>> {
+		if find_own_draft(self, id).await?.is_none() {
+			return Ok(None);
+		}
+		Ok(scheduled_post::Entity::find()
+			.filter(scheduled_post::Column::DraftId.eq(id))
+			.one(self.inner())
+			.await?)
+	}
+
+	/// Loads all drafts, the most recently saved ones first.
+	pub async fn load_drafts(&self) -> Result<Vec<draft::Model>> {
+		Ok(draft::Entity::find()
+			.filter(local_user::user_condition(draft::Column::UserId))
+			.order_by_desc(draft::Column::Updated)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Removes an attachment from the draft.
+	/// Returns false if the draft didn't have the attachment.
+	pub async fn remove_draft_attachment(&self, id: i64, file_hash: &IdType) -> Result<bool> {
+		let tx = self.transaction().await?;
+		if find_own_draft(&tx, id).await?.is_none() {
+			return Ok(false);
+		}
+		let result = draft_file::Entity::delete_many()
+			.filter(draft_file::Column::DraftId.eq(id))
+			.filter(draft_file::Column::FileHash.eq(file_hash))
+			.exec(tx.inner())
+			.await?;
+		let orphaned_blocks = tx.delete_orphans().await?;
+		tx.commit().await?;
+
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(result.rows_affected > 0)
+	}
+
+	/// Loads the scheduled posts that are due at the given unix timestamp in
+	/// milliseconds, the ones that were due first coming first.
+	pub async fn load_scheduled_posts(&self, until: i64) -> Result<Vec<scheduled_post::Model>> {
+		Ok(scheduled_post::Entity::find()
+			.filter(scheduled_post::Column::PublishAt.lte(until))
+			.order_by_asc(scheduled_post::Column::PublishAt)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Schedules the draft to be published by the given identity, or moves it
+	/// to another time if it was scheduled already. Returns false if the draft
+	/// doesn't exist.
+	pub async fn schedule_draft(
+		&self, id: i64, actor_address: &ActorAddress, publish_at: i64,
+	) -> Result<bool> {
+		let tx = self.transaction().await?;
+		if find_own_draft(&tx, id).await?.is_none() {
+			return Ok(false);
+		}
+
+		let model = scheduled_post::ActiveModel {
+			id: NotSet,
+			draft_id: Set(id),
+			actor_address: Set(actor_address.clone()),
+			publish_at: Set(publish_at),
+			last_error: Set(None),
+		};
+		scheduled_post::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(scheduled_post::Column::DraftId)
+					.update_columns([
+						scheduled_post::Column::ActorAddress,
+						scheduled_post::Column::PublishAt,
+						scheduled_post::Column::LastError,
+					])
+					.to_owned(),
+			)
+			.exec_without_returning(tx.inner())
+			.await?;
+		tx.commit().await?;
+		Ok(true)
+	}
+
+	/// Remembers why the scheduled post couldn't be published, so that it can
+	/// be shown to the user. Publishing it is tried again later.
+	pub async fn set_scheduled_post_error(&self, id: i64, error: &str) -> Result<()> {
+		scheduled_post::Entity::update_many()
+			.col_expr(scheduled_post::Column::LastError, Expr::value(error))
+			.filter(scheduled_post::Column::Id.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Keeps the draft from being published automatically. Returns false if it
+	/// wasn't scheduled.
+	pub async fn unschedule_draft(&self, id: i64) -> Result<bool> {
+		if find_own_draft(self, id).await?.is_none() {
+			return Ok(false);
+		}
+		let result = scheduled_post::Entity::delete_many()
+			.filter(scheduled_post::Column::DraftId.eq(id))
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected > 0)
+	}
+
+	/// Replaces the message of the draft, and adds the attachments to the ones
+	/// it has already. Returns false if the draft doesn't exist.
+	pub async fn update_draft(
+		&self, id: i64, message: &str, new_attachments: &[FileData],
+	) -> Result<bool> {
+		let tx = self.transaction().await?;
+		let result = draft::Entity::update_many()
+			.col_expr(draft::Column::Message, Expr::value(message))
+			.col_expr(
+				draft::Column::Updated,
+				Expr::value(current_timestamp() as i64),
+			)
+			.filter(draft::Column::Id.eq(id))
+			.filter(local_user::user_condition(draft::Column::UserId))
+			.exec(tx.inner())
+			.await?;
+		if result.rows_affected == 0 {
+			return Ok(false);
+		}
+
+		let next_sequence = draft_file::Entity::find()
+			.filter(draft_file::Column::DraftId.eq(id))
+			.order_by_desc(draft_file::Column::Sequence)
+			.one(tx.inner())
+			.await?
+			.map(|f| f.sequence + 1)
+			.unwrap_or(0);
+		tx.stage_draft_files(id, next_sequence, new_attachments)
+			.await?;
+		tx.commit().await?;
+		Ok(true)
+	}
+}
+
+/// Finds the draft, if it belongs to the current user.
+async fn find_own_draft(db: &impl PersistenceHandle, id: i64) -> Result<Option<draft::Model>> {
+	Ok(draft::Entity::find_by_id(id)
+		.filter(local_user::user_condition(draft::Column::UserId))
+		.one(db.inner())
+		.await?)
+}
+
+impl Transaction {
+	async fn stage_draft_files(
+		&self, draft_id: i64, first_sequence: i32, attachments: &[FileData],
+	) -> Result<()> {
+		for (i, attachment) in attachments.iter().enumerate() {
+			let (_, file_hash, _) = self.create_file(attachment).await?;
+			let model = draft_file::ActiveModel {
+				id: NotSet,
+				draft_id: Set(draft_id),
+				file_hash: Set(file_hash),
+				sequence: Set(first_sequence + i as i32),
+			};
+			draft_file::Entity::insert(model)
+				.exec_without_returning(self.inner())
+				.await?;
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_drafts() {
+		let db = test::load_database("draft").await;
+
+		let attachment = FileData {
+			mime_type: "text/plain".into(),
+			data: b"Not announced yet".to_vec(),
+		};
+		let id = db
+			.create_draft("First try", std::slice::from_ref(&attachment), None)
+			.await
+			.unwrap();
+		let other = FileData {
+			mime_type: "text/plain".into(),
+			data: b"Another one".to_vec(),
+		};
+		assert!(db.update_draft(id, "Second try", &[other]).await.unwrap());
+		assert!(!db.update_draft(id + 1, "Nothing", &[]).await.unwrap());
+
+		let (record, files) = db.load_draft(id).await.unwrap().unwrap();
+		assert_eq!(record.message, "Second try");
+		assert_eq!(files.len(), 2);
+		assert_eq!(db.load_drafts().await.unwrap().len(), 1);
+
+		// The attachments survive the cleanup of orphaned files
+		assert_eq!(db.prune_orphans().await.unwrap(), 0);
+		let data = db.load_file_data(&files[0]).await.unwrap().unwrap();
+		assert_eq!(data.data, attachment.data);
+
+		let address = ActorAddress::V1(IdType::random(&mut test::initialize_rng()));
+		assert!(db.schedule_draft(id, &address, 2000).await.unwrap());
+		assert!(db.schedule_draft(id, &address, 1000).await.unwrap());
+		assert!(!db.schedule_draft(id + 1, &address, 1000).await.unwrap());
+		assert_eq!(db.load_scheduled_posts(999).await.unwrap().len(), 0);
+		let due = db.load_scheduled_posts(1000).await.unwrap();
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].draft_id, id);
+		db.set_scheduled_post_error(due[0].id, "Keys are locked")
+			.await
+			.unwrap();
+		let schedule = db.load_draft_schedule(id).await.unwrap().unwrap();
+		assert_eq!(schedule.last_error.as_deref(), Some("Keys are locked"));
+		assert!(db.unschedule_draft(id).await.unwrap());
+		assert!(!db.unschedule_draft(id).await.unwrap());
+		assert!(db.schedule_draft(id, &address, 1000).await.unwrap());
+
+		assert!(db.remove_draft_attachment(id, &files[1]).await.unwrap());
+		assert!(db.load_file_data(&files[1]).await.unwrap().is_none());
+		assert!(db.delete_draft(id).await.unwrap());
+		assert!(db.load_file_data(&files[0]).await.unwrap().is_none());
+		assert!(db.load_draft(id).await.unwrap().is_none());
+		assert!(db.load_draft_schedule(id).await.unwrap().is_none());
+	}
+}
@@ -0,0 +1,163 @@
+//! Encrypts the whole database file with SQLCipher, so that the follows and
+//! the other private metadata in it can't be read without the passphrase. This
+//! is only possible when Stonenet is built with the `sqlcipher` feature, which
+//! links against SQLCipher instead of SQLite.
+//!
+//! The key is derived from the passphrase with Argon2 and a random salt, and is
+//! given to SQLCipher as a raw key, together with the salt. SQLCipher keeps the
+//! salt at the start of the file, where it is read from again the next time the
+//! database is opened. Because the key is only derived once, opening another
+//! connection to the database stays cheap.
+//!
+//! A database that isn't encrypted yet is encrypted the first time the daemon
+//! starts with a passphrase, by exporting it into a new encrypted file that
+//! takes its place.
+
+use std::{
+	fmt::Write as _,
+	fs::{self, File},
+	io::{self, Read},
+	path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::params;
+use zeroize::Zeroizing;
+
+use super::Result;
+
+
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+/// What the file of a database that isn't encrypted starts with.
+const PLAIN_HEADER: &[u8; SALT_SIZE] = b"SQLite format 3\0";
+
+
+/// The key that the database is encrypted with, in the form in which SQLCipher
+/// takes it.
+pub struct DatabaseKey(Zeroizing<String>);
+
+
+impl DatabaseKey {
+	/// Derives the key for the database at the given path. The salt is read
+	/// from the database if it is encrypted already, and generated otherwise.
+	pub fn derive(path: &Path, passphrase: &str) -> io::Result<Self> {
+		let salt = match read_salt(path)? {
+			Some(s) => s,
+			None => {
+				let mut salt = [0u8; SALT_SIZE];
+				OsRng.fill_bytes(&mut salt);
+				salt
+			}
+		};
+		let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+		Argon2::default()
+			.hash_password_into(passphrase.as_bytes(), &salt, &mut *key)
+			.expect("invalid key derivation parameters");
+
+		let mut value = Zeroizing::new(String::with_capacity(3 + 2 * (KEY_SIZE + SALT_SIZE)));
+		value.push_str("x'");
+		for byte in key.iter().chain(salt.iter()) {
+			write!(value, "{:02x}", byte).unwrap();
+		}
+		value.push('\'');
+		Ok(Self(value))
+	}
+
+	/// The value of the `key` pragma.
+	pub(super) fn pragma_value(&self) -> &str { &self.0 }
+}
+
+
+/// Whether the database at the path is encrypted. A database that doesn't
+/// exist yet isn't.
+pub fn is_encrypted(path: &Path) -> io::Result<bool> { Ok(read_salt(path)?.is_some()) }
+
+/// Encrypts the database at the path with the key, if it exists and isn't
+/// encrypted yet. Returns whether that happened.
+pub fn encrypt_existing(path: &Path, key: &DatabaseKey) -> Result<bool> {
+	if !path.exists() || fs::metadata(path)?.len() == 0 || is_encrypted(path)? {
+		return Ok(false);
+	}
+
+	let mut encrypted_path = path.as_os_str().to_owned();
+	encrypted_path.push(".encrypted");
+	let encrypted_path = PathBuf::from(encrypted_path);
+	if encrypted_path.exists() {
+		// Left behind by an attempt that was interrupted
+		fs::remove_file(&encrypted_path)?;
+	}
+
+	let connection = rusqlite::Connection::open(path)?;
+	connection.execute(
+		"ATTACH DATABASE ?1 AS encrypted KEY ?2",
+		params![encrypted_path.to_string_lossy(), key.pragma_value()],
+	)?;
+	connection.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+	connection.execute("DETACH DATABASE encrypted", [])?;
+	drop(connection);
+
+	fs::rename(&encrypted_path, path)?;
+	Ok(true)
+}
+
+/// Reads the salt of an encrypted database, which SQLCipher keeps in the first
+/// bytes of the file.
+fn read_salt(path: &Path) -> io::Result<Option<[u8; SALT_SIZE]>> {
+	let mut file = match File::open(path) {
+		Ok(f) => f,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e),
+	};
+	let mut header = [0u8; SALT_SIZE];
+	match file.read_exact(&mut header) {
+		Ok(()) => {}
+		// An empty file is a database that hasn't been created yet
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e),
+	}
+	if &header == PLAIN_HEADER {
+		Ok(None)
+	} else {
+		Ok(Some(header))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use super::*;
+
+	#[test]
+	fn test_database_key() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("db.sqlite");
+		assert!(!is_encrypted(&path).unwrap());
+
+		// A database that isn't encrypted gets a new salt every time
+		rusqlite::Connection::open(&path)
+			.unwrap()
+			.execute_batch("CREATE TABLE a (b integer)")
+			.unwrap();
+		assert!(!is_encrypted(&path).unwrap());
+		let first = DatabaseKey::derive(&path, "passphrase").unwrap();
+		let second = DatabaseKey::derive(&path, "passphrase").unwrap();
+		assert_ne!(first.pragma_value(), second.pragma_value());
+		assert_eq!(first.pragma_value().len(), 3 + 2 * (KEY_SIZE + SALT_SIZE));
+
+		// Once encrypted, the salt at the start of the file is used
+		let mut file = File::create(&path).unwrap();
+		file.write_all(&[7u8; 64]).unwrap();
+		drop(file);
+		assert!(is_encrypted(&path).unwrap());
+		let first = DatabaseKey::derive(&path, "passphrase").unwrap();
+		let second = DatabaseKey::derive(&path, "passphrase").unwrap();
+		let other = DatabaseKey::derive(&path, "other").unwrap();
+		assert_eq!(first.pragma_value(), second.pragma_value());
+		assert_ne!(first.pragma_value(), other.pragma_value());
+		assert!(first.pragma_value().ends_with(&format!("{}'", "07".repeat(SALT_SIZE))));
+	}
+}
@@ -0,0 +1,204 @@
+//! Keeps the amount of block data within the storage quota.
+//!
+//! Nodes store the blocks of the actor networks they take part in, which would
+//! otherwise grow without bound. Only the blocks of the files of our own
+//! identities, the actors that we follow, the actors that we track or archive
+//! and the objects that have been bookmarked are pinned, and so are the
+//! attachments of drafts. All other blocks may be evicted, the least recently requested
+//! ones first. Because the last access time of a block is only kept at a
+//! resolution of a day, the blocks of the actors whose addresses are farthest
+//! from our node ID go first among the blocks of the same day.
+//!
+//! The parity blocks that we keep for other nodes are evicted before any block
+//! is. Only the block data is removed. The objects and the meta data of their
+//! files stay, so that the blocks can be found on the network again when they
+//! are needed.
+
+use num::bigint::BigUint;
+use sea_orm::{prelude::*, QuerySelect, Statement, Value};
+
+use super::{archive::LAST_ACCESS_RESOLUTION, Database, PersistenceHandle, Result};
+use crate::{common::IdType, core::ActorAddress, entity::*};
+
+
+/// The number of blocks that are considered for eviction at once.
+const EVICTION_BATCH_SIZE: u64 = 10000;
+
+
+struct EvictionCandidate {
+	block_id: i64,
+	size: u64,
+	last_access: i64,
+	/// An actor that has a post that uses the block, if any.
+	actor_address: Option<ActorAddress>,
+}
+
+
+impl Database {
+	/// Removes unpinned blocks until at least the given amount of bytes has
+	/// been freed, or until there are no more unpinned blocks.
+	/// Returns the number of bytes that have been freed.
+	pub async fn evict_blocks(
+		&self, pinned_actors: &[ActorAddress], node_id: &IdType, amount: u64,
+	) -> Result<u64> {
+		// Parity blocks are only a fallback, so they go first
+		let mut freed = self.evict_parity_blocks(amount).await?;
+		if freed >= amount {
+			return Ok(freed);
+		}
+
+		let mut candidates = self.load_eviction_candidates(pinned_actors).await?;
+		order_eviction_candidates(&mut candidates, node_id);
+
+		let mut block_ids = Vec::new();
+		for candidate in candidates {
+			if freed >= amount {
+				break;
+			}
+			freed += candidate.size;
+			block_ids.push(candidate.block_id);
+		}
+		if block_ids.len() == 0 {
+			return Ok(freed);
+		}
+
+		let hashes = block::Entity::find()
+			.select_only()
+			.column(block::Column::Hash)
+			.filter(block::Column::Id.is_in(block_ids.clone()))
+			.into_tuple::<IdType>()
+			.all(self.inner())
+			.await?;
+		let tx = self.transaction().await?;
+		archived_block::Entity::delete_many()
+			.filter(archived_block::Column::BlockId.is_in(block_ids.clone()))
+			.exec(tx.inner())
+			.await?;
+		block::Entity::delete_many()
+			.filter(block::Column::Id.is_in(block_ids))
+			.exec(tx.inner())
+			.await?;
+		tx.commit().await?;
+		self.remove_block_data(hashes).await?;
+		Ok(freed)
+	}
+
+	async fn load_eviction_candidates(
+		&self, pinned_actors: &[ActorAddress],
+	) -> Result<Vec<EvictionCandidate>> {
+		let mut tracked_condition = String::new();
+		if pinned_actors.len() > 0 {
+			let placeholders = vec!["?"; pinned_actors.len()].join(",");
+			tracked_condition = format!(
+				"OR actor_id IN (SELECT id FROM actor WHERE address IN ({}))",
+				placeholders
+			);
+		}
+		let sql = format!(
+			r#"
+			WITH pinned_object AS (
+				SELECT id FROM object
+				WHERE actor_id IN (SELECT actor_id FROM following)
+					OR actor_id IN (SELECT actor_id FROM identity)
+					OR hash IN (SELECT object_hash FROM bookmark)
+					{}
+			), pinned_file AS (
+				SELECT hash FROM post_file WHERE object_id IN (SELECT id FROM pinned_object)
+				UNION SELECT avatar_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM pinned_object)
+				UNION SELECT wallpaper_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM pinned_object)
+				UNION SELECT description_file_hash FROM profile_object
+				WHERE object_id IN (SELECT id FROM pinned_object)
+				UNION SELECT file_hash FROM draft_file
+			)
+			SELECT b.id, b.size, b.last_access, MIN(a.address)
+			FROM block AS b
+			LEFT JOIN file_block AS fb ON fb.block_hash = b.hash
+			LEFT JOIN file AS f ON f.id = fb.file_id
+			LEFT JOIN post_file AS pf ON pf.hash = f.hash
+			LEFT JOIN object AS o ON o.id = pf.object_id
+			LEFT JOIN actor AS a ON a.id = o.actor_id
+			WHERE b.hash NOT IN (
+				SELECT pfb.block_hash FROM file_block AS pfb
+				INNER JOIN file AS pff ON pff.id = pfb.file_id
+				WHERE pff.hash IN (SELECT hash FROM pinned_file)
+			)
+			GROUP BY b.id
+			ORDER BY b.last_access ASC
+			LIMIT ?
+		"#,
+			tracked_condition
+		);
+		let mut values: Vec<Value> = pinned_actors.iter().map(|a| a.clone().into()).collect();
+		values.push(EVICTION_BATCH_SIZE.into());
+
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(self.backend(), sql, values))
+			.await?;
+		let mut candidates = Vec::with_capacity(results.len());
+		for result in results {
+			let size: i64 = result.try_get_by_index(1)?;
+			candidates.push(EvictionCandidate {
+				block_id: result.try_get_by_index(0)?,
+				size: size as _,
+				last_access: result.try_get_by_index(2)?,
+				actor_address: result.try_get_by_index(3)?,
+			});
+		}
+		Ok(candidates)
+	}
+}
+
+/// Orders the candidates from the one that should be evicted first to the one
+/// that should be evicted last.
+fn order_eviction_candidates(candidates: &mut [EvictionCandidate], node_id: &IdType) {
+	// Blocks that aren't used by any post have no distance, and go first
+	let max_distance = BigUint::from_bytes_be(&[0xFF; 32]) + 1u32;
+	candidates.sort_by_cached_key(|c| {
+		let distance = match &c.actor_address {
+			Some(address) => address.as_id().distance(node_id),
+			None => max_distance.clone(),
+		};
+		(
+			c.last_access / LAST_ACCESS_RESOLUTION,
+			max_distance.clone() - distance,
+		)
+	});
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_order_eviction_candidates() {
+		let mut rng = test::initialize_rng();
+		let node_id = IdType::random(&mut rng);
+		let near = ActorAddress::V1(node_id.clone());
+		let far = ActorAddress::V1(IdType::random(&mut rng));
+		let day = LAST_ACCESS_RESOLUTION;
+
+		let candidate = |block_id, last_access, actor_address| EvictionCandidate {
+			block_id,
+			size: 1,
+			last_access,
+			actor_address,
+		};
+		let mut candidates = vec![
+			candidate(1, day + 1, Some(near.clone())),
+			candidate(2, 0, Some(near.clone())),
+			candidate(3, day + 2, Some(far.clone())),
+			candidate(4, 2 * day, None),
+			candidate(5, day, None),
+		];
+		order_eviction_candidates(&mut candidates, &node_id);
+
+		// Blocks are evicted by day first, and by distance second
+		let order: Vec<_> = candidates.iter().map(|c| c.block_id).collect();
+		assert_eq!(order, vec![2, 5, 3, 1, 4]);
+	}
+}
@@ -0,0 +1,100 @@
+//! The metadata of the media files that have been uploaded, like their
+//! dimensions. It is kept by the hash of the file, so that it still applies
+//! when the file is attached to another post, or stored again after having
+//! been removed.
+
+use sea_orm::{prelude::*, sea_query::OnConflict, NotSet, Set};
+
+use super::{Database, PersistenceHandle, Result, Transaction};
+use crate::{common::IdType, entity::file_metadata, media::MediaMetadata};
+
+
+impl Database {
+	pub async fn load_file_metadata(&self, file_hash: &IdType) -> Result<Option<MediaMetadata>> {
+		let record = file_metadata::Entity::find()
+			.filter(file_metadata::Column::FileHash.eq(file_hash))
+			.one(self.inner())
+			.await?;
+		Ok(record.map(|r| MediaMetadata {
+			width: r.width,
+			height: r.height,
+			duration: r.duration.map(|d| d as u64),
+			blurhash: r.blurhash,
+		}))
+	}
+}
+
+impl Transaction {
+	/// Stores the metadata of the file, replacing what was stored for it
+	/// before.
+	pub async fn store_file_metadata(
+		&self, file_hash: &IdType, mime_type: &str, metadata: &MediaMetadata,
+	) -> Result<()> {
+		let model = file_metadata::ActiveModel {
+			id: NotSet,
+			file_hash: Set(file_hash.clone()),
+			mime_type: Set(mime_type.to_string()),
+			width: Set(metadata.width),
+			height: Set(metadata.height),
+			duration: Set(metadata.duration.map(|d| d as i64)),
+			blurhash: Set(metadata.blurhash.clone()),
+		};
+		file_metadata::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(file_metadata::Column::FileHash)
+					.update_columns([
+						file_metadata::Column::MimeType,
+						file_metadata::Column::Width,
+						file_metadata::Column::Height,
+						file_metadata::Column::Duration,
+						file_metadata::Column::Blurhash,
+					])
+					.to_owned(),
+			)
+			.exec_without_returning(self.inner())
+			.await?;
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_file_metadata() {
+		let db = test::load_database("file_metadata").await;
+		let file_hash = IdType::hash(b"image");
+		assert_eq!(db.load_file_metadata(&file_hash).await.unwrap(), None);
+
+		let mut metadata = MediaMetadata {
+			width: Some(640),
+			height: Some(480),
+			duration: None,
+			blurhash: Some("LEHV6nWB2yk8pyo0adR*.7kCMdnj".into()),
+		};
+		let tx = db.transaction().await.unwrap();
+		tx.store_file_metadata(&file_hash, "image/png", &metadata)
+			.await
+			.unwrap();
+		tx.commit().await.unwrap();
+		assert_eq!(
+			db.load_file_metadata(&file_hash).await.unwrap().as_ref(),
+			Some(&metadata)
+		);
+
+		// Storing it again replaces it
+		metadata.width = Some(320);
+		let tx = db.transaction().await.unwrap();
+		tx.store_file_metadata(&file_hash, "image/png", &metadata)
+			.await
+			.unwrap();
+		tx.commit().await.unwrap();
+		assert_eq!(
+			db.load_file_metadata(&file_hash).await.unwrap().unwrap().width,
+			Some(320)
+		);
+	}
+}
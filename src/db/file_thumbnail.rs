@@ -0,0 +1,76 @@
+//! The thumbnails of images, which are kept by the hash of the image, like its
+//! metadata.
+
+use sea_orm::{prelude::*, sea_query::OnConflict, NotSet, Set};
+
+use super::{Database, PersistenceHandle, Result, Transaction};
+use crate::{common::IdType, entity::file_thumbnail, media::Thumbnail};
+
+
+impl Database {
+	/// Loads the MIME type and the data of the thumbnail of the file.
+	pub async fn load_file_thumbnail(
+		&self, file_hash: &IdType,
+	) -> Result<Option<(String, Vec<u8>)>> {
+		let record = file_thumbnail::Entity::find()
+			.filter(file_thumbnail::Column::FileHash.eq(file_hash))
+			.one(self.inner())
+			.await?;
+		Ok(record.map(|r| (r.mime_type, r.data)))
+	}
+}
+
+impl Transaction {
+	/// Stores the thumbnail of the file, replacing what was stored for it
+	/// before.
+	pub async fn store_file_thumbnail(
+		&self, file_hash: &IdType, thumbnail: &Thumbnail,
+	) -> Result<()> {
+		let model = file_thumbnail::ActiveModel {
+			id: NotSet,
+			file_hash: Set(file_hash.clone()),
+			mime_type: Set(thumbnail.mime_type.to_string()),
+			data: Set(thumbnail.data.clone()),
+		};
+		file_thumbnail::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(file_thumbnail::Column::FileHash)
+					.update_columns([
+						file_thumbnail::Column::MimeType,
+						file_thumbnail::Column::Data,
+					])
+					.to_owned(),
+			)
+			.exec_without_returning(self.inner())
+			.await?;
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_file_thumbnail() {
+		let db = test::load_database("file_thumbnail").await;
+		let file_hash = IdType::hash(b"image");
+		assert_eq!(db.load_file_thumbnail(&file_hash).await.unwrap(), None);
+
+		let thumbnail = Thumbnail {
+			mime_type: "image/jpeg",
+			data: vec![1, 2, 3],
+		};
+		let tx = db.transaction().await.unwrap();
+		tx.store_file_thumbnail(&file_hash, &thumbnail)
+			.await
+			.unwrap();
+		tx.commit().await.unwrap();
+		assert_eq!(
+			db.load_file_thumbnail(&file_hash).await.unwrap(),
+			Some(("image/jpeg".to_string(), vec![1, 2, 3]))
+		);
+	}
+}
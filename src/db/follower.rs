@@ -0,0 +1,161 @@
+//! Actors that only hand out their objects to the followers they approved.
+//!
+//! Such an actor has set `followers_only` on its latest profile. Other actors
+//! send it a signed follow request, which its owner accepts or rejects. The
+//! accepted requests make up a follower list object in the chain of the actor,
+//! which every node of the actor network uses to decide which nodes it serves.
+//! The profile and the follower lists themselves are served to anyone, so that
+//! others can see whom to ask.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::IdType,
+	core::{
+		ApprovedFollower, FollowRequestObject, NodeAddress, OBJECT_TYPE_FOLLOWER_LIST,
+		OBJECT_TYPE_PROFILE,
+	},
+	entity::*,
+};
+
+
+impl Database {
+	/// Decides on the follow request that was sent to the actor. Returns the
+	/// request, or `None` if it doesn't exist.
+	pub async fn decide_follow_request(
+		&self, actor_id: i64, id: i64, accept: bool,
+	) -> Result<Option<follow_request::Model>> {
+		let record = match follow_request::Entity::find_by_id(id)
+			.filter(follow_request::Column::ActorId.eq(actor_id))
+			.one(self.inner())
+			.await?
+		{
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		let mut record: follow_request::ActiveModel = record.into();
+		record.accepted = Set(Some(accept));
+		Ok(Some(record.update(self.inner()).await?))
+	}
+
+	/// Whether the objects of the actor are only handed out to its approved
+	/// followers, according to its latest profile.
+	pub async fn is_followers_only(&self, actor_id: i64) -> Result<bool> {
+		let result = profile_object::Entity::find()
+			.inner_join(object::Entity)
+			.filter(object::Column::ActorId.eq(actor_id))
+			.order_by_desc(object::Column::Sequence)
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|p| p.followers_only).unwrap_or(false))
+	}
+
+	/// Whether the node is on the latest follower list of the actor.
+	pub async fn is_approved_follower_node(
+		&self, actor_id: i64, node_address: &NodeAddress,
+	) -> Result<bool> {
+		let latest_list = object::Entity::find()
+			.select_only()
+			.column(object::Column::Id)
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Type.eq(OBJECT_TYPE_FOLLOWER_LIST))
+			.order_by_desc(object::Column::Sequence)
+			.into_tuple::<i64>()
+			.one(self.inner())
+			.await?;
+		let object_id = match latest_list {
+			Some(id) => id,
+			None => return Ok(false),
+		};
+		let result = follower_list_entry::Entity::find()
+			.filter(follower_list_entry::Column::ObjectId.eq(object_id))
+			.filter(follower_list_entry::Column::NodeAddress.eq(node_address))
+			.one(self.inner())
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Whether the object of the actor is served to anyone, even if the actor
+	/// only hands out its objects to its approved followers.
+	pub async fn is_public_object(&self, actor_id: i64, hash: &IdType) -> Result<bool> {
+		let result = object::Entity::find()
+			.filter(object::Column::ActorId.eq(actor_id))
+			.filter(object::Column::Hash.eq(hash))
+			.filter(object::Column::Type.is_in([OBJECT_TYPE_PROFILE, OBJECT_TYPE_FOLLOWER_LIST]))
+			.one(self.inner())
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Loads the followers that have been accepted for the actor, in the order
+	/// in which they have asked.
+	pub async fn load_approved_followers(&self, actor_id: i64) -> Result<Vec<ApprovedFollower>> {
+		let results = follow_request::Entity::find()
+			.filter(follow_request::Column::ActorId.eq(actor_id))
+			.filter(follow_request::Column::Accepted.eq(true))
+			.order_by_asc(follow_request::Column::Id)
+			.all(self.inner())
+			.await?;
+		Ok(results
+			.into_iter()
+			.map(|r| ApprovedFollower {
+				actor_address: r.requester_address,
+				node_address: r.node_address,
+			})
+			.collect())
+	}
+
+	/// Loads the follow requests that have been sent to the actor, the ones
+	/// that haven't been decided on yet first.
+	pub async fn load_follow_requests(&self, actor_id: i64) -> Result<Vec<follow_request::Model>> {
+		Ok(follow_request::Entity::find()
+			.filter(follow_request::Column::ActorId.eq(actor_id))
+			.order_by_asc(follow_request::Column::Accepted.is_not_null())
+			.order_by_desc(follow_request::Column::Created)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Stores a follow request that has been sent to the actor. A new request
+	/// of the same requester replaces the old one, unless it has been accepted
+	/// for the same node already. Returns whether it needs to be decided on.
+	pub async fn store_follow_request(
+		&self, actor_id: i64, request: &FollowRequestObject,
+	) -> Result<bool> {
+		let hash = request.hash();
+		let existing = follow_request::Entity::find()
+			.filter(follow_request::Column::ActorId.eq(actor_id))
+			.filter(follow_request::Column::RequesterAddress.eq(&request.requester))
+			.one(self.inner())
+			.await?;
+		if let Some(record) = existing {
+			if record.hash == hash
+				|| (record.accepted == Some(true) && record.node_address == request.node_address)
+			{
+				return Ok(false);
+			}
+			let mut record: follow_request::ActiveModel = record.into();
+			record.node_address = Set(request.node_address.clone());
+			record.hash = Set(hash);
+			record.created = Set(request.created as _);
+			record.accepted = Set(None);
+			record.update(self.inner()).await?;
+			return Ok(true);
+		}
+
+		let record = follow_request::ActiveModel {
+			id: NotSet,
+			actor_id: Set(actor_id),
+			requester_address: Set(request.requester.clone()),
+			node_address: Set(request.node_address.clone()),
+			hash: Set(hash),
+			created: Set(request.created as _),
+			accepted: Set(None),
+		};
+		follow_request::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		Ok(true)
+	}
+}
@@ -0,0 +1,271 @@
+//! Keeps the node running while the database is unavailable.
+//!
+//! When the SQLite database becomes locked, unreadable or full in the middle of
+//! a run, the database is marked as degraded. The network stack keeps going:
+//! writes that can wait, like the data that is being synchronized, are queued
+//! in memory up to a limit, and reads fall back on whatever is cached. The
+//! health of the database is checked periodically, and once it responds again,
+//! the queued writes are flushed and the degraded state is lifted.
+
+use std::{
+	collections::VecDeque,
+	sync::Mutex,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::*;
+use rusqlite::ErrorCode;
+use serde::Serialize;
+
+use super::{
+	maintenance::MaintenanceReport, run_blocking, Connection, Database, Error, PersistenceHandle,
+	Result,
+};
+
+
+/// The maximum number of bytes of writes that are kept in memory.
+const WRITE_QUEUE_SIZE_LIMIT: usize = 32 * 1024 * 1024;
+/// The maximum number of writes that are kept in memory.
+const WRITE_QUEUE_LENGTH_LIMIT: usize = 10000;
+
+
+type QueuedWrite = Box<dyn Fn(&mut Connection) -> Result<()> + Send>;
+
+#[derive(Default)]
+pub(super) struct Health {
+	state: Mutex<HealthState>,
+	/// Held while the queue is being written, so that the queued writes are
+	/// performed in order. The state itself is never locked during a write.
+	flushing: Mutex<()>,
+}
+
+#[derive(Default)]
+struct HealthState {
+	/// The unix timestamp at which the database became unavailable, in seconds.
+	degraded_since: Option<u64>,
+	last_error: Option<String>,
+	queue: VecDeque<(usize, QueuedWrite)>,
+	queued_bytes: usize,
+	dropped_writes: u64,
+	last_maintenance: Option<MaintenanceReport>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DatabaseStatus {
+	pub is_degraded: bool,
+	pub degraded_since: Option<u64>,
+	pub last_error: Option<String>,
+	pub queued_writes: usize,
+	pub queued_bytes: usize,
+	/// The number of writes that have been lost because the queue was full.
+	pub dropped_writes: u64,
+	pub last_maintenance: Option<MaintenanceReport>,
+}
+
+
+impl Error {
+	/// Whether the error means that the database can't be used at the moment,
+	/// as opposed to something being wrong with the query or the data.
+	pub fn is_unavailable(&self) -> bool {
+		match self {
+			Self::Unavailable => true,
+			Self::SqliteError(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+				e.code,
+				ErrorCode::DatabaseBusy
+					| ErrorCode::DatabaseLocked
+					| ErrorCode::DatabaseCorrupt
+					| ErrorCode::NotADatabase
+					| ErrorCode::SystemIoFailure
+					| ErrorCode::CannotOpen
+					| ErrorCode::DiskFull
+					| ErrorCode::ReadOnly
+			),
+			Self::OrmError(e) => match e {
+				sea_orm::DbErr::ConnectionAcquire(_) | sea_orm::DbErr::Conn(_) => true,
+				other => {
+					let message = other.to_string();
+					message.contains("database is locked")
+						|| message.contains("malformed")
+						|| message.contains("unable to open database")
+						|| message.contains("disk I/O error")
+				}
+			},
+			_ => false,
+		}
+	}
+}
+
+impl HealthState {
+	fn degrade(&mut self, error: &Error) {
+		if self.degraded_since.is_none() {
+			error!(
+				"Database has become unavailable, continuing in degraded mode: {}",
+				error
+			);
+			let now = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or_default();
+			self.degraded_since = Some(now);
+		}
+		self.last_error = Some(error.to_string());
+	}
+
+	/// Queues the write, unless that would exceed the limits of the queue.
+	fn enqueue(&mut self, size: usize, write: QueuedWrite) -> bool {
+		if self.queue.len() >= WRITE_QUEUE_LENGTH_LIMIT
+			|| self.queued_bytes + size > WRITE_QUEUE_SIZE_LIMIT
+		{
+			self.dropped_writes += 1;
+			return false;
+		}
+		self.queue.push_back((size, write));
+		self.queued_bytes += size;
+		true
+	}
+}
+
+impl Database {
+	/// Checks whether the database responds again, and if so, writes all
+	/// queued writes to it.
+	/// Returns whether the database is healthy.
+	pub async fn check_health(&self) -> bool {
+		if let Err(e) = self.inner().ping().await {
+			self.observe_error(&e.into());
+			return false;
+		}
+		if !self.is_degraded() {
+			return true;
+		}
+
+		let this = self.clone();
+		let result = self
+			.perform(move |mut c| {
+				let _flushing = this.health.flushing.lock().unwrap();
+				loop {
+					let (size, write) = {
+						let mut state = this.health.state.lock().unwrap();
+						match state.queue.pop_front() {
+							Some(entry) => entry,
+							// Writes keep being queued until the queue is seen empty, so
+							// lift the degraded state under the same lock
+							None => {
+								if state.degraded_since.take().is_some() {
+									info!("Database is available again.");
+								}
+								state.last_error = None;
+								return Ok(());
+							}
+						}
+					};
+					if let Err(e) = write(&mut c) {
+						if e.is_unavailable() {
+							this.health
+								.state
+								.lock()
+								.unwrap()
+								.queue
+								.push_front((size, write));
+							return Err(e);
+						}
+						error!("Unable to write queued data to the database: {:?}", e);
+					}
+					this.health.state.lock().unwrap().queued_bytes -= size;
+				}
+			})
+			.await;
+		if let Err(e) = result {
+			self.observe_error(&e);
+			return false;
+		}
+		true
+	}
+
+	pub fn is_degraded(&self) -> bool { self.health.state.lock().unwrap().degraded_since.is_some() }
+
+	/// Marks the database as degraded if the error means that it is
+	/// unavailable.
+	pub fn observe_error(&self, error: &Error) {
+		if error.is_unavailable() {
+			self.health.state.lock().unwrap().degrade(error);
+		}
+	}
+
+	/// Performs a write that may be postponed. While the database is
+	/// unavailable, the write is queued in memory, to be performed once the
+	/// database has recovered. `size` is roughly the amount of memory the write
+	/// holds on to.
+	///
+	/// Returns `Error::Unavailable` only if the write had to be dropped because
+	/// the queue is full.
+	pub async fn perform_write(
+		&self, size: usize, mut write: impl Fn(&mut Connection) -> Result<()> + Send + 'static,
+	) -> Result<()> {
+		if !self.is_degraded() {
+			// The write is handed back, so that it can still be queued
+			let this = self.clone();
+			let result;
+			(result, write) = run_blocking(move || {
+				let result = this.connect_old().and_then(|mut c| write(&mut c));
+				(result, write)
+			})
+			.await?;
+			match result {
+				Err(e) if e.is_unavailable() => self.health.state.lock().unwrap().degrade(&e),
+				other => return other,
+			}
+		}
+
+		let mut state = self.health.state.lock().unwrap();
+		if state.enqueue(size, Box::new(write)) {
+			Ok(())
+		} else {
+			Err(Error::Unavailable)?
+		}
+	}
+
+	/// Remembers the outcome of the maintenance, so that it shows up in the
+	/// status of the database.
+	pub fn record_maintenance(&self, report: MaintenanceReport) {
+		self.health.state.lock().unwrap().last_maintenance = Some(report);
+	}
+
+	pub fn status(&self) -> DatabaseStatus {
+		let state = self.health.state.lock().unwrap();
+		DatabaseStatus {
+			is_degraded: state.degraded_since.is_some(),
+			degraded_since: state.degraded_since,
+			last_error: state.last_error.clone(),
+			queued_writes: state.queue.len(),
+			queued_bytes: state.queued_bytes,
+			dropped_writes: state.dropped_writes,
+			last_maintenance: state.last_maintenance.clone(),
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_write_queue() {
+		let locked = Error::SqliteError(rusqlite::Error::SqliteFailure(
+			rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_LOCKED),
+			None,
+		));
+		assert!(locked.is_unavailable());
+		assert!(!Error::UnexpectedState("test".into()).is_unavailable());
+
+		let mut state = HealthState::default();
+		state.degrade(&locked);
+		assert!(state.degraded_since.is_some());
+		assert!(state.enqueue(WRITE_QUEUE_SIZE_LIMIT - 1, Box::new(|_| Ok(()))));
+		assert!(state.enqueue(1, Box::new(|_| Ok(()))));
+		assert!(!state.enqueue(1, Box::new(|_| Ok(()))));
+		assert_eq!(state.queue.len(), 2);
+		assert_eq!(state.queued_bytes, WRITE_QUEUE_SIZE_LIMIT);
+		assert_eq!(state.dropped_writes, 1);
+	}
+}
@@ -0,0 +1,729 @@
+//! Merges the data of another Stonenet installation into our own database.
+//!
+//! This is meant for moving from one machine to another, like from a desktop to
+//! a home server. The other installation's database is never modified, it is
+//! copied and migrated to the latest version before anything is read from it.
+
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use log::*;
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set};
+use tempfile::TempDir;
+
+use super::{
+	keyring::{self, ACTOR_PRIVATE_KEY_SIZE},
+	local_user, search, Database, Error, PersistenceHandle, Result, Transaction,
+};
+use crate::{common::IdType, core::ActorAddress, entity::*, migration::Migrations};
+
+
+/// The file name of the database inside a Stonenet data directory.
+pub const DATABASE_FILE_NAME: &str = "db.sqlite";
+
+
+/// Describes what has been merged into our database by an import.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+	/// The identities that have been imported, and need to be announced on the
+	/// network again.
+	pub identities: Vec<ActorAddress>,
+	/// The actors that are now being followed because of the import.
+	pub follows: Vec<ActorAddress>,
+	pub actors: usize,
+	pub objects: usize,
+	pub files: usize,
+	pub blocks: usize,
+	/// The number of objects that were skipped because we already have a
+	/// different object with the same sequence number for that actor.
+	pub conflicts: usize,
+}
+
+
+impl Database {
+	/// Imports all identities, follows, objects, files & blocks from the
+	/// Stonenet installation found at the given path. The path may point to a
+	/// data directory, or directly to a (backed up) database file.
+	///
+	/// Whenever our own database already has the same data, it is left as it
+	/// is. Whenever it has conflicting data, our own data takes precedence.
+	pub async fn import_from(&self, path: &Path) -> Result<ImportSummary> {
+		let source_path = locate_database_file(path)?;
+
+		// Work on a copy, so that the source never gets migrated or otherwise
+		// touched. Its blocks are kept in a directory next to the database file,
+		// so they have to be copied along.
+		let copy_dir = TempDir::new().map_err(|e| {
+			Error::UnexpectedState(format!("unable to create temporary directory: {}", e))
+		})?;
+		let copy_path = copy_dir.path().join(DATABASE_FILE_NAME);
+		fs::copy(&source_path, &copy_path).map_err(|e| {
+			Error::UnexpectedState(format!(
+				"unable to copy {}: {}",
+				source_path.display(),
+				e
+			))
+		})?;
+		// With write-ahead logging, the latest changes may still be in the log
+		let source_wal = wal_path(&source_path);
+		if source_wal.is_file() {
+			fs::copy(&source_wal, wal_path(&copy_path))?;
+		}
+		let source_blocks = Database::block_store_dir(&source_path);
+		if source_blocks.is_dir() {
+			copy_dir_all(&source_blocks, &Database::block_store_dir(&copy_path))?;
+		}
+		let source = Database::load(copy_path).await?;
+		Migrations::load().run(&source).await?;
+
+		let tx = self.transaction().await?;
+		let summary = tx.import(&source).await?;
+		tx.commit().await?;
+		self.update_search_index().await?;
+		Ok(summary)
+	}
+}
+
+impl Transaction {
+	async fn import(&self, source: &Database) -> Result<ImportSummary> {
+		let mut summary = ImportSummary::default();
+
+		let actor_ids = self.import_actors(source, &mut summary).await?;
+		self.import_identities(source, &actor_ids, &mut summary)
+			.await?;
+		self.import_follows(source, &actor_ids, &mut summary)
+			.await?;
+		self.import_blocks(source, &mut summary).await?;
+		self.import_files(source, &mut summary).await?;
+		self.import_objects(source, &actor_ids, &mut summary)
+			.await?;
+		Ok(summary)
+	}
+
+	/// Imports all actors, and returns a map that translates their actor IDs
+	/// in the source database to the ones in our database.
+	async fn import_actors(
+		&self, source: &Database, summary: &mut ImportSummary,
+	) -> Result<HashMap<i64, i64>> {
+		let mut actor_ids = HashMap::new();
+		for record in actor::Entity::find().all(source.inner()).await? {
+			let local_id = if let Some(local) = actor::Entity::find()
+				.filter(actor::Column::Address.eq(&record.address))
+				.one(self.inner())
+				.await?
+			{
+				local.id
+			} else {
+				let model = actor::ActiveModel {
+					id: NotSet,
+					address: Set(record.address),
+					public_key: Set(record.public_key),
+					first_object: Set(record.first_object),
+					r#type: Set(record.r#type),
+				};
+				summary.actors += 1;
+				actor::Entity::insert(model)
+					.exec(self.inner())
+					.await?
+					.last_insert_id
+			};
+			actor_ids.insert(record.id, local_id);
+		}
+		Ok(actor_ids)
+	}
+
+	async fn import_blocks(&self, source: &Database, summary: &mut ImportSummary) -> Result<()> {
+		let hashes = block::Entity::find()
+			.select_only()
+			.column(block::Column::Hash)
+			.into_tuple::<IdType>()
+			.all(source.inner())
+			.await?;
+		for hash in hashes {
+			if self.has_block(&hash).await? {
+				continue;
+			}
+
+			// Load the blocks one by one, as they may be large. They may also have been
+			// archived by the other installation.
+			if let Some(data) = source.load_block(&hash).await? {
+				self.block_store().store(&hash, &data)?;
+				let model = block::ActiveModel {
+					id: NotSet,
+					hash: Set(hash),
+					size: Set(data.len() as _),
+					data: Set(Vec::new()),
+					last_access: Set(Utc::now().timestamp_millis()),
+				};
+				block::Entity::insert(model).exec(self.inner()).await?;
+				summary.blocks += 1;
+			}
+		}
+		Ok(())
+	}
+
+	async fn import_files(&self, source: &Database, summary: &mut ImportSummary) -> Result<()> {
+		for record in file::Entity::find().all(source.inner()).await? {
+			if self.has_file(&record.hash).await? {
+				continue;
+			}
+
+			let model = file::ActiveModel {
+				id: NotSet,
+				hash: Set(record.hash),
+				compression_type: Set(record.compression_type),
+				mime_type: Set(record.mime_type),
+				block_count: Set(record.block_count),
+				plain_hash: Set(record.plain_hash),
+			};
+			let file_id = file::Entity::insert(model)
+				.exec(self.inner())
+				.await?
+				.last_insert_id;
+
+			let file_blocks = file_block::Entity::find()
+				.filter(file_block::Column::FileId.eq(record.id))
+				.order_by_asc(file_block::Column::Sequence)
+				.all(source.inner())
+				.await?;
+			for file_block in file_blocks {
+				let model = file_block::ActiveModel {
+					id: NotSet,
+					file_id: Set(file_id),
+					block_hash: Set(file_block.block_hash),
+					sequence: Set(file_block.sequence),
+				};
+				file_block::Entity::insert(model)
+					.exec(self.inner())
+					.await?;
+			}
+			summary.files += 1;
+		}
+		Ok(())
+	}
+
+	async fn import_follows(
+		&self, source: &Database, actor_ids: &HashMap<i64, i64>, summary: &mut ImportSummary,
+	) -> Result<()> {
+		for record in following::Entity::find().all(source.inner()).await? {
+			let actor_id = translate_actor_id(actor_ids, record.actor_id)?;
+			if following::Entity::find_by_id(actor_id)
+				.one(self.inner())
+				.await?
+				.is_some()
+			{
+				continue;
+			}
+
+			let model = following::ActiveModel {
+				actor_id: Set(actor_id),
+			};
+			following::Entity::insert(model).exec(self.inner()).await?;
+			summary.follows.push(self.load_actor_address(actor_id).await?);
+		}
+		Ok(())
+	}
+
+	async fn import_identities(
+		&self, source: &Database, actor_ids: &HashMap<i64, i64>, summary: &mut ImportSummary,
+	) -> Result<()> {
+		for record in identity::Entity::find().all(source.inner()).await? {
+			let actor_id = translate_actor_id(actor_ids, record.actor_id)?;
+			if identity::Entity::find()
+				.filter(identity::Column::ActorId.eq(actor_id))
+				.one(self.inner())
+				.await?
+				.is_some()
+			{
+				continue;
+			}
+
+			// The keys of the other installation can only be used with its own passphrase
+			let is_external = keyring::is_external_key(&record.private_key);
+			if !is_external && record.private_key.len() != ACTOR_PRIVATE_KEY_SIZE {
+				Err(Error::UnexpectedState(
+					"the private keys to import are encrypted with a passphrase".to_string(),
+				))?;
+			}
+			let label = self.find_free_identity_label(&record.label).await?;
+			if label != record.label {
+				warn!(
+					"Identity label \"{}\" is already in use, importing it as \"{}\".",
+					&record.label, &label
+				);
+			}
+			let model = identity::ActiveModel {
+				label: Set(label),
+				actor_id: Set(actor_id),
+				private_key: Set(if is_external {
+					record.private_key
+				} else {
+					self.keyring().encrypt(&record.private_key)?
+				}),
+				is_private: Set(record.is_private),
+				seed: Set(record.seed.map(|s| self.keyring().encrypt(&s)).transpose()?),
+				is_active: Set(false),
+				delegation: Set(record.delegation),
+				user_id: Set(local_user::current_user()),
+			};
+			identity::Entity::insert(model).exec(self.inner()).await?;
+			summary
+				.identities
+				.push(self.load_actor_address(actor_id).await?);
+		}
+		Ok(())
+	}
+
+	async fn import_objects(
+		&self, source: &Database, actor_ids: &HashMap<i64, i64>, summary: &mut ImportSummary,
+	) -> Result<()> {
+		let objects = object::Entity::find()
+			.order_by_asc(object::Column::ActorId)
+			.order_by_asc(object::Column::Sequence)
+			.all(source.inner())
+			.await?;
+		for record in objects {
+			let actor_id = translate_actor_id(actor_ids, record.actor_id)?;
+			if let Some(local) = object::Entity::find()
+				.filter(object::Column::ActorId.eq(actor_id))
+				.filter(object::Column::Sequence.eq(record.sequence))
+				.one(self.inner())
+				.await?
+			{
+				if local.hash != record.hash {
+					warn!(
+						"Object {} conflicts with our own object {} at sequence {}, keeping ours.",
+						&record.hash, &local.hash, record.sequence
+					);
+					summary.conflicts += 1;
+				} else if record.verified_from_start && !local.verified_from_start {
+					let mut model: object::ActiveModel = local.into();
+					model.verified_from_start = Set(true);
+					model.update(self.inner()).await?;
+				}
+				continue;
+			}
+
+			let source_object_id = record.id;
+			let model = object::ActiveModel {
+				id: NotSet,
+				actor_id: Set(actor_id),
+				hash: Set(record.hash),
+				sequence: Set(record.sequence),
+				previous_hash: Set(record.previous_hash),
+				created: Set(record.created),
+				found: Set(record.found),
+				r#type: Set(record.r#type),
+				signature: Set(record.signature),
+				verified_from_start: Set(record.verified_from_start),
+				published_on_fediverse: Set(record.published_on_fediverse),
+				delegation: Set(record.delegation),
+			};
+			let object_id = object::Entity::insert(model)
+				.exec(self.inner())
+				.await?
+				.last_insert_id;
+			self.import_object_payload(source, source_object_id, object_id)
+				.await?;
+			summary.objects += 1;
+		}
+		Ok(())
+	}
+
+	async fn import_object_payload(
+		&self, source: &Database, source_object_id: i64, object_id: i64,
+	) -> Result<()> {
+		if let Some(record) = post_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = post_object::ActiveModel {
+				object_id: Set(object_id),
+				in_reply_to_actor_address: Set(record.in_reply_to_actor_address),
+				in_reply_to_object_hash: Set(record.in_reply_to_object_hash),
+				file_count: Set(record.file_count),
+			};
+			post_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+			search::queue_object(self, object_id).await?;
+
+			let tags = post_tag::Entity::find()
+				.filter(post_tag::Column::ObjectId.eq(source_object_id))
+				.all(source.inner())
+				.await?;
+			for tag in tags {
+				let model = post_tag::ActiveModel {
+					id: NotSet,
+					object_id: Set(object_id),
+					tag: Set(tag.tag),
+				};
+				post_tag::Entity::insert(model).exec(self.inner()).await?;
+			}
+			self.import_post_files(source, source_object_id, object_id)
+				.await?;
+		}
+
+		if let Some(record) = profile_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = profile_object::ActiveModel {
+				object_id: Set(object_id),
+				name: Set(record.name),
+				avatar_file_hash: Set(record.avatar_file_hash),
+				wallpaper_file_hash: Set(record.wallpaper_file_hash),
+				description_file_hash: Set(record.description_file_hash),
+				followers_only: Set(record.followers_only),
+			};
+			profile_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+			search::queue_object(self, object_id).await?;
+		}
+
+		if let Some(record) = share_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = share_object::ActiveModel {
+				object_id: Set(object_id),
+				actor_address: Set(record.actor_address),
+				object_hash: Set(record.object_hash),
+			};
+			share_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+		}
+
+		if let Some(record) = key_rotation_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = key_rotation_object::ActiveModel {
+				object_id: Set(object_id),
+				new_public_key: Set(record.new_public_key),
+				proof: Set(record.proof),
+			};
+			key_rotation_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+		}
+
+		if let Some(record) = edit_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = edit_object::ActiveModel {
+				object_id: Set(object_id),
+				edited_object_hash: Set(record.edited_object_hash),
+				file_count: Set(record.file_count),
+			};
+			edit_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+			self.import_post_files(source, source_object_id, object_id)
+				.await?;
+			search::queue_object(self, object_id).await?;
+		}
+
+		if let Some(record) = delete_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = delete_object::ActiveModel {
+				object_id: Set(object_id),
+				deleted_object_hash: Set(record.deleted_object_hash),
+			};
+			delete_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+		}
+
+		if let Some(record) = reaction_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = reaction_object::ActiveModel {
+				object_id: Set(object_id),
+				actor_address: Set(record.actor_address),
+				object_hash: Set(record.object_hash),
+				emoji: Set(record.emoji),
+				notified: Set(record.notified),
+			};
+			reaction_object::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+		}
+
+		if let Some(record) = poll_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = poll_object::ActiveModel {
+				object_id: Set(object_id),
+				question: Set(record.question),
+				multiple_choice: Set(record.multiple_choice),
+				closes: Set(record.closes),
+			};
+			poll_object::Entity::insert(model).exec(self.inner()).await?;
+			let options = poll_option::Entity::find()
+				.filter(poll_option::Column::ObjectId.eq(source_object_id))
+				.order_by_asc(poll_option::Column::Sequence)
+				.all(source.inner())
+				.await?;
+			for option in options {
+				let model = poll_option::ActiveModel {
+					id: NotSet,
+					object_id: Set(object_id),
+					sequence: Set(option.sequence),
+					text: Set(option.text),
+				};
+				poll_option::Entity::insert(model).exec(self.inner()).await?;
+			}
+		}
+
+		if let Some(record) = vote_object::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+		{
+			let model = vote_object::ActiveModel {
+				object_id: Set(object_id),
+				actor_address: Set(record.actor_address),
+				object_hash: Set(record.object_hash),
+				choices: Set(record.choices),
+			};
+			vote_object::Entity::insert(model).exec(self.inner()).await?;
+		}
+
+		let followers = follower_list_entry::Entity::find()
+			.filter(follower_list_entry::Column::ObjectId.eq(source_object_id))
+			.all(source.inner())
+			.await?;
+		for record in followers {
+			let model = follower_list_entry::ActiveModel {
+				id: NotSet,
+				object_id: Set(object_id),
+				sequence: Set(record.sequence),
+				actor_address: Set(record.actor_address),
+				node_address: Set(record.node_address),
+			};
+			follower_list_entry::Entity::insert(model)
+				.exec(self.inner())
+				.await?;
+		}
+
+		if tombstone::Entity::find_by_id(source_object_id)
+			.one(source.inner())
+			.await?
+			.is_some()
+		{
+			let model = tombstone::ActiveModel {
+				object_id: Set(object_id),
+			};
+			tombstone::Entity::insert(model).exec(self.inner()).await?;
+		}
+		Ok(())
+	}
+
+	/// Copies the files of a post, or of an edit of one.
+	async fn import_post_files(
+		&self, source: &Database, source_object_id: i64, object_id: i64,
+	) -> Result<()> {
+		let files = post_file::Entity::find()
+			.filter(post_file::Column::ObjectId.eq(source_object_id))
+			.order_by_asc(post_file::Column::Sequence)
+			.all(source.inner())
+			.await?;
+		for file in files {
+			let model = post_file::ActiveModel {
+				id: NotSet,
+				object_id: Set(object_id),
+				hash: Set(file.hash),
+				sequence: Set(file.sequence),
+			};
+			post_file::Entity::insert(model).exec(self.inner()).await?;
+		}
+		Ok(())
+	}
+
+	/// Finds a label that isn't used by any identity yet, based on the given
+	/// label.
+	async fn find_free_identity_label(&self, label: &str) -> Result<String> {
+		let mut candidate = label.to_string();
+		let mut i = 1;
+		while identity::Entity::find_by_id(candidate.clone())
+			.one(self.inner())
+			.await?
+			.is_some()
+		{
+			i += 1;
+			candidate = format!("{} ({})", label, i);
+		}
+		Ok(candidate)
+	}
+
+	async fn load_actor_address(&self, actor_id: i64) -> Result<ActorAddress> {
+		if let Some(record) = actor::Entity::find_by_id(actor_id)
+			.one(self.inner())
+			.await?
+		{
+			Ok(record.address)
+		} else {
+			Err(Error::UnexpectedState(format!(
+				"actor {} disappeared during import",
+				actor_id
+			)))?
+		}
+	}
+}
+
+
+/// Copies the directory with everything in it.
+fn copy_dir_all(from: &Path, to: &Path) -> Result<()> {
+	fs::create_dir_all(to)?;
+	for entry in fs::read_dir(from)? {
+		let entry = entry?;
+		let target = to.join(entry.file_name());
+		if entry.file_type()?.is_dir() {
+			copy_dir_all(&entry.path(), &target)?;
+		} else {
+			fs::copy(entry.path(), target)?;
+		}
+	}
+	Ok(())
+}
+
+/// The file that SQLite keeps the write-ahead log of the database in.
+fn wal_path(path: &Path) -> PathBuf {
+	let mut wal_path = path.as_os_str().to_owned();
+	wal_path.push("-wal");
+	PathBuf::from(wal_path)
+}
+
+/// Finds the database file for the given path, which can either be a Stonenet
+/// data directory, or the database file itself.
+pub fn locate_database_file(path: &Path) -> Result<PathBuf> {
+	let file_path = if path.is_dir() {
+		path.join(DATABASE_FILE_NAME)
+	} else {
+		path.to_owned()
+	};
+	if !file_path.is_file() {
+		Err(Error::UnexpectedState(format!(
+			"no Stonenet database found at {}",
+			file_path.display()
+		)))?;
+	}
+	Ok(file_path)
+}
+
+fn translate_actor_id(actor_ids: &HashMap<i64, i64>, actor_id: i64) -> Result<i64> {
+	if let Some(id) = actor_ids.get(&actor_id) {
+		Ok(*id)
+	} else {
+		Err(Error::UnexpectedState(format!(
+			"source database references unknown actor {}",
+			actor_id
+		)))?
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+
+	use super::*;
+	use crate::{core::FileData, identity::ActorPrivateKeyV1, test};
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_import() {
+		let source = test::load_database("import-source").await;
+		let target = test::load_database("import-target").await;
+		let mut rng = test::initialize_rng();
+
+		let mut file_data = FileData {
+			mime_type: "image/png".into(),
+			data: vec![0u8; 1000],
+		};
+		rng.fill_bytes(&mut file_data.data);
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let first_object = IdType::random(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+
+		let tx = source.transaction().await.unwrap();
+		let (_, file_hash, _) = tx.create_file(&file_data).await.unwrap();
+		let actor_id = tx
+			.create_identity(
+				"test",
+				&address,
+				&private_key.public(),
+				&private_key,
+				false,
+				&first_object,
+				None,
+			)
+			.await
+			.unwrap();
+		tx.store_profile(
+			actor_id,
+			0,
+			&first_object,
+			&IdType::default(),
+			&private_key.sign(b"test"),
+			true,
+			"Test",
+			Some(file_hash.clone()),
+			None,
+			None,
+			false,
+		)
+		.await
+		.unwrap();
+		tx.commit().await.unwrap();
+
+		// Make sure the label conflict gets resolved
+		let tx = target.transaction().await.unwrap();
+		let other_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		tx.create_identity(
+			"test",
+			&ActorAddress::V1(IdType::random(&mut rng)),
+			&other_key.public(),
+			&other_key,
+			false,
+			&IdType::random(&mut rng),
+			None,
+		)
+		.await
+		.unwrap();
+		tx.commit().await.unwrap();
+
+		let summary = target.import_from(&source.path).await.unwrap();
+		assert_eq!(summary.identities, vec![address.clone()]);
+		assert_eq!(summary.objects, 1);
+		assert_eq!(summary.files, 1);
+		assert_eq!(summary.conflicts, 0);
+
+		let profile = target.load_profile(&address).await.unwrap().unwrap();
+		assert_eq!(profile.name.as_str(), "Test");
+		let fetched = target.load_file_data(&file_hash).await.unwrap().unwrap();
+		assert_eq!(fetched.data, file_data.data, "corrupted file data");
+		let record = identity::Entity::find_by_id("test (2)")
+			.one(target.inner())
+			.await
+			.unwrap();
+		assert!(record.is_some(), "conflicting label not resolved");
+
+		// Importing the same installation twice should not change anything
+		let summary = target.import_from(&source.path).await.unwrap();
+		assert_eq!(summary.identities.len(), 0);
+		assert_eq!(summary.objects, 0);
+		assert_eq!(summary.files, 0);
+		assert_eq!(summary.blocks, 0);
+	}
+}
@@ -0,0 +1,214 @@
+//! A journal of the actions that the user has taken on this node.
+//!
+//! Everything that changes what this node publishes or who it talks to is
+//! recorded, so that the owner of the node can see what it has been doing on
+//! their behalf. The journal is append-only: entries are never removed, and
+//! the actions that can be reversed are undone by taking the opposite action,
+//! which is recorded as well. Every local user only sees the actions that they
+//! took themselves.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set};
+
+use super::{local_user, Database, PersistenceHandle, Result};
+use crate::{common::*, entity::journal_entry};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalAction {
+	CreatedIdentity     = 0,
+	PublishedPost       = 1,
+	PublishedShare      = 2,
+	UpdatedProfile      = 3,
+	Followed            = 4,
+	Unfollowed          = 5,
+	Banned              = 6,
+	Unbanned            = 7,
+	RestoredIdentity    = 8,
+	RotatedKey          = 9,
+	IssuedDeviceKey     = 10,
+	AddedDeviceIdentity = 11,
+	EditedPost          = 12,
+	DeletedPost         = 13,
+	Reacted             = 14,
+	PublishedPoll       = 15,
+	Voted               = 16,
+	RequestedFollow     = 17,
+	AcceptedFollower    = 18,
+	RejectedFollower    = 19,
+	MutedActor          = 20,
+	BlockedActor        = 21,
+	UnblockedActor      = 22,
+}
+
+
+impl JournalAction {
+	pub fn from_u8(value: u8) -> Option<Self> {
+		Some(match value {
+			0 => Self::CreatedIdentity,
+			1 => Self::PublishedPost,
+			2 => Self::PublishedShare,
+			3 => Self::UpdatedProfile,
+			4 => Self::Followed,
+			5 => Self::Unfollowed,
+			6 => Self::Banned,
+			7 => Self::Unbanned,
+			8 => Self::RestoredIdentity,
+			9 => Self::RotatedKey,
+			10 => Self::IssuedDeviceKey,
+			11 => Self::AddedDeviceIdentity,
+			12 => Self::EditedPost,
+			13 => Self::DeletedPost,
+			14 => Self::Reacted,
+			15 => Self::PublishedPoll,
+			16 => Self::Voted,
+			17 => Self::RequestedFollow,
+			18 => Self::AcceptedFollower,
+			19 => Self::RejectedFollower,
+			20 => Self::MutedActor,
+			21 => Self::BlockedActor,
+			22 => Self::UnblockedActor,
+			_ => return None,
+		})
+	}
+
+	/// Whether the action can be reversed. Published objects have been signed
+	/// and spread over the network already, so they can't be taken back.
+	pub fn is_undoable(&self) -> bool {
+		match self {
+			Self::Followed
+			| Self::Unfollowed
+			| Self::Banned
+			| Self::Unbanned
+			| Self::MutedActor
+			| Self::BlockedActor => true,
+			_ => false,
+		}
+	}
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Self::CreatedIdentity => "Created identity",
+			Self::PublishedPost => "Published post",
+			Self::PublishedShare => "Shared post",
+			Self::UpdatedProfile => "Updated profile",
+			Self::Followed => "Followed",
+			Self::Unfollowed => "Unfollowed",
+			Self::Banned => "Banned",
+			Self::Unbanned => "Lifted ban",
+			Self::RestoredIdentity => "Restored identity",
+			Self::RotatedKey => "Rotated key",
+			Self::IssuedDeviceKey => "Issued device key",
+			Self::AddedDeviceIdentity => "Added identity from another device",
+			Self::EditedPost => "Edited post",
+			Self::DeletedPost => "Deleted post",
+			Self::Reacted => "Reacted to post",
+			Self::PublishedPoll => "Published poll",
+			Self::Voted => "Voted in poll",
+			Self::RequestedFollow => "Asked to follow",
+			Self::AcceptedFollower => "Accepted follower",
+			Self::RejectedFollower => "Rejected follower",
+			Self::MutedActor => "Muted actor",
+			Self::BlockedActor => "Blocked actor",
+			Self::UnblockedActor => "Unmuted or unblocked actor",
+		}
+	}
+}
+
+/// Adds an entry to the journal. Pass a transaction to have the entry only be
+/// recorded if the action itself is committed.
+pub async fn record(
+	db: &impl PersistenceHandle, action: JournalAction, subject: &str,
+	object_hash: Option<&IdType>, detail: Option<&str>,
+) -> Result<i64> {
+	let model = journal_entry::ActiveModel {
+		id: NotSet,
+		action: Set(action as u8),
+		subject: Set(subject.to_string()),
+		object_hash: Set(object_hash.cloned()),
+		detail: Set(detail.map(|d| d.to_string())),
+		created: Set(current_timestamp() as i64),
+		undone: Set(None),
+		user_id: Set(local_user::current_user()),
+	};
+	let result = journal_entry::Entity::insert(model)
+		.exec(db.inner())
+		.await?;
+	Ok(result.last_insert_id)
+}
+
+
+impl Database {
+	pub async fn find_journal_entry(&self, id: i64) -> Result<Option<journal_entry::Model>> {
+		Ok(journal_entry::Entity::find_by_id(id)
+			.filter(local_user::user_condition(journal_entry::Column::UserId))
+			.one(self.inner())
+			.await?)
+	}
+
+	/// Loads the journal, the latest entries first.
+	pub async fn load_journal(
+		&self, limit: u64, offset: u64,
+	) -> Result<Vec<journal_entry::Model>> {
+		Ok(journal_entry::Entity::find()
+			.filter(local_user::user_condition(journal_entry::Column::UserId))
+			.order_by_desc(journal_entry::Column::Id)
+			.limit(limit)
+			.offset(offset)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Marks the entry as undone.
+	/// Returns false if it doesn't exist or has been undone already.
+	pub async fn mark_journal_entry_undone(&self, id: i64) -> Result<bool> {
+		let result = journal_entry::Entity::update_many()
+			.col_expr(
+				journal_entry::Column::Undone,
+				Expr::value(current_timestamp() as i64),
+			)
+			.filter(journal_entry::Column::Id.eq(id))
+			.filter(journal_entry::Column::Undone.is_null())
+			.filter(local_user::user_condition(journal_entry::Column::UserId))
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected > 0)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_journal() {
+		let db = test::load_database("db").await;
+		let mut rng = test::initialize_rng();
+		let hash = IdType::random(&mut rng);
+
+		let tx = db.transaction().await.unwrap();
+		let first = record(&tx, JournalAction::PublishedPost, "actor", Some(&hash), None)
+			.await
+			.unwrap();
+		tx.commit().await.unwrap();
+		let second = record(&db, JournalAction::Followed, "other", None, None)
+			.await
+			.unwrap();
+
+		let entries = db.load_journal(10, 0).await.unwrap();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].id, second);
+		assert_eq!(entries[1].id, first);
+		assert_eq!(entries[1].object_hash, Some(hash));
+		let action = JournalAction::from_u8(entries[0].action);
+		assert_eq!(action, Some(JournalAction::Followed));
+
+		// An entry can only be undone once
+		assert!(db.mark_journal_entry_undone(second).await.unwrap());
+		assert!(!db.mark_journal_entry_undone(second).await.unwrap());
+		let entry = db.find_journal_entry(second).await.unwrap().unwrap();
+		assert!(entry.undone.is_some());
+		assert!(db.find_journal_entry(first).await.unwrap().unwrap().undone.is_none());
+	}
+}
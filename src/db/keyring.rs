@@ -0,0 +1,389 @@
+//! Encrypts the private keys that are stored in the database with a passphrase.
+//!
+//! Key encryption is enabled by starting the daemon with a passphrase for the
+//! first time. From then on, the private keys of the node and of our own
+//! identities are only stored encrypted, with a key that is derived from the
+//! passphrase with Argon2, and the daemon needs the passphrase again to start.
+//!
+//! The derived key is kept in memory for as long as the private keys of our
+//! identities are being used. It can be forgotten again after a while of not
+//! being used, after which the keys have to be unlocked through the user
+//! interface before anything can be published again. The private key of the
+//! node itself stays in memory for as long as the node is running, because it
+//! is needed for every connection.
+//!
+//! An encrypted key is stored as a nonce, followed by the key sealed with
+//! XChaCha20-Poly1305. Because that is longer than any unencrypted key, the
+//! keys that were stored before encryption was enabled can still be told
+//! apart from the others.
+//!
+//! The private key of an identity can also be held by an external signer
+//! instead. Only a reference to it is stored then, which is the public key with
+//! a prefix, and isn't encrypted because it isn't secret.
+
+use std::{
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex as StdMutex,
+	},
+	time::{Duration, Instant},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+	aead::{Aead, KeyInit},
+	XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use sea_orm::{prelude::*, NotSet, Set};
+use zeroize::Zeroizing;
+
+use super::{encryption::DatabaseKey, Database, Error, PersistenceHandle, Result};
+use crate::{
+	common::IdType,
+	core::NodeAddress,
+	entity::*,
+	identity::{
+		agent::AgentSigner, ActorPrivateKeyV1, ActorPublicKeyV1, NodePrivateKey, Signer,
+		SignerError,
+	},
+	util,
+};
+
+
+pub const ACTOR_PRIVATE_KEY_SIZE: usize = 57;
+pub const NODE_PRIVATE_KEY_SIZE: usize = 32;
+pub const SEED_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+/// The value that is encrypted to check the passphrase with.
+const VERIFIER_PLAINTEXT: &[u8] = b"stonenet private keys";
+/// What a private key that is held by the external signer is stored as,
+/// followed by the public key.
+const EXTERNAL_KEY_PREFIX: &[u8] = b"external signer:";
+
+
+/// Holds the key that the private keys are encrypted with, while they are
+/// unlocked.
+#[derive(Default)]
+pub struct Keyring {
+	is_enabled: AtomicBool,
+	unlocked: StdMutex<Option<UnlockedKey>>,
+	/// The socket of the external signer, if one is configured.
+	external_signer: StdMutex<Option<PathBuf>>,
+	/// The key that the whole database is encrypted with, if it is.
+	database_key: Option<DatabaseKey>,
+}
+
+struct UnlockedKey {
+	key: Zeroizing<[u8; 32]>,
+	last_used: Instant,
+}
+
+
+impl Keyring {
+	pub fn new(database_key: Option<DatabaseKey>) -> Self {
+		Self {
+			database_key,
+			..Self::default()
+		}
+	}
+
+	/// The key that every connection to the database has to be opened with.
+	pub fn database_key(&self) -> Option<&DatabaseKey> { self.database_key.as_ref() }
+
+	/// Whether the private keys are stored encrypted.
+	pub fn is_enabled(&self) -> bool { self.is_enabled.load(Ordering::Relaxed) }
+
+	/// Whether the private keys are encrypted and can't be used at the moment.
+	pub fn is_locked(&self) -> bool {
+		self.is_enabled() && self.unlocked.lock().unwrap().is_none()
+	}
+
+	/// Forgets the key, so that the private keys can't be used until they are
+	/// unlocked again.
+	pub fn lock(&self) { *self.unlocked.lock().unwrap() = None; }
+
+	/// Forgets the key if it hasn't been used for the given time. Returns
+	/// whether that happened.
+	pub fn lock_if_idle(&self, timeout: Duration) -> bool {
+		let mut unlocked = self.unlocked.lock().unwrap();
+		match &*unlocked {
+			Some(u) if u.last_used.elapsed() >= timeout => {
+				*unlocked = None;
+				true
+			}
+			_ => false,
+		}
+	}
+
+	fn unlock(&self, key: Zeroizing<[u8; 32]>) {
+		*self.unlocked.lock().unwrap() = Some(UnlockedKey {
+			key,
+			last_used: Instant::now(),
+		});
+	}
+
+	fn use_key(&self) -> Result<Zeroizing<[u8; 32]>> {
+		let mut unlocked = self.unlocked.lock().unwrap();
+		match &mut *unlocked {
+			Some(u) => {
+				u.last_used = Instant::now();
+				Ok(u.key.clone())
+			}
+			None => Err(Error::KeysLocked)?,
+		}
+	}
+
+	/// Returns the private key in the form that it is to be stored in.
+	pub fn encrypt(&self, private_key: &[u8]) -> Result<Vec<u8>> {
+		if !self.is_enabled() {
+			return Ok(private_key.to_vec());
+		}
+		Ok(seal(&self.use_key()?, private_key))
+	}
+
+	/// Returns the private key from the form that it is stored in.
+	pub fn decrypt(&self, stored: &[u8], key_size: usize) -> Result<Zeroizing<Vec<u8>>> {
+		// Keys that were stored before encryption was enabled have their plain size
+		if stored.len() == key_size {
+			return Ok(Zeroizing::new(stored.to_vec()));
+		}
+		match open(&self.use_key()?, stored) {
+			Some(private_key) if private_key.len() == key_size => Ok(private_key),
+			_ => Err(Error::InvalidPrivateKey(stored.len()))?,
+		}
+	}
+
+	pub fn set_external_signer(&self, socket_path: Option<PathBuf>) {
+		*self.external_signer.lock().unwrap() = socket_path;
+	}
+
+	/// Returns the signer for a key that is held by the external signer.
+	pub fn external_signer(&self, public_key: &ActorPublicKeyV1) -> Result<AgentSigner> {
+		match &*self.external_signer.lock().unwrap() {
+			Some(socket_path) => Ok(AgentSigner::new(socket_path.clone(), public_key.clone())),
+			None => Err(Error::Signer(SignerError::NotConfigured))?,
+		}
+	}
+
+	/// Lists the keys that the external signer holds.
+	pub fn list_external_keys(&self) -> Result<Vec<ActorPublicKeyV1>> {
+		let socket_path = match &*self.external_signer.lock().unwrap() {
+			Some(p) => p.clone(),
+			None => Err(Error::Signer(SignerError::NotConfigured))?,
+		};
+		Ok(util::block_in_place(|| AgentSigner::list_keys(&socket_path))?)
+	}
+
+	/// Returns the key of the signer in the form that it is to be stored in.
+	pub fn store_signer(&self, signer: &dyn Signer) -> Result<Vec<u8>> {
+		match signer.private_key() {
+			Some(private_key) => self.encrypt(private_key.as_bytes()),
+			None => {
+				let mut stored = EXTERNAL_KEY_PREFIX.to_vec();
+				stored.extend(signer.public().to_bytes());
+				Ok(stored)
+			}
+		}
+	}
+
+	/// Returns the signer for the private key, from the form that it is stored
+	/// in.
+	pub fn load_signer(&self, stored: &[u8]) -> Result<Box<dyn Signer>> {
+		match stored.strip_prefix(EXTERNAL_KEY_PREFIX) {
+			Some(buffer) if buffer.len() == 57 => {
+				let public_key = ActorPublicKeyV1::from_bytes(*array_ref![buffer, 0, 57]).unwrap();
+				Ok(Box::new(self.external_signer(&public_key)?))
+			}
+			Some(_) => Err(Error::InvalidPrivateKey(stored.len()))?,
+			None => Ok(Box::new(self.decrypt_actor_key(stored)?)),
+		}
+	}
+
+	pub fn decrypt_actor_key(&self, stored: &[u8]) -> Result<ActorPrivateKeyV1> {
+		let buffer = self.decrypt(stored, ACTOR_PRIVATE_KEY_SIZE)?;
+		Ok(ActorPrivateKeyV1::from_bytes(*array_ref![
+			buffer,
+			0,
+			ACTOR_PRIVATE_KEY_SIZE
+		]))
+	}
+}
+
+/// Whether the stored private key is only a reference to a key of the external
+/// signer.
+pub fn is_external_key(stored: &[u8]) -> bool { stored.starts_with(EXTERNAL_KEY_PREFIX) }
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Zeroizing<[u8; 32]> {
+	let mut key = Zeroizing::new([0u8; 32]);
+	Argon2::default()
+		.hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+		.expect("invalid key derivation parameters");
+	key
+}
+
+fn seal(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+	let cipher = XChaCha20Poly1305::new(key.into());
+	let mut nonce = [0u8; NONCE_SIZE];
+	OsRng.fill_bytes(&mut nonce);
+	let ciphertext = cipher
+		.encrypt(XNonce::from_slice(&nonce), plaintext)
+		.expect("unable to encrypt private key");
+
+	let mut sealed = nonce.to_vec();
+	sealed.extend(ciphertext);
+	sealed
+}
+
+fn open(key: &[u8; 32], sealed: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+	if sealed.len() < NONCE_SIZE {
+		return None;
+	}
+	let cipher = XChaCha20Poly1305::new(key.into());
+	cipher
+		.decrypt(XNonce::from_slice(&sealed[..NONCE_SIZE]), &sealed[NONCE_SIZE..])
+		.ok()
+		.map(Zeroizing::new)
+}
+
+impl Database {
+	pub fn keyring(&self) -> &Keyring { &self.keyring }
+
+	/// Checks whether the private keys are stored encrypted. Needs to be done
+	/// before any private key is stored or loaded.
+	pub async fn load_key_encryption(&self) -> Result<bool> {
+		let is_enabled = key_encryption::Entity::find()
+			.one(self.inner())
+			.await?
+			.is_some();
+		self.keyring.is_enabled.store(is_enabled, Ordering::Relaxed);
+		Ok(is_enabled)
+	}
+
+	/// Unlocks the private keys with the passphrase. Returns false if the
+	/// passphrase is wrong.
+	pub async fn unlock_keys(&self, passphrase: &str) -> Result<bool> {
+		let record = match key_encryption::Entity::find().one(self.inner()).await? {
+			Some(r) => r,
+			None => return Ok(true),
+		};
+
+		let key = util::block_in_place(|| derive_key(passphrase, &record.salt));
+		match open(&key, &record.verifier) {
+			Some(plaintext) if &**plaintext == VERIFIER_PLAINTEXT => {
+				self.keyring.unlock(key);
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+
+	/// Encrypts all private keys with the passphrase, and keeps them unlocked.
+	pub async fn enable_key_encryption(&self, passphrase: &str) -> Result<()> {
+		let mut salt = [0u8; SALT_SIZE];
+		OsRng.fill_bytes(&mut salt);
+		let key = util::block_in_place(|| derive_key(passphrase, &salt));
+
+		let tx = self.transaction().await?;
+		for record in node_identity::Entity::find().all(tx.inner()).await? {
+			let private_key = self.keyring.decrypt(&record.private_key, NODE_PRIVATE_KEY_SIZE)?;
+			let mut model: node_identity::ActiveModel = record.into();
+			model.private_key = Set(seal(&key, &private_key));
+			model.update(tx.inner()).await?;
+		}
+		for record in identity::Entity::find().all(tx.inner()).await? {
+			if is_external_key(&record.private_key) {
+				continue;
+			}
+			let private_key = self
+				.keyring
+				.decrypt(&record.private_key, ACTOR_PRIVATE_KEY_SIZE)?;
+			let seed = match &record.seed {
+				Some(s) => Some(seal(&key, &self.keyring.decrypt(s, SEED_SIZE)?)),
+				None => None,
+			};
+			let mut model: identity::ActiveModel = record.into();
+			model.private_key = Set(seal(&key, &private_key));
+			model.seed = Set(seed);
+			model.update(tx.inner()).await?;
+		}
+		key_encryption::Entity::insert(key_encryption::ActiveModel {
+			id: Set(0),
+			salt: Set(salt.to_vec()),
+			verifier: Set(seal(&key, VERIFIER_PLAINTEXT)),
+		})
+		.exec(tx.inner())
+		.await?;
+		tx.commit().await?;
+
+		self.keyring.is_enabled.store(true, Ordering::Relaxed);
+		self.keyring.unlock(key);
+		Ok(())
+	}
+
+	/// Loads the address and private key of our node, or generates them if we
+	/// don't have them yet.
+	pub async fn load_node_identity(&self) -> Result<(NodeAddress, NodePrivateKey)> {
+		let result = match node_identity::Entity::find().one(self.inner()).await? {
+			Some(m) => {
+				let buffer = self.keyring.decrypt(&m.private_key, NODE_PRIVATE_KEY_SIZE)?;
+				(
+					m.address,
+					NodePrivateKey::from_bytes(*array_ref![buffer, 0, NODE_PRIVATE_KEY_SIZE]),
+				)
+			}
+			None => {
+				let private_key = NodePrivateKey::generate();
+				let address = NodeAddress::V1(IdType::hash(&private_key.public().to_bytes()));
+
+				let record = node_identity::ActiveModel {
+					id: NotSet,
+					address: Set(address.clone()),
+					private_key: Set(self.keyring.encrypt(private_key.as_bytes())?),
+				};
+				node_identity::Entity::insert(record)
+					.exec(self.inner())
+					.await?;
+				(address, private_key)
+			}
+		};
+		Ok(result)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_key_encryption() {
+		let db = test::load_database("keyring").await;
+		let (address, private_key) = db.load_node_identity().await.unwrap();
+
+		assert!(!db.load_key_encryption().await.unwrap());
+		db.enable_key_encryption("correct horse").await.unwrap();
+		let stored = node_identity::Entity::find()
+			.one(db.inner())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_ne!(&stored.private_key[..], &private_key.as_bytes()[..]);
+
+		db.keyring().lock();
+		assert!(db.keyring().is_locked());
+		assert!(db.load_node_identity().await.is_err());
+		assert!(!db.unlock_keys("wrong horse").await.unwrap());
+		assert!(db.unlock_keys("correct horse").await.unwrap());
+		let (address2, private_key2) = db.load_node_identity().await.unwrap();
+		assert_eq!(address, address2);
+		assert_eq!(private_key.as_bytes(), private_key2.as_bytes());
+
+		assert!(!db.keyring().lock_if_idle(Duration::from_secs(60)));
+		assert!(db.keyring().lock_if_idle(Duration::ZERO));
+		assert!(db.keyring().is_locked());
+	}
+}
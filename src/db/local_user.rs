@@ -0,0 +1,373 @@
+//! The local users of the node, that each have their own login, identities,
+//! follows and feed, while sharing the daemon and the database.
+//!
+//! Everything that belongs to the owner of the node has no user set. The
+//! requests that a local user makes are handled within the scope of that user,
+//! so that the queries that load identities, follows or feeds only give what
+//! belongs to the user. Actor networks are joined by the node as a whole: it
+//! follows every actor that any of its users follows.
+
+use std::future::Future;
+
+use argon2::{
+	password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+	Argon2,
+};
+use rand::rngs::OsRng;
+use sea_orm::{
+	prelude::*,
+	sea_query::{Query, SimpleExpr},
+	NotSet, QueryOrder, Set,
+};
+
+use super::{Database, Error, PersistenceHandle, Result};
+use crate::{common::current_timestamp, entity::*, util};
+
+
+tokio::task_local! {
+	/// The local user that the request that is being handled is made by.
+	static CURRENT_USER: i64;
+}
+
+
+/// The local user that the request that is being handled is made by, or `None`
+/// if it is made by the owner of the node.
+pub fn current_user() -> Option<i64> { CURRENT_USER.try_with(|u| *u).ok() }
+
+/// Runs the future within the scope of the local user.
+pub async fn scope_user<F: Future>(user_id: Option<i64>, f: F) -> F::Output {
+	match user_id {
+		Some(id) => CURRENT_USER.scope(id, f).await,
+		None => f.await,
+	}
+}
+
+/// The condition on the `user_id` column of a table that only leaves the rows
+/// of the current user.
+pub fn user_condition(column: impl ColumnTrait) -> SimpleExpr {
+	match current_user() {
+		Some(id) => column.eq(id),
+		None => column.is_null(),
+	}
+}
+
+/// The condition on identities that only leaves the ones of the current user.
+pub fn identity_condition() -> SimpleExpr { user_condition(identity::Column::UserId) }
+
+/// The condition on a column of actor addresses that only leaves the addresses
+/// of the identities of the current user.
+pub fn identity_address_condition(column: impl ColumnTrait) -> SimpleExpr {
+	column.in_subquery(
+		Query::select()
+			.column((actor::Entity, actor::Column::Address))
+			.from(identity::Entity)
+			.inner_join(
+				actor::Entity,
+				Expr::col((actor::Entity, actor::Column::Id))
+					.equals((identity::Entity, identity::Column::ActorId)),
+			)
+			.and_where(identity_condition())
+			.take(),
+	)
+}
+
+/// The condition on `consolidated_object` that leaves out the objects of the
+/// actors that the current user doesn't follow, except the ones of its own
+/// identities. The owner of the node doesn't see the actors that only local
+/// users follow. The objects from the fediverse are left in, as those are
+/// followed by the node as a whole.
+pub fn feed_condition() -> SimpleExpr {
+	match current_user() {
+		Some(user_id) => Expr::cust_with_values(
+			r#"
+			type != 0 OR actor_id IN (
+				SELECT actor_id FROM local_user_following WHERE user_id = ?
+				UNION
+				SELECT actor_id FROM identity WHERE user_id = ?
+			)
+		"#,
+			[user_id, user_id],
+		),
+		None => Expr::cust(
+			r#"
+			type != 0 OR actor_id NOT IN (SELECT actor_id FROM following WHERE user_only)
+		"#,
+		),
+	}
+}
+
+
+impl Database {
+	/// Creates a new local user. Returns `None` if the name is taken already.
+	pub async fn create_local_user(
+		&self, name: &str, password: &str, is_admin: bool,
+	) -> Result<Option<i64>> {
+		if local_user::Entity::find()
+			.filter(local_user::Column::Name.eq(name))
+			.one(self.inner())
+			.await?
+			.is_some()
+		{
+			return Ok(None);
+		}
+
+		let salt = SaltString::generate(&mut OsRng);
+		let hash = util::block_in_place(|| {
+			Argon2::default()
+				.hash_password(password.as_bytes(), &salt)
+				.map(|h| h.to_string())
+		})
+		.map_err(|e| Error::UnexpectedState(format!("unable to hash password: {}", e)))?;
+		let record = local_user::ActiveModel {
+			id: NotSet,
+			name: Set(name.to_string()),
+			password_hash: Set(hash),
+			is_admin: Set(is_admin),
+			created: Set(current_timestamp() as _),
+		};
+		let result = local_user::Entity::insert(record)
+			.exec(self.inner())
+			.await?;
+		Ok(Some(result.last_insert_id))
+	}
+
+	/// Deletes the local user. Its identities, drafts and journal entries are
+	/// handed over to the owner of the node, and its bookmarks are removed.
+	/// Returns false if the user didn't exist.
+	pub async fn delete_local_user(&self, user_id: i64) -> Result<bool> {
+		let tx = self.transaction().await?;
+		identity::Entity::update_many()
+			.col_expr(identity::Column::UserId, Expr::value(Option::<i64>::None))
+			.col_expr(identity::Column::IsActive, Expr::value(false))
+			.filter(identity::Column::UserId.eq(user_id))
+			.exec(tx.inner())
+			.await?;
+		local_user_following::Entity::delete_many()
+			.filter(local_user_following::Column::UserId.eq(user_id))
+			.exec(tx.inner())
+			.await?;
+		draft::Entity::update_many()
+			.col_expr(draft::Column::UserId, Expr::value(Option::<i64>::None))
+			.filter(draft::Column::UserId.eq(user_id))
+			.exec(tx.inner())
+			.await?;
+		journal_entry::Entity::update_many()
+			.col_expr(journal_entry::Column::UserId, Expr::value(Option::<i64>::None))
+			.filter(journal_entry::Column::UserId.eq(user_id))
+			.exec(tx.inner())
+			.await?;
+		bookmark::Entity::delete_many()
+			.filter(bookmark::Column::UserId.eq(user_id))
+			.exec(tx.inner())
+			.await?;
+		let result = local_user::Entity::delete_by_id(user_id)
+			.exec(tx.inner())
+			.await?;
+		tx.commit().await?;
+		Ok(result.rows_affected > 0)
+	}
+
+	pub async fn load_local_users(&self) -> Result<Vec<local_user::Model>> {
+		Ok(local_user::Entity::find()
+			.order_by_asc(local_user::Column::Name)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Marks whether the node only follows the actor because of its local
+	/// users.
+	pub async fn set_follow_user_only(&self, actor_id: i64, user_only: bool) -> Result<()> {
+		following::Entity::update_many()
+			.col_expr(following::Column::UserOnly, Expr::value(user_only))
+			.filter(following::Column::ActorId.eq(actor_id))
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Lets the local user follow the actor, which the node has to follow
+	/// already.
+	pub async fn store_user_follow(&self, user_id: i64, actor_id: i64) -> Result<()> {
+		let existing = local_user_following::Entity::find_by_id((user_id, actor_id))
+			.one(self.inner())
+			.await?;
+		if existing.is_none() {
+			let record = local_user_following::ActiveModel {
+				user_id: Set(user_id),
+				actor_id: Set(actor_id),
+			};
+			local_user_following::Entity::insert(record)
+				.exec(self.inner())
+				.await?;
+		}
+		Ok(())
+	}
+
+	/// Whether any local user follows the actor.
+	pub async fn is_followed_by_users(&self, actor_id: i64) -> Result<bool> {
+		Ok(local_user_following::Entity::find()
+			.filter(local_user_following::Column::ActorId.eq(actor_id))
+			.one(self.inner())
+			.await?
+			.is_some())
+	}
+
+	/// Stops the local user from following the actor. Returns whether the node
+	/// doesn't need to follow the actor anymore, because neither the owner nor
+	/// any other user follows it, or `None` if the user didn't follow it.
+	pub async fn remove_user_follow(&self, user_id: i64, actor_id: i64) -> Result<Option<bool>> {
+		let result = local_user_following::Entity::delete_by_id((user_id, actor_id))
+			.exec(self.inner())
+			.await?;
+		if result.rows_affected == 0 {
+			return Ok(None);
+		}
+
+		let still_followed = self.is_followed_by_users(actor_id).await?;
+		let user_only = following::Entity::find_by_id(actor_id)
+			.one(self.inner())
+			.await?
+			.map(|f| f.user_only)
+			.unwrap_or(false);
+		Ok(Some(!still_followed && user_only))
+	}
+
+	/// Checks the password of the local user, and returns the user if it is
+	/// right.
+	pub async fn verify_local_user(
+		&self, name: &str, password: &str,
+	) -> Result<Option<local_user::Model>> {
+		let user = match local_user::Entity::find()
+			.filter(local_user::Column::Name.eq(name))
+			.one(self.inner())
+			.await?
+		{
+			Some(u) => u,
+			None => return Ok(None),
+		};
+		let hash = match PasswordHash::new(&user.password_hash) {
+			Ok(h) => h,
+			Err(_) => return Ok(None),
+		};
+		// Verifying the password is made slow on purpose
+		let result = util::block_in_place(|| {
+			Argon2::default().verify_password(password.as_bytes(), &hash)
+		});
+		Ok(result.ok().map(|_| user))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		common::IdType,
+		core::{ActorAddress, DirectMessageContent},
+		db::journal::{self, JournalAction},
+		identity::ActorPrivateKeyV1,
+		test,
+	};
+
+	#[tokio::test]
+	async fn test_local_users() {
+		let db = test::load_database("local_user").await;
+		let id = db
+			.create_local_user("alice", "secret", false)
+			.await
+			.unwrap()
+			.expect("name already taken");
+		assert!(db
+			.create_local_user("alice", "other", false)
+			.await
+			.unwrap()
+			.is_none());
+
+		assert!(db.verify_local_user("alice", "wrong").await.unwrap().is_none());
+		assert!(db.verify_local_user("bob", "secret").await.unwrap().is_none());
+		let user = db
+			.verify_local_user("alice", "secret")
+			.await
+			.unwrap()
+			.expect("password not accepted");
+		assert_eq!(user.id, id);
+
+		assert_eq!(current_user(), None);
+		scope_user(Some(id), async { assert_eq!(current_user(), Some(id)) }).await;
+
+		assert!(db.delete_local_user(id).await.unwrap());
+		assert!(db.load_local_users().await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_user_scopes() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("local_user_scopes").await;
+		let alice = db.create_local_user("alice", "a", false).await.unwrap().unwrap();
+		let bob = db.create_local_user("bob", "b", false).await.unwrap().unwrap();
+		let identity = ActorAddress::V1(IdType::random(&mut rng));
+		let peer = ActorAddress::V1(IdType::random(&mut rng));
+		let hash = IdType::random(&mut rng);
+
+		let (conversation_id, draft_id) = scope_user(Some(alice), async {
+			let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+			let tx = db.transaction().await.unwrap();
+			tx.create_identity(
+				"alice",
+				&identity,
+				&private_key.public(),
+				&private_key,
+				false,
+				&IdType::random(&mut rng),
+				None,
+			)
+			.await
+			.unwrap();
+			tx.commit().await.unwrap();
+
+			let conversation_id = db.ensure_conversation(&identity, &peer).await.unwrap();
+			let content = DirectMessageContent {
+				mime_type: "text/markdown".into(),
+				body: "Only for Alice".to_string(),
+			};
+			db.store_outgoing_direct_message(conversation_id, &content, 0)
+				.await
+				.unwrap();
+			let draft_id = db.create_draft("Secret", &[], None).await.unwrap();
+			assert!(db.add_bookmark(&peer, &hash).await.unwrap());
+			journal::record(&db, JournalAction::Followed, "peer", None, None)
+				.await
+				.unwrap();
+
+			assert_eq!(db.load_conversations().await.unwrap().len(), 1);
+			assert!(db.load_draft(draft_id).await.unwrap().is_some());
+			assert!(db.is_bookmarked(&peer, &hash).await.unwrap());
+			assert_eq!(db.load_journal(10, 0).await.unwrap().len(), 1);
+			(conversation_id, draft_id)
+		})
+		.await;
+
+		// Neither another user nor the owner of the node sees any of it
+		for user_id in [Some(bob), None] {
+			scope_user(user_id, async {
+				assert!(db.load_conversations().await.unwrap().is_empty());
+				assert!(db.load_conversation(conversation_id).await.unwrap().is_none());
+				assert!(db.load_drafts().await.unwrap().is_empty());
+				assert!(db.load_draft(draft_id).await.unwrap().is_none());
+				assert!(!db.update_draft(draft_id, "Changed", &[]).await.unwrap());
+				assert!(!db.delete_draft(draft_id).await.unwrap());
+				assert!(db.load_bookmarks(10, 0).await.unwrap().is_empty());
+				assert!(!db.is_bookmarked(&peer, &hash).await.unwrap());
+				assert!(!db.remove_bookmark(&peer, &hash).await.unwrap());
+				assert!(db.load_journal(10, 0).await.unwrap().is_empty());
+			})
+			.await;
+		}
+
+		// Bookmarking the same object as another user is fine
+		scope_user(Some(bob), async {
+			assert!(db.add_bookmark(&peer, &hash).await.unwrap());
+		})
+		.await;
+	}
+}
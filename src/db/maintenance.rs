@@ -0,0 +1,131 @@
+//! The routine upkeep of the database, that runs in the background every so
+//! often.
+//!
+//! Besides giving free pages back to the file system (see the `vacuum`
+//! module), the statistics that the query planner uses are updated, and rows
+//! that have been left behind without the rows they belong to are removed.
+//! Such rows can only be the result of a bug or of an interrupted migration,
+//! but they would otherwise stay around forever.
+
+use sea_orm::ConnectionTrait;
+use serde::Serialize;
+
+use super::{Database, PersistenceHandle, Result};
+
+
+/// The number of rows per index that `ANALYZE` looks at, which keeps it fast
+/// on large databases while still giving the query planner good estimates.
+const ANALYSIS_LIMIT: u32 = 1000;
+
+
+/// The number of rows that have been removed because what they belonged to was
+/// gone.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OrphanCleanup {
+	pub file_blocks: u64,
+	pub objects: u64,
+	pub blocks: usize,
+}
+
+/// The outcome of the last time the database has been maintained.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MaintenanceReport {
+	/// The unix timestamp at which the maintenance finished, in seconds.
+	pub finished: u64,
+	/// How long the maintenance took, in milliseconds.
+	pub duration: u64,
+	pub orphans: OrphanCleanup,
+	/// The number of free pages that have been given back to the file system.
+	pub released_pages: u64,
+	pub analyzed: bool,
+	pub errors: Vec<String>,
+}
+
+
+impl Database {
+	/// Updates the statistics that the query planner uses to pick its indexes.
+	pub async fn analyze(&self) -> Result<()> {
+		// Both statements need to run on the same connection
+		self.inner()
+			.execute_unprepared(&format!(
+				"PRAGMA analysis_limit = {}; ANALYZE;",
+				ANALYSIS_LIMIT
+			))
+			.await?;
+		Ok(())
+	}
+
+	/// Removes the blocks of files that don't exist, and the objects of actors
+	/// that don't exist, together with everything that refers to them.
+	pub async fn clean_orphaned_rows(&self) -> Result<OrphanCleanup> {
+		let tx = self.transaction().await?;
+		let mut cleanup = OrphanCleanup::default();
+		cleanup.file_blocks = tx
+			.inner()
+			.execute_unprepared("DELETE FROM file_block WHERE file_id NOT IN (SELECT id FROM file)")
+			.await?
+			.rows_affected();
+		cleanup.objects = tx
+			.delete_objects("actor_id NOT IN (SELECT id FROM actor)", Vec::new())
+			.await?;
+		let orphaned_blocks = if cleanup.file_blocks > 0 {
+			tx.delete_orphaned_blocks().await?
+		} else {
+			Vec::new()
+		};
+		tx.commit().await?;
+
+		cleanup.blocks = orphaned_blocks.len();
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(cleanup)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use sea_orm::EntityTrait;
+
+	use super::*;
+	use crate::{core::FileData, entity::block, test};
+
+	#[tokio::test]
+	async fn test_clean_orphaned_rows() {
+		let db = test::load_database("maintenance").await;
+
+		let file_data = FileData {
+			mime_type: "text/plain".into(),
+			data: b"Left behind".to_vec(),
+		};
+		let tx = db.transaction().await.unwrap();
+		let (file_id, ..) = tx.create_file(&file_data).await.unwrap();
+		tx.commit().await.unwrap();
+
+		// Nothing is removed while the file still exists
+		let cleanup = db.clean_orphaned_rows().await.unwrap();
+		assert_eq!(cleanup.file_blocks, 0);
+		assert_eq!(cleanup.objects, 0);
+
+		// Foreign keys aren't enforced on connections of the old kind
+		let c = db.connect_old().unwrap();
+		c.execute("DELETE FROM file WHERE id = ?", [file_id]).unwrap();
+		c.execute(
+			r#"
+			INSERT INTO object (
+				actor_id, sequence, previous_hash, hash, signature, created, found, type,
+				verified_from_start
+			) VALUES (1234, 0, X'00', X'00', X'00', 1, 1, 0, 0)
+		"#,
+			[],
+		)
+		.unwrap();
+
+		let cleanup = db.clean_orphaned_rows().await.unwrap();
+		assert_eq!(cleanup.file_blocks, 1);
+		assert_eq!(cleanup.objects, 1);
+		assert_eq!(cleanup.blocks, 1);
+		assert_eq!(block::Entity::find().all(db.inner()).await.unwrap().len(), 0);
+
+		db.analyze().await.unwrap();
+	}
+}
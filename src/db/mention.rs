@@ -0,0 +1,169 @@
+//! The actors that are mentioned in posts.
+//!
+//! An actor is mentioned by writing `@` followed by its address in the message
+//! of a post. Like hashtags, mentions are found when the post is indexed for
+//! searching, because that is when its message has been downloaded.
+
+use std::{ops::Range, str::FromStr};
+
+use sea_orm::{prelude::*, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::{ActorAddress, Address},
+};
+
+
+/// The number of mentions in a post that are stored at most.
+const MAX_MENTIONS: usize = 64;
+/// Mentions in posts that have been found longer ago than this, in
+/// milliseconds, are not notified of anymore.
+const NOTIFY_MAX_AGE: u64 = 7 * 24 * 60 * 60 * 1000;
+
+
+/// A mention of one of our identities that hasn't been notified of yet.
+#[derive(Clone, Debug)]
+pub struct NewMention {
+	pub identity: ActorAddress,
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+}
+
+
+/// Finds the words in the text that are preceded by an `@`, and returns where
+/// they are in the text, including the `@`. Names like `alice@example.com`
+/// and `@alice@example.com` are not mentions.
+pub fn find_mention_words(text: &str) -> Vec<(Range<usize>, &str)> {
+	let mut words = Vec::new();
+	let mut previous = None;
+	let mut chars = text.char_indices().peekable();
+	while let Some((i, c)) = chars.next() {
+		let starts_word = c == '@' && !matches!(previous, Some(p) if is_word_char(p));
+		previous = Some(c);
+		if !starts_word {
+			continue;
+		}
+
+		let mut end = i + 1;
+		while let Some(&(j, c)) = chars.peek() {
+			if !is_word_char(c) {
+				break;
+			}
+			end = j + c.len_utf8();
+			previous = Some(c);
+			chars.next();
+		}
+		let rest = &text[end..];
+		let is_domain_name = rest.starts_with('@')
+			|| (rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_alphanumeric()));
+		if end > i + 1 && !is_domain_name {
+			words.push((i..end, &text[i + 1..end]));
+		}
+	}
+	words
+}
+
+fn is_word_char(c: char) -> bool { c.is_alphanumeric() || c == '_' || c == '-' }
+
+/// Finds the actor addresses that are mentioned in the text.
+pub fn parse_mentions(text: &str) -> Vec<ActorAddress> {
+	let mut addresses = Vec::new();
+	for (_, word) in find_mention_words(text) {
+		if let Ok(Address::Actor(address)) = Address::from_str(word) {
+			if !addresses.contains(&address) {
+				addresses.push(address);
+				if addresses.len() == MAX_MENTIONS {
+					break;
+				}
+			}
+		}
+	}
+	addresses
+}
+
+/// Stores the actors that the post mentions. Mentions that are stored already
+/// are left alone.
+pub(super) async fn store_mentions(
+	db: &impl PersistenceHandle, object_id: i64, addresses: &[ActorAddress],
+) -> Result<()> {
+	for address in addresses {
+		db.inner()
+			.execute(Statement::from_sql_and_values(
+				db.backend(),
+				"INSERT OR IGNORE INTO post_mention (object_id, actor_address) VALUES (?, ?)",
+				[object_id.into(), address.into()],
+			))
+			.await?;
+	}
+	Ok(())
+}
+
+impl Database {
+	/// Loads the mentions of our identities by other actors that haven't been
+	/// notified of yet, and marks them as notified.
+	pub async fn take_new_mentions(&self) -> Result<Vec<NewMention>> {
+		let min_found = current_timestamp().saturating_sub(NOTIFY_MAX_AGE);
+		let tx = self.transaction().await?;
+		let results = tx
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				tx.backend(),
+				r#"
+				SELECT m.actor_address, a.address, o.hash
+				FROM post_mention AS m
+				INNER JOIN object AS o ON o.id = m.object_id
+				INNER JOIN actor AS a ON a.id = o.actor_id
+				WHERE NOT m.notified AND o.found >= ?
+					AND m.actor_address IN (
+						SELECT address FROM actor WHERE id IN (SELECT actor_id FROM identity)
+					)
+					AND o.actor_id NOT IN (SELECT actor_id FROM identity)
+				ORDER BY o.found
+			"#,
+				[min_found.into()],
+			))
+			.await?;
+		let mut mentions = Vec::with_capacity(results.len());
+		for r in results {
+			mentions.push(NewMention {
+				identity: r.try_get_by_index(0)?,
+				actor_address: r.try_get_by_index(1)?,
+				object_hash: r.try_get_by_index(2)?,
+			});
+		}
+
+		// The mentions that aren't notified of are marked as well, so that they
+		// don't need to be looked at again
+		tx.inner()
+			.execute_unprepared("UPDATE post_mention SET notified = TRUE WHERE NOT notified")
+			.await?;
+		tx.commit().await?;
+		Ok(mentions)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_parse_mentions() {
+		let mut rng = test::initialize_rng();
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let text = format!(
+			"Hi @{}, @{} and @alice! Mail bob@example.com or @bob@example.com, or see \
+			 @example.com.",
+			address, address
+		);
+		let words: Vec<_> = find_mention_words(&text)
+			.into_iter()
+			.map(|(_, w)| w)
+			.collect();
+		let address_string = address.to_string();
+		assert_eq!(words, vec![address_string.as_str(), address_string.as_str(), "alice"]);
+		assert_eq!(parse_mentions(&text), vec![address]);
+	}
+}
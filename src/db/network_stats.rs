@@ -0,0 +1,253 @@
+//! Keeps the traffic of the node per hour, so that it can be looked back on.
+//!
+//! The counters of the network stack only ever go up, and start at zero again
+//! when the node restarts. So what is stored is how much they went up during
+//! each hour. Because the increases are added to the row of the hour they
+//! happened in, the node can record them as often as it likes.
+//!
+//! The traffic with each other node is kept the same way, but per day, as
+//! there can be a lot of nodes.
+
+use std::collections::HashMap;
+
+use sea_orm::{prelude::*, QueryOrder, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	core::NodeAddress,
+	entity::{network_stats, peer_traffic},
+	net::sstp::PeerTraffic,
+};
+
+
+/// The columns that hold counters, which are summed up.
+const COUNTER_COLUMNS: &[&str] = &[
+	"udp_bytes_in",
+	"udp_bytes_out",
+	"tcp_bytes_in",
+	"tcp_bytes_out",
+	"opened_connections",
+	"relayed_sessions",
+	"relayed_bytes",
+	"find_node_lookups",
+	"find_value_lookups",
+];
+
+
+impl Database {
+	/// Adds the increases of the counters to the statistics of their hour. The
+	/// highest number of sessions is kept, rather than summed up.
+	pub async fn add_network_stats(&self, stats: &network_stats::Model) -> Result<()> {
+		let updates = COUNTER_COLUMNS
+			.iter()
+			.map(|c| format!("{} = {} + excluded.{}", c, c, c))
+			.collect::<Vec<_>>()
+			.join(", ");
+		let sql = format!(
+			r#"
+			INSERT INTO network_stats (hour, max_sessions, {})
+			VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+			ON CONFLICT (hour) DO UPDATE SET
+				max_sessions = MAX(max_sessions, excluded.max_sessions), {}
+		"#,
+			COUNTER_COLUMNS.join(", "),
+			updates
+		);
+		self.inner()
+			.execute(Statement::from_sql_and_values(
+				self.backend(),
+				sql,
+				[
+					stats.hour.into(),
+					stats.max_sessions.into(),
+					stats.udp_bytes_in.into(),
+					stats.udp_bytes_out.into(),
+					stats.tcp_bytes_in.into(),
+					stats.tcp_bytes_out.into(),
+					stats.opened_connections.into(),
+					stats.relayed_sessions.into(),
+					stats.relayed_bytes.into(),
+					stats.find_node_lookups.into(),
+					stats.find_value_lookups.into(),
+				],
+			))
+			.await?;
+		Ok(())
+	}
+
+	/// Loads the statistics of the hours that started at or after the given
+	/// unix timestamp, oldest first.
+	pub async fn load_network_stats(&self, since: i64) -> Result<Vec<network_stats::Model>> {
+		Ok(network_stats::Entity::find()
+			.filter(network_stats::Column::Hour.gte(since))
+			.order_by_asc(network_stats::Column::Hour)
+			.all(self.inner())
+			.await?)
+	}
+
+	/// Removes the statistics of the hours that started before the given unix
+	/// timestamp. Returns the number of hours that have been removed.
+	pub async fn prune_network_stats(&self, before: i64) -> Result<u64> {
+		let result = network_stats::Entity::delete_many()
+			.filter(network_stats::Column::Hour.lt(before))
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected)
+	}
+
+	/// Adds the traffic that has been exchanged with each node to its traffic
+	/// of the day that started at the given unix timestamp.
+	pub async fn add_peer_traffic(
+		&self, day: i64, traffic: &HashMap<NodeAddress, PeerTraffic>,
+	) -> Result<()> {
+		let tx = self.transaction().await?;
+		for (node_address, t) in traffic {
+			tx.inner()
+				.execute(Statement::from_sql_and_values(
+					self.backend(),
+					r#"
+					INSERT INTO peer_traffic (day, node_address, bytes_in, bytes_out)
+					VALUES (?, ?, ?, ?)
+					ON CONFLICT (day, node_address) DO UPDATE SET
+						bytes_in = bytes_in + excluded.bytes_in,
+						bytes_out = bytes_out + excluded.bytes_out
+				"#,
+					[
+						day.into(),
+						node_address.clone().into(),
+						(t.bytes_in as i64).into(),
+						(t.bytes_out as i64).into(),
+					],
+				))
+				.await?;
+		}
+		tx.commit().await?;
+		Ok(())
+	}
+
+	/// Loads the nodes with which the most traffic has been exchanged during
+	/// the days that started at or after the given unix timestamp, together
+	/// with the bytes that have been received from and sent to them.
+	pub async fn load_top_peers(
+		&self, since: i64, limit: u64,
+	) -> Result<Vec<(NodeAddress, PeerTraffic)>> {
+		let rows = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT node_address, SUM(bytes_in) AS bytes_in, SUM(bytes_out) AS bytes_out
+				FROM peer_traffic
+				WHERE day >= ?
+				GROUP BY node_address
+				ORDER BY SUM(bytes_in) + SUM(bytes_out) DESC
+				LIMIT ?
+			"#,
+				[since.into(), (limit as i64).into()],
+			))
+			.await?;
+
+		let mut peers = Vec::with_capacity(rows.len());
+		for row in rows {
+			let node_address: NodeAddress = row.try_get_by_index(0)?;
+			let bytes_in: i64 = row.try_get_by_index(1)?;
+			let bytes_out: i64 = row.try_get_by_index(2)?;
+			peers.push((
+				node_address,
+				PeerTraffic {
+					bytes_in: bytes_in as _,
+					bytes_out: bytes_out as _,
+				},
+			));
+		}
+		Ok(peers)
+	}
+
+	/// Removes the traffic of the days that started before the given unix
+	/// timestamp.
+	pub async fn prune_peer_traffic(&self, before: i64) -> Result<u64> {
+		let result = peer_traffic::Entity::delete_many()
+			.filter(peer_traffic::Column::Day.lt(before))
+			.exec(self.inner())
+			.await?;
+		Ok(result.rows_affected)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[tokio::test]
+	async fn test_network_stats() {
+		let db = test::load_database("network_stats").await;
+
+		let mut stats = network_stats::Model {
+			hour: 3600,
+			udp_bytes_in: 100,
+			max_sessions: 5,
+			find_node_lookups: 1,
+			..Default::default()
+		};
+		db.add_network_stats(&stats).await.unwrap();
+		stats.max_sessions = 3;
+		db.add_network_stats(&stats).await.unwrap();
+		stats.hour = 7200;
+		db.add_network_stats(&stats).await.unwrap();
+
+		let loaded = db.load_network_stats(0).await.unwrap();
+		assert_eq!(loaded.len(), 2);
+		assert_eq!(loaded[0].hour, 3600);
+		assert_eq!(loaded[0].udp_bytes_in, 200);
+		assert_eq!(loaded[0].find_node_lookups, 2);
+		assert_eq!(loaded[0].max_sessions, 5);
+		assert_eq!(loaded[1].udp_bytes_in, 100);
+		assert_eq!(db.load_network_stats(7200).await.unwrap().len(), 1);
+
+		assert_eq!(db.prune_network_stats(7200).await.unwrap(), 1);
+		let loaded = db.load_network_stats(0).await.unwrap();
+		assert_eq!(loaded.len(), 1);
+		assert_eq!(loaded[0].hour, 7200);
+	}
+
+	#[tokio::test]
+	async fn test_peer_traffic() {
+		let db = test::load_database("peer_traffic").await;
+		let mut rng = test::initialize_rng();
+		let busy_node = NodeAddress::V1(IdType::random(&mut rng));
+		let quiet_node = NodeAddress::V1(IdType::random(&mut rng));
+
+		let mut traffic = HashMap::new();
+		traffic.insert(
+			busy_node.clone(),
+			PeerTraffic {
+				bytes_in: 1000,
+				bytes_out: 500,
+			},
+		);
+		traffic.insert(
+			quiet_node.clone(),
+			PeerTraffic {
+				bytes_in: 10,
+				bytes_out: 0,
+			},
+		);
+		db.add_peer_traffic(0, &traffic).await.unwrap();
+		db.add_peer_traffic(0, &traffic).await.unwrap();
+		db.add_peer_traffic(86400, &traffic).await.unwrap();
+
+		let peers = db.load_top_peers(0, 10).await.unwrap();
+		assert_eq!(peers.len(), 2);
+		assert_eq!(peers[0].0, busy_node);
+		assert_eq!(peers[0].1.bytes_in, 3000);
+		assert_eq!(peers[0].1.bytes_out, 1500);
+		assert_eq!(peers[1].0, quiet_node);
+		assert_eq!(db.load_top_peers(0, 1).await.unwrap().len(), 1);
+		assert_eq!(db.load_top_peers(86400, 10).await.unwrap()[0].1.bytes_in, 1000);
+
+		assert_eq!(db.prune_peer_traffic(86400).await.unwrap(), 2);
+		assert_eq!(db.load_top_peers(0, 10).await.unwrap()[0].1.bytes_in, 1000);
+	}
+}
@@ -0,0 +1,140 @@
+//! Keeps the parity blocks that other nodes have handed out to us.
+//!
+//! See the `parity` module of the actor network for how they are computed and
+//! used.
+//!
+//! Parity blocks count towards the storage quota. Because they are only a
+//! fallback for blocks that can't be found anymore, they are evicted before
+//! any block is, the oldest ones first. They are removed along with the file
+//! that they belong to.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, QuerySelect, Set, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{common::*, entity::parity_block};
+
+
+/// The number of parity blocks that are considered for eviction at once.
+const EVICTION_BATCH_SIZE: u64 = 1000;
+
+
+impl Database {
+	/// Removes parity blocks, the oldest first, until at least the given amount
+	/// of bytes has been freed, or until there are none left.
+	/// Returns the number of bytes that have been freed.
+	pub async fn evict_parity_blocks(&self, amount: u64) -> Result<u64> {
+		let candidates: Vec<(i64, i64)> = parity_block::Entity::find()
+			.select_only()
+			.column(parity_block::Column::Id)
+			.column_as(Expr::cust("LENGTH(data)"), "size")
+			.order_by_asc(parity_block::Column::Created)
+			.order_by_asc(parity_block::Column::Id)
+			.limit(EVICTION_BATCH_SIZE)
+			.into_tuple()
+			.all(self.inner())
+			.await?;
+
+		let mut freed = 0;
+		let mut ids = Vec::new();
+		for (id, size) in candidates {
+			if freed >= amount {
+				break;
+			}
+			freed += size as u64;
+			ids.push(id);
+		}
+		if ids.len() > 0 {
+			parity_block::Entity::delete_many()
+				.filter(parity_block::Column::Id.is_in(ids))
+				.exec(self.inner())
+				.await?;
+		}
+		Ok(freed)
+	}
+
+
+	pub async fn load_parity_block(
+		&self, file_hash: &IdType, stripe: u32, shard: u8,
+	) -> Result<Option<Vec<u8>>> {
+		let result = parity_block::Entity::find()
+			.filter(parity_block::Column::FileHash.eq(file_hash))
+			.filter(parity_block::Column::Stripe.eq(stripe))
+			.filter(parity_block::Column::Shard.eq(shard))
+			.one(self.inner())
+			.await?;
+		Ok(result.map(|p| p.data))
+	}
+
+	/// Stores a parity block, unless we already have it.
+	/// Returns whether the parity block was new.
+	pub async fn store_parity_block(
+		&self, file_hash: &IdType, stripe: u32, shard: u8, data: Vec<u8>,
+	) -> Result<bool> {
+		if self
+			.load_parity_block(file_hash, stripe, shard)
+			.await?
+			.is_some()
+		{
+			return Ok(false);
+		}
+
+		let model = parity_block::ActiveModel {
+			id: NotSet,
+			file_hash: Set(file_hash.clone()),
+			stripe: Set(stripe),
+			shard: Set(shard),
+			data: Set(data),
+			created: Set(current_timestamp() as i64),
+		};
+		parity_block::Entity::insert(model)
+			.exec(self.inner())
+			.await?;
+		Ok(true)
+	}
+
+	/// The number of bytes of parity data that we keep.
+	pub(super) async fn total_parity_size(&self) -> Result<u64> {
+		let result = self
+			.inner()
+			.query_one(Statement::from_string(
+				self.backend(),
+				"SELECT SUM(LENGTH(data)) FROM parity_block",
+			))
+			.await?;
+		let sum: Option<i64> = match result {
+			Some(r) => r.try_get_by_index(0)?,
+			None => None,
+		};
+		Ok(sum.unwrap_or(0) as u64)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_parity_blocks() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("parity").await;
+		let file_hash = IdType::random(&mut rng);
+
+		assert!(db.store_parity_block(&file_hash, 1, 8, vec![1, 2, 3]).await.unwrap());
+		assert!(!db.store_parity_block(&file_hash, 1, 8, vec![4, 5, 6]).await.unwrap());
+		assert_eq!(
+			db.load_parity_block(&file_hash, 1, 8).await.unwrap(),
+			Some(vec![1, 2, 3])
+		);
+		assert_eq!(db.load_parity_block(&file_hash, 1, 9).await.unwrap(), None);
+
+		assert!(db.store_parity_block(&file_hash, 1, 9, vec![7; 10]).await.unwrap());
+		assert_eq!(db.total_parity_size().await.unwrap(), 13);
+		// The oldest parity block goes first
+		assert_eq!(db.evict_parity_blocks(1).await.unwrap(), 3);
+		assert_eq!(db.load_parity_block(&file_hash, 1, 8).await.unwrap(), None);
+		assert_eq!(db.evict_parity_blocks(100).await.unwrap(), 10);
+		assert_eq!(db.total_parity_size().await.unwrap(), 0);
+	}
+}
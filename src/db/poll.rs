@@ -0,0 +1,143 @@
+//! Counting the votes of polls.
+//!
+//! Votes are objects in the chains of the actors that vote, so the outcome of
+//! a poll is only known for the voters that this node stores. Each voter
+//! counts once: only its latest vote from before the poll closed is taken
+//! into account.
+
+use std::collections::HashSet;
+
+use sea_orm::{prelude::*, QueryOrder, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::IdType,
+	core::{ActorAddress, OBJECT_TYPE_POLL},
+	entity::*,
+};
+
+
+/// The fewest options a poll can have.
+pub const MIN_POLL_OPTIONS: usize = 2;
+/// The most options a poll can have.
+pub const MAX_POLL_OPTIONS: usize = 16;
+
+
+/// A poll with the votes that have been counted for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PollTally {
+	pub question: String,
+	pub options: Vec<String>,
+	pub multiple_choice: bool,
+	pub closes: u64,
+	/// The number of votes for each of the options.
+	pub votes: Vec<u64>,
+	/// The number of actors that have voted.
+	pub voters: u64,
+}
+
+
+/// Whether the choices make up a valid vote in a poll with the given number of
+/// options.
+pub fn is_valid_vote(choices: &[u8], option_count: usize, multiple_choice: bool) -> bool {
+	if choices.len() == 0 || (!multiple_choice && choices.len() > 1) {
+		return false;
+	}
+	let mut seen = HashSet::with_capacity(choices.len());
+	choices
+		.iter()
+		.all(|c| (*c as usize) < option_count && seen.insert(*c))
+}
+
+impl Database {
+	/// Loads the poll of the actor, and counts the votes for it.
+	pub async fn load_poll_tally(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> Result<Option<PollTally>> {
+		let object = object::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(actor_address))
+			.filter(object::Column::Hash.eq(object_hash))
+			.filter(object::Column::Type.eq(OBJECT_TYPE_POLL))
+			.one(self.inner())
+			.await?;
+		let poll = match object {
+			Some(o) => match poll_object::Entity::find_by_id(o.id)
+				.one(self.inner())
+				.await?
+			{
+				Some(p) => p,
+				None => return Ok(None),
+			},
+			None => return Ok(None),
+		};
+		let options: Vec<String> = poll_option::Entity::find()
+			.filter(poll_option::Column::ObjectId.eq(poll.object_id))
+			.order_by_asc(poll_option::Column::Sequence)
+			.all(self.inner())
+			.await?
+			.into_iter()
+			.map(|o| o.text)
+			.collect();
+
+		// The latest vote of each actor comes first
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT o.actor_id, v.choices
+				FROM vote_object AS v
+				INNER JOIN object AS o ON o.id = v.object_id
+				WHERE v.actor_address = ? AND v.object_hash = ? AND o.created <= ?
+				ORDER BY o.actor_id, o.sequence DESC
+			"#,
+				[actor_address.into(), object_hash.into(), poll.closes.into()],
+			))
+			.await?;
+		let mut votes = vec![0; options.len()];
+		let mut voters = 0;
+		let mut seen = HashSet::new();
+		for r in results {
+			let actor_id: i64 = r.try_get_by_index(0)?;
+			let choices: Vec<u8> = r.try_get_by_index(1)?;
+			if !seen.insert(actor_id) {
+				continue;
+			}
+			// A vote that doesn't make sense doesn't count, and doesn't bring
+			// back an earlier one either
+			if !is_valid_vote(&choices, options.len(), poll.multiple_choice) {
+				continue;
+			}
+			for choice in choices {
+				votes[choice as usize] += 1;
+			}
+			voters += 1;
+		}
+
+		Ok(Some(PollTally {
+			question: poll.question,
+			options,
+			multiple_choice: poll.multiple_choice,
+			closes: poll.closes as _,
+			votes,
+			voters,
+		}))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_valid_vote() {
+		assert!(is_valid_vote(&[1], 3, false));
+		assert!(is_valid_vote(&[0, 2], 3, true));
+		assert!(!is_valid_vote(&[], 3, true));
+		assert!(!is_valid_vote(&[0, 2], 3, false));
+		assert!(!is_valid_vote(&[3], 3, false));
+		assert!(!is_valid_vote(&[1, 1], 3, true));
+	}
+}
@@ -0,0 +1,289 @@
+//! Removes the old objects of other actors, according to the retention
+//! policies.
+//!
+//! Without a retention policy, the objects of the actors that we follow only
+//! ever accumulate. A policy limits them by age, by the amount of block data
+//! that the files of the actor take up, or by both. The oldest objects go
+//! first. The objects of our own identities are never removed, and neither are
+//! the objects that our identities have shared or replied to, or that have been
+//! bookmarked. The head and the latest profile object of an actor are always
+//! kept, because they are needed to show and to synchronize the actor.
+
+use sea_orm::{prelude::*, Statement, Value};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{common::current_timestamp, core::ActorAddress};
+
+
+/// The number of objects that are removed with a single statement.
+const DELETE_BATCH_SIZE: usize = 500;
+/// The condition that matches the objects `o` that a retention policy may
+/// remove.
+const REMOVABLE_OBJECT_CONDITION: &str = r#"
+	o.sequence < (SELECT MAX(sequence) FROM object WHERE actor_id = o.actor_id)
+	AND o.id IS NOT (
+		SELECT po.object_id FROM profile_object AS po
+		INNER JOIN object AS p ON p.id = po.object_id
+		WHERE p.actor_id = o.actor_id
+		ORDER BY p.sequence DESC LIMIT 1
+	)
+	AND o.hash NOT IN (
+		SELECT so.object_hash FROM share_object AS so
+		INNER JOIN object AS s ON s.id = so.object_id
+		WHERE s.actor_id IN (SELECT actor_id FROM identity)
+	)
+	AND o.hash NOT IN (
+		SELECT po.in_reply_to_object_hash FROM post_object AS po
+		INNER JOIN object AS r ON r.id = po.object_id
+		WHERE r.actor_id IN (SELECT actor_id FROM identity)
+			AND po.in_reply_to_object_hash IS NOT NULL
+	)
+	AND o.hash NOT IN (SELECT object_hash FROM bookmark)
+"#;
+
+
+/// Limits how much of the history of an actor is kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetentionPolicy {
+	/// The number of days after which objects are removed.
+	pub max_age: Option<u32>,
+	/// The number of bytes of block data that the files of the actor may take
+	/// up.
+	pub max_size: Option<u64>,
+}
+
+struct PruneCandidate {
+	object_id: i64,
+	created: u64,
+	size: u64,
+}
+
+
+impl RetentionPolicy {
+	pub fn is_limited(&self) -> bool { self.max_age.is_some() || self.max_size.is_some() }
+
+	/// The timestamp before which objects are removed, in milliseconds.
+	pub fn min_created(&self) -> u64 {
+		match self.max_age {
+			None => 0,
+			Some(days) => current_timestamp().saturating_sub(days as u64 * 24 * 3600 * 1000),
+		}
+	}
+}
+
+impl Database {
+	/// Returns the actors of which objects are stored, except for our own
+	/// identities.
+	pub async fn fetch_foreign_actors(&self) -> Result<Vec<(i64, ActorAddress)>> {
+		let results = self
+			.inner()
+			.query_all(Statement::from_string(
+				self.backend(),
+				r#"
+				SELECT id, address FROM actor
+				WHERE id IN (SELECT actor_id FROM object)
+					AND id NOT IN (SELECT actor_id FROM identity)
+			"#,
+			))
+			.await?;
+		let mut actors = Vec::with_capacity(results.len());
+		for result in results {
+			actors.push((result.try_get_by_index(0)?, result.try_get_by_index(1)?));
+		}
+		Ok(actors)
+	}
+
+	/// Removes the objects of the actor that the policy doesn't allow to be
+	/// kept. Their files and blocks stay until [`Self::prune_orphans`] is
+	/// called. Returns the number of objects that have been removed.
+	pub async fn prune_actor(&self, actor_id: i64, policy: &RetentionPolicy) -> Result<u64> {
+		if !policy.is_limited() {
+			return Ok(0);
+		}
+
+		let min_created = policy.min_created();
+		let mut size = match policy.max_size {
+			Some(_) => self.actor_block_size(actor_id).await?,
+			None => 0,
+		};
+		let mut object_ids: Vec<Value> = Vec::new();
+		for candidate in self.load_prune_candidates(actor_id).await? {
+			let is_too_old = candidate.created < min_created;
+			let is_too_large = match policy.max_size {
+				Some(max_size) => size > max_size,
+				None => false,
+			};
+			if !is_too_old && !is_too_large {
+				continue;
+			}
+			size = size.saturating_sub(candidate.size);
+			object_ids.push(candidate.object_id.into());
+		}
+		if object_ids.len() == 0 {
+			return Ok(0);
+		}
+
+		let tx = self.transaction().await?;
+		let mut removed = 0;
+		for batch in object_ids.chunks(DELETE_BATCH_SIZE) {
+			let condition = format!("id IN ({})", vec!["?"; batch.len()].join(","));
+			removed += tx.delete_objects(&condition, batch.to_vec()).await?;
+		}
+		tx.commit().await?;
+		Ok(removed)
+	}
+
+	/// Removes the files that no object uses anymore, and the blocks that no
+	/// file uses anymore. Returns the number of blocks that have been removed.
+	pub async fn prune_orphans(&self) -> Result<usize> {
+		let tx = self.transaction().await?;
+		let orphaned_blocks = tx.delete_orphans().await?;
+		tx.commit().await?;
+
+		let removed = orphaned_blocks.len();
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(removed)
+	}
+
+	/// Loads the objects of the actor that may be removed, oldest first,
+	/// together with the amount of block data that their files take up.
+	async fn load_prune_candidates(&self, actor_id: i64) -> Result<Vec<PruneCandidate>> {
+		let sql = format!(
+			r#"
+			SELECT o.id, o.created, (
+				SELECT SUM(b.size) FROM block AS b WHERE b.hash IN (
+					SELECT fb.block_hash FROM file_block AS fb
+					INNER JOIN file AS f ON f.id = fb.file_id
+					WHERE f.hash IN (
+						SELECT hash FROM post_file WHERE object_id = o.id
+						UNION SELECT avatar_file_hash FROM profile_object WHERE object_id = o.id
+						UNION SELECT wallpaper_file_hash FROM profile_object
+						WHERE object_id = o.id
+						UNION SELECT description_file_hash FROM profile_object
+						WHERE object_id = o.id
+					)
+				)
+			)
+			FROM object AS o
+			WHERE o.actor_id = ? AND {}
+			ORDER BY o.sequence ASC
+		"#,
+			REMOVABLE_OBJECT_CONDITION
+		);
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				sql,
+				[actor_id.into()],
+			))
+			.await?;
+
+		let mut candidates = Vec::with_capacity(results.len());
+		for result in results {
+			let created: i64 = result.try_get_by_index(1)?;
+			let size: Option<i64> = result.try_get_by_index(2)?;
+			candidates.push(PruneCandidate {
+				object_id: result.try_get_by_index(0)?,
+				created: created as _,
+				size: size.unwrap_or(0) as _,
+			});
+		}
+		Ok(candidates)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		common::*, core::*, db::SyncDepth, identity::ActorPrivateKeyV1, net::binserde, test,
+	};
+
+	#[tokio::test]
+	async fn test_prune_actor() {
+		let db = test::load_database("prune").await;
+		let mut rng = test::initialize_rng();
+
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let actor_info = ActorInfo::V1(ActorInfoV1 {
+			flags: 0,
+			public_key: private_key.public(),
+			first_object: IdType::random(&mut rng),
+			actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+		});
+		let mut c = db.connect_old().unwrap();
+		c.follow(&address, &actor_info, &SyncDepth::default()).unwrap();
+
+		// Three old posts with a file each, of which the last one is the head
+		let mut hashes = Vec::new();
+		for sequence in 0..3u64 {
+			let file_data = FileData {
+				mime_type: "text/markdown".into(),
+				data: format!("Post number {}", sequence).into_bytes(),
+			};
+			let tx = db.transaction().await.unwrap();
+			let (_, file_hash, _) = tx.create_file(&file_data).await.unwrap();
+			tx.commit().await.unwrap();
+
+			let payload = ObjectPayload::Post(PostObject {
+				in_reply_to: None,
+				data: PostObjectCryptedData::Plain(PostObjectDataPlain {
+					tags: Vec::new().into(),
+					files: vec![file_hash.clone()].into(),
+				}),
+			});
+			let signature = private_key.sign(&binserde::serialize(&payload).unwrap());
+			let object = BlogchainObject {
+				signature: signature.clone(),
+				sequence,
+				previous_hash: IdType::default(),
+				created: 1 + sequence,
+				payload,
+				delegation: None,
+			};
+			let object_hash = signature.hash();
+			assert!(c.store_object(&address, &object_hash, &object, true).unwrap());
+			hashes.push((object_hash, file_hash));
+		}
+
+		let actors = db.fetch_foreign_actors().await.unwrap();
+		assert_eq!(actors.len(), 1);
+		let (actor_id, _) = actors[0].clone();
+		assert_eq!(
+			db.prune_actor(actor_id, &RetentionPolicy::default())
+				.await
+				.unwrap(),
+			0
+		);
+
+		// Only the first post has to go to get below the size limit
+		let file_size = db.actor_block_size(actor_id).await.unwrap() / 3;
+		let policy = RetentionPolicy {
+			max_age: None,
+			max_size: Some(2 * file_size),
+		};
+		assert_eq!(db.prune_actor(actor_id, &policy).await.unwrap(), 1);
+		assert!(c.fetch_object(&hashes[0].0).unwrap().is_none());
+		assert!(c.fetch_object(&hashes[1].0).unwrap().is_some());
+		assert_eq!(db.prune_orphans().await.unwrap(), 1);
+		assert!(db.load_file_data(&hashes[0].1).await.unwrap().is_none());
+
+		// Bookmarked objects are kept, no matter how old they are
+		let policy = RetentionPolicy {
+			max_age: Some(1),
+			max_size: None,
+		};
+		db.add_bookmark(&address, &hashes[1].0).await.unwrap();
+		assert_eq!(db.prune_actor(actor_id, &policy).await.unwrap(), 0);
+		db.remove_bookmark(&address, &hashes[1].0).await.unwrap();
+
+		// Everything is too old, but the head stays
+		assert_eq!(db.prune_actor(actor_id, &policy).await.unwrap(), 1);
+		assert!(c.fetch_object(&hashes[1].0).unwrap().is_none());
+		assert!(c.fetch_object(&hashes[2].0).unwrap().is_some());
+		assert_eq!(db.prune_actor(actor_id, &policy).await.unwrap(), 0);
+	}
+}
@@ -0,0 +1,315 @@
+//! Removes the data of actors that aren't needed anymore.
+//!
+//! When an actor is unfollowed, it is marked as abandoned. Its objects are
+//! kept around for a grace period, so that following it again shortly after
+//! doesn't require everything to be synchronized again. Once the grace period
+//! has passed, the objects of the actor are removed, together with the files
+//! and blocks that no other object refers to anymore. Only the objects that
+//! have been bookmarked are kept.
+
+use sea_orm::{prelude::*, sea_query::OnConflict, QuerySelect, Set, Statement, Value};
+
+use super::{Database, PersistenceHandle, Result, Transaction};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::ActorAddress,
+	entity::*,
+};
+
+
+/// The condition that matches the files that no object or draft refers to.
+/// Objects that have become tombstones don't count.
+const ORPHANED_FILE_CONDITION: &str = r#"
+	hash NOT IN (
+		SELECT hash FROM post_file WHERE object_id NOT IN (SELECT object_id FROM tombstone)
+	)
+	AND hash NOT IN (
+		SELECT avatar_file_hash FROM profile_object WHERE avatar_file_hash IS NOT NULL
+	)
+	AND hash NOT IN (
+		SELECT wallpaper_file_hash FROM profile_object WHERE wallpaper_file_hash IS NOT NULL
+	)
+	AND hash NOT IN (
+		SELECT description_file_hash FROM profile_object WHERE description_file_hash IS NOT NULL
+	)
+	AND hash NOT IN (SELECT file_hash FROM draft_file)
+"#;
+
+
+impl Database {
+	/// Marks the actor as abandoned, which starts its grace period. Nothing
+	/// changes if it was abandoned already.
+	/// Returns false if the actor is not known.
+	pub async fn abandon_actor(&self, address: &ActorAddress) -> Result<bool> {
+		let actor_id = match actor::Entity::find()
+			.select_only()
+			.column(actor::Column::Id)
+			.filter(actor::Column::Address.eq(address))
+			.into_tuple::<i64>()
+			.one(self.inner())
+			.await?
+		{
+			None => return Ok(false),
+			Some(id) => id,
+		};
+
+		let model = abandoned_actor::ActiveModel {
+			actor_id: Set(actor_id),
+			since: Set(current_timestamp() as i64),
+		};
+		abandoned_actor::Entity::insert(model)
+			.on_conflict(
+				OnConflict::column(abandoned_actor::Column::ActorId)
+					.do_nothing()
+					.to_owned(),
+			)
+			.exec_without_returning(self.inner())
+			.await?;
+		Ok(true)
+	}
+
+	/// Returns the actors that have been abandoned before the given timestamp.
+	pub async fn fetch_abandoned_actors(
+		&self, abandoned_before: i64,
+	) -> Result<Vec<(i64, ActorAddress)>> {
+		let results = abandoned_actor::Entity::find()
+			.find_also_related(actor::Entity)
+			.filter(abandoned_actor::Column::Since.lt(abandoned_before))
+			.all(self.inner())
+			.await?;
+		Ok(results
+			.into_iter()
+			.filter_map(|(record, actor)| actor.map(|a| (record.actor_id, a.address)))
+			.collect())
+	}
+
+	/// Whether the actor is still followed, or is one of our own identities.
+	pub async fn is_actor_in_use(&self, address: &ActorAddress) -> Result<bool> {
+		let following = following::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(address))
+			.count(self.inner())
+			.await?;
+		let identities = identity::Entity::find()
+			.inner_join(actor::Entity)
+			.filter(actor::Column::Address.eq(address))
+			.count(self.inner())
+			.await?;
+		Ok(following > 0 || identities > 0)
+	}
+
+	/// Lifts the abandonment of the actor, without removing anything.
+	pub async fn keep_actor(&self, actor_id: i64) -> Result<()> {
+		abandoned_actor::Entity::delete_by_id(actor_id)
+			.exec(self.inner())
+			.await?;
+		Ok(())
+	}
+
+	/// Removes all objects of the actor that aren't bookmarked, and the files
+	/// and blocks that are not used by any other object. The actor itself is
+	/// still remembered.
+	/// Returns the number of objects that have been removed.
+	pub async fn purge_actor(&self, actor_id: i64) -> Result<u64> {
+		let tx = self.transaction().await?;
+		let removed = tx
+			.delete_objects(
+				"actor_id = ? AND hash NOT IN (SELECT object_hash FROM bookmark)",
+				vec![actor_id.into()],
+			)
+			.await?;
+		abandoned_actor::Entity::delete_by_id(actor_id)
+			.exec(tx.inner())
+			.await?;
+		let orphaned_blocks = tx.delete_orphans().await?;
+		tx.commit().await?;
+
+		// Only remove the data once the records are gone for sure
+		self.remove_block_data(orphaned_blocks).await?;
+		Ok(removed)
+	}
+}
+
+impl Transaction {
+	/// Removes the objects that match the condition on the object table,
+	/// together with everything that refers to them.
+	/// Returns the number of objects that have been removed.
+	pub(super) async fn delete_objects(&self, condition: &str, values: Vec<Value>) -> Result<u64> {
+		let object_ids = format!("SELECT id FROM object WHERE {}", condition);
+		for table in [
+			"post_file",
+			"post_mention",
+			"post_tag",
+			"post_object",
+			"share_object",
+			"key_rotation_object",
+			"edit_object",
+			"delete_object",
+			"tombstone",
+			"reaction_object",
+			"poll_option",
+			"poll_object",
+			"vote_object",
+			"follower_list_entry",
+			"profile_object",
+			"search_queue",
+		] {
+			self.execute_with_values(
+				&format!("DELETE FROM {} WHERE object_id IN ({})", table, object_ids),
+				values.clone(),
+			)
+			.await?;
+		}
+		// Consolidated objects of type 0 refer to the object table
+		self.execute_with_values(
+			&format!(
+				"DELETE FROM consolidated_object WHERE type = 0 AND object_id IN ({})",
+				object_ids
+			),
+			values.clone(),
+		)
+		.await?;
+		self.execute_with_values(
+			&format!("DELETE FROM search_index WHERE rowid IN ({})", object_ids),
+			values.clone(),
+		)
+		.await?;
+		self.execute_with_values(&format!("DELETE FROM object WHERE {}", condition), values)
+			.await
+	}
+
+	/// Removes the files that no object refers to, along with their thumbnails
+	/// and parity blocks, and the blocks that no file refers to. Returns the
+	/// hashes of the blocks, of which the data is to be removed from the block
+	/// store once the transaction has been committed.
+	pub(super) async fn delete_orphans(&self) -> Result<Vec<IdType>> {
+		self.inner()
+			.execute_unprepared(&format!(
+				"DELETE FROM file_block WHERE file_id IN (SELECT id FROM file WHERE {})",
+				ORPHANED_FILE_CONDITION
+			))
+			.await?;
+		self.inner()
+			.execute_unprepared(&format!(
+				"DELETE FROM file WHERE {}",
+				ORPHANED_FILE_CONDITION
+			))
+			.await?;
+		self.inner()
+			.execute_unprepared(
+				"DELETE FROM file_thumbnail WHERE file_hash NOT IN (SELECT hash FROM file)",
+			)
+			.await?;
+		self.inner()
+			.execute_unprepared(
+				"DELETE FROM parity_block WHERE file_hash NOT IN (SELECT hash FROM file)",
+			)
+			.await?;
+		self.delete_orphaned_blocks().await
+	}
+
+	/// Removes the blocks that no file refers to. Returns their hashes, of
+	/// which the data is to be removed from the block store once the
+	/// transaction has been committed.
+	pub(super) async fn delete_orphaned_blocks(&self) -> Result<Vec<IdType>> {
+		let orphaned_blocks = block::Entity::find()
+			.select_only()
+			.column(block::Column::Hash)
+			.filter(Expr::cust("hash NOT IN (SELECT block_hash FROM file_block)"))
+			.into_tuple::<IdType>()
+			.all(self.inner())
+			.await?;
+		// The data of archived blocks stays behind in the segment files
+		self.inner()
+			.execute_unprepared(
+				r#"
+			DELETE FROM archived_block WHERE block_id IN (
+				SELECT id FROM block WHERE hash NOT IN (SELECT block_hash FROM file_block)
+			);
+			DELETE FROM block WHERE hash NOT IN (SELECT block_hash FROM file_block);
+		"#,
+			)
+			.await?;
+		Ok(orphaned_blocks)
+	}
+
+	async fn execute_with_values(&self, sql: &str, values: Vec<Value>) -> Result<u64> {
+		let result = self
+			.inner()
+			.execute(Statement::from_sql_and_values(self.backend(), sql, values))
+			.await?;
+		Ok(result.rows_affected())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		common::*, core::*, db::SyncDepth, identity::ActorPrivateKeyV1, net::binserde, test,
+	};
+
+	#[tokio::test]
+	async fn test_purge_actor() {
+		let db = test::load_database("db").await;
+		let mut rng = test::initialize_rng();
+
+		// Follow an actor that has a post with a file
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let actor_info = ActorInfo::V1(ActorInfoV1 {
+			flags: 0,
+			public_key: private_key.public(),
+			first_object: IdType::random(&mut rng),
+			actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+		});
+		let file_data = FileData {
+			mime_type: "text/markdown".into(),
+			data: "Hello".as_bytes().to_vec(),
+		};
+		let tx = db.transaction().await.unwrap();
+		let (_, file_hash, _) = tx.create_file(&file_data).await.unwrap();
+		tx.commit().await.unwrap();
+
+		let payload = ObjectPayload::Post(PostObject {
+			in_reply_to: None,
+			data: PostObjectCryptedData::Plain(PostObjectDataPlain {
+				tags: Vec::new().into(),
+				files: vec![file_hash.clone()].into(),
+			}),
+		});
+		let signature = private_key.sign(&binserde::serialize(&payload).unwrap());
+		let object = BlogchainObject {
+			signature: signature.clone(),
+			sequence: 0,
+			previous_hash: IdType::default(),
+			created: 1,
+			payload,
+			delegation: None,
+		};
+		let object_hash = signature.hash();
+		let mut c = db.connect_old().unwrap();
+		c.follow(&address, &actor_info, &SyncDepth::default()).unwrap();
+		let stored = c.store_object(&address, &object_hash, &object, true).unwrap();
+		assert!(stored);
+
+		// An actor that is still followed is in use
+		assert!(db.abandon_actor(&address).await.unwrap());
+		let abandoned = db.fetch_abandoned_actors(i64::MAX).await.unwrap();
+		assert_eq!(abandoned.len(), 1);
+		let (actor_id, _) = abandoned[0].clone();
+		assert!(db.is_actor_in_use(&address).await.unwrap());
+
+		c.unfollow(&address).unwrap();
+		assert!(!db.is_actor_in_use(&address).await.unwrap());
+		// The grace period hasn't passed yet
+		assert_eq!(db.fetch_abandoned_actors(0).await.unwrap().len(), 0);
+
+		assert_eq!(db.purge_actor(actor_id).await.unwrap(), 1);
+		let abandoned = db.fetch_abandoned_actors(i64::MAX).await.unwrap();
+		assert_eq!(abandoned.len(), 0);
+		assert!(c.fetch_object(&object_hash).unwrap().is_none());
+		assert!(db.load_file_data(&file_hash).await.unwrap().is_none());
+	}
+}
@@ -0,0 +1,149 @@
+//! Reactions with an emoji to posts.
+//!
+//! A reaction is an object in the chain of the actor that reacts, so the
+//! reactions to a post are only known for the actors that this node stores.
+//! Each actor counts once per emoji, and emojis that are used equally often
+//! are ordered by which was used first.
+
+use sea_orm::{prelude::*, Statement};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::{current_timestamp, IdType},
+	core::ActorAddress,
+};
+
+
+/// Reactions that have been found longer ago than this, in milliseconds, are
+/// not notified of anymore.
+const NOTIFY_MAX_AGE: u64 = 7 * 24 * 60 * 60 * 1000;
+
+
+/// A reaction to a post of one of our identities that hasn't been notified of
+/// yet.
+#[derive(Clone, Debug)]
+pub struct NewReaction {
+	pub identity: ActorAddress,
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+	pub emoji: String,
+}
+
+
+/// Whether the text can be used as a reaction. It needs to be short, and not
+/// look like a word.
+pub fn is_valid_emoji(text: &str) -> bool {
+	text.len() > 0
+		&& text.len() <= 32
+		&& !text
+			.chars()
+			.any(|c| c.is_whitespace() || c.is_ascii_alphabetic())
+}
+
+impl Database {
+	/// Whether the actor has reacted to the post with the emoji already.
+	pub async fn has_reacted(
+		&self, actor_id: i64, target_address: &ActorAddress, object_hash: &IdType, emoji: &str,
+	) -> Result<bool> {
+		let result = self
+			.inner()
+			.query_one(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT 1
+				FROM reaction_object AS r
+				INNER JOIN object AS o ON o.id = r.object_id
+				WHERE o.actor_id = ? AND r.actor_address = ? AND r.object_hash = ?
+					AND r.emoji = ?
+			"#,
+				[actor_id.into(), target_address.into(), object_hash.into(), emoji.into()],
+			))
+			.await?;
+		Ok(result.is_some())
+	}
+
+	/// Counts the reactions to the post, by emoji, the most used one first.
+	pub async fn load_reactions(
+		&self, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> Result<Vec<(String, u64)>> {
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT r.emoji, COUNT(DISTINCT o.actor_id) AS count
+				FROM reaction_object AS r
+				INNER JOIN object AS o ON o.id = r.object_id
+				WHERE r.actor_address = ? AND r.object_hash = ?
+				GROUP BY r.emoji
+				ORDER BY count DESC, MIN(o.id)
+			"#,
+				[actor_address.into(), object_hash.into()],
+			))
+			.await?;
+		let mut reactions = Vec::with_capacity(results.len());
+		for r in results {
+			let count: i64 = r.try_get_by_index(1)?;
+			reactions.push((r.try_get_by_index(0)?, count as u64));
+		}
+		Ok(reactions)
+	}
+
+	/// Loads the reactions to the posts of our identities by other actors that
+	/// haven't been notified of yet, and marks them as notified.
+	pub async fn take_new_reactions(&self) -> Result<Vec<NewReaction>> {
+		let min_found = current_timestamp().saturating_sub(NOTIFY_MAX_AGE);
+		let tx = self.transaction().await?;
+		let results = tx
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				tx.backend(),
+				r#"
+				SELECT r.actor_address, a.address, r.object_hash, r.emoji
+				FROM reaction_object AS r
+				INNER JOIN object AS o ON o.id = r.object_id
+				INNER JOIN actor AS a ON a.id = o.actor_id
+				WHERE NOT r.notified AND o.found >= ?
+					AND r.actor_address IN (
+						SELECT address FROM actor WHERE id IN (SELECT actor_id FROM identity)
+					)
+					AND o.actor_id NOT IN (SELECT actor_id FROM identity)
+				ORDER BY o.found
+			"#,
+				[min_found.into()],
+			))
+			.await?;
+		let mut reactions = Vec::with_capacity(results.len());
+		for r in results {
+			reactions.push(NewReaction {
+				identity: r.try_get_by_index(0)?,
+				actor_address: r.try_get_by_index(1)?,
+				object_hash: r.try_get_by_index(2)?,
+				emoji: r.try_get_by_index(3)?,
+			});
+		}
+
+		tx.inner()
+			.execute_unprepared("UPDATE reaction_object SET notified = TRUE WHERE NOT notified")
+			.await?;
+		tx.commit().await?;
+		Ok(reactions)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_valid_emoji() {
+		assert!(is_valid_emoji("👍"));
+		assert!(is_valid_emoji("❤️"));
+		assert!(is_valid_emoji("1️⃣"));
+		assert!(!is_valid_emoji(""));
+		assert!(!is_valid_emoji("like"));
+		assert!(!is_valid_emoji("👍 👍"));
+		assert!(!is_valid_emoji(&"👍".repeat(9)));
+	}
+}
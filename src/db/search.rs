@@ -0,0 +1,446 @@
+//! A full-text index over the posts and profiles that are stored locally.
+//!
+//! The text of a post, or the description of a profile, is kept in a file that
+//! is often only downloaded some time after the object itself. So storing an
+//! object only puts it in the search queue. The queue is worked through before
+//! searching, and an object stays in it until its text has been downloaded
+//! completely. The index itself is an FTS5 table, of which the row IDs are the
+//! IDs of the objects. The hashtags and mentions in the message of a post are
+//! stored while indexing it as well.
+//!
+//! The objects are indexed while they come in, so that searching doesn't do
+//! any of that work. The new message of an edit is indexed as well, and a post
+//! is only found by the text of its latest version. Search results are
+//! filtered by visibility, and by the actors that the searcher has muted or
+//! blocked.
+
+use sea_orm::{prelude::*, QueryOrder, Statement};
+
+use super::{mention, tag, Database, DerefConnection, Error, PersistenceHandle, Result};
+use crate::{common::*, compression::decompress, core::*, entity::*};
+
+
+/// The number of bytes of the text of an object that are indexed at most.
+const MAX_TEXT_LENGTH: usize = 0x10000;
+/// How many words around the match are shown in the snippet.
+const SNIPPET_WORDS: u32 = 24;
+
+
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+	pub object_hash: IdType,
+	pub object_type: u8,
+	pub actor_address: ActorAddress,
+	pub created: u64,
+	/// The part of the text that matched.
+	pub snippet: String,
+}
+
+
+/// Puts the object in the search queue, on a connection of the old kind.
+pub(super) fn queue_object_old(tx: &impl DerefConnection, object_id: i64) -> Result<()> {
+	tx.execute(
+		"INSERT OR IGNORE INTO search_queue (object_id) VALUES (?)",
+		[object_id],
+	)?;
+	Ok(())
+}
+
+/// Puts the object in the search queue. Pass the transaction that stores the
+/// object, so that it is only queued if the object is stored.
+pub async fn queue_object(db: &impl PersistenceHandle, object_id: i64) -> Result<()> {
+	db.inner()
+		.execute(Statement::from_sql_and_values(
+			db.backend(),
+			"INSERT OR IGNORE INTO search_queue (object_id) VALUES (?)",
+			[object_id.into()],
+		))
+		.await?;
+	Ok(())
+}
+
+/// Removes the object from the index and from the search queue, on a
+/// connection of the old kind.
+pub(super) fn remove_object_old(tx: &impl DerefConnection, object_id: i64) -> Result<()> {
+	tx.execute("DELETE FROM search_index WHERE rowid = ?", [object_id])?;
+	tx.execute("DELETE FROM search_queue WHERE object_id = ?", [object_id])?;
+	Ok(())
+}
+
+/// Builds the FTS5 query that matches all words of the input, without letting
+/// any of them be taken as an operator.
+fn compose_match_query(input: &str) -> String {
+	input
+		.split_whitespace()
+		.map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Indexes the text of the object if it is a post or a profile. Returns false
+/// if its text hasn't been downloaded completely yet.
+async fn index_object(db: &impl PersistenceHandle, object_id: i64) -> Result<bool> {
+	let mut text = String::new();
+	if post_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?
+		.is_some()
+	{
+		// The message of a post is its first file
+		if let Some(file) = post_file::Entity::find()
+			.filter(post_file::Column::ObjectId.eq(object_id))
+			.order_by_asc(post_file::Column::Sequence)
+			.one(db.inner())
+			.await?
+		{
+			match load_text(db, &file.hash).await? {
+				Some(t) => text = t,
+				None => return Ok(false),
+			}
+			tag::store_tags(db, object_id, &tag::parse_hashtags(&text)).await?;
+			mention::store_mentions(db, object_id, &mention::parse_mentions(&text)).await?;
+		}
+	} else if edit_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?
+		.is_some()
+	{
+		// The new message of an edit is found in place of the edited post
+		if let Some(file) = post_file::Entity::find()
+			.filter(post_file::Column::ObjectId.eq(object_id))
+			.order_by_asc(post_file::Column::Sequence)
+			.one(db.inner())
+			.await?
+		{
+			match load_text(db, &file.hash).await? {
+				Some(t) => text = t,
+				None => return Ok(false),
+			}
+		}
+	} else if let Some(profile) = profile_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?
+	{
+		text = profile.name;
+		if let Some(hash) = &profile.description_file_hash {
+			match load_text(db, hash).await? {
+				Some(t) => {
+					text.push('\n');
+					text.push_str(&t);
+				}
+				None => return Ok(false),
+			}
+		}
+	}
+
+	db.inner()
+		.execute(Statement::from_sql_and_values(
+			db.backend(),
+			"DELETE FROM search_index WHERE rowid = ?",
+			[object_id.into()],
+		))
+		.await?;
+	if text.trim().len() > 0 {
+		db.inner()
+			.execute(Statement::from_sql_and_values(
+				db.backend(),
+				"INSERT INTO search_index (rowid, text) VALUES (?, ?)",
+				[object_id.into(), text.into()],
+			))
+			.await?;
+	}
+	Ok(true)
+}
+
+/// Loads the text in the file. Returns None if the file hasn't been downloaded
+/// completely yet, and an empty string if it doesn't contain text.
+async fn load_text(db: &impl PersistenceHandle, hash: &IdType) -> Result<Option<String>> {
+	let file = match file::Entity::find()
+		.filter(file::Column::Hash.eq(hash))
+		.one(db.inner())
+		.await?
+	{
+		Some(f) => f,
+		None => return Ok(None),
+	};
+	if !file.mime_type.starts_with("text/") {
+		return Ok(Some(String::new()));
+	}
+	let compression_type = match CompressionType::from_u8(file.compression_type) {
+		Some(c) => c,
+		None => Err(Error::InvalidCompressionType(file.compression_type))?,
+	};
+
+	let data = match db
+		.find_file_data(file.id, &file.plain_hash, file.block_count)
+		.await
+	{
+		Ok(Some(d)) => d,
+		Ok(None) => return Ok(None),
+		Err(e) => match &*e {
+			Error::FileMissingBlock(..) => return Ok(None),
+			_ => return Err(e),
+		},
+	};
+	let data = if compression_type != CompressionType::None {
+		decompress(compression_type, &data).map_err(|e| Error::from(e))?
+	} else {
+		data
+	};
+
+	let mut text =
+		String::from_utf8_lossy(&data[..data.len().min(MAX_TEXT_LENGTH)]).into_owned();
+	// A character may have been cut in half at the end
+	if text.ends_with(char::REPLACEMENT_CHARACTER) {
+		text.pop();
+	}
+	Ok(Some(text))
+}
+
+impl Database {
+	/// Finds the posts and profiles that contain all words of the query, the
+	/// best matches first. A match on an edit is returned as a match on the
+	/// edited post, and older versions of a post aren't matched at all.
+	///
+	/// The objects of private identities and of followers-only actors are left
+	/// out unless `include_private` is set. If `searcher` is given, the objects
+	/// of the actors that it has muted or blocked are left out as well.
+	pub async fn search(
+		&self, query: &str, include_private: bool, searcher: Option<i64>, limit: u64,
+		offset: u64,
+	) -> Result<Vec<SearchHit>> {
+		let match_query = compose_match_query(query);
+		if match_query.len() == 0 {
+			return Ok(Vec::new());
+		}
+
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT COALESCE(e.edited_object_hash, o.hash),
+					CASE WHEN e.object_id IS NULL THEN o.type ELSE ? END,
+					a.address, o.created, snippet(search_index, 0, '', '', '…', ?)
+				FROM search_index
+				INNER JOIN object AS o ON o.id = search_index.rowid
+				INNER JOIN actor AS a ON a.id = o.actor_id
+				LEFT JOIN edit_object AS e ON e.object_id = o.id
+				WHERE search_index MATCH ? AND NOT EXISTS (
+					SELECT 1
+					FROM edit_object AS le
+					INNER JOIN object AS lo ON lo.id = le.object_id
+					WHERE le.edited_object_hash = COALESCE(e.edited_object_hash, o.hash)
+						AND lo.actor_id = o.actor_id AND lo.sequence > o.sequence
+				) AND (? OR (
+					o.actor_id NOT IN (SELECT actor_id FROM identity WHERE is_private)
+					AND o.actor_id NOT IN (
+						SELECT po.actor_id
+						FROM object AS po
+						INNER JOIN profile_object AS p ON p.object_id = po.id
+						WHERE p.followers_only AND po.sequence = (
+							SELECT MAX(lpo.sequence)
+							FROM object AS lpo
+							INNER JOIN profile_object AS lp ON lp.object_id = lpo.id
+							WHERE lpo.actor_id = po.actor_id
+						)
+					)
+				)) AND a.address NOT IN (
+					SELECT actor_address FROM actor_block
+					WHERE actor_id = ? AND actor_address IS NOT NULL
+				)
+				ORDER BY search_index.rank
+				LIMIT ? OFFSET ?
+			"#,
+				[
+					OBJECT_TYPE_POST.into(),
+					SNIPPET_WORDS.into(),
+					match_query.into(),
+					include_private.into(),
+					searcher.into(),
+					limit.into(),
+					offset.into(),
+				],
+			))
+			.await?;
+
+		let mut hits = Vec::with_capacity(results.len());
+		for r in results {
+			let created: i64 = r.try_get_by_index(3)?;
+			hits.push(SearchHit {
+				object_hash: r.try_get_by_index(0)?,
+				object_type: r.try_get_by_index(1)?,
+				actor_address: r.try_get_by_index(2)?,
+				created: created as _,
+				snippet: r.try_get_by_index(4)?,
+			});
+		}
+		Ok(hits)
+	}
+
+	/// Indexes the objects in the search queue of which the text has been
+	/// downloaded by now. Returns the number of objects that have been indexed.
+	pub async fn update_search_index(&self) -> Result<usize> {
+		let results = self
+			.inner()
+			.query_all(Statement::from_string(
+				self.backend(),
+				"SELECT object_id FROM search_queue ORDER BY object_id",
+			))
+			.await?;
+
+		let tx = self.transaction().await?;
+		let mut indexed = 0;
+		for r in results {
+			let object_id: i64 = r.try_get_by_index(0)?;
+			if index_object(&tx, object_id).await? {
+				tx.inner()
+					.execute(Statement::from_sql_and_values(
+						tx.backend(),
+						"DELETE FROM search_queue WHERE object_id = ?",
+						[object_id.into()],
+					))
+					.await?;
+				indexed += 1;
+			}
+		}
+		tx.commit().await?;
+		Ok(indexed)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		db::{block_list::BlockTarget, SyncDepth},
+		identity::ActorPrivateKeyV1,
+		net::binserde,
+		test,
+	};
+
+	async fn search(db: &Database, query: &str) -> Vec<SearchHit> {
+		db.search(query, true, None, 10, 0).await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_search() {
+		let db = test::load_database("search").await;
+		let mut rng = test::initialize_rng();
+
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let actor_info = ActorInfo::V1(ActorInfoV1 {
+			flags: 0,
+			public_key: private_key.public(),
+			first_object: IdType::random(&mut rng),
+			actor_type: ACTOR_TYPE_BLOGCHAIN.into(),
+		});
+		let file_data = FileData {
+			mime_type: "text/markdown".into(),
+			data: "An old post about sailing boats #Boats".as_bytes().to_vec(),
+		};
+		let tx = db.transaction().await.unwrap();
+		let (_, file_hash, _) = tx.create_file(&file_data).await.unwrap();
+		tx.commit().await.unwrap();
+
+		// One post of which the message has been downloaded, and one of which it
+		// hasn't
+		let mut c = db.connect_old().unwrap();
+		c.follow(&address, &actor_info, &SyncDepth::default()).unwrap();
+		let mut hashes = Vec::new();
+		for (sequence, hash) in [file_hash, IdType::random(&mut rng)].into_iter().enumerate() {
+			let payload = ObjectPayload::Post(PostObject {
+				in_reply_to: None,
+				data: PostObjectCryptedData::Plain(PostObjectDataPlain {
+					tags: Vec::new().into(),
+					files: vec![hash].into(),
+				}),
+			});
+			let signature = private_key.sign(&binserde::serialize(&payload).unwrap());
+			let object = BlogchainObject {
+				signature: signature.clone(),
+				sequence: sequence as _,
+				previous_hash: IdType::default(),
+				created: 1,
+				payload,
+				delegation: None,
+			};
+			let object_hash = signature.hash();
+			assert!(c.store_object(&address, &object_hash, &object, true).unwrap());
+			hashes.push(object_hash);
+		}
+
+		assert_eq!(db.update_search_index().await.unwrap(), 1);
+		// The other one stays in the queue
+		assert_eq!(db.update_search_index().await.unwrap(), 0);
+
+		let hits = db.search("Sailing  post", true, None, 10, 0).await.unwrap();
+		assert_eq!(hits.len(), 1);
+		assert_eq!(hits[0].object_hash, hashes[0]);
+		assert_eq!(hits[0].actor_address, address);
+		assert!(hits[0].snippet.contains("sailing"));
+		assert_eq!(search(&db, "sailing cars").await.len(), 0);
+		// Operators are taken literally
+		assert_eq!(search(&db, "\"sailing OR").await.len(), 0);
+		assert_eq!(search(&db, "  ").await.len(), 0);
+		let object_id = object::Entity::find()
+			.filter(object::Column::Hash.eq(&hashes[0]))
+			.one(db.inner())
+			.await
+			.unwrap()
+			.unwrap()
+			.id;
+		assert_eq!(db.load_tagged_posts("#boats", false, 10, 0).await.unwrap(), vec![object_id]);
+
+
+		// Nothing is found of the actors that the searcher has muted
+		let actor_id = object::Entity::find_by_id(object_id)
+			.one(db.inner())
+			.await
+			.unwrap()
+			.unwrap()
+			.actor_id;
+		db.block_actor(actor_id, &BlockTarget::Actor(address.clone()), false)
+			.await
+			.unwrap();
+		assert_eq!(search(&db, "sailing").await.len(), 1);
+		let hits = db.search("sailing", false, Some(actor_id), 10, 0).await.unwrap();
+		assert_eq!(hits.len(), 0);
+
+		assert!(c.delete_object(&address, &hashes[0]).unwrap());
+		assert_eq!(search(&db, "sailing").await.len(), 0);
+
+		// The other post is only found by the message of its latest edit
+		for (sequence, text) in [(2, "Now about ships"), (3, "Now about planes")] {
+			let file_data = FileData {
+				mime_type: "text/markdown".into(),
+				data: text.as_bytes().to_vec(),
+			};
+			let tx = db.transaction().await.unwrap();
+			let (_, file_hash, _) = tx.create_file(&file_data).await.unwrap();
+			tx.commit().await.unwrap();
+			let payload = ObjectPayload::Edit(EditObject {
+				object_hash: hashes[1].clone(),
+				files: vec![file_hash].into(),
+			});
+			let signature = private_key.sign(&binserde::serialize(&payload).unwrap());
+			let object = BlogchainObject {
+				signature: signature.clone(),
+				sequence,
+				previous_hash: IdType::default(),
+				created: 1,
+				payload,
+				delegation: None,
+			};
+			assert!(c.store_object(&address, &signature.hash(), &object, true).unwrap());
+		}
+		assert_eq!(db.update_search_index().await.unwrap(), 2);
+		assert_eq!(search(&db, "ships").await.len(), 0);
+		let hits = search(&db, "planes").await;
+		assert_eq!(hits.len(), 1);
+		assert_eq!(hits[0].object_hash, hashes[1]);
+		assert_eq!(hits[0].object_type, OBJECT_TYPE_POST);
+	}
+}
@@ -0,0 +1,161 @@
+//! The hashtags of posts.
+//!
+//! Posts carry a list of tags themselves, but most clients only write their
+//! hashtags in the message. So the hashtags in the message are added to the
+//! tags of the post once the message has been downloaded, which happens when
+//! the post is indexed for searching. Tags are stored in lower case without
+//! the `#`, so that they can be looked up regardless of how they were written.
+
+use sea_orm::{prelude::*, Statement};
+
+use super::{Database, DerefConnection, PersistenceHandle, Result};
+
+
+/// The number of bytes a tag can have at most, which is the limit that posts
+/// put on their tags.
+const MAX_TAG_LENGTH: usize = 32;
+/// The number of tags a post can have at most.
+pub const MAX_TAGS: usize = 64;
+
+
+/// Turns a tag into the form in which it is stored, or returns `None` if it
+/// isn't a valid tag.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+	let tag = tag.strip_prefix('#').unwrap_or(tag).to_lowercase();
+	if tag.len() == 0
+		|| tag.len() > MAX_TAG_LENGTH
+		|| !tag.chars().all(is_tag_char)
+		// Numbers are often preceded by a # without being meant as a tag
+		|| tag.chars().all(|c| c.is_ascii_digit())
+	{
+		return None;
+	}
+	Some(tag)
+}
+
+/// Finds the hashtags in the text, in the order in which they first appear.
+pub fn parse_hashtags(text: &str) -> Vec<String> {
+	let mut tags = Vec::new();
+	let mut previous = None;
+	let mut chars = text.char_indices().peekable();
+	while let Some((i, c)) = chars.next() {
+		// A # in the middle of a word, like in a URL or in a character
+		// reference, doesn't start a tag
+		let starts_tag = c == '#'
+			&& !matches!(previous, Some(p) if is_tag_char(p) || p == '#' || p == '&' || p == '/');
+		previous = Some(c);
+		if !starts_tag {
+			continue;
+		}
+
+		let mut end = i + 1;
+		while let Some(&(j, c)) = chars.peek() {
+			if !is_tag_char(c) {
+				break;
+			}
+			end = j + c.len_utf8();
+			previous = Some(c);
+			chars.next();
+		}
+		if let Some(tag) = normalize_tag(&text[i + 1..end]) {
+			if !tags.contains(&tag) {
+				tags.push(tag);
+				if tags.len() == MAX_TAGS {
+					break;
+				}
+			}
+		}
+	}
+	tags
+}
+
+fn is_tag_char(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+
+/// Stores the tags of the post, on a connection of the old kind. Tags that are
+/// invalid or that the post has already are left out.
+pub(super) fn store_tags_old(
+	tx: &impl DerefConnection, object_id: i64, tags: &[String],
+) -> Result<()> {
+	for tag in tags.iter().filter_map(|t| normalize_tag(t)) {
+		tx.execute(
+			"INSERT OR IGNORE INTO post_tag (object_id, tag) VALUES (?, ?)",
+			rusqlite::params![object_id, tag],
+		)?;
+	}
+	Ok(())
+}
+
+/// Stores the tags of the post. Tags that are invalid or that the post has
+/// already are left out.
+pub(super) async fn store_tags(
+	db: &impl PersistenceHandle, object_id: i64, tags: &[String],
+) -> Result<()> {
+	for tag in tags.iter().filter_map(|t| normalize_tag(t)) {
+		db.inner()
+			.execute(Statement::from_sql_and_values(
+				db.backend(),
+				"INSERT OR IGNORE INTO post_tag (object_id, tag) VALUES (?, ?)",
+				[object_id.into(), tag.into()],
+			))
+			.await?;
+	}
+	Ok(())
+}
+
+impl Database {
+	/// Finds the IDs of the posts with the given tag, the newest first. The
+	/// posts of private identities are left out unless `include_private` is
+	/// set.
+	pub async fn load_tagged_posts(
+		&self, tag: &str, include_private: bool, limit: u64, offset: u64,
+	) -> Result<Vec<i64>> {
+		let tag = match normalize_tag(tag) {
+			Some(t) => t,
+			None => return Ok(Vec::new()),
+		};
+
+		let results = self
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				self.backend(),
+				r#"
+				SELECT o.id
+				FROM post_tag AS t
+				INNER JOIN object AS o ON o.id = t.object_id
+				WHERE t.tag = ? AND (? OR o.actor_id NOT IN (
+					SELECT actor_id FROM identity WHERE is_private
+				))
+				ORDER BY o.created DESC
+				LIMIT ? OFFSET ?
+			"#,
+				[
+					tag.into(),
+					include_private.into(),
+					limit.into(),
+					offset.into(),
+				],
+			))
+			.await?;
+		let mut ids = Vec::with_capacity(results.len());
+		for r in results {
+			ids.push(r.try_get_by_index(0)?);
+		}
+		Ok(ids)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_hashtags() {
+		let text = "#Rust is nice. #rust and #Ελληνικά, but not a#b, #1, &#39; or \
+		            https://example.com/#anchor.\n# Heading\n#a_b!";
+		assert_eq!(parse_hashtags(text), vec!["rust", "ελληνικά", "a_b"]);
+		assert_eq!(normalize_tag("#Tag"), Some("tag".to_string()));
+		assert_eq!(normalize_tag("two words"), None);
+		assert_eq!(normalize_tag(&"a".repeat(MAX_TAG_LENGTH + 1)), None);
+	}
+}
@@ -0,0 +1,191 @@
+//! Files that are uploaded in chunks.
+//!
+//! The chunks are kept in the database until the upload is complete, so that
+//! an upload that got interrupted can continue where it left off, even after a
+//! restart. A complete upload is attached to a post by its ID, at which point
+//! it is split into blocks like any other file. Uploads that haven't been
+//! touched for a day are removed.
+
+use sea_orm::{prelude::*, NotSet, QueryOrder, Set};
+
+use super::{Database, PersistenceHandle, Result};
+use crate::{
+	common::current_timestamp,
+	core::FileData,
+	entity::{upload, upload_chunk},
+};
+
+
+/// The largest file that can be uploaded.
+pub const MAX_UPLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+/// The largest chunk that is accepted at once.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+/// The time after which an upload that hasn't received anything is removed, in
+/// milliseconds.
+const UPLOAD_MAX_AGE: u64 = 24 * 60 * 60 * 1000;
+
+
+/// What happened to a chunk that was sent for an upload.
+#[derive(Debug, PartialEq)]
+pub enum ChunkOutcome {
+	/// The chunk has been stored, and this many bytes have been received now.
+	Stored(u64),
+	/// The chunk doesn't start where the upload left off, which is at the given
+	/// offset.
+	WrongOffset(u64),
+	/// The chunk goes past the end of the file.
+	TooLarge,
+	/// The upload doesn't exist, or it has expired.
+	NotFound,
+}
+
+
+impl Database {
+	/// Starts a new upload with the given ID. The uploads that have expired are
+	/// removed at the same time.
+	pub async fn create_upload(&self, id: &str, mime_type: &str, size: u64) -> Result<()> {
+		let now = current_timestamp();
+		let tx = self.transaction().await?;
+		let expired = upload::Entity::find()
+			.select_only()
+			.column(upload::Column::Id)
+			.filter(upload::Column::Updated.lt(now.saturating_sub(UPLOAD_MAX_AGE) as i64))
+			.into_tuple::<String>()
+			.all(tx.inner())
+			.await?;
+		if expired.len() > 0 {
+			upload_chunk::Entity::delete_many()
+				.filter(upload_chunk::Column::UploadId.is_in(expired.clone()))
+				.exec(tx.inner())
+				.await?;
+			upload::Entity::delete_many()
+				.filter(upload::Column::Id.is_in(expired))
+				.exec(tx.inner())
+				.await?;
+		}
+
+		let model = upload::ActiveModel {
+			id: Set(id.to_string()),
+			mime_type: Set(mime_type.to_string()),
+			size: Set(size as i64),
+			received: Set(0),
+			updated: Set(now as i64),
+		};
+		upload::Entity::insert(model).exec(tx.inner()).await?;
+		tx.commit().await?;
+		Ok(())
+	}
+
+	/// Removes the upload with everything that has been received for it.
+	/// Returns false if it didn't exist.
+	pub async fn delete_upload(&self, id: &str) -> Result<bool> {
+		let tx = self.transaction().await?;
+		upload_chunk::Entity::delete_many()
+			.filter(upload_chunk::Column::UploadId.eq(id))
+			.exec(tx.inner())
+			.await?;
+		let result = upload::Entity::delete_by_id(id).exec(tx.inner()).await?;
+		tx.commit().await?;
+		Ok(result.rows_affected > 0)
+	}
+
+	pub async fn load_upload(&self, id: &str) -> Result<Option<upload::Model>> {
+		Ok(upload::Entity::find_by_id(id).one(self.inner()).await?)
+	}
+
+	/// Puts the chunks of the upload together. Returns `None` if the upload
+	/// doesn't exist or hasn't been completed yet.
+	pub async fn load_upload_data(&self, id: &str) -> Result<Option<FileData>> {
+		let record = match self.load_upload(id).await? {
+			Some(r) if r.received == r.size => r,
+			_ => return Ok(None),
+		};
+		let chunks = upload_chunk::Entity::find()
+			.filter(upload_chunk::Column::UploadId.eq(id))
+			.order_by_asc(upload_chunk::Column::Start)
+			.all(self.inner())
+			.await?;
+		let mut data = Vec::with_capacity(record.size as usize);
+		for chunk in chunks {
+			data.extend(chunk.data);
+		}
+		Ok(Some(FileData {
+			mime_type: record.mime_type.into(),
+			data,
+		}))
+	}
+
+	/// Stores the chunk of the upload, if it starts where the upload left off.
+	pub async fn store_upload_chunk(
+		&self, id: &str, start: u64, data: &[u8],
+	) -> Result<ChunkOutcome> {
+		let tx = self.transaction().await?;
+		let record = match upload::Entity::find_by_id(id).one(tx.inner()).await? {
+			Some(r) => r,
+			None => return Ok(ChunkOutcome::NotFound),
+		};
+		let received = record.received as u64;
+		if start != received {
+			return Ok(ChunkOutcome::WrongOffset(received));
+		}
+		if received + data.len() as u64 > record.size as u64 {
+			return Ok(ChunkOutcome::TooLarge);
+		}
+
+		let model = upload_chunk::ActiveModel {
+			id: NotSet,
+			upload_id: Set(id.to_string()),
+			start: Set(start as i64),
+			data: Set(data.to_vec()),
+		};
+		upload_chunk::Entity::insert(model).exec(tx.inner()).await?;
+		let received = received + data.len() as u64;
+		let mut record: upload::ActiveModel = record.into();
+		record.received = Set(received as i64);
+		record.updated = Set(current_timestamp() as i64);
+		record.update(tx.inner()).await?;
+		tx.commit().await?;
+		Ok(ChunkOutcome::Stored(received))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_upload() {
+		let db = test::load_database("upload").await;
+		db.create_upload("a", "text/plain", 10).await.unwrap();
+
+		assert_eq!(
+			db.store_upload_chunk("a", 0, b"Hello").await.unwrap(),
+			ChunkOutcome::Stored(5)
+		);
+		assert!(db.load_upload_data("a").await.unwrap().is_none());
+		// A chunk that got sent twice isn't stored twice
+		assert_eq!(
+			db.store_upload_chunk("a", 0, b"Hello").await.unwrap(),
+			ChunkOutcome::WrongOffset(5)
+		);
+		assert_eq!(
+			db.store_upload_chunk("a", 5, b" world").await.unwrap(),
+			ChunkOutcome::TooLarge
+		);
+		assert_eq!(
+			db.store_upload_chunk("a", 5, b" you!").await.unwrap(),
+			ChunkOutcome::Stored(10)
+		);
+		let file = db.load_upload_data("a").await.unwrap().unwrap();
+		assert_eq!(file.mime_type.as_str(), "text/plain");
+		assert_eq!(file.data, b"Hello you!");
+
+		assert!(db.delete_upload("a").await.unwrap());
+		assert_eq!(
+			db.store_upload_chunk("a", 10, b"!").await.unwrap(),
+			ChunkOutcome::NotFound
+		);
+	}
+}
@@ -0,0 +1,163 @@
+//! Gradually gives the free pages of the database file back to the file system.
+//!
+//! SQLite doesn't shrink its database file when data is deleted, it only marks
+//! the pages as free. A full `VACUUM` rewrites the whole file at once, which
+//! freezes the node for a long time on large databases. Instead, the database
+//! is put in incremental auto-vacuum mode, so that the free pages can be
+//! released a few at a time, with pauses in between.
+//!
+//! New databases are created in incremental auto-vacuum mode. Older ones need
+//! one full vacuum to be converted, which is only done when asked for with the
+//! `--enable-incremental-vacuum` argument, before the node is started.
+
+use std::time::Duration;
+
+use log::*;
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+use tokio::time::sleep;
+
+use super::{Database, PersistenceHandle, Result};
+
+
+/// The value of the `auto_vacuum` pragma for incremental mode.
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+
+/// The progress of an ongoing compaction.
+#[derive(Clone, Debug, Default)]
+pub struct VacuumProgress {
+	/// The number of pages that have been released so far.
+	pub released: u64,
+	/// The number of free pages at the start of the compaction.
+	pub total: u64,
+}
+
+
+impl Database {
+	/// Releases all free pages of the database file, `pages_per_step` pages at
+	/// a time, waiting `step_delay` in between steps so that other tasks don't
+	/// have to wait on the database for too long.
+	/// The given closure is called with the progress after each step.
+	///
+	/// Nothing is released if the database isn't in incremental auto-vacuum
+	/// mode.
+	pub async fn compact(
+		&self, pages_per_step: u32, step_delay: Duration,
+		mut on_progress: impl FnMut(&VacuumProgress) + Send,
+	) -> Result<VacuumProgress> {
+		if self.query_pragma("auto_vacuum").await? != AUTO_VACUUM_INCREMENTAL {
+			warn!(
+				"The database isn't in incremental auto-vacuum mode, so its free pages can't be \
+				 released. Run stonenetd with --enable-incremental-vacuum once to convert it."
+			);
+			return Ok(VacuumProgress::default());
+		}
+
+		let mut progress = VacuumProgress {
+			released: 0,
+			total: self.query_pragma("freelist_count").await? as _,
+		};
+		while progress.released < progress.total {
+			self.inner()
+				.execute_unprepared(&format!("PRAGMA incremental_vacuum({})", pages_per_step))
+				.await?;
+
+			let remaining = self.query_pragma("freelist_count").await? as u64;
+			let released = progress.total.saturating_sub(remaining);
+			// Stop if nothing could be released anymore, which can happen if other
+			// tasks are freeing pages at the same time.
+			if released <= progress.released {
+				break;
+			}
+			progress.released = released;
+			on_progress(&progress);
+
+			sleep(step_delay).await;
+		}
+		Ok(progress)
+	}
+
+	/// Puts the database in incremental auto-vacuum mode, if it isn't yet.
+	/// This requires a full vacuum, which rewrites the whole database file, so
+	/// it should only be done while nothing else uses the database. Returns
+	/// whether the database has been converted.
+	pub async fn enable_incremental_vacuum(&self) -> Result<bool> {
+		if self.query_pragma("auto_vacuum").await? == AUTO_VACUUM_INCREMENTAL {
+			return Ok(false);
+		}
+		// Both statements need to run on the same connection
+		self.inner()
+			.execute_unprepared("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")
+			.await?;
+		Ok(true)
+	}
+
+	async fn query_pragma(&self, name: &str) -> Result<i64> {
+		let result = self
+			.inner()
+			.query_one(Statement::from_string(
+				DatabaseBackend::Sqlite,
+				format!("PRAGMA {}", name),
+			))
+			.await?;
+		Ok(if let Some(row) = result {
+			row.try_get_by_index(0)?
+		} else {
+			0
+		})
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+	use sea_orm::EntityTrait;
+
+	use super::*;
+	use crate::{core::FileData, entity::block, test};
+
+	#[tokio::test]
+	async fn test_compact() {
+		let db = test::load_database("vacuum").await;
+		let mut rng = test::initialize_rng();
+
+		let mut file_data = FileData {
+			mime_type: "image/png".into(),
+			data: vec![0u8; 0x200000],
+		};
+		rng.fill_bytes(&mut file_data.data);
+		let tx = db.transaction().await.unwrap();
+		tx.create_file(&file_data).await.unwrap();
+		tx.commit().await.unwrap();
+		block::Entity::delete_many().exec(db.inner()).await.unwrap();
+
+		let mut steps = 0;
+		let progress = db
+			.compact(16, Duration::from_millis(0), |_| steps += 1)
+			.await
+			.unwrap();
+		assert!(progress.total > 0, "no free pages after deleting blocks");
+		assert_eq!(progress.released, progress.total);
+		assert!(steps > 1, "compaction didn't happen incrementally");
+		assert_eq!(db.query_pragma("freelist_count").await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_enable_incremental_vacuum() {
+		let db = test::load_database("vacuum_mode").await;
+		db.inner()
+			.execute_unprepared("PRAGMA auto_vacuum = NONE; VACUUM;")
+			.await
+			.unwrap();
+
+		// Compacting doesn't convert the database by itself
+		let progress = db.compact(16, Duration::from_millis(0), |_| {}).await.unwrap();
+		assert_eq!(progress.total, 0);
+		assert_ne!(db.query_pragma("auto_vacuum").await.unwrap(), AUTO_VACUUM_INCREMENTAL);
+
+		assert!(db.enable_incremental_vacuum().await.unwrap());
+		assert_eq!(db.query_pragma("auto_vacuum").await.unwrap(), AUTO_VACUUM_INCREMENTAL);
+		assert!(!db.enable_incremental_vacuum().await.unwrap());
+	}
+}
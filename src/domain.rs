@@ -0,0 +1,174 @@
+//! Proves that an actor is controlled by the owner of a domain.
+//!
+//! The owner of the domain serves a JSON document at
+//! `https://<domain>/.well-known/stonenet`, which lists the addresses of the
+//! actors that belong to the domain. Each address comes with a signature of the
+//! actor on the domain name, so that a domain can't claim an actor without the
+//! actor agreeing to it. Once a domain has been verified for an actor, it is
+//! remembered for a while, so that the actor can be shown with a badge without
+//! checking the domain every time.
+
+use std::{str::FromStr, time::Duration};
+
+use base58::FromBase58;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use sea_orm::{prelude::*, sea_query::OnConflict, NotSet, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	core::{ActorAddress, Address},
+	db::{self, Database, PersistenceHandle},
+	entity::domain_verification,
+	identity::ActorSignatureV1,
+	naming,
+	trace::Traced,
+};
+
+
+pub const WELL_KNOWN_PATH: &str = "/.well-known/stonenet";
+/// How long a verified domain is trusted before it has to be checked again, in
+/// milliseconds.
+pub const VERIFICATION_VALIDITY: i64 = 30 * 24 * 60 * 60 * 1000;
+const MAX_DOCUMENT_SIZE: usize = 0x10000;
+
+lazy_static! {
+	static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+		.timeout(Duration::from_secs(10))
+		.build()
+		.unwrap();
+}
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("database error: {0}")]
+	Database(Traced<db::Error>),
+	#[error("document is too large")]
+	DocumentTooLarge,
+	#[error("unable to fetch document: {0}")]
+	Http(#[from] reqwest::Error),
+	#[error("invalid document: {0}")]
+	InvalidDocument(#[from] serde_json::Error),
+	#[error("\"{0}\" is not a valid domain name")]
+	InvalidDomainName(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The document that is served at the well-known path of a domain.
+#[derive(Default, Deserialize, Serialize)]
+pub struct DomainDocument {
+	pub actors: Vec<DomainDocumentEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DomainDocumentEntry {
+	pub address: String,
+	/// The signature of the actor on the domain name, in base58.
+	pub signature: String,
+}
+
+
+impl From<Traced<db::Error>> for Error {
+	fn from(other: Traced<db::Error>) -> Self { Self::Database(other) }
+}
+
+impl DomainDocument {
+	/// Returns the signature that the document has for the actor, if it lists
+	/// the actor.
+	pub fn find_signature(&self, address: &ActorAddress) -> Option<ActorSignatureV1> {
+		self.actors
+			.iter()
+			.filter(|entry| match Address::from_str(entry.address.trim()) {
+				Ok(Address::Actor(a)) => &a == address,
+				_ => false,
+			})
+			.find_map(|entry| match entry.signature.trim().from_base58() {
+				Ok(buffer) if buffer.len() == 114 =>
+					Some(ActorSignatureV1::from_bytes(*array_ref![buffer, 0, 114])),
+				_ => None,
+			})
+	}
+}
+
+
+/// Brings the domain name into the form in which it is signed and stored.
+pub fn normalize_domain_name(domain: &str) -> Result<String> {
+	let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+	if !naming::is_valid_domain_name(&domain) {
+		return Err(Error::InvalidDomainName(domain));
+	}
+	Ok(domain)
+}
+
+/// Fetches the document from the well-known path of the domain.
+pub async fn fetch_document(domain: &str) -> Result<DomainDocument> {
+	let domain = normalize_domain_name(domain)?;
+	let response = HTTP_CLIENT
+		.get(format!("https://{}{}", domain, WELL_KNOWN_PATH))
+		.send()
+		.await?
+		.error_for_status()?;
+	if response.content_length().unwrap_or(0) > MAX_DOCUMENT_SIZE as u64 {
+		return Err(Error::DocumentTooLarge);
+	}
+	let body = response.bytes().await?;
+	if body.len() > MAX_DOCUMENT_SIZE {
+		return Err(Error::DocumentTooLarge);
+	}
+	Ok(serde_json::from_slice(&body)?)
+}
+
+/// Loads the domains that have been verified for the actor recently enough.
+pub async fn load_verified_domains(
+	db: &Database, address: &ActorAddress,
+) -> db::Result<Vec<String>> {
+	let threshold = Utc::now().timestamp_millis() - VERIFICATION_VALIDITY;
+	Ok(domain_verification::Entity::find()
+		.filter(domain_verification::Column::ActorAddress.eq(address))
+		.filter(domain_verification::Column::Verified.gt(threshold))
+		.order_by_asc(domain_verification::Column::Domain)
+		.all(db.inner())
+		.await?
+		.into_iter()
+		.map(|r| r.domain)
+		.collect())
+}
+
+/// Remembers that the domain has just been found to vouch for the actor.
+pub async fn store_verification(
+	db: &Database, address: &ActorAddress, domain: &str,
+) -> db::Result<()> {
+	let model = domain_verification::ActiveModel {
+		id: NotSet,
+		actor_address: Set(address.clone()),
+		domain: Set(domain.to_string()),
+		verified: Set(Utc::now().timestamp_millis()),
+	};
+	domain_verification::Entity::insert(model)
+		.on_conflict(
+			OnConflict::columns([
+				domain_verification::Column::ActorAddress,
+				domain_verification::Column::Domain,
+			])
+			.update_column(domain_verification::Column::Verified)
+			.to_owned(),
+		)
+		.exec(db.inner())
+		.await?;
+	Ok(())
+}
+
+/// Forgets that the domain has vouched for the actor, because it doesn't
+/// anymore.
+pub async fn remove_verification(
+	db: &Database, address: &ActorAddress, domain: &str,
+) -> db::Result<()> {
+	domain_verification::Entity::delete_many()
+		.filter(domain_verification::Column::ActorAddress.eq(address))
+		.filter(domain_verification::Column::Domain.eq(domain))
+		.exec(db.inner())
+		.await?;
+	Ok(())
+}
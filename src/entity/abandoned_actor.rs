@@ -0,0 +1,33 @@
+//! An `abandoned_actor` is an actor that isn't followed by any of our
+//! identities anymore, and whose objects are removed once the grace period
+//! since then has passed.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "abandoned_actor")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub actor_id: i64,
+	/// The timestamp at which the actor was abandoned, in milliseconds.
+	pub since: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::actor::Entity",
+		from = "Column::ActorId",
+		to = "super::actor::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Actor,
+}
+
+impl Related<super::actor::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Actor.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
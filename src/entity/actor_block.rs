@@ -0,0 +1,39 @@
+//! An actor that one of our identities has muted or blocked. Exactly one of
+//! `actor_address` and `activity_pub_actor_url` is set.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "actor_block")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	/// The actor of our identity that has muted or blocked the other actor.
+	pub actor_id: i64,
+	pub actor_address: Option<ActorAddress>,
+	pub activity_pub_actor_url: Option<String>,
+	/// Whether the actor is blocked, rather than only muted.
+	pub blocked: bool,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::actor::Entity",
+		from = "Column::ActorId",
+		to = "super::actor::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Actor,
+}
+
+impl Related<super::actor::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Actor.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
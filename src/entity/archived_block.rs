@@ -0,0 +1,36 @@
+//! An `archived_block` tells where the data of a block can be found, after it
+//! has been moved out of the database into an archive segment.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "archived_block")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub block_id: i64,
+	pub segment: String,
+	pub offset: i64,
+	pub length: i64,
+	pub compression_type: u8,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::block::Entity",
+		from = "Column::BlockId",
+		to = "super::block::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Block,
+}
+
+impl Related<super::block::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Block.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
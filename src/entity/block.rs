@@ -14,6 +14,7 @@ pub struct Model {
 	pub size: u32,
 	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
 	pub data: Vec<u8>,
+	pub last_access: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
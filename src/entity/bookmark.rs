@@ -0,0 +1,26 @@
+//! A `bookmark` is an object that the user has saved, to find it back later.
+//! The objects may be of our own identities or of other actors, and aren't
+//! removed by pruning or eviction as long as they are bookmarked.
+
+use sea_orm::entity::prelude::*;
+
+use crate::{common::IdType, core::ActorAddress};
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "bookmark")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+	pub created: i64,
+	/// The local user that bookmarked the object, or `None` for the owner of
+	/// the node.
+	pub user_id: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+//! A `conversation` holds the direct messages between one of our identities
+//! and another actor.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "conversation")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub identity_address: ActorAddress,
+	pub peer_address: ActorAddress,
+	/// The unix timestamp in milliseconds of the latest message.
+	pub last_activity: i64,
+	/// The number of incoming messages that haven't been read yet.
+	pub unread: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(has_many = "super::direct_message::Entity")]
+	DirectMessage,
+}
+
+impl Related<super::direct_message::Entity> for Entity {
+	fn to() -> RelationDef { Relation::DirectMessage.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+//! The payload of the objects that delete an earlier post.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "delete_object")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub object_id: i64,
+	/// The hash of the post that is deleted, which belongs to the same actor.
+	pub deleted_object_hash: IdType,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,28 @@
+//! A `delivery_queue` entry is an object announcement for a follower that
+//! couldn't be reached when the object was published.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "delivery_queue")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub actor_id: i64,
+	pub object_hash: IdType,
+	pub recipient_address: String,
+	/// The serialized contact info of the follower.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+	pub recipient_contact: Vec<u8>,
+	pub failures: u32,
+	pub next_attempt: i64,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,46 @@
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "direct_message")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub conversation_id: i64,
+	/// The hash of the sealed message. Not set for outgoing messages that
+	/// haven't been sealed yet, as they are only sealed when they are first
+	/// sent.
+	#[sea_orm(unique)]
+	pub hash: Option<IdType>,
+	pub incoming: bool,
+	pub mime_type: String,
+	pub body: String,
+	pub created: i64,
+	pub delivered: bool,
+	pub failures: i32,
+	/// When delivery of an outgoing message is tried again.
+	pub next_attempt: Option<i64>,
+	/// The serialized sealed message of an outgoing message, so that every
+	/// retry sends the very same message.
+	pub sealed: Option<Vec<u8>>,
+}
+
+#[derive(Copy, Clone, Debug, DeriveRelation, EnumIter)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::conversation::Entity",
+		from = "Column::ConversationId",
+		to = "super::conversation::Column::Id",
+		on_update = "NoAction",
+		on_delete = "Cascade"
+	)]
+	Conversation,
+}
+
+impl Related<super::conversation::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Conversation.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+//! A `domain_verification` remembers that the owner of a domain has vouched for
+//! an actor, so that the domain doesn't have to be checked every time the actor
+//! is shown.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "domain_verification")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub actor_address: ActorAddress,
+	pub domain: String,
+	/// When the domain has last been found to vouch for the actor.
+	pub verified: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,35 @@
+//! A `draft` is a post that hasn't been published yet. Its attachments are
+//! stored locally already, see `draft_file`, but nothing is announced to the
+//! network until the draft is published.
+
+use sea_orm::entity::prelude::*;
+
+use crate::{common::IdType, core::ActorAddress};
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "draft")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub message: String,
+	pub in_reply_to_actor_address: Option<ActorAddress>,
+	pub in_reply_to_object_hash: Option<IdType>,
+	pub created: i64,
+	pub updated: i64,
+	/// The local user that wrote the draft, or `None` for the owner of the
+	/// node.
+	pub user_id: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(has_many = "super::draft_file::Entity")]
+	DraftFile,
+}
+
+impl Related<super::draft_file::Entity> for Entity {
+	fn to() -> RelationDef { Relation::DraftFile.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "draft_file")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub draft_id: i64,
+	pub file_hash: IdType,
+	pub sequence: i32,
+}
+
+#[derive(Copy, Clone, Debug, DeriveRelation, EnumIter)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::draft::Entity",
+		from = "Column::DraftId",
+		to = "super::draft::Column::Id",
+		on_update = "NoAction",
+		on_delete = "Cascade"
+	)]
+	Draft,
+}
+
+impl Related<super::draft::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Draft.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
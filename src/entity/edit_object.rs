@@ -0,0 +1,35 @@
+//! The payload of the objects that edit an earlier post. The files of the new
+//! version are stored in `post_file`, like those of posts.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "edit_object")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub object_id: i64,
+	/// The hash of the post that is edited, which belongs to the same actor.
+	pub edited_object_hash: IdType,
+	pub file_count: u32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
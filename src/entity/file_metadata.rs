@@ -0,0 +1,27 @@
+//! The `file_metadata` of a media file is extracted when it is uploaded, so
+//! that posts can be laid out before their attachments have been loaded.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "file_metadata")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub file_hash: IdType,
+	pub mime_type: String,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	/// The play time in milliseconds.
+	pub duration: Option<i64>,
+	pub blurhash: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
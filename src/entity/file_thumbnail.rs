@@ -0,0 +1,23 @@
+//! The `file_thumbnail` of an image is a smaller version of it, that is shown
+//! in the feeds instead of the original.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "file_thumbnail")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub file_hash: IdType,
+	pub mime_type: String,
+	pub data: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
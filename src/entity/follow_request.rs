@@ -0,0 +1,45 @@
+//! A request of another actor to follow one of our identities, if the
+//! identity only hands out its objects to the followers it has approved.
+
+use sea_orm::entity::prelude::*;
+
+use crate::{
+	common::IdType,
+	core::{ActorAddress, NodeAddress},
+};
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "follow_request")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	/// The actor of our identity that is asked.
+	pub actor_id: i64,
+	pub requester_address: ActorAddress,
+	pub node_address: NodeAddress,
+	/// The hash of the signed request.
+	pub hash: IdType,
+	pub created: i64,
+	/// Whether the request has been accepted or rejected, or `None` if it
+	/// hasn't been decided on yet.
+	pub accepted: Option<bool>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::actor::Entity",
+		from = "Column::ActorId",
+		to = "super::actor::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Actor,
+}
+
+impl Related<super::actor::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Actor.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,36 @@
+//! One of the followers on a follower list object, in the order given by
+//! `sequence`.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::{ActorAddress, NodeAddress};
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "follower_list_entry")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub object_id: i64,
+	pub sequence: i32,
+	pub actor_address: ActorAddress,
+	pub node_address: NodeAddress,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
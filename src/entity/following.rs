@@ -7,6 +7,11 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
 	#[sea_orm(primary_key, auto_increment = false)]
 	pub actor_id: i64,
+	pub sync_object_limit: Option<i64>,
+	pub sync_max_age: Option<i64>,
+	/// Whether the actor is only followed because local users follow it, and
+	/// not by the owner of the node.
+	pub user_only: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,23 @@
+//! An `idempotency_key` remembers the outcome of a mutating operation that was
+//! requested with a key supplied by the client, so that a retried request
+//! isn't performed twice.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "idempotency_key")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub key: String,
+	pub operation: String,
+	/// The serialized result of the operation, or nothing if the operation is
+	/// still in progress.
+	pub result: Option<Vec<u8>>,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -12,6 +12,20 @@ pub struct Model {
 	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
 	pub private_key: Vec<u8>,
 	pub is_private: bool,
+	/// The entropy of the mnemonic phrase that the private key was derived
+	/// from. Identities that were created before mnemonic phrases existed
+	/// don't have one.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))", nullable)]
+	pub seed: Option<Vec<u8>>,
+	/// Whether this is the identity that is used to publish with.
+	pub is_active: bool,
+	/// The delegation certificate of the private key, if it is a device key
+	/// instead of the key of the actor itself.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))", nullable)]
+	pub delegation: Option<Vec<u8>>,
+	/// The local user that the identity belongs to, or `None` if it belongs
+	/// to the owner of the node.
+	pub user_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,31 @@
+//! A `journal_entry` records an action that the user has taken on this node.
+//! Undoing the action only marks the entry as undone.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "journal_entry")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub action: u8,
+	/// The actor address or ban target that the action applied to.
+	pub subject: String,
+	/// The hash of the object that was published, if any.
+	pub object_hash: Option<IdType>,
+	pub detail: Option<String>,
+	pub created: i64,
+	/// The timestamp at which the action was undone, if it was.
+	pub undone: Option<i64>,
+	/// The local user that took the action, or `None` for the owner of the
+	/// node.
+	pub user_id: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+//! The `key_encryption` table has a row only if the private keys in the
+//! database are encrypted with a passphrase.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "key_encryption")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub id: i64,
+	/// The salt that the encryption key is derived from the passphrase with.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+	pub salt: Vec<u8>,
+	/// A known value encrypted with the encryption key, to check the
+	/// passphrase with.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+	pub verifier: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,35 @@
+//! The payload of the objects that hand an actor over to a new key.
+
+use sea_orm::entity::prelude::*;
+
+use crate::identity::ActorSignatureV1;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "key_rotation_object")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub object_id: i64,
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+	pub new_public_key: Vec<u8>,
+	/// The signature of the new key that proves it agreed to take over.
+	pub proof: ActorSignatureV1,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
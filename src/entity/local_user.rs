@@ -0,0 +1,24 @@
+//! A user of the web interface that has its own login, identities, follows and
+//! feed, next to the owner of the node.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "local_user")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub name: String,
+	/// The Argon2 hash of the password.
+	pub password_hash: String,
+	/// Whether the user may manage the node, like its owner.
+	pub is_admin: bool,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
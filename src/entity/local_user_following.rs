@@ -0,0 +1,44 @@
+//! An actor that a local user follows. The node itself follows every actor
+//! that any of its users follows, see the `user_only` column of `following`.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "local_user_following")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub user_id: i64,
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub actor_id: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::actor::Entity",
+		from = "Column::ActorId",
+		to = "super::actor::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Actor,
+	#[sea_orm(
+		belongs_to = "super::local_user::Entity",
+		from = "Column::UserId",
+		to = "super::local_user::Column::Id",
+		on_update = "NoAction",
+		on_delete = "Cascade"
+	)]
+	LocalUser,
+}
+
+impl Related<super::actor::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Actor.def() }
+}
+
+impl Related<super::local_user::Entity> for Entity {
+	fn to() -> RelationDef { Relation::LocalUser.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,3 +1,4 @@
+pub mod abandoned_actor;
 pub mod activity_pub_actor;
 pub mod activity_pub_follower;
 pub mod activity_pub_following;
@@ -6,22 +7,58 @@ pub mod activity_pub_object;
 pub mod activity_pub_send_queue;
 pub mod activity_pub_shared_inbox;
 pub mod actor;
+pub mod actor_block;
+pub mod archived_block;
 pub mod block;
+pub mod bookmark;
 pub mod bootstrap_node_id;
 pub mod consolidated_object;
+pub mod conversation;
+pub mod delete_object;
+pub mod delivery_queue;
+pub mod direct_message;
+pub mod draft;
+pub mod draft_file;
+pub mod domain_verification;
+pub mod edit_object;
 pub mod file;
 pub mod file_block;
+pub mod file_metadata;
+pub mod file_thumbnail;
+pub mod follow_request;
+pub mod follower_list_entry;
 pub mod following;
+pub mod idempotency_key;
 pub mod identity;
+pub mod journal_entry;
+pub mod key_encryption;
+pub mod key_rotation_object;
+pub mod local_user;
+pub mod local_user_following;
+pub mod network_stats;
 pub mod node_identity;
+pub mod node_reputation;
 pub mod object;
+pub mod parity_block;
+pub mod peer_ban;
+pub mod peer_traffic;
+pub mod petname;
+pub mod poll_object;
+pub mod poll_option;
 pub mod post_file;
+pub mod post_mention;
 pub mod post_object;
 pub mod post_tag;
 pub mod profile_object;
+pub mod reaction_object;
 pub mod remembered_fingers;
+pub mod scheduled_post;
 pub mod share_object;
+pub mod tombstone;
 pub mod trust_list_checksum;
 pub mod trusted_node;
 pub mod trusted_node_trust_item;
 pub mod trusted_node_update;
+pub mod upload;
+pub mod upload_chunk;
+pub mod vote_object;
@@ -0,0 +1,29 @@
+//! The traffic of the node during one hour, summed up.
+
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+
+#[derive(Clone, Debug, Default, PartialEq, DeriveEntityModel, Eq, Serialize)]
+#[sea_orm(table_name = "network_stats")]
+pub struct Model {
+	/// The unix timestamp at which the hour started, in seconds.
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub hour: i64,
+	pub udp_bytes_in: i64,
+	pub udp_bytes_out: i64,
+	pub tcp_bytes_in: i64,
+	pub tcp_bytes_out: i64,
+	pub opened_connections: i64,
+	/// The highest number of sessions that were open at the same time.
+	pub max_sessions: i64,
+	pub relayed_sessions: i64,
+	pub relayed_bytes: i64,
+	pub find_node_lookups: i64,
+	pub find_value_lookups: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
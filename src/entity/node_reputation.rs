@@ -0,0 +1,23 @@
+//! The reputation score of another node, as it was at the time it was last
+//! updated. The score decays over time, see `net::reputation`.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::NodeAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "node_reputation")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub address: NodeAddress,
+	pub score: f64,
+	pub updated: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -20,6 +20,10 @@ pub struct Model {
 	pub verified_from_start: bool,
 	#[sea_orm(default_value = false)]
 	pub published_on_fediverse: bool,
+	/// The serialized delegation certificate, if the object is signed with a
+	/// device key.
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))", nullable)]
+	pub delegation: Option<Vec<u8>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,29 @@
+//! A `parity_block` is a parity shard of a stripe of blocks of a file, that
+//! another node has asked us to keep. Together with the other shards of the
+//! stripe, it can be used to reconstruct the blocks of the stripe that can't
+//! be found anymore.
+
+use sea_orm::entity::prelude::*;
+
+use crate::common::IdType;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "parity_block")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub file_hash: IdType,
+	/// The index of the stripe within the file.
+	pub stripe: u32,
+	/// The index of the shard within the stripe, counting the data shards.
+	pub shard: u8,
+	#[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+	pub data: Vec<u8>,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
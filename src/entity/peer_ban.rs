@@ -0,0 +1,25 @@
+//! A `peer_ban` keeps a node, or all nodes within an IP subnet, out of our
+//! network. Exactly one of `node_address` and `subnet` is set.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::NodeAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "peer_ban")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub node_address: Option<NodeAddress>,
+	#[sea_orm(unique)]
+	pub subnet: Option<String>,
+	pub reason: String,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
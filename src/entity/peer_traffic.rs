@@ -0,0 +1,24 @@
+//! The traffic that has been exchanged with another node during one day,
+//! summed up.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::NodeAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "peer_traffic")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	/// The unix timestamp at which the day started, in seconds.
+	pub day: i64,
+	pub node_address: NodeAddress,
+	pub bytes_in: i64,
+	pub bytes_out: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
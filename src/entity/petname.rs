@@ -0,0 +1,25 @@
+//! A `petname` is a name that the user has given to an actor, to recognize it
+//! by. Petnames are only known locally, and take precedence over the name the
+//! actor has given itself.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "petname")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub actor_address: ActorAddress,
+	#[sea_orm(unique)]
+	pub name: String,
+	pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,38 @@
+//! A poll, of which the options are in `poll_option`.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "poll_object")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub object_id: i64,
+	pub question: String,
+	pub multiple_choice: bool,
+	pub closes: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+	#[sea_orm(has_many = "super::poll_option::Entity")]
+	PollOption,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl Related<super::poll_option::Entity> for Entity {
+	fn to() -> RelationDef { Relation::PollOption.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
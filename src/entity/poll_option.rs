@@ -0,0 +1,32 @@
+//! One of the options of a poll, in the order given by `sequence`.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "poll_option")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub object_id: i64,
+	pub sequence: i32,
+	pub text: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::poll_object::Entity",
+		from = "Column::ObjectId",
+		to = "super::poll_object::Column::ObjectId",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	PollObject,
+}
+
+impl Related<super::poll_object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::PollObject.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,37 @@
+//! An actor that is mentioned in the message of a post, with `@` and its
+//! address.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "post_mention")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub object_id: i64,
+	pub actor_address: ActorAddress,
+	/// Whether a notification has been given for the mention already, if the
+	/// mentioned actor is one of our identities.
+	pub notified: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::post_object::Entity",
+		from = "Column::ObjectId",
+		to = "super::post_object::Column::ObjectId",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	PostObject,
+}
+
+impl Related<super::post_object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::PostObject.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
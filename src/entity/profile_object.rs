@@ -13,6 +13,7 @@ pub struct Model {
 	pub avatar_file_hash: Option<IdType>,
 	pub wallpaper_file_hash: Option<IdType>,
 	pub description_file_hash: Option<IdType>,
+	pub followers_only: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,37 @@
+//! A reaction with an emoji to a post, of any actor.
+
+use sea_orm::entity::prelude::*;
+
+use crate::{common::IdType, core::ActorAddress};
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "reaction_object")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub object_id: i64,
+	pub actor_address: ActorAddress,
+	pub object_hash: IdType,
+	pub emoji: String,
+	/// Whether a notification has been given for the reaction already, if it
+	/// is a reaction to a post of one of our identities.
+	pub notified: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::object::Entity",
+		from = "Column::ObjectId",
+		to = "super::object::Column::Id",
+		on_update = "NoAction",
+		on_delete = "NoAction"
+	)]
+	Object,
+}
+
+impl Related<super::object::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Object.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
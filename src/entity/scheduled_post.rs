@@ -0,0 +1,39 @@
+//! A `scheduled_post` is a draft that is published automatically, by the given
+//! identity, once its time has come.
+
+use sea_orm::entity::prelude::*;
+
+use crate::core::ActorAddress;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "scheduled_post")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	#[sea_orm(unique)]
+	pub draft_id: i64,
+	pub actor_address: ActorAddress,
+	/// The unix timestamp in milliseconds at which the post gets published.
+	pub publish_at: i64,
+	/// Why the last attempt to publish the post failed, if it did.
+	pub last_error: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, DeriveRelation, EnumIter)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::draft::Entity",
+		from = "Column::DraftId",
+		to = "super::draft::Column::Id",
+		on_update = "NoAction",
+		on_delete = "Cascade"
+	)]
+	Draft,
+}
+
+impl Related<super::draft::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Draft.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
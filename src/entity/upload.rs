@@ -0,0 +1,30 @@
+//! An `upload` of a file that is sent in chunks, so that it can be resumed
+//! when the connection breaks.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "upload")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = false)]
+	pub id: String,
+	pub mime_type: String,
+	pub size: i64,
+	/// The number of bytes that have been received so far.
+	pub received: i64,
+	/// When the last chunk was received, or when the upload was started.
+	pub updated: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(has_many = "super::upload_chunk::Entity")]
+	UploadChunk,
+}
+
+impl Related<super::upload_chunk::Entity> for Entity {
+	fn to() -> RelationDef { Relation::UploadChunk.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+//! A chunk of the data of an `upload`.
+
+use sea_orm::entity::prelude::*;
+
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "upload_chunk")]
+pub struct Model {
+	#[sea_orm(primary_key, auto_increment = true)]
+	pub id: i64,
+	pub upload_id: String,
+	/// The offset of the chunk in the file.
+	pub start: i64,
+	pub data: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+	#[sea_orm(
+		belongs_to = "super::upload::Entity",
+		from = "Column::UploadId",
+		to = "super::upload::Column::Id",
+		on_update = "NoAction",
+		on_delete = "Cascade"
+	)]
+	Upload,
+}
+
+impl Related<super::upload::Entity> for Entity {
+	fn to() -> RelationDef { Relation::Upload.def() }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
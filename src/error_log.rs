@@ -0,0 +1,67 @@
+//! Keeps the errors that have been logged most recently in memory, so that
+//! they can be looked at in the user interface without access to the log.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use log::{Level, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::common::current_timestamp;
+
+
+/// The number of errors that are kept.
+const CAPACITY: usize = 100;
+
+static RECENT_ERRORS: Mutex<VecDeque<LoggedError>> = Mutex::new(VecDeque::new());
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LoggedError {
+	/// The time the error was logged at, in milliseconds since the epoch.
+	pub time: u64,
+	/// The module the error was logged from.
+	pub target: String,
+	pub message: String,
+}
+
+/// Passes everything on to another logger, and remembers the errors.
+pub struct RecordingLogger<L: Log> {
+	inner: L,
+}
+
+
+impl<L: Log> RecordingLogger<L> {
+	pub fn new(inner: L) -> Self { Self { inner } }
+}
+
+impl<L: Log> Log for RecordingLogger<L> {
+	fn enabled(&self, metadata: &Metadata) -> bool { self.inner.enabled(metadata) }
+
+	fn log(&self, record: &Record) {
+		if record.level() == Level::Error {
+			record_error(record.target(), record.args().to_string());
+		}
+		self.inner.log(record);
+	}
+
+	fn flush(&self) { self.inner.flush() }
+}
+
+
+fn record_error(target: &str, message: String) {
+	let mut errors = RECENT_ERRORS.lock().unwrap();
+	if errors.len() >= CAPACITY {
+		errors.pop_front();
+	}
+	errors.push_back(LoggedError {
+		time: current_timestamp(),
+		target: target.to_string(),
+		message,
+	});
+}
+
+/// The errors that have been logged most recently, the newest first.
+pub fn recent_errors(limit: usize) -> Vec<LoggedError> {
+	let errors = RECENT_ERRORS.lock().unwrap();
+	errors.iter().rev().take(limit).cloned().collect()
+}
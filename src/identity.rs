@@ -1,10 +1,13 @@
+pub mod agent;
+pub mod message_key;
+
 use std::{
 	error::Error,
-	fmt,
+	fmt, io,
 	ops::{Deref, DerefMut},
 };
 
-use ed25519_dalek::{self as ed25519, Signer};
+use ed25519_dalek::{self as ed25519, Signer as _};
 use ed448_rust as ed448;
 use rand::{prelude::*, rngs::OsRng};
 use rusqlite::{
@@ -14,12 +17,20 @@ use rusqlite::{
 use sea_orm::{prelude::*, ColIdx, TryGetError};
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
-use sha3::{Digest, Sha3_256};
+use sha3::{digest::ExtendableOutput, Digest, Sha3_256, Shake256};
 use zeroize::Zeroize;
 
 use crate::{common::*, core::NodeAddress};
 
 
+/// The number of leading zero bits that the hash of a node's public key and its
+/// proof-of-work nonce needs to have. This makes it costly to generate lots of
+/// node IDs, which makes it harder to flood the network with fake nodes.
+#[cfg(not(test))]
+pub const NODE_ID_DIFFICULTY: u32 = 16;
+#[cfg(test)]
+pub const NODE_ID_DIFFICULTY: u32 = 8;
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct ActorPublicKeyV1(#[serde(with = "BigArray")] [u8; 57]);
 pub type ActorPublicKeyV1Error = ();
@@ -50,6 +61,29 @@ pub struct NodePrivateKey {
 #[zeroize(drop)]
 struct NodePrivateKeyCopy([u8; ed25519::SECRET_KEY_LENGTH]);
 
+/// Signs messages with the private key of an actor. The key doesn't need to be
+/// in the memory of the daemon, it can also be held by an external signer.
+pub trait Signer: Send + Sync {
+	fn public(&self) -> ActorPublicKeyV1;
+
+	fn sign(&self, message: &[u8]) -> Result<ActorSignatureV1, SignerError>;
+
+	/// The private key itself, if it is held in memory.
+	fn private_key(&self) -> Option<&ActorPrivateKeyV1> { None }
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+	/// No external signer has been configured.
+	NotConfigured,
+	/// The external signer couldn't be reached.
+	Io(io::Error),
+	/// The external signer doesn't have the key, or declined to sign with it.
+	Refused,
+	/// The signature that came back doesn't belong to the message.
+	InvalidSignature,
+}
+
 
 impl ActorPublicKeyV1 {
 	pub fn from_bytes(bytes: [u8; 57]) -> Result<Self, ActorPublicKeyV1Error> { Ok(Self(bytes)) }
@@ -69,6 +103,18 @@ impl ActorPrivateKeyV1 {
 		Self(ed448::PrivateKey::from(bytes))
 	}
 
+	/// Derives the private key from the seed of a mnemonic phrase.
+	pub fn from_seed(seed: &[u8]) -> Self {
+		let mut buffer = b"stonenet actor key v1".to_vec();
+		buffer.extend(seed);
+		let mut bytes = [0u8; ed448::KEY_LENGTH];
+		Shake256::digest_xof(&buffer, &mut bytes);
+		buffer.zeroize();
+		let this = Self::from_bytes(bytes);
+		bytes.zeroize();
+		this
+	}
+
 	pub fn generate_with_rng<R>(rng: &mut R) -> Self
 	where
 		R: CryptoRng + RngCore,
@@ -85,6 +131,16 @@ impl ActorPrivateKeyV1 {
 	}
 }
 
+impl Signer for ActorPrivateKeyV1 {
+	fn public(&self) -> ActorPublicKeyV1 { ActorPrivateKeyV1::public(self) }
+
+	fn sign(&self, message: &[u8]) -> Result<ActorSignatureV1, SignerError> {
+		Ok(ActorPrivateKeyV1::sign(self, message))
+	}
+
+	fn private_key(&self) -> Option<&ActorPrivateKeyV1> { Some(self) }
+}
+
 impl ActorSignatureV1 {
 	pub fn as_bytes(&self) -> &[u8; ed448::SIG_LENGTH] { &self.0 }
 
@@ -164,9 +220,27 @@ impl NodePublicKey {
 		buffer.into()
 	}
 
+	/// Finds the nonce that proves that work has been done to generate the
+	/// node ID that belongs to this public key.
+	pub fn generate_proof_of_work(&self) -> u64 {
+		let mut nonce = 0u64;
+		while !self.verify_proof_of_work(nonce) {
+			nonce += 1;
+		}
+		nonce
+	}
+
 	pub fn verify(&self, message: &[u8], signature: &NodeSignature) -> bool {
 		self.0.verify_strict(message, &signature.0).is_ok()
 	}
+
+	pub fn verify_proof_of_work(&self, nonce: u64) -> bool {
+		let mut hasher = Sha3_256::new();
+		hasher.update(self.0.to_bytes());
+		hasher.update(nonce.to_le_bytes());
+		let hash: [u8; 32] = hasher.finalize().into();
+		leading_zero_bits(&hash) >= NODE_ID_DIFFICULTY
+	}
 }
 
 impl NodePrivateKey {
@@ -238,6 +312,23 @@ impl fmt::Display for NodePublicKeyError {
 	}
 }
 
+impl Error for SignerError {}
+
+impl fmt::Display for SignerError {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+		match self {
+			Self::NotConfigured => write!(fmt, "no external signer is configured"),
+			Self::Io(e) => write!(fmt, "unable to reach external signer: {}", e),
+			Self::Refused => write!(fmt, "external signer refused to sign"),
+			Self::InvalidSignature => write!(fmt, "external signer returned an invalid signature"),
+		}
+	}
+}
+
+impl From<io::Error> for SignerError {
+	fn from(other: io::Error) -> Self { Self::Io(other) }
+}
+
 impl Clone for NodePrivateKey {
 	fn clone(&self) -> Self { Self::new(ed25519::SigningKey::from_bytes(&self.inner.to_bytes())) }
 }
@@ -291,6 +382,18 @@ impl Serialize for NodePublicKey {
 }
 
 
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+	let mut count = 0;
+	for byte in bytes {
+		count += byte.leading_zeros();
+		if *byte != 0 {
+			break;
+		}
+	}
+	count
+}
+
+
 #[cfg(test)]
 mod tests {
 	use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
@@ -348,4 +451,18 @@ mod tests {
 			"can't verify own signature after encoding+decoding it"
 		);
 	}
+
+	#[test]
+	fn test_proof_of_work() {
+		let mut rng = test::initialize_rng();
+		let public_key = NodePrivateKey::generate_with_rng(&mut rng).public();
+		let nonce = public_key.generate_proof_of_work();
+		assert!(public_key.verify_proof_of_work(nonce));
+
+		// The first nonce that works should have been taken
+		assert!((0..nonce).all(|n| !public_key.verify_proof_of_work(n)));
+
+		assert_eq!(leading_zero_bits(&[0, 0x10, 0xFF]), 11);
+		assert_eq!(leading_zero_bits(&[0, 0]), 16);
+	}
 }
@@ -0,0 +1,170 @@
+//! Signs with actor keys that are held by an external signer, so that they
+//! don't have to be in the memory of the daemon.
+//!
+//! The external signer is a separate process that listens on a unix socket,
+//! much like ssh-agent. It can keep the keys to itself, or pass the signing on
+//! to a hardware token or to the keystore of the OS. Every request is answered
+//! on a new connection, with the following messages:
+//!
+//! * A request is a message type byte, followed by the length of the body as a
+//!   32-bit big-endian integer, and the body.
+//! * A response is a status byte, which is 0 on success, followed by the length
+//!   of the body in the same way, and the body.
+//!
+//! Message type 1 lists the public keys of the signer, with an empty body. The
+//! response body holds the public keys, one after the other. Message type 2
+//! signs a message with one of them. The request body holds the public key,
+//! followed by the message, and the response body holds the signature.
+
+use std::{
+	io::{self, Read, Write},
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use super::*;
+
+
+pub const AGENT_MESSAGE_TYPE_LIST_KEYS: u8 = 1;
+pub const AGENT_MESSAGE_TYPE_SIGN: u8 = 2;
+
+/// Hardware tokens may wait for the user to confirm, so this is quite long.
+const TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_RESPONSE_SIZE: usize = 0x10000;
+
+
+pub struct AgentSigner {
+	socket_path: PathBuf,
+	public_key: ActorPublicKeyV1,
+}
+
+
+impl AgentSigner {
+	pub fn new(socket_path: PathBuf, public_key: ActorPublicKeyV1) -> Self {
+		Self {
+			socket_path,
+			public_key,
+		}
+	}
+
+	/// Lists the keys that the external signer can sign with.
+	pub fn list_keys(socket_path: &Path) -> Result<Vec<ActorPublicKeyV1>, SignerError> {
+		let body = exchange(socket_path, AGENT_MESSAGE_TYPE_LIST_KEYS, &[])?;
+		if body.len() % 57 != 0 {
+			return Err(SignerError::Io(io::ErrorKind::InvalidData.into()));
+		}
+		Ok(body
+			.chunks_exact(57)
+			.map(|c| ActorPublicKeyV1::from_bytes(*array_ref![c, 0, 57]).unwrap())
+			.collect())
+	}
+}
+
+impl Signer for AgentSigner {
+	fn public(&self) -> ActorPublicKeyV1 { self.public_key.clone() }
+
+	fn sign(&self, message: &[u8]) -> Result<ActorSignatureV1, SignerError> {
+		let mut body = self.public_key.clone().to_bytes().to_vec();
+		body.extend(message);
+		let response = exchange(&self.socket_path, AGENT_MESSAGE_TYPE_SIGN, &body)?;
+		if response.len() != ed448::SIG_LENGTH {
+			return Err(SignerError::InvalidSignature);
+		}
+
+		// Don't trust the signer blindly, a bad signature would invalidate the object
+		let signature =
+			ActorSignatureV1::from_bytes(*array_ref![response, 0, ed448::SIG_LENGTH]);
+		if !self.public_key.verify(message, &signature) {
+			return Err(SignerError::InvalidSignature);
+		}
+		Ok(signature)
+	}
+}
+
+#[cfg(not(target_family = "windows"))]
+fn exchange(socket_path: &Path, message_type: u8, body: &[u8]) -> Result<Vec<u8>, SignerError> {
+	use std::os::unix::net::UnixStream;
+
+	let mut stream = UnixStream::connect(socket_path)?;
+	stream.set_read_timeout(Some(TIMEOUT))?;
+	stream.set_write_timeout(Some(TIMEOUT))?;
+
+	let mut request = Vec::with_capacity(5 + body.len());
+	request.push(message_type);
+	request.extend((body.len() as u32).to_be_bytes());
+	request.extend(body);
+	stream.write_all(&request)?;
+
+	let mut header = [0u8; 5];
+	stream.read_exact(&mut header)?;
+	if header[0] != 0 {
+		return Err(SignerError::Refused);
+	}
+	let length = u32::from_be_bytes(*array_ref![header, 1, 4]) as usize;
+	if length > MAX_RESPONSE_SIZE {
+		return Err(SignerError::Io(io::ErrorKind::InvalidData.into()));
+	}
+	let mut response = vec![0u8; length];
+	stream.read_exact(&mut response)?;
+	Ok(response)
+}
+
+#[cfg(target_family = "windows")]
+fn exchange(_socket_path: &Path, _message_type: u8, _body: &[u8]) -> Result<Vec<u8>, SignerError> {
+	Err(SignerError::Io(io::ErrorKind::Unsupported.into()))
+}
+
+
+#[cfg(all(test, not(target_family = "windows")))]
+mod tests {
+	use std::{os::unix::net::UnixListener, thread};
+
+	use super::*;
+	use crate::test;
+
+	/// Answers requests like an external signer would, with a single key.
+	fn serve(listener: UnixListener, private_key: ActorPrivateKeyV1, requests: usize) {
+		for stream in listener.incoming().take(requests) {
+			let mut stream = stream.unwrap();
+			let mut header = [0u8; 5];
+			stream.read_exact(&mut header).unwrap();
+			let mut body = vec![0u8; u32::from_be_bytes(*array_ref![header, 1, 4]) as usize];
+			stream.read_exact(&mut body).unwrap();
+
+			let public_key = private_key.public().to_bytes();
+			let (status, response) = match header[0] {
+				AGENT_MESSAGE_TYPE_LIST_KEYS => (0, public_key.to_vec()),
+				AGENT_MESSAGE_TYPE_SIGN if body[..57] == public_key[..] =>
+					(0, private_key.sign(&body[57..]).to_bytes().to_vec()),
+				_ => (1, Vec::new()),
+			};
+			stream.write_all(&[status]).unwrap();
+			stream.write_all(&(response.len() as u32).to_be_bytes()).unwrap();
+			stream.write_all(&response).unwrap();
+		}
+	}
+
+	#[test]
+	fn test_agent_signer() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let other_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let public_key = private_key.public();
+		let dir = tempfile::tempdir().unwrap();
+		let socket_path = dir.path().join("agent.sock");
+		let listener = UnixListener::bind(&socket_path).unwrap();
+		let server = thread::spawn(move || serve(listener, private_key, 3));
+
+		let keys = AgentSigner::list_keys(&socket_path).unwrap();
+		assert_eq!(keys, vec![public_key.clone()]);
+
+		let signer = AgentSigner::new(socket_path.clone(), public_key.clone());
+		let signature = signer.sign(b"hello").unwrap();
+		assert!(public_key.verify(b"hello", &signature));
+
+		// A key that the signer doesn't have is refused
+		let signer = AgentSigner::new(socket_path, other_key.public());
+		assert!(matches!(signer.sign(b"hello"), Err(SignerError::Refused)));
+		server.join().unwrap();
+	}
+}
@@ -0,0 +1,148 @@
+//! The keys that direct messages are sealed with.
+//!
+//! The keys of actors are Ed448 keys, which can only sign. So for every
+//! identity, an X25519 key is derived from its private key. The public half of
+//! it, the message key, is handed out signed by the identity, so that others
+//! can seal messages to it. Each message is sealed with a new ephemeral key,
+//! which means that only the recipient is able to open it, not even its sender.
+
+use chacha20poly1305::{
+	aead::{Aead, KeyInit},
+	XChaCha20Poly1305, XNonce,
+};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::*;
+
+
+/// Is prepended to the private key of the identity to derive its message key
+/// from, and to the message key when the identity signs it.
+const MESSAGE_KEY_CONTEXT: &[u8] = b"stonenet direct message key";
+const NONCE_SIZE: usize = 24;
+
+
+/// The public key that direct messages to an identity are sealed with.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MessageKey([u8; 32]);
+
+/// The private half of a message key.
+pub struct MessageSecret(StaticSecret);
+
+/// A message that only the owner of a message key can open.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SealedMessage {
+	/// The public half of the ephemeral key the message is sealed with.
+	pub ephemeral_key: [u8; 32],
+	pub nonce: [u8; NONCE_SIZE],
+	pub ciphertext: Vec<u8>,
+}
+
+
+impl MessageKey {
+	pub fn from_bytes(bytes: [u8; 32]) -> Self { Self(bytes) }
+
+	pub fn as_bytes(&self) -> &[u8; 32] { &self.0 }
+
+	/// Seals the message so that only the owner of this key can open it.
+	pub fn seal(&self, plaintext: &[u8]) -> SealedMessage {
+		let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+		let ephemeral_key = PublicKey::from(&ephemeral_secret);
+		let shared_secret = ephemeral_secret.diffie_hellman(&PublicKey::from(self.0));
+		let key = derive_cipher_key(shared_secret.as_bytes(), ephemeral_key.as_bytes(), &self.0);
+
+		let mut nonce = [0u8; NONCE_SIZE];
+		OsRng.fill_bytes(&mut nonce);
+		let ciphertext = XChaCha20Poly1305::new((&*key).into())
+			.encrypt(XNonce::from_slice(&nonce), plaintext)
+			.expect("unable to seal message");
+		SealedMessage {
+			ephemeral_key: ephemeral_key.to_bytes(),
+			nonce,
+			ciphertext,
+		}
+	}
+
+	/// The data that the identity signs to vouch for its message key.
+	pub fn sign_data(&self) -> Vec<u8> {
+		let mut buffer = MESSAGE_KEY_CONTEXT.to_vec();
+		buffer.extend(&self.0);
+		buffer
+	}
+}
+
+impl MessageSecret {
+	/// Derives the message key of the identity from its private key. The same
+	/// private key always results in the same message key.
+	pub fn derive(private_key: &ActorPrivateKeyV1) -> Self {
+		let mut hasher = Sha3_256::new();
+		hasher.update(MESSAGE_KEY_CONTEXT);
+		hasher.update(private_key.as_bytes());
+		let mut bytes: [u8; 32] = hasher.finalize().into();
+		let secret = StaticSecret::from(bytes);
+		bytes.zeroize();
+		Self(secret)
+	}
+
+	/// Opens the sealed message, or returns `None` if it wasn't sealed with
+	/// this key or if it has been tampered with.
+	pub fn open(&self, sealed: &SealedMessage) -> Option<Zeroizing<Vec<u8>>> {
+		let public = PublicKey::from(&self.0);
+		let shared_secret = self
+			.0
+			.diffie_hellman(&PublicKey::from(sealed.ephemeral_key));
+		let key = derive_cipher_key(
+			shared_secret.as_bytes(),
+			&sealed.ephemeral_key,
+			public.as_bytes(),
+		);
+		XChaCha20Poly1305::new((&*key).into())
+			.decrypt(XNonce::from_slice(&sealed.nonce), sealed.ciphertext.as_slice())
+			.ok()
+			.map(Zeroizing::new)
+	}
+
+	pub fn public(&self) -> MessageKey { MessageKey(PublicKey::from(&self.0).to_bytes()) }
+}
+
+/// Binds the key of the cipher to both public keys, so that a sealed message
+/// can't be passed off as having been sealed to another key.
+fn derive_cipher_key(
+	shared_secret: &[u8; 32], ephemeral_key: &[u8; 32], recipient_key: &[u8; 32],
+) -> Zeroizing<[u8; 32]> {
+	let mut hasher = Sha3_256::new();
+	hasher.update(shared_secret);
+	hasher.update(ephemeral_key);
+	hasher.update(recipient_key);
+	Zeroizing::new(hasher.finalize().into())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_seal_and_open() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let secret = MessageSecret::derive(&private_key);
+		assert_eq!(
+			MessageSecret::derive(&private_key).public(),
+			secret.public()
+		);
+
+		let sealed = secret.public().seal(b"Only for you");
+		assert_eq!(
+			secret.open(&sealed).unwrap().as_slice(),
+			b"Only for you"
+		);
+
+		let other = MessageSecret::derive(&ActorPrivateKeyV1::generate_with_rng(&mut rng));
+		assert!(other.open(&sealed).is_none());
+		let mut tampered = sealed.clone();
+		tampered.ciphertext[0] ^= 1;
+		assert!(secret.open(&tampered).is_none());
+	}
+}
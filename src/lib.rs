@@ -3,17 +3,23 @@
 extern crate arrayref;
 
 pub mod api;
+pub mod bech32;
 pub mod common;
 pub mod compression;
 pub mod config;
 pub mod core;
 pub mod db;
+pub mod domain;
 pub mod entity;
+pub mod error_log;
 pub mod identity;
 pub mod limited_store;
+pub mod media;
 pub mod migration;
+pub mod naming;
 pub mod net;
 pub mod serde_limit;
+pub mod task;
 pub mod test;
 mod trace;
 pub mod util;
@@ -132,6 +132,20 @@ where
 		}
 		None
 	}
+
+	/// Finds the value of the given key, and moves it to the front, so that it
+	/// is the last one to be removed.
+	pub fn promote<'a>(&'a mut self, key: &K) -> Option<&'a mut V> {
+		let i = self.index_of(key)?;
+		let entry = self.base.store.remove(i).unwrap();
+		self.base.store.push_front(entry);
+		self.base.store.front_mut().map(|e| &mut e.1)
+	}
+
+	pub fn remove(&mut self, key: &K) -> Option<V> {
+		let i = self.index_of(key)?;
+		self.base.store.remove(i).map(|e| e.1)
+	}
 }
 
 impl<V> Deref for LimitedVec<V> {
@@ -4,17 +4,23 @@
 extern crate arrayref;
 
 mod api;
+mod bech32;
 mod common;
 mod compression;
 mod config;
 mod core;
 mod db;
+mod domain;
 mod entity;
+mod error_log;
 mod identity;
 mod limited_store;
+mod media;
 mod migration;
+mod naming;
 mod net;
 mod serde_limit;
+mod task;
 #[cfg(test)]
 mod test;
 mod trace;
@@ -25,8 +31,8 @@ mod web;
 use std::fs;
 use std::{
 	env, fmt,
-	fs::File,
-	io::{self, prelude::*},
+	fs::{File, OpenOptions},
+	io::{self, prelude::*, IsTerminal},
 	net::SocketAddr,
 	path::{Path, PathBuf},
 	process,
@@ -35,21 +41,85 @@ use std::{
 		atomic::{AtomicBool, Ordering},
 		Arc,
 	},
-	time::Duration,
+	thread,
+	time::{Duration, Instant},
 };
 
-use api::Api;
+use api::{direct_message::deliver_direct_messages, draft::publish_scheduled_posts, Api};
+use chrono::Utc;
 use config::Config;
-use db::Database;
+use db::{
+	encryption::{self, DatabaseKey},
+	maintenance::MaintenanceReport,
+	Database, DatabaseOptions,
+};
 use log::*;
 use net::{overlay::OverlayNode, resolve_bootstrap_addresses, Openness};
 use semver::Version;
 use signal_hook::flag;
-use tokio::{spawn, time::sleep};
+use tokio::{
+	runtime::{self, Runtime},
+	time::sleep,
+};
+use zeroize::Zeroizing;
 
 use crate::{config::CONFIG, core::Address, db::PersistenceHandle, migration::Migrations};
 
 
+/// The number of threads tokio allows in its blocking pool by default.
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+/// The thread limits for the low-memory profile, as every thread needs its own
+/// stack.
+const LOW_MEMORY_MAX_BLOCKING_THREADS: usize = 16;
+const LOW_MEMORY_WORKER_THREADS: usize = 2;
+
+
+/// Builds the tokio runtime according to the configuration, and logs the
+/// settings it ends up using.
+fn build_runtime(config: &Config) -> io::Result<Runtime> {
+	let low_memory = config.low_memory.unwrap_or(false);
+	let max_blocking_threads = config
+		.runtime_max_blocking_threads
+		.unwrap_or(if low_memory {
+			LOW_MEMORY_MAX_BLOCKING_THREADS
+		} else {
+			DEFAULT_MAX_BLOCKING_THREADS
+		})
+		.max(1);
+
+	let mut builder = if config.runtime_current_thread.unwrap_or(false) {
+		info!(
+			"Using a current-thread runtime, with at most {} blocking threads.",
+			max_blocking_threads
+		);
+		runtime::Builder::new_current_thread()
+	} else {
+		let worker_threads = match config.runtime_worker_threads {
+			Some(count) => count.max(1),
+			None => {
+				let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+				if low_memory {
+					cores.min(LOW_MEMORY_WORKER_THREADS)
+				} else {
+					cores
+				}
+			}
+		};
+		info!(
+			"Using a multi-threaded runtime with {} worker threads, and at most {} blocking \
+			 threads.",
+			worker_threads, max_blocking_threads
+		);
+		let mut builder = runtime::Builder::new_multi_thread();
+		builder.worker_threads(worker_threads);
+		builder
+	};
+	builder
+		.max_blocking_threads(max_blocking_threads)
+		.enable_all()
+		.build()
+}
+
 /// Gets the latest version, and whether it is required or not
 #[allow(dead_code)]
 async fn check_version() -> Option<(String, bool)> {
@@ -140,12 +210,23 @@ fn initialize_logging() {
 	#[cfg(not(target_family = "windows"))]
 	let result = env::var_os("SYSTEM_LOG_FILE").map(|os| PathBuf::from(os));
 
-	if let Some(filename) = result {
-		simple_logging::log_to_file(filename, LevelFilter::Debug)
-			.expect("unable to unitialize logger")
+	let logger = if let Some(filename) = result {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(filename)
+			.expect("unable to open log file");
+		env_logger::Builder::new()
+			.filter_level(LevelFilter::Debug)
+			.target(env_logger::Target::Pipe(Box::new(file)))
+			.build()
 	} else {
-		env_logger::init()
-	}
+		env_logger::Builder::from_default_env().build()
+	};
+	// Remember the errors, so that they can be shown in the user interface
+	log::set_max_level(logger.filter());
+	log::set_boxed_logger(Box::new(error_log::RecordingLogger::new(logger)))
+		.expect("unable to initialize logger");
 }
 
 fn load_config<P>(path: P) -> Option<Config>
@@ -196,22 +277,66 @@ async fn load_database(config: &Config, _install_dir: PathBuf) -> io::Result<Dat
 	)
 	.await?;
 
-	let db = Database::load(db_path)
-		.await
-		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-	Ok(db)
+	open_database(config, db_path).await
 }
 
 #[cfg(target_family = "windows")]
-async fn load_database(_config: &Config, install_dir: PathBuf) -> io::Result<Database> {
+async fn load_database(config: &Config, install_dir: PathBuf) -> io::Result<Database> {
 	let mut db_path = PathBuf::from(env::var_os("APPDATA").expect("Unable to read %APPDATA%."));
 	db_path.push("Stonenet");
 	let _ = fs::create_dir(&db_path);
 	db_path.push("db.sqlite");
-	let db = Database::load(db_path)
+	open_database(config, db_path).await
+}
+
+/// Opens the database, and encrypts it first if that is asked for and it isn't
+/// encrypted yet.
+/// Applies the database settings of the config on top of the defaults.
+fn database_options(config: &Config) -> DatabaseOptions {
+	let mut options = DatabaseOptions::default();
+	if let Some(wal) = config.database_wal {
+		options.wal = wal;
+	}
+	if let Some(timeout) = config.database_busy_timeout {
+		options.busy_timeout = Duration::from_millis(timeout);
+	}
+	if let Some(max_connections) = config.database_max_connections {
+		options.max_connections = max_connections.max(1);
+	}
+	if let Some(timeout) = config.database_acquire_timeout {
+		options.acquire_timeout = Duration::from_millis(timeout);
+	}
+	options.archive_dir = config.archive_path.as_ref().map(PathBuf::from);
+	options
+}
+
+async fn open_database(config: &Config, db_path: PathBuf) -> io::Result<Database> {
+	let options = database_options(config);
+	let is_encrypted = encryption::is_encrypted(&db_path)?;
+	if !is_encrypted && !config.encrypt_database.unwrap_or(false) {
+		return Database::load_with_key(db_path, None, options)
+			.await
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+	}
+	if !cfg!(feature = "sqlcipher") {
+		return Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"encrypting the database needs stonenet to be built with the sqlcipher feature",
+		));
+	}
+
+	let passphrase = load_database_passphrase(config)?;
+	let key = DatabaseKey::derive(&db_path, &passphrase)?;
+	if encryption::encrypt_existing(&db_path, &key)
+		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+	{
+		info!("Encrypted the database with the given passphrase.");
+	}
+	Database::load_with_key(db_path, Some(key), options)
 		.await
-		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-	Ok(db)
+		.map_err(|e| {
+			io::Error::new(io::ErrorKind::Other, format!("{} (the passphrase may be wrong)", e))
+		})
 }
 
 #[cfg(not(target_family = "windows"))]
@@ -251,6 +376,120 @@ async fn load_trusted_node_config(db: &Database, config: &Config) -> db::Result<
 	db.clear_trusted_nodes_except(trusted_node_ids).await
 }
 
+/// Periodically locks the private keys of our identities again if they haven't
+/// been used for the given time, until the node stops.
+async fn lock_idle_keys(db: Database, stop_flag: Arc<AtomicBool>, timeout: Duration) {
+	while util::sleep_unless_stopped(&stop_flag, Duration::from_secs(60)).await {
+		if db.keyring().lock_if_idle(timeout) {
+			info!("Locked the private keys after not being used for a while.");
+		}
+	}
+}
+
+/// Returns the passphrase for the private keys, if one is given.
+fn load_passphrase(config: &Config) -> io::Result<Option<String>> {
+	if let Some(passphrase) = env::var_os("STONENET_PASSPHRASE") {
+		return Ok(Some(passphrase.to_string_lossy().into_owned()));
+	}
+	match &config.key_passphrase_file {
+		None => Ok(None),
+		Some(path) => Ok(Some(read_passphrase_file(path)?)),
+	}
+}
+
+/// Returns the passphrase for the database, and asks for it on the terminal if
+/// it isn't given otherwise.
+fn load_database_passphrase(config: &Config) -> io::Result<Zeroizing<String>> {
+	if let Some(passphrase) = env::var_os("STONENET_DATABASE_PASSPHRASE") {
+		return Ok(Zeroizing::new(passphrase.to_string_lossy().into_owned()));
+	}
+	if let Some(path) = &config.database_passphrase_file {
+		return Ok(Zeroizing::new(read_passphrase_file(path)?));
+	}
+	if !io::stdin().is_terminal() {
+		return Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			"the database is encrypted, but no passphrase was given. Set it in the \
+			 STONENET_DATABASE_PASSPHRASE environment variable, or with the \
+			 `database_passphrase_file` option.",
+		));
+	}
+	Ok(Zeroizing::new(rpassword::prompt_password("Passphrase of the database: ")?))
+}
+
+fn read_passphrase_file(path: &str) -> io::Result<String> {
+	let mut content = String::new();
+	File::open(path)?.read_to_string(&mut content)?;
+	Ok(content.trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+}
+
+/// Unlocks the private keys if they are encrypted, or encrypts them if a
+/// passphrase is given for the first time. Returns false if the node can't
+/// start.
+async fn unlock_keys(db: &Database, config: &Config) -> bool {
+	let passphrase = match load_passphrase(config) {
+		Ok(p) => p,
+		Err(e) => {
+			error!("Unable to read passphrase file: {}", e);
+			return false;
+		}
+	};
+	let is_enabled = match db.load_key_encryption().await {
+		Ok(e) => e,
+		Err(e) => {
+			error!("Unable to load key encryption: {}", e);
+			return false;
+		}
+	};
+
+	match (is_enabled, passphrase) {
+		(true, None) => {
+			error!(
+				"The private keys are encrypted, but no passphrase was given. Set it in the \
+				 STONENET_PASSPHRASE environment variable, or with the `key_passphrase_file` \
+				 option."
+			);
+			false
+		}
+		(true, Some(passphrase)) => match db.unlock_keys(&passphrase).await {
+			Ok(true) => true,
+			Ok(false) => {
+				error!("The passphrase for the private keys is wrong.");
+				false
+			}
+			Err(e) => {
+				error!("Unable to unlock the private keys: {}", e);
+				false
+			}
+		},
+		(false, Some(passphrase)) => match db.enable_key_encryption(&passphrase).await {
+			Ok(()) => {
+				info!("Encrypted the private keys with the given passphrase.");
+				true
+			}
+			Err(e) => {
+				error!("Unable to encrypt the private keys: {}", e);
+				false
+			}
+		},
+		(false, None) => true,
+	}
+}
+
+/// Returns whether the given argument has been passed.
+fn has_argument(name: &str) -> bool { env::args().skip(1).any(|a| a == name) }
+
+/// Returns the path given with the `--import` argument, if any.
+fn parse_import_argument() -> Option<PathBuf> {
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		if arg == "--import" {
+			return args.next().map(PathBuf::from);
+		}
+	}
+	None
+}
+
 fn parse_versions(string: &str) -> (&str, Option<&str>) {
 	if let Some(i) = string.find('\n') {
 		let latest_version = &string[..i];
@@ -293,8 +532,7 @@ fn version_message(_version_str: &str) -> String {
 	"use your package manager to update the stonenet client".to_owned()
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
 	initialize_logging();
 
 	let install_dir = match load_install_dir() {
@@ -308,130 +546,235 @@ async fn main() {
 	// Load config
 	let config_path = config_path(install_dir.clone());
 	if let Some(config) = load_config(&config_path) {
-		if let Err(_) = CONFIG.set(config.clone()) {
-			panic!("Unable to set config global.")
-		}
-
-		// Catch signals
-		let stop_flag = Arc::new(AtomicBool::new(false));
-		flag::register(signal_hook::consts::SIGINT, stop_flag.clone()).unwrap();
-		flag::register(signal_hook::consts::SIGTERM, stop_flag.clone()).unwrap();
-		let stop_flag2 = stop_flag.clone();
-		ctrlc::set_handler(move || {
-			stop_flag2.store(true, Ordering::Relaxed);
-		})
-		.expect("Error setting Ctrl-C handler");
-
-		// Load database
-		let db = match load_database(&config, install_dir).await {
-			Ok(db) => db,
+		let runtime = match build_runtime(&config) {
+			Ok(r) => r,
 			Err(e) => {
-				error!("Unable to load database: {}", e);
+				error!("Unable to start runtime: {}", e);
 				return;
 			}
 		};
+		runtime.block_on(run(install_dir, config));
+	}
+}
 
-		// Run migrations (does nothing if there is nothing to migrate)
-		{
-			let migrations = Migrations::load();
-			migrations.run(&db).await.expect("migration issue");
-		}
+async fn run(install_dir: PathBuf, config: Config) {
+	if let Err(_) = CONFIG.set(config.clone()) {
+		panic!("Unable to set config global.")
+	}
 
-		// Load configured trusted nodes into database
-		if let Err(e) = load_trusted_node_config(&db, &config).await {
-			error!("Unable to load trusted node list: {}", e);
+	// Catch signals
+	let stop_flag = Arc::new(AtomicBool::new(false));
+	flag::register(signal_hook::consts::SIGINT, stop_flag.clone()).unwrap();
+	flag::register(signal_hook::consts::SIGTERM, stop_flag.clone()).unwrap();
+	let stop_flag2 = stop_flag.clone();
+	ctrlc::set_handler(move || {
+		stop_flag2.store(true, Ordering::Relaxed);
+	})
+	.expect("Error setting Ctrl-C handler");
+
+	// Load database
+	let db = match load_database(&config, install_dir).await {
+		Ok(db) => db,
+		Err(e) => {
+			error!("Unable to load database: {}", e);
 			return;
 		}
+	};
 
-		// Load node
-		let node = if let Some(n) = load_node(stop_flag.clone(), db.clone(), &config).await {
-			n
-		} else {
-			info!("Node not loaded, exiting...");
-			return;
-		};
-		info!(
-			"Loaded node with address {}",
-			Address::Node(node.node_id().clone())
-		);
+	// Run migrations (does nothing if there is nothing to migrate)
+	if let Err(e) = Migrations::load().run(&db).await {
+		error!("Unable to migrate database: {}", e);
+		return;
+	}
 
-		// Test openness
-		let api = Api { node, db };
-		let new_bootstrap_nodes = test_bootstrap_nodes(&api, &config).await;
-		test_openness(&api, &config, !new_bootstrap_nodes).await;
-
-		// Check for updates (only in release mode)
-		#[cfg(not(debug_assertions))]
-		let update_message = {
-			let new_version_opt = check_version().await;
-			if let Some((new_version, required)) = new_version_opt {
-				Some((version_message(&new_version), required))
-			} else {
-				None
+	// Converting the database rewrites all of it, so it is done on request
+	// only, without running the node.
+	if has_argument("--enable-incremental-vacuum") {
+		info!("Converting database to incremental auto-vacuum mode, this may take a while...");
+		match db.enable_incremental_vacuum().await {
+			Ok(true) => info!("Database converted to incremental auto-vacuum mode."),
+			Ok(false) => info!("Database is in incremental auto-vacuum mode already."),
+			Err(e) => error!("Unable to convert database: {}", e),
+		}
+		return;
+	}
+
+	// Unlock the private keys before anything needs them
+	if !unlock_keys(&db, &config).await {
+		return;
+	}
+	db.keyring()
+		.set_external_signer(config.external_signer_socket.as_ref().map(PathBuf::from));
+
+	// Merge the data of another installation into ours, if requested. Imported
+	// identities and follows are announced when the actor networks are joined.
+	if let Some(import_path) = parse_import_argument() {
+		match db.import_from(&import_path).await {
+			Ok(summary) => info!(
+				"Imported {} identities, {} follows, {} actors, {} objects, {} files and {} \
+				 blocks from {}, skipped {} conflicting objects.",
+				summary.identities.len(),
+				summary.follows.len(),
+				summary.actors,
+				summary.objects,
+				summary.files,
+				summary.blocks,
+				import_path.display(),
+				summary.conflicts
+			),
+			Err(e) => {
+				error!("Unable to import {}: {}", import_path.display(), e);
+				return;
 			}
-		};
-		#[cfg(debug_assertions)]
-		let update_message = None;
-
-		// Spawn web servers
-		if config.load_web_interface.unwrap_or(false) {
-			let server_info = web::server::ServerInfo {
-				is_exposed: true,
-				federation_domain: config
-					.federation_domain
-					.clone()
-					.unwrap_or("localhost".to_string()),
-				url_base: config.web_url_base.clone().unwrap_or(String::new()),
-				update_message: None,
-			};
-			let stop_flag2 = stop_flag.clone();
-			let api2 = api.clone();
-			let config2 = config.clone();
-			spawn(async move {
-				web::server::serve(
-					stop_flag2,
-					config.web_interface_port.unwrap_or(80),
-					None,
-					api2,
-					server_info,
-					config2,
-				)
-				.await
-				.unwrap();
-			});
 		}
-		if config.load_user_interface.unwrap_or(false) {
-			let port = config.user_interface_port.unwrap_or(37338);
-			let server_info = web::server::ServerInfo {
-				is_exposed: false,
-				federation_domain: config
-					.federation_domain
-					.clone()
-					.unwrap_or("localhost".to_string()),
-				url_base: config
-					.web_url_base
-					.clone()
-					.unwrap_or(format!("http://localhost:{}", port)),
-				update_message,
-			};
-			let stop_flag2 = stop_flag.clone();
-			let api2 = api.clone();
-			let config2 = config.clone();
-			spawn(async move {
-				web::server::serve(stop_flag2, port, None, api2, server_info, config2)
-					.await
-					.unwrap();
-			});
+	}
+
+	// Load configured trusted nodes into database
+	if let Err(e) = load_trusted_node_config(&db, &config).await {
+		error!("Unable to load trusted node list: {}", e);
+		return;
+	}
+
+	// Load node
+	let node = if let Some(n) = load_node(stop_flag.clone(), db.clone(), &config).await {
+		n
+	} else {
+		info!("Node not loaded, exiting...");
+		return;
+	};
+	info!(
+		"Loaded node with address {}",
+		Address::Node(node.node_id().clone())
+	);
+	if let Some(minutes) = config.key_idle_lock {
+		node.tasks().spawn(
+			"key lock",
+			lock_idle_keys(
+				db.clone(),
+				stop_flag.clone(),
+				Duration::from_secs(minutes * 60),
+			),
+		);
+	}
+
+	// Move the data that hasn't been accessed for a while into the archive, and
+	// give free space back to the file system
+	node.tasks().spawn(
+		"database maintenance",
+		maintain_database(
+			db.clone(),
+			stop_flag.clone(),
+			config.archive_after_days,
+			config.clone(),
+		),
+	);
+
+	// Publish the posts that have been scheduled, once they are due
+	let api = Api { node, db };
+	api.node
+		.tasks()
+		.spawn("post scheduler", publish_scheduled_posts(api.clone()));
+	// Retry the direct messages that couldn't be delivered yet
+	api.node
+		.tasks()
+		.spawn("direct message delivery", deliver_direct_messages(api.clone()));
+
+	// Test openness
+	let new_bootstrap_nodes = test_bootstrap_nodes(&api, &config).await;
+	test_openness(&api, &config, !new_bootstrap_nodes).await;
+
+	// Check for updates (only in release mode)
+	#[cfg(not(debug_assertions))]
+	let update_message = {
+		let new_version_opt = check_version().await;
+		if let Some((new_version, required)) = new_version_opt {
+			Some((version_message(&new_version), required))
+		} else {
+			None
 		}
+	};
+	#[cfg(debug_assertions)]
+	let update_message = None;
+
+	spawn_web_servers(&stop_flag, &api, &config, update_message);
+
+	// Run the main loop, until it exits because of a signal
+	node_main(stop_flag, &api, &config).await;
 
-		// Run the main loop, until it exits because of a signal
-		node_main(stop_flag, &api, &config).await;
+	// Shutdown the node and the web servers
+	info!("Exiting stonenetd...");
 
-		// Shutdown rocket servers
-		info!("Exiting stonenetd...");
+	// Give all background tasks some time to finish what they're doing
+	let node = api.node.clone();
+	api.close().await;
+	let grace_period = Duration::from_secs(config.shutdown_grace_period.unwrap_or(10));
+	node.tasks().shutdown(grace_period).await;
+	info!("Done.");
+}
+
+#[cfg(feature = "web")]
+fn spawn_web_servers(
+	stop_flag: &Arc<AtomicBool>, api: &Api, config: &Config,
+	update_message: Option<(String, bool)>,
+) {
+	if config.load_web_interface.unwrap_or(false) {
+		let server_info = web::server::ServerInfo {
+			is_exposed: true,
+			federation_domain: config
+				.federation_domain
+				.clone()
+				.unwrap_or("localhost".to_string()),
+			url_base: config.web_url_base.clone().unwrap_or(String::new()),
+			update_message: None,
+		};
+		let stop_flag2 = stop_flag.clone();
+		let api2 = api.clone();
+		let config2 = config.clone();
+		api.node.tasks().spawn("web interface server", async move {
+			web::server::serve(
+				stop_flag2,
+				config2.web_interface_port.unwrap_or(80),
+				None,
+				api2,
+				server_info,
+				config2,
+			)
+			.await
+			.unwrap();
+		});
+	}
+	if config.load_user_interface.unwrap_or(false) {
+		let port = config.user_interface_port.unwrap_or(37338);
+		let server_info = web::server::ServerInfo {
+			is_exposed: false,
+			federation_domain: config
+				.federation_domain
+				.clone()
+				.unwrap_or("localhost".to_string()),
+			url_base: config
+				.web_url_base
+				.clone()
+				.unwrap_or(format!("http://localhost:{}", port)),
+			update_message,
+		};
+		let stop_flag2 = stop_flag.clone();
+		let api2 = api.clone();
+		let config2 = config.clone();
+		api.node.tasks().spawn("user interface server", async move {
+			web::server::serve(stop_flag2, port, None, api2, server_info, config2)
+				.await
+				.unwrap();
+		});
+	}
+}
 
-		api.close().await;
-		info!("Done.");
+#[cfg(not(feature = "web"))]
+fn spawn_web_servers(
+	_stop_flag: &Arc<AtomicBool>, _api: &Api, config: &Config,
+	_update_message: Option<(String, bool)>,
+) {
+	if config.load_web_interface.unwrap_or(false) || config.load_user_interface.unwrap_or(false) {
+		warn!("The web interface has been left out of this build, so it can't be loaded.");
 	}
 }
 
@@ -455,6 +798,112 @@ async fn load_node(
 	}
 }
 
+/// Periodically moves the data of blocks that haven't been accessed for the
+/// given number of days into the archive, maintains the database on the
+/// configured schedule, and releases the free pages of the database file after
+/// garbage has been collected from it, until the node stops.
+async fn maintain_database(
+	db: Database, stop_flag: Arc<AtomicBool>, archive_after_days: Option<u32>, config: Config,
+) {
+	let maintenance_interval = config.maintenance_interval.unwrap_or(24) as u64 * 3600;
+	let vacuum_interval = config.vacuum_interval.unwrap_or(24) as u64 * 3600;
+	let pages_per_step = config.vacuum_pages_per_step.unwrap_or(256);
+	let step_delay = Duration::from_millis(config.vacuum_step_delay.unwrap_or(100));
+
+	let mut last_maintenance = Instant::now();
+	let mut last_vacuum = Instant::now();
+	loop {
+		if let Some(days) = archive_after_days {
+			let accessed_before = Utc::now().timestamp_millis() - days as i64 * 24 * 3600 * 1000;
+			match db.archive_blocks(accessed_before).await {
+				Ok(0) => {}
+				Ok(count) => info!("Moved {} blocks into the archive.", count),
+				Err(e) => error!("Unable to archive blocks: {}", e),
+			}
+		}
+
+		let started = Instant::now();
+		// Leave the database alone while it is struggling already
+		let maintenance_due = maintenance_interval > 0
+			&& last_maintenance.elapsed().as_secs() >= maintenance_interval
+			&& !db.is_degraded();
+		let mut report = MaintenanceReport::default();
+		if maintenance_due {
+			match db.clean_orphaned_rows().await {
+				Ok(orphans) => report.orphans = orphans,
+				Err(e) => report.errors.push(format!("unable to clean up orphaned rows: {}", e)),
+			}
+			match db.analyze().await {
+				Ok(()) => report.analyzed = true,
+				Err(e) => report.errors.push(format!("unable to analyze database: {}", e)),
+			}
+		}
+
+		// Only release the free pages after garbage collection, because that is
+		// what frees them up
+		let collected = report.orphans.file_blocks > 0
+			|| report.orphans.objects > 0
+			|| report.orphans.blocks > 0;
+		// Compacting can take a while, so don't start with it when stopping
+		if vacuum_interval > 0
+			&& (collected || last_vacuum.elapsed().as_secs() >= vacuum_interval)
+			&& !stop_flag.load(Ordering::Relaxed)
+		{
+			let mut last_percentage = 0;
+			let result = db
+				.compact(pages_per_step, step_delay, |progress| {
+					let percentage = progress.released * 100 / progress.total;
+					if percentage / 10 > last_percentage / 10 {
+						info!("Compacting database... {}%", percentage);
+						last_percentage = percentage;
+					}
+				})
+				.await;
+			match result {
+				Ok(progress) => {
+					if progress.released > 0 {
+						info!(
+							"Released {} free pages of the database file.",
+							progress.released
+						);
+					}
+					report.released_pages = progress.released;
+				}
+				Err(e) => {
+					error!("Unable to compact database: {}", e);
+					report.errors.push(format!("unable to compact database: {}", e));
+				}
+			}
+			last_vacuum = Instant::now();
+		}
+
+		if maintenance_due {
+			report.finished = Utc::now().timestamp() as u64;
+			report.duration = started.elapsed().as_millis() as u64;
+			if report.errors.len() == 0 {
+				info!(
+					"Maintained database in {} ms: removed {} orphaned file blocks, {} orphaned \
+					 objects and {} blocks.",
+					report.duration,
+					report.orphans.file_blocks,
+					report.orphans.objects,
+					report.orphans.blocks
+				);
+			} else {
+				for e in &report.errors {
+					error!("Database maintenance: {}", e);
+				}
+			}
+			db.record_maintenance(report);
+			last_maintenance = Instant::now();
+		}
+
+		if !util::sleep_unless_stopped(&stop_flag, Duration::from_secs(3600)).await {
+			break;
+		}
+	}
+}
+
 async fn node_main(stop_flag: Arc<AtomicBool>, g: &Api, config: &Config) {
 	info!("Network node started.");
 
@@ -462,7 +911,7 @@ async fn node_main(stop_flag: Arc<AtomicBool>, g: &Api, config: &Config) {
 	if config.bootstrap_nodes.len() > 0 {
 		let flag2 = stop_flag.clone();
 		let node = g.node.clone();
-		tokio::spawn(async move {
+		g.node.tasks().spawn("network joiner", async move {
 			if !node.join_network(flag2).await {
 				error!("Attempt at joining the network failed.");
 			} else {
@@ -0,0 +1,294 @@
+//! Extracts the metadata of the media files that are attached to posts, so
+//! that the space they need can be reserved before they have been loaded.
+//!
+//! Images are decoded to find their dimensions and to compute a blurhash,
+//! which is a short string that describes a blurry version of the image that
+//! can be shown as a placeholder. The duration and the dimensions of videos
+//! and sound files are only read from the MP4 container format.
+//!
+//! Large images also get a thumbnail, which is shown in the feeds instead of
+//! the original.
+
+use std::io::Cursor;
+
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat, ImageOutputFormat, Limits};
+use serde::Serialize;
+
+
+/// The number of horizontal and vertical components of a blurhash. More
+/// components give more detail, but make the blurhash longer.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+/// The size the images are scaled down to before their blurhash is computed,
+/// which is a lot faster and hardly makes a difference.
+const BLURHASH_SAMPLE_SIZE: u32 = 32;
+/// The most memory that may be allocated while decoding an image.
+const MAX_DECODE_ALLOCATION: u64 = 256 * 1024 * 1024;
+/// The width and height that thumbnails fit in. Images that fit in it already
+/// don't get a thumbnail.
+pub const THUMBNAIL_SIZE: u32 = 640;
+/// The quality with which the thumbnails of opaque images are encoded.
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MediaMetadata {
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	/// The play time in milliseconds.
+	pub duration: Option<u64>,
+	pub blurhash: Option<String>,
+}
+
+
+pub struct Thumbnail {
+	pub mime_type: &'static str,
+	pub data: Vec<u8>,
+}
+
+
+impl MediaMetadata {
+	fn is_empty(&self) -> bool {
+		self.width.is_none()
+			&& self.height.is_none()
+			&& self.duration.is_none()
+			&& self.blurhash.is_none()
+	}
+}
+
+
+/// Extracts the metadata of the file, if it is a kind of media that is
+/// understood. This may take a while for large images, so it shouldn't be
+/// called on the async runtime directly.
+pub fn extract_metadata(mime_type: &str, data: &[u8]) -> Option<MediaMetadata> {
+	let metadata = match mime_type {
+		"image/gif" | "image/jpeg" | "image/png" | "image/webp" =>
+			extract_image_metadata(mime_type, data),
+		"audio/mp4" | "audio/m4a" | "audio/x-m4a" | "video/mp4" | "video/quicktime" =>
+			extract_mp4_metadata(data),
+		_ => None,
+	}?;
+	if metadata.is_empty() {
+		None
+	} else {
+		Some(metadata)
+	}
+}
+
+/// Whether a thumbnail can be generated for files of the given type. Animated
+/// GIFs would lose their animation, so they are left out.
+pub fn has_thumbnail(mime_type: &str) -> bool {
+	matches!(mime_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+/// Scales the image down to fit in `THUMBNAIL_SIZE`. Images with transparency
+/// become WebP images, and opaque images become JPEG images. Returns `None` if
+/// the file isn't an image that can be decoded, or if it is small enough to be
+/// shown as it is. Like `extract_metadata`, this shouldn't be called on the
+/// async runtime directly.
+pub fn generate_thumbnail(mime_type: &str, data: &[u8]) -> Option<Thumbnail> {
+	if !has_thumbnail(mime_type) {
+		return None;
+	}
+	let format = ImageFormat::from_mime_type(mime_type)?;
+	let (width, height) = open_image(data, format).into_dimensions().ok()?;
+	if width <= THUMBNAIL_SIZE && height <= THUMBNAIL_SIZE {
+		return None;
+	}
+
+	let image = open_image(data, format).decode().ok()?;
+	let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+	let (mime_type, thumbnail, output_format) = if thumbnail.color().has_alpha() {
+		(
+			"image/webp",
+			DynamicImage::ImageRgba8(thumbnail.to_rgba8()),
+			ImageOutputFormat::WebP,
+		)
+	} else {
+		(
+			"image/jpeg",
+			DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+			ImageOutputFormat::Jpeg(THUMBNAIL_JPEG_QUALITY),
+		)
+	};
+	let mut encoded = Vec::new();
+	thumbnail
+		.write_to(&mut Cursor::new(&mut encoded), output_format)
+		.ok()?;
+	Some(Thumbnail {
+		mime_type,
+		data: encoded,
+	})
+}
+
+fn open_image(data: &[u8], format: ImageFormat) -> ImageReader<Cursor<&[u8]>> {
+	let mut reader = ImageReader::with_format(Cursor::new(data), format);
+	let mut limits = Limits::default();
+	limits.max_alloc = Some(MAX_DECODE_ALLOCATION);
+	reader.limits(limits);
+	reader
+}
+
+fn extract_image_metadata(mime_type: &str, data: &[u8]) -> Option<MediaMetadata> {
+	let format = ImageFormat::from_mime_type(mime_type)?;
+
+	// Even if the image can't be decoded, its dimensions are still useful
+	let (width, height) = open_image(data, format).into_dimensions().ok()?;
+	let blurhash = open_image(data, format).decode().ok().and_then(|image| {
+		let sample = image
+			.thumbnail(BLURHASH_SAMPLE_SIZE, BLURHASH_SAMPLE_SIZE)
+			.to_rgba8();
+		blurhash::encode(
+			BLURHASH_COMPONENTS.0,
+			BLURHASH_COMPONENTS.1,
+			sample.width(),
+			sample.height(),
+			sample.as_raw(),
+		)
+		.ok()
+	});
+	Some(MediaMetadata {
+		width: Some(width),
+		height: Some(height),
+		duration: None,
+		blurhash,
+	})
+}
+
+/// Reads the duration from the movie header, and the dimensions from the first
+/// track that has any.
+fn extract_mp4_metadata(data: &[u8]) -> Option<MediaMetadata> {
+	let moov = find_mp4_box(data, b"moov")?;
+	let mut metadata = MediaMetadata::default();
+
+	if let Some(mvhd) = find_mp4_box(moov, b"mvhd") {
+		let (timescale, duration) = match *mvhd.first()? {
+			0 => (read_u32(mvhd, 12)?, read_u32(mvhd, 16)? as u64),
+			_ => (read_u32(mvhd, 20)?, read_u64(mvhd, 24)?),
+		};
+		if timescale > 0 {
+			metadata.duration = Some((duration as u128 * 1000 / timescale as u128) as u64);
+		}
+	}
+
+	for trak in iter_mp4_boxes(moov).filter(|(t, _)| t == b"trak") {
+		if let Some(tkhd) = find_mp4_box(trak.1, b"tkhd") {
+			let offset = match *tkhd.first()? {
+				0 => 76,
+				_ => 88,
+			};
+			// The dimensions are fixed-point numbers, of which only the integer
+			// part is needed
+			let width = read_u32(tkhd, offset)? >> 16;
+			let height = read_u32(tkhd, offset + 4)? >> 16;
+			if width > 0 && height > 0 {
+				metadata.width = Some(width);
+				metadata.height = Some(height);
+				break;
+			}
+		}
+	}
+	Some(metadata)
+}
+
+fn find_mp4_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+	iter_mp4_boxes(data)
+		.find(|(t, _)| t == box_type)
+		.map(|(_, content)| content)
+}
+
+/// Iterates over the type and the content of the boxes that follow each other
+/// in the data, until one is found that doesn't fit.
+fn iter_mp4_boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> + '_ {
+	std::iter::from_fn(move || {
+		let size = read_u32(data, 0)? as u64;
+		let box_type = *array_ref![data.get(4..8)?, 0, 4];
+		let (header_size, size) = match size {
+			0 => (8, data.len() as u64),
+			1 => (16, read_u64(data, 8)?),
+			_ => (8, size),
+		};
+		if size < header_size || size > data.len() as u64 {
+			return None;
+		}
+		let content = &data[header_size as usize..size as usize];
+		data = &data[size as usize..];
+		Some((box_type, content))
+	})
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+	let bytes = data.get(offset..offset + 4)?;
+	Some(u32::from_be_bytes(*array_ref![bytes, 0, 4]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+	let bytes = data.get(offset..offset + 8)?;
+	Some(u64::from_be_bytes(*array_ref![bytes, 0, 8]))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use image::{Rgb, RgbImage};
+
+	use super::*;
+
+	#[test]
+	fn test_extract_metadata() {
+		let image = RgbImage::from_fn(64, 48, |x, _| Rgb([(x * 4) as u8, 100, 200]));
+		let mut png = Vec::new();
+		image
+			.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+			.unwrap();
+
+		let metadata = extract_metadata("image/png", &png).unwrap();
+		assert_eq!(metadata.width, Some(64));
+		assert_eq!(metadata.height, Some(48));
+		assert!(metadata.blurhash.is_some());
+		assert_eq!(metadata.duration, None);
+
+		// A movie header with a timescale of 1000 and a duration of 2.5 seconds
+		let mut mvhd = vec![0u8; 100];
+		mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+		mvhd[16..20].copy_from_slice(&2500u32.to_be_bytes());
+		let mut moov = Vec::new();
+		moov.extend((8 + mvhd.len() as u32).to_be_bytes());
+		moov.extend(b"mvhd");
+		moov.extend(mvhd);
+		let mut mp4 = Vec::new();
+		mp4.extend((8 + moov.len() as u32).to_be_bytes());
+		mp4.extend(b"moov");
+		mp4.extend(moov);
+
+		let metadata = extract_metadata("video/mp4", &mp4).unwrap();
+		assert_eq!(metadata.duration, Some(2500));
+		assert_eq!(metadata.width, None);
+
+		assert_eq!(extract_metadata("image/png", b"not an image"), None);
+		assert_eq!(extract_metadata("text/plain", b"text"), None);
+	}
+
+	#[test]
+	fn test_generate_thumbnail() {
+		let image = RgbImage::from_fn(1280, 960, |x, y| Rgb([x as u8, y as u8, 200]));
+		let mut png = Vec::new();
+		image
+			.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+			.unwrap();
+
+		let thumbnail = generate_thumbnail("image/png", &png).unwrap();
+		assert_eq!(thumbnail.mime_type, "image/jpeg");
+		let metadata = extract_metadata(thumbnail.mime_type, &thumbnail.data).unwrap();
+		assert_eq!(metadata.width, Some(THUMBNAIL_SIZE));
+		assert_eq!(metadata.height, Some(THUMBNAIL_SIZE * 3 / 4));
+
+		// Small images are shown as they are
+		let image = RgbImage::from_pixel(64, 48, Rgb([0, 0, 0]));
+		let mut png = Vec::new();
+		image
+			.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+			.unwrap();
+		assert!(generate_thumbnail("image/png", &png).is_none());
+		assert!(generate_thumbnail("image/gif", &png).is_none());
+	}
+}
@@ -1,4 +1,13 @@
 //! The module for migrating the database.
+//!
+//! Migrations only know how to go up. So that a release can still be rolled
+//! back, a migration may come with SQL that undoes it, which is stored in the
+//! database along with it. An older version of Stonenet that finds a database
+//! of a newer version runs that SQL to get it back to a version that it knows.
+//! If any of the newer migrations can't be undone, it refuses to use the
+//! database instead. Reverting a migration must never lose anything that the
+//! older version needs, so only migrations that add things that can be derived
+//! again, like an index, should be reversible.
 mod util;
 mod v0;
 
@@ -14,8 +23,14 @@ use crate::db::{self, PersistenceHandle};
 /// The latest database version.
 pub const LATEST_VERSION: Version = Version {
 	major: 0,
-	minor: 6,
-	patch: 0,
+	minor: 7,
+	patch: 38,
+};
+/// The version since which the SQL to revert migrations is stored.
+const REVERT_TABLE_VERSION: Version = Version {
+	major: 0,
+	minor: 7,
+	patch: 18,
 };
 
 
@@ -29,11 +44,16 @@ pub struct Version {
 pub struct Migrations {
 	/// A list of available migrations, ordered at version
 	list: Vec<(Version, Box<dyn MigrationTrait>)>,
+	latest: Version,
 }
 
 #[async_trait]
 trait MigrationTrait {
 	async fn run(&self, tx: &db::Transaction) -> db::Result<()>;
+
+	/// The SQL that undoes the migration, if that can be done without losing
+	/// anything.
+	fn revert_sql(&self) -> Option<&'static str> { None }
 }
 
 
@@ -49,10 +69,117 @@ impl Migrations {
 				(Version::new(0, 4, 1), Box::new(v0::v4::v1::Migration)),
 				(Version::new(0, 5, 0), Box::new(v0::v5::v0::Migration)),
 				(Version::new(0, 6, 0), Box::new(v0::v6::v0::Migration)),
+				(Version::new(0, 7, 0), Box::new(v0::v7::v0::Migration)),
+				(Version::new(0, 7, 1), Box::new(v0::v7::v1::Migration)),
+				(Version::new(0, 7, 2), Box::new(v0::v7::v2::Migration)),
+				(Version::new(0, 7, 3), Box::new(v0::v7::v3::Migration)),
+				(Version::new(0, 7, 4), Box::new(v0::v7::v4::Migration)),
+				(Version::new(0, 7, 5), Box::new(v0::v7::v5::Migration)),
+				(Version::new(0, 7, 6), Box::new(v0::v7::v6::Migration)),
+				(Version::new(0, 7, 7), Box::new(v0::v7::v7::Migration)),
+				(Version::new(0, 7, 8), Box::new(v0::v7::v8::Migration)),
+				(Version::new(0, 7, 9), Box::new(v0::v7::v9::Migration)),
+				(Version::new(0, 7, 10), Box::new(v0::v7::v10::Migration)),
+				(Version::new(0, 7, 11), Box::new(v0::v7::v11::Migration)),
+				(Version::new(0, 7, 12), Box::new(v0::v7::v12::Migration)),
+				(Version::new(0, 7, 13), Box::new(v0::v7::v13::Migration)),
+				(Version::new(0, 7, 14), Box::new(v0::v7::v14::Migration)),
+				(Version::new(0, 7, 15), Box::new(v0::v7::v15::Migration)),
+				(Version::new(0, 7, 16), Box::new(v0::v7::v16::Migration)),
+				(Version::new(0, 7, 17), Box::new(v0::v7::v17::Migration)),
+				(Version::new(0, 7, 18), Box::new(v0::v7::v18::Migration)),
+				(Version::new(0, 7, 19), Box::new(v0::v7::v19::Migration)),
+				(Version::new(0, 7, 20), Box::new(v0::v7::v20::Migration)),
+				(Version::new(0, 7, 21), Box::new(v0::v7::v21::Migration)),
+				(Version::new(0, 7, 22), Box::new(v0::v7::v22::Migration)),
+				(Version::new(0, 7, 23), Box::new(v0::v7::v23::Migration)),
+				(Version::new(0, 7, 24), Box::new(v0::v7::v24::Migration)),
+				(Version::new(0, 7, 25), Box::new(v0::v7::v25::Migration)),
+				(Version::new(0, 7, 26), Box::new(v0::v7::v26::Migration)),
+				(Version::new(0, 7, 27), Box::new(v0::v7::v27::Migration)),
+				(Version::new(0, 7, 28), Box::new(v0::v7::v28::Migration)),
+				(Version::new(0, 7, 29), Box::new(v0::v7::v29::Migration)),
+				(Version::new(0, 7, 30), Box::new(v0::v7::v30::Migration)),
+				(Version::new(0, 7, 31), Box::new(v0::v7::v31::Migration)),
+				(Version::new(0, 7, 32), Box::new(v0::v7::v32::Migration)),
+				(Version::new(0, 7, 33), Box::new(v0::v7::v33::Migration)),
+				(Version::new(0, 7, 34), Box::new(v0::v7::v34::Migration)),
+				(Version::new(0, 7, 35), Box::new(v0::v7::v35::Migration)),
+				(Version::new(0, 7, 36), Box::new(v0::v7::v36::Migration)),
+				(Version::new(0, 7, 37), Box::new(v0::v7::v37::Migration)),
+				(Version::new(0, 7, 38), Box::new(v0::v7::v38::Migration)),
 			],
+			latest: LATEST_VERSION,
 		}
 	}
 
+	/// Reverts the migrations that a newer version of Stonenet has done, with
+	/// the SQL that they left behind for it. Fails if any of them can't be
+	/// reverted.
+	async fn downgrade(&self, connection: &db::Database, current: &Version) -> db::Result<()> {
+		let results = connection
+			.inner()
+			.query_all(Statement::from_sql_and_values(
+				DatabaseBackend::Sqlite,
+				r#"
+				SELECT major, minor, patch, sql FROM migration_revert
+				WHERE major > ? OR (major = ? AND (minor > ? OR (minor = ? AND patch > ?)))
+				ORDER BY major DESC, minor DESC, patch DESC
+			"#,
+				[
+					self.latest.major.into(),
+					self.latest.major.into(),
+					self.latest.minor.into(),
+					self.latest.minor.into(),
+					self.latest.patch.into(),
+				],
+			))
+			.await?;
+		let mut reverts = Vec::with_capacity(results.len());
+		for result in results {
+			let version = Version::new(
+				result.try_get_by_index(0)?,
+				result.try_get_by_index(1)?,
+				result.try_get_by_index(2)?,
+			);
+			let sql: Option<String> = result.try_get_by_index(3)?;
+			reverts.push((version, sql));
+		}
+		// Every migration since the revert table exists leaves a record behind, so the
+		// newest one has to be there
+		let is_complete = reverts.first().map(|r| &r.0) == Some(current) &&
+			reverts.iter().all(|r| r.1.is_some());
+		if !is_complete {
+			Err(db::Error::DatabaseTooNew(current.to_string()))?;
+		}
+
+		let tx = connection.transaction().await?;
+		for (version, sql) in reverts {
+			info!("Reverting database migration {}...", version);
+			tx.inner().execute_unprepared(&sql.unwrap()).await?;
+		}
+		tx.inner()
+			.execute(Statement::from_sql_and_values(
+				DatabaseBackend::Sqlite,
+				r#"
+				DELETE FROM migration_revert
+				WHERE major > ? OR (major = ? AND (minor > ? OR (minor = ? AND patch > ?)))
+			"#,
+				[
+					self.latest.major.into(),
+					self.latest.major.into(),
+					self.latest.minor.into(),
+					self.latest.minor.into(),
+					self.latest.patch.into(),
+				],
+			))
+			.await?;
+		self.store_version(&tx, &self.latest).await?;
+		tx.commit().await?;
+		info!("Downgraded database from {} to {}.", current, &self.latest);
+		Ok(())
+	}
+
 	async fn load_version(&self, connection: &db::Database) -> db::Result<Version> {
 		let q = Query::select()
 			.from(Alias::new("version"))
@@ -99,6 +226,29 @@ impl Migrations {
 		Ok(())
 	}
 
+	/// Remembers how to revert the migration to the given version, or that it
+	/// can't be reverted.
+	async fn store_revert(
+		&self, tx: &impl PersistenceHandle, version: &Version, sql: Option<&str>,
+	) -> db::Result<()> {
+		tx.inner()
+			.execute(Statement::from_sql_and_values(
+				DatabaseBackend::Sqlite,
+				r#"
+				INSERT OR REPLACE INTO migration_revert (major, minor, patch, sql)
+				VALUES (?, ?, ?, ?)
+			"#,
+				[
+					version.major.into(),
+					version.minor.into(),
+					version.patch.into(),
+					sql.map(|s| s.to_string()).into(),
+				],
+			))
+			.await?;
+		Ok(())
+	}
+
 	pub async fn run(&self, connection: &db::Database) -> db::Result<()> {
 		// Stop foreign key errors
 		connection
@@ -107,6 +257,11 @@ impl Migrations {
 			.await?;
 
 		let mut current_version = self.load_version(connection).await?;
+		// The database may have been migrated by a newer version already
+		if current_version > self.latest {
+			self.downgrade(connection, &current_version).await?;
+			current_version = self.latest.clone();
+		}
 
 		for (new_version, migration) in &self.list {
 			if new_version > &current_version {
@@ -117,6 +272,10 @@ impl Migrations {
 				);
 				migration.run(&tx).await?;
 				self.store_version(&tx, new_version).await?;
+				if new_version >= &REVERT_TABLE_VERSION {
+					self.store_revert(&tx, new_version, migration.revert_sql())
+						.await?;
+				}
 				tx.commit().await?;
 				info!("Migrated database to {}.", new_version);
 				current_version = new_version.clone();
@@ -124,7 +283,7 @@ impl Migrations {
 		}
 
 		assert_eq!(
-			current_version, LATEST_VERSION,
+			current_version, self.latest,
 			"not migrated to latest version"
 		);
 		connection
@@ -170,3 +329,81 @@ impl PartialOrd for Version {
 		self.patch.partial_cmp(&other.patch)
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	struct IndexMigration;
+
+	struct TableMigration;
+
+	#[async_trait]
+	impl MigrationTrait for IndexMigration {
+		async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+			tx.inner()
+				.execute_unprepared(r#"CREATE INDEX "object_created" ON "object" ("created")"#)
+				.await?;
+			Ok(())
+		}
+
+		fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP INDEX "object_created""#) }
+	}
+
+	#[async_trait]
+	impl MigrationTrait for TableMigration {
+		async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+			tx.inner()
+				.execute_unprepared(r#"CREATE TABLE "future" ("id" integer PRIMARY KEY)"#)
+				.await?;
+			Ok(())
+		}
+	}
+
+	/// Our own migrations, followed by the ones of a newer version.
+	fn newer_migrations(extra: Vec<(Version, Box<dyn MigrationTrait>)>) -> Migrations {
+		let mut migrations = Migrations::load();
+		migrations.latest = extra.last().unwrap().0.clone();
+		migrations.list.extend(extra);
+		migrations
+	}
+
+	#[tokio::test]
+	async fn test_downgrade() {
+		let db = test::load_database("migration").await;
+
+		// A newer version that only added an index can be rolled back
+		newer_migrations(vec![(Version::new(0, 7, 100), Box::new(IndexMigration))])
+			.run(&db)
+			.await
+			.unwrap();
+		Migrations::load().run(&db).await.unwrap();
+		assert_eq!(
+			Migrations::load().load_version(&db).await.unwrap(),
+			LATEST_VERSION
+		);
+		// Upgrading again works as before
+		newer_migrations(vec![(Version::new(0, 7, 100), Box::new(IndexMigration))])
+			.run(&db)
+			.await
+			.unwrap();
+		Migrations::load().run(&db).await.unwrap();
+
+		// One that can't be reverted is refused
+		newer_migrations(vec![
+			(Version::new(0, 7, 100), Box::new(IndexMigration)),
+			(Version::new(0, 7, 101), Box::new(TableMigration)),
+		])
+		.run(&db)
+		.await
+		.unwrap();
+		let error = Migrations::load().run(&db).await.unwrap_err();
+		assert!(matches!(&*error, db::Error::DatabaseTooNew(_)));
+		assert_eq!(
+			Migrations::load().load_version(&db).await.unwrap(),
+			Version::new(0, 7, 101)
+		);
+	}
+}
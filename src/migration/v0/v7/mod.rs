@@ -0,0 +1,39 @@
+pub mod v0;
+pub mod v1;
+pub mod v10;
+pub mod v11;
+pub mod v12;
+pub mod v13;
+pub mod v14;
+pub mod v15;
+pub mod v16;
+pub mod v17;
+pub mod v18;
+pub mod v19;
+pub mod v2;
+pub mod v20;
+pub mod v21;
+pub mod v22;
+pub mod v23;
+pub mod v24;
+pub mod v25;
+pub mod v26;
+pub mod v27;
+pub mod v28;
+pub mod v29;
+pub mod v30;
+pub mod v31;
+pub mod v32;
+pub mod v33;
+pub mod v34;
+pub mod v35;
+pub mod v36;
+pub mod v37;
+pub mod v38;
+pub mod v3;
+pub mod v4;
+pub mod v5;
+pub mod v6;
+pub mod v7;
+pub mod v8;
+pub mod v9;
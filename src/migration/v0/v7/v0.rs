@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+				ALTER TABLE "block" ADD COLUMN "last_access" bigint NOT NULL DEFAULT 0;
+				UPDATE "block" SET "last_access" = CAST(strftime('%s', 'now') AS integer) * 1000;
+
+				CREATE TABLE "archived_block" (
+					"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+					"block_id" bigint NOT NULL UNIQUE,
+					"segment" text NOT NULL,
+					"offset" bigint NOT NULL,
+					"length" bigint NOT NULL,
+					"compression_type" integer NOT NULL,
+					FOREIGN KEY ("block_id") REFERENCES "block" ("id")
+				);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "peer_ban" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"node_address" blob UNIQUE,
+				"subnet" text UNIQUE,
+				"reason" text NOT NULL,
+				"created" bigint NOT NULL,
+				CHECK ("node_address" IS NOT NULL OR "subnet" IS NOT NULL)
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
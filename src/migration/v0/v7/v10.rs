@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "key_encryption" (
+				"id" integer NOT NULL PRIMARY KEY,
+				"salt" blob NOT NULL,
+				"verifier" blob NOT NULL
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,25 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			ALTER TABLE "identity" ADD COLUMN "seed" blob;
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
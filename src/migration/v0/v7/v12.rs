@@ -0,0 +1,27 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			ALTER TABLE "identity" ADD COLUMN "is_active" boolean NOT NULL DEFAULT FALSE;
+			UPDATE "identity" SET "is_active" = TRUE
+			WHERE "rowid" = (SELECT MIN("rowid") FROM "identity");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
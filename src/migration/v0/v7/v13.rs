@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "key_rotation_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"new_public_key" blob NOT NULL,
+				"proof" blob NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
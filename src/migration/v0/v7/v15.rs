@@ -0,0 +1,31 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "domain_verification" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_address" blob NOT NULL,
+				"domain" text NOT NULL,
+				"verified" bigint NOT NULL,
+				UNIQUE("actor_address", "domain")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	common::IdType,
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+/// The number of blocks that are loaded into memory at once.
+const BATCH_SIZE: i64 = 100;
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// Move the data of the blocks into the block store. Archived blocks already
+		// have their data elsewhere.
+		let mut last_id = 0i64;
+		loop {
+			let stat = Statement::from_sql_and_values(
+				tx.backend(),
+				r#"
+				SELECT "id", "hash", "data" FROM "block"
+				WHERE "id" > ? AND length("data") > 0
+				ORDER BY "id" ASC LIMIT ?
+			"#,
+				[last_id.into(), BATCH_SIZE.into()],
+			);
+			let rows = tx.inner().query_all(stat).await?;
+			if rows.len() == 0 {
+				break;
+			}
+			for row in rows {
+				last_id = row.try_get_by_index(0)?;
+				let hash: IdType = row.try_get_by_index(1)?;
+				let data: Vec<u8> = row.try_get_by_index(2)?;
+				tx.block_store().store(&hash, &data)?;
+			}
+		}
+
+		tx.inner()
+			.execute_unprepared(r#"UPDATE "block" SET "data" = x'' WHERE length("data") > 0"#)
+			.await?;
+		Ok(())
+	}
+}
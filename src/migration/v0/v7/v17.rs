@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// All posts and profiles that exist already are indexed the next time
+		// anything is searched for
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE VIRTUAL TABLE "search_index" USING fts5(
+				"text",
+				tokenize = 'unicode61 remove_diacritics 2'
+			);
+			CREATE TABLE "search_queue" (
+				"object_id" integer NOT NULL PRIMARY KEY
+			);
+			INSERT INTO "search_queue" ("object_id")
+			SELECT "object_id" FROM "post_object"
+			UNION SELECT "object_id" FROM "profile_object";
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// Versions before this one can't read this table, so this migration itself
+		// can't be reverted
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "migration_revert" (
+				"major" integer NOT NULL,
+				"minor" integer NOT NULL,
+				"patch" integer NOT NULL,
+				"sql" text,
+				PRIMARY KEY ("major", "minor", "patch")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
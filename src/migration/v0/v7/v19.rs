@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "network_stats" (
+				"hour" integer NOT NULL PRIMARY KEY,
+				"udp_bytes_in" integer NOT NULL DEFAULT 0,
+				"udp_bytes_out" integer NOT NULL DEFAULT 0,
+				"tcp_bytes_in" integer NOT NULL DEFAULT 0,
+				"tcp_bytes_out" integer NOT NULL DEFAULT 0,
+				"opened_connections" integer NOT NULL DEFAULT 0,
+				"max_sessions" integer NOT NULL DEFAULT 0,
+				"relayed_sessions" integer NOT NULL DEFAULT 0,
+				"relayed_bytes" integer NOT NULL DEFAULT 0,
+				"find_node_lookups" integer NOT NULL DEFAULT 0,
+				"find_value_lookups" integer NOT NULL DEFAULT 0
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// Only the statistics are lost
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "network_stats";"#) }
+}
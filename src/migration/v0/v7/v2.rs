@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "node_reputation" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"address" blob NOT NULL UNIQUE,
+				"score" real NOT NULL,
+				"updated" bigint NOT NULL
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
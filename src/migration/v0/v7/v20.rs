@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "bookmark" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_address" blob NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"created" bigint NOT NULL,
+				UNIQUE ("actor_address", "object_hash")
+			);
+			CREATE INDEX "bookmark_object_hash" ON "bookmark" ("object_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// Only the bookmarks are lost
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "bookmark";"#) }
+}
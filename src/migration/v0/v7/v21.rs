@@ -0,0 +1,47 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "draft" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"message" text NOT NULL,
+				"in_reply_to_actor_address" blob,
+				"in_reply_to_object_hash" text(45),
+				"created" bigint NOT NULL,
+				"updated" bigint NOT NULL
+			);
+			CREATE TABLE "draft_file" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"draft_id" bigint NOT NULL,
+				"file_hash" text(45) NOT NULL,
+				"sequence" integer NOT NULL,
+				FOREIGN KEY ("draft_id") REFERENCES "draft" ("id") ON DELETE CASCADE,
+				UNIQUE ("draft_id", "sequence")
+			);
+			CREATE INDEX "draft_file_file_hash" ON "draft_file" ("file_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// The drafts are lost, and their attachments are removed by the next
+	// cleanup of orphaned files
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(r#"DROP TABLE "draft_file"; DROP TABLE "draft";"#)
+	}
+}
@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "scheduled_post" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"draft_id" bigint NOT NULL UNIQUE,
+				"actor_address" blob NOT NULL,
+				"publish_at" bigint NOT NULL,
+				"last_error" text,
+				FOREIGN KEY ("draft_id") REFERENCES "draft" ("id") ON DELETE CASCADE
+			);
+			CREATE INDEX "scheduled_post_publish_at" ON "scheduled_post" ("publish_at");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// The scheduled posts remain as ordinary drafts
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "scheduled_post";"#) }
+}
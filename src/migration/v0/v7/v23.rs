@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "conversation" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"identity_address" blob NOT NULL,
+				"peer_address" blob NOT NULL,
+				"last_activity" bigint NOT NULL,
+				"unread" integer NOT NULL DEFAULT 0,
+				UNIQUE ("identity_address", "peer_address")
+			);
+			CREATE TABLE "direct_message" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"conversation_id" bigint NOT NULL,
+				"hash" text(45) UNIQUE,
+				"incoming" boolean NOT NULL,
+				"mime_type" text NOT NULL,
+				"body" text NOT NULL,
+				"created" bigint NOT NULL,
+				"delivered" boolean NOT NULL,
+				"failures" integer NOT NULL DEFAULT 0,
+				"next_attempt" bigint,
+				FOREIGN KEY ("conversation_id") REFERENCES "conversation" ("id") ON DELETE CASCADE
+			);
+			CREATE INDEX "direct_message_conversation"
+				ON "direct_message" ("conversation_id", "created");
+			CREATE INDEX "direct_message_next_attempt" ON "direct_message" ("next_attempt");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(r#"DROP TABLE "direct_message"; DROP TABLE "conversation";"#)
+	}
+}
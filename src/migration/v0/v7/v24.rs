@@ -0,0 +1,37 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "file_metadata" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"file_hash" text(45) NOT NULL UNIQUE,
+				"mime_type" text NOT NULL,
+				"width" integer,
+				"height" integer,
+				"duration" bigint,
+				"blurhash" text
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// The metadata of the files that have been uploaded so far is lost, which
+	// only means that their posts are shown without placeholders
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "file_metadata";"#) }
+}
@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "peer_traffic" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"day" bigint NOT NULL,
+				"node_address" blob NOT NULL,
+				"bytes_in" integer NOT NULL DEFAULT 0,
+				"bytes_out" integer NOT NULL DEFAULT 0,
+				UNIQUE ("day", "node_address")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// Only the statistics are lost
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "peer_traffic";"#) }
+}
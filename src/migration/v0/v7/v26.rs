@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// Tags are stored in lower case without the # from now on. All posts are
+		// indexed again, so that the hashtags in their messages are found.
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			UPDATE OR IGNORE "post_tag" SET "tag" = lower(ltrim("tag", '#'));
+			DELETE FROM "post_tag" WHERE "tag" <> lower(ltrim("tag", '#'));
+			CREATE INDEX "post_tag_tag" ON "post_tag" ("tag");
+			INSERT OR IGNORE INTO "search_queue" ("object_id")
+			SELECT "object_id" FROM "post_object";
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// The tags stay the way they are now, which older versions can work with
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP INDEX "post_tag_tag";"#) }
+}
@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// The mentions in the posts that exist already are found the next time
+		// the posts are indexed, which the previous migration has queued them
+		// for
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "post_mention" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"object_id" bigint NOT NULL,
+				"actor_address" blob NOT NULL,
+				"notified" boolean NOT NULL DEFAULT FALSE,
+				FOREIGN KEY ("object_id") REFERENCES "post_object" ("object_id") ON DELETE NO ACTION ON UPDATE NO ACTION,
+				UNIQUE ("object_id", "actor_address")
+			);
+			CREATE INDEX "post_mention_actor_address" ON "post_mention" ("actor_address");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "post_mention";"#) }
+}
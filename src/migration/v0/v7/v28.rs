@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// The thumbnails of the images that exist already are generated when
+		// they are first asked for
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "file_thumbnail" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"file_hash" text(45) NOT NULL UNIQUE,
+				"mime_type" text NOT NULL,
+				"data" blob NOT NULL
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "file_thumbnail";"#) }
+}
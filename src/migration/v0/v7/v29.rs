@@ -0,0 +1,43 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "upload" (
+				"id" text NOT NULL PRIMARY KEY,
+				"mime_type" text NOT NULL,
+				"size" bigint NOT NULL,
+				"received" bigint NOT NULL DEFAULT 0,
+				"updated" bigint NOT NULL
+			);
+			CREATE TABLE "upload_chunk" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"upload_id" text NOT NULL,
+				"start" bigint NOT NULL,
+				"data" blob NOT NULL,
+				FOREIGN KEY ("upload_id") REFERENCES "upload" ("id") ON DELETE CASCADE ON UPDATE NO ACTION,
+				UNIQUE ("upload_id", "start")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(r#"DROP TABLE "upload_chunk"; DROP TABLE "upload";"#)
+	}
+}
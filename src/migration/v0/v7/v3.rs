@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "petname" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_address" blob NOT NULL UNIQUE,
+				"name" text NOT NULL UNIQUE,
+				"created" bigint NOT NULL
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
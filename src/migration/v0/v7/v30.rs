@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "edit_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"edited_object_hash" text(45) NOT NULL,
+				"file_count" bigint NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			CREATE INDEX "edit_object_edited_object_hash" ON "edit_object" ("edited_object_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "edit_object";"#) }
+}
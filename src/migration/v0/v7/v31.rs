@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "delete_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"deleted_object_hash" text(45) NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			CREATE INDEX "delete_object_deleted_object_hash" ON "delete_object" ("deleted_object_hash");
+			CREATE TABLE "tombstone" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(r#"DROP TABLE "tombstone"; DROP TABLE "delete_object";"#)
+	}
+}
@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "reaction_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"actor_address" blob NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"emoji" text NOT NULL,
+				"notified" boolean NOT NULL DEFAULT FALSE,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			CREATE INDEX "reaction_object_object_hash" ON "reaction_object" ("object_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> { Some(r#"DROP TABLE "reaction_object";"#) }
+}
@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "poll_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"question" text NOT NULL,
+				"multiple_choice" boolean NOT NULL,
+				"closes" bigint NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			CREATE TABLE "poll_option" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"object_id" bigint NOT NULL,
+				"sequence" integer NOT NULL,
+				"text" text NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "poll_object" ("object_id") ON DELETE NO ACTION ON UPDATE NO ACTION,
+				UNIQUE ("object_id", "sequence")
+			);
+			CREATE TABLE "vote_object" (
+				"object_id" bigint NOT NULL PRIMARY KEY,
+				"actor_address" blob NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"choices" blob NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			CREATE INDEX "vote_object_object_hash" ON "vote_object" ("object_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(r#"DROP TABLE "vote_object"; DROP TABLE "poll_option"; DROP TABLE "poll_object";"#)
+	}
+}
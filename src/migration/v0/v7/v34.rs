@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			ALTER TABLE "profile_object" ADD COLUMN "followers_only" boolean NOT NULL DEFAULT FALSE;
+			CREATE TABLE "follower_list_entry" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"object_id" bigint NOT NULL,
+				"sequence" integer NOT NULL,
+				"actor_address" blob NOT NULL,
+				"node_address" blob NOT NULL,
+				FOREIGN KEY ("object_id") REFERENCES "object" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION,
+				UNIQUE ("object_id", "sequence")
+			);
+			CREATE TABLE "follow_request" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_id" bigint NOT NULL,
+				"requester_address" blob NOT NULL,
+				"node_address" blob NOT NULL,
+				"hash" text(45) NOT NULL,
+				"created" bigint NOT NULL,
+				"accepted" boolean,
+				FOREIGN KEY ("actor_id") REFERENCES "actor" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION,
+				UNIQUE ("actor_id", "requester_address")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(
+			r#"
+			DROP TABLE "follow_request";
+			DROP TABLE "follower_list_entry";
+			ALTER TABLE "profile_object" DROP COLUMN "followers_only";
+		"#,
+		)
+	}
+}
@@ -0,0 +1,43 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "actor_block" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_id" bigint NOT NULL,
+				"actor_address" blob,
+				"activity_pub_actor_url" text,
+				"blocked" boolean NOT NULL,
+				"created" bigint NOT NULL,
+				FOREIGN KEY ("actor_id") REFERENCES "actor" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION,
+				UNIQUE ("actor_id", "actor_address"),
+				UNIQUE ("actor_id", "activity_pub_actor_url")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(
+			r#"
+			DROP TABLE "actor_block";
+		"#,
+		)
+	}
+}
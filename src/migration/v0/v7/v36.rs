@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "local_user" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"name" text NOT NULL UNIQUE,
+				"password_hash" text NOT NULL,
+				"is_admin" boolean NOT NULL,
+				"created" bigint NOT NULL
+			);
+			CREATE TABLE "local_user_following" (
+				"user_id" bigint NOT NULL,
+				"actor_id" bigint NOT NULL,
+				PRIMARY KEY ("user_id", "actor_id"),
+				FOREIGN KEY ("user_id") REFERENCES "local_user" ("id") ON DELETE CASCADE ON UPDATE NO ACTION,
+				FOREIGN KEY ("actor_id") REFERENCES "actor" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+			ALTER TABLE "identity" ADD COLUMN "user_id" bigint REFERENCES "local_user" ("id");
+			ALTER TABLE "following" ADD COLUMN "user_only" boolean NOT NULL DEFAULT FALSE;
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(
+			r#"
+			ALTER TABLE "following" DROP COLUMN "user_only";
+			ALTER TABLE "identity" DROP COLUMN "user_id";
+			DROP TABLE "local_user_following";
+			DROP TABLE "local_user";
+		"#,
+		)
+	}
+}
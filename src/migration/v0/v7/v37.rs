@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		// The bookmark table is made again, because every local user can
+		// bookmark the same object
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			ALTER TABLE "draft" ADD COLUMN "user_id" bigint;
+			ALTER TABLE "journal_entry" ADD COLUMN "user_id" bigint;
+			CREATE TABLE "bookmark_new" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_address" blob NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"created" bigint NOT NULL,
+				"user_id" bigint
+			);
+			INSERT INTO "bookmark_new" ("id", "actor_address", "object_hash", "created")
+				SELECT "id", "actor_address", "object_hash", "created" FROM "bookmark";
+			DROP TABLE "bookmark";
+			ALTER TABLE "bookmark_new" RENAME TO "bookmark";
+			CREATE UNIQUE INDEX "bookmark_user_object" ON "bookmark" (
+				IFNULL("user_id", 0), "actor_address", "object_hash"
+			);
+			CREATE INDEX "bookmark_object_hash" ON "bookmark" ("object_hash");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+
+	// The bookmarks, drafts and journal entries of local users are handed over
+	// to the owner of the node
+	fn revert_sql(&self) -> Option<&'static str> {
+		Some(
+			r#"
+			CREATE TABLE "bookmark_old" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_address" blob NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"created" bigint NOT NULL,
+				UNIQUE ("actor_address", "object_hash")
+			);
+			INSERT OR IGNORE INTO "bookmark_old" ("id", "actor_address", "object_hash", "created")
+				SELECT "id", "actor_address", "object_hash", "created" FROM "bookmark";
+			DROP TABLE "bookmark";
+			ALTER TABLE "bookmark_old" RENAME TO "bookmark";
+			CREATE INDEX "bookmark_object_hash" ON "bookmark" ("object_hash");
+			ALTER TABLE "journal_entry" DROP COLUMN "user_id";
+			ALTER TABLE "draft" DROP COLUMN "user_id";
+		"#,
+		)
+	}
+}
@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "abandoned_actor" (
+				"actor_id" bigint NOT NULL PRIMARY KEY,
+				"since" bigint NOT NULL,
+				FOREIGN KEY ("actor_id") REFERENCES "actor" ("id") ON DELETE NO ACTION ON UPDATE NO ACTION
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "journal_entry" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"action" integer NOT NULL,
+				"subject" text NOT NULL,
+				"object_hash" text(45),
+				"detail" text,
+				"created" bigint NOT NULL,
+				"undone" bigint
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "parity_block" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"file_hash" text(45) NOT NULL,
+				"stripe" integer NOT NULL,
+				"shard" integer NOT NULL,
+				"data" blob NOT NULL,
+				"created" bigint NOT NULL,
+				UNIQUE("file_hash", "stripe", "shard")
+			);
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
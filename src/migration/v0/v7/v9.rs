@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use sea_orm::*;
+
+use crate::{
+	db::{self, PersistenceHandle},
+	migration::MigrationTrait,
+};
+
+
+pub struct Migration;
+
+
+#[async_trait]
+impl MigrationTrait for Migration {
+	async fn run(&self, tx: &db::Transaction) -> db::Result<()> {
+		tx.inner()
+			.execute_unprepared(
+				r#"
+			CREATE TABLE "delivery_queue" (
+				"id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+				"actor_id" integer NOT NULL,
+				"object_hash" text(45) NOT NULL,
+				"recipient_address" text NOT NULL,
+				"recipient_contact" blob NOT NULL,
+				"failures" integer NOT NULL DEFAULT 0,
+				"next_attempt" bigint NOT NULL,
+				"created" bigint NOT NULL,
+				UNIQUE("actor_id", "object_hash", "recipient_address")
+			);
+			CREATE INDEX "delivery_queue_next_attempt" ON "delivery_queue" ("next_attempt");
+		"#,
+			)
+			.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,235 @@
+//! Resolves human-friendly names into actor addresses.
+//!
+//! An actor can be referred to by its address, by a petname that the user has
+//! given it, or by a name like `alice@example.com`. The latter is only resolved
+//! if `resolve_dns_names` is enabled, and uses the TXT record of
+//! `alice._stonenet.example.com`, which should contain the actor's address.
+
+use std::str::FromStr;
+
+use chrono::Utc;
+use hickory_resolver::{
+	error::{ResolveError, ResolveErrorKind},
+	TokioAsyncResolver,
+};
+use sea_orm::{prelude::*, NotSet, QueryOrder, Set};
+
+use crate::{
+	core::{ActorAddress, Address},
+	db::{self, Database, PersistenceHandle},
+	entity::petname,
+	trace::Traced,
+};
+
+
+/// The maximum number of characters of a petname.
+pub const PETNAME_MAX_LENGTH: usize = 64;
+/// The message to show when a petname is already given to another actor.
+pub const PETNAME_TAKEN_MESSAGE: &str = "That petname is already given to another actor";
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("database error: {0}")]
+	Database(Traced<db::Error>),
+	#[error("DNS lookup failed: {0}")]
+	Dns(#[from] ResolveError),
+	#[error("\"{0}\" is not a valid domain name")]
+	InvalidDomainName(String),
+	#[error("not an actor address")]
+	NotAnActor,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+impl From<Traced<db::Error>> for Error {
+	fn from(other: Traced<db::Error>) -> Self { Self::Database(other) }
+}
+
+
+/// Checks whether the given name can be used as a petname. Petnames can't be
+/// mistaken for an address or a DNS name.
+pub fn check_petname(name: &str) -> std::result::Result<(), &'static str> {
+	if name.trim().is_empty() {
+		Err("a petname can't be empty")
+	} else if name.chars().count() > PETNAME_MAX_LENGTH {
+		Err("a petname can't be longer than 64 characters")
+	} else if name.contains('@') {
+		Err("a petname can't contain an @ sign")
+	} else if Address::from_str(name).is_ok() {
+		Err("a petname can't be an address")
+	} else {
+		Ok(())
+	}
+}
+
+pub async fn find_by_petname(db: &Database, name: &str) -> db::Result<Option<ActorAddress>> {
+	Ok(petname::Entity::find()
+		.filter(petname::Column::Name.eq(name))
+		.one(db.inner())
+		.await?
+		.map(|r| r.actor_address))
+}
+
+pub async fn find_petname(db: &Database, address: &ActorAddress) -> db::Result<Option<String>> {
+	Ok(petname::Entity::find()
+		.filter(petname::Column::ActorAddress.eq(address))
+		.one(db.inner())
+		.await?
+		.map(|r| r.name))
+}
+
+pub async fn load_petnames(db: &Database) -> db::Result<Vec<petname::Model>> {
+	Ok(petname::Entity::find()
+		.order_by_asc(petname::Column::Name)
+		.all(db.inner())
+		.await?)
+}
+
+/// Gives the actor the given petname, replacing the one it had before.
+/// Returns None if another actor already goes by that petname.
+pub async fn set_petname(
+	db: &Database, address: &ActorAddress, name: &str,
+) -> db::Result<Option<petname::Model>> {
+	let name = name.trim();
+	if let Some(other) = find_by_petname(db, name).await? {
+		if &other != address {
+			return Ok(None);
+		}
+	}
+
+	let tx = db.transaction().await?;
+	petname::Entity::delete_many()
+		.filter(petname::Column::ActorAddress.eq(address))
+		.exec(tx.inner())
+		.await?;
+	let model = petname::ActiveModel {
+		id: NotSet,
+		actor_address: Set(address.clone()),
+		name: Set(name.to_string()),
+		created: Set(Utc::now().timestamp_millis()),
+	};
+	let record = model.insert(tx.inner()).await?;
+	tx.commit().await?;
+	Ok(Some(record))
+}
+
+/// Removes the petname of the given actor, if it has one.
+pub async fn clear_petname(db: &Database, address: &ActorAddress) -> db::Result<()> {
+	petname::Entity::delete_many()
+		.filter(petname::Column::ActorAddress.eq(address))
+		.exec(db.inner())
+		.await?;
+	Ok(())
+}
+
+/// Removes the petname with the given ID. Returns false if it didn't exist.
+pub async fn remove_petname(db: &Database, id: i64) -> db::Result<bool> {
+	let result = petname::Entity::delete_by_id(id).exec(db.inner()).await?;
+	Ok(result.rows_affected > 0)
+}
+
+fn is_valid_label(label: &str) -> bool {
+	!label.is_empty() &&
+		label.len() <= 63 &&
+		label
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn is_valid_domain_name(domain: &str) -> bool { domain.split('.').all(is_valid_label) }
+
+/// Resolves the given input into an actor address. The input can be an actor
+/// address, a petname if `use_petnames` is set, or a DNS name if `use_dns` is
+/// set. Returns `None` if the name isn't known.
+pub async fn resolve(
+	db: &Database, input: &str, use_petnames: bool, use_dns: bool,
+) -> Result<Option<ActorAddress>> {
+	let input = input.trim();
+	if let Ok(address) = Address::from_str(input) {
+		return match address {
+			Address::Actor(actor_address) => Ok(Some(actor_address)),
+			_ => Err(Error::NotAnActor),
+		};
+	}
+
+	if let Some((name, domain)) = input.split_once('@') {
+		if use_dns {
+			resolve_dns_name(name, domain).await
+		} else {
+			Ok(None)
+		}
+	} else if use_petnames {
+		Ok(find_by_petname(db, input).await?)
+	} else {
+		Ok(None)
+	}
+}
+
+/// Looks up the actor address that the given domain has published for the
+/// given name.
+pub async fn resolve_dns_name(name: &str, domain: &str) -> Result<Option<ActorAddress>> {
+	if !is_valid_label(name) || !is_valid_domain_name(domain) {
+		return Err(Error::InvalidDomainName(format!("{}@{}", name, domain)));
+	}
+
+	let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+	let lookup = match resolver
+		.txt_lookup(format!("{}._stonenet.{}.", name, domain))
+		.await
+	{
+		Ok(l) => l,
+		Err(e) => match e.kind() {
+			ResolveErrorKind::NoRecordsFound { .. } => return Ok(None),
+			_ => return Err(e.into()),
+		},
+	};
+	for record in lookup.iter() {
+		let text: String = record
+			.txt_data()
+			.iter()
+			.map(|part| String::from_utf8_lossy(part))
+			.collect();
+		if let Ok(Address::Actor(address)) = Address::from_str(text.trim()) {
+			return Ok(Some(address));
+		}
+	}
+	Ok(None)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_check_petname() {
+		let mut rng = test::initialize_rng();
+		let address = Address::Actor(ActorAddress::V1(IdType::random(&mut rng)));
+
+		assert!(check_petname("Alice").is_ok());
+		assert!(check_petname("  ").is_err());
+		assert!(check_petname("alice@example.com").is_err());
+		assert!(check_petname(&address.to_string()).is_err());
+		assert!(check_petname(&"a".repeat(PETNAME_MAX_LENGTH + 1)).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_set_petname() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("petname").await;
+		let alice = ActorAddress::V1(IdType::random(&mut rng));
+		let bob = ActorAddress::V1(IdType::random(&mut rng));
+
+		assert!(set_petname(&db, &alice, "Friend").await.unwrap().is_some());
+		assert!(set_petname(&db, &alice, " Friend ").await.unwrap().is_some());
+		// The name is taken, and the petname of Bob is left alone
+		assert!(set_petname(&db, &bob, "Friend").await.unwrap().is_none());
+		assert!(set_petname(&db, &bob, "Other").await.unwrap().is_some());
+		assert!(set_petname(&db, &bob, "Friend").await.unwrap().is_none());
+		assert_eq!(find_petname(&db, &bob).await.unwrap().as_deref(), Some("Other"));
+		assert_eq!(find_by_petname(&db, "Friend").await.unwrap(), Some(alice));
+	}
+}
@@ -1,13 +1,21 @@
 pub mod actor;
 mod actor_store;
+pub mod banlist;
 pub mod binserde;
-mod bucket;
+pub mod bucket;
 mod connection_manager;
+pub mod event;
+pub mod load;
 pub mod message;
 mod node;
 pub mod overlay;
+pub mod rate_limit;
+pub mod reputation;
 mod socket;
 pub(crate) mod sstp;
+pub mod stats;
+mod value_cache;
+pub mod value_type;
 
 
 use std::{
@@ -94,6 +102,21 @@ pub struct LinkProtocol {
 	pub use_tcp: bool,
 }
 
+/// The limits on the caches and buffers of a node, which are lowered by the
+/// low-memory profile.
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryLimits {
+	pub sessions: usize,
+	/// The most packets that are buffered for one window of a message.
+	pub window_packets: u16,
+	pub attached_nodes: usize,
+	/// The number of values that are cached per network.
+	pub cached_values: usize,
+	/// The number of unresponsive nodes that are kept aside per bucket.
+	pub bucket_replacements: usize,
+	pub rate_limited_peers: usize,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct NodeContactInfo {
 	pub address: NodeAddress,
@@ -1,10 +1,25 @@
+pub mod download;
+pub mod delivery;
+mod direct_message;
+mod follower;
+mod fork;
+mod gossip;
+mod key_chain;
+mod log_sync;
+mod parity;
+pub mod reach;
+pub mod status;
+
+
 use std::{
+	collections::HashSet,
+	mem,
 	net::SocketAddr,
 	sync::{
 		atomic::{AtomicBool, AtomicPtr, Ordering},
 		Arc, Mutex as StdMutex,
 	},
-	time::{Duration, SystemTime, UNIX_EPOCH},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
@@ -12,28 +27,32 @@ use futures::{
 	future::{join_all, BoxFuture},
 	FutureExt,
 };
-use log::{error, warn};
+use log::{debug, error, warn};
 use sea_orm::{prelude::*, ActiveValue::*};
 use serde::de::DeserializeOwned;
 use tokio::{spawn, time::sleep};
 
+use self::{fork::wins_fork, gossip::GOSSIP_HOPS, reach::ReachEstimator};
 use super::{
 	binserde,
+	event::{Event, SyncStage},
 	message::{
 		FindBlockResult, FindFileResult, FindNextObjectResult, FindObjectResult, GetProfileRequest,
-		GetProfileResponse, HeadResponse, PublishObjectMessage, PublishObjectRequest,
-		PublishObjectResponse,
+		GetProfileResponse, HeadResponse, NotifyObjectRequest, NotifyObjectResponse,
+		PublishObjectMessage, PublishObjectRequest, PublishObjectResponse, SignedValue,
 	},
 	node::{ContactStrategyMethod, Node, NodeInterface},
-	overlay::OverlayNode,
+	overlay::{archiver::Archiver, seeding::SeedingPolicy, OverlayNode},
 	sstp::{self, Connection, MessageProcessorResult, MessageWorkToDo, Result},
+	value_type::{ValueHandler, ValueTypeRegistry},
 };
 use crate::{
 	common::*,
 	core::*,
-	db::{self, Database, PersistenceHandle},
-	entity::{block, object},
+	db::{self, Database, PersistenceHandle, SyncDepth},
+	entity::object,
 	identity::ActorPublicKeyV1,
+	limited_store::LimitedMap,
 	net::{message::BlogchainValueType, NodeContactInfo},
 	trace::Mutex,
 };
@@ -45,6 +64,44 @@ pub const ACTOR_MESSAGE_TYPE_GET_PROFILE_REQUEST: u8 = 66;
 pub const ACTOR_MESSAGE_TYPE_GET_PROFILE_RESPONSE: u8 = 67 | 0x80;
 pub const ACTOR_MESSAGE_TYPE_PUBLISH_OBJECT_REQUEST: u8 = 70;
 pub const ACTOR_MESSAGE_TYPE_PUBLISH_OBJECT_RESPONSE: u8 = 71 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_NOTIFY_OBJECT_REQUEST: u8 = 72;
+pub const ACTOR_MESSAGE_TYPE_NOTIFY_OBJECT_RESPONSE: u8 = 73 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_SYNC_LOG_REQUEST: u8 = 74;
+pub const ACTOR_MESSAGE_TYPE_SYNC_LOG_RESPONSE: u8 = 75 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST: u8 = 76;
+pub const ACTOR_MESSAGE_TYPE_BLOCK_MAP_RESPONSE: u8 = 77 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_STORE_PARITY_REQUEST: u8 = 78;
+pub const ACTOR_MESSAGE_TYPE_STORE_PARITY_RESPONSE: u8 = 79 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST: u8 = 80;
+pub const ACTOR_MESSAGE_TYPE_FIND_PARITY_RESPONSE: u8 = 81 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_REACH_SKETCH_REQUEST: u8 = 82;
+pub const ACTOR_MESSAGE_TYPE_REACH_SKETCH_RESPONSE: u8 = 83 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_MESSAGE_KEY_REQUEST: u8 = 84;
+pub const ACTOR_MESSAGE_TYPE_MESSAGE_KEY_RESPONSE: u8 = 85 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_REQUEST: u8 = 86;
+pub const ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_RESPONSE: u8 = 87 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_REQUEST: u8 = 88;
+pub const ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_RESPONSE: u8 = 89 | 0x80;
+pub const ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_REQUEST: u8 = 90;
+pub const ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_RESPONSE: u8 = 91 | 0x80;
+
+/// The protocol version that other nodes need to speak to take part in actor
+/// networks with us. Version 1 changed how objects are encoded, with key
+/// rotations, delegation certificates and the other new payloads, and sends
+/// next objects along with their signature. Nodes of version 0 can't decode
+/// any of that, so they are neither sent requests nor answered.
+pub const ACTOR_PROTOCOL_VERSION: u16 = 1;
+
+/// The amount of block data that is collected before storing it all at once.
+/// It is kept small, because the blocks of a batch that fails to be stored need
+/// to be downloaded again.
+const BLOCK_INGEST_BATCH_LIMIT: usize = 0x200000; // 2 MiB
+/// The maximum number of followers that are notified of new objects directly.
+const FOLLOWERS_LIMIT: usize = 100;
+/// Nodes that haven't synchronized with us for this long are not considered to
+/// be following anymore. This is longer than the interval at which actor
+/// networks are synchronized, so that followers keep showing up.
+const FOLLOWER_TTL: Duration = Duration::from_secs(7200);
 
 /// The amount of recent objects to always store for an actor.
 pub const ACTOR_LIMIT_RECENT_OBJECTS: u64 = 1_000;
@@ -65,8 +122,19 @@ pub const ACTOR_MIN_LIMIT_TOTAL_BLOCK_SPACE: u64 = 100_000_000;
 
 pub struct ActorNode {
 	pub(super) base: Arc<Node<ActorInterface>>,
+	/// The nodes that belong to actors that the actor has blocked, which are
+	/// refused.
+	blocked_nodes: StdMutex<HashSet<NodeAddress>>,
 	downloading_objects: Mutex<Vec<IdType>>,
 	is_synchonizing: Arc<AtomicBool>,
+	/// The nodes that have recently synchronized with us in this actor network,
+	/// and when they did. Only the nodes that follow the actor keep
+	/// synchronizing, other nodes just look things up.
+	followers: StdMutex<LimitedMap<NodeAddress, (NodeContactInfo, Instant)>>,
+	/// When the last synchronization finished without errors, in milliseconds
+	/// since the epoch.
+	last_synchronized: StdMutex<Option<u64>>,
+	reach: StdMutex<ReachEstimator>,
 }
 
 pub struct ActorInterface {
@@ -76,7 +144,21 @@ pub struct ActorInterface {
 	actor_id: i64,
 	actor_info: ActorInfo,
 	head_sequence: StdMutex<Option<u64>>,
+	key_chain: StdMutex<KeyChain>,
 	is_lurker: bool,
+	value_types: ValueTypeRegistry<ActorInterface>,
+}
+
+struct BlockValueHandler;
+struct FileValueHandler;
+struct ObjectValueHandler;
+struct NextObjectValueHandler;
+
+struct NotifyObjectToDo {
+	node: Arc<ActorNode>,
+	id: IdType,
+	object: BlogchainObject,
+	hops_left: u8,
 }
 
 struct PublishObjectToDo {
@@ -87,15 +169,8 @@ struct PublishObjectToDo {
 
 impl ActorInterface {
 	async fn find_block(&self, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		let result = block::Entity::find()
-			.filter(block::Column::Hash.eq(id))
-			.one(self.db.inner())
-			.await?;
-
-		if let Some(record) = result {
-			let response = FindBlockResult {
-				data: record.data.into(),
-			};
+		if let Some(data) = self.db.load_block(id).await? {
+			let response = FindBlockResult { data: data.into() };
 			Ok(Some(binserde::serialize(&response).unwrap()))
 		} else {
 			Ok(None)
@@ -103,28 +178,33 @@ impl ActorInterface {
 	}
 
 	async fn find_file(&self, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db.connect_old()?;
-			c.fetch_file(id)
-		})?;
+		let id = id.clone();
+		let result = self.db.perform(move |c| c.fetch_file(&id)).await?;
 		Ok(result.map(|file| binserde::serialize(&file).unwrap()))
 	}
 
 	async fn find_object(&self, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db.connect_old()?;
-			c.fetch_object(id)
-		})?;
+		let id = id.clone();
+		let result = self.db.perform(move |c| c.fetch_object(&id)).await?;
 		Ok(result.map(|(object, _)| binserde::serialize(&FindObjectResult { object }).unwrap()))
 	}
 
 	async fn find_next_object(&self, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		let result = tokio::task::block_in_place(|| {
-			let mut c = self.db.connect_old()?;
-			c.fetch_next_object(&self.actor_address, id)
-		})?;
+		let actor_address = self.actor_address.clone();
+		let id = id.clone();
+		let result = self
+			.db
+			.perform(move |mut c| c.fetch_next_object(&actor_address, &id))
+			.await?;
+		// The next object can't be verified by the ID that was looked up, so it is
+		// sent along with the signature and creation time of the actor.
 		Ok(result.map(|(hash, object, _)| {
-			binserde::serialize(&FindNextObjectResult { hash, object }).unwrap()
+			let signed_value = SignedValue {
+				signature: object.signature.clone(),
+				published: object.created,
+				data: binserde::serialize(&FindNextObjectResult { hash, object }).unwrap(),
+			};
+			binserde::serialize(&signed_value).unwrap()
 		}))
 	}
 }
@@ -133,22 +213,7 @@ impl ActorInterface {
 impl NodeInterface for ActorInterface {
 	async fn close(&self) {}
 
-	async fn find_value(&self, value_type: u8, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		const VALUE_TYPE_BLOCK: u8 = BlogchainValueType::Block as _;
-		const VALUE_TYPE_FILE: u8 = BlogchainValueType::File as _;
-		const VALUE_TYPE_OBJECT: u8 = BlogchainValueType::Object as _;
-		const VALUE_TYPE_NEXT_OBJECT: u8 = BlogchainValueType::NextObject as _;
-		match value_type {
-			VALUE_TYPE_BLOCK => self.find_block(id).await,
-			VALUE_TYPE_FILE => self.find_file(id).await,
-			VALUE_TYPE_OBJECT => self.find_object(id).await,
-			VALUE_TYPE_NEXT_OBJECT => self.find_next_object(id).await,
-			other => {
-				warn!("Invalid block type requested: {} for id {}", other, id);
-				return Ok(None);
-			}
-		}
-	}
+	fn min_protocol_version(&self) -> u16 { ACTOR_PROTOCOL_VERSION }
 
 	fn overlay_node(&self) -> Arc<OverlayNode> { self.overlay_node.clone() }
 
@@ -160,6 +225,94 @@ impl NodeInterface for ActorInterface {
 		new_buffer.extend(buffer);
 		new_buffer
 	}
+
+	fn value_types(&self) -> &ValueTypeRegistry<Self> { &self.value_types }
+}
+
+#[async_trait]
+impl ValueHandler<ActorInterface> for BlockValueHandler {
+	async fn find(&self, actor: &ActorInterface, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+		actor.find_block(id).await
+	}
+
+	fn verify(&self, _actor: &ActorInterface, id: &IdType, data: &[u8]) -> bool {
+		match binserde::deserialize::<FindBlockResult>(data) {
+			Err(e) => {
+				warn!("Malformed block received: {}", e);
+				false
+			}
+			Ok(result) => &IdType::hash(&result.data) == id,
+		}
+	}
+}
+
+#[async_trait]
+impl ValueHandler<ActorInterface> for FileValueHandler {
+	async fn find(&self, actor: &ActorInterface, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+		actor.find_file(id).await
+	}
+
+	fn verify(&self, _actor: &ActorInterface, id: &IdType, data: &[u8]) -> bool {
+		match binserde::deserialize::<FindFileResult>(data) {
+			Err(e) => {
+				warn!("Malformed file received: {}", e);
+				false
+			}
+			Ok(result) => &IdType::hash(&binserde::serialize(&result.file).unwrap()) == id,
+		}
+	}
+}
+
+#[async_trait]
+impl ValueHandler<ActorInterface> for ObjectValueHandler {
+	async fn find(&self, actor: &ActorInterface, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+		actor.find_object(id).await
+	}
+
+	fn verify(&self, actor: &ActorInterface, id: &IdType, data: &[u8]) -> bool {
+		match binserde::deserialize::<FindObjectResult>(data) {
+			Err(e) => {
+				warn!("Malformed object received: {}", e);
+				false
+			}
+			Ok(result) => actor.verify_object(id, &result.object),
+		}
+	}
+}
+
+#[async_trait]
+impl ValueHandler<ActorInterface> for NextObjectValueHandler {
+	async fn find(&self, actor: &ActorInterface, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+		actor.find_next_object(id).await
+	}
+
+	fn is_signed(&self) -> bool { true }
+
+	// The next object is verified completely with its signature already.
+	fn verify(&self, _actor: &ActorInterface, _id: &IdType, _data: &[u8]) -> bool { true }
+
+	fn verify_signature(&self, actor: &ActorInterface, id: &IdType, value: &SignedValue) -> bool {
+		let result: FindNextObjectResult = match binserde::deserialize(&value.data) {
+			Err(e) => {
+				warn!("Malformed next object received: {}", e);
+				return false;
+			}
+			Ok(r) => r,
+		};
+		let object = &result.object;
+		if &object.previous_hash != id {
+			warn!("Next object {} doesn't follow object {}.", &result.hash, id);
+			return false;
+		}
+		if object.signature != value.signature || object.created != value.published {
+			warn!(
+				"Next object {} doesn't match the signature it came with.",
+				&result.hash
+			);
+			return false;
+		}
+		actor.verify_object(&result.hash, object)
+	}
 }
 
 impl ActorNode {
@@ -167,6 +320,22 @@ impl ActorNode {
 
 	pub async fn close(self: Arc<Self>) { self.base.close().await; }
 
+	/// Whether the node belongs to an actor that the actor has blocked.
+	pub fn is_blocked_node(&self, address: &NodeAddress) -> bool {
+		self.blocked_nodes.lock().unwrap().contains(address)
+	}
+
+	/// Loads the nodes to refuse again, after the actor has blocked or unblocked
+	/// another actor.
+	pub async fn reload_blocked_nodes(&self) -> db::Result<()> {
+		let blocked_nodes = self
+			.db()
+			.load_blocked_nodes(self.base.interface.actor_id)
+			.await?;
+		*self.blocked_nodes.lock().unwrap() = blocked_nodes;
+		Ok(())
+	}
+
 	/// Attempts to collect as much blocks of this file on the given connection.
 	pub async fn collect_block(
 		&self, connection: &mut Connection, file_id: i64, block_id: &IdType,
@@ -176,7 +345,7 @@ impl ActorNode {
 			.await
 		{
 			if self.verify_block(block_id, &result.data) {
-				self.store_block(file_id, block_id, &result.data)?;
+				self.store_block(file_id, block_id, &result.data).await?;
 			} else {
 				return Ok(false);
 			}
@@ -193,11 +362,11 @@ impl ActorNode {
 			.await
 		{
 			if self.verify_file(file_id, &result.file) {
-				let file_id = self.store_file(file_id, &result.file)?;
+				let file_id = self.store_file(file_id, &result.file).await?;
 
 				for sequence in 0..result.file.blocks.len() {
 					let block_id = &result.file.blocks[sequence];
-					if self.needs_block(block_id) {
+					if self.needs_block(block_id).await {
 						if !self.collect_block(connection, file_id, block_id).await? {
 							return Ok(false);
 						}
@@ -218,7 +387,7 @@ impl ActorNode {
 			.exchange_find_object_on_connection(connection, hash)
 			.await
 		{
-			self.store_object(hash, &result.object, false)?;
+			self.store_object(hash, &result.object, false).await?;
 			let completed = self
 				.complete_object(connection, result.object.clone())
 				.await?;
@@ -237,21 +406,21 @@ impl ActorNode {
 			match object.payload {
 				ObjectPayload::Profile(payload) => {
 					if let Some(file_id) = payload.description.as_ref() {
-						if self.needs_file(file_id) {
+						if self.needs_file(file_id).await {
 							if !self.collect_file(connection, file_id).await? {
 								return Ok(false);
 							}
 						}
 					}
 					if let Some(hash) = payload.avatar.as_ref() {
-						if self.needs_file(&hash) {
+						if self.needs_file(&hash).await {
 							if !self.collect_file(connection, &hash).await? {
 								return Ok(false);
 							}
 						}
 					}
 					if let Some(hash) = payload.wallpaper.as_ref() {
-						if self.needs_file(&hash) {
+						if self.needs_file(&hash).await {
 							if !self.collect_file(connection, &hash).await? {
 								return Ok(false);
 							}
@@ -272,7 +441,7 @@ impl ActorNode {
 					match &payload.data {
 						PostObjectCryptedData::Plain(plain) =>
 							for hash in &plain.files {
-								if self.needs_file(&hash) {
+								if self.needs_file(&hash).await {
 									if !self.collect_file(connection, &hash).await? {
 										return Ok(false);
 									}
@@ -292,6 +461,20 @@ impl ActorNode {
 						};
 					}
 				}
+				ObjectPayload::Edit(payload) =>
+					for hash in payload.files.iter() {
+						if self.needs_file(&hash).await {
+							if !self.collect_file(connection, &hash).await? {
+								return Ok(false);
+							}
+						}
+					},
+				ObjectPayload::KeyRotation(_)
+				| ObjectPayload::Delete(_)
+				| ObjectPayload::Reaction(_)
+				| ObjectPayload::Poll(_)
+				| ObjectPayload::Vote(_)
+				| ObjectPayload::FollowerList(_) => {}
 			}
 			Ok(true)
 		}
@@ -312,7 +495,6 @@ impl ActorNode {
 			.base
 			.handle_connection_issue(result, &connection.their_node_info())
 			.await?;
-		// FIXME: Verify object here
 		Some(response)
 	}
 
@@ -370,6 +552,32 @@ impl ActorNode {
 		response.object
 	}
 
+	/// Pushes an object on a connection, and returns whether the other side
+	/// stored it.
+	async fn exchange_notify_object_on_connection(
+		&self, connection: &mut Connection, id: &IdType, object: &BlogchainObject, hops_left: u8,
+	) -> Option<bool> {
+		let request = NotifyObjectRequest {
+			id: id.clone(),
+			object: object.clone(),
+			hops_left,
+		};
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_NOTIFY_OBJECT_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<_> = binserde::deserialize_sstp(&raw_response);
+		let response: NotifyObjectResponse = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		Some(response.stored)
+	}
+
 	/// Publishes an object on a connection.
 	async fn exchange_publish_object_on_connection(
 		&self, connection: &mut Connection, id: &IdType,
@@ -421,6 +629,8 @@ impl ActorNode {
 		Some(*result)
 	}
 
+	/// Finds a value on the network. The value has already been verified by the
+	/// handler of its value type by the time it is parsed here.
 	async fn find_value<V>(
 		&self, value_type: BlogchainValueType, id: &IdType, hop_limit: usize,
 		only_narrow_down: bool,
@@ -462,6 +672,7 @@ impl ActorNode {
 				hop_limit,
 				only_narrow_down,
 				true,
+				1,
 				parse_value::<V>,
 			)
 			.await;
@@ -471,12 +682,20 @@ impl ActorNode {
 		})
 	}
 
-	fn has_object_by_sequence(&self, sequence: u64) -> bool {
-		tokio::task::block_in_place(|| {
-			let c = self.db().connect_old().expect("unable to open database");
-			c.has_object_sequence(self.actor_address(), sequence)
-				.expect("unable to read object from database")
-		})
+	async fn has_object_by_sequence(&self, sequence: u64) -> bool {
+		let actor_address = self.actor_address().clone();
+		let result = self
+			.db()
+			.perform(move |c| Ok(c.has_object_sequence(&actor_address, sequence)?))
+			.await;
+		match result {
+			Ok(has) => has,
+			Err(e) => {
+				self.db().observe_error(&e);
+				error!("Unable to check for object {}: {:?}", sequence, e);
+				false
+			}
+		}
 	}
 
 	/// Does all the work that is expected upon joining the network.
@@ -497,11 +716,8 @@ impl ActorNode {
 	}
 
 	/// Returns a list of block hashes that we'd like to have.
-	fn investigate_missing_blocks(&self) -> db::Result<Vec<(i64, IdType)>> {
-		tokio::task::block_in_place(|| {
-			let c = self.db().connect_old()?;
-			c.fetch_missing_file_blocks()
-		})
+	async fn investigate_missing_blocks(&self) -> db::Result<Vec<(i64, IdType)>> {
+		self.db().perform(|c| c.fetch_missing_file_blocks()).await
 	}
 
 	async fn object_missing_files(&self, object: &ObjectPayload) -> db::Result<Vec<IdType>> {
@@ -534,7 +750,22 @@ impl ActorNode {
 					results
 				}
 			},
-			ObjectPayload::Share(_) => Vec::new(),
+			ObjectPayload::Edit(payload) => {
+				let mut results = Vec::with_capacity(payload.files.len());
+				for file_hash in payload.files.iter() {
+					if !self.db().has_file(file_hash).await? {
+						results.push(file_hash.clone());
+					}
+				}
+				results
+			}
+			ObjectPayload::Share(_)
+			| ObjectPayload::KeyRotation(_)
+			| ObjectPayload::Delete(_)
+			| ObjectPayload::Reaction(_)
+			| ObjectPayload::Poll(_)
+			| ObjectPayload::Vote(_)
+			| ObjectPayload::FollowerList(_) => Vec::new(),
 		};
 		Ok(results)
 	}
@@ -560,6 +791,9 @@ impl ActorNode {
 				// TODO: If the amount of files found exceeds the file_limit, only insert the
 				// hashes up to the limit and don't let it go over only to shrink the array
 				// later.
+				if self.db().is_tombstone(object.id).await? {
+					continue;
+				}
 				if results.len() < file_limit as usize {
 					let payload = self
 						.db()
@@ -577,11 +811,11 @@ impl ActorNode {
 	}
 
 	#[allow(dead_code)]
-	fn investigate_missing_object_files(
+	async fn investigate_missing_object_files(
 		&self, object: &BlogchainObject,
 	) -> db::Result<Vec<IdType>> {
-		tokio::task::block_in_place(|| {
-			let c = self.db().connect_old()?;
+		let object = object.clone();
+		self.db().perform(move |c| {
 			let mut results = Vec::new();
 			match &object.payload {
 				ObjectPayload::Profile(payload) => {
@@ -604,10 +838,23 @@ impl ActorNode {
 							}
 						},
 				},
-				ObjectPayload::Share(_) => {}
+				ObjectPayload::Edit(payload) =>
+					for file_hash in payload.files.iter() {
+						if !c.has_file(&file_hash)? {
+							results.push(file_hash.clone());
+						}
+					},
+				ObjectPayload::Share(_)
+				| ObjectPayload::KeyRotation(_)
+				| ObjectPayload::Delete(_)
+				| ObjectPayload::Reaction(_)
+				| ObjectPayload::Poll(_)
+				| ObjectPayload::Vote(_)
+				| ObjectPayload::FollowerList(_) => {}
 			}
 			Ok(results)
 		})
+		.await
 	}
 
 	pub async fn join_network_starting_with_connection(
@@ -648,44 +895,59 @@ impl ActorNode {
 	}
 
 	#[allow(dead_code)]
-	fn load_public_key(&self) -> ActorPublicKeyV1 {
-		let actor_info = tokio::task::block_in_place(|| {
-			let c = self
-				.db()
-				.connect_old()
-				.expect("unable to connect to database");
-			c.fetch_identity(self.actor_address())
-				.expect("unable to load identity for actor node")
-				.expect("no identity for actor node")
-		});
+	async fn load_public_key(&self) -> ActorPublicKeyV1 {
+		let actor_address = self.actor_address().clone();
+		let actor_info = self
+			.db()
+			.perform(move |c| c.fetch_identity(&actor_address))
+			.await
+			.expect("unable to load identity for actor node")
+			.expect("no identity for actor node");
 		match actor_info {
 			ActorInfo::V1(ai) => ai.public_key,
 		}
 	}
 
-	pub fn new(
+	pub async fn new(
 		stop_flag: Arc<AtomicBool>, overlay_node: Arc<OverlayNode>, node_id: NodeAddress,
 		socket: Arc<sstp::Server>, actor_address: ActorAddress, actor_id: i64,
 		actor_info: ActorInfo, db: Database, bucket_size: usize, leak_first_request: bool,
 		is_lurker: bool,
-	) -> Self {
+	) -> db::Result<Self> {
+		let value_cache_capacity = overlay_node.base.value_cache_capacity;
+		let replacement_cache_size = overlay_node.base.replacement_cache_size.min(bucket_size);
+		let metrics = overlay_node.base.metrics.clone();
+		let actor_address2 = actor_address.clone();
+		let (key_rotations, head) = db
+			.perform(move |c| {
+				let key_rotations = c.fetch_key_rotations(&actor_address2)?;
+				let head = c.fetch_head(&actor_address2)?;
+				Ok((key_rotations, head))
+			})
+			.await?;
+		let key_chain = KeyChain::new(actor_info.public_key.clone(), key_rotations);
+		let blocked_nodes = db.load_blocked_nodes(actor_id).await?;
 		let interface = ActorInterface {
 			overlay_node,
 			db: db.clone(),
 			actor_id,
 			actor_info,
-			// TODO: Load head sequence from parameter in new, and create an async method `load`
-			// that does the same as `new` except it also loads the head_sequence from DB
-			head_sequence: StdMutex::new(
-				db.perform(|c| c.fetch_head(&actor_address))
-					.unwrap()
-					.map(|o| o.1.sequence),
-			),
+			head_sequence: StdMutex::new(head.map(|o| o.1.sequence)),
+			key_chain: StdMutex::new(key_chain),
 			actor_address,
 			is_lurker,
+			value_types: ValueTypeRegistry::new()
+				.with(BlogchainValueType::Block as _, BlockValueHandler)
+				.with(BlogchainValueType::File as _, FileValueHandler)
+				.with(BlogchainValueType::Object as _, ObjectValueHandler)
+				.with(BlogchainValueType::NextObject as _, NextObjectValueHandler),
 		};
-		Self {
+		Ok(Self {
+			blocked_nodes: StdMutex::new(blocked_nodes),
 			is_synchonizing: Arc::new(AtomicBool::new(false)),
+			followers: StdMutex::new(LimitedMap::new(FOLLOWERS_LIMIT)),
+			last_synchronized: StdMutex::new(None),
+			reach: StdMutex::new(ReachEstimator::new()),
 			base: Arc::new(Node::new(
 				stop_flag,
 				db,
@@ -693,10 +955,13 @@ impl ActorNode {
 				socket,
 				interface,
 				bucket_size,
+				replacement_cache_size,
+				value_cache_capacity,
 				leak_first_request,
+				metrics,
 			)),
 			downloading_objects: Mutex::new(Vec::new()),
-		}
+		})
 	}
 
 	async fn process_get_profile_request(&self, buffer: &[u8]) -> MessageProcessorResult {
@@ -708,10 +973,11 @@ impl ActorNode {
 			}
 		};
 
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db().connect_old()?;
-			c.fetch_profile_object(&self.base.interface.actor_address)
-		});
+		let actor_address = self.actor_address().clone();
+		let result = self
+			.db()
+			.perform(move |c| c.fetch_profile_object(&actor_address))
+			.await;
 		let object = match result {
 			Ok(p) => p,
 			Err(e) => {
@@ -739,16 +1005,89 @@ impl ActorNode {
 		Ok(())
 	}
 
+	async fn process_notify_object_request(
+		self: &Arc<Self>, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: NotifyObjectRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed notify object request from {}: {}", addr, e);
+				return None;
+			}
+		};
+		if !self.base.interface.verify_object(&request.id, &request.object) {
+			warn!("Invalid object pushed by {}: verification failed.", addr);
+			return None;
+		}
+
+		let stored = if self.needs_object(self.actor_address(), &request.id).await {
+			match self.store_object(&request.id, &request.object, false).await {
+				Ok(stored) => stored,
+				Err(e) => {
+					error!("Unable to store pushed object: {:?}", e);
+					false
+				}
+			}
+		} else {
+			false
+		};
+		let response = NotifyObjectResponse { stored };
+		let response_buffer = self
+			.base
+			.simple_response(ACTOR_MESSAGE_TYPE_NOTIFY_OBJECT_RESPONSE, &response);
+		if !stored {
+			return Some((response_buffer, None));
+		}
+
+		// Download the files of the object from the node that pushed it
+		Some((
+			response_buffer,
+			Some(Box::new(NotifyObjectToDo {
+				node: self.clone(),
+				id: request.id,
+				object: request.object,
+				hops_left: request.hops_left,
+			})),
+		))
+	}
+
 	pub(super) async fn process_request(
 		self: &Arc<Self>, message_type: u8, buffer: &[u8], addr: &SocketAddr,
-		_node_info: &NodeContactInfo,
+		node_info: &NodeContactInfo,
 	) -> MessageProcessorResult {
+		if message_type == ACTOR_MESSAGE_TYPE_HEAD_REQUEST
+			|| message_type == ACTOR_MESSAGE_TYPE_SYNC_LOG_REQUEST
+		{
+			self.record_follower(node_info);
+		}
 		match message_type {
-			ACTOR_MESSAGE_TYPE_HEAD_REQUEST => self.process_head_request(buffer).await,
+			ACTOR_MESSAGE_TYPE_HEAD_REQUEST =>
+				self.process_head_request(buffer, node_info).await,
 			ACTOR_MESSAGE_TYPE_GET_PROFILE_REQUEST =>
 				self.process_get_profile_request(buffer).await,
+			ACTOR_MESSAGE_TYPE_NOTIFY_OBJECT_REQUEST =>
+				self.process_notify_object_request(buffer, addr).await,
 			ACTOR_MESSAGE_TYPE_PUBLISH_OBJECT_REQUEST =>
 				self.process_publish_object_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_SYNC_LOG_REQUEST =>
+				self.process_sync_log_request(buffer, addr, node_info).await,
+			ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST =>
+				self.process_block_map_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_STORE_PARITY_REQUEST =>
+				self.process_store_parity_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST =>
+				self.process_find_parity_request(buffer, addr, node_info)
+					.await,
+			ACTOR_MESSAGE_TYPE_REACH_SKETCH_REQUEST =>
+				self.process_reach_sketch_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_MESSAGE_KEY_REQUEST =>
+				self.process_message_key_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_REQUEST =>
+				self.process_direct_message_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_REQUEST =>
+				self.process_follow_request_request(buffer, addr).await,
+			ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_REQUEST =>
+				self.process_key_rotations_request(buffer, addr).await,
 			other_id => {
 				error!(
 					"Unknown actor message type ID received from {}: {}",
@@ -759,28 +1098,26 @@ impl ActorNode {
 		}
 	}
 
-	async fn process_head_request(&self, buffer: &[u8]) -> MessageProcessorResult {
+	async fn process_head_request(
+		&self, buffer: &[u8], node_info: &NodeContactInfo,
+	) -> MessageProcessorResult {
 		if buffer.len() > 0 {
 			warn!("Malformed head request");
 			return None;
 		}
 
-		let head_result = tokio::task::block_in_place(|| {
-			let c = match self.db().connect_old() {
-				Ok(c) => c,
-				Err(e) => {
-					error!("Unable to connect to database to check block: {}", e);
-					return None;
-				}
-			};
-			match c.fetch_head(&self.base.interface.actor_address) {
-				Ok(h) => h,
-				Err(e) => {
-					error!("Unable to fetch head: {}", e);
-					return None;
-				}
+		let actor_address = self.actor_address().clone();
+		let head_result = match self
+			.db()
+			.perform(move |c| c.fetch_head(&actor_address))
+			.await
+		{
+			Ok(h) => h,
+			Err(e) => {
+				error!("Unable to fetch head: {}", e);
+				return None;
 			}
-		});
+		};
 
 		let response = match head_result {
 			None => {
@@ -792,6 +1129,20 @@ impl ActorNode {
 			}
 			Some((hash, object, ..)) => HeadResponse { hash, object },
 		};
+		match self
+			.may_serve_value(BlogchainValueType::Object as u8, &response.hash, &node_info.address)
+			.await
+		{
+			Ok(true) => {}
+			Ok(false) => {
+				debug!("Not handing out head to unapproved node {}", &node_info.address);
+				return None;
+			}
+			Err(e) => {
+				error!("Unable to check whether head may be served: {}", e);
+				return None;
+			}
+		}
 		self.base
 			.simple_result(ACTOR_MESSAGE_TYPE_HEAD_RESPONSE, &response)
 	}
@@ -814,7 +1165,7 @@ impl ActorNode {
 			let mut needed = !downloading_objects.contains(&request.id);
 			let actor_id = &self.actor_address();
 			if needed {
-				needed = self.needs_object(actor_id, &request.id);
+				needed = self.needs_object(actor_id, &request.id).await;
 				if needed {
 					downloading_objects.push(request.id.clone());
 				}
@@ -841,64 +1192,76 @@ impl ActorNode {
 		))
 	}
 
-	fn needs_object(&self, actor_address: &ActorAddress, id: &IdType) -> bool {
-		tokio::task::block_in_place(|| {
-			let c = match self.db().connect_old() {
-				Ok(c) => c,
-				Err(e) => {
-					error!("Unable to connect to database to check object: {}", e);
-					return false;
-				}
-			};
-			let has_object = match c.has_object(actor_address, id) {
-				Ok(b) => b,
-				Err(e) => {
-					error!("Unable to check object: {}", e);
-					return false;
-				}
-			};
-			!has_object
-		})
+	async fn needs_object(&self, actor_address: &ActorAddress, id: &IdType) -> bool {
+		let actor_address = actor_address.clone();
+		let id = id.clone();
+		match self
+			.db()
+			.perform(move |c| Ok(c.has_object(&actor_address, &id)?))
+			.await
+		{
+			Ok(has) => !has,
+			Err(e) => {
+				error!("Unable to check object: {}", e);
+				false
+			}
+		}
 	}
 
-	fn needs_file(&self, id: &IdType) -> bool {
-		tokio::task::block_in_place(|| {
-			let c = match self.db().connect_old() {
-				Ok(c) => c,
-				Err(e) => {
-					error!("Unable to connect to database to check file: {}", e);
-					return false;
-				}
-			};
-			let has_object = match c.has_file(id) {
-				Ok(b) => b,
-				Err(e) => {
-					error!("Unable to check file: {}", e);
-					return false;
-				}
-			};
-			!has_object
-		})
+	async fn needs_file(&self, id: &IdType) -> bool {
+		let id2 = id.clone();
+		match self.db().perform(move |c| Ok(c.has_file(&id2)?)).await {
+			Ok(true) => return false,
+			Ok(false) => {}
+			Err(e) => {
+				error!("Unable to check file: {}", e);
+				return false;
+			}
+		}
+		// The files of deleted posts aren't kept anymore
+		match self.db().is_file_dropped(id).await {
+			Ok(dropped) => !dropped,
+			Err(e) => {
+				error!("Unable to check file: {}", e);
+				false
+			}
+		}
 	}
 
-	fn needs_block(&self, id: &IdType) -> bool {
-		tokio::task::block_in_place(|| {
-			let c = match self.db().connect_old() {
-				Ok(c) => c,
-				Err(e) => {
-					error!("Unable to connect to database to check block: {}", e);
-					return false;
-				}
-			};
-			let has_object = match c.has_block(id) {
-				Ok(b) => b,
-				Err(e) => {
-					error!("Unable to check block: {}", e);
-					return false;
-				}
-			};
-			!has_object
-		})
+	/// Whether the block may be served to the given peer, according to the
+	/// seeding policy of this node.
+	pub(super) async fn may_seed_block(
+		&self, id: &IdType, peer: &NodeAddress,
+	) -> db::Result<bool> {
+		let seeder = &self.base.interface.overlay_node.seeder;
+		let actor_id = self.base.interface.actor_id;
+		let allowed = match seeder.policy {
+			SeedingPolicy::All => true,
+			SeedingPolicy::Following =>
+				self.db().has_identity(actor_id).await?
+					|| self.db().is_followed(actor_id).await?,
+			SeedingPolicy::Own => self.db().has_identity(actor_id).await?,
+		};
+		if !allowed || !seeder.take_slot(peer) {
+			return Ok(false);
+		}
+
+		// Blocks that aren't part of any file that we know of aren't limited
+		match self.db().find_block_file(id).await? {
+			Some((file_hash, size)) => Ok(seeder.take_bandwidth(&file_hash, size as _)),
+			None => Ok(true),
+		}
+	}
+
+	async fn needs_block(&self, id: &IdType) -> bool {
+		let id = id.clone();
+		match self.db().perform(move |c| Ok(c.has_block(&id)?)).await {
+			Ok(has) => !has,
+			Err(e) => {
+				error!("Unable to check block: {}", e);
+				false
+			}
+		}
 	}
 
 	fn verify_block(&self, id: &IdType, data: &[u8]) -> bool {
@@ -950,55 +1313,116 @@ impl ActorNode {
 		true
 	}*/
 
-	fn verify_object(
-		&self, id: &IdType, object: &BlogchainObject, public_key: &ActorPublicKeyV1,
-	) -> bool {
-		if object.created as u128
-			> SystemTime::now()
-				.duration_since(UNIX_EPOCH)
-				.unwrap()
-				.as_millis()
-		{
-			warn!(
-				"Object {} is invalid: creation timestamp is from the future: {}",
-				&id, object.created
-			);
-			return false;
+	fn record_follower(&self, node_info: &NodeContactInfo) {
+		let mut followers = self.followers.lock().unwrap();
+		followers.remove(&node_info.address);
+		followers.insert(
+			node_info.address.clone(),
+			(node_info.clone(), Instant::now()),
+		);
+	}
+
+	async fn republish_object(
+		self: &Arc<Self>, overlay_node: &Arc<OverlayNode>, id: &IdType, object: &BlogchainObject,
+		source_node_id: &IdType,
+	) {
+		if let Some(bucket_offset) = self.base.differs_at_bit(source_node_id) {
+			self.publish_object(overlay_node, id, object, &[], bucket_offset)
+				.await;
+		} else {
+			debug_assert!(false, "Republishing an object received from ourselves.");
 		}
+	}
 
-		let sign_data = ObjectSignData {
-			previous_hash: object.previous_hash.clone(),
-			sequence: object.sequence,
-			created: object.created,
-			payload: &object.payload,
+	/// Pushes a new object to the nodes that are following the actor at the
+	/// moment, so that they don't have to wait until they synchronize again.
+	/// The followers that can't be reached get the object delivered later.
+	/// Returns the addresses of the followers that were reached.
+	pub async fn notify_followers(
+		self: &Arc<Self>, id: &IdType, object: &BlogchainObject,
+	) -> Vec<NodeAddress> {
+		let followers: Vec<NodeContactInfo> = self
+			.followers
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(_, (_, last_seen))| last_seen.elapsed() < FOLLOWER_TTL)
+			.map(|(_, (contact, _))| contact.clone())
+			.collect();
+		let followers = match self.filter_approved_followers(followers).await {
+			Ok(f) => f,
+			Err(e) => {
+				self.db().observe_error(&e);
+				error!("Unable to check the approved followers: {}", e);
+				return Vec::new();
+			}
 		};
-		let raw_sign_data = binserde::serialize(&sign_data).unwrap();
-		if !public_key.verify(&raw_sign_data, &object.signature) {
-			warn!("Object {} is invalid: signature is incorrect.", &id);
-			return false;
+
+		let futs = followers.into_iter().map(|follower| async move {
+			let reached = self.notify_follower(&follower, id, object).await;
+			(follower, reached)
+		});
+		let mut notified = Vec::new();
+		for (follower, reached) in join_all(futs).await {
+			if reached {
+				notified.push(follower.address);
+			} else if let Err(e) = self.queue_delivery(id, &follower).await {
+				self.db().observe_error(&e);
+				error!("Unable to queue delivery of object {}: {}", id, e);
+			}
 		}
+		debug!(
+			"Pushed object {} to {} follower(s) of actor {}.",
+			id,
+			notified.len(),
+			self.actor_address()
+		);
+		notified
+	}
 
-		let signature_hash = object.signature.hash();
-		if &signature_hash != id {
-			warn!(
-				"Object {} is invalid: id is not a hash of the signature: {}",
-				&id, signature_hash,
+	/// Pushes an object to a follower. Returns whether the follower was
+	/// reached.
+	async fn notify_follower(
+		&self, follower: &NodeContactInfo, id: &IdType, object: &BlogchainObject,
+	) -> bool {
+		let mut connection = match self.base.select_connection(follower, None).await {
+			Some((c, _)) => c,
+			None => return false,
+		};
+		let stored = match self
+			.exchange_notify_object_on_connection(&mut connection, id, object, GOSSIP_HOPS)
+			.await
+		{
+			Some(s) => s,
+			None => return false,
+		};
+		if stored {
+			// Keep the connection open in the background, so that the follower
+			// can download the files of the object from us
+			let packet_server = self.base.packet_server.clone();
+			self.base.packet_server.tasks.spawn(
+				format!("connection with follower {}", &follower.address),
+				async move {
+					packet_server.handle_connection(connection, None).await;
+				},
 			);
-			return false;
 		}
 		true
 	}
 
-	async fn republish_object(
+	/// Publishes an object that we've just created. The followers are
+	/// notified first, and then it is published to the rest of the network.
+	pub async fn publish_new_object(
 		self: &Arc<Self>, overlay_node: &Arc<OverlayNode>, id: &IdType, object: &BlogchainObject,
-		source_node_id: &IdType,
 	) {
-		if let Some(bucket_offset) = self.base.differs_at_bit(source_node_id) {
-			self.publish_object(overlay_node, id, object, &[], bucket_offset)
-				.await;
-		} else {
-			debug_assert!(false, "Republishing an object received from ourselves.");
+		self.base.interface.remember_key_rotation(id, object);
+		// Our own objects can be searched for right away
+		if let Err(e) = self.db().update_search_index().await {
+			error!("Unable to update the search index: {}", e);
 		}
+		let notified = self.notify_followers(id, object).await;
+		self.publish_object(overlay_node, id, object, &notified, 0)
+			.await;
 	}
 
 	pub async fn publish_object(
@@ -1019,6 +1443,19 @@ impl ActorNode {
 			let id2 = id.clone();
 			let object2 = object.clone();
 			futs.push(async move {
+				// Objects of followers-only actors are only pushed to their approved followers
+				match this
+					.may_serve_value(BlogchainValueType::Object as u8, &id2, &finger.address)
+					.await
+				{
+					Ok(true) => {}
+					Ok(false) => return,
+					Err(e) => {
+						this.db().observe_error(&e);
+						error!("Unable to check whether object {} may be published: {}", id2, e);
+						return;
+					}
+				}
 				if let Some((connection, _)) = this.base.select_connection(&finger, None).await {
 					this.publish_object_on_connection(overlay_node2, connection, &id2, &object2)
 						.await;
@@ -1069,47 +1506,84 @@ impl ActorNode {
 				.get_actor_node_or_lurker(&actor_address)
 				.await
 			{
-				// Find the object on the network
-				if let Some(result) = actor_node.find_object(&object_hash).await {
-					let result = async {
-						if actor_node.store_object(&object_hash, &result.object, false)? {
-							// If found, collect all files & blocks on the network as well.
-							actor_node
-								.synchronize_files_and_blocks_of_object(&result.object.payload)
-								.await?;
-						}
-						db::Result::Ok(())
-					};
-					if let Err(e) = result.await {
-						error!(
-							"Database error while synchronizing object, files & blocks with actor \
-							 network {}: {:?}",
-							actor_address, e
-						);
-					}
-				} else {
-					warn!(
+				match actor_node.collect_object_from_network(&object_hash).await {
+					Ok(true) => {}
+					Ok(false) => warn!(
 						"Object {} not found on actor network {}.",
 						object_hash, actor_address
-					);
+					),
+					Err(e) => error!(
+						"Database error while synchronizing object, files & blocks with actor \
+						 network {}: {:?}",
+						actor_address, e
+					),
 				}
 			}
 		});
 	}
 
-	fn store_block(&self, file_id: i64, id: &IdType, data: &[u8]) -> db::Result<()> {
-		self.db().perform(|mut c| c.store_block(file_id, id, data))
+	/// Finds the object on the network, and stores it together with its files
+	/// and blocks. Returns false if the object couldn't be found.
+	pub async fn collect_object_from_network(
+		self: &Arc<Self>, object_hash: &IdType,
+	) -> db::Result<bool> {
+		let result = match self.find_object(object_hash).await {
+			Some(r) => r,
+			None => return Ok(false),
+		};
+		if self.store_object(object_hash, &result.object, false).await? {
+			// If found, collect all files & blocks on the network as well.
+			self.synchronize_files_and_blocks_of_object(&result.object.payload)
+				.await?;
+		}
+		Ok(true)
 	}
 
-	fn store_file(&self, id: &IdType, file: &File) -> db::Result<i64> {
-		self.db().perform(|mut c| c.store_file(id, file))
+	async fn store_block(&self, file_id: i64, id: &IdType, data: &[u8]) -> db::Result<()> {
+		let id = id.clone();
+		let data = data.to_vec();
+		self.db()
+			.perform(move |mut c| c.store_block(file_id, &id, &data))
+			.await
+	}
+
+	async fn store_file(&self, id: &IdType, file: &File) -> db::Result<i64> {
+		let id = id.clone();
+		let file = file.clone();
+		self.db()
+			.perform(move |mut c| c.store_file(&id, &file))
+			.await
 	}
 
-	fn store_object(
+	async fn store_object(
 		&self, id: &IdType, object: &BlogchainObject, verified_from_start: bool,
 	) -> db::Result<bool> {
-		self.db()
-			.perform(|mut c| c.store_object(self.actor_address(), id, object, verified_from_start))
+		let actor_address = self.actor_address().clone();
+		let id2 = id.clone();
+		let object2 = object.clone();
+		let stored = self
+			.db()
+			.perform(move |mut c| {
+				c.store_object(&actor_address, &id2, &object2, verified_from_start)
+			})
+			.await?;
+		self.base.interface.remember_key_rotation(id, object);
+		if stored {
+			// A deletion can arrive before or after the post it deletes
+			match &object.payload {
+				ObjectPayload::Post(_) | ObjectPayload::Edit(_) | ObjectPayload::Delete(_) => {
+					self.db()
+						.apply_deletions(self.base.interface.actor_id)
+						.await?;
+				}
+				_ => {}
+			}
+			self.base
+				.overlay_node()
+				.events
+				.publish(Event::new_object(self.actor_address(), id));
+		}
+		Ok(stored)
 	}
 
 	/// Does everything needed to make sure a node is up to date with the rest
@@ -1160,25 +1634,102 @@ impl ActorNode {
 				None
 			};
 
+		let sync_depth = self.load_sync_depth().await?;
+
 		// Try to sychronize objects from the start of the blogchain so that we may be
 		// able to set the verify_from_start flag on objects.
-		self.synchronize_objects_from_start().await?;
+		if !sync_depth.is_limited() {
+			self.synchronize_objects_from_start().await?;
+		}
 		if let Some(head) = &head_opt {
-			self.synchronize_objects_from_head(head, ACTOR_LIMIT_RECENT_OBJECTS)
+			let (object_limit, min_created) = self.synchronization_window(&sync_depth).await?;
+			let synchronized = self
+				.synchronize_objects_from_head(head, object_limit, min_created)
 				.await?;
+			self.publish_sync_progress(SyncStage::Objects);
 			// Synchronize any file and block that we need but don't have yet
-			self.synchronize_files(
-				head,
-				ACTOR_LIMIT_RECENT_OBJECTS,
-				ACTOR_LIMIT_RECENT_OBJECTS_FILES,
-			)
-			.await?;
-			self.synchronize_blocks().await?;
+			let file_object_limit = if sync_depth.is_limited() {
+				synchronized
+			} else {
+				ACTOR_LIMIT_RECENT_OBJECTS
+			};
+			self.synchronize_files(head, file_object_limit, ACTOR_LIMIT_RECENT_OBJECTS_FILES)
+				.await?;
+			self.synchronize_blocks(None).await?;
+			self.publish_sync_progress(SyncStage::Files);
+			if let Err(e) = self.base.overlay_node().notify_new_mentions().await {
+				error!("Unable to look for new mentions: {}", e);
+			}
+			if let Err(e) = self.base.overlay_node().notify_new_reactions().await {
+				error!("Unable to look for new reactions: {}", e);
+			}
+
+			// Archive nodes go on to collect everything else as well
+			let overlay_node = self.base.overlay_node();
+			if let Some(archiver) = &overlay_node.archiver {
+				self.synchronize_archive(archiver, head).await?;
+			}
 		}
 
+		// Our own log may have forked if we publish from more than one device
+		self.resolve_forks().await?;
+
+		self.publish_sync_progress(SyncStage::Done);
 		Ok(())
 	}
 
+	/// If we follow the actor with a limited sync depth, only its recent history
+	/// is synchronized.
+	async fn load_sync_depth(&self) -> db::Result<SyncDepth> {
+		Ok(self
+			.db()
+			.load_sync_depth(self.actor_address())
+			.await?
+			.unwrap_or_default())
+	}
+
+	/// Returns the number of objects before the head that are synchronized, and
+	/// the timestamp before which objects are left out.
+	async fn synchronization_window(&self, sync_depth: &SyncDepth) -> db::Result<(u64, u64)> {
+		// The head itself counts as one of the latest objects
+		let object_limit = sync_depth
+			.object_limit
+			.map(|l| l.saturating_sub(1))
+			.unwrap_or(ACTOR_LIMIT_RECENT_OBJECTS);
+		// Don't download what would be pruned again right away
+		let min_created = self
+			.base
+			.overlay_node()
+			.retention_min_created(self.actor_address(), self.base.interface.actor_id)
+			.await?
+			.max(sync_depth.min_created());
+		Ok((object_limit, min_created))
+	}
+
+	fn publish_sync_progress(&self, stage: SyncStage) {
+		self.base
+			.overlay_node()
+			.events
+			.publish(Event::sync_progress(self.actor_address(), stage));
+	}
+
+	/// Collects the objects, files and blocks that a normal node wouldn't keep,
+	/// for as long as the archive quota allows it.
+	async fn synchronize_archive(
+		&self, archiver: &Archiver, head: &BlogchainObject,
+	) -> db::Result<()> {
+		archiver.refresh_usage(self.db()).await?;
+		if archiver.is_full() {
+			return Ok(());
+		}
+
+		self.synchronize_objects_from_head(head, head.sequence, 0)
+			.await?;
+		self.synchronize_files(head, head.sequence, u64::MAX)
+			.await?;
+		self.synchronize_blocks(Some(archiver)).await
+	}
+
 	pub async fn synchronize_files_and_blocks_of_object(
 		self: &Arc<Self>, payload: &ObjectPayload,
 	) -> db::Result<()> {
@@ -1194,21 +1745,24 @@ impl ActorNode {
 				result
 			} else {
 				if let Some(result) = self.find_file(&file_hash).await {
-					let file_id = self.store_file(&file_hash, &result.file)?;
+					let file_id = self.store_file(&file_hash, &result.file).await?;
 					(file_id, result.file)
 				} else {
 					continue;
 				}
 			};
 
-			// Find missing blocks
-			for block_hash in file.blocks {
+			// Download the missing blocks, which are stored as they come in
+			let mut missing_blocks = Vec::new();
+			for (i, block_hash) in file.blocks.into_iter().enumerate() {
 				if !self.db().has_block(&block_hash).await? {
-					if let Some(result) = self.find_block(&block_hash).await {
-						self.store_block(file_id, &block_hash, &result.data)?;
-					}
+					missing_blocks.push((i, block_hash));
 				}
 			}
+			if missing_blocks.len() > 0 {
+				let mut downloads = self.download_blocks(file_id, &file_hash, missing_blocks, false);
+				while downloads.recv().await.is_some() {}
+			}
 		}
 		Ok(())
 	}
@@ -1219,13 +1773,30 @@ impl ActorNode {
 	pub async fn synchronize_missed_objects_on_connection(
 		self: &Arc<Self>, connection: &mut Connection, up_to_object: BlogchainObject,
 	) -> db::Result<()> {
+		// No further back than the sync depth allows
+		let sync_depth = self.load_sync_depth().await?;
+		let (object_limit, min_created) = self.synchronization_window(&sync_depth).await?;
+
+		// Try to fill the gap in bulk first, and walk back object by object only if
+		// that didn't work out
+		let min_sequence = up_to_object.sequence.saturating_sub(object_limit);
+		if self
+			.synchronize_log_on_connection(connection, &up_to_object, min_sequence, min_created)
+			.await?
+		{
+			return Ok(());
+		}
+
 		let up_to_sequence = up_to_object.sequence;
 		let mut i = up_to_object.sequence as i128;
 		let mut last_object = up_to_object;
 
-		while i > 0 && (up_to_sequence - i as u64) < ACTOR_LIMIT_RECENT_OBJECTS {
+		while i > 0
+			&& (up_to_sequence - i as u64) < object_limit
+			&& last_object.created >= min_created
+		{
 			i -= 1;
-			if !self.has_object_by_sequence(i as u64) {
+			if !self.has_object_by_sequence(i as u64).await {
 				match self
 					.collect_object(connection, &last_object.previous_hash)
 					.await?
@@ -1243,16 +1814,50 @@ impl ActorNode {
 		Ok(())
 	}
 
-	pub(super) async fn synchronize_blocks(&self) -> db::Result<()> {
-		let missing_blocks = self.investigate_missing_blocks()?;
-		for (file_id, hash) in missing_blocks {
+	/// Collects the blocks that are missing from the files that we have. If an
+	/// archiver is given, no more blocks are collected once its quota is
+	/// reached.
+	pub(super) async fn synchronize_blocks(&self, archiver: Option<&Archiver>) -> db::Result<()> {
+		let missing_blocks = self.investigate_missing_blocks().await?;
+		// Store the found blocks in batches, so that not every block needs its own
+		// transaction
+		let mut batch = Vec::new();
+		let mut batch_size = 0;
+		for (_, hash) in missing_blocks {
+			if archiver.map(|a| a.is_full()).unwrap_or(false) {
+				break;
+			}
 			if let Some(result) = self.find_block(&hash).await {
-				tokio::task::block_in_place(|| {
-					let mut c = self.db().connect_old()?;
-					c.store_block(file_id, &hash, &result.data)
-				})?;
+				batch_size += result.data.len();
+				batch.push((hash, result.data.into()));
+				if batch_size >= BLOCK_INGEST_BATCH_LIMIT {
+					self.store_block_batch(&mut batch, archiver).await?;
+					batch_size = 0;
+				}
 			}
 		}
+		self.store_block_batch(&mut batch, archiver).await
+	}
+
+	/// Stores the blocks of the batch, and only then accounts for them in the
+	/// quota of the archiver.
+	async fn store_block_batch(
+		&self, batch: &mut Vec<(IdType, Vec<u8>)>, archiver: Option<&Archiver>,
+	) -> db::Result<()> {
+		if batch.len() == 0 {
+			return Ok(());
+		}
+		let blocks = mem::take(batch);
+		let size = blocks.iter().map(|(_, d)| d.len()).sum();
+		self.db()
+			.perform_write(size, move |c| {
+				c.store_blocks(blocks.iter().map(|(h, d)| (h, d.as_slice())))?;
+				Ok(())
+			})
+			.await?;
+		if let Some(archiver) = archiver {
+			archiver.record_stored(size);
+		}
 		Ok(())
 	}
 
@@ -1265,7 +1870,11 @@ impl ActorNode {
 		self: &Arc<Self>,
 	) -> db::Result<Option<(IdType, BlogchainObject, Vec<NodeAddress>, bool, bool)>> {
 		let mut up_to_date_nodes = Vec::with_capacity(4);
-		let our_head_info = self.db().perform(|c| c.fetch_head(self.actor_address()))?;
+		let actor_address = self.actor_address().clone();
+		let our_head_info = self
+			.db()
+			.perform(move |c| c.fetch_head(&actor_address))
+			.await?;
 		let our_head_sequence = if let Some((_, o, _)) = &our_head_info {
 			*self.base.interface.head_sequence.lock().unwrap() = Some(o.sequence);
 			o.sequence as i128
@@ -1294,14 +1903,14 @@ impl ActorNode {
 
 							// If the object is the same as our head, nothing will need to happen
 							if object.sequence as i128 == our_head_sequence {
-								// But if the sequence is the same but the hash different, it may
-								// have been overwritten
+								// But if the sequence is the same but the hash different, the log
+								// has forked, and everyone needs to keep the same side of it
 								if let Some((our_head_hash, our_head, _)) = &our_head_info {
 									if our_head_hash != &hash {
-										if object.created < our_head.created {
-											a_node_is_behind = true;
-										} else if object.created > our_head.created {
+										if wins_fork(&hash, &object, our_head_hash, our_head) {
 											is_newer = true;
+										} else {
+											a_node_is_behind = true;
 										}
 									}
 								}
@@ -1346,14 +1955,21 @@ impl ActorNode {
 		self: &Arc<Self>, connection: &mut Connection,
 	) -> db::Result<Option<(IdType, BlogchainObject)>> {
 		if let Some(response) = self.exchange_head_on_connection(connection).await {
-			let stored = self.db().perform(|mut c| {
-				c.store_object(
-					self.actor_address(),
-					&response.hash,
-					&response.object,
-					false,
-				)
-			})?;
+			// A newer head may be signed with a key that has been rotated to in the
+			// part of the log that we don't have yet
+			let head_sequence = *self.base.interface.head_sequence.lock().unwrap();
+			if head_sequence.map(|s| response.object.sequence > s).unwrap_or(true) {
+				self.synchronize_key_rotations_on_connection(connection).await?;
+			}
+			if !self
+				.base
+				.interface
+				.verify_object(&response.hash, &response.object)
+			{
+				warn!("Invalid head object received from {}.", connection.peer_address());
+				return Ok(None);
+			}
+			let stored = self.store_object(&response.hash, &response.object, false).await?;
 
 			if stored {
 				self.process_new_head_on_connection(
@@ -1376,10 +1992,13 @@ impl ActorNode {
 			.await?;
 		for hash in missing_files {
 			if let Some(result) = self.find_file(&hash).await {
-				tokio::task::block_in_place(|| {
-					let mut c = self.db().connect_old()?;
-					c.store_file(&hash, &result.file)
-				})?;
+				let size = result.file.blocks.len() * 32;
+				self.db()
+					.perform_write(size, move |c| {
+						c.store_file(&hash, &result.file)?;
+						Ok(())
+					})
+					.await?;
 			}
 		}
 		Ok(())
@@ -1389,54 +2008,71 @@ impl ActorNode {
 	async fn synchronize_object(
 		&self, connection: &mut Connection, object: &BlogchainObject,
 	) -> db::Result<()> {
-		let missing_files = self.investigate_missing_object_files(object)?;
+		let missing_files = self.investigate_missing_object_files(object).await?;
 		for file_id in &missing_files {
 			self.collect_file(connection, file_id).await?;
 		}
 		Ok(())
 	}
 
-	/// Attempts to synchonize the few objects before our known head object
+	/// Attempts to synchonize the few objects before our known head object.
+	/// Objects that were created before `min_created` are left out.
+	/// Returns the number of objects before the head that we have now.
 	async fn synchronize_objects_from_head(
-		&self, head: &BlogchainObject, count: u64,
-	) -> db::Result<bool> {
+		&self, head: &BlogchainObject, count: u64, min_created: u64,
+	) -> db::Result<u64> {
+		if count == 0 || head.sequence == 0 {
+			return Ok(0);
+		}
 		let stop_sequence = if head.sequence as u64 >= count {
 			head.sequence as u64 - count
 		} else {
 			0
 		};
 
-		let mut current_sequence: u64;
+		// Fetch the log in batches from one of our peers first, so that the objects
+		// only need to be looked up one by one if that peer couldn't help
+		if let Some(mut connection) = self.connect_to_peers(1).await.pop() {
+			self.synchronize_log_on_connection(&mut connection, head, stop_sequence, min_created)
+				.await?;
+		}
+
+		let mut synchronized = 0;
 		let mut previous_hash = head.previous_hash.clone();
 		loop {
-			if let Some((previous_object, _)) =
-				self.db().perform(|c| c.fetch_object(&previous_hash))?
-			{
-				current_sequence = previous_object.sequence;
-				previous_hash = previous_object.previous_hash;
-			} else {
-				if let Some(result) = self.find_object(&previous_hash).await {
-					self.store_object(&previous_hash, &result.object, false)?;
-					current_sequence = result.object.sequence;
-					previous_hash = result.object.previous_hash;
-				} else {
-					return Ok(false);
-				}
+			let hash = previous_hash.clone();
+			let (previous_object, is_stored) =
+				match self.db().perform(move |c| c.fetch_object(&hash)).await? {
+					Some((object, _)) => (object, true),
+					None => match self.find_object(&previous_hash).await {
+						Some(result) => (result.object, false),
+						None => return Ok(synchronized),
+					},
+				};
+
+			if previous_object.created < min_created {
+				return Ok(synchronized);
 			}
+			if !is_stored {
+				self.store_object(&previous_hash, &previous_object, false).await?;
+			}
+			synchronized += 1;
 
-			if current_sequence <= stop_sequence {
-				debug_assert_eq!(current_sequence, stop_sequence);
-				return Ok(true);
+			if previous_object.sequence <= stop_sequence {
+				debug_assert_eq!(previous_object.sequence, stop_sequence);
+				return Ok(synchronized);
 			}
+			previous_hash = previous_object.previous_hash;
 		}
 	}
 
 	/// Iteratively search the network for object meta data.
 	async fn synchronize_objects_from_start(&self) -> db::Result<bool> {
-		let result = tokio::task::block_in_place(|| {
-			let c = self.db().connect_old()?;
-			c.fetch_last_verified_object(self.actor_address())
-		})?;
+		let actor_address = self.actor_address().clone();
+		let result = self
+			.db()
+			.perform(move |c| c.fetch_last_verified_object(&actor_address))
+			.await?;
 
 		let (mut last_known_object_id, mut last_known_object_sequence) = match result {
 			Some((hash, object)) => (hash, object.sequence),
@@ -1446,16 +2082,19 @@ impl ActorNode {
 				match self.find_object(first_object_hash).await {
 					None => return Ok(false),
 					Some(object_result) => {
-						if self.verify_object(
-							&first_object_hash,
-							&object_result.object,
-							&self.base.interface.actor_info.public_key,
-						) {
-							if !self.store_object(
-								&self.base.interface.actor_info.first_object,
-								&object_result.object,
-								true,
-							)? {
+						if self
+							.base
+							.interface
+							.verify_object(&first_object_hash, &object_result.object)
+						{
+							if !self
+								.store_object(
+									&self.base.interface.actor_info.first_object,
+									&object_result.object,
+									true,
+								)
+								.await?
+							{
 								return Ok(false);
 							}
 						} else {
@@ -1479,12 +2118,8 @@ impl ActorNode {
 						return Ok(true);
 					}
 
-					if self.verify_object(
-						&hash,
-						&object,
-						&self.base.interface.actor_info.public_key,
-					) {
-						self.store_object(&hash, &object, true)?;
+					if self.base.interface.verify_object(&hash, &object) {
+						self.store_object(&hash, &object, true).await?;
 					} else {
 						return Ok(false);
 					}
@@ -1494,38 +2129,45 @@ impl ActorNode {
 					// Update the objects we may have already stored if we know they have been
 					// verified.
 					loop {
-						let to_break: db::Result<bool> = tokio::task::block_in_place(|| {
-							let mut c = self.db().connect_old()?;
+						let actor_address = self.actor_address().clone();
+						let previous_hash = object.previous_hash.clone();
+						let sequence = object.sequence;
+						let last_known = (last_known_object_id.clone(), last_known_object_sequence);
+						let result = self.db().perform(move |mut c| {
+							let (mut last_known_object_id, mut last_known_object_sequence) =
+								last_known;
 							let result = c.fetch_object_by_sequence(
-								self.actor_address(),
+								&actor_address,
 								last_known_object_sequence + 1,
 							)?;
 
-							if let Some((hash, _, verified_from_start)) = result {
+							let to_break = if let Some((hash, _, verified_from_start)) = result {
 								// If hashes don't compare, we know the object is invalid (even
 								// though the signature is correct), and so we should delete it.
 
-								if (object.sequence > 0
-									&& object.previous_hash != last_known_object_id)
-									|| (object.sequence == 0
-										&& object.previous_hash != IdType::default())
+								if (sequence > 0 && previous_hash != last_known_object_id)
+									|| (sequence == 0 && previous_hash != IdType::default())
 								{
-									c.delete_object(self.actor_address(), &hash)?;
-									Ok(true)
+									c.delete_object(&actor_address, &hash)?;
+									true
 								} else {
 									last_known_object_sequence += 1;
 									last_known_object_id = hash.clone();
 									if !verified_from_start {
-										c.update_object_verified(self.actor_address(), &hash)?;
+										c.update_object_verified(&actor_address, &hash)?;
 									}
 
-									Ok(false)
+									false
 								}
 							} else {
-								Ok(true)
-							}
+								true
+							};
+							Ok((to_break, last_known_object_id, last_known_object_sequence))
 						});
-						if to_break? {
+						let to_break;
+						(to_break, last_known_object_id, last_known_object_sequence) =
+							result.await?;
+						if to_break {
 							break;
 						}
 					}
@@ -1538,14 +2180,16 @@ impl ActorNode {
 	pub fn start_synchronization(self: &Arc<Self>) -> bool {
 		if !self.is_synchonizing.swap(true, Ordering::Acquire) {
 			let this = self.clone();
-			spawn(async move {
+			let name = format!("synchronization of actor {}", self.actor_address());
+			self.base.overlay_node().tasks().spawn(name, async move {
 				let result = this.synchronize().await;
-				if let Err(e) = result {
-					error!(
+				match result {
+					Ok(()) => *this.last_synchronized.lock().unwrap() = Some(current_timestamp()),
+					Err(e) => error!(
 						"Error occurred during synchronization for actor {:?}: {:?}",
 						this.actor_address(),
 						e
-					);
+					),
 				}
 				this.is_synchonizing.store(false, Ordering::Release);
 			});
@@ -1569,7 +2213,7 @@ impl ActorNode {
 
 impl PublishObjectToDo {
 	async fn receive_object(
-		&self, c: &mut Connection, object_id: &IdType, public_key: &ActorPublicKeyV1,
+		&self, c: &mut Connection, object_id: &IdType,
 	) -> Option<BlogchainObject> {
 		let buffer = match c.receive().await {
 			Ok(v) => Arc::new(v),
@@ -1586,10 +2230,7 @@ impl PublishObjectToDo {
 			}
 		};
 
-		if !self
-			.node
-			.verify_object(&object_id, &upload.object, public_key)
-		{
+		if !self.node.base.interface.verify_object(&object_id, &upload.object) {
 			warn!("Invalid object received: verification failed.");
 			return None;
 		}
@@ -1598,19 +2239,42 @@ impl PublishObjectToDo {
 	}
 }
 
+#[async_trait]
+impl MessageWorkToDo for NotifyObjectToDo {
+	async fn run(&mut self, mut connection: Box<Connection>) -> Result<Option<Box<Connection>>> {
+		let result = async {
+			self.node
+				.complete_object(&mut connection, self.object.clone())
+				.await?;
+			self.node
+				.synchronize_missed_objects_on_connection(&mut connection, self.object.clone())
+				.await
+		};
+		if let Err(e) = result.await {
+			error!("Database error while processing pushed object: {}", e);
+			return Ok(None);
+		}
+		self.node.gossip_object(
+			self.id.clone(),
+			self.object.clone(),
+			self.hops_left,
+			vec![connection.their_node_id().clone()],
+		);
+		if let Err(e) = self.node.base.overlay_node().notify_new_mentions().await {
+			error!("Unable to look for new mentions: {}", e);
+		}
+		Ok(Some(connection))
+	}
+}
+
 #[async_trait]
 impl MessageWorkToDo for PublishObjectToDo {
 	async fn run(&mut self, mut connection: Box<Connection>) -> Result<Option<Box<Connection>>> {
-		let public_key = &self.node.base.interface.actor_info.public_key;
-		let object_result = self
-			.receive_object(&mut connection, &self.hash, public_key)
-			.await;
+		let object_result = self.receive_object(&mut connection, &self.hash).await;
 
 		// Store object
 		let stored = if let Some(object) = &object_result {
-			match self.node.db().perform(|mut c| {
-				c.store_object(self.node.actor_address(), &self.hash, object, false)
-			}) {
+			match self.node.store_object(&self.hash, object, false).await {
 				Ok(r) => r,
 				Err(e) => {
 					error!("Unable to store received object: {:?}", e);
@@ -1639,6 +2303,15 @@ impl MessageWorkToDo for PublishObjectToDo {
 				error!("Database error while processing new head: {}", e);
 				return Ok(None);
 			}
+			self.node.gossip_object(
+				self.hash.clone(),
+				object.clone(),
+				GOSSIP_HOPS,
+				vec![connection.their_node_id().clone()],
+			);
+			if let Err(e) = self.node.base.overlay_node().notify_new_mentions().await {
+				error!("Unable to look for new mentions: {}", e);
+			}
 
 			// Republish object if it was newer than our head
 			let guard = self.node.base.interface.head_sequence.lock().unwrap();
@@ -1665,3 +2338,90 @@ impl MessageWorkToDo for PublishObjectToDo {
 		Ok(Some(connection))
 	}
 }
+
+
+fn verify_object(id: &IdType, object: &BlogchainObject, public_key: &ActorPublicKeyV1) -> bool {
+	if object.created as u128
+		> SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_millis()
+	{
+		warn!(
+			"Object {} is invalid: creation timestamp is from the future: {}",
+			&id, object.created
+		);
+		return false;
+	}
+
+	let sign_data = ObjectSignData {
+		previous_hash: object.previous_hash.clone(),
+		sequence: object.sequence,
+		created: object.created,
+		payload: &object.payload,
+	};
+	let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+	if !public_key.verify(&raw_sign_data, &object.signature) {
+		warn!("Object {} is invalid: signature is incorrect.", &id);
+		return false;
+	}
+
+	let signature_hash = object.signature.hash();
+	if &signature_hash != id {
+		warn!(
+			"Object {} is invalid: id is not a hash of the signature: {}",
+			&id, signature_hash,
+		);
+		return false;
+	}
+	true
+}
+
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, test};
+
+	#[test]
+	fn test_verify_object() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let other_private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+
+		let previous_hash = IdType::random(&mut rng);
+		let payload = ObjectPayload::Share(ShareObject {
+			actor_address: ActorAddress::V1(IdType::random(&mut rng)),
+			object_hash: IdType::random(&mut rng),
+		});
+		let created = rng.next_u32() as u64;
+		let sign_data = ObjectSignData {
+			sequence: 1,
+			previous_hash: previous_hash.clone(),
+			created,
+			payload: &payload,
+		};
+		let signature = private_key.sign(&binserde::serialize(&sign_data).unwrap());
+		let mut object = BlogchainObject {
+			signature: signature.clone(),
+			sequence: 1,
+			previous_hash,
+			created,
+			payload,
+			delegation: None,
+		};
+		let hash = signature.hash();
+
+		assert!(verify_object(&hash, &object, &private_key.public()));
+		assert!(!verify_object(&hash, &object, &other_private_key.public()));
+		assert!(!verify_object(
+			&IdType::random(&mut rng),
+			&object,
+			&private_key.public()
+		));
+		object.created += 1;
+		assert!(!verify_object(&hash, &object, &private_key.public()));
+	}
+}
@@ -0,0 +1,179 @@
+//! Delivers object announcements to followers that were offline.
+//!
+//! New objects are pushed to the nodes that have recently been following the
+//! actor. When such a follower can't be reached, the announcement is queued in
+//! the database, and retried with an exponential backoff until the follower
+//! can be reached, or until it has been tried for too long. That way, the
+//! follower still hears about the object quickly once it comes back online,
+//! even if both nodes are rarely online at the same time.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::{stream, StreamExt};
+use log::*;
+use tokio::time::timeout;
+
+use super::ActorNode;
+use crate::{
+	common::*,
+	db,
+	net::{binserde, overlay::OverlayNode, NodeContactInfo},
+};
+
+
+/// How often the delivery queue is checked for announcements that are due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The time to wait before the first retry. Doubles with every failure.
+const RETRY_BASE_DELAY: u64 = 60_000;
+/// The longest time to wait between retries.
+const RETRY_MAX_DELAY: u64 = 12 * 60 * 60 * 1000;
+/// Announcements are given up on after this many failures, which spans about
+/// a week with the delays above.
+const RETRY_LIMIT: u32 = 20;
+/// The maximum number of announcements that are retried at once per actor.
+const RETRY_BATCH_SIZE: u64 = 50;
+/// The number of announcements that are being retried at the same time.
+const RETRY_CONCURRENCY: usize = 8;
+/// How long a single retry may take before it counts as a failure.
+const RETRY_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// The delay before the next attempt, after the given number of failures.
+fn retry_delay(failures: u32) -> u64 {
+	RETRY_BASE_DELAY
+		.saturating_mul(1u64 << failures.min(32))
+		.min(RETRY_MAX_DELAY)
+}
+
+impl ActorNode {
+	/// Queues the announcement of an object for a follower that couldn't be
+	/// reached.
+	pub(super) async fn queue_delivery(
+		&self, object_hash: &IdType, follower: &NodeContactInfo,
+	) -> db::Result<()> {
+		let contact = binserde::serialize(follower).unwrap();
+		let next_attempt = current_timestamp() + retry_delay(0);
+		self.db()
+			.queue_delivery(
+				self.base.interface.actor_id,
+				object_hash,
+				&follower.address.to_string(),
+				contact,
+				next_attempt as _,
+			)
+			.await
+	}
+
+	/// Retries the announcements that are due. A few followers are contacted
+	/// at the same time, so that a follower that is slow to respond doesn't
+	/// hold up the others.
+	async fn retry_deliveries(&self) -> db::Result<()> {
+		let now = current_timestamp();
+		let deliveries = self
+			.db()
+			.load_due_deliveries(self.base.interface.actor_id, now as _, RETRY_BATCH_SIZE)
+			.await?;
+		let mut attempts = Vec::with_capacity(deliveries.len());
+		for delivery in deliveries {
+			let follower: NodeContactInfo =
+				match binserde::deserialize_owned(&delivery.recipient_contact) {
+					Ok(f) => f,
+					Err(e) => {
+						warn!("Invalid contact info in delivery queue: {}", e);
+						self.db().remove_delivery(delivery.id).await?;
+						continue;
+					}
+				};
+			let object_hash = delivery.object_hash.clone();
+			let object = match self
+				.db()
+				.perform(move |c| c.fetch_object(&object_hash))
+				.await?
+			{
+				Some((o, _)) => o,
+				None => {
+					self.db().remove_delivery(delivery.id).await?;
+					continue;
+				}
+			};
+			attempts.push((delivery, follower, object));
+		}
+
+		let results: Vec<_> = stream::iter(attempts)
+			.map(|(delivery, follower, object)| async move {
+				let attempt = self.notify_follower(&follower, &delivery.object_hash, &object);
+				let reached = timeout(RETRY_TIMEOUT, attempt).await.unwrap_or(false);
+				(delivery, follower, reached)
+			})
+			.buffer_unordered(RETRY_CONCURRENCY)
+			.collect()
+			.await;
+		for (delivery, follower, reached) in results {
+			if reached {
+				debug!(
+					"Delivered object {} to follower {} after {} failure(s).",
+					&delivery.object_hash, &follower.address, delivery.failures
+				);
+				self.db().remove_delivery(delivery.id).await?;
+			} else if delivery.failures + 1 >= RETRY_LIMIT {
+				debug!(
+					"Giving up on delivering object {} to follower {}.",
+					&delivery.object_hash, &follower.address
+				);
+				self.db().remove_delivery(delivery.id).await?;
+			} else {
+				let failures = delivery.failures + 1;
+				let next_attempt = now + retry_delay(failures);
+				self.db()
+					.postpone_delivery(delivery.id, failures, next_attempt as _)
+					.await?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Starts retrying the queued announcements of all actor networks that we are
+/// in.
+pub fn maintain_delivery_queue(node: Arc<OverlayNode>) {
+	node.tasks()
+		.spawn("delivery queue", keep_delivering(node.clone()));
+}
+
+async fn keep_delivering(node: Arc<OverlayNode>) {
+	loop {
+		let actor_nodes: Vec<Arc<ActorNode>> = node
+			.base
+			.interface
+			.actor_nodes
+			.lock()
+			.await
+			.values()
+			.cloned()
+			.collect();
+		for actor_node in actor_nodes {
+			if let Err(e) = actor_node.retry_deliveries().await {
+				node.db().observe_error(&e);
+				error!("Unable to retry deliveries: {}", e);
+			}
+		}
+
+		if !node.sleep_while_running(CHECK_INTERVAL).await {
+			break;
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_retry_delay() {
+		assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+		assert_eq!(retry_delay(3), 8 * RETRY_BASE_DELAY);
+		assert_eq!(retry_delay(RETRY_LIMIT), RETRY_MAX_DELAY);
+		assert_eq!(retry_delay(u32::MAX), RETRY_MAX_DELAY);
+	}
+}
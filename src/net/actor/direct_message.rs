@@ -0,0 +1,256 @@
+//! Delivers direct messages over the actor network of their recipient.
+//!
+//! Only the node of the recipient itself accepts a direct message, as it is
+//! the only one that is able to open it. It is also the only one that hands
+//! out the message key of the recipient, signed by the recipient, so that the
+//! sender can be sure that nobody else is able to read along. This means that
+//! a direct message can only be delivered while the recipient is online, which
+//! is why the sender retries it for a while.
+
+use std::net::SocketAddr;
+
+use log::*;
+
+use super::{
+	ActorNode, ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_REQUEST,
+	ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_RESPONSE, ACTOR_MESSAGE_TYPE_MESSAGE_KEY_REQUEST,
+	ACTOR_MESSAGE_TYPE_MESSAGE_KEY_RESPONSE,
+};
+use crate::{
+	core::*,
+	db::{self, PersistenceHandle},
+	identity::{
+		message_key::{MessageKey, MessageSecret},
+		ActorSignatureV1, Signer,
+	},
+	net::{
+		binserde,
+		event::{Event, NotificationKind},
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+	},
+};
+
+
+impl ActorNode {
+	/// Sends the direct message to the nodes of the actor network, until the
+	/// node of the recipient has accepted it. Returns whether it did.
+	pub async fn deliver_direct_message(
+		&self, sender_info: &ActorInfo, message: &DirectMessageObject,
+	) -> bool {
+		let request = DirectMessageRequest {
+			sender_info: sender_info.clone(),
+			message: message.clone(),
+		};
+		let mut iter = self.base.iter_all_fingers_local_first().await;
+		while let Some(finger) = iter.next().await {
+			if let Some((mut connection, _)) = self.base.select_connection(&finger, None).await {
+				if self
+					.exchange_direct_message_on_connection(&mut connection, &request)
+					.await == Some(true)
+				{
+					return true;
+				}
+			}
+		}
+		false
+	}
+
+	async fn exchange_direct_message_on_connection(
+		&self, connection: &mut Connection, request: &DirectMessageRequest,
+	) -> Option<bool> {
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_REQUEST,
+				&binserde::serialize(request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<DirectMessageResponse> =
+			binserde::deserialize_sstp(&raw_response);
+		let response = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		Some(response.accepted)
+	}
+
+	async fn exchange_message_key_on_connection(
+		&self, connection: &mut Connection,
+	) -> Option<(MessageKey, ActorSignatureV1)> {
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_MESSAGE_KEY_REQUEST,
+				&binserde::serialize(&MessageKeyRequest {}).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<MessageKeyResponse> = binserde::deserialize_sstp(&raw_response);
+		let response = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		response.key
+	}
+
+	/// Asks the nodes of the actor network for the message key of the actor,
+	/// until one of them responds with a key that the actor has signed.
+	pub async fn find_message_key(&self) -> Option<MessageKey> {
+		let public_key = self.base.interface.key_chain.lock().unwrap().current().clone();
+		let mut iter = self.base.iter_all_fingers_local_first().await;
+		while let Some(finger) = iter.next().await {
+			if let Some((mut connection, _)) = self.base.select_connection(&finger, None).await {
+				if let Some((key, signature)) =
+					self.exchange_message_key_on_connection(&mut connection).await
+				{
+					if public_key.verify(&key.sign_data(), &signature) {
+						return Some(key);
+					}
+					warn!(
+						"Node {} responded with a message key that isn't signed by actor {}.",
+						connection.their_node_id(),
+						self.actor_address()
+					);
+				}
+			}
+		}
+		None
+	}
+
+	/// Loads the signer of the actor, if the actor is one of our identities.
+	pub(super) async fn load_own_signer(&self) -> db::Result<Option<Box<dyn Signer>>> {
+		let actor_address = self.actor_address().clone();
+		let identity = self
+			.db()
+			.perform(move |c| c.fetch_my_identity(&actor_address))
+			.await?;
+		Ok(identity.map(|(_, signer)| signer))
+	}
+
+	/// Opens and stores the direct message, if it has been sent to us. Returns
+	/// whether it has been accepted.
+	async fn accept_direct_message(&self, request: &DirectMessageRequest) -> db::Result<bool> {
+		let message = &request.message;
+		if &message.recipient != self.actor_address()
+			|| request.sender_info.generate_address() != message.sender
+		{
+			return Ok(false);
+		}
+		let signer = match self.load_own_signer().await? {
+			Some(s) => s,
+			None => return Ok(false),
+		};
+		if self
+			.db()
+			.is_actor_blocked(self.base.interface.actor_id, &message.sender)
+			.await?
+		{
+			return Ok(false);
+		}
+		// Messages can't be opened with the keys of an external signer
+		let secret = match signer.private_key() {
+			Some(private_key) => MessageSecret::derive(private_key),
+			None => return Ok(false),
+		};
+
+		// Check the signature against the key that the sender has now
+		let sender_id = self
+			.db()
+			.ensure_actor_id(&message.sender, &request.sender_info)
+			.await?;
+		let public_key = match self.db().load_current_public_key(sender_id).await? {
+			Some(k) => k,
+			None => return Ok(false),
+		};
+		if !message.verify(&public_key) {
+			warn!("Direct message from {} has an invalid signature.", &message.sender);
+			return Ok(false);
+		}
+		let content = match message.open(&secret) {
+			Some(c) => c,
+			None => {
+				warn!("Unable to open direct message from {}.", &message.sender);
+				return Ok(false);
+			}
+		};
+
+		// A message that was received before is accepted again, so that the
+		// sender stops retrying it
+		let is_new = self
+			.db()
+			.store_incoming_direct_message(
+				&message.recipient,
+				&message.sender,
+				&message.hash(),
+				message.created as _,
+				&content,
+			)
+			.await?;
+		if is_new {
+			self.base.overlay_node().events.publish(Event::NewNotification {
+				kind: NotificationKind::DirectMessage,
+				identity: message.recipient.to_string(),
+				actor_address: message.sender.to_string(),
+			});
+		}
+		Ok(true)
+	}
+
+	pub(super) async fn process_direct_message_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: DirectMessageRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed direct message request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let accepted = match self.accept_direct_message(&request).await {
+			Ok(a) => a,
+			Err(e) => {
+				error!("Unable to accept direct message: {:?}", e);
+				false
+			}
+		};
+		let response = DirectMessageResponse { accepted };
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_DIRECT_MESSAGE_RESPONSE, &response)
+	}
+
+	pub(super) async fn process_message_key_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let _request: MessageKeyRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed message key request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let key = match self.load_own_signer().await {
+			Ok(Some(signer)) => signer.private_key().and_then(|private_key| {
+				let key = MessageSecret::derive(private_key).public();
+				match signer.sign(&key.sign_data()) {
+					Ok(signature) => Some((key, signature)),
+					Err(e) => {
+						warn!("Unable to sign message key: {}", e);
+						None
+					}
+				}
+			}),
+			Ok(None) => None,
+			Err(e) => {
+				error!("Unable to load identity: {:?}", e);
+				None
+			}
+		};
+		let response = MessageKeyResponse { key };
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_MESSAGE_KEY_RESPONSE, &response)
+	}
+}
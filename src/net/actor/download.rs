@@ -0,0 +1,468 @@
+//! Downloads the blocks of a file from several peers at once.
+//!
+//! Looking up every block separately means that a file is only ever downloaded
+//! from one node at a time. Instead, a connection is opened to a few of the
+//! peers in the network of the actor, and each of them is asked which blocks
+//! of the file it has. Each peer takes the next block from a shared schedule
+//! as soon as it has sent the last one, so faster peers end up providing more
+//! of the blocks. The blocks are verified and stored by a separate loop, so
+//! that a peer can be asked for its next block while the last one is being
+//! written to disk. Blocks that no peer had are looked up on the network
+//! afterwards, one by one, and the ones that can't be found at all are
+//! reconstructed from parity blocks if possible.
+//!
+//! The blocks are scheduled rarest-first: a peer is given the block that it
+//! has, that the fewest of the other peers have. That way, the blocks that are
+//! at risk of disappearing from the network when a peer leaves are secured
+//! first, and large files remain retrievable even as their original seeder
+//! comes and goes. Files that are being streamed are downloaded in order
+//! instead, so that they can be played back while they are coming in.
+
+use std::{
+	collections::{HashMap, HashSet},
+	net::SocketAddr,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc, Mutex as StdMutex,
+	},
+};
+
+use futures::future::join_all;
+use log::*;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::{
+	ActorNode, ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST, ACTOR_MESSAGE_TYPE_BLOCK_MAP_RESPONSE,
+};
+use crate::{
+	common::*,
+	db::PersistenceHandle,
+	net::{
+		binserde,
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+	},
+};
+
+
+/// The maximum number of peers that the blocks of a file are downloaded from.
+const DOWNLOAD_PEER_LIMIT: usize = 4;
+/// The number of blocks a peer may fail to provide before it isn't asked
+/// anymore.
+const PEER_MISS_LIMIT: usize = 3;
+
+
+/// Keeps track of the downloads that are in progress. The same file may be
+/// downloaded more than once at the same time, so every download has its own
+/// ID.
+#[derive(Default)]
+pub struct DownloadTracker {
+	next_id: AtomicU64,
+	downloads: StdMutex<HashMap<u64, (IdType, Arc<DownloadProgress>)>>,
+}
+
+#[derive(Default)]
+pub(super) struct DownloadProgress {
+	total_blocks: usize,
+	downloaded_blocks: AtomicUsize,
+	downloaded_bytes: AtomicU64,
+	peers: AtomicUsize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DownloadInfo {
+	pub file_hash: String,
+	pub total_blocks: usize,
+	pub downloaded_blocks: usize,
+	pub downloaded_bytes: u64,
+	/// The number of peers that blocks are being downloaded from.
+	pub peers: usize,
+}
+
+/// Decides which peer downloads which block.
+struct BlockScheduler {
+	/// The blocks that still need to be downloaded, with their index in the
+	/// file.
+	pending: Vec<(usize, IdType)>,
+	peers: Vec<PeerBlocks>,
+	/// Whether to hand out the blocks in order, rather than rarest-first.
+	sequential: bool,
+}
+
+struct PeerBlocks {
+	/// The block map that the peer has sent, if it has.
+	bitmap: Option<Vec<u8>>,
+	/// The blocks that the peer turned out not to have after all.
+	lacking: HashSet<usize>,
+}
+
+
+impl DownloadTracker {
+	fn finish(&self, id: u64) { self.downloads.lock().unwrap().remove(&id); }
+
+	pub fn list(&self) -> Vec<DownloadInfo> {
+		self.downloads
+			.lock()
+			.unwrap()
+			.values()
+			.map(|(hash, progress)| DownloadInfo {
+				file_hash: hash.to_string(),
+				total_blocks: progress.total_blocks,
+				downloaded_blocks: progress.downloaded_blocks.load(Ordering::Relaxed),
+				downloaded_bytes: progress.downloaded_bytes.load(Ordering::Relaxed),
+				peers: progress.peers.load(Ordering::Relaxed),
+			})
+			.collect()
+	}
+
+	/// Registers a new download. Returns its ID, along with its progress.
+	fn start(&self, file_hash: &IdType, total_blocks: usize) -> (u64, Arc<DownloadProgress>) {
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let progress = Arc::new(DownloadProgress {
+			total_blocks,
+			..Default::default()
+		});
+		self.downloads
+			.lock()
+			.unwrap()
+			.insert(id, (file_hash.clone(), progress.clone()));
+		(id, progress)
+	}
+}
+
+impl BlockScheduler {
+	/// The number of peers that have the block.
+	fn availability(&self, index: usize) -> usize {
+		self.peers.iter().filter(|p| p.has(index)).count()
+	}
+
+	/// Takes the block that the peer has, that the fewest peers have. Or the
+	/// first block that the peer has, when downloading sequentially.
+	fn next_block(&mut self, peer: usize) -> Option<(usize, IdType)> {
+		let position = self
+			.pending
+			.iter()
+			.enumerate()
+			.filter(|(_, (index, _))| self.peers[peer].has(*index))
+			.min_by_key(|(_, (index, _))| {
+				if self.sequential {
+					(0, *index)
+				} else {
+					(self.availability(*index), *index)
+				}
+			})
+			.map(|(position, _)| position)?;
+		Some(self.pending.swap_remove(position))
+	}
+
+	/// Puts a block back that the peer didn't have after all.
+	fn return_block(&mut self, peer: usize, block: (usize, IdType)) {
+		self.peers[peer].lacking.insert(block.0);
+		self.pending.push(block);
+	}
+}
+
+impl PeerBlocks {
+	/// Whether the peer has the block. Peers that haven't sent a block map are
+	/// assumed to have every block.
+	fn has(&self, index: usize) -> bool {
+		if self.lacking.contains(&index) {
+			return false;
+		}
+		match &self.bitmap {
+			Some(bitmap) => bitmap_contains(bitmap, index),
+			None => true,
+		}
+	}
+}
+
+impl ActorNode {
+	async fn exchange_block_map_on_connection(
+		&self, connection: &mut Connection, file_hash: &IdType,
+	) -> Option<BlockMapResponse> {
+		let request = BlockMapRequest {
+			file_hash: file_hash.clone(),
+		};
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<_> = binserde::deserialize_sstp(&raw_response);
+		self.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await
+	}
+
+	pub(super) async fn process_block_map_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: BlockMapRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed block map request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let bitmap = match self.db().find_held_blocks(&request.file_hash).await {
+			Ok(held) => held.map(|h| encode_bitmap(&h).into()),
+			Err(e) => {
+				self.db().observe_error(&e);
+				error!("Unable to load block map: {:?}", e);
+				return None;
+			}
+		};
+		let response = BlockMapResponse { bitmap };
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_BLOCK_MAP_RESPONSE, &response)
+	}
+
+	/// Downloads the given blocks of a file, which are given together with
+	/// their index in the file. The blocks are stored, and sent on the returned
+	/// channel as they come in, which is not necessarily in order. The channel
+	/// is closed once all blocks that could be found have been sent.
+	/// If `sequential` is set, the blocks are requested in order, so that they
+	/// come in roughly in order as well.
+	pub fn download_blocks(
+		self: &Arc<Self>, file_id: i64, file_hash: &IdType, blocks: Vec<(usize, IdType)>,
+		sequential: bool,
+	) -> mpsc::Receiver<(usize, Vec<u8>)> {
+		let (tx, rx) = mpsc::channel(DOWNLOAD_PEER_LIMIT);
+		let this = self.clone();
+		let file_hash = file_hash.clone();
+		let name = format!("download of file {}", file_hash);
+		self.base.overlay_node().tasks().spawn(name, async move {
+			let tracker = &this.base.interface.overlay_node.downloads;
+			let (id, progress) = tracker.start(&file_hash, blocks.len());
+			this.download_blocks_from_peers(file_id, &file_hash, blocks, sequential, &progress, tx)
+				.await;
+			tracker.finish(id);
+		});
+		rx
+	}
+
+	async fn download_blocks_from_peers(
+		&self, file_id: i64, file_hash: &IdType, blocks: Vec<(usize, IdType)>, sequential: bool,
+		progress: &DownloadProgress, tx: mpsc::Sender<(usize, Vec<u8>)>,
+	) {
+		let mut scheduler = BlockScheduler {
+			pending: blocks,
+			peers: Vec::with_capacity(DOWNLOAD_PEER_LIMIT),
+			sequential,
+		};
+		let mut connections = Vec::with_capacity(DOWNLOAD_PEER_LIMIT);
+		let mut iter = self.base.iter_all_fingers_top_down(0).await;
+		while let Some(finger) = iter.next().await {
+			if let Some((mut connection, _)) =
+				self.base.select_direct_connection(&finger, None).await
+			{
+				let bitmap: Option<Option<Vec<u8>>> = self
+					.exchange_block_map_on_connection(&mut connection, file_hash)
+					.await
+					.map(|r| r.bitmap.map(|b| b.into()));
+				// Peers that don't know the file have nothing to offer
+				if bitmap == Some(None) {
+					continue;
+				}
+				scheduler.peers.push(PeerBlocks {
+					bitmap: bitmap.flatten(),
+					lacking: HashSet::new(),
+				});
+				connections.push(connection);
+				if connections.len() == DOWNLOAD_PEER_LIMIT {
+					break;
+				}
+			}
+		}
+		progress.peers.store(connections.len(), Ordering::Relaxed);
+
+		// Let every peer download blocks, while storing them as they come in
+		let scheduler = StdMutex::new(scheduler);
+		let (block_tx, mut block_rx) = mpsc::channel(DOWNLOAD_PEER_LIMIT);
+		let workers = join_all(connections.into_iter().enumerate().map(|(peer, connection)| {
+			self.download_blocks_from_peer(connection, peer, &scheduler, block_tx.clone())
+		}));
+		drop(block_tx);
+		let collector = async {
+			while let Some((index, hash, data)) = block_rx.recv().await {
+				self.collect_downloaded_block(file_id, index, &hash, data, progress, &tx)
+					.await;
+			}
+		};
+		tokio::join!(workers, collector);
+		progress.peers.store(0, Ordering::Relaxed);
+
+		// Look up the blocks that couldn't be downloaded from the peers
+		let mut remaining = scheduler.into_inner().unwrap().pending;
+		if sequential {
+			remaining.sort_by_key(|(index, _)| *index);
+		}
+		let mut lost = Vec::new();
+		for (index, hash) in remaining {
+			if let Some(result) = self.find_block(&hash).await {
+				let data = result.data.into();
+				self.collect_downloaded_block(file_id, index, &hash, data, progress, &tx)
+					.await;
+			} else {
+				lost.push((index, hash));
+			}
+		}
+
+		if lost.len() > 0 {
+			if let Err(e) = self
+				.reconstruct_blocks(file_id, file_hash, lost, progress, &tx)
+				.await
+			{
+				self.db().observe_error(&e);
+				error!("Unable to reconstruct blocks of file {}: {}", file_hash, e);
+			}
+		}
+	}
+
+	async fn download_blocks_from_peer(
+		&self, mut connection: Box<Connection>, peer: usize, scheduler: &StdMutex<BlockScheduler>,
+		tx: mpsc::Sender<(usize, IdType, Vec<u8>)>,
+	) {
+		let mut misses = 0;
+		while misses < PEER_MISS_LIMIT {
+			let (index, hash) = match scheduler.lock().unwrap().next_block(peer) {
+				Some(b) => b,
+				None => break,
+			};
+
+			match self
+				.exchange_find_block_on_connection(&mut connection, &hash)
+				.await
+			{
+				Some(result) if self.verify_block(&hash, &result.data) => {
+					if tx.send((index, hash, result.data.into())).await.is_err() {
+						break;
+					}
+				}
+				_ => {
+					scheduler.lock().unwrap().return_block(peer, (index, hash));
+					misses += 1;
+				}
+			}
+		}
+	}
+
+	pub(super) async fn collect_downloaded_block(
+		&self, file_id: i64, index: usize, hash: &IdType, data: Vec<u8>,
+		progress: &DownloadProgress, tx: &mpsc::Sender<(usize, Vec<u8>)>,
+	) {
+		if let Err(e) = self.store_block(file_id, hash, &data).await {
+			self.db().observe_error(&e);
+			error!("Unable to store downloaded block {}: {}", hash, e);
+		}
+		progress.downloaded_blocks.fetch_add(1, Ordering::Relaxed);
+		progress
+			.downloaded_bytes
+			.fetch_add(data.len() as _, Ordering::Relaxed);
+		// The receiver may have stopped listening, but the block is stored anyway
+		let _ = tx.send((index, data)).await;
+	}
+}
+
+
+/// Packs the flags into a bitmap, the first one being the least significant
+/// bit of the first byte.
+fn encode_bitmap(flags: &[bool]) -> Vec<u8> {
+	let mut bitmap = vec![0u8; (flags.len() + 7) / 8];
+	for (i, flag) in flags.iter().enumerate() {
+		if *flag {
+			bitmap[i / 8] |= 1 << (i % 8);
+		}
+	}
+	bitmap
+}
+
+fn bitmap_contains(bitmap: &[u8], index: usize) -> bool {
+	match bitmap.get(index / 8) {
+		Some(byte) => byte & (1 << (index % 8)) != 0,
+		None => false,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_download_tracker() {
+		let mut rng = test::initialize_rng();
+		let file_hash = IdType::random(&mut rng);
+		let tracker = DownloadTracker::default();
+
+		let (id, progress) = tracker.start(&file_hash, 3);
+		progress.downloaded_blocks.fetch_add(1, Ordering::Relaxed);
+		progress.downloaded_bytes.fetch_add(100, Ordering::Relaxed);
+		let downloads = tracker.list();
+		assert_eq!(downloads.len(), 1);
+		assert_eq!(downloads[0].file_hash, file_hash.to_string());
+		assert_eq!(downloads[0].total_blocks, 3);
+		assert_eq!(downloads[0].downloaded_blocks, 1);
+		assert_eq!(downloads[0].downloaded_bytes, 100);
+
+		// Another download of the same file doesn't replace the first one
+		let (other_id, _) = tracker.start(&file_hash, 3);
+		assert_ne!(id, other_id);
+		assert_eq!(tracker.list().len(), 2);
+		tracker.finish(other_id);
+		assert_eq!(tracker.list()[0].downloaded_blocks, 1);
+
+		tracker.finish(id);
+		assert_eq!(tracker.list().len(), 0);
+	}
+
+	#[test]
+	fn test_block_scheduler() {
+		let mut rng = test::initialize_rng();
+		let bitmap = encode_bitmap(&[true, false, true, true, false, false, false, false, true]);
+		assert_eq!(bitmap, vec![0b00001101, 0b00000001]);
+		assert!(bitmap_contains(&bitmap, 8));
+		assert!(!bitmap_contains(&bitmap, 9));
+
+		// Block 0 is held by all three peers, block 1 by two and block 2 only by
+		// the first one
+		let pending = (0..3).map(|i| (i, IdType::random(&mut rng))).collect();
+		let peer = |flags: &[bool]| PeerBlocks {
+			bitmap: Some(encode_bitmap(flags)),
+			lacking: HashSet::new(),
+		};
+		let mut scheduler = BlockScheduler {
+			pending,
+			peers: vec![
+				peer(&[true, true, true]),
+				peer(&[true, true, false]),
+				peer(&[true, false, false]),
+			],
+			sequential: false,
+		};
+
+		// Every peer gets the rarest block that it has
+		assert_eq!(scheduler.next_block(2).map(|b| b.0), Some(0));
+		assert_eq!(scheduler.next_block(1).map(|b| b.0), Some(1));
+		assert_eq!(scheduler.next_block(2), None);
+
+		// A block that a peer didn't have after all can go to another peer
+		let block = scheduler.next_block(0).unwrap();
+		assert_eq!(block.0, 2);
+		scheduler.return_block(0, block);
+		assert_eq!(scheduler.next_block(0), None);
+		assert_eq!(scheduler.availability(2), 0);
+
+		// When streaming, the first block that a peer has goes first
+		scheduler.sequential = true;
+		scheduler.pending.push((1, IdType::random(&mut rng)));
+		scheduler.pending.push((0, IdType::random(&mut rng)));
+		assert_eq!(scheduler.next_block(1).map(|b| b.0), Some(0));
+	}
+}
@@ -0,0 +1,285 @@
+//! Keeps the objects of actors that only hand them out to their approved
+//! followers away from other nodes, and delivers follow requests to them.
+//!
+//! Like a direct message, a follow request is only accepted by the node of
+//! the actor that is asked, because only its owner can approve it. The
+//! approved followers are published in a follower list object, so that every
+//! node of the actor network knows which nodes it may serve.
+
+use std::net::SocketAddr;
+
+use log::*;
+
+use super::{
+	ActorNode, ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_REQUEST,
+	ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_RESPONSE,
+};
+use crate::{
+	common::IdType,
+	core::*,
+	db,
+	net::{
+		binserde,
+		event::{Event, NotificationKind},
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+		NodeContactInfo,
+	},
+};
+
+
+impl ActorNode {
+	/// Stores the follow request, if it has been sent to us. Returns whether it
+	/// has been accepted.
+	async fn accept_follow_request(&self, request: &FollowRequestRequest) -> db::Result<bool> {
+		let object = &request.request;
+		if &object.actor_address != self.actor_address()
+			|| request.requester_info.generate_address() != object.requester
+		{
+			return Ok(false);
+		}
+		if self.load_own_signer().await?.is_none()
+			|| self
+				.db()
+				.is_actor_blocked(self.base.interface.actor_id, &object.requester)
+				.await?
+		{
+			return Ok(false);
+		}
+
+		let requester_id = self
+			.db()
+			.ensure_actor_id(&object.requester, &request.requester_info)
+			.await?;
+		let public_key = match self.db().load_current_public_key(requester_id).await? {
+			Some(k) => k,
+			None => return Ok(false),
+		};
+		if !object.verify(&public_key) {
+			warn!("Follow request from {} has an invalid signature.", &object.requester);
+			return Ok(false);
+		}
+
+		// A request that was received before is accepted again, so that the
+		// requester knows that it has arrived
+		let is_new = self
+			.db()
+			.store_follow_request(self.base.interface.actor_id, object)
+			.await?;
+		if is_new {
+			self.base.overlay_node().events.publish(Event::NewNotification {
+				kind: NotificationKind::FollowRequest,
+				identity: object.actor_address.to_string(),
+				actor_address: object.requester.to_string(),
+			});
+		}
+		Ok(true)
+	}
+
+	/// Sends the follow request to the nodes of the actor network, until the
+	/// node of the actor has accepted it. Returns whether it did.
+	pub async fn deliver_follow_request(
+		&self, requester_info: &ActorInfo, request: &FollowRequestObject,
+	) -> bool {
+		let request = FollowRequestRequest {
+			requester_info: requester_info.clone(),
+			request: request.clone(),
+		};
+		let mut iter = self.base.iter_all_fingers_local_first().await;
+		while let Some(finger) = iter.next().await {
+			if let Some((mut connection, _)) = self.base.select_connection(&finger, None).await {
+				if self
+					.exchange_follow_request_on_connection(&mut connection, &request)
+					.await == Some(true)
+				{
+					return true;
+				}
+			}
+		}
+		false
+	}
+
+	async fn exchange_follow_request_on_connection(
+		&self, connection: &mut Connection, request: &FollowRequestRequest,
+	) -> Option<bool> {
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_REQUEST,
+				&binserde::serialize(request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<FollowRequestResponse> =
+			binserde::deserialize_sstp(&raw_response);
+		let response = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		Some(response.accepted)
+	}
+
+	/// Leaves out the nodes that may not receive the objects of the actor.
+	pub(super) async fn filter_approved_followers(
+		&self, followers: Vec<NodeContactInfo>,
+	) -> db::Result<Vec<NodeContactInfo>> {
+		let actor_id = self.base.interface.actor_id;
+		if !self.db().is_followers_only(actor_id).await? {
+			return Ok(followers);
+		}
+		let mut approved = Vec::with_capacity(followers.len());
+		for follower in followers {
+			if self
+				.db()
+				.is_approved_follower_node(actor_id, &follower.address)
+				.await?
+			{
+				approved.push(follower);
+			}
+		}
+		Ok(approved)
+	}
+
+	/// Whether the value may be handed out to the node. If the actor only hands
+	/// out its objects to its approved followers, the node has to be on its
+	/// latest follower list, unless the value is its profile or one of its
+	/// follower lists.
+	pub async fn may_serve_value(
+		&self, value_type: u8, id: &IdType, node_address: &NodeAddress,
+	) -> db::Result<bool> {
+		let actor_id = self.base.interface.actor_id;
+		if !self.db().is_followers_only(actor_id).await?
+			|| self
+				.db()
+				.is_approved_follower_node(actor_id, node_address)
+				.await?
+		{
+			return Ok(true);
+		}
+		if value_type == BlogchainValueType::Object as u8 {
+			return self.db().is_public_object(actor_id, id).await;
+		}
+		Ok(false)
+	}
+
+	pub(super) async fn process_follow_request_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: FollowRequestRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed follow request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let accepted = match self.accept_follow_request(&request).await {
+			Ok(a) => a,
+			Err(e) => {
+				error!("Unable to accept follow request: {:?}", e);
+				false
+			}
+		};
+		let response = FollowRequestResponse { accepted };
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_FOLLOW_REQUEST_RESPONSE, &response)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use sea_orm::prelude::*;
+
+	use super::*;
+	use crate::{api::Api, entity::*, net::ContactInfo, test};
+
+	async fn sync_log(actor_node: &ActorNode, node_info: &NodeContactInfo) -> Vec<IdType> {
+		let request = SyncLogRequest {
+			checkpoints: Vec::new().into(),
+			min_sequence: 0,
+		};
+		let addr = "127.0.0.1:1".parse().unwrap();
+		let (buffer, _) = actor_node
+			.process_sync_log_request(&binserde::serialize(&request).unwrap(), &addr, node_info)
+			.await
+			.expect("no sync log response");
+		let response: SyncLogResponse = binserde::deserialize(&buffer[1..]).unwrap();
+		response.objects.iter().map(|(hash, _)| hash.clone()).collect()
+	}
+
+	#[tokio::test]
+	async fn test_serve_followers_only() {
+		let mut rng = test::initialize_rng();
+		let db = test::load_database("actor_follower").await;
+		let node = test::empty_node(db.clone(), &mut rng).await;
+		let api = Api { node, db };
+
+		let (address, actor_info) = api
+			.create_identity("test", "Test", None, None, None)
+			.await
+			.unwrap();
+		let (requester, _) = api
+			.create_identity("requester", "Requester", None, None, None)
+			.await
+			.unwrap();
+		let (_, signer) = api.fetch_my_identity(&address).await.unwrap().unwrap();
+		let (_, requester_signer) = api.fetch_my_identity(&requester).await.unwrap().unwrap();
+		let actor_id = actor::Entity::find()
+			.filter(actor::Column::Address.eq(&address))
+			.one(api.db.inner())
+			.await
+			.unwrap()
+			.unwrap()
+			.id;
+		let profile_hash = api
+			.update_profile(&*signer, actor_id, "test", "test", "Test", None, None, None, true)
+			.await
+			.unwrap();
+		let post_hash = api
+			.publish_post(&address, &*signer, "text/plain", "Hello", Vec::new(), &[], None)
+			.await
+			.unwrap();
+		let actor_node = api
+			.node
+			.join_actor_network(&address, &actor_info)
+			.await
+			.unwrap();
+		let node_info = NodeContactInfo {
+			address: NodeAddress::V1(IdType::random(&mut rng)),
+			contact_info: ContactInfo::default(),
+		};
+
+		// An unapproved node only gets the log up to the followers-only post
+		let objects = sync_log(&actor_node, &node_info).await;
+		assert_eq!(objects.len(), 2);
+		assert_eq!(objects[1], profile_hash);
+		assert!(actor_node.process_head_request(&[], &node_info).await.is_none());
+
+		let request = FollowRequestObject::new(
+			requester,
+			address,
+			node_info.address.clone(),
+			&*requester_signer,
+		)
+		.unwrap();
+		api.db.store_follow_request(actor_id, &request).await.unwrap();
+		let request_id = api.db.load_follow_requests(actor_id).await.unwrap()[0].id;
+		let list_hash = api
+			.decide_follow_request(&*signer, actor_id, request_id, true)
+			.await
+			.unwrap()
+			.expect("follow request not found");
+
+		// Once approved, it gets the whole log
+		let objects = sync_log(&actor_node, &node_info).await;
+		assert_eq!(objects.len(), 4);
+		assert_eq!(objects[2], post_hash);
+		let (buffer, _) = actor_node
+			.process_head_request(&[], &node_info)
+			.await
+			.expect("no head response");
+		let response: HeadResponse = binserde::deserialize(&buffer[1..]).unwrap();
+		assert_eq!(response.hash, list_hash);
+	}
+}
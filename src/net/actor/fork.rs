@@ -0,0 +1,172 @@
+//! Resolves the forks in the logs of our identities.
+//!
+//! An identity that is used on more than one device can end up with two
+//! objects with the same sequence number, if both devices publish something
+//! before they have seen the object of the other. Every node settles on the
+//! same one of them: the one that was created first, or the one with the
+//! lowest hash if they were created at the same time. The device that
+//! published the other one removes it, along with the objects that it has
+//! built on top of it, and publishes their payloads again after the head.
+
+use std::sync::Arc;
+
+use log::*;
+
+use super::ActorNode;
+use crate::{common::*, core::*, db, identity::Signer, net::binserde, util};
+
+
+/// Whether the object is the one that is kept, instead of the other object with
+/// the same sequence number.
+pub(super) fn wins_fork(
+	hash: &IdType, object: &BlogchainObject, other_hash: &IdType, other: &BlogchainObject,
+) -> bool {
+	(object.created, hash.to_string()) < (other.created, other_hash.to_string())
+}
+
+impl ActorNode {
+	fn is_signed_by(&self, object: &BlogchainObject, signer: &dyn Signer) -> bool {
+		match &object.delegation {
+			Some(certificate) => certificate.device_key == signer.public(),
+			None => {
+				let key_chain = self.base.interface.key_chain.lock().unwrap();
+				key_chain.key_at(object.sequence) == &signer.public()
+			}
+		}
+	}
+
+	/// Publishes the objects that we've published on the losing side of a fork
+	/// again, if the actor is one of our identities.
+	pub(super) async fn resolve_forks(self: &Arc<Self>) -> db::Result<()> {
+		let signer = match self.load_own_signer().await? {
+			Some(s) => s,
+			None => return Ok(()),
+		};
+		let actor_address = self.actor_address().clone();
+		let sequences = self
+			.db()
+			.perform(move |c| c.fetch_forked_sequences(&actor_address))
+			.await?;
+
+		for sequence in sequences {
+			let actor_address = self.actor_address().clone();
+			let objects = self
+				.db()
+				.perform(move |c| c.fetch_objects_by_sequence(&actor_address, sequence))
+				.await?;
+			// The first one is the one that is kept
+			for (hash, object) in objects.into_iter().skip(1) {
+				if self.is_signed_by(&object, &*signer) {
+					self.republish_branch(hash, object, &*signer).await?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Removes the object and the objects of ours that have been built on top of
+	/// it, and publishes their payloads again after the head.
+	async fn republish_branch(
+		self: &Arc<Self>, hash: IdType, object: BlogchainObject, signer: &dyn Signer,
+	) -> db::Result<()> {
+		let mut branch = vec![(hash, object)];
+		loop {
+			let (last_hash, sequence) = branch
+				.last()
+				.map(|(h, o)| (h.clone(), o.sequence + 1))
+				.unwrap();
+			let actor_address = self.actor_address().clone();
+			let objects = self
+				.db()
+				.perform(move |c| c.fetch_objects_by_sequence(&actor_address, sequence))
+				.await?;
+			match objects
+				.into_iter()
+				.find(|(_, o)| o.previous_hash == last_hash && self.is_signed_by(o, signer))
+			{
+				Some(next) => branch.push(next),
+				None => break,
+			}
+		}
+
+		let actor_address = self.actor_address().clone();
+		let hashes: Vec<_> = branch.iter().rev().map(|(h, _)| h.clone()).collect();
+		let head = self
+			.db()
+			.perform(move |c| {
+				for hash in &hashes {
+					c.delete_object(&actor_address, hash)?;
+				}
+				c.fetch_head(&actor_address)
+			})
+			.await?;
+		let (mut previous_hash, mut sequence) = match head {
+			Some((hash, object, _)) => (hash, object.sequence + 1),
+			None => return Ok(()),
+		};
+
+		let overlay_node = self.base.overlay_node();
+		for (old_hash, old_object) in branch {
+			let created = current_timestamp();
+			let sign_data = ObjectSignData {
+				previous_hash: previous_hash.clone(),
+				sequence,
+				created,
+				payload: &old_object.payload,
+			};
+			let raw_sign_data = binserde::serialize(&sign_data).unwrap();
+			let signature = util::block_in_place(|| signer.sign(&raw_sign_data))?;
+			let hash = signature.hash();
+			let object = BlogchainObject {
+				signature,
+				sequence,
+				previous_hash,
+				created,
+				payload: old_object.payload,
+				delegation: old_object.delegation,
+			};
+			self.store_object(&hash, &object, false).await?;
+			info!(
+				"Published object {} again as object {}, after it lost a fork.",
+				old_hash, hash
+			);
+			self.publish_new_object(&overlay_node, &hash, &object).await;
+
+			previous_hash = hash;
+			sequence += 1;
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, test};
+
+	#[test]
+	fn test_wins_fork() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let object = |created| BlogchainObject {
+			signature: private_key.sign(b"test"),
+			sequence: 1,
+			previous_hash: IdType::default(),
+			created,
+			payload: ObjectPayload::Share(ShareObject {
+				actor_address: ActorAddress::V1(IdType::default()),
+				object_hash: IdType::default(),
+			}),
+			delegation: None,
+		};
+		let (hash1, hash2) = (IdType::random(&mut rng), IdType::random(&mut rng));
+
+		// The oldest object wins, and the lowest hash if they're equally old
+		assert!(wins_fork(&hash1, &object(1), &hash2, &object(2)));
+		assert!(!wins_fork(&hash1, &object(2), &hash2, &object(1)));
+		let first = hash1.to_string() < hash2.to_string();
+		assert_eq!(wins_fork(&hash1, &object(1), &hash2, &object(1)), first);
+		assert_eq!(wins_fork(&hash2, &object(1), &hash1, &object(1)), !first);
+	}
+}
@@ -0,0 +1,133 @@
+//! Spreads new objects through an actor network by gossip.
+//!
+//! Whenever a node learns of an object that is new to it, it forwards the
+//! announcement to a few random peers of the actor network. Those do the same
+//! if the object is new to them as well, until the announcement has travelled
+//! a limited number of hops. That way, an object still reaches most of the
+//! network if the author goes offline right after posting, even when only a
+//! single node got it from the author.
+//!
+//! An object is only forwarded once its files have been downloaded, because
+//! the peers that it is forwarded to download them from us.
+
+use std::sync::Arc;
+
+use futures::future::join_all;
+use log::*;
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+
+use super::ActorNode;
+use crate::{
+	common::*,
+	core::*,
+	net::{message::BlogchainValueType, NodeContactInfo},
+};
+
+
+/// The number of peers that an announcement is forwarded to.
+const GOSSIP_FANOUT: usize = 3;
+/// The maximum number of times an announcement is forwarded.
+pub const GOSSIP_HOPS: u8 = 4;
+
+
+impl ActorNode {
+	/// Forwards the announcement of an object that is new to us to a few
+	/// random peers, leaving out the nodes that are known to have it already,
+	/// and the ones that may not have it. The number of hops that are left is
+	/// given by the node that announced it to us, so it is capped.
+	pub(super) fn gossip_object(
+		self: &Arc<Self>, id: IdType, object: BlogchainObject, hops_left: u8,
+		exclude: Vec<NodeAddress>,
+	) {
+		let hops_left = hops_left.min(GOSSIP_HOPS);
+		if hops_left == 0 {
+			return;
+		}
+
+		let this = self.clone();
+		let name = format!("gossip of object {}", &id);
+		self.base.overlay_node().tasks().spawn(name, async move {
+			let mut candidates = Vec::new();
+			let mut iter = this.base.iter_all_fingers_local_first().await;
+			while let Some(finger) = iter.next().await {
+				match this
+					.may_serve_value(BlogchainValueType::Object as u8, &id, &finger.address)
+					.await
+				{
+					Ok(true) => candidates.push(finger),
+					Ok(false) => {}
+					Err(e) => {
+						error!("Unable to check whether object may be gossiped: {:?}", e);
+						return;
+					}
+				}
+			}
+
+			let peers = pick_peers(candidates, &exclude, &mut OsRng);
+			let futs = peers.into_iter().map(|peer| {
+				let (this, id, object) = (&this, &id, &object);
+				async move {
+					let (mut connection, _) = match this.base.select_connection(&peer, None).await {
+						Some(r) => r,
+						None => return,
+					};
+					let stored = this
+						.exchange_notify_object_on_connection(
+							&mut connection,
+							id,
+							object,
+							hops_left - 1,
+						)
+						.await;
+					// Let the peer download the files of the object from us
+					if stored == Some(true) {
+						this.base
+							.packet_server
+							.handle_connection(connection, None)
+							.await;
+					}
+				}
+			});
+			join_all(futs).await;
+		});
+	}
+}
+
+/// Picks up to `GOSSIP_FANOUT` random peers out of the candidates.
+fn pick_peers(
+	candidates: Vec<NodeContactInfo>, exclude: &[NodeAddress], rng: &mut impl Rng,
+) -> Vec<NodeContactInfo> {
+	let candidates: Vec<NodeContactInfo> = candidates
+		.into_iter()
+		.filter(|c| !exclude.contains(&c.address))
+		.collect();
+	candidates
+		.choose_multiple(rng, GOSSIP_FANOUT)
+		.cloned()
+		.collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{net::ContactInfo, test};
+
+	#[test]
+	fn test_pick_peers() {
+		let mut rng = test::initialize_rng();
+		let candidates: Vec<NodeContactInfo> = (0..10)
+			.map(|_| NodeContactInfo {
+				address: NodeAddress::V1(IdType::random(&mut rng)),
+				contact_info: ContactInfo::default(),
+			})
+			.collect();
+		let exclude: Vec<NodeAddress> = candidates[..8].iter().map(|c| c.address.clone()).collect();
+
+		let peers = pick_peers(candidates.clone(), &[], &mut rng);
+		assert_eq!(peers.len(), GOSSIP_FANOUT);
+		let peers = pick_peers(candidates, &exclude, &mut rng);
+		assert_eq!(peers.len(), 2);
+		assert!(peers.iter().all(|p| !exclude.contains(&p.address)));
+	}
+}
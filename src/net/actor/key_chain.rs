@@ -0,0 +1,373 @@
+//! Keeps track of which key the objects of an actor are signed with.
+//!
+//! An actor starts out with the key in its actor info, which its address is a
+//! hash of. It can hand over to a new key by publishing a key rotation object,
+//! which is signed with the key before it, and which carries a proof that the
+//! new key agreed to take over. Every object after it has to be signed with
+//! the new key. Because the address never changes, followers keep following
+//! the same actor through any number of rotations.
+//!
+//! Objects may also be signed by a device key, if they carry a delegation
+//! certificate that is signed with the key of the actor. Certificates are
+//! checked against the key that is in effect for the object, so rotating the
+//! key revokes all device keys that were issued before.
+//!
+//! Only the first rotation that is known for a sequence number is accepted,
+//! and only once it has been verified with the key before it, so the chain
+//! can't be rewritten without that key.
+//!
+//! A node that starts following an actor doesn't have the older part of its
+//! log, so it asks its peers for the key rotation objects before it verifies
+//! any newer head object. These are checked one after another, each with the
+//! key that the one before it rotated to.
+
+use std::net::SocketAddr;
+
+use log::*;
+
+use super::{
+	verify_object, ActorInterface, ActorNode, ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_REQUEST,
+	ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_RESPONSE,
+};
+use crate::{
+	common::*,
+	core::*,
+	db,
+	identity::ActorPublicKeyV1,
+	net::{
+		binserde,
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+	},
+};
+
+
+/// The maximum number of key rotation objects that are sent in one response.
+const BATCH_SIZE: usize = 64;
+
+
+#[derive(Clone)]
+pub struct KeyChain {
+	original: ActorPublicKeyV1,
+	/// The sequence numbers of the key rotation objects that we know of, and
+	/// the keys that they rotated to, in order.
+	rotations: Vec<(u64, ActorPublicKeyV1)>,
+}
+
+
+impl KeyChain {
+	pub fn new(original: ActorPublicKeyV1, mut rotations: Vec<(u64, ActorPublicKeyV1)>) -> Self {
+		rotations.sort_by_key(|(s, _)| *s);
+		rotations.dedup_by_key(|(s, _)| *s);
+		Self {
+			original,
+			rotations,
+		}
+	}
+
+	/// The key that the object with the given sequence number has to be signed
+	/// with.
+	pub fn key_at(&self, sequence: u64) -> &ActorPublicKeyV1 {
+		match self
+			.rotations
+			.iter()
+			.rev()
+			.find(|(rotated_at, _)| *rotated_at < sequence)
+		{
+			Some((_, key)) => key,
+			None => &self.original,
+		}
+	}
+
+	/// The key that the newest objects are signed with.
+	pub fn current(&self) -> &ActorPublicKeyV1 {
+		match self.rotations.last() {
+			Some((_, key)) => key,
+			None => &self.original,
+		}
+	}
+
+	/// The sequence number of the last key rotation that we know of.
+	pub fn last_rotation(&self) -> Option<u64> { self.rotations.last().map(|(s, _)| *s) }
+
+	pub fn insert(&mut self, sequence: u64, key: ActorPublicKeyV1) -> bool {
+		match self.rotations.binary_search_by_key(&sequence, |(s, _)| *s) {
+			Ok(_) => false,
+			Err(index) => {
+				self.rotations.insert(index, (sequence, key));
+				true
+			}
+		}
+	}
+
+	/// Checks the object with the key that it has to be signed with, and if it
+	/// rotates the key, whether the new key agreed to that.
+	pub fn verify(
+		&self, actor_address: &ActorAddress, id: &IdType, object: &BlogchainObject,
+	) -> bool {
+		let actor_key = self.key_at(object.sequence);
+		let signing_key = match &object.delegation {
+			None => actor_key,
+			Some(certificate) => {
+				if !certificate.verify(actor_address, actor_key) {
+					warn!("Object {} is invalid: delegation certificate is incorrect.", id);
+					return false;
+				}
+				if !certificate.allows(&object.payload) {
+					warn!("Object {} is invalid: device key is not allowed to sign it.", id);
+					return false;
+				}
+				&certificate.device_key
+			}
+		};
+		if !verify_object(id, object, signing_key) {
+			return false;
+		}
+		if let ObjectPayload::KeyRotation(rotation) = &object.payload {
+			if !rotation.verify_proof(actor_address, object.sequence) {
+				warn!("Object {} is invalid: key rotation proof is incorrect.", id);
+				return false;
+			}
+		}
+		true
+	}
+}
+
+impl ActorInterface {
+	pub(super) fn verify_object(&self, id: &IdType, object: &BlogchainObject) -> bool {
+		self.key_chain
+			.lock()
+			.unwrap()
+			.verify(&self.actor_address, id, object)
+	}
+
+	/// Starts requiring the new key for the objects after the given one, if it
+	/// is a valid key rotation object.
+	pub(super) fn remember_key_rotation(&self, id: &IdType, object: &BlogchainObject) {
+		if let ObjectPayload::KeyRotation(rotation) = &object.payload {
+			let mut key_chain = self.key_chain.lock().unwrap();
+			if key_chain.verify(&self.actor_address, id, object)
+				&& key_chain.insert(object.sequence, rotation.new_public_key.clone())
+			{
+				info!(
+					"Actor {} rotated to a new key at object {}.",
+					&self.actor_address, object.sequence
+				);
+			}
+		}
+	}
+}
+
+impl ActorNode {
+	async fn exchange_key_rotations_on_connection(
+		&self, connection: &mut Connection, min_sequence: u64,
+	) -> Option<KeyRotationsResponse> {
+		let request = KeyRotationsRequest { min_sequence };
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<_> = binserde::deserialize_sstp(&raw_response);
+		self.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await
+	}
+
+	pub(super) async fn process_key_rotations_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: KeyRotationsRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed key rotations request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let actor_address = self.actor_address().clone();
+		let result = self.db().perform(move |c| {
+			let mut rotations = Vec::new();
+			for (sequence, _) in c.fetch_key_rotations(&actor_address)? {
+				if sequence < request.min_sequence {
+					continue;
+				}
+				if rotations.len() == BATCH_SIZE {
+					break;
+				}
+				if let Some((hash, object, _)) =
+					c.fetch_object_by_sequence(&actor_address, sequence)?
+				{
+					rotations.push((hash, object));
+				}
+			}
+			Ok(rotations)
+		});
+		let rotations = match result.await {
+			Ok(r) => r,
+			Err(e) => {
+				error!("Unable to load key rotations: {:?}", e);
+				return None;
+			}
+		};
+
+		let response = KeyRotationsResponse {
+			rotations: rotations.into(),
+		};
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_KEY_ROTATIONS_RESPONSE, &response)
+	}
+
+	/// Learns the key rotations that the peer knows of, after the last one that
+	/// we know of.
+	/// Returns whether any new key rotation has been learned.
+	pub(super) async fn synchronize_key_rotations_on_connection(
+		&self, connection: &mut Connection,
+	) -> db::Result<bool> {
+		let mut learned = false;
+		loop {
+			let min_sequence = self
+				.base
+				.interface
+				.key_chain
+				.lock()
+				.unwrap()
+				.last_rotation()
+				.map(|s| s + 1)
+				.unwrap_or(0);
+			let response = match self
+				.exchange_key_rotations_on_connection(connection, min_sequence)
+				.await
+			{
+				Some(r) => r,
+				None => return Ok(learned),
+			};
+
+			let rotations: Vec<_> = response.rotations.into();
+			let count = rotations.len();
+			for (hash, object) in rotations {
+				// Every rotation is verified with the key that the one before it
+				// rotated to, so they have to come in order
+				let is_valid = object.sequence >= min_sequence
+					&& matches!(object.payload, ObjectPayload::KeyRotation(_))
+					&& self.base.interface.verify_object(&hash, &object);
+				if !is_valid {
+					warn!("Invalid key rotation received from {}.", connection.peer_address());
+					return Ok(learned);
+				}
+				self.store_object(&hash, &object, false).await?;
+				learned = true;
+			}
+			if count < BATCH_SIZE {
+				return Ok(learned);
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, net::binserde, test};
+
+	fn sign(
+		private_key: &ActorPrivateKeyV1, sequence: u64, payload: ObjectPayload,
+	) -> (IdType, BlogchainObject) {
+		let sign_data = ObjectSignData {
+			sequence,
+			previous_hash: IdType::default(),
+			created: 0,
+			payload: &payload,
+		};
+		let signature = private_key.sign(&binserde::serialize(&sign_data).unwrap());
+		let object = BlogchainObject {
+			signature: signature.clone(),
+			sequence,
+			previous_hash: IdType::default(),
+			created: 0,
+			payload,
+			delegation: None,
+		};
+		(signature.hash(), object)
+	}
+
+	#[test]
+	fn test_key_chain() {
+		let mut rng = test::initialize_rng();
+		let old_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let new_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let share = || {
+			ObjectPayload::Share(ShareObject {
+				actor_address: address.clone(),
+				object_hash: IdType::default(),
+			})
+		};
+
+		// The rotation is signed with the old key, and proven by the new one
+		let mut chain = KeyChain::new(old_key.public(), Vec::new());
+		let rotation = KeyRotationObject::new(&address, 3, &new_key);
+		let (id, object) = sign(&old_key, 3, ObjectPayload::KeyRotation(rotation.clone()));
+		assert!(chain.verify(&address, &id, &object));
+		let (id, object) = sign(&new_key, 3, ObjectPayload::KeyRotation(rotation.clone()));
+		assert!(!chain.verify(&address, &id, &object));
+		let other_rotation = KeyRotationObject::new(&address, 2, &new_key);
+		let (id, object) = sign(&old_key, 3, ObjectPayload::KeyRotation(other_rotation));
+		assert!(!chain.verify(&address, &id, &object));
+
+		// Objects after the rotation need the new key, the ones before still the
+		// old key
+		assert!(chain.insert(3, new_key.public()));
+		assert!(!chain.insert(3, old_key.public()));
+		let (id, object) = sign(&new_key, 4, share());
+		assert!(chain.verify(&address, &id, &object));
+		let (id, object) = sign(&old_key, 4, share());
+		assert!(!chain.verify(&address, &id, &object));
+		let (id, object) = sign(&old_key, 2, share());
+		assert!(chain.verify(&address, &id, &object));
+		assert_eq!(chain.current(), &new_key.public());
+	}
+
+	#[test]
+	fn test_delegation() {
+		let mut rng = test::initialize_rng();
+		let actor_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let device_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let mut chain = KeyChain::new(actor_key.public(), Vec::new());
+		let certificate = DelegationCertificate::new(
+			&address,
+			device_key.public(),
+			DELEGATION_SCOPE_SHARE,
+			&actor_key,
+		)
+		.unwrap();
+		let share = ObjectPayload::Share(ShareObject {
+			actor_address: address.clone(),
+			object_hash: IdType::default(),
+		});
+
+		// The device key can sign what it has been given the scope for
+		let (id, mut object) = sign(&device_key, 1, share.clone());
+		assert!(!chain.verify(&address, &id, &object));
+		object.delegation = Some(certificate.clone());
+		assert!(chain.verify(&address, &id, &object));
+
+		// But nothing else, and never a key rotation
+		let rotation = KeyRotationObject::new(&address, 2, &device_key);
+		let (id, mut object) = sign(&device_key, 2, ObjectPayload::KeyRotation(rotation));
+		object.delegation = Some(certificate.clone());
+		assert!(!chain.verify(&address, &id, &object));
+
+		// Nor after the key of the actor has been rotated
+		let new_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		chain.insert(2, new_key.public());
+		let (id, mut object) = sign(&device_key, 3, share);
+		object.delegation = Some(certificate);
+		assert!(!chain.verify(&address, &id, &object));
+	}
+}
@@ -0,0 +1,344 @@
+//! Fills the gaps in the log of an actor in bulk.
+//!
+//! The objects of an actor form a hash chain: every object is signed along
+//! with the hash of the object before it. The hash of an object therefore
+//! commits to the whole log up to it, like the root of a Merkle log does. To
+//! catch up, a node sends the hashes of some of the objects that it has, at
+//! sequence numbers that lie exponentially further back, like the levels of a
+//! skip list. The other node picks the latest one that is part of its own log
+//! as well, and responds with the objects that follow it. That way, the point
+//! from which objects are missing is found in a single exchange, no matter how
+//! large the gap is, and the gap is then filled in batches instead of one
+//! object at a time.
+
+use std::{cmp::max, net::SocketAddr};
+
+use log::*;
+
+use super::{
+	key_chain::KeyChain, ActorNode, ACTOR_MESSAGE_TYPE_SYNC_LOG_REQUEST,
+	ACTOR_MESSAGE_TYPE_SYNC_LOG_RESPONSE,
+};
+use crate::{
+	common::*,
+	core::*,
+	db,
+	net::{
+		binserde,
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+		NodeContactInfo,
+	},
+};
+
+
+/// The maximum number of objects that are sent in one response.
+const BATCH_SIZE: usize = 64;
+/// The maximum number of checkpoints that are sent in one request.
+const CHECKPOINT_LIMIT: usize = 64;
+
+
+impl ActorNode {
+	async fn exchange_sync_log_on_connection(
+		&self, connection: &mut Connection, checkpoints: Vec<(u64, IdType)>, min_sequence: u64,
+	) -> Option<SyncLogResponse> {
+		let request = SyncLogRequest {
+			checkpoints: checkpoints.into(),
+			min_sequence,
+		};
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_SYNC_LOG_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<_> = binserde::deserialize_sstp(&raw_response);
+		self.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await
+	}
+
+	pub(super) async fn process_sync_log_request(
+		&self, buffer: &[u8], addr: &SocketAddr, node_info: &NodeContactInfo,
+	) -> MessageProcessorResult {
+		let request: SyncLogRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed sync log request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		let actor_address = self.actor_address().clone();
+		let result = self.db().perform(move |c| {
+			let actor_address = &actor_address;
+
+			// The checkpoints are ordered from new to old
+			let mut common_sequence = None;
+			for (sequence, hash) in request.checkpoints.iter() {
+				if let Some((our_hash, ..)) = c.fetch_object_by_sequence(actor_address, *sequence)? {
+					if &our_hash == hash {
+						common_sequence = Some(*sequence);
+						break;
+					}
+				}
+			}
+
+			let mut sequence = match common_sequence {
+				Some(s) => max(s + 1, request.min_sequence),
+				None => request.min_sequence,
+			};
+			let mut objects = Vec::with_capacity(BATCH_SIZE);
+			while objects.len() < BATCH_SIZE {
+				match c.fetch_object_by_sequence(actor_address, sequence)? {
+					None => break,
+					Some((hash, object, _)) => objects.push((hash, object)),
+				}
+				sequence += 1;
+			}
+			Ok((common_sequence, objects))
+		});
+		let (common_sequence, mut objects) = match result.await {
+			Ok(r) => r,
+			Err(e) => {
+				error!("Unable to load log for sync log request: {:?}", e);
+				return None;
+			}
+		};
+
+		// The log is cut off at the first object that the node may not have, so
+		// that the objects still follow each other without any gaps.
+		let mut servable = 0;
+		for (hash, _) in &objects {
+			match self
+				.may_serve_value(BlogchainValueType::Object as u8, hash, &node_info.address)
+				.await
+			{
+				Ok(true) => servable += 1,
+				Ok(false) => break,
+				Err(e) => {
+					error!("Unable to check whether object may be served: {:?}", e);
+					return None;
+				}
+			}
+		}
+		objects.truncate(servable);
+
+		let response = SyncLogResponse {
+			common_sequence,
+			objects: objects.into(),
+		};
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_SYNC_LOG_RESPONSE, &response)
+	}
+
+	/// Downloads the objects that are missing right before the given object,
+	/// in batches, going back no further than `min_sequence`. Objects that were
+	/// created before `min_created` are verified as part of the log, but not
+	/// stored.
+	/// Returns whether the gap has been closed. If not, the peer may not have
+	/// been able to help, and the gap may still need to be filled in another
+	/// way.
+	pub(super) async fn synchronize_log_on_connection(
+		&self, connection: &mut Connection, up_to_object: &BlogchainObject, min_sequence: u64,
+		min_created: u64,
+	) -> db::Result<bool> {
+		if up_to_object.sequence == 0 {
+			return Ok(true);
+		}
+
+		let actor_address = self.actor_address().clone();
+		let up_to_sequence = up_to_object.sequence;
+		let mut checkpoints = self
+			.db()
+			.perform(move |c| {
+				let mut checkpoints = Vec::new();
+				for sequence in checkpoint_sequences(up_to_sequence) {
+					if let Some((hash, ..)) =
+						c.fetch_object_by_sequence(&actor_address, sequence)?
+					{
+						checkpoints.push((sequence, hash));
+					}
+				}
+				Ok(checkpoints)
+			})
+			.await?;
+		// Nothing is missing if we have the object right before it
+		if checkpoints.first().map(|(s, _)| *s) == Some(up_to_object.sequence - 1) {
+			return Ok(true);
+		}
+
+		let mut synchronized_up_to = None;
+		loop {
+			let response = match self
+				.exchange_sync_log_on_connection(connection, checkpoints.clone(), min_sequence)
+				.await
+			{
+				Some(r) => r,
+				None => return Ok(false),
+			};
+			let start = response
+				.common_sequence
+				.and_then(|s| checkpoints.iter().find(|(cs, _)| *cs == s));
+			let objects: Vec<_> = Into::<Vec<_>>::into(response.objects)
+				.into_iter()
+				.filter(|(_, o)| o.sequence < up_to_object.sequence)
+				.collect();
+			let (last_hash, last_sequence) = match objects.last() {
+				Some((hash, object)) => (hash.clone(), object.sequence),
+				None => return Ok(false),
+			};
+
+			// Every batch after the first one has to continue where the last one
+			// left off
+			if let Some(s) = synchronized_up_to {
+				if start.map(|(cs, _)| *cs) != Some(s) || objects[0].1.sequence != s + 1 {
+					return Ok(false);
+				}
+			}
+			let is_valid = {
+				let key_chain = self.base.interface.key_chain.lock().unwrap();
+				verify_log(start, &objects, self.actor_address(), &key_chain)
+			};
+			if !is_valid {
+				warn!("Invalid log received from {}.", connection.peer_address());
+				return Ok(false);
+			}
+			for (hash, object) in objects.iter().filter(|(_, o)| o.created >= min_created) {
+				self.store_object(hash, object, false).await?;
+			}
+
+			if last_sequence + 1 == up_to_object.sequence {
+				return Ok(up_to_object.previous_hash == last_hash);
+			}
+			synchronized_up_to = Some(last_sequence);
+			checkpoints = vec![(last_sequence, last_hash)];
+		}
+	}
+}
+
+/// Returns the sequence numbers of the objects that are offered as
+/// checkpoints for a gap right before the given sequence number: the one
+/// right before it, and then ones that lie twice as far back every time.
+fn checkpoint_sequences(before: u64) -> Vec<u64> {
+	let mut sequences = Vec::new();
+	let mut offset = 1u64;
+	while offset <= before && sequences.len() < CHECKPOINT_LIMIT {
+		sequences.push(before - offset);
+		offset = match offset.checked_mul(2) {
+			Some(o) => o,
+			None => break,
+		};
+	}
+	sequences
+}
+
+/// Checks whether all objects are valid, and whether they form a chain that
+/// continues from the checkpoint, if they start right after it. The key
+/// rotations in the log are taken into account for the objects after them.
+fn verify_log(
+	start: Option<&(u64, IdType)>, objects: &[(IdType, BlogchainObject)],
+	actor_address: &ActorAddress, key_chain: &KeyChain,
+) -> bool {
+	let mut key_chain = key_chain.clone();
+	if let Some((sequence, hash)) = start {
+		let first = &objects[0].1;
+		if first.sequence <= *sequence
+			|| (first.sequence == sequence + 1 && &first.previous_hash != hash)
+		{
+			return false;
+		}
+	}
+	for (i, (hash, object)) in objects.iter().enumerate() {
+		if i > 0 {
+			let (previous_hash, previous) = &objects[i - 1];
+			if object.sequence != previous.sequence + 1 || &object.previous_hash != previous_hash {
+				return false;
+			}
+		}
+		if !key_chain.verify(actor_address, hash, object) {
+			return false;
+		}
+		if let ObjectPayload::KeyRotation(rotation) = &object.payload {
+			key_chain.insert(object.sequence, rotation.new_public_key.clone());
+		}
+	}
+	true
+}
+
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, test};
+
+	#[test]
+	fn test_checkpoint_sequences() {
+		assert_eq!(checkpoint_sequences(0), Vec::<u64>::new());
+		assert_eq!(checkpoint_sequences(1), vec![0]);
+		assert_eq!(checkpoint_sequences(10), vec![9, 8, 6, 2]);
+		assert_eq!(checkpoint_sequences(16), vec![15, 14, 12, 8, 0]);
+		assert_eq!(checkpoint_sequences(u64::MAX).len(), CHECKPOINT_LIMIT);
+	}
+
+	#[test]
+	fn test_verify_log() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+
+		// Build a chain of a few objects
+		let mut log = Vec::new();
+		let mut previous_hash = IdType::default();
+		for sequence in 0..5 {
+			let payload = ObjectPayload::Share(ShareObject {
+				actor_address: ActorAddress::V1(IdType::random(&mut rng)),
+				object_hash: IdType::random(&mut rng),
+			});
+			let created = rng.next_u32() as u64;
+			let sign_data = ObjectSignData {
+				sequence,
+				previous_hash: previous_hash.clone(),
+				created,
+				payload: &payload,
+			};
+			let signature = private_key.sign(&binserde::serialize(&sign_data).unwrap());
+			let hash = signature.hash();
+			log.push((
+				hash.clone(),
+				BlogchainObject {
+					signature,
+					sequence,
+					previous_hash,
+					created,
+					payload,
+					delegation: None,
+				},
+			));
+			previous_hash = hash;
+		}
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let key_chain = KeyChain::new(private_key.public(), Vec::new());
+		let checkpoint = (0, log[0].0.clone());
+
+		assert!(verify_log(Some(&checkpoint), &log[1..], &address, &key_chain));
+		assert!(verify_log(None, &log[2..], &address, &key_chain));
+		// The objects may start further on than right after the checkpoint
+		assert!(verify_log(Some(&checkpoint), &log[3..], &address, &key_chain));
+		// But they may not overlap with it
+		assert!(!verify_log(Some(&checkpoint), &log[0..], &address, &key_chain));
+		// Nor skip any object
+		let gapped = vec![log[1].clone(), log[3].clone()];
+		assert!(!verify_log(Some(&checkpoint), &gapped, &address, &key_chain));
+		// Nor continue from a different object
+		let other_checkpoint = (0, IdType::random(&mut rng));
+		assert!(!verify_log(Some(&other_checkpoint), &log[1..], &address, &key_chain));
+		// Nor be signed by someone else
+		let other_private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let other_key_chain = KeyChain::new(other_private_key.public(), Vec::new());
+		assert!(!verify_log(None, &log[1..], &address, &other_key_chain));
+	}
+}
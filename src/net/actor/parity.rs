@@ -0,0 +1,501 @@
+//! Erasure-coded redundancy for the blocks of files.
+//!
+//! Every node in the network of an actor tries to store all blocks of its
+//! files, but when the nodes that have a block all leave, the file can't be
+//! downloaded completely anymore. To guard against that, the publisher of a
+//! file can compute Reed-Solomon parity blocks for it, and hand them out to
+//! distinct nodes in the network of the actor.
+//!
+//! The blocks of a file are grouped into stripes of `STRIPE_SIZE` blocks. Every
+//! data block of a stripe is prefixed with its length and padded to the length
+//! of the largest block in the stripe, which makes up a shard. The parity
+//! shards are computed over those. Any `STRIPE_SIZE` shards of a stripe are
+//! enough to reconstruct the others, regardless of how many parity shards
+//! there are. Parity shards can't be verified on their own, but the blocks
+//! that are reconstructed from them are verified against the block list of the
+//! file, like any other block.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use log::*;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use tokio::sync::mpsc;
+
+use super::{
+	download::DownloadProgress, ActorNode, ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST,
+	ACTOR_MESSAGE_TYPE_FIND_PARITY_RESPONSE, ACTOR_MESSAGE_TYPE_STORE_PARITY_REQUEST,
+	ACTOR_MESSAGE_TYPE_STORE_PARITY_RESPONSE,
+};
+use crate::{
+	common::*,
+	db::{self, PersistenceHandle, BLOCK_SIZE},
+	net::{
+		binserde,
+		message::*,
+		sstp::{self, Connection, MessageProcessorResult},
+		NodeContactInfo,
+	},
+};
+
+
+/// The number of data blocks in a stripe.
+const STRIPE_SIZE: usize = 8;
+/// The maximum number of parity blocks per stripe.
+const MAX_PARITY_BLOCKS: u8 = 8;
+/// The size of the length prefix of every data shard.
+const SHARD_HEADER_SIZE: usize = 4;
+
+
+impl ActorNode {
+	/// Computes the parity blocks of the given files in the background, and
+	/// hands them out to other nodes in the network. Does nothing if erasure
+	/// coding isn't enabled.
+	pub fn distribute_parity(self: &Arc<Self>, file_hashes: Vec<IdType>) {
+		let parity_blocks = self
+			.base
+			.interface
+			.overlay_node
+			.parity_blocks
+			.min(MAX_PARITY_BLOCKS);
+		if parity_blocks == 0 {
+			return;
+		}
+
+		let this = self.clone();
+		let name = format!("parity distribution for actor {}", self.actor_address());
+		self.base.overlay_node().tasks().spawn(name, async move {
+			for file_hash in &file_hashes {
+				if let Err(e) = this.distribute_file_parity(file_hash, parity_blocks).await {
+					this.db().observe_error(&e);
+					error!("Unable to distribute parity blocks of file {}: {}", file_hash, e);
+				}
+			}
+		});
+	}
+
+	async fn distribute_file_parity(
+		&self, file_hash: &IdType, parity_blocks: u8,
+	) -> db::Result<()> {
+		let file = match self.db().find_file(file_hash).await? {
+			Some((_, f)) => f,
+			None => return Ok(()),
+		};
+		let mut connections = self.connect_to_peers(parity_blocks as usize).await;
+		if connections.len() == 0 {
+			warn!("No peers to hand out the parity blocks of file {} to.", file_hash);
+			return Ok(());
+		}
+
+		for (stripe, block_hashes) in file.blocks.chunks(STRIPE_SIZE).enumerate() {
+			let mut blocks = Vec::with_capacity(block_hashes.len());
+			for hash in block_hashes {
+				match self.db().load_block(hash).await? {
+					Some(data) => blocks.push(data),
+					None => {
+						warn!("Missing block {} for computing parity blocks.", hash);
+						return Ok(());
+					}
+				}
+			}
+
+			// Every parity block goes to another peer, as far as there are enough of them
+			let parity = encode_stripe(&blocks, parity_blocks);
+			for (i, data) in parity.into_iter().enumerate() {
+				let shard = (block_hashes.len() + i) as u8;
+				let connection = &mut connections[i % connections.len()];
+				let stored = self
+					.exchange_store_parity_on_connection(
+						connection,
+						file_hash,
+						stripe as u32,
+						shard,
+						data,
+					)
+					.await;
+				if !stored {
+					warn!(
+						"Peer {} didn't store parity block {} of stripe {} of file {}.",
+						connection.their_node_id(),
+						shard,
+						stripe,
+						file_hash
+					);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	async fn exchange_store_parity_on_connection(
+		&self, connection: &mut Connection, file_hash: &IdType, stripe: u32, shard: u8,
+		data: Vec<u8>,
+	) -> bool {
+		let request = StoreParityRequest {
+			file_hash: file_hash.clone(),
+			stripe,
+			shard,
+			data: data.into(),
+		};
+		let raw_response = match self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_STORE_PARITY_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await
+		{
+			Some(r) => r,
+			None => return false,
+		};
+		let result: sstp::Result<StoreParityResponse> = binserde::deserialize_sstp(&raw_response);
+		self.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await
+			.map(|r| r.stored)
+			.unwrap_or(false)
+	}
+
+	async fn exchange_find_parity_on_connection(
+		&self, connection: &mut Connection, file_hash: &IdType, stripe: u32, shard: u8,
+	) -> Option<Vec<u8>> {
+		let request = FindParityRequest {
+			file_hash: file_hash.clone(),
+			stripe,
+			shard,
+		};
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<FindParityResponse> = binserde::deserialize_sstp(&raw_response);
+		let response = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		response.data.map(|d| d.into())
+	}
+
+	pub(super) async fn process_store_parity_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: StoreParityRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed store parity request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		// Only keep parity blocks of the files of this actor that we know of
+		let stored = if request.data.len() > BLOCK_SIZE + SHARD_HEADER_SIZE {
+			false
+		} else {
+			match self.store_parity(request).await {
+				Ok(stored) => stored,
+				Err(e) => {
+					self.db().observe_error(&e);
+					error!("Unable to store parity block: {:?}", e);
+					false
+				}
+			}
+		};
+		let response = StoreParityResponse { stored };
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_STORE_PARITY_RESPONSE, &response)
+	}
+
+	async fn store_parity(&self, request: StoreParityRequest) -> db::Result<bool> {
+		let file = match self.db().find_file(&request.file_hash).await? {
+			Some((_, f)) => f,
+			None => return Ok(false),
+		};
+		if !is_parity_shard(file.blocks.len(), request.stripe, request.shard) {
+			return Ok(false);
+		}
+		self.db()
+			.store_parity_block(
+				&request.file_hash,
+				request.stripe,
+				request.shard,
+				request.data.into(),
+			)
+			.await?;
+		Ok(true)
+	}
+
+	pub(super) async fn process_find_parity_request(
+		&self, buffer: &[u8], addr: &SocketAddr, node_info: &NodeContactInfo,
+	) -> MessageProcessorResult {
+		let request: FindParityRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed find parity request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		// The parity of a file reveals its contents just as well as the file itself
+		match self
+			.may_serve_value(
+				BlogchainValueType::File as u8,
+				&request.file_hash,
+				&node_info.address,
+			)
+			.await
+		{
+			Ok(true) => {}
+			Ok(false) => {
+				let response = FindParityResponse { data: None };
+				return self
+					.base
+					.simple_result(ACTOR_MESSAGE_TYPE_FIND_PARITY_RESPONSE, &response);
+			}
+			Err(e) => {
+				self.db().observe_error(&e);
+				error!("Unable to check whether parity may be served: {:?}", e);
+				return None;
+			}
+		}
+
+		let data = match self
+			.db()
+			.load_parity_block(&request.file_hash, request.stripe, request.shard)
+			.await
+		{
+			Ok(d) => d,
+			Err(e) => {
+				self.db().observe_error(&e);
+				error!("Unable to load parity block: {:?}", e);
+				return None;
+			}
+		};
+		let response = FindParityResponse {
+			data: data.map(|d| d.into()),
+		};
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_FIND_PARITY_RESPONSE, &response)
+	}
+
+	/// Tries to reconstruct the given blocks of a file from the parity blocks
+	/// that other nodes have. The reconstructed blocks are handed to the
+	/// download, like any other block that has been found.
+	pub(super) async fn reconstruct_blocks(
+		&self, file_id: i64, file_hash: &IdType, lost: Vec<(usize, IdType)>,
+		progress: &DownloadProgress, tx: &mpsc::Sender<(usize, Vec<u8>)>,
+	) -> db::Result<()> {
+		let file = match self.db().find_file(file_hash).await? {
+			Some((_, f)) => f,
+			None => return Ok(()),
+		};
+		let mut connections = self.connect_to_peers(MAX_PARITY_BLOCKS as usize).await;
+		if connections.len() == 0 {
+			return Ok(());
+		}
+
+		let mut stripes: Vec<usize> = lost.iter().map(|(index, _)| index / STRIPE_SIZE).collect();
+		stripes.sort();
+		stripes.dedup();
+		for stripe in stripes {
+			let start = stripe * STRIPE_SIZE;
+			let block_hashes = &file.blocks[start..file.blocks.len().min(start + STRIPE_SIZE)];
+
+			// Collect the blocks of the stripe that we have
+			let mut blocks = Vec::with_capacity(block_hashes.len());
+			let mut missing = 0;
+			for hash in block_hashes {
+				let block = self.db().load_block(hash).await?;
+				if block.is_none() {
+					missing += 1;
+				}
+				blocks.push(block);
+			}
+
+			// Find as many parity blocks as there are blocks missing
+			let mut parity = vec![None; MAX_PARITY_BLOCKS as usize];
+			let mut found = 0;
+			'search: for i in 0..MAX_PARITY_BLOCKS {
+				let shard = (block_hashes.len() as u8) + i;
+				for connection in connections.iter_mut() {
+					if let Some(data) = self
+						.exchange_find_parity_on_connection(
+							connection,
+							file_hash,
+							stripe as u32,
+							shard,
+						)
+						.await
+					{
+						parity[i as usize] = Some(data);
+						found += 1;
+						if found == missing {
+							break 'search;
+						}
+						break;
+					}
+				}
+			}
+			if found < missing {
+				warn!(
+					"Not enough parity blocks to reconstruct stripe {} of file {}.",
+					stripe, file_hash
+				);
+				continue;
+			}
+
+			let restored = match reconstruct_stripe(blocks, parity) {
+				Some(r) => r,
+				None => {
+					warn!("Unable to reconstruct stripe {} of file {}.", stripe, file_hash);
+					continue;
+				}
+			};
+			for (i, data) in restored.into_iter().enumerate() {
+				let index = start + i;
+				if !lost.iter().any(|(l, _)| *l == index) {
+					continue;
+				}
+				let hash = &block_hashes[i];
+				if !self.verify_block(hash, &data) {
+					warn!("Reconstructed block {} of file {} is invalid.", hash, file_hash);
+					continue;
+				}
+				self.collect_downloaded_block(file_id, index, hash, data, progress, tx)
+					.await;
+			}
+		}
+		Ok(())
+	}
+
+	pub(super) async fn connect_to_peers(&self, limit: usize) -> Vec<Box<Connection>> {
+		let mut connections = Vec::with_capacity(limit);
+		let mut iter = self.base.iter_all_fingers_top_down(0).await;
+		while let Some(finger) = iter.next().await {
+			if let Some((connection, _)) = self.base.select_direct_connection(&finger, None).await {
+				connections.push(connection);
+				if connections.len() == limit {
+					break;
+				}
+			}
+		}
+		connections
+	}
+}
+
+
+/// Computes the parity shards of the given blocks.
+fn encode_stripe(blocks: &[Vec<u8>], parity_blocks: u8) -> Vec<Vec<u8>> {
+	let shard_size = blocks.iter().map(|b| b.len()).max().unwrap_or(0) + SHARD_HEADER_SIZE;
+	let mut shards: Vec<Vec<u8>> = blocks.iter().map(|b| encode_shard(b, shard_size)).collect();
+	shards.extend((0..parity_blocks).map(|_| vec![0u8; shard_size]));
+
+	let encoder = ReedSolomon::new(blocks.len(), parity_blocks as usize).unwrap();
+	encoder.encode(&mut shards).unwrap();
+	shards.split_off(blocks.len())
+}
+
+/// Restores the missing blocks of a stripe from the parity shards.
+/// Returns all data blocks of the stripe, or `None` if there weren't enough
+/// shards, or if they didn't fit together.
+fn reconstruct_stripe(
+	blocks: Vec<Option<Vec<u8>>>, parity: Vec<Option<Vec<u8>>>,
+) -> Option<Vec<Vec<u8>>> {
+	let shard_size = parity.iter().flatten().next()?.len();
+	if parity.iter().flatten().any(|p| p.len() != shard_size) {
+		return None;
+	}
+
+	let data_count = blocks.len();
+	let mut shards: Vec<Option<Vec<u8>>> = blocks
+		.into_iter()
+		.map(|b| b.map(|data| encode_shard(&data, shard_size)))
+		.collect();
+	if shards.iter().flatten().any(|s| s.len() != shard_size) {
+		return None;
+	}
+	let parity_count = parity.len();
+	shards.extend(parity);
+
+	let decoder = ReedSolomon::new(data_count, parity_count).ok()?;
+	decoder.reconstruct_data(&mut shards).ok()?;
+	shards
+		.into_iter()
+		.take(data_count)
+		.map(|s| decode_shard(&s?))
+		.collect()
+}
+
+fn encode_shard(block: &[u8], shard_size: usize) -> Vec<u8> {
+	let mut shard = Vec::with_capacity(shard_size);
+	shard.extend(&(block.len() as u32).to_le_bytes());
+	shard.extend(block);
+	shard.resize(shard_size, 0);
+	shard
+}
+
+fn decode_shard(shard: &[u8]) -> Option<Vec<u8>> {
+	if shard.len() < SHARD_HEADER_SIZE {
+		return None;
+	}
+	let length = u32::from_le_bytes(shard[..SHARD_HEADER_SIZE].try_into().unwrap()) as usize;
+	shard
+		.get(SHARD_HEADER_SIZE..(SHARD_HEADER_SIZE + length))
+		.map(|b| b.to_vec())
+}
+
+/// Whether the shard is one of the parity shards that a file with the given
+/// number of blocks can have in the stripe.
+fn is_parity_shard(block_count: usize, stripe: u32, shard: u8) -> bool {
+	let stripe_start = stripe as usize * STRIPE_SIZE;
+	if stripe_start >= block_count {
+		return false;
+	}
+	let data_shards = (block_count - stripe_start).min(STRIPE_SIZE);
+	let shard = shard as usize;
+	shard >= data_shards && shard < data_shards + MAX_PARITY_BLOCKS as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_erasure_coding() {
+		let blocks = vec![vec![1u8; 100], vec![2u8; 100], vec![3u8; 42]];
+		let parity = encode_stripe(&blocks, 3);
+		assert_eq!(parity.len(), 3);
+		assert!(parity.iter().all(|p| p.len() == 100 + SHARD_HEADER_SIZE));
+
+		// Lose two blocks and one parity block, and pad the parity blocks to the
+		// maximum, like a node that doesn't know how many there are does
+		let mut parity: Vec<_> = parity.into_iter().map(Some).collect();
+		parity[0] = None;
+		parity.resize(MAX_PARITY_BLOCKS as usize, None);
+		let restored =
+			reconstruct_stripe(vec![None, Some(blocks[1].clone()), None], parity).unwrap();
+		assert_eq!(restored, blocks);
+
+		// Too few shards left
+		let parity = vec![None; MAX_PARITY_BLOCKS as usize];
+		assert!(reconstruct_stripe(vec![None, Some(blocks[1].clone()), None], parity).is_none());
+	}
+
+	#[test]
+	fn test_is_parity_shard() {
+		// A file of 11 blocks has a full stripe and a stripe of 3 blocks
+		assert!(is_parity_shard(11, 0, 8));
+		assert!(is_parity_shard(11, 0, 15));
+		assert!(!is_parity_shard(11, 0, 7));
+		assert!(!is_parity_shard(11, 0, 16));
+		assert!(is_parity_shard(11, 1, 3));
+		assert!(is_parity_shard(11, 1, 10));
+		assert!(!is_parity_shard(11, 1, 2));
+		assert!(!is_parity_shard(11, 1, 11));
+		assert!(!is_parity_shard(11, 2, 8));
+	}
+}
@@ -0,0 +1,372 @@
+//! Estimates how many nodes take part in the network of an actor, without
+//! keeping a list of those nodes.
+//!
+//! Every node of an actor network keeps a HyperLogLog sketch, and adds itself
+//! to it with a hash of its node ID, the actor address and the current epoch.
+//! Because of that hash, a sketch can't be correlated with the sketches of
+//! other actor networks or of other epochs. A sketch doesn't list the nodes
+//! that it has seen, but anyone who knows the ID of a node can check whether
+//! a sketch is consistent with that node having been added to it, so it only
+//! hides which nodes take part to a limited degree.
+//!
+//! The nodes regularly ask a few random peers for their sketch, and keep the
+//! maximum of each register, so that all sketches converge to the sketch of the
+//! whole network. Only the sketches of the peers that we've picked ourselves are
+//! merged, so a node can't push its sketch onto others. Because a sketch can't
+//! be validated, the registers are capped and can only rise a little with every
+//! exchange, which limits how far a malicious peer can inflate the estimate.
+//!
+//! Nodes that leave the network can't be taken out of a sketch, so a new sketch
+//! is started every epoch. The estimate of the previous epoch is reported until
+//! the current sketch has seen at least as many nodes.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use log::*;
+use rand::{rngs::OsRng, seq::SliceRandom};
+
+use super::{
+	ActorNode, ACTOR_MESSAGE_TYPE_REACH_SKETCH_REQUEST, ACTOR_MESSAGE_TYPE_REACH_SKETCH_RESPONSE,
+};
+use crate::{
+	common::*,
+	net::{
+		binserde,
+		message::*,
+		overlay::OverlayNode,
+		sstp::{self, Connection, MessageProcessorResult},
+	},
+};
+
+
+/// The number of bits of the hash that select a register.
+const PRECISION: u32 = 8;
+/// The number of registers of a sketch, which gives an error of about 6.5%.
+const REGISTERS: usize = 1 << PRECISION;
+/// The length of an epoch, in milliseconds.
+const EPOCH_LENGTH: u64 = 24 * 60 * 60 * 1000;
+/// How often the sketches are exchanged with other nodes.
+const EXCHANGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// The number of random peers to exchange the sketch with every time.
+const EXCHANGE_PEERS: usize = 3;
+/// The highest value a register can have, which is enough for networks of
+/// billions of nodes.
+const MAX_RANK: u8 = 32;
+/// How much a register can rise with a single exchange.
+const MAX_RANK_INCREASE: u8 = 2;
+
+
+#[derive(Clone)]
+struct Sketch {
+	epoch: u64,
+	registers: Vec<u8>,
+}
+
+pub(super) struct ReachEstimator {
+	current: Sketch,
+	/// The estimate of the last epoch that we've taken part in.
+	previous_estimate: Option<u64>,
+}
+
+
+impl Sketch {
+	fn new(epoch: u64) -> Self {
+		Self {
+			epoch,
+			registers: vec![0; REGISTERS],
+		}
+	}
+
+	fn insert(&mut self, hash: &IdType) {
+		let bytes = hash.as_bytes();
+		let index = bytes[0] as usize;
+		let rest = u64::from_be_bytes(bytes[1..9].try_into().unwrap());
+		let rank = (rest.leading_zeros() as u8 + 1).min(MAX_RANK);
+		if rank > self.registers[index] {
+			self.registers[index] = rank;
+		}
+	}
+
+	/// Keeps the maximum of each register, but only lets each register rise
+	/// by a limited amount, because the other sketch can't be trusted.
+	fn merge(&mut self, registers: &[u8]) {
+		for (ours, theirs) in self.registers.iter_mut().zip(registers) {
+			let limit = ours.saturating_add(MAX_RANK_INCREASE).min(MAX_RANK);
+			let theirs = (*theirs).min(limit);
+			if theirs > *ours {
+				*ours = theirs;
+			}
+		}
+	}
+
+	fn estimate(&self) -> u64 {
+		let m = REGISTERS as f64;
+		let alpha = 0.7213 / (1.0 + 1.079 / m);
+		let sum: f64 = self
+			.registers
+			.iter()
+			.map(|r| 2f64.powi(-(*r as i32)))
+			.sum();
+		let estimate = alpha * m * m / sum;
+
+		// Small cardinalities are better estimated by the empty registers
+		let empty = self.registers.iter().filter(|r| **r == 0).count();
+		if estimate <= 2.5 * m && empty > 0 {
+			(m * (m / empty as f64).ln()).round() as u64
+		} else {
+			estimate.round() as u64
+		}
+	}
+}
+
+impl ReachEstimator {
+	pub fn new() -> Self {
+		Self {
+			current: Sketch::new(0),
+			previous_estimate: None,
+		}
+	}
+
+	/// Starts a new sketch if a new epoch has begun, and adds the given node to
+	/// it.
+	fn roll_over(&mut self, epoch: u64, node: Option<&IdType>, actor: &IdType) {
+		if self.current.epoch != epoch {
+			if self.current.epoch + 1 == epoch {
+				self.previous_estimate = Some(self.current.estimate());
+			} else {
+				self.previous_estimate = None;
+			}
+			self.current = Sketch::new(epoch);
+		}
+		if let Some(node_id) = node {
+			let mut buffer = Vec::with_capacity(72);
+			buffer.extend(node_id.as_bytes());
+			buffer.extend(actor.as_bytes());
+			buffer.extend(epoch.to_be_bytes());
+			self.current.insert(&IdType::hash(&buffer));
+		}
+	}
+
+	fn estimate(&self) -> u64 {
+		let estimate = self.current.estimate();
+		match self.previous_estimate {
+			Some(previous) => previous.max(estimate),
+			None => estimate,
+		}
+	}
+}
+
+impl ActorNode {
+	/// An estimate of the number of nodes that take part in this actor
+	/// network, including ourselves if we do.
+	pub fn estimate_reach(&self) -> u64 {
+		let mut estimator = self.reach.lock().unwrap();
+		self.roll_over_reach(&mut estimator);
+		estimator.estimate()
+	}
+
+	fn roll_over_reach(&self, estimator: &mut ReachEstimator) {
+		let epoch = current_timestamp() / EPOCH_LENGTH;
+		// Lurkers don't store anything for the network, so they don't count
+		let node_id = if self.base.interface.is_lurker {
+			None
+		} else {
+			Some(self.base.address.as_id().into_owned())
+		};
+		estimator.roll_over(epoch, node_id.as_ref(), &self.actor_address().as_id());
+	}
+
+	/// Merges the given registers into our sketch if they are of the current
+	/// epoch.
+	fn merge_reach(&self, epoch: u64, registers: &[u8]) {
+		let mut estimator = self.reach.lock().unwrap();
+		self.roll_over_reach(&mut estimator);
+		if estimator.current.epoch == epoch && registers.len() == REGISTERS {
+			estimator.current.merge(registers);
+		}
+	}
+
+	/// Returns our registers if the given epoch is the current one.
+	fn reach_registers(&self, epoch: u64) -> Option<Vec<u8>> {
+		let mut estimator = self.reach.lock().unwrap();
+		self.roll_over_reach(&mut estimator);
+		if estimator.current.epoch != epoch {
+			return None;
+		}
+		Some(estimator.current.registers.clone())
+	}
+
+	async fn exchange_reach_sketch_on_connection(
+		&self, connection: &mut Connection,
+	) -> Option<()> {
+		let request = {
+			let mut estimator = self.reach.lock().unwrap();
+			self.roll_over_reach(&mut estimator);
+			ReachSketchRequest {
+				epoch: estimator.current.epoch,
+				registers: estimator.current.registers.clone().into(),
+			}
+		};
+		let raw_response = self
+			.base
+			.exchange_on_connection(
+				connection,
+				ACTOR_MESSAGE_TYPE_REACH_SKETCH_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await?;
+		let result: sstp::Result<ReachSketchResponse> = binserde::deserialize_sstp(&raw_response);
+		let response = self
+			.base
+			.handle_connection_issue(result, connection.their_node_info())
+			.await?;
+		if let Some(registers) = response.registers {
+			self.merge_reach(request.epoch, &registers);
+		}
+		Some(())
+	}
+
+	pub(super) async fn process_reach_sketch_request(
+		&self, buffer: &[u8], addr: &SocketAddr,
+	) -> MessageProcessorResult {
+		let request: ReachSketchRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed reach sketch request from {}: {}", addr, e);
+				return None;
+			}
+		};
+
+		// The sketch of the requester isn't merged, otherwise anyone could keep
+		// pushing an inflated sketch onto us
+		let response = ReachSketchResponse {
+			registers: self.reach_registers(request.epoch).map(|r| r.into()),
+		};
+		self.base
+			.simple_result(ACTOR_MESSAGE_TYPE_REACH_SKETCH_RESPONSE, &response)
+	}
+
+	/// Exchanges our sketch with a few random peers.
+	async fn spread_reach_sketch(&self) {
+		let mut fingers = Vec::new();
+		let mut iter = self.base.iter_all_fingers_top_down(0).await;
+		while let Some(finger) = iter.next().await {
+			fingers.push(finger);
+		}
+		fingers.shuffle(&mut OsRng);
+
+		let mut exchanged = 0;
+		for finger in fingers {
+			if exchanged >= EXCHANGE_PEERS || !self.base.is_running() {
+				break;
+			}
+			if let Some((mut connection, _)) =
+				self.base.select_direct_connection(&finger, None).await
+			{
+				if self
+					.exchange_reach_sketch_on_connection(&mut connection)
+					.await
+					.is_some()
+				{
+					exchanged += 1;
+				}
+			}
+		}
+	}
+}
+
+/// Starts exchanging the sketches of all actor networks that we are in.
+pub fn maintain_reach_estimation(node: Arc<OverlayNode>) {
+	node.tasks()
+		.spawn("reach estimation", keep_estimating(node.clone()));
+}
+
+async fn keep_estimating(node: Arc<OverlayNode>) {
+	loop {
+		let actor_nodes: Vec<Arc<ActorNode>> = node
+			.base
+			.interface
+			.actor_nodes
+			.lock()
+			.await
+			.values()
+			.cloned()
+			.collect();
+		for actor_node in actor_nodes {
+			if !node.base.is_running() {
+				return;
+			}
+			actor_node.spread_reach_sketch().await;
+		}
+
+		if !node.sleep_while_running(EXCHANGE_INTERVAL).await {
+			break;
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_reach_sketch() {
+		let mut rng = test::initialize_rng();
+		let actor = IdType::random(&mut rng);
+		let nodes: Vec<IdType> = (0..1000).map(|_| IdType::random(&mut rng)).collect();
+
+		// Two halves of the network merge into an estimate of the whole
+		let mut first = ReachEstimator::new();
+		let mut second = ReachEstimator::new();
+		for (i, node) in nodes.iter().enumerate() {
+			let estimator = if i % 2 == 0 { &mut first } else { &mut second };
+			estimator.roll_over(1, Some(node), &actor);
+		}
+		// It may take a few exchanges for the registers to catch up
+		for _ in 0..(MAX_RANK / MAX_RANK_INCREASE) {
+			first.current.merge(&second.current.registers);
+		}
+		let estimate = first.estimate();
+		assert!(estimate > 850 && estimate < 1150, "estimate {}", estimate);
+
+		// Adding the same nodes again doesn't change anything
+		for node in &nodes {
+			first.roll_over(1, Some(node), &actor);
+		}
+		assert_eq!(first.estimate(), estimate);
+
+		// The previous estimate is kept in the next epoch
+		first.roll_over(2, None, &actor);
+		assert_eq!(first.current.estimate(), 0);
+		assert_eq!(first.estimate(), estimate);
+		// But not after an epoch has been skipped
+		first.roll_over(4, None, &actor);
+		assert_eq!(first.estimate(), 0);
+	}
+
+	#[test]
+	fn test_reach_sketch_inflation() {
+		let mut rng = test::initialize_rng();
+		let actor = IdType::random(&mut rng);
+		let mut estimator = ReachEstimator::new();
+		for _ in 0..100 {
+			estimator.roll_over(1, Some(&IdType::random(&mut rng)), &actor);
+		}
+		let before = estimator.current.registers.clone();
+
+		// A forged sketch can only raise each register a little
+		estimator.current.merge(&vec![u8::MAX; REGISTERS]);
+		for (after, before) in estimator.current.registers.iter().zip(&before) {
+			assert!(*after <= before + MAX_RANK_INCREASE);
+			assert!(*after <= MAX_RANK);
+		}
+
+		// And never above the cap, no matter how often it is merged
+		for _ in 0..100 {
+			estimator.current.merge(&vec![u8::MAX; REGISTERS]);
+		}
+		assert!(estimator.current.registers.iter().all(|r| *r == MAX_RANK));
+	}
+}
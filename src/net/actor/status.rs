@@ -0,0 +1,78 @@
+//! Reports the state of an actor network that we are in, to help find out why
+//! a feed isn't updating.
+
+use std::sync::atomic::Ordering;
+
+use serde::Serialize;
+
+use super::ActorNode;
+use crate::{
+	db::{self, PersistenceHandle},
+	net::bucket::BucketEntryInfo,
+};
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ActorNetworkStatus {
+	/// The number of nodes of the actor network in our routing table, not
+	/// counting the ones that have stopped responding.
+	pub known_peers: usize,
+	/// The number of those nodes that we keep a connection open with.
+	pub connected_peers: usize,
+	pub is_synchronizing: bool,
+	/// When the last synchronization finished without errors, in milliseconds
+	/// since the epoch. Not set if it hasn't happened since the node started.
+	pub last_synchronized: Option<u64>,
+	/// The sequence number of the newest object that we know of.
+	pub head_sequence: Option<u64>,
+	pub objects_stored: u64,
+	/// The number of objects up to the head that we don't have. If the actor is
+	/// followed with a limited sync depth, the older ones are never
+	/// synchronized.
+	pub objects_behind: u64,
+	/// The number of bytes of block data that we have of the actor's files.
+	pub bytes_stored: u64,
+	/// An estimate of the number of nodes in the actor network, including
+	/// ourselves.
+	pub reach_estimate: u64,
+	/// The nodes in our routing table, and the state of our connections with
+	/// them.
+	pub peers: Vec<BucketEntryInfo>,
+}
+
+
+impl ActorNode {
+	pub async fn network_status(&self) -> db::Result<ActorNetworkStatus> {
+		let actor_id = self.base.interface.actor_id;
+		let objects_stored = self.db().count_actor_objects(actor_id).await?;
+		let bytes_stored = self.db().actor_block_size(actor_id).await?;
+		let head_sequence = *self.base.interface.head_sequence.lock().unwrap();
+		let objects_behind = match head_sequence {
+			Some(sequence) => (sequence + 1).saturating_sub(objects_stored),
+			None => 0,
+		};
+
+		let peers: Vec<BucketEntryInfo> = self
+			.base
+			.routing_table()
+			.await
+			.into_iter()
+			.flat_map(|b| b.entries)
+			.collect();
+		let known_peers = peers.iter().filter(|e| !e.replacement).count();
+		let connected_peers = peers.iter().filter(|e| e.connected).count();
+
+		Ok(ActorNetworkStatus {
+			known_peers,
+			connected_peers,
+			is_synchronizing: self.is_synchonizing.load(Ordering::Relaxed),
+			last_synchronized: *self.last_synchronized.lock().unwrap(),
+			head_sequence,
+			objects_stored,
+			objects_behind,
+			bytes_stored,
+			reach_estimate: self.estimate_reach(),
+			peers,
+		})
+	}
+}
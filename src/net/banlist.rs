@@ -0,0 +1,235 @@
+//! Keeps track of the peers that we don't want to have anything to do with.
+//!
+//! Peers can be banned by their node ID, or by the IP subnet they connect
+//! from. These bans are stored in the database and last until they are lifted
+//! again. Apart from that, IP addresses that keep sending packets with invalid
+//! signatures can be banned automatically, but only temporarily.
+
+use std::{
+	collections::{HashMap, HashSet},
+	fmt,
+	net::IpAddr,
+	str::FromStr,
+	sync::Mutex,
+	time::{Duration, SystemTime},
+};
+
+use ipnetwork::IpNetwork;
+use log::*;
+use sea_orm::EntityTrait;
+
+use crate::{
+	config::Config,
+	core::{Address, NodeAddress},
+	db::{self, Database, PersistenceHandle},
+	entity::peer_ban,
+};
+
+
+const AUTO_BAN_DURATION_DEFAULT: u64 = 3600;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BanTarget {
+	Node(NodeAddress),
+	Subnet(IpNetwork),
+}
+
+pub struct Banlist {
+	node_ids: Mutex<HashSet<NodeAddress>>,
+	subnets: Mutex<Vec<IpNetwork>>,
+	auto_ban_threshold: Option<u32>,
+	auto_ban_duration: Duration,
+	/// The number of invalid signatures received per IP address, and since when
+	/// they are being counted.
+	offenses: Mutex<HashMap<IpAddr, (u32, SystemTime)>>,
+	/// The IP addresses that have been banned automatically, and until when.
+	temporary_bans: Mutex<HashMap<IpAddr, SystemTime>>,
+}
+
+
+impl BanTarget {
+	pub fn from_model(model: &peer_ban::Model) -> Option<Self> {
+		if let Some(address) = &model.node_address {
+			Some(Self::Node(address.clone()))
+		} else if let Some(subnet) = &model.subnet {
+			match IpNetwork::from_str(subnet) {
+				Ok(n) => Some(Self::Subnet(n)),
+				Err(e) => {
+					warn!("Invalid subnet \"{}\" in banlist: {}", subnet, e);
+					None
+				}
+			}
+		} else {
+			None
+		}
+	}
+}
+
+impl fmt::Display for BanTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Node(address) => address.fmt(f),
+			Self::Subnet(subnet) => subnet.fmt(f),
+		}
+	}
+}
+
+impl FromStr for BanTarget {
+	type Err = String;
+
+	/// Parses either a node address, or an IP address or subnet in CIDR
+	/// notation.
+	fn from_str(string: &str) -> Result<Self, Self::Err> {
+		if let Ok(subnet) = IpNetwork::from_str(string) {
+			return Ok(Self::Subnet(subnet));
+		}
+		match Address::from_str(string) {
+			Ok(Address::Node(address)) => Ok(Self::Node(address)),
+			Ok(_) => Err(format!("not a node address: {}", string)),
+			Err(e) => Err(format!("invalid node address or subnet \"{}\": {}", string, e)),
+		}
+	}
+}
+
+impl Banlist {
+	pub fn new(config: &Config) -> Self {
+		Self {
+			node_ids: Mutex::new(HashSet::new()),
+			subnets: Mutex::new(Vec::new()),
+			auto_ban_threshold: config.auto_ban_threshold,
+			auto_ban_duration: Duration::from_secs(
+				config.auto_ban_duration.unwrap_or(AUTO_BAN_DURATION_DEFAULT),
+			),
+			offenses: Mutex::new(HashMap::new()),
+			temporary_bans: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub fn add(&self, target: &BanTarget) {
+		match target {
+			BanTarget::Node(address) => {
+				self.node_ids.lock().unwrap().insert(address.clone());
+			}
+			BanTarget::Subnet(subnet) => {
+				let mut subnets = self.subnets.lock().unwrap();
+				if !subnets.contains(subnet) {
+					subnets.push(*subnet);
+				}
+			}
+		}
+	}
+
+	pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+		{
+			let mut temporary_bans = self.temporary_bans.lock().unwrap();
+			if let Some(until) = temporary_bans.get(ip) {
+				if *until > SystemTime::now() {
+					return true;
+				}
+				temporary_bans.remove(ip);
+			}
+		}
+
+		self.subnets
+			.lock()
+			.unwrap()
+			.iter()
+			.any(|subnet| subnet.contains(*ip))
+	}
+
+	pub fn is_node_banned(&self, address: &NodeAddress) -> bool {
+		self.node_ids.lock().unwrap().contains(address)
+	}
+
+	/// Loads all bans from the database.
+	pub async fn load(&self, db: &Database) -> db::Result<()> {
+		let records = peer_ban::Entity::find().all(db.inner()).await?;
+		for record in records {
+			if let Some(target) = BanTarget::from_model(&record) {
+				self.add(&target);
+			}
+		}
+		Ok(())
+	}
+
+	/// Counts an invalid signature against the given IP address, and bans it
+	/// temporarily once it has sent too many of them.
+	/// Returns whether the IP address got banned.
+	pub fn record_invalid_signature(&self, ip: IpAddr) -> bool {
+		let threshold = if let Some(t) = self.auto_ban_threshold {
+			t
+		} else {
+			return false;
+		};
+
+		let now = SystemTime::now();
+		let banned = {
+			let mut offenses = self.offenses.lock().unwrap();
+			let entry = offenses.entry(ip).or_insert((0, now));
+			// Forget about old offenses
+			if now.duration_since(entry.1).unwrap_or_default() >= self.auto_ban_duration {
+				*entry = (0, now);
+			}
+			entry.0 += 1;
+			if entry.0 >= threshold {
+				offenses.remove(&ip);
+				true
+			} else {
+				false
+			}
+		};
+
+		if banned {
+			self.temporary_bans
+				.lock()
+				.unwrap()
+				.insert(ip, now + self.auto_ban_duration);
+		}
+		banned
+	}
+
+	pub fn remove(&self, target: &BanTarget) {
+		match target {
+			BanTarget::Node(address) => {
+				self.node_ids.lock().unwrap().remove(address);
+			}
+			BanTarget::Subnet(subnet) => {
+				self.subnets.lock().unwrap().retain(|s| s != subnet);
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_auto_ban() {
+		let mut config = Config::default();
+		config.auto_ban_threshold = Some(3);
+		let banlist = Banlist::new(&config);
+		let ip: IpAddr = "192.168.1.10".parse().unwrap();
+
+		assert!(!banlist.record_invalid_signature(ip));
+		assert!(!banlist.record_invalid_signature(ip));
+		assert!(!banlist.is_ip_banned(&ip));
+		assert!(banlist.record_invalid_signature(ip));
+		assert!(banlist.is_ip_banned(&ip));
+		assert!(!banlist.is_ip_banned(&"192.168.1.11".parse().unwrap()));
+	}
+
+	#[test]
+	fn test_subnet_ban() {
+		let banlist = Banlist::new(&Config::default());
+		let target = BanTarget::from_str("10.0.0.0/8").unwrap();
+		banlist.add(&target);
+		assert!(banlist.is_ip_banned(&"10.1.2.3".parse().unwrap()));
+		assert!(!banlist.is_ip_banned(&"11.1.2.3".parse().unwrap()));
+
+		banlist.remove(&target);
+		assert!(!banlist.is_ip_banned(&"10.1.2.3".parse().unwrap()));
+	}
+}
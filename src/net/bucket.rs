@@ -1,8 +1,14 @@
 use std::cmp::Ordering;
 
-use super::{distance, NodeContactInfo};
-use crate::{core::NodeAddress, limited_store::LimitedVec};
+use serde::Serialize;
 
+use super::{distance, ContactInfo, NodeContactInfo, Openness};
+use crate::{common::current_timestamp, core::NodeAddress, limited_store::LimitedVec};
+
+
+/// The number of nodes that stopped responding that are kept aside per bucket
+/// in the low-memory profile. Otherwise, as many are kept as fit in the bucket.
+pub const REPLACEMENT_CACHE_SIZE_LOW_MEMORY: usize = 1;
 
 pub struct Bucket {
 	pub(super) connections: LimitedVec<NodeContactInfo>,
@@ -16,6 +22,8 @@ struct BucketEntry {
 	trust_score: u8,
 	is_relay: bool,
 	values_obtained: u32,
+	/// The last time the node responded to us, in milliseconds since the epoch.
+	last_seen: u64,
 }
 
 #[derive(Clone)]
@@ -24,6 +32,52 @@ pub struct BucketReplacementEntry {
 	failed_attempts: u8,
 }
 
+/// A snapshot of a bucket of the routing table.
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketInfo {
+	/// The position of the first bit in which the IDs of the nodes in this
+	/// bucket differ from our own node ID.
+	pub index: usize,
+	pub entries: Vec<BucketEntryInfo>,
+}
+
+/// A snapshot of a node in a bucket, to show what the routing table looks like.
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketEntryInfo {
+	pub node_id: String,
+	pub contact_options: Vec<ContactOptionInfo>,
+	/// The last time the node responded to us, in milliseconds since the epoch.
+	pub last_seen: Option<u64>,
+	/// Whether a connection with the node is being kept open.
+	pub connected: bool,
+	/// Whether the node has been put aside in the replacement cache, because it
+	/// stopped responding.
+	pub replacement: bool,
+	pub failed_attempts: u8,
+	pub trust_score: u8,
+	pub is_relay: bool,
+	pub values_obtained: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ContactOptionInfo {
+	pub protocol: &'static str,
+	pub address: String,
+	pub openness: Openness,
+}
+
+/// The number of nodes in the routing table, without the details of each one.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RoutingTableSummary {
+	/// The number of buckets that contain any node.
+	pub buckets_used: usize,
+	pub nodes: usize,
+	pub connected: usize,
+	pub relays: usize,
+	/// The nodes that stopped responding, which are kept aside.
+	pub replacements: usize,
+}
+
 
 impl Bucket {
 	pub fn add_connection(
@@ -106,10 +160,9 @@ impl Bucket {
 			// If the finger already exists in this bucket, just update its contact info with the
 			// latest contact info
 			Some(index) => {
-				self.fingers[index]
-					.node_info
-					.contact_info
-					.merge(&node_info.contact_info);
+				let entry = &mut self.fingers[index];
+				entry.node_info.contact_info.merge(&node_info.contact_info);
+				entry.last_seen = current_timestamp();
 			}
 			// If the finger is not in this bucket, check if it is in the replacement cache
 			None => {
@@ -199,17 +252,33 @@ impl Bucket {
 		}
 	}
 
-	pub fn new(size: usize) -> Self {
+	pub fn new(size: usize, replacement_cache_size: usize) -> Self {
 		Self {
 			connections: LimitedVec::new(size),
 			fingers: LimitedVec::new(size),
-			replacement_cache: LimitedVec::new(size),
+			replacement_cache: LimitedVec::new(replacement_cache_size),
 		}
 	}
 
 	pub fn remember(&mut self, node: NodeContactInfo, trust_score: u8, is_relay: bool) -> bool {
-		let new_entry = BucketEntry::new(node, trust_score, is_relay);
+		self.insert_entry(BucketEntry::new(node, trust_score, is_relay))
+	}
+
+	/// Updates whether the node is a relay node, if it is in this bucket.
+	pub fn set_relay(&mut self, address: &NodeAddress, is_relay: bool) {
+		if let Some(index) = self
+			.fingers
+			.iter()
+			.position(|f| &f.node_info.address == address && f.is_relay != is_relay)
+		{
+			// Relay nodes are ordered differently, so put it back in its new place
+			let mut entry = self.fingers.remove(index).unwrap();
+			entry.is_relay = is_relay;
+			self.insert_entry(entry);
+		}
+	}
 
+	fn insert_entry(&mut self, new_entry: BucketEntry) -> bool {
 		// Try to add it above an exististing entry if it has higher priority
 		if let Some(pos) = self.fingers.iter().rev().position(|e| &new_entry < e) {
 			self.fingers.insert(pos, new_entry);
@@ -225,6 +294,43 @@ impl Bucket {
 		false
 	}
 
+	/// Describes all the nodes that are in this bucket.
+	pub fn snapshot(&self) -> Vec<BucketEntryInfo> {
+		let is_connected =
+			|address: &NodeAddress| self.connections.iter().any(|n| &n.address == address);
+		let mut entries: Vec<BucketEntryInfo> = self
+			.fingers
+			.iter()
+			.map(|e| e.info(is_connected(&e.node_info.address), false, 0))
+			.chain(self.replacement_cache.iter().map(|e| {
+				e.finger.info(
+					is_connected(&e.finger.node_info.address),
+					true,
+					e.failed_attempts,
+				)
+			}))
+			.collect();
+
+		// Connected nodes that aren't fingers themselves
+		for node_info in self.connections.iter() {
+			let node_id = node_info.address.to_string();
+			if !entries.iter().any(|e| e.node_id == node_id) {
+				entries.push(BucketEntryInfo {
+					node_id,
+					contact_options: contact_options(&node_info.contact_info),
+					last_seen: None,
+					connected: true,
+					replacement: false,
+					failed_attempts: 0,
+					trust_score: 0,
+					is_relay: false,
+					values_obtained: 0,
+				});
+			}
+		}
+		entries
+	}
+
 	pub fn remove_connection(&mut self, address: &NodeAddress) -> bool {
 		if let Some(index) = self.connections.iter().position(|n| &n.address == address) {
 			self.connections.remove(index);
@@ -242,6 +348,21 @@ impl BucketEntry {
 			trust_score,
 			values_obtained: 0,
 			is_relay,
+			last_seen: current_timestamp(),
+		}
+	}
+
+	fn info(&self, connected: bool, replacement: bool, failed_attempts: u8) -> BucketEntryInfo {
+		BucketEntryInfo {
+			node_id: self.node_info.address.to_string(),
+			contact_options: contact_options(&self.node_info.contact_info),
+			last_seen: Some(self.last_seen),
+			connected,
+			replacement,
+			failed_attempts,
+			trust_score: self.trust_score,
+			is_relay: self.is_relay,
+			values_obtained: self.values_obtained,
 		}
 	}
 }
@@ -286,3 +407,115 @@ impl PartialOrd for BucketEntry {
 		self.values_obtained.partial_cmp(&other.values_obtained)
 	}
 }
+
+impl RoutingTableSummary {
+	pub fn new(buckets: &[BucketInfo]) -> Self {
+		let mut summary = Self::default();
+		for bucket in buckets {
+			if !bucket.entries.is_empty() {
+				summary.buckets_used += 1;
+			}
+			for entry in &bucket.entries {
+				if entry.replacement {
+					summary.replacements += 1;
+					continue;
+				}
+				summary.nodes += 1;
+				if entry.connected {
+					summary.connected += 1;
+				}
+				if entry.is_relay {
+					summary.relays += 1;
+				}
+			}
+		}
+		summary
+	}
+}
+
+
+fn contact_options(contact_info: &ContactInfo) -> Vec<ContactOptionInfo> {
+	let ips = [
+		contact_info
+			.ipv4
+			.as_ref()
+			.map(|e| (e.addr.to_string(), &e.availability)),
+		contact_info
+			.ipv6
+			.as_ref()
+			.map(|e| (format!("[{}]", e.addr), &e.availability)),
+	];
+
+	let mut options = Vec::new();
+	for (ip, availability) in ips.into_iter().flatten() {
+		for (protocol, entry) in [("udp", &availability.udp), ("tcp", &availability.tcp)] {
+			if let Some(entry) = entry {
+				options.push(ContactOptionInfo {
+					protocol,
+					address: format!("{}:{}", ip, entry.port),
+					openness: entry.openness,
+				});
+			}
+		}
+	}
+	options
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		common::IdType,
+		net::{IpAvailability, Ipv4ContactInfo, TransportAvailabilityEntry},
+		test,
+	};
+
+	#[test]
+	fn test_snapshot() {
+		let mut rng = test::initialize_rng();
+		let our_node_id = NodeAddress::V1(IdType::random(&mut rng));
+		let node_info = |rng: &mut _| NodeContactInfo {
+			address: NodeAddress::V1(IdType::random(rng)),
+			contact_info: ContactInfo {
+				ipv4: Some(Ipv4ContactInfo {
+					addr: "192.168.1.10".parse().unwrap(),
+					availability: IpAvailability {
+						udp: Some(TransportAvailabilityEntry {
+							port: 37337,
+							openness: Openness::Bidirectional,
+						}),
+						tcp: None,
+					},
+				}),
+				ipv6: None,
+			},
+		};
+		let finger = node_info(&mut rng);
+		let problematic = node_info(&mut rng);
+		let connection = node_info(&mut rng);
+
+		let mut bucket = Bucket::new(4, 4);
+		bucket.remember(finger.clone(), 0, false);
+		bucket.remember(problematic.clone(), 0, true);
+		bucket.mark_problematic(&problematic.address);
+		bucket.add_connection(&connection, &our_node_id);
+		bucket.add_connection(&finger, &our_node_id);
+
+		let entries = bucket.snapshot();
+		assert_eq!(entries.len(), 3);
+		let entry = |node_info: &NodeContactInfo| {
+			let node_id = node_info.address.to_string();
+			entries.iter().find(|e| e.node_id == node_id).unwrap()
+		};
+		assert!(entry(&finger).connected);
+		assert!(!entry(&finger).replacement);
+		assert!(entry(&finger).last_seen.is_some());
+		assert_eq!(entry(&finger).contact_options[0].address, "192.168.1.10:37337");
+		assert_eq!(entry(&finger).contact_options[0].protocol, "udp");
+		assert!(entry(&problematic).replacement);
+		assert_eq!(entry(&problematic).failed_attempts, 1);
+		assert!(entry(&connection).connected);
+		assert!(entry(&connection).last_seen.is_none());
+	}
+}
@@ -39,6 +39,9 @@ impl ConnectionManager {
 			.collect()
 	}
 
+	/// The most connections that are kept.
+	pub fn limit(&self) -> usize { self.limit }
+
 	/// Checks whether there is space for a new connection in the manager,
 	pub async fn find_space<'a>(
 		&'a self, node_info: &NodeContactInfo,
@@ -0,0 +1,129 @@
+//! Announces what happens on the node while it happens, so that a user
+//! interface can show it right away instead of having to be refreshed.
+//!
+//! Events are only kept for the subscribers that are listening at the moment
+//! they are published. A subscriber that falls too far behind misses the
+//! oldest ones.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{common::IdType, core::ActorAddress};
+
+
+/// The number of events that are kept for a subscriber that hasn't received
+/// them yet.
+const EVENT_CAPACITY: usize = 256;
+
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+	/// An object of another actor has been received.
+	NewObject {
+		actor_address: String,
+		hash: String,
+	},
+	/// Something has been received that is meant for one of our identities.
+	NewNotification {
+		kind: NotificationKind,
+		identity: String,
+		actor_address: String,
+	},
+	/// The synchronization of the network of an actor made some progress.
+	SyncProgress {
+		actor_address: String,
+		stage: SyncStage,
+	},
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationKind {
+	DirectMessage,
+	/// Another actor asks to follow one of our identities.
+	FollowRequest,
+	/// A post of another actor mentions one of our identities.
+	Mention,
+	/// Another actor has reacted to a post of one of our identities.
+	Reaction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncStage {
+	Objects,
+	Files,
+	Done,
+}
+
+pub struct EventBus {
+	sender: broadcast::Sender<Event>,
+}
+
+
+impl Event {
+	pub fn new_object(actor_address: &ActorAddress, hash: &IdType) -> Self {
+		Self::NewObject {
+			actor_address: actor_address.to_string(),
+			hash: hash.to_string(),
+		}
+	}
+
+	pub fn sync_progress(actor_address: &ActorAddress, stage: SyncStage) -> Self {
+		Self::SyncProgress {
+			actor_address: actor_address.to_string(),
+			stage,
+		}
+	}
+}
+
+impl EventBus {
+	pub fn new() -> Self {
+		Self {
+			sender: broadcast::channel(EVENT_CAPACITY).0,
+		}
+	}
+
+	pub fn publish(&self, event: Event) {
+		// It is fine if nobody is listening
+		let _ = self.sender.send(event);
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<Event> { self.sender.subscribe() }
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[tokio::test]
+	async fn test_event_bus() {
+		let mut rng = test::initialize_rng();
+		let bus = EventBus::new();
+		let actor_address = ActorAddress::V1(IdType::random(&mut rng));
+		let hash = IdType::random(&mut rng);
+
+		// Events that are published before subscribing are not received
+		bus.publish(Event::sync_progress(&actor_address, SyncStage::Objects));
+		let mut receiver = bus.subscribe();
+		bus.publish(Event::new_object(&actor_address, &hash));
+		match receiver.recv().await.unwrap() {
+			Event::NewObject {
+				actor_address: a,
+				hash: h,
+			} => {
+				assert_eq!(a, actor_address.to_string());
+				assert_eq!(h, hash.to_string());
+			}
+			other => panic!("unexpected event {:?}", other),
+		}
+
+		let json = serde_json::to_value(&Event::sync_progress(&actor_address, SyncStage::Done))
+			.unwrap();
+		assert_eq!(json["type"], "sync-progress");
+		assert_eq!(json["stage"], "done");
+	}
+}
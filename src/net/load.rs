@@ -0,0 +1,197 @@
+//! Sheds load when the node is running out of resources.
+//!
+//! The memory usage of the process, the number of open sessions and the
+//! latency of the database are sampled every few seconds. As soon as any of
+//! them exceeds its threshold, the node starts shedding load: it refuses to
+//! relay new sessions, postpones the background synchronization of actor
+//! networks, and answers lookups for actors that it doesn't follow with only
+//! the contacts it has in memory, so that the requester moves on to other
+//! nodes. Once all of them have dropped well below their thresholds again, the
+//! node goes back to normal.
+
+use std::{
+	fs,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Mutex,
+	},
+	time::Duration,
+};
+
+use log::*;
+use serde::Serialize;
+
+use crate::config::Config;
+
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// The fraction of the session limit at which load is shed by default.
+const SESSIONS_THRESHOLD_RATIO: f64 = 0.9;
+const DB_LATENCY_THRESHOLD_DEFAULT: u64 = 500;
+/// Load shedding only stops once every sample is below this fraction of its
+/// threshold, so that the node doesn't keep flipping between both states.
+const RECOVERY_RATIO: f64 = 0.8;
+
+
+pub struct LoadMonitor {
+	is_enabled: bool,
+	/// The maximum resident set size, in KiB.
+	memory_threshold: Option<u64>,
+	sessions_threshold: Option<usize>,
+	/// The maximum time a database query may take, in milliseconds.
+	db_latency_threshold: u64,
+	is_shedding: AtomicBool,
+	last_sample: Mutex<Option<LoadSample>>,
+	episodes: AtomicU64,
+	rejected_relays: AtomicU64,
+	busy_lookups: AtomicU64,
+	delayed_synchronizations: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LoadSample {
+	/// The resident set size of the process in KiB, if it could be determined.
+	pub memory: Option<u64>,
+	pub sessions: usize,
+	pub session_limit: usize,
+	/// The time a simple database query took, in milliseconds.
+	pub db_latency: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LoadStats {
+	pub is_shedding: bool,
+	pub last_sample: Option<LoadSample>,
+	/// The number of times load shedding has started.
+	pub episodes: u64,
+	pub rejected_relays: u64,
+	pub busy_lookups: u64,
+	pub delayed_synchronizations: u64,
+}
+
+
+impl LoadMonitor {
+	pub fn new(config: &Config) -> Self {
+		Self {
+			is_enabled: config.load_shedding.unwrap_or(true),
+			memory_threshold: config.load_shedding_memory_limit.map(|mb| mb * 1024),
+			sessions_threshold: config.load_shedding_session_limit,
+			db_latency_threshold: config
+				.load_shedding_db_latency
+				.unwrap_or(DB_LATENCY_THRESHOLD_DEFAULT),
+			is_shedding: AtomicBool::new(false),
+			last_sample: Mutex::new(None),
+			episodes: AtomicU64::new(0),
+			rejected_relays: AtomicU64::new(0),
+			busy_lookups: AtomicU64::new(0),
+			delayed_synchronizations: AtomicU64::new(0),
+		}
+	}
+
+	pub fn is_shedding(&self) -> bool { self.is_shedding.load(Ordering::Relaxed) }
+
+	/// Returns the reason to shed load if any sample exceeds the given fraction
+	/// of its threshold.
+	fn exceeded(&self, sample: &LoadSample, ratio: f64) -> Option<String> {
+		if let (Some(memory), Some(threshold)) = (sample.memory, self.memory_threshold) {
+			if memory as f64 >= threshold as f64 * ratio {
+				return Some(format!("memory usage is {} KiB", memory));
+			}
+		}
+		let sessions_threshold = self
+			.sessions_threshold
+			.unwrap_or((sample.session_limit as f64 * SESSIONS_THRESHOLD_RATIO) as usize);
+		if sample.sessions as f64 >= sessions_threshold as f64 * ratio {
+			return Some(format!("{} sessions are open", sample.sessions));
+		}
+		if sample.db_latency as f64 >= self.db_latency_threshold as f64 * ratio {
+			return Some(format!("database latency is {} ms", sample.db_latency));
+		}
+		None
+	}
+
+	/// Takes a new sample into account, and starts or stops shedding load
+	/// accordingly. Returns whether load is being shed.
+	pub fn process_sample(&self, sample: LoadSample) -> bool {
+		let was_shedding = self.is_shedding();
+		let is_shedding = if !self.is_enabled {
+			false
+		} else if !was_shedding {
+			if let Some(reason) = self.exceeded(&sample, 1.0) {
+				warn!("Running out of resources, shedding load: {}.", reason);
+				self.episodes.fetch_add(1, Ordering::Relaxed);
+				true
+			} else {
+				false
+			}
+		} else if self.exceeded(&sample, RECOVERY_RATIO).is_none() {
+			info!("Resources have recovered, no longer shedding load.");
+			false
+		} else {
+			true
+		};
+
+		self.is_shedding.store(is_shedding, Ordering::Relaxed);
+		*self.last_sample.lock().unwrap() = Some(sample);
+		is_shedding
+	}
+
+	pub fn record_busy_lookup(&self) { self.busy_lookups.fetch_add(1, Ordering::Relaxed); }
+
+	pub fn record_delayed_synchronization(&self) {
+		self.delayed_synchronizations.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_rejected_relay(&self) { self.rejected_relays.fetch_add(1, Ordering::Relaxed); }
+
+	pub fn stats(&self) -> LoadStats {
+		LoadStats {
+			is_shedding: self.is_shedding(),
+			last_sample: self.last_sample.lock().unwrap().clone(),
+			episodes: self.episodes.load(Ordering::Relaxed),
+			rejected_relays: self.rejected_relays.load(Ordering::Relaxed),
+			busy_lookups: self.busy_lookups.load(Ordering::Relaxed),
+			delayed_synchronizations: self.delayed_synchronizations.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// Reads the resident set size of this process, in KiB. Only works on Linux.
+pub fn resident_set_size() -> Option<u64> {
+	let status = fs::read_to_string("/proc/self/status").ok()?;
+	let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+	line.split_whitespace().nth(1)?.parse().ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_load_shedding() {
+		let mut config = Config::default();
+		config.load_shedding_memory_limit = Some(100);
+		let monitor = LoadMonitor::new(&config);
+		let sample = |memory, sessions, db_latency| LoadSample {
+			memory: Some(memory),
+			sessions,
+			session_limit: 1000,
+			db_latency,
+		};
+
+		assert!(!monitor.process_sample(sample(50 * 1024, 100, 10)));
+		assert!(monitor.process_sample(sample(50 * 1024, 950, 10)));
+		// Still too close to the threshold to recover
+		assert!(monitor.process_sample(sample(50 * 1024, 800, 10)));
+		assert!(!monitor.process_sample(sample(50 * 1024, 100, 10)));
+		assert!(monitor.process_sample(sample(100 * 1024, 100, 10)));
+		assert!(!monitor.process_sample(sample(50 * 1024, 100, 10)));
+		assert!(monitor.process_sample(sample(50 * 1024, 100, 600)));
+
+		let stats = monitor.stats();
+		assert!(stats.is_shedding);
+		assert_eq!(stats.episodes, 3);
+		assert_eq!(stats.last_sample.unwrap().db_latency, 600);
+	}
+}
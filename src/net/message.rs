@@ -4,6 +4,7 @@ use self::sstp::server::RelayedHelloPacket;
 use crate::{
 	common::*,
 	core::*,
+	identity::{message_key::MessageKey, ActorSignatureV1},
 	net::{
 		sstp::server::{RelayHelloAckPacket, RelayHelloPacket},
 		*,
@@ -107,6 +108,16 @@ pub struct KeepAliveResponse {
 	pub ok: bool,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VersionRequest {
+	pub protocol_version: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VersionResponse {
+	pub protocol_version: u16,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListActorsRequest {}
 
@@ -133,6 +144,23 @@ pub enum ListTrustedNodesResult {
 	List(LimVec<(NodeAddress, u8), Limit1M>),
 }
 
+/// Pushes a newly published object to a node that follows the actor, or
+/// gossips it to a peer in the actor network.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyObjectRequest {
+	pub id: IdType,
+	pub object: BlogchainObject,
+	/// The number of times the receiver may still gossip the object further.
+	pub hops_left: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyObjectResponse {
+	/// Whether the object was new to the follower. If so, it will go on to
+	/// download the files of the object on the same connection.
+	pub stored: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenRelayRequest {
 	pub target_node_id: NodeAddress,
@@ -240,6 +268,18 @@ pub struct StartRelayResponse {
 	pub ok: bool,
 }
 
+/// A value that can't be verified by its ID alone, as it is returned in a find
+/// value response. It comes with the signature and publication time of the
+/// actor that originally published it, so that it can be checked before it is
+/// accepted.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SignedValue {
+	pub signature: ActorSignatureV1,
+	/// The time the value was published, in milliseconds since the UNIX epoch.
+	pub published: u64,
+	pub data: Vec<u8>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StoreActorRequest {
 	pub actor_id: IdType,
@@ -249,6 +289,128 @@ pub struct StoreActorRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StoreActorResponse {}
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockMapRequest {
+	pub file_hash: IdType,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockMapResponse {
+	/// A bit for every block of the file, set if the responder has the block.
+	/// The first block is the least significant bit of the first byte. Not set
+	/// if the responder doesn't know the file.
+	pub bitmap: Option<LimVec<u8, Limit1M>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreParityRequest {
+	pub file_hash: IdType,
+	pub stripe: u32,
+	/// The index of the shard within the stripe, counting the data shards.
+	pub shard: u8,
+	pub data: LimVec<u8, Limit10M>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreParityResponse {
+	/// Whether the parity block was accepted.
+	pub stored: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FindParityRequest {
+	pub file_hash: IdType,
+	pub stripe: u32,
+	pub shard: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FindParityResponse {
+	pub data: Option<LimVec<u8, Limit10M>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReachSketchRequest {
+	/// The number of days since the epoch that the sketch is of.
+	pub epoch: u64,
+	pub registers: LimVec<u8, Limit256>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReachSketchResponse {
+	/// The registers of the responder, after merging the requester's into
+	/// them. Not set if the responder is at another epoch.
+	pub registers: Option<LimVec<u8, Limit256>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageKeyRequest {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageKeyResponse {
+	/// The message key of the actor, with the actor's signature on it. Only set
+	/// if the responder is the actor itself.
+	pub key: Option<(MessageKey, ActorSignatureV1)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectMessageRequest {
+	/// The actor info of the sender, in case the recipient doesn't know it yet.
+	pub sender_info: ActorInfo,
+	pub message: DirectMessageObject,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectMessageResponse {
+	/// Whether the responder is the recipient, and has stored the message.
+	pub accepted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FollowRequestRequest {
+	/// The actor info of the requester, in case the actor doesn't know it yet.
+	pub requester_info: ActorInfo,
+	pub request: FollowRequestObject,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FollowRequestResponse {
+	/// Whether the responder is the actor that is asked, and has stored the
+	/// request.
+	pub accepted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncLogRequest {
+	/// The hashes of some of the objects that the requester has, at decreasing
+	/// sequence numbers.
+	pub checkpoints: LimVec<(u64, IdType), Limit64>,
+	/// The lowest sequence number of the objects that the requester wants.
+	pub min_sequence: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncLogResponse {
+	/// The sequence number of the latest checkpoint that the responder has as
+	/// well, if any.
+	pub common_sequence: Option<u64>,
+	/// The objects that follow the common checkpoint, in order of sequence.
+	pub objects: LimVec<(IdType, BlogchainObject), Limit64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRotationsRequest {
+	/// The lowest sequence number of the key rotation objects that the
+	/// requester wants.
+	pub min_sequence: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRotationsResponse {
+	/// The key rotation objects of the actor, in order of sequence.
+	pub rotations: LimVec<(IdType, BlogchainObject), Limit64>,
+}
+
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum BlogchainValueType {
@@ -1,11 +1,27 @@
-use std::{collections::VecDeque, sync::atomic::*};
+use std::{
+	collections::{HashMap, VecDeque},
+	future::Future,
+	sync::atomic::*,
+	time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use async_trait::async_trait;
 use futures::future::join_all;
 use log::*;
 use serde::de::DeserializeOwned;
-
-use super::{bucket::Bucket, message::*, overlay::OverlayNode, sstp::MessageProcessorResult, *};
+use tokio::{runtime::Handle, select, spawn, time::sleep};
+
+use super::{
+	bucket::{Bucket, BucketInfo},
+	message::*,
+	overlay::OverlayNode,
+	reputation::{HELPFUL_REWARD, PROBLEMATIC_PENALTY},
+	sstp::MessageProcessorResult,
+	stats::{LookupKind, NetworkMetrics},
+	value_cache::ValueCache,
+	value_type::ValueTypeRegistry,
+	*,
+};
 use crate::{
 	common::*,
 	db::{self, Database, PersistenceHandle},
@@ -20,6 +36,18 @@ pub const NETWORK_MESSAGE_TYPE_FIND_NODE_REQUEST: u8 = 2;
 pub const NETWORK_MESSAGE_TYPE_FIND_NODE_RESPONSE: u8 = 3;
 pub const NETWORK_MESSAGE_TYPE_FIND_VALUE_REQUEST: u8 = 4;
 pub const NETWORK_MESSAGE_TYPE_FIND_VALUE_RESPONSE: u8 = 5;
+/// Sent instead of the expected response by a node that is too busy to handle
+/// the request. It doesn't answer any request of its own.
+pub const NETWORK_MESSAGE_TYPE_BUSY_RESPONSE: u8 = 63;
+
+/// The protocol version since which nodes understand the busy response.
+const BUSY_RESPONSE_MIN_PROTOCOL_VERSION: u16 = 2;
+
+/// The maximum amount of time a single step of a lookup may take, which
+/// includes connecting to the node and exchanging the request with it.
+const LOOKUP_STEP_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often a waiting lookup checks whether the node is being stopped.
+const STOP_FLAG_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 
 pub struct AllFingersIter<'a> {
@@ -61,7 +89,7 @@ where
 	pub(super) overlay_node: Arc<OverlayNode>,
 	expect_fingers_in_response: bool,
 
-	id: IdType,
+	pub(super) id: IdType,
 	value_type_id: u8,
 	do_verify:
 		Box<dyn Fn(&IdType, &NodeContactInfo, &[u8]) -> Option<AtomicPtr<()>> + Send + Sync + 'a>,
@@ -69,10 +97,29 @@ where
 	use_relays: bool,
 
 	visited: Vec<(NodeAddress, ContactOption)>,
-	candidates: VecDeque<(BigUint, NodeContactInfo, ContactStrategy)>,
+	/// The candidates of each of the disjoint lookup paths. No node is ever
+	/// visited by more than one path.
+	paths: Vec<LookupPath>,
+	/// The path to take the next step on.
+	next_path: usize,
+	/// The nodes that responded, but didn't have the value.
+	missed: Vec<(BigUint, NodeContactInfo)>,
+	/// The number of hops that it takes to reach each of the candidates, which
+	/// is one for the fingers that the search started with.
+	hops: HashMap<NodeAddress, usize>,
+	/// The most hops that have been taken on any of the paths so far.
+	max_hops: usize,
+	/// A value that was found in the cache, which is returned before searching
+	/// the network.
+	cached: Option<(Vec<u8>, NodeContactInfo)>,
 	open_assistant_connection: Option<(IdType, Arc<Mutex<Option<Box<Connection>>>>)>,
+	started: Instant,
+	/// Whether the outcome of the lookup has been recorded in the metrics yet.
+	recorded: bool,
 }
 
+type LookupPath = VecDeque<(BigUint, NodeContactInfo, ContactStrategy)>;
+
 pub struct Node<I>
 where
 	I: NodeInterface,
@@ -84,7 +131,12 @@ where
 	pub(super) interface: I,
 	pub(super) packet_server: Arc<sstp::Server>,
 	pub(super) bucket_size: usize,
+	/// The number of unresponsive nodes that are kept aside per bucket.
+	pub(super) replacement_cache_size: usize,
 	pub(super) leak_first_request: bool,
+	value_cache: Mutex<ValueCache>,
+	pub(super) value_cache_capacity: usize,
+	pub(super) metrics: Arc<NetworkMetrics>,
 }
 
 #[async_trait]
@@ -100,6 +152,9 @@ pub trait NodeInterface {
 
 		// Receive response
 		let mut response = connection.receive().await?;
+		if response[0] == NETWORK_MESSAGE_TYPE_BUSY_RESPONSE {
+			return trace::err(sstp::Error::Busy);
+		}
 		if response[0] != (request_message_type + 1) {
 			return Err(sstp::Error::InvalidResponseMessageType((
 				response[0],
@@ -112,7 +167,21 @@ pub trait NodeInterface {
 		return Ok(response);
 	}
 
-	async fn find_value(&self, value_type: u8, id: &IdType) -> db::Result<Option<Vec<u8>>>;
+	/// Loads the value of the given type with the given ID, with the handler
+	/// that is registered for the value type.
+	async fn find_value(&self, value_type: u8, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+		match self.value_types().get(value_type) {
+			Some(handler) => handler.find(self, id).await,
+			None => {
+				warn!("Invalid value type requested: {} for id {}", value_type, id);
+				Ok(None)
+			}
+		}
+	}
+
+	/// The lowest protocol version that another node needs to speak for
+	/// anything to be exchanged with it on this interface.
+	fn min_protocol_version(&self) -> u16 { 0 }
 
 	fn overlay_node(&self) -> Arc<OverlayNode>;
 
@@ -126,10 +195,24 @@ pub trait NodeInterface {
 		// Send request
 		connection.send(real_buffer).await
 	}
+
+	/// The handlers of the value types that can be looked up with this
+	/// interface.
+	fn value_types(&self) -> &ValueTypeRegistry<Self>;
 }
 
 pub fn differs_at_bit(a: &IdType, b: &IdType) -> Option<u8> { a.differs_at_bit(b) }
 
+/// Deals the candidates out over the given number of paths, so that each path
+/// starts with some of the closest candidates.
+fn split_into_paths<T>(candidates: VecDeque<T>, path_count: usize) -> Vec<VecDeque<T>> {
+	let mut paths: Vec<VecDeque<T>> = (0..path_count).map(|_| VecDeque::new()).collect();
+	for (i, candidate) in candidates.into_iter().enumerate() {
+		paths[i % path_count].push_back(candidate);
+	}
+	paths
+}
+
 impl ContactStrategy {
 	fn new(contact: ContactOption, openness: Openness) -> Option<Self> {
 		Some(Self {
@@ -150,6 +233,21 @@ impl fmt::Display for ContactStrategy {
 }
 
 impl ContactStrategyMethod {
+	/// Decides how to contact a node, given its openness and our own openness
+	/// at the same contact option.
+	fn pick(target_openness: Openness, own_openness: Option<Openness>) -> Option<Self> {
+		Some(match target_openness {
+			Openness::Bidirectional => Self::Direct,
+			Openness::Punchable => Self::PunchHole,
+			Openness::Unidirectional =>
+				if own_openness? != Openness::Unidirectional {
+					Self::Reversed
+				} else {
+					Self::Relay
+				},
+		})
+	}
+
 	pub fn to_byte(&self) -> u8 {
 		match self {
 			Self::Direct => 0,
@@ -183,7 +281,79 @@ impl<'a, I> FindValueIter<'a, I>
 where
 	I: NodeInterface + Send + Sync,
 {
+	/// Takes the node closest to the value's ID that responded without having
+	/// the value, which is the place to store a copy of the value at.
+	pub fn take_closest_without_value(&mut self) -> Option<NodeContactInfo> {
+		let (i, _) = self
+			.missed
+			.iter()
+			.enumerate()
+			.min_by(|(_, a), (_, b)| a.0.cmp(&b.0))?;
+		Some(self.missed.remove(i).1)
+	}
+
+	/// Records the outcome of the lookup, but only the first time it finishes.
+	fn record_outcome(&mut self, succeeded: bool, hops: usize) {
+		if !self.recorded {
+			self.recorded = true;
+			self.node.metrics.record_lookup(
+				LookupKind::FindValue,
+				succeeded,
+				hops,
+				self.started.elapsed(),
+			);
+		}
+	}
+
 	pub fn visited(&self) -> &[(NodeAddress, ContactOption)] { &self.visited }
+
+	/// Makes the search ignore the value that we have in our cache, so that
+	/// only the values held by other nodes are returned.
+	pub fn skip_cache(&mut self) { self.cached = None; }
+
+	fn is_candidate_of_other_path(&self, path_index: usize, address: &NodeAddress) -> bool {
+		self.paths
+			.iter()
+			.enumerate()
+			.any(|(i, p)| i != path_index && p.iter().any(|c| &c.1.address == address))
+	}
+
+	/// Picks the path to take the next step on, taking turns between the paths
+	/// that still have candidates left.
+	fn pick_next_path(&mut self) -> Option<usize> {
+		let path_count = self.paths.len();
+		for offset in 0..path_count {
+			let i = (self.next_path + offset) % path_count;
+			if !self.paths[i].is_empty() {
+				self.next_path = (i + 1) % path_count;
+				return Some(i);
+			}
+		}
+		None
+	}
+}
+
+impl<'a, I> Drop for FindValueIter<'a, I>
+where
+	I: NodeInterface + Send + Sync,
+{
+	fn drop(&mut self) {
+		// The assistant connection may still be in use by a pinging task, so it is
+		// closed from a separate task once it has been released. Without a runtime,
+		// which is the case when it is dropped during shutdown, the connection is
+		// just left to time out.
+		if let Some((_, connection)) = self.open_assistant_connection.take() {
+			let runtime = match Handle::try_current() {
+				Ok(h) => h,
+				Err(_) => return,
+			};
+			runtime.spawn(async move {
+				if let Some(c) = connection.lock().await.take() {
+					c.close_async();
+				}
+			});
+		}
+	}
 }
 
 impl<I> Node<I>
@@ -248,7 +418,7 @@ where
 		&self, node_info: &NodeContactInfo, strategy: &ContactStrategy,
 		already_open_connection: Option<&mut Connection>, request: Option<&[u8]>,
 	) -> Option<(Box<Connection>, Option<Vec<u8>>)> {
-		match &strategy.method {
+		let result = match &strategy.method {
 			ContactStrategyMethod::Direct =>
 				self.connect(&strategy.contact, Some(&node_info.address), request)
 					.await,
@@ -277,7 +447,10 @@ where
 				.open_relay(node_info)
 				.await
 				.map(|r| (r, None)),
-		}
+		};
+		self.metrics
+			.record_strategy(&strategy.method, result.is_some(), false);
+		result
 	}
 
 	pub async fn connect_with_timeout(
@@ -421,6 +594,8 @@ where
 		let response = response_result?;
 		self.process_find_value_response(
 			connection.their_node_info(),
+			&request.id,
+			request.value_type,
 			&response,
 			expect_fingers_in_response,
 		)
@@ -449,6 +624,8 @@ where
 		let (value_result, fingers_result) = self
 			.process_find_value_response(
 				connection.their_node_info(),
+				&request.id,
+				request.value_type,
 				&raw_response,
 				expect_fingers_in_response,
 			)
@@ -468,6 +645,9 @@ where
 	pub async fn exchange_on_connection(
 		&self, connection: &mut Connection, message_type_id: u8, buffer: &[u8],
 	) -> Option<Vec<u8>> {
+		if !self.ensure_compatible(connection).await {
+			return None;
+		}
 		let result = self
 			.interface
 			.exchange(connection, message_type_id, buffer)
@@ -652,6 +832,7 @@ where
 	pub async fn find_node_from_fingers(
 		&self, id: &IdType, fingers: &[NodeContactInfo], result_limit: usize, visit_limit: usize,
 	) -> Vec<NodeContactInfo> {
+		let started = Instant::now();
 		let mut visited = Vec::<(NodeAddress, ContactOption)>::new();
 		let mut candidates = VecDeque::with_capacity(fingers.len());
 		for (d, n) in Self::sort_fingers(id, fingers).into_iter() {
@@ -662,6 +843,12 @@ where
 				}
 			}
 		}
+		self.prioritize_candidates(&mut candidates);
+		let mut hops: HashMap<_, _> = candidates
+			.iter()
+			.map(|c| (c.1.address.clone(), 1))
+			.collect();
+		let mut max_hops = 0;
 		let mut found = candidates.clone();
 		while found.len() > result_limit {
 			found.pop_back();
@@ -679,14 +866,18 @@ where
 				continue;
 			}
 			visited.push((candidate_contact.address.clone(), strategy.contact.clone()));
+			let candidate_hops = hops.get(&candidate_contact.address).copied().unwrap_or(1);
+			max_hops = max_hops.max(candidate_hops);
 
 			let request = FindNodeRequest {
 				node_id: id.clone(),
 			};
-			match self
+			let result = self
 				.exchange_find_node_at(&strategy.contact, &candidate_contact.address, &request)
-				.await
-			{
+				.await;
+			self.metrics
+				.record_strategy(&strategy.method, result.is_some(), false);
+			match result {
 				None => info!("Disregarding finger {},", &candidate_contact.address),
 				Some((response, _)) => {
 					if response.is_relay_node && strategy.method == ContactStrategyMethod::Direct {
@@ -705,6 +896,10 @@ where
 						let finger_dist = distance(id, &f.address.as_id());
 						finger_dist < candidate_dist
 					});
+					for (finger, _) in &new_fingers {
+						hops.entry(finger.address.clone())
+							.or_insert(candidate_hops + 1);
+					}
 					Self::append_candidates(id, &mut found, &new_fingers);
 					while found.len() > result_limit {
 						found.pop_back();
@@ -730,13 +925,38 @@ where
 			i += 1;
 		}
 
+		self.metrics.record_lookup(
+			LookupKind::FindNode,
+			found.len() > 0,
+			max_hops,
+			started.elapsed(),
+		);
 		found.into_iter().map(|c| c.1).collect()
 	}
 
+	/// Finds the value in our own store. If the database is unavailable, the
+	/// value is taken from the value cache instead, if it is in there.
+	pub async fn find_local_value(
+		&self, db: &Database, value_type: u8, id: &IdType,
+	) -> db::Result<Option<Vec<u8>>> {
+		match self.interface.find_value(value_type, id).await {
+			Err(e) if e.is_unavailable() => {
+				db.observe_error(&e);
+				Ok(self
+					.value_cache
+					.lock()
+					.await
+					.get(value_type, id)
+					.map(|(value, _)| value))
+			}
+			other => other,
+		}
+	}
+
 	pub async fn find_value_from_fingers<'a>(
 		self: &'a Arc<Self>, overlay_node: Arc<OverlayNode>, id: &IdType, value_type_id: u8,
 		expect_fingers_in_response: bool, fingers: &[NodeContactInfo], visit_limit: usize,
-		narrow_down: bool, use_relays: bool,
+		narrow_down: bool, use_relays: bool, disjoint_paths: usize,
 		do_verify: impl Fn(&IdType, &NodeContactInfo, &[u8]) -> Option<AtomicPtr<()>> + Send + Sync + 'a,
 	) -> Option<AtomicPtr<()>> {
 		self.find_value_from_fingers_iter(
@@ -748,6 +968,7 @@ where
 			visit_limit,
 			narrow_down,
 			use_relays,
+			disjoint_paths,
 			do_verify,
 		)
 		.await
@@ -755,10 +976,17 @@ where
 		.await
 	}
 
+	/// Searches the network for a value, starting at the given fingers.
+	///
+	/// With `disjoint_paths` set to more than one, the search runs along that
+	/// many paths that never visit the same node, as in S/Kademlia. A value is
+	/// only accepted once `do_verify` has confirmed it against the ID, so a
+	/// single path that runs into malicious nodes can't hand us a forged value.
+	/// The visit limit applies to each path.
 	pub async fn find_value_from_fingers_iter<'a>(
 		self: &'a Arc<Self>, overlay_node: Arc<OverlayNode>, id: &IdType, value_type_id: u8,
 		expect_fingers_in_response: bool, fingers: &[NodeContactInfo], visit_limit: usize,
-		narrow_down: bool, use_relays: bool,
+		narrow_down: bool, use_relays: bool, disjoint_paths: usize,
 		do_verify: impl Fn(&IdType, &NodeContactInfo, &[u8]) -> Option<AtomicPtr<()>> + Send + Sync + 'a,
 	) -> FindValueIter<'a, I> {
 		// Initialize the candidates by picking a contact strategy for each candidate.
@@ -771,6 +999,14 @@ where
 				}
 			}
 		}
+		self.prioritize_candidates(&mut candidates);
+		let hops = candidates
+			.iter()
+			.map(|c| (c.1.address.clone(), 1))
+			.collect();
+		let disjoint_paths = disjoint_paths.max(1);
+		let paths = split_into_paths(candidates, disjoint_paths);
+		let cached = self.value_cache.lock().await.get(value_type_id, id);
 
 		FindValueIter {
 			node: self,
@@ -781,9 +1017,16 @@ where
 			do_verify: Box::new(do_verify),
 			narrow_down,
 			use_relays,
-			visited: Vec::with_capacity(visit_limit),
-			candidates,
+			visited: Vec::with_capacity(visit_limit * disjoint_paths),
+			paths,
+			next_path: 0,
+			missed: Vec::new(),
+			hops,
+			max_hops: 0,
+			cached,
 			open_assistant_connection: None,
+			started: Instant::now(),
+			recorded: false,
 		}
 	}
 
@@ -798,6 +1041,10 @@ where
 	pub(super) async fn handle_connection_issue<T>(
 		&self, result: sstp::Result<T>, node_info: &NodeContactInfo,
 	) -> Option<T> {
+		self.metrics.record_exchange(
+			result.is_ok(),
+			matches!(&result, Err(e) if matches!(&**e, sstp::Error::Timeout(_))),
+		);
 		match result {
 			Err(e) => {
 				match &*e {
@@ -805,6 +1052,7 @@ where
 						warn!("Problematic node {}: {:?}", node_info, e);
 						self.mark_node_problematic(&node_info.address).await;
 					}
+					sstp::Error::Busy => debug!("Node {} is too busy.", node_info),
 					_ =>
 						if !e.forgivable() {
 							warn!("Problematic node {}: {:?}", node_info, e);
@@ -825,6 +1073,10 @@ where
 	pub(super) async fn handle_connection_issue_find(
 		&self, result: sstp::Result<FindNodeResponse>, node_info: &NodeContactInfo,
 	) -> Option<FindNodeResponse> {
+		self.metrics.record_exchange(
+			result.is_ok(),
+			matches!(&result, Err(e) if matches!(&**e, sstp::Error::Timeout(_))),
+		);
 		match result {
 			Err(e) => {
 				match &*e {
@@ -832,6 +1084,7 @@ where
 						warn!("Problematic node {}: {:?}", node_info, e);
 						self.mark_node_problematic(&node_info.address).await;
 					}
+					sstp::Error::Busy => debug!("Node {} is too busy.", node_info),
 					_ =>
 						if !e.forgivable() {
 							warn!("Problematic node {}: {:?}", node_info, e);
@@ -858,7 +1111,9 @@ where
 			// TODO: Hmmm, what's the point of a response message type? Lets just remove
 			// it...
 			let response_message_type = buffer.remove(0);
-			if (response_message_type & 0x7F) != (request_message_type + 1) {
+			if response_message_type == NETWORK_MESSAGE_TYPE_BUSY_RESPONSE {
+				trace::err(sstp::Error::Busy)
+			} else if (response_message_type & 0x7F) != (request_message_type + 1) {
 				warn!("Received invalid response message type, dropping response.");
 				trace::err(sstp::Error::MalformedMessage(None))
 			} else {
@@ -866,6 +1121,11 @@ where
 			}
 		}
 
+		// The request may have been sent along with the hello packet already, but
+		// then the response can't be expected to be understood
+		if !self.ensure_compatible(connection).await {
+			return None;
+		}
 		let result = if let Some(buffer) = opt_response {
 			parse_response(buffer, message_type)
 		} else if first_request_included {
@@ -882,6 +1142,33 @@ where
 			.await
 	}
 
+	/// Whether the other side of the connection speaks a protocol version that
+	/// is recent enough for this interface. Nodes that don't are left alone
+	/// without being held responsible, because they just don't know better.
+	/// The version of a relayed node may have to be asked for first.
+	async fn ensure_compatible(&self, connection: &mut Connection) -> bool {
+		let min_version = self.interface.min_protocol_version();
+		if min_version == 0 {
+			return true;
+		}
+		let version = match connection.protocol_version() {
+			Some(v) => v,
+			None =>
+				self.overlay_node()
+					.exchange_version_on_connection(connection)
+					.await,
+		};
+		if version < min_version {
+			debug!(
+				"Not exchanging with node {}, which speaks protocol version {}.",
+				connection.their_node_id(),
+				version
+			);
+			return false;
+		}
+		true
+	}
+
 	async fn initiate_assisted_connection(
 		&self, already_open_relay_connection: Option<&mut Connection>, node_info: &NodeContactInfo,
 		strategy: &ContactStrategy, reversed: bool,
@@ -981,6 +1268,23 @@ where
 
 	pub fn is_running(&self) -> bool { !self.stop_flag.load(Ordering::Relaxed) }
 
+	/// Runs the given future until it completes, unless the timeout expires or
+	/// the node is being stopped before that.
+	async fn run_interruptible<T>(
+		&self, timeout: Duration, future: impl Future<Output = T>,
+	) -> Option<T> {
+		let stopped = async {
+			while self.is_running() {
+				sleep(STOP_FLAG_POLL_INTERVAL).await;
+			}
+		};
+		select! {
+			result = future => Some(result),
+			() = stopped => None,
+			() = sleep(timeout) => None,
+		}
+	}
+
 	/// Joins the network via a peer. If pinging that peer fails, returns an I/O
 	/// error.
 	pub async fn join_network_starting_at(&self, node_address: &ContactInfo) -> bool {
@@ -1060,6 +1364,9 @@ where
 
 	/// Use this if a node is giving a timeout.
 	pub(super) async fn mark_node_problematic(&self, address: &NodeAddress) {
+		self.packet_server
+			.reputations
+			.adjust(address, PROBLEMATIC_PENALTY);
 		if let Some(bucket_index) = self.differs_at_bit(&address.as_id()) {
 			let removed = {
 				let mut bucket = self.buckets[bucket_index as usize].lock().await;
@@ -1072,6 +1379,12 @@ where
 	}
 
 	pub(super) async fn mark_node_helpful(&self, node_info: &NodeContactInfo) {
+		if self.packet_server.banlist.is_node_banned(&node_info.address) {
+			return;
+		}
+		self.packet_server
+			.reputations
+			.adjust(&node_info.address, HELPFUL_REWARD);
 		if let Some(bucket_index) = self.differs_at_bit(&node_info.address.as_id()) {
 			let trust_score = self.load_trust_score(&node_info.address).await;
 			let mut bucket = self.buckets[bucket_index as usize].lock().await;
@@ -1082,6 +1395,12 @@ where
 	pub(super) async fn mark_node_helpful_relay(
 		&self, node_info: &NodeContactInfo, is_relay: bool,
 	) {
+		if self.packet_server.banlist.is_node_banned(&node_info.address) {
+			return;
+		}
+		self.packet_server
+			.reputations
+			.adjust(&node_info.address, HELPFUL_REWARD);
 		if let Some(bucket_index) = self.differs_at_bit(&node_info.address.as_id()) {
 			let trust_score = self.load_trust_score(&node_info.address).await;
 			let mut bucket = self.buckets[bucket_index as usize].lock().await;
@@ -1089,6 +1408,14 @@ where
 		}
 	}
 
+	/// Updates whether a node in our routing table is a relay node, after it
+	/// has announced a change.
+	pub(super) async fn update_relay_status(&self, node_id: &NodeAddress, is_relay: bool) {
+		if let Some(mutex) = self.find_bucket(&node_id.as_id()).await {
+			mutex.lock().await.set_relay(node_id, is_relay);
+		}
+	}
+
 	async fn mark_obtained_value(&self, node_id: &NodeAddress) {
 		if let Some(mutex) = self.find_bucket(&node_id.as_id()).await {
 			mutex.lock().await.mark_obtained_value(node_id);
@@ -1097,11 +1424,12 @@ where
 
 	pub fn new(
 		stop_flag: Arc<AtomicBool>, db: Database, node_id: NodeAddress, socket: Arc<sstp::Server>,
-		interface: I, bucket_size: usize, leak_first_request: bool,
+		interface: I, bucket_size: usize, replacement_cache_size: usize,
+		value_cache_capacity: usize, leak_first_request: bool, metrics: Arc<NetworkMetrics>,
 	) -> Self {
 		let mut buckets = Vec::with_capacity(KADEMLIA_BITS);
 		for _ in 0..KADEMLIA_BITS {
-			buckets.push(Mutex::new(Bucket::new(bucket_size)));
+			buckets.push(Mutex::new(Bucket::new(bucket_size, replacement_cache_size)));
 		}
 
 		Self {
@@ -1112,7 +1440,11 @@ where
 			interface,
 			packet_server: socket,
 			bucket_size,
+			replacement_cache_size,
 			leak_first_request,
+			value_cache: Mutex::new(ValueCache::new(value_cache_capacity)),
+			value_cache_capacity,
+			metrics,
 		}
 	}
 
@@ -1120,24 +1452,26 @@ where
 
 	pub fn overlay_node(&self) -> Arc<OverlayNode> { self.interface.overlay_node() }
 
+	/// Describes the non-empty buckets of the routing table.
+	pub async fn routing_table(&self) -> Vec<BucketInfo> {
+		let mut buckets = Vec::new();
+		for (index, mutex) in self.buckets.iter().enumerate() {
+			let entries = mutex.lock().await.snapshot();
+			if !entries.is_empty() {
+				buckets.push(BucketInfo { index, entries });
+			}
+		}
+		buckets
+	}
+
 	fn pick_contact_option(&self, target: &ContactInfo) -> Option<(ContactOption, Openness)> {
 		self.packet_server.pick_contact_option(target)
 	}
 
 	pub(super) fn pick_contact_strategy(&self, target: &ContactInfo) -> Option<ContactStrategy> {
 		let (option, openness) = self.pick_contact_option(target)?;
-		let method = match openness {
-			Openness::Bidirectional => ContactStrategyMethod::Direct,
-			Openness::Punchable => ContactStrategyMethod::PunchHole,
-			Openness::Unidirectional => {
-				let own_openness = self.contact_info().openness_at_option(&option)?;
-				if own_openness != Openness::Unidirectional {
-					ContactStrategyMethod::Reversed
-				} else {
-					ContactStrategyMethod::Relay
-				}
-			}
-		};
+		let own_openness = self.contact_info().openness_at_option(&option);
+		let method = ContactStrategyMethod::pick(openness, own_openness)?;
 
 		Some(ContactStrategy {
 			method,
@@ -1157,7 +1491,7 @@ where
 		// Collect all fingers we have
 		let (connected, fingers) = self.find_nearest_public_contacts(&request.node_id).await;
 		let response = FindNodeResponse {
-			is_relay_node: self.overlay_node().is_relay_node,
+			is_relay_node: self.overlay_node().is_relay_node(),
 			connected: connected.into(),
 			fingers: fingers.into(),
 		};
@@ -1165,7 +1499,8 @@ where
 	}
 
 	async fn process_find_value_response(
-		&self, node_info: &NodeContactInfo, response: &[u8], expect_fingers_in_response: bool,
+		&self, node_info: &NodeContactInfo, id: &IdType, value_type: u8, response: &[u8],
+		expect_fingers_in_response: bool,
 	) -> Option<(Option<Vec<u8>>, Option<FindNodeResponse>)> {
 		// If fingers are expected in the response, parse them
 		let (contacts, contacts_len) = if expect_fingers_in_response {
@@ -1187,11 +1522,18 @@ where
 				return Some((None, contacts));
 			} else {
 				let value = response[contacts_len..].to_vec();
+				let value = self
+					.verify_value_response(node_info, id, value_type, value)
+					.await?;
 				return Some((Some(value), contacts));
 			}
 		} else {
 			if response[contacts_len] == 1 {
-				return Some((Some(response[(contacts_len + 1)..].to_vec()), contacts));
+				let value = response[(contacts_len + 1)..].to_vec();
+				let value = self
+					.verify_value_response(node_info, id, value_type, value)
+					.await?;
+				return Some((Some(value), contacts));
 			} else if response[contacts_len] == 0 {
 				return Some((None, contacts));
 			} else {
@@ -1205,8 +1547,67 @@ where
 		}
 	}
 
+	/// Verifies a value that was found on the network, with the handler of its
+	/// value type. Values that came with the signature of their publisher are
+	/// unpacked as well.
+	async fn verify_value_response(
+		&self, node_info: &NodeContactInfo, id: &IdType, value_type: u8, value: Vec<u8>,
+	) -> Option<Vec<u8>> {
+		let handler = match self.interface.value_types().get(value_type) {
+			Some(h) => h,
+			None => {
+				warn!("Received value of unknown type {}.", value_type);
+				return None;
+			}
+		};
+
+		let data = if handler.is_signed() {
+			let result: sstp::Result<SignedValue> =
+				binserde::deserialize(&value).map_err(|e| Traced::capture(e.into()));
+			let signed_value = match result {
+				Ok(v) => v,
+				Err(e) => return self.handle_connection_issue(Err(e), node_info).await,
+			};
+
+			let now = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap()
+				.as_millis() as u64;
+			if signed_value.published > now {
+				warn!(
+					"Value {} (type {}) has a publication time in the future: {}",
+					id, value_type, signed_value.published
+				);
+				return self
+					.handle_connection_issue(trace::err(sstp::Error::InvalidSignature), node_info)
+					.await;
+			}
+			if !handler.verify_signature(&self.interface, id, &signed_value) {
+				warn!(
+					"Value {} (type {}) has an invalid publisher signature.",
+					id, value_type
+				);
+				return self
+					.handle_connection_issue(trace::err(sstp::Error::InvalidSignature), node_info)
+					.await;
+			}
+			signed_value.data
+		} else {
+			value
+		};
+
+		if !handler.verify(&self.interface, id, &data) {
+			warn!("Value {} (type {}) didn't verify.", id, value_type);
+			return self
+				.handle_connection_issue(trace::err(sstp::Error::MalformedMessage(None)), node_info)
+				.await;
+		}
+		Some(data)
+	}
+
 	async fn process_find_value_request(
 		&self, buffer: &[u8], overlay_node: Arc<OverlayNode>, actor_id: Option<&IdType>,
+		peer: &NodeAddress,
 	) -> MessageProcessorResult {
 		let force_including_fingers = actor_id.is_none();
 
@@ -1219,21 +1620,46 @@ where
 		};
 
 		let result = match actor_id {
+			// When busy, only point to the nodes that are closer to the value, unless
+			// the peer understands that we are busy
 			None =>
-				overlay_node
-					.base
-					.interface
-					.find_value(request.value_type, &request.id)
-					.await,
+				if overlay_node.should_shed_lookup(&request.id).await {
+					if self.understands_busy_response(peer) {
+						return self.busy_result();
+					}
+					Ok(None)
+				} else {
+					overlay_node
+						.base
+						.find_local_value(overlay_node.db(), request.value_type, &request.id)
+						.await
+				},
 			Some(id) => {
 				if let Some(actor_node) =
 					overlay_node.base.interface.actor_nodes.lock().await.get(id)
 				{
-					actor_node
-						.base
-						.interface
-						.find_value(request.value_type, &request.id)
+					// Values that we don't want to hand out to the peer, like blocks
+					// that we don't want to seed, are reported as not found
+					let allowed = match actor_node
+						.may_serve_value(request.value_type, &request.id, peer)
 						.await
+					{
+						Ok(true) if request.value_type == BlogchainValueType::Block as u8 =>
+							actor_node.may_seed_block(&request.id, peer).await,
+						other => other,
+					};
+					match allowed {
+						Ok(true) =>
+							actor_node
+								.base
+								.find_local_value(
+									overlay_node.db(),
+									request.value_type,
+									&request.id,
+								)
+								.await,
+						other => other.map(|_| None),
+					}
 				} else {
 					warn!("Value of unknown actor requested: {}", id);
 					return None;
@@ -1258,7 +1684,7 @@ where
 			// Collect all fingers we have
 			let (connection, fingers) = self.find_nearest_public_contacts(&request.id).await;
 			let response = FindNodeResponse {
-				is_relay_node: self.overlay_node().is_relay_node,
+				is_relay_node: self.overlay_node().is_relay_node(),
 				connected: connection.into(),
 				fingers: fingers.into(),
 			};
@@ -1274,7 +1700,7 @@ where
 			} else {
 				let (connection, fingers) = self.find_nearest_public_contacts(&request.id).await;
 				let response = FindNodeResponse {
-					is_relay_node: self.overlay_node().is_relay_node,
+					is_relay_node: self.overlay_node().is_relay_node(),
 					connected: connection.into(),
 					fingers: fingers.into(),
 				};
@@ -1296,13 +1722,13 @@ where
 
 	pub(super) async fn process_request(
 		self: &Arc<Self>, overlay_node: Arc<OverlayNode>, message_type: u8, buffer: &[u8],
-		addr: &SocketAddr, _node_info: &NodeContactInfo, actor_id: Option<&IdType>,
+		addr: &SocketAddr, node_info: &NodeContactInfo, actor_id: Option<&IdType>,
 	) -> (MessageProcessorResult, bool) {
 		let result = match message_type {
 			NETWORK_MESSAGE_TYPE_PING_REQUEST => self.process_ping_request(addr).await,
 			NETWORK_MESSAGE_TYPE_FIND_NODE_REQUEST => self.process_find_node_request(buffer).await,
 			NETWORK_MESSAGE_TYPE_FIND_VALUE_REQUEST =>
-				self.process_find_value_request(buffer, overlay_node, actor_id)
+				self.process_find_value_request(buffer, overlay_node, actor_id, &node_info.address)
 					.await,
 			_ => return (None, false),
 		};
@@ -1327,8 +1753,22 @@ where
 		Some(latency)
 	}
 
+	/// Leaves out the candidates that are distrusted, and moves the ones that
+	/// have a bad reputation to the back, while keeping the order otherwise.
+	fn prioritize_candidates(
+		&self, candidates: &mut VecDeque<(BigUint, NodeContactInfo, ContactStrategy)>,
+	) {
+		let reputations = &self.packet_server.reputations;
+		candidates.retain(|c| !reputations.is_distrusted(&c.1.address));
+		let (good, bad): (VecDeque<_>, VecDeque<_>) = candidates
+			.drain(..)
+			.partition(|c| reputations.score(&c.1.address) >= 0.0);
+		candidates.extend(good);
+		candidates.extend(bad);
+	}
+
 	/// Removes the node from our buckets.
-	async fn reject_node(&self, node_id: &NodeAddress) {
+	pub(super) async fn reject_node(&self, node_id: &NodeAddress) {
 		if let Some(bucket_index) = self.differs_at_bit(node_id.as_id().as_ref()) {
 			let mut bucket = self.buckets[bucket_index as usize].lock().await;
 			bucket.reject(node_id);
@@ -1345,6 +1785,17 @@ where
 		buffer
 	}
 
+	/// The response to a request that we are too busy to handle.
+	pub(super) fn busy_result(&self) -> MessageProcessorResult {
+		Some((vec![NETWORK_MESSAGE_TYPE_BUSY_RESPONSE], None))
+	}
+
+	/// Whether the node knows what to do with a busy response. Other nodes
+	/// should get the response that they expect, without anything in it.
+	pub(super) fn understands_busy_response(&self, node_id: &NodeAddress) -> bool {
+		self.packet_server.peer_version(node_id).unwrap_or(0) >= BUSY_RESPONSE_MIN_PROTOCOL_VERSION
+	}
+
 	pub(super) fn simple_result<T>(&self, message_type: u8, response: &T) -> MessageProcessorResult
 	where
 		T: Serialize,
@@ -1473,8 +1924,21 @@ where
 
 	// FIXME: Only contact the same node once
 	async fn next(&mut self) -> Option<Self::Item> {
-		while self.candidates.len() > 0 && self.visited.len() < self.visited.capacity() {
-			let (dist, candidate_contact, strategy) = self.candidates.pop_front().unwrap();
+		if let Some((value, peer)) = self.cached.take() {
+			if let Some(result) = (self.do_verify)(&self.id, &peer, &value) {
+				// The value didn't have to be looked up on the network at all
+				self.record_outcome(true, 0);
+				return Some(result);
+			}
+		}
+
+		while self.node.is_running() && self.visited.len() < self.visited.capacity() {
+			let path_index = match self.pick_next_path() {
+				Some(i) => i,
+				None => break,
+			};
+			let (dist, candidate_contact, strategy) =
+				self.paths[path_index].pop_front().unwrap();
 			let contact_option = strategy.contact.clone();
 			// If we ourselves are listed as a candidate, ignore it.
 			if &candidate_contact.address == self.node.node_id() {
@@ -1497,30 +1961,52 @@ where
 			if strategy.method == ContactStrategyMethod::Relay && !self.use_relays {
 				continue;
 			}
-
-			match self
-				.node
-				.connect_by_strategy(&candidate_contact, &strategy, None, None)
-				.await
-			{
+			let candidate_hops = self
+				.hops
+				.get(&candidate_contact.address)
+				.copied()
+				.unwrap_or(1);
+			self.max_hops = self.max_hops.max(candidate_hops);
+
+			// Don't let a wedged connection attempt or exchange hold up the whole
+			// lookup, or the shutdown of the node.
+			let node = self.node;
+			// The attempt to connect is recorded once it finishes, so it only needs to
+			// be recorded here if it timed out.
+			let mut connected = false;
+			let exchange = async {
+				let (mut connection, _) = node
+					.connect_by_strategy(&candidate_contact, &strategy, None, None)
+					.await?;
+				connected = true;
+				Some(
+					node.exchange_find_value_on_connection(
+						&mut connection,
+						self.id.clone(),
+						self.value_type_id,
+						self.expect_fingers_in_response,
+					)
+					.await,
+				)
+			};
+			match node.run_interruptible(LOOKUP_STEP_TIMEOUT, exchange).await {
 				None => {
+					if !node.is_running() {
+						return None;
+					}
+					if !connected {
+						node.metrics.record_strategy(&strategy.method, false, true);
+					}
+					debug!("Lookup step with finger {} timed out", &candidate_contact)
+				}
+				Some(None) => {
 					trace!("Disregarding finger {}", &candidate_contact)
 				}
-				Some((mut connection, _)) => {
-					match self
-						.node
-						.exchange_find_value_on_connection(
-							&mut connection,
-							self.id.clone(),
-							self.value_type_id,
-							self.expect_fingers_in_response,
-						)
-						.await
-					{
+				Some(Some(response)) => {
+					match response {
 						// If node didn't respond right, ignore it
 						None => {}
 						Some((possible_value, possible_contacts)) => {
-							drop(connection);
 
 							// If node returned new fingers, append them to our list
 							if let Some(find_node_response) = possible_contacts {
@@ -1541,15 +2027,21 @@ where
 										&distance(&self.id, &f.address.as_id()) < &dist
 									});
 								}
-
-								Node::<I>::append_candidates(
-									&self.id,
-									&mut self.candidates,
-									&new_fingers,
-								);
+								// Keep the paths disjoint
+								new_fingers.retain(|(f, _)| {
+									!self.is_candidate_of_other_path(path_index, &f.address)
+								});
+
+								for (finger, _) in &new_fingers {
+									self.hops
+										.entry(finger.address.clone())
+										.or_insert(candidate_hops + 1);
+								}
+								let candidates = &mut self.paths[path_index];
+								Node::<I>::append_candidates(&self.id, candidates, &new_fingers);
 								if self.narrow_down {
-									while self.candidates.len() > self.node.bucket_size {
-										self.candidates.pop_back();
+									while candidates.len() > self.node.bucket_size {
+										candidates.pop_back();
 									}
 								}
 
@@ -1588,14 +2080,78 @@ where
 									self.node
 										.mark_obtained_value(&candidate_contact.address)
 										.await;
+									self.node.value_cache.lock().await.insert(
+										self.value_type_id,
+										&self.id,
+										&value,
+										&candidate_contact,
+									);
+									self.record_outcome(true, candidate_hops);
 									return Some(result);
+								} else {
+									warn!(
+										"Node {} responded with a value that didn't verify.",
+										&candidate_contact
+									);
+									self.node
+										.mark_node_problematic(&candidate_contact.address)
+										.await;
 								}
+							} else {
+								self.missed.push((dist, candidate_contact));
 							}
 						}
 					}
 				}
 			}
 		}
+		if self.node.is_running() {
+			self.record_outcome(false, self.max_hops);
+		}
 		None
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_split_into_paths() {
+		let candidates: VecDeque<u32> = (0..7).collect();
+		let paths = split_into_paths(candidates, 3);
+		assert_eq!(paths.len(), 3);
+		assert_eq!(paths[0], [0, 3, 6]);
+		assert_eq!(paths[1], [1, 4]);
+		assert_eq!(paths[2], [2, 5]);
+	}
+
+	#[test]
+	fn test_contact_strategy_method() {
+		use ContactStrategyMethod::*;
+		use Openness::*;
+
+		let cases = [
+			(Bidirectional, None, Some(Direct)),
+			(Bidirectional, Some(Unidirectional), Some(Direct)),
+			(Punchable, None, Some(PunchHole)),
+			(Punchable, Some(Unidirectional), Some(PunchHole)),
+			(Unidirectional, Some(Bidirectional), Some(Reversed)),
+			(Unidirectional, Some(Punchable), Some(Reversed)),
+			(Unidirectional, Some(Unidirectional), Some(Relay)),
+			// Without knowing our own openness, we can't tell whether the node
+			// would be able to reach us.
+			(Unidirectional, None, None),
+		];
+		for (target, own, expected) in cases {
+			assert_eq!(
+				ContactStrategyMethod::pick(target, own),
+				expected,
+				"target {:?}, own {:?}",
+				target,
+				own
+			);
+		}
+	}
+}
@@ -1,10 +1,19 @@
 #![allow(deprecated)]
+pub mod archiver;
+pub mod availability;
+mod election;
+mod quota;
+mod retention;
+pub mod seeding;
+mod stats_history;
+pub mod telemetry;
 mod trust;
 
 
 use std::{
 	result::Result as StdResult,
 	sync::{atomic::*, Mutex as StdMutex, OnceLock},
+	time::Instant,
 };
 
 use async_trait::async_trait;
@@ -15,15 +24,37 @@ use futures::{
 use log::*;
 use rand::{rngs::OsRng, Rng};
 use sea_orm::{prelude::*, QueryOrder, Set};
-use tokio::{select, spawn, time::sleep};
+use tokio::{
+	pin, select, spawn,
+	time::{interval, sleep},
+};
 
-use self::connection_manager::ConnectionManager;
+use self::{
+	archiver::{ArchiveStats, Archiver},
+	availability::ActorAvailability,
+	connection_manager::ConnectionManager,
+	election::{RelayAnnounceRequest, RelayAnnounceResponse},
+	retention::RetentionPolicies,
+	seeding::Seeder,
+	telemetry::{TelemetryCollector, TelemetryReport, TelemetryReportResponse, TelemetryStats},
+};
 use super::{
-	actor::*,
+	actor::{
+		delivery::maintain_delivery_queue, download::DownloadTracker,
+		reach::maintain_reach_estimation, *,
+	},
 	actor_store::*,
+	banlist::BanTarget,
+	bucket::{BucketInfo, RoutingTableSummary, REPLACEMENT_CACHE_SIZE_LOW_MEMORY},
+	event::{Event, EventBus, NotificationKind},
+	load::{self, LoadMonitor, LoadSample, LoadStats},
 	message::*,
 	node::*,
+	rate_limit::RateLimitStats,
 	sstp::{server::*, MessageWorkToDo, Result, DEFAULT_TIMEOUT},
+	stats::{NetworkMetrics, NetworkStats},
+	value_cache::{VALUE_CACHE_CAPACITY, VALUE_CACHE_CAPACITY_LOW_MEMORY},
+	value_type::{ValueHandler, ValueTypeRegistry},
 };
 use crate::{
 	common::*,
@@ -35,15 +66,26 @@ use crate::{
 	limited_store::LimitedVec,
 	net::*,
 	serde_limit::LimVec,
+	task::TaskRegistry,
 	trace::Mutex,
+	util,
 };
 
 
 const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(120);
+const REPUTATION_FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+/// How often the actor networks that we're in are synchronized.
+const SYNCHRONIZATION_INTERVAL: Duration = Duration::from_secs(3600);
+/// The number of disjoint paths that actor lookups take, so that the nodes
+/// around a popular actor can't easily hide it from us.
+const FIND_ACTOR_DISJOINT_PATHS: usize = 3;
 
 const OVERLAY_ATTACHED_NODES_LIMIT_DEFAULT: usize = 1000;
 const OVERLAY_ATTACHED_NODES_MINIMUM: usize = 100;
 
+/// The value type of the actor info that is looked up on the overlay network.
+pub const OVERLAY_VALUE_TYPE_ACTOR: u8 = 0;
+
 // Messages for the overlay network:
 pub const OVERLAY_MESSAGE_TYPE_FIND_ACTOR_REQUEST: u8 = 64;
 pub const OVERLAY_MESSAGE_TYPE_FIND_ACTOR_RESPONSE: u8 = 65;
@@ -65,6 +107,12 @@ pub const OVERLAY_MESSAGE_TYPE_RELAY_REQUEST_REQUEST: u8 = 80;
 pub const OVERLAY_MESSAGE_TYPE_RELAY_REQUEST_RESPONSE: u8 = 81;
 pub const OVERLAY_MESSAGE_TYPE_TRUST_LIST_REQUEST: u8 = 82;
 pub const OVERLAY_MESSAGE_TYPE_TRUST_LIST_RESPONSE: u8 = 83;
+pub const OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_REQUEST: u8 = 84;
+pub const OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_RESPONSE: u8 = 85;
+pub const OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_REQUEST: u8 = 86;
+pub const OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_RESPONSE: u8 = 87;
+pub const OVERLAY_MESSAGE_TYPE_VERSION_REQUEST: u8 = 88;
+pub const OVERLAY_MESSAGE_TYPE_VERSION_RESPONSE: u8 = 89;
 
 
 pub struct ConnectActorIter<'a> {
@@ -93,12 +141,24 @@ struct OpenRelayToDo {
 
 pub struct OverlayNode {
 	pub(super) base: Arc<Node<OverlayInterface>>,
+	/// Only set if this node is an archive node.
+	pub(super) archiver: Option<Archiver>,
 	bootstrap_nodes: Vec<SocketAddr>,
+	pub(crate) downloads: DownloadTracker,
+	pub(crate) events: EventBus,
 	pub(super) expected_connections:
 		Arc<Mutex<HashMap<NodeAddress, oneshot::Sender<Box<sstp::Connection>>>>>,
-	pub(super) is_relay_node: bool,
+	is_relay_node: AtomicBool,
 	pub(crate) tracked_actors: Mutex<HashMap<ActorAddress, Option<ActorInfo>>>,
+	pub(super) load_monitor: LoadMonitor,
+	/// The number of parity blocks to compute for every stripe of the files
+	/// that are published. Zero if erasure coding is disabled.
+	pub(super) parity_blocks: u8,
 	relay_nodes: Mutex<LimitedVec<NodeContactInfo>>,
+	pub(super) retention: RetentionPolicies,
+	pub(super) seeder: Seeder,
+	/// Only set if this node collects the telemetry reports of other nodes.
+	telemetry_collector: Option<TelemetryCollector>,
 }
 
 pub(super) struct OverlayInterface {
@@ -107,8 +167,13 @@ pub(super) struct OverlayInterface {
 	pub(super) actor_nodes: Mutex<HashMap<IdType, Arc<ActorNode>>>,
 	last_message_time: StdMutex<SystemTime>,
 	connection_manager: Arc<ConnectionManager>,
+	value_types: ValueTypeRegistry<OverlayInterface>,
 }
 
+/// Handles the actor info, which is the only value type on the overlay
+/// network.
+struct ActorValueHandler;
+
 struct ReverseConnectionToDo {
 	sender: Option<oneshot::Sender<Box<Connection>>>,
 }
@@ -140,11 +205,38 @@ impl NodeInterface for OverlayInterface {
 		return Ok(response);
 	}
 
-	async fn find_value(&self, value_type: u8, id: &IdType) -> db::Result<Option<Vec<u8>>> {
-		if value_type > 0 {
-			return Ok(None);
-		}
+	fn overlay_node(&self) -> Arc<OverlayNode> {
+		self.node
+			.get()
+			.expect("missing node in overlay interface")
+			.as_ref()
+			.expect("overlay interface already closed")
+			.clone()
+	}
+
+	fn prepare(&self, message_type: u8, buffer: &[u8]) -> Vec<u8> {
+		let mut new_buffer = Vec::with_capacity(1 + buffer.len());
+		new_buffer.push(message_type);
+		new_buffer.extend(buffer);
+		new_buffer
+	}
+
+	async fn send(
+		&self, connection: &mut Connection, message_type: u8, buffer: &[u8],
+	) -> sstp::Result<()> {
+		*self.last_message_time.lock().unwrap() = SystemTime::now();
+
+		// Send request
+		let real_buffer = self.prepare(message_type, buffer);
+		connection.send(real_buffer).await
+	}
 
+	fn value_types(&self) -> &ValueTypeRegistry<Self> { &self.value_types }
+}
+
+#[async_trait]
+impl ValueHandler<OverlayInterface> for ActorValueHandler {
+	async fn find(&self, overlay: &OverlayInterface, id: &IdType) -> db::Result<Option<Vec<u8>>> {
 		// Check what we have in memory first.
 		let node_actor_store = NODE_ACTOR_STORE.lock().await;
 		let value = if let Some(store_entry) = node_actor_store.find(id) {
@@ -153,7 +245,7 @@ impl NodeInterface for OverlayInterface {
 				store_entry.available_nodes.clone().into_iter().collect();
 			drop(node_actor_store);
 
-			let actor_nodes = self.actor_nodes.lock().await;
+			let actor_nodes = overlay.actor_nodes.lock().await;
 			let i_am_available = actor_nodes.contains_key(id);
 			drop(actor_nodes);
 
@@ -165,10 +257,11 @@ impl NodeInterface for OverlayInterface {
 		}
 		// Otherwise, check our database
 		else {
-			let result = tokio::task::block_in_place(|| {
-				let db = self.db.connect_old()?;
-				db.fetch_identity_by_id(id)
-			})?;
+			let id = id.clone();
+			let result = overlay
+				.db
+				.perform(move |c| c.fetch_identity_by_id(&id))
+				.await?;
 			if result.is_none() {
 				return Ok(None);
 			}
@@ -184,34 +277,20 @@ impl NodeInterface for OverlayInterface {
 		Ok(Some(binserde::serialize(&value).unwrap()))
 	}
 
-	fn overlay_node(&self) -> Arc<OverlayNode> {
-		self.node
-			.get()
-			.expect("missing node in overlay interface")
-			.as_ref()
-			.expect("overlay interface already closed")
-			.clone()
-	}
-
-	fn prepare(&self, message_type: u8, buffer: &[u8]) -> Vec<u8> {
-		let mut new_buffer = Vec::with_capacity(1 + buffer.len());
-		new_buffer.push(message_type);
-		new_buffer.extend(buffer);
-		new_buffer
-	}
-
-	async fn send(
-		&self, connection: &mut Connection, message_type: u8, buffer: &[u8],
-	) -> sstp::Result<()> {
-		*self.last_message_time.lock().unwrap() = SystemTime::now();
-
-		// Send request
-		let real_buffer = self.prepare(message_type, buffer);
-		connection.send(real_buffer).await
+	fn verify(&self, _overlay: &OverlayInterface, id: &IdType, data: &[u8]) -> bool {
+		match binserde::deserialize::<FindActorResult>(data) {
+			Err(e) => {
+				warn!("Malformed actor info received: {}", e);
+				false
+			}
+			Ok(result) => result.actor_info.generate_address().as_id().as_ref() == id,
+		}
 	}
 }
 
 impl<'a> FindActorIter<'a> {
+	pub fn skip_cache(&mut self) { self.0.skip_cache(); }
+
 	pub fn visited(&self) -> &[(NodeAddress, ContactOption)] { self.0.visited() }
 }
 
@@ -221,9 +300,34 @@ impl<'a> AsyncIterator for FindActorIter<'a> {
 
 	async fn next(&mut self) -> Option<Self::Item> {
 		let result = self.0.next().await;
-		result.map(|p| unsafe {
+		let value = result.map(|p| unsafe {
 			Box::from_raw(p.into_inner() as *mut (ActorInfo, Vec<NodeContactInfo>))
-		})
+		})?;
+
+		// Store the actor info at the closest node that didn't have it yet, so that
+		// the next lookup for it will be shorter. The node we store it at will
+		// consider us to be available in the actor network, so this can only be done
+		// if we actually are.
+		if let Some(target) = self.0.take_closest_without_value() {
+			let overlay_node = self.0.overlay_node.clone();
+			let actor_id = self.0.id.clone();
+			let actor_info = value.0.clone();
+			spawn(async move {
+				let is_available = overlay_node
+					.base
+					.interface
+					.actor_nodes
+					.lock()
+					.await
+					.contains_key(&actor_id);
+				if is_available {
+					overlay_node
+						.exchange_store_actor(&target, actor_id, actor_info)
+						.await;
+				}
+			});
+		}
+		Some(value)
 	}
 }
 
@@ -378,7 +482,15 @@ impl MessageWorkToDo for KeepAliveToDo {
 		// If the node's ID is not our own, and if there is space for it in the
 		// connection manager, keep it alive.
 		let node_info = connection.their_node_info().clone();
-		if let Some(bucket_index) = self.node.base.differs_at_bit(&self.node_id.as_id()) {
+		let is_banned = self
+			.node
+			.base
+			.packet_server
+			.banlist
+			.is_node_banned(&node_info.address);
+		if is_banned {
+			// Don't keep connections with banned nodes alive
+		} else if let Some(bucket_index) = self.node.base.differs_at_bit(&self.node_id.as_id()) {
 			let mut bucket = self.node.base.buckets[bucket_index as usize].lock().await;
 
 			if let Some(space) = self
@@ -422,7 +534,121 @@ impl MessageWorkToDo for KeepAliveToDo {
 }
 
 impl OverlayNode {
-	pub async fn close(self: Arc<Self>) { self.base.close().await; }
+	/// Stops accepting connections from the given node or subnet, and forgets
+	/// about any node that is banned by it.
+	pub async fn ban(&self, target: &BanTarget) {
+		self.base.packet_server.banlist.add(target);
+		if let BanTarget::Node(address) = target {
+			self.base.reject_node(address).await;
+		}
+	}
+
+	/// Lifts a ban that was placed with `ban`.
+	pub fn unban(&self, target: &BanTarget) { self.base.packet_server.banlist.remove(target); }
+
+	/// How much of its quota an archive node uses, if this is one.
+	pub fn archive_stats(&self) -> Option<ArchiveStats> {
+		self.archiver.as_ref().map(|a| a.stats())
+	}
+
+	/// Estimates the number of nodes that take part in the network of the given
+	/// actor, by sampling the nodes that store it.
+	pub async fn estimate_actor_availability(
+		self: &Arc<Self>, address: &ActorAddress,
+	) -> ActorAvailability {
+		availability::estimate_actor_availability(self, address).await
+	}
+
+	pub fn load_stats(&self) -> LoadStats { self.load_monitor.stats() }
+
+	pub fn network_stats(&self) -> NetworkStats { self.base.metrics.stats() }
+
+	pub fn rate_limit_stats(&self) -> RateLimitStats {
+		self.base.packet_server.rate_limiter.stats()
+	}
+
+	pub async fn relay_stats(&self) -> RelayStats { self.base.packet_server.relay_stats().await }
+
+	pub async fn memory_limits(&self) -> MemoryLimits {
+		let packet_server = &self.base.packet_server;
+		MemoryLimits {
+			sessions: packet_server.sessions_limit().await,
+			window_packets: packet_server.max_window_size(),
+			attached_nodes: self.connection_manager().limit(),
+			cached_values: self.base.value_cache_capacity,
+			bucket_replacements: self.base.replacement_cache_size,
+			rate_limited_peers: packet_server.rate_limiter.tracked_peers_limit(),
+		}
+	}
+
+	pub async fn routing_table(&self) -> Vec<BucketInfo> { self.base.routing_table().await }
+
+	pub async fn routing_table_summary(&self) -> RoutingTableSummary {
+		RoutingTableSummary::new(&self.base.routing_table().await)
+	}
+
+	/// The actor networks that this node takes part in.
+	pub async fn actor_nodes(&self) -> Vec<Arc<ActorNode>> {
+		let nodes = self.base.interface.actor_nodes.lock().await;
+		nodes.values().cloned().collect()
+	}
+
+	/// Indexes the objects of which the text has been downloaded by now, and
+	/// announces the mentions of our identities in them that are new.
+	pub async fn notify_new_mentions(&self) -> db::Result<()> {
+		self.db().update_search_index().await?;
+		for mention in self.db().take_new_mentions().await? {
+			self.events.publish(Event::NewNotification {
+				kind: NotificationKind::Mention,
+				identity: mention.identity.to_string(),
+				actor_address: mention.actor_address.to_string(),
+			});
+		}
+		Ok(())
+	}
+
+	/// Announces the reactions to the posts of our identities that are new.
+	pub async fn notify_new_reactions(&self) -> db::Result<()> {
+		for reaction in self.db().take_new_reactions().await? {
+			self.events.publish(Event::NewNotification {
+				kind: NotificationKind::Reaction,
+				identity: reaction.identity.to_string(),
+				actor_address: reaction.actor_address.to_string(),
+			});
+		}
+		Ok(())
+	}
+
+	/// The sessions that are open with other nodes at the moment.
+	pub async fn sessions(&self) -> Vec<SessionInfo> {
+		self.base.packet_server.list_sessions().await
+	}
+
+	/// The telemetry report exactly as it would be sent, whether telemetry is
+	/// enabled or not.
+	pub async fn telemetry_preview(&self) -> TelemetryReport {
+		telemetry::prepare_report(self).await
+	}
+
+	/// The statistics of the telemetry reports that other nodes have sent us,
+	/// if we collect them.
+	pub fn telemetry_stats(&self) -> Option<TelemetryStats> {
+		self.telemetry_collector.as_ref().map(|c| c.stats())
+	}
+
+	/// The registry of the long-lived tasks of this node and its actor nodes.
+	pub fn tasks(&self) -> &TaskRegistry { &self.base.packet_server.tasks }
+
+	/// Sleeps for the given duration, unless the node is being stopped before
+	/// that. Returns whether the node is still running.
+	pub async fn sleep_while_running(&self, duration: Duration) -> bool {
+		util::sleep_unless_stopped(&self.base.stop_flag, duration).await
+	}
+
+	pub async fn close(self: Arc<Self>) {
+		self.flush_reputations().await;
+		self.base.close().await;
+	}
 
 	pub fn connection_manager(&self) -> &ConnectionManager {
 		&self.base.interface.connection_manager
@@ -432,15 +658,29 @@ impl OverlayNode {
 		stop_flag: Arc<AtomicBool>, config: &Config, node_id: NodeAddress,
 		private_key: NodePrivateKey, db: Database,
 	) -> StdResult<Arc<Self>, SocketBindError> {
+		let low_memory = config.low_memory.unwrap_or(false);
 		let attached_node_limit = if let Some(limit) = config.attached_nodes_limit {
 			if limit >= OVERLAY_ATTACHED_NODES_MINIMUM {
 				limit
 			} else {
 				OVERLAY_ATTACHED_NODES_MINIMUM
 			}
+		} else if low_memory {
+			OVERLAY_ATTACHED_NODES_MINIMUM
 		} else {
 			OVERLAY_ATTACHED_NODES_LIMIT_DEFAULT
 		};
+		let value_cache_capacity = if low_memory {
+			VALUE_CACHE_CAPACITY_LOW_MEMORY
+		} else {
+			VALUE_CACHE_CAPACITY
+		};
+		let bucket_size = config.bucket_size.unwrap_or(4);
+		let replacement_cache_size = if low_memory {
+			REPLACEMENT_CACHE_SIZE_LOW_MEMORY
+		} else {
+			bucket_size
+		};
 
 		let bootstrap_nodes = resolve_bootstrap_addresses(&config.bootstrap_nodes, true, true);
 
@@ -454,6 +694,12 @@ impl OverlayNode {
 		.await?;
 		let mut rng = OsRng {};
 		socket.set_next_session_id(rng.gen()).await;
+		if let Err(e) = socket.banlist.load(&db).await {
+			error!("Unable to load banlist: {}", e);
+		}
+		if let Err(e) = socket.reputations.load(&db).await {
+			error!("Unable to load node reputations: {}", e);
+		}
 
 		let node_id2 = node_id.clone();
 		let this = Arc::new(Self {
@@ -471,20 +717,37 @@ impl OverlayNode {
 						node_id2.as_id().into_owned(),
 						attached_node_limit,
 					)),
+					value_types: ValueTypeRegistry::new()
+						.with(OVERLAY_VALUE_TYPE_ACTOR, ActorValueHandler),
 				},
-				config.bucket_size.unwrap_or(4),
+				bucket_size,
+				replacement_cache_size,
+				value_cache_capacity,
 				config.leak_first_request.unwrap_or(false),
+				Arc::new(NetworkMetrics::new()),
 			)),
+			archiver: Archiver::from_config(config),
 			bootstrap_nodes,
+			downloads: DownloadTracker::default(),
+			events: EventBus::new(),
 			expected_connections: Arc::new(Mutex::new(HashMap::new())),
-			is_relay_node: config.relay_node.unwrap_or(false),
+			is_relay_node: AtomicBool::new(config.relay_node.unwrap_or(false)),
+			load_monitor: LoadMonitor::new(config),
+			parity_blocks: config.parity_blocks.unwrap_or(0),
 			relay_nodes: Mutex::new(LimitedVec::new(100)),
+			retention: RetentionPolicies::from_config(config),
+			seeder: Seeder::new(config),
 			tracked_actors: Mutex::new(HashMap::from_iter(
 				config
 					.parse_tracked_actors()
 					.into_iter()
 					.map(|aa| (aa, None)),
 			)),
+			telemetry_collector: if config.telemetry_collector.unwrap_or(false) {
+				Some(TelemetryCollector::new())
+			} else {
+				None
+			},
 		});
 		let _is_set = this.base.interface.node.set(Some(this.clone())).is_ok();
 		debug_assert!(_is_set);
@@ -513,18 +776,56 @@ impl OverlayNode {
 		let idle_time = config.node_ping_interval.unwrap_or(60);
 		let this4 = this.clone();
 		let stop_flag2 = stop_flag.clone();
-		spawn(async move {
+		this.tasks().spawn("overlay node pinger", async move {
 			this4
 				.keep_hole_open(stop_flag2, Duration::from_secs(idle_time))
 				.await;
 		});
 
+		// Keep saving the reputation scores of other nodes
+		let this4 = this.clone();
+		let stop_flag2 = stop_flag.clone();
+		this.tasks().spawn("reputation flusher", async move {
+			let stopped = async {
+				while !stop_flag2.load(Ordering::Relaxed) {
+					sleep(Duration::from_secs(1)).await;
+				}
+			};
+			pin!(stopped);
+			let mut flush_interval = interval(REPUTATION_FLUSH_INTERVAL);
+			// The first tick completes right away
+			flush_interval.tick().await;
+			// The scores are flushed one last time when the node is closed
+			loop {
+				select! {
+					_ = flush_interval.tick() => this4.flush_reputations().await,
+					_ = &mut stopped => break,
+				}
+			}
+		});
+
 		// When nodes attach to us, make sure to ping on those connections to keep them
 		// alive.
 		this.maintain_node_connections();
 		// Synchronize data on each actor network every hour
 		this.maintain_synchronization();
+		this.maintain_load_monitor();
 		trust::maintain_trust_web(this.clone());
+		retention::maintain_retention(this.clone(), config.purge_unfollowed_after_days);
+		stats_history::maintain_stats_history(this.clone(), config.network_stats_retention);
+		quota::maintain_storage_quota(this.clone(), config.max_storage);
+		election::maintain_relay_election(this.clone(), config);
+		// Retry pushing new objects to followers that were offline
+		maintain_delivery_queue(this.clone());
+		// Keep estimating the size of the actor networks that we are in
+		maintain_reach_estimation(this.clone());
+		// Only send telemetry reports if the user has opted in
+		if config.telemetry.unwrap_or(false) {
+			match config.parse_telemetry_node() {
+				Some(telemetry_node) => telemetry::maintain_telemetry(this.clone(), telemetry_node),
+				None => warn!("Telemetry is enabled, but no valid telemetry node is configured."),
+			}
+		}
 
 		Ok(this)
 	}
@@ -533,6 +834,12 @@ impl OverlayNode {
 
 	pub fn db(&self) -> &db::Database { &self.base.interface.db }
 
+	async fn flush_reputations(&self) {
+		if let Err(e) = self.base.packet_server.reputations.flush(self.db()).await {
+			error!("Unable to save node reputations: {}", e);
+		}
+	}
+
 	pub async fn drop_actor_network(&self, actor_id: &IdType) -> bool {
 		match self
 			.base
@@ -543,9 +850,8 @@ impl OverlayNode {
 			.remove(actor_id)
 		{
 			None => false,
-			Some(_node) => {
-				//node
-
+			Some(node) => {
+				node.close().await;
 				true
 			}
 		}
@@ -570,6 +876,43 @@ impl OverlayNode {
 		Some(response.ok)
 	}
 
+	/// Asks a peer which protocol version it speaks, for connections over
+	/// which the handshake couldn't tell. Nodes that don't understand the
+	/// request are taken to speak version 0, and aren't penalized for it.
+	pub(super) async fn exchange_version_on_connection(&self, connection: &mut Connection) -> u16 {
+		let request = VersionRequest {
+			protocol_version: sstp::PROTOCOL_VERSION,
+		};
+		let result = self
+			.base
+			.interface
+			.exchange(
+				connection,
+				OVERLAY_MESSAGE_TYPE_VERSION_REQUEST,
+				&binserde::serialize(&request).unwrap(),
+			)
+			.await;
+		let version = match result
+			.and_then(|r| binserde::deserialize_sstp::<VersionResponse>(&r))
+		{
+			Ok(response) => response.protocol_version,
+			Err(e) => {
+				debug!(
+					"Unable to learn the protocol version of node {}: {}",
+					connection.their_node_id(),
+					e
+				);
+				0
+			}
+		};
+
+		self.base
+			.packet_server
+			.record_peer_version(connection.their_node_id(), version);
+		connection.set_protocol_version(version);
+		version
+	}
+
 	async fn exchange_pass_relayed_hello_packet(
 		&self, target: &NodeContactInfo, request: &PassRelayRequestRequest,
 	) -> Option<PassRelayRequestResponse> {
@@ -759,7 +1102,7 @@ impl OverlayNode {
 					}
 				};
 
-				let node = Arc::new(ActorNode::new(
+				let result = ActorNode::new(
 					self.base.stop_flag.clone(),
 					self.clone(),
 					self.node_id().clone(),
@@ -771,7 +1114,15 @@ impl OverlayNode {
 					self.base.bucket_size,
 					self.base.leak_first_request,
 					true,
-				));
+				)
+				.await;
+				let node = match result {
+					Ok(n) => Arc::new(n),
+					Err(e) => {
+						error!("Unable to load actor node for {}: {:?}", actor_address, e);
+						return None;
+					}
+				};
 
 				let (object_id, object) =
 					if let Some(r) = node.exchange_profile_on_connection(&mut connection).await {
@@ -788,9 +1139,14 @@ impl OverlayNode {
 						return Some(object);
 					}
 				};
+				let (actor_address2, object_id2, object2) =
+					(actor_address.clone(), object_id.clone(), object.clone());
 				if let Err(e) = self
 					.db()
-					.perform(|mut c| c.store_object(actor_address, &object_id, &object, false))
+					.perform(move |mut c| {
+						c.store_object(&actor_address2, &object_id2, &object2, false)
+					})
+					.await
 				{
 					error!(
 						"Unable to store profile object for {}: {:?}",
@@ -843,6 +1199,19 @@ impl OverlayNode {
 	/// Tries to find the
 	pub async fn find_actor_iter<'a>(
 		self: &'a Arc<Self>, address: &ActorAddress, hop_limit: usize, narrow_down: bool,
+	) -> FindActorIter<'a> {
+		let fingers = self
+			.base
+			.find_nearest_private_fingers(&address.as_id())
+			.await;
+		self.find_actor_iter_from_fingers(address, &fingers, hop_limit, narrow_down)
+			.await
+	}
+
+	/// Like `find_actor_iter`, but starts the search at the given fingers.
+	pub async fn find_actor_iter_from_fingers<'a>(
+		self: &'a Arc<Self>, address: &ActorAddress, fingers: &[NodeContactInfo],
+		hop_limit: usize, narrow_down: bool,
 	) -> FindActorIter<'a> {
 		fn verify_pubkey(
 			id: &IdType, peer: &NodeContactInfo, data: &[u8],
@@ -875,19 +1244,19 @@ impl OverlayNode {
 		}
 
 		let id = address.as_id();
-		let fingers = self.base.find_nearest_private_fingers(&id).await;
 		let this = self.clone();
 		let iter = self
 			.base
 			.find_value_from_fingers_iter(
 				this,
 				&id,
-				0,
+				OVERLAY_VALUE_TYPE_ACTOR,
 				true,
-				&fingers,
+				fingers,
 				hop_limit,
 				narrow_down,
 				false,
+				FIND_ACTOR_DISJOINT_PATHS,
 				verify_pubkey,
 			)
 			.await;
@@ -1026,7 +1395,7 @@ impl OverlayNode {
 				};
 
 				// Start up a new node for the actor network
-				let node = Arc::new(ActorNode::new(
+				let result = ActorNode::new(
 					self.base.stop_flag.clone(),
 					self.clone(),
 					self.node_id().clone(),
@@ -1038,7 +1407,18 @@ impl OverlayNode {
 					self.base.bucket_size,
 					self.base.leak_first_request,
 					false,
-				));
+				)
+				.await;
+				let node = match result {
+					Ok(n) => Arc::new(n),
+					Err(e) => {
+						error!(
+							"Unable to load actor node for actor network {}: {:?}",
+							&actor_address, e
+						);
+						return None;
+					}
+				};
 				actor_nodes.insert(actor_address.as_id().into_owned(), node.clone());
 				node
 			}
@@ -1114,31 +1494,36 @@ impl OverlayNode {
 			{
 				false => warn!("Bootstrap node {} wasn't available", bootstrap_node),
 				true => {
-					match self.db().connect_old() {
+					// Load actor nodes for both your own actors and the ones you are
+					// following.
+					self.maintain_tracked_actors().await;
+					let this = self.clone();
+					let result = self.db().perform(move |c| {
+						let mut list = this.load_following_actor_nodes(&c);
+						list.extend(this.load_my_actor_nodes(&c).into_iter().map(
+							|(id, first_object, actor_type, public_key)| {
+								(
+									id,
+									ActorInfo::V1(ActorInfoV1 {
+										flags: 0,
+										public_key,
+										first_object,
+										actor_type: actor_type.into(),
+									}),
+								)
+							},
+						));
+						Ok(list)
+					});
+					match result.await {
 						Err(e) => {
-							panic!("Unable to connect to database to load actor nodes: {}", e);
+							// Stay in the network, even though we can't join the actor
+							// networks
+							self.db().observe_error(&e);
+							error!("Unable to connect to database to load actor nodes: {}", e);
+							return true;
 						}
-						Ok(c) => {
-							// Load actor nodes for both your own actors and the
-							// ones you are following.
-							self.maintain_tracked_actors().await;
-							let actor_node_infos = tokio::task::block_in_place(|| {
-								let mut list = self.load_following_actor_nodes(&c);
-								list.extend(self.load_my_actor_nodes(&c).into_iter().map(
-									|(id, first_object, actor_type, private_key)| {
-										(
-											id,
-											ActorInfo::V1(ActorInfoV1 {
-												flags: 0,
-												public_key: private_key.public(),
-												first_object,
-												actor_type: actor_type.into(),
-											}),
-										)
-									},
-								));
-								list
-							});
+						Ok(actor_node_infos) => {
 
 							// Open and maintain a connection to a bidirectional node
 							// TODO: Do the same thing for IPv6
@@ -1225,7 +1610,7 @@ impl OverlayNode {
 
 	fn load_my_actor_nodes(
 		&self, c: &db::Connection,
-	) -> Vec<(ActorAddress, IdType, String, ActorPrivateKeyV1)> {
+	) -> Vec<(ActorAddress, IdType, String, ActorPublicKeyV1)> {
 		let result = match c.fetch_my_identities() {
 			Ok(r) => r,
 			Err(e) => {
@@ -1236,8 +1621,8 @@ impl OverlayNode {
 
 		result
 			.into_iter()
-			.map(|(_, actor_id, first_object, actor_type, private_key)| {
-				(actor_id, first_object, actor_type, private_key)
+			.map(|(_, actor_id, first_object, actor_type, public_key)| {
+				(actor_id, first_object, actor_type, public_key)
 			})
 			.collect()
 	}
@@ -1258,7 +1643,7 @@ impl OverlayNode {
 				}
 			};
 
-			let node = Arc::new(ActorNode::new(
+			let result = ActorNode::new(
 				self.base.stop_flag.clone(),
 				self.clone(),
 				self.base.address.clone(),
@@ -1270,7 +1655,15 @@ impl OverlayNode {
 				1, // A lurker node doesn't need to keep fingers in the first place
 				self.base.leak_first_request,
 				true,
-			));
+			)
+			.await;
+			let node = match result {
+				Ok(n) => Arc::new(n),
+				Err(e) => {
+					error!("Unable to load actor node for {}: {:?}", address, e);
+					return None;
+				}
+			};
 			node.base
 				.mark_node_helpful(connection.their_node_info())
 				.await;
@@ -1279,11 +1672,24 @@ impl OverlayNode {
 		None
 	}
 
+	/// Samples the load of the node periodically, so that it starts or stops
+	/// shedding load accordingly.
+	fn maintain_load_monitor(self: &Arc<Self>) {
+		let this = self.clone();
+		self.tasks().spawn("load monitor", async move {
+			while this.base.is_running() {
+				let sample = this.sample_load().await;
+				this.load_monitor.process_sample(sample);
+				sleep(load::SAMPLE_INTERVAL).await;
+			}
+		});
+	}
+
 	/// Will send a ping request every minute or so to all connections that are
 	/// maintained on the overlay network.
 	fn maintain_node_connections(self: &Arc<Self>) {
 		let this = self.clone();
-		spawn(async move {
+		self.tasks().spawn("node connection maintainer", async move {
 			let mut next_ping = SystemTime::now() + Duration::from_secs(60);
 			let stop_flag = this.base.stop_flag.clone();
 			while !stop_flag.load(Ordering::Relaxed) {
@@ -1332,7 +1738,7 @@ impl OverlayNode {
 
 	fn maintain_synchronization(self: &Arc<Self>) {
 		let this = self.clone();
-		spawn(async move {
+		self.tasks().spawn("synchronization scheduler", async move {
 			while this.base.is_running() {
 				let actor_nodes: Vec<Arc<ActorNode>> = this
 					.base
@@ -1344,10 +1750,22 @@ impl OverlayNode {
 					.map(|a| a.clone())
 					.collect();
 				for actor_node in actor_nodes {
+					// Postpone the synchronization until we have the resources for it again,
+					// and until the database can be used again
+					let mut delayed = false;
+					while (this.load_monitor.is_shedding() || this.db().is_degraded())
+						&& this.base.is_running()
+					{
+						if !delayed {
+							this.load_monitor.record_delayed_synchronization();
+							delayed = true;
+						}
+						sleep(load::SAMPLE_INTERVAL).await;
+					}
 					actor_node.start_synchronization();
 				}
 
-				sleep(Duration::from_secs(3600)).await;
+				sleep(SYNCHRONIZATION_INTERVAL).await;
 			}
 		});
 	}
@@ -1731,6 +2149,8 @@ impl OverlayNode {
 
 	pub fn node_id(&self) -> &NodeAddress { &self.base.address }
 
+	pub fn is_relay_node(&self) -> bool { self.is_relay_node.load(Ordering::Relaxed) }
+
 	#[allow(dead_code)]
 	pub async fn ping(&self, target: &NodeContactInfo) -> Option<u32> {
 		self.base.ping(target).await
@@ -1745,6 +2165,28 @@ impl OverlayNode {
 		self: &Arc<Self>, actor_node: &Arc<ActorNode>, message_type: u8, buffer: &[u8],
 		addr: &SocketAddr, node_info: &NodeContactInfo, is_lurker: bool,
 	) -> MessageProcessorResult {
+		if actor_node.is_blocked_node(&node_info.address) {
+			debug!(
+				"Refused request from node {}, which belongs to an actor blocked by {}.",
+				&node_info.address,
+				actor_node.actor_address()
+			);
+			return None;
+		}
+		// Nodes of an older protocol version couldn't decode our response
+		let version = self
+			.base
+			.packet_server
+			.peer_version(&node_info.address)
+			.unwrap_or(0);
+		if version < ACTOR_PROTOCOL_VERSION {
+			debug!(
+				"Ignoring actor request from node {}, which speaks protocol version {}.",
+				&node_info.address, version
+			);
+			return None;
+		}
+
 		let (result, processed) = actor_node
 			.base
 			.process_request(
@@ -1783,7 +2225,9 @@ impl OverlayNode {
 		}
 	}
 
-	async fn process_find_actor_request(&self, buffer: &[u8]) -> MessageProcessorResult {
+	async fn process_find_actor_request(
+		&self, buffer: &[u8], peer: &NodeAddress,
+	) -> MessageProcessorResult {
 		let request: FindActorRequest = match binserde::deserialize(buffer) {
 			Err(e) => {
 				error!("Malformed find actor request: {}", e);
@@ -1798,7 +2242,7 @@ impl OverlayNode {
 			.await;
 		let mut response = FindActorResponse {
 			contacts: FindNodeResponse {
-				is_relay_node: self.is_relay_node,
+				is_relay_node: self.is_relay_node(),
 				connected: connected.into(),
 				fingers: fingers.into(),
 			},
@@ -1820,11 +2264,22 @@ impl OverlayNode {
 			}
 		}
 
+		// Don't bother the database for actors we don't follow when we're busy
+		if self.should_shed_lookup(&request.node_id).await {
+			if self.base.understands_busy_response(peer) {
+				return self.base.busy_result();
+			}
+			return self
+				.base
+				.simple_result(OVERLAY_MESSAGE_TYPE_FIND_ACTOR_RESPONSE, &response);
+		}
+
 		// If we have the public key in our own database, show that as well.
-		let actor_info_result = tokio::task::block_in_place(|| {
-			let c = self.db().connect_old()?;
-			c.fetch_identity_by_id(&request.node_id)
-		});
+		let node_id = request.node_id.clone();
+		let actor_info_result = self
+			.db()
+			.perform(move |c| c.fetch_identity_by_id(&node_id))
+			.await;
 		match actor_info_result {
 			Err(e) => error!("Database error while looking for public key: {}", e),
 			Ok(actor_info) =>
@@ -1866,6 +2321,15 @@ impl OverlayNode {
 	async fn process_open_relay_request(
 		self: &Arc<Self>, buffer: &[u8], addr: &SocketAddr,
 	) -> MessageProcessorResult {
+		if self.load_monitor.is_shedding() {
+			debug!("Refusing to open a relay for {}, shedding load.", addr);
+			self.load_monitor.record_rejected_relay();
+			let response = OpenRelayResponse { ok: false };
+			return self
+				.base
+				.simple_result(OVERLAY_MESSAGE_TYPE_OPEN_RELAY_RESPONSE, &response);
+		}
+
 		let request: OpenRelayRequest = match binserde::deserialize(buffer) {
 			Ok(r) => r,
 			Err(e) => {
@@ -2193,7 +2657,8 @@ impl OverlayNode {
 	) -> MessageProcessorResult {
 		match message_type {
 			OVERLAY_MESSAGE_TYPE_FIND_ACTOR_REQUEST =>
-				self.process_find_actor_request(buffer).await,
+				self.process_find_actor_request(buffer, &node_info.address)
+					.await,
 			OVERLAY_MESSAGE_TYPE_STORE_ACTOR_REQUEST =>
 				self.process_store_actor_request(buffer, node_info).await,
 			OVERLAY_MESSAGE_TYPE_KEEP_ALIVE_REQUEST =>
@@ -2216,6 +2681,14 @@ impl OverlayNode {
 			OVERLAY_MESSAGE_TYPE_TRUST_LIST_REQUEST =>
 				self.process_trust_list_request(buffer, &node_info.address)
 					.await,
+			OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_REQUEST =>
+				self.process_telemetry_report_request(buffer, &node_info.address)
+					.await,
+			OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_REQUEST =>
+				self.process_relay_announce_request(buffer, &node_info.address)
+					.await,
+			OVERLAY_MESSAGE_TYPE_VERSION_REQUEST =>
+				self.process_version_request(buffer, &node_info.address),
 			other_id => {
 				warn!(
 					"Unknown overlay message type ID received from {}: {}",
@@ -2226,6 +2699,68 @@ impl OverlayNode {
 		}
 	}
 
+	async fn process_relay_announce_request(
+		&self, buffer: &[u8], node_address: &NodeAddress,
+	) -> MessageProcessorResult {
+		let request: RelayAnnounceRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed relay announce request: {}", e);
+				return None;
+			}
+		};
+
+		self.base
+			.update_relay_status(node_address, request.is_relay_node)
+			.await;
+		self.base.simple_result(
+			OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_RESPONSE,
+			&RelayAnnounceResponse {},
+		)
+	}
+
+	fn process_version_request(
+		&self, buffer: &[u8], node_address: &NodeAddress,
+	) -> MessageProcessorResult {
+		let request: VersionRequest = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed version request: {}", e);
+				return None;
+			}
+		};
+
+		self.base
+			.packet_server
+			.record_peer_version(node_address, request.protocol_version);
+		self.base.simple_result(
+			OVERLAY_MESSAGE_TYPE_VERSION_RESPONSE,
+			&VersionResponse {
+				protocol_version: sstp::PROTOCOL_VERSION,
+			},
+		)
+	}
+
+	async fn process_telemetry_report_request(
+		&self, buffer: &[u8], node_address: &NodeAddress,
+	) -> MessageProcessorResult {
+		let report: TelemetryReport = match binserde::deserialize(buffer) {
+			Ok(r) => r,
+			Err(e) => {
+				warn!("Malformed telemetry report: {}", e);
+				return None;
+			}
+		};
+
+		let accepted = match &self.telemetry_collector {
+			Some(collector) => collector.collect(node_address, report),
+			None => false,
+		};
+		let response = TelemetryReportResponse { accepted };
+		self.base
+			.simple_result(OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_RESPONSE, &response)
+	}
+
 	async fn process_reverse_connection_request(
 		&self, buffer: &[u8], node_id: &NodeAddress,
 	) -> MessageProcessorResult {
@@ -2253,7 +2788,7 @@ impl OverlayNode {
 	}
 
 	async fn process_store_actor_request(
-		&self, buffer: &[u8], node_info: &NodeContactInfo,
+		self: &Arc<Self>, buffer: &[u8], node_info: &NodeContactInfo,
 	) -> MessageProcessorResult {
 		let request: StoreActorRequest = match binserde::deserialize(buffer) {
 			Err(e) => {
@@ -2269,6 +2804,7 @@ impl OverlayNode {
 			warn!("Actor store request invalid: public key doesn't match actor ID.");
 			return None;
 		}
+		archiver::consider_actor(self, actor_id_test, request.actor_info.clone());
 
 		// Add actor to store
 		let mut node_store = NODE_ACTOR_STORE.lock().await;
@@ -2315,10 +2851,48 @@ impl OverlayNode {
 		}
 	}
 
+	async fn sample_load(&self) -> LoadSample {
+		let (sessions, session_limit) = self.base.packet_server.session_count().await;
+		let started = Instant::now();
+		// Also flushes the writes that were queued while the database was
+		// unavailable
+		if !self.db().check_health().await {
+			warn!("Database is unavailable.");
+		}
+		LoadSample {
+			memory: load::resident_set_size(),
+			sessions,
+			session_limit,
+			db_latency: started.elapsed().as_millis() as u64,
+		}
+	}
+
 	pub fn set_contact_info(&self, contact_info: ContactInfo) {
 		self.base.set_contact_info(contact_info);
 	}
 
+	/// Whether a lookup for the given actor should get a busy response. While
+	/// shedding load, only the actors that we follow are looked up in the
+	/// database.
+	pub(super) async fn should_shed_lookup(&self, actor_id: &IdType) -> bool {
+		if !self.load_monitor.is_shedding() {
+			return false;
+		}
+		if self
+			.base
+			.interface
+			.actor_nodes
+			.lock()
+			.await
+			.contains_key(actor_id)
+		{
+			return false;
+		}
+
+		self.load_monitor.record_busy_lookup();
+		true
+	}
+
 	pub async fn store_actor(
 		&self, actor_id: &IdType, duplicates: usize, actor_info: &ActorInfo,
 	) -> usize {
@@ -2551,9 +3125,15 @@ impl OverlayNode {
 			else {
 				if let Some(result) = self.find_actor(&address, 100, true).await {
 					let actor_info = result.0;
-					if let Err(e) = self.db().perform(|mut c| {
-						c.store_identity(&address, &actor_info.public_key, &actor_info.first_object)
-					}) {
+					let (address2, actor_info2) = (address.clone(), actor_info.clone());
+					let result = self.db().perform(move |mut c| {
+						c.store_identity(
+							&address2,
+							&actor_info2.public_key,
+							&actor_info2.first_object,
+						)
+					});
+					if let Err(e) = result.await {
 						error!("Unable to store identity of tracked actor: {:?}", e);
 					}
 					join(self.clone(), address.clone(), actor_info);
@@ -2779,69 +3359,45 @@ mod tests {
 
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_direct() {
-		test_overlay_connectivity("unidirectional", "bidirectional", false, 11000).await;
+		test_overlay_connectivity("unidirectional", "bidirectional", false).await;
 	}
 
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_hole_punching_normal() {
-		test_overlay_connectivity("unidirectional", "punchable", false, 12000).await;
+		test_overlay_connectivity("unidirectional", "punchable", false).await;
 	}
 
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_hole_punching_reversed() {
-		test_overlay_connectivity("punchable", "unidirectional", false, 13000).await;
+		test_overlay_connectivity("punchable", "unidirectional", false).await;
 	}
 
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_relay_direct() {
-		test_overlay_connectivity("unidirectional", "unidirectional", true, 14000).await;
+		test_overlay_connectivity("unidirectional", "unidirectional", true).await;
 	}
 
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_relay_indirect() {
-		test_overlay_connectivity("unidirectional", "unidirectional", false, 15000).await;
+		test_overlay_connectivity("unidirectional", "unidirectional", false).await;
 	}
 
 	async fn test_overlay_connectivity(
 		openness_source_node: &str, openness_target_node: &str, assistant_is_relay: bool,
-		first_port: u16,
 	) {
 		// Setup all nodes
 		let mut rng = test::initialize_rng();
 		let stop_flag = Arc::new(AtomicBool::new(false));
-		let mut assistant_config = Config::default();
-		assistant_config.ipv4_address = Some("127.0.0.1".to_string());
-		assistant_config.ipv4_udp_port = Some(first_port);
-		assistant_config.ipv4_udp_openness = Some("bidirectional".to_string());
+		let mut assistant_config = test::simulated_config("bidirectional", Vec::new());
 		assistant_config.relay_node = Some(assistant_is_relay);
-		let mut relay_config = Config::default();
-		relay_config.bootstrap_nodes = vec![format!(
-			"127.0.0.1:{}",
-			assistant_config.ipv4_udp_port.unwrap()
-		)];
-		relay_config.ipv4_address = Some("127.0.0.1".to_string());
-		relay_config.ipv4_udp_port = Some(first_port + 1);
-		relay_config.ipv4_udp_openness = Some("bidirectional".to_string());
-		relay_config.relay_node = Some(true);
-		let mut source_config = Config::default();
-		source_config.bootstrap_nodes = vec![format!(
-			"127.0.0.1:{}",
-			assistant_config.ipv4_udp_port.unwrap()
-		)];
-		source_config.ipv4_address = Some("127.0.0.1".to_string());
-		source_config.ipv4_udp_port = Some(first_port + 2);
-		source_config.ipv4_udp_openness = Some(openness_source_node.to_string());
-		let mut target_config = Config::default();
-		target_config.bootstrap_nodes = vec![format!(
-			"127.0.0.1:{}",
-			assistant_config.ipv4_udp_port.unwrap()
-		)];
-		target_config.ipv4_address = Some("127.0.0.1".to_string());
-		target_config.ipv4_udp_port = Some(first_port + 3);
-		target_config.ipv4_udp_openness = Some(openness_target_node.to_string());
-
-		let _assistant_node =
+		let assistant_node =
 			test::load_test_node(stop_flag.clone(), &mut rng, &assistant_config, "assistant").await;
+		let bootstrap_nodes = vec![test::bootstrap_address(&assistant_node)];
+		let mut relay_config = test::simulated_config("bidirectional", bootstrap_nodes.clone());
+		relay_config.relay_node = Some(true);
+		let source_config = test::simulated_config(openness_source_node, bootstrap_nodes.clone());
+		let target_config = test::simulated_config(openness_target_node, bootstrap_nodes);
+
 		let target_node =
 			test::load_test_node(stop_flag.clone(), &mut rng, &target_config, "target").await;
 		// Load the 'relay' node after the 'target' node, so that the 'target' node
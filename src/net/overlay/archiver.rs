@@ -0,0 +1,191 @@
+//! Archive node mode, in which the node keeps everything of the actor networks
+//! that it joins, to act as a community archive and an always-on seeder.
+//!
+//! A normal node only keeps the most recent objects of an actor, and the files
+//! of those. An archive node goes on to collect the whole blogchain, with all
+//! of its files and blocks, until the block data that it stores reaches the
+//! configured quota. From then on, it only keeps the recent objects like any
+//! other node.
+//!
+//! If `archive_node_discover` is enabled, the node also joins the networks of
+//! the actors that other nodes store at it. Those are admitted only while
+//! there is room for them: the quota may not be reached, the node may not be
+//! shedding load, and no more than `archive_node_max_actors` of them are
+//! archived at a time. Discovered actors are not remembered across restarts,
+//! they are admitted again when they are stored at the node again.
+
+use std::{
+	collections::HashSet,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex as StdMutex,
+	},
+};
+
+use log::*;
+use serde::Serialize;
+
+use super::OverlayNode;
+use crate::{
+	config::Config,
+	core::{ActorAddress, ActorInfo},
+	db::{self, Database, PersistenceHandle},
+};
+
+
+const QUOTA_DEFAULT: u64 = 10 * 1024; // 10 GiB
+const MAX_ACTORS_DEFAULT: usize = 1000;
+
+
+pub struct Archiver {
+	/// The number of bytes of block data that may be stored.
+	quota: u64,
+	/// The number of bytes of block data that is stored, as far as we know.
+	used: AtomicU64,
+	discover: bool,
+	max_discovered_actors: usize,
+	discovered_actors: StdMutex<HashSet<ActorAddress>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ArchiveStats {
+	pub quota: u64,
+	pub used: u64,
+	pub discovered_actors: usize,
+}
+
+
+impl Archiver {
+	/// Returns an archiver if the node is configured to be an archive node.
+	pub fn from_config(config: &Config) -> Option<Self> {
+		if !config.archive_node.unwrap_or(false) {
+			return None;
+		}
+
+		Some(Self {
+			quota: config.archive_node_quota.unwrap_or(QUOTA_DEFAULT) * 1024 * 1024,
+			used: AtomicU64::new(0),
+			discover: config.archive_node_discover.unwrap_or(false),
+			max_discovered_actors: config
+				.archive_node_max_actors
+				.unwrap_or(MAX_ACTORS_DEFAULT),
+			discovered_actors: StdMutex::new(HashSet::new()),
+		})
+	}
+
+	/// Decides whether to start archiving an actor that we've learned about,
+	/// and remembers it if so.
+	pub fn admit(&self, address: &ActorAddress, is_shedding_load: bool) -> bool {
+		if !self.discover || is_shedding_load || self.is_full() {
+			return false;
+		}
+
+		let mut discovered_actors = self.discovered_actors.lock().unwrap();
+		if discovered_actors.len() >= self.max_discovered_actors {
+			return false;
+		}
+		discovered_actors.insert(address.clone())
+	}
+
+	/// Whether the actor has been discovered and is being archived.
+	pub fn is_archiving(&self, address: &ActorAddress) -> bool {
+		self.discovered_actors.lock().unwrap().contains(address)
+	}
+
+	/// The actors that have been discovered and are being archived.
+	pub fn archived_actors(&self) -> Vec<ActorAddress> {
+		self.discovered_actors.lock().unwrap().iter().cloned().collect()
+	}
+
+	pub fn is_full(&self) -> bool { self.used.load(Ordering::Relaxed) >= self.quota }
+
+	/// Accounts for block data that has just been stored.
+	pub fn record_stored(&self, size: usize) { self.used.fetch_add(size as _, Ordering::Relaxed); }
+
+	/// Reads the amount of stored block data from the database again, because
+	/// blocks may have been stored or removed in other ways as well.
+	pub async fn refresh_usage(&self, db: &Database) -> db::Result<()> {
+		let used = db.total_block_size().await?;
+		self.used.store(used, Ordering::Relaxed);
+		Ok(())
+	}
+
+	pub fn stats(&self) -> ArchiveStats {
+		ArchiveStats {
+			quota: self.quota,
+			used: self.used.load(Ordering::Relaxed),
+			discovered_actors: self.discovered_actors.lock().unwrap().len(),
+		}
+	}
+}
+
+/// Joins the network of an actor that has been stored at us, if the archiver
+/// admits it.
+pub fn consider_actor(node: &Arc<OverlayNode>, address: ActorAddress, actor_info: ActorInfo) {
+	match &node.archiver {
+		Some(archiver) if archiver.discover => {}
+		_ => return,
+	}
+
+	let this = node.clone();
+	node.tasks().spawn("actor archiver", async move {
+		let archiver = this.archiver.as_ref().unwrap();
+		// The actors that we follow are archived already
+		if this.get_actor_node(&address.as_id()).await.is_some() {
+			return;
+		}
+		if !archiver.admit(&address, this.load_monitor.is_shedding()) {
+			return;
+		}
+
+		match this.join_actor_network(&address, &actor_info).await {
+			Some(actor_node) => {
+				info!("Archiving actor {}.", &address);
+				actor_node.start_synchronization();
+			}
+			None => {
+				warn!("Unable to join network of actor {} to archive it.", &address);
+				archiver.discovered_actors.lock().unwrap().remove(&address);
+			}
+		}
+	});
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_admission() {
+		let mut rng = test::initialize_rng();
+		let mut config = Config::default();
+		config.archive_node = Some(true);
+		config.archive_node_quota = Some(1);
+		config.archive_node_max_actors = Some(2);
+		let mut address = || ActorAddress::V1(IdType::random(&mut rng));
+
+		// Discovering actors is opt-in
+		let archiver = Archiver::from_config(&config).unwrap();
+		assert!(!archiver.admit(&address(), false));
+
+		config.archive_node_discover = Some(true);
+		let archiver = Archiver::from_config(&config).unwrap();
+		let first = address();
+		assert!(!archiver.admit(&first, true));
+		assert!(archiver.admit(&first, false));
+		assert!(!archiver.admit(&first, false));
+		assert!(archiver.admit(&address(), false));
+		assert!(!archiver.admit(&address(), false));
+		let archived = archiver.archived_actors();
+		assert_eq!(archived.len(), 2);
+		assert!(archived.contains(&first));
+
+		config.archive_node_max_actors = None;
+		let archiver = Archiver::from_config(&config).unwrap();
+		archiver.record_stored(1024 * 1024);
+		assert!(archiver.is_full());
+		assert!(!archiver.admit(&address(), false));
+	}
+}
@@ -0,0 +1,128 @@
+//! Estimates how many nodes take part in the network of an actor, which tells
+//! how likely it is that the actor's content can be found.
+//!
+//! The nodes that store an actor respond to a lookup with a few of the nodes
+//! that they know to be in its network. The estimate is made by sampling those
+//! responses twice, with lookups that start from two random halves of our
+//! fingers, and comparing how many of the nodes were seen both times.
+
+use std::{collections::HashSet, sync::Arc};
+
+use rand::{rngs::OsRng, seq::SliceRandom};
+use serde::Serialize;
+
+use super::OverlayNode;
+use crate::{common::AsyncIterator, core::*};
+
+
+/// The number of responses to collect in each of the sampling rounds.
+const SAMPLES_PER_ROUND: usize = 4;
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ActorAvailability {
+	/// The number of responses that were received from the nodes that store
+	/// the actor.
+	pub samples: usize,
+	/// The number of distinct nodes of the actor network that have been seen.
+	pub seen_nodes: usize,
+	/// The estimated number of nodes in the actor network.
+	pub estimate: usize,
+	/// Whether the estimate is only a lower bound, because the rounds didn't
+	/// see any of the same nodes.
+	pub is_lower_bound: bool,
+	/// Whether we are part of the actor network ourselves. We are not counted
+	/// in the estimate.
+	pub is_connected: bool,
+}
+
+
+/// Estimates the size of a population from two samples of it, with the
+/// Chapman variant of the Lincoln-Petersen method. If the samples don't
+/// overlap, only the number of seen members can be given as a lower bound.
+fn estimate_population<T>(first: &HashSet<T>, second: &HashSet<T>) -> (usize, bool)
+where
+	T: Eq + std::hash::Hash,
+{
+	let seen = first.union(second).count();
+	let overlap = first.intersection(second).count();
+	if overlap == 0 {
+		return (seen, true);
+	}
+
+	let estimate = (first.len() + 1) * (second.len() + 1) / (overlap + 1) - 1;
+	(estimate.max(seen), false)
+}
+
+pub async fn estimate_actor_availability(
+	node: &Arc<OverlayNode>, address: &ActorAddress,
+) -> ActorAvailability {
+	let mut fingers = node
+		.base
+		.find_nearest_private_fingers(&address.as_id())
+		.await;
+	fingers.shuffle(&mut OsRng);
+	let (first_fingers, second_fingers) = fingers.split_at(fingers.len() / 2);
+	let mut samples = 0;
+	let mut rounds = [HashSet::new(), HashSet::new()];
+	for (round, fingers) in rounds.iter_mut().zip([first_fingers, second_fingers]) {
+		if fingers.is_empty() {
+			continue;
+		}
+
+		let mut iter = node
+			.find_actor_iter_from_fingers(address, fingers, 100, false)
+			.await;
+		// The cached value would end up in both rounds
+		iter.skip_cache();
+		for _ in 0..SAMPLES_PER_ROUND {
+			match iter.next().await {
+				None => break,
+				Some(result) => {
+					samples += 1;
+					round.extend(result.1.into_iter().map(|n| n.address));
+				}
+			}
+		}
+	}
+
+	// We know ourselves whether we're in the network or not
+	let our_address = node.node_id();
+	for round in &mut rounds {
+		round.remove(our_address);
+	}
+	let is_connected = node
+		.base
+		.interface
+		.actor_nodes
+		.lock()
+		.await
+		.contains_key(&address.as_id());
+
+	let (estimate, is_lower_bound) = estimate_population(&rounds[0], &rounds[1]);
+	ActorAvailability {
+		samples,
+		seen_nodes: rounds[0].union(&rounds[1]).count(),
+		estimate,
+		is_lower_bound,
+		is_connected,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_estimate_population() {
+		let first: HashSet<u32> = (0..10).collect();
+		let second: HashSet<u32> = (5..15).collect();
+		// (11 * 11) / 6 - 1
+		assert_eq!(estimate_population(&first, &second), (19, false));
+
+		let disjoint: HashSet<u32> = (20..23).collect();
+		assert_eq!(estimate_population(&first, &disjoint), (13, true));
+		assert_eq!(estimate_population(&first, &first), (10, false));
+	}
+}
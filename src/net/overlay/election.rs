@@ -0,0 +1,235 @@
+//! Lets the node decide for itself whether it should be a relay node.
+//!
+//! Only if `relay_election` is enabled, the node keeps measuring how long it
+//! has been running, the highest bandwidth that it reached recently, and
+//! whether any of its transport protocols accepts connections from anyone.
+//! Once all of those meet the configured criteria, the node promotes itself to
+//! a relay node, and once they don't anymore, it demotes itself again. Either
+//! way, the nodes in our routing table are told about the change, so that they
+//! don't have to wait until they happen to ask us for nodes again.
+
+use std::{
+	collections::VecDeque,
+	sync::{atomic::Ordering, Arc},
+	time::{Duration, Instant},
+};
+
+use futures::{future, stream, StreamExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use super::{OverlayNode, OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_REQUEST};
+use crate::{
+	common::AsyncIterator,
+	config::Config,
+	net::{binserde, sstp, ContactInfo, NodeContactInfo, Openness},
+};
+
+
+/// How often the bandwidth is sampled, and the criteria are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+/// The number of bandwidth samples to keep, which covers a day.
+const BANDWIDTH_SAMPLES: usize = 144;
+/// The number of nodes that the relay status is announced to at the same time.
+const ANNOUNCE_CONCURRENCY: usize = 8;
+/// The lowest protocol version of the nodes that know about relay
+/// announcements. Older nodes would take them as garbage.
+const ANNOUNCE_MIN_PROTOCOL_VERSION: u16 = 1;
+
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RelayAnnounceRequest {
+	pub is_relay_node: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RelayAnnounceResponse {}
+
+struct ElectionCriteria {
+	min_uptime: Duration,
+	/// In bytes per second.
+	min_bandwidth: u64,
+	/// The bandwidth that the node is said to have in the config, in bytes per
+	/// second.
+	declared_bandwidth: Option<u64>,
+	require_bidirectional: bool,
+}
+
+struct Measurements {
+	uptime: Duration,
+	/// The bandwidth of the node in bytes per second. This is the declared
+	/// bandwidth if there is one. Otherwise it is the highest throughput that
+	/// was measured recently, which is only a lower bound of the bandwidth, as
+	/// it depends on how much traffic there was to begin with.
+	bandwidth: u64,
+	is_bidirectional: bool,
+}
+
+
+impl ElectionCriteria {
+	fn from_config(config: &Config) -> Option<Self> {
+		if !config.relay_election.unwrap_or(false) {
+			return None;
+		}
+		Some(Self {
+			min_uptime: Duration::from_secs(config.relay_min_uptime.unwrap_or(24) * 3600),
+			min_bandwidth: config.relay_min_bandwidth.unwrap_or(256) * 1024,
+			declared_bandwidth: config.relay_bandwidth.map(|b| b * 1024),
+			require_bidirectional: config.relay_require_bidirectional.unwrap_or(true),
+		})
+	}
+
+	fn is_met(&self, measurements: &Measurements) -> bool {
+		measurements.uptime >= self.min_uptime
+			&& measurements.bandwidth >= self.min_bandwidth
+			&& (measurements.is_bidirectional || !self.require_bidirectional)
+	}
+}
+
+impl OverlayNode {
+	/// Tells the nodes in our routing table whether we are a relay node now.
+	/// Only the nodes that are known to understand the announcement are told.
+	async fn announce_relay_status(&self, is_relay_node: bool) {
+		let request = binserde::serialize(&RelayAnnounceRequest { is_relay_node }).unwrap();
+		let mut fingers = Vec::new();
+		let mut iter = self.base.iter_all_fingers_top_down(0).await;
+		while let Some(finger) = iter.next().await {
+			match self.base.packet_server.peer_version(&finger.address) {
+				Some(version) if version >= ANNOUNCE_MIN_PROTOCOL_VERSION => fingers.push(finger),
+				_ => {}
+			}
+		}
+
+		let announced = stream::iter(fingers)
+			.map(|finger| {
+				let request = &request;
+				async move { self.exchange_relay_announce(&finger, request).await }
+			})
+			.buffer_unordered(ANNOUNCE_CONCURRENCY)
+			.filter(|announced| future::ready(*announced))
+			.count()
+			.await;
+		debug!("Announced relay status to {} nodes.", announced);
+	}
+
+	async fn exchange_relay_announce(&self, finger: &NodeContactInfo, request: &[u8]) -> bool {
+		if let Some((raw_response, c)) = self
+			.base
+			.exchange(finger, OVERLAY_MESSAGE_TYPE_RELAY_ANNOUNCE_REQUEST, request)
+			.await
+		{
+			drop(c);
+			let result: sstp::Result<RelayAnnounceResponse> =
+				binserde::deserialize_sstp(&raw_response);
+			self.base
+				.handle_connection_issue(result, finger)
+				.await
+				.is_some()
+		} else {
+			false
+		}
+	}
+
+	/// Promotes or demotes this node, and announces it if that changed
+	/// anything.
+	async fn elect_relay_status(&self, is_relay_node: bool) {
+		if self.is_relay_node.swap(is_relay_node, Ordering::Relaxed) == is_relay_node {
+			return;
+		}
+
+		if is_relay_node {
+			info!("Promoted this node to a relay node.");
+		} else {
+			info!("Demoted this node from being a relay node.");
+		}
+		self.announce_relay_status(is_relay_node).await;
+	}
+}
+
+/// Whether any of the transport protocols can accept connections from anyone.
+fn is_bidirectional(contact_info: &ContactInfo) -> bool {
+	[&contact_info.ipv4, &contact_info.ipv6]
+		.into_iter()
+		.flatten()
+		.flat_map(|entry| [&entry.availability.udp, &entry.availability.tcp])
+		.flatten()
+		.any(|entry| entry.openness == Openness::Bidirectional)
+}
+
+/// Starts electing this node as a relay node or not, if enabled.
+pub fn maintain_relay_election(node: Arc<OverlayNode>, config: &Config) {
+	let criteria = match ElectionCriteria::from_config(config) {
+		Some(c) => c,
+		None => return,
+	};
+	node.tasks()
+		.spawn("relay election", keep_electing(node.clone(), criteria));
+}
+
+async fn keep_electing(node: Arc<OverlayNode>, criteria: ElectionCriteria) {
+	let started = Instant::now();
+	let mut samples = VecDeque::with_capacity(BANDWIDTH_SAMPLES);
+	let mut last_transferred = node.base.packet_server.transferred_bytes();
+	while node.sleep_while_running(CHECK_INTERVAL).await {
+		let transferred = node.base.packet_server.transferred_bytes();
+		if samples.len() == BANDWIDTH_SAMPLES {
+			samples.pop_front();
+		}
+		samples.push_back((transferred - last_transferred) / CHECK_INTERVAL.as_secs());
+		last_transferred = transferred;
+
+		let measurements = Measurements {
+			uptime: started.elapsed(),
+			bandwidth: criteria
+				.declared_bandwidth
+				.unwrap_or_else(|| samples.iter().max().cloned().unwrap_or(0)),
+			is_bidirectional: is_bidirectional(&node.contact_info()),
+		};
+		node.elect_relay_status(criteria.is_met(&measurements))
+			.await;
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_election_criteria() {
+		let mut config = Config::default();
+		assert!(ElectionCriteria::from_config(&config).is_none());
+
+		config.relay_election = Some(true);
+		config.relay_min_uptime = Some(1);
+		config.relay_min_bandwidth = Some(100);
+		let criteria = ElectionCriteria::from_config(&config).unwrap();
+		let mut measurements = Measurements {
+			uptime: Duration::from_secs(3600),
+			bandwidth: 100 * 1024,
+			is_bidirectional: true,
+		};
+		assert!(criteria.is_met(&measurements));
+
+		measurements.is_bidirectional = false;
+		assert!(!criteria.is_met(&measurements));
+		measurements.is_bidirectional = true;
+		measurements.uptime = Duration::from_secs(3599);
+		assert!(!criteria.is_met(&measurements));
+		measurements.uptime = Duration::from_secs(3600);
+		measurements.bandwidth -= 1;
+		assert!(!criteria.is_met(&measurements));
+
+		config.relay_require_bidirectional = Some(false);
+		let criteria = ElectionCriteria::from_config(&config).unwrap();
+		measurements.bandwidth += 1;
+		measurements.is_bidirectional = false;
+		assert!(criteria.is_met(&measurements));
+
+		// The declared bandwidth is in KiB/s as well
+		assert_eq!(criteria.declared_bandwidth, None);
+		config.relay_bandwidth = Some(50);
+		let criteria = ElectionCriteria::from_config(&config).unwrap();
+		assert_eq!(criteria.declared_bandwidth, Some(50 * 1024));
+	}
+}
@@ -0,0 +1,86 @@
+//! Enforces the `max_storage` quota on the block data that is stored.
+//!
+//! The amount of stored block data is checked periodically. Once it exceeds
+//! the quota, the blocks that aren't pinned are evicted until it fits again.
+//! On an archive node, the actors that it archives are pinned as well.
+//! See the `eviction` module of `db` for the order in which that happens.
+
+use std::{sync::Arc, time::Duration};
+
+use log::*;
+
+use super::OverlayNode;
+use crate::{
+	core::ActorAddress,
+	db::{self, PersistenceHandle},
+};
+
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+
+impl OverlayNode {
+	/// Evicts blocks until the stored block data fits within the quota again.
+	async fn enforce_storage_quota(&self, quota: u64) -> db::Result<()> {
+		let used = self.db().total_block_size().await?;
+		if used <= quota {
+			return Ok(());
+		}
+
+		// An archive node would otherwise evict the very actors that it archives
+		let mut pinned_actors: Vec<ActorAddress> =
+			self.tracked_actors.lock().await.keys().cloned().collect();
+		if let Some(archiver) = &self.archiver {
+			pinned_actors.extend(archiver.archived_actors());
+		}
+		let mut freed = 0;
+		while freed < used - quota {
+			let evicted = self
+				.db()
+				.evict_blocks(
+					&pinned_actors,
+					self.node_id().as_id().as_ref(),
+					used - quota - freed,
+				)
+				.await?;
+			if evicted == 0 {
+				break;
+			}
+			freed += evicted;
+		}
+
+		if freed < used - quota {
+			warn!(
+				"Storage quota exceeded by {} bytes, but all remaining blocks are pinned.",
+				used - quota - freed
+			);
+		}
+		if freed > 0 {
+			info!("Evicted {} bytes of block data.", freed);
+		}
+		Ok(())
+	}
+}
+
+/// Starts enforcing the storage quota, if one has been configured.
+pub fn maintain_storage_quota(node: Arc<OverlayNode>, max_storage: Option<u64>) {
+	let quota = match max_storage {
+		Some(q) => q * 1024 * 1024,
+		None => return,
+	};
+	node.tasks()
+		.spawn("storage quota", keep_enforcing(node.clone(), quota));
+}
+
+async fn keep_enforcing(node: Arc<OverlayNode>, quota: u64) {
+	loop {
+		if let Err(e) = node.enforce_storage_quota(quota).await {
+			node.db().observe_error(&e);
+			error!("Unable to enforce storage quota: {}", e);
+		}
+
+		if !node.sleep_while_running(CHECK_INTERVAL).await {
+			break;
+		}
+	}
+}
@@ -0,0 +1,207 @@
+//! Leaves the actor networks that this node doesn't need anymore, and keeps
+//! the history of the other actors within the retention policies.
+//!
+//! Once an actor isn't followed anymore, and isn't tracked or archived either,
+//! its actor node is closed right away, so that it stops taking up
+//! connections. Its objects and files are only removed once the grace period
+//! has passed, which is checked every hour.
+//!
+//! The actors that are still followed are pruned according to the retention
+//! policies every few hours, if any have been configured. See the `prune`
+//! module of `db` for what is kept regardless. Actors that are tracked or
+//! archived are never pruned.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use log::*;
+
+use super::OverlayNode;
+use crate::{
+	common::current_timestamp,
+	config::Config,
+	core::{ActorAddress, Address},
+	db::{self, prune::RetentionPolicy, PersistenceHandle},
+};
+
+
+/// The number of days the data of an unfollowed actor is kept by default.
+const GRACE_PERIOD_DEFAULT: u32 = 7;
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+
+
+/// The retention policies that have been configured.
+pub struct RetentionPolicies {
+	default: RetentionPolicy,
+	overrides: HashMap<ActorAddress, RetentionPolicy>,
+}
+
+
+impl RetentionPolicies {
+	pub fn from_config(config: &Config) -> Self {
+		let default = RetentionPolicy {
+			max_age: config.retention_max_age,
+			max_size: config.retention_max_size.map(|s| s * 1024 * 1024),
+		};
+		let mut overrides = HashMap::new();
+		for entry in config.retention_override.iter().flatten() {
+			let address = match Address::from_str(&entry.actor) {
+				Ok(Address::Actor(a)) => a,
+				_ => {
+					error!(
+						"Invalid actor address in retention_override config parameter: {}",
+						&entry.actor
+					);
+					continue;
+				}
+			};
+			// A limit of zero lifts the default limit
+			let policy = RetentionPolicy {
+				max_age: match entry.max_age {
+					Some(0) => None,
+					Some(days) => Some(days),
+					None => default.max_age,
+				},
+				max_size: match entry.max_size {
+					Some(0) => None,
+					Some(size) => Some(size * 1024 * 1024),
+					None => default.max_size,
+				},
+			};
+			overrides.insert(address, policy);
+		}
+		Self { default, overrides }
+	}
+
+	pub fn is_limited(&self) -> bool {
+		self.default.is_limited() || self.overrides.values().any(|p| p.is_limited())
+	}
+
+	pub fn policy_for(&self, address: &ActorAddress) -> &RetentionPolicy {
+		self.overrides.get(address).unwrap_or(&self.default)
+	}
+}
+
+
+impl OverlayNode {
+	/// Leaves the network of an actor that has just been unfollowed, unless
+	/// the actor is still needed, and starts its grace period.
+	/// Returns whether the network has been left.
+	pub async fn abandon_actor_network(&self, address: &ActorAddress) -> db::Result<bool> {
+		if self.is_actor_pinned(address).await || self.db().is_actor_in_use(address).await? {
+			return Ok(false);
+		}
+
+		self.drop_actor_network(&address.as_id()).await;
+		self.db().abandon_actor(address).await?;
+		Ok(true)
+	}
+
+	/// Whether the actor is tracked or archived by this node, in which case its
+	/// network is kept regardless of whether it is followed.
+	async fn is_actor_pinned(&self, address: &ActorAddress) -> bool {
+		if self.tracked_actors.lock().await.contains_key(address) {
+			return true;
+		}
+		match &self.archiver {
+			Some(archiver) => archiver.is_archiving(address),
+			None => false,
+		}
+	}
+
+	/// Removes the objects of the other actors that their retention policy
+	/// doesn't allow to be kept, and the files and blocks that aren't used
+	/// anymore.
+	async fn prune_actors(&self) -> db::Result<()> {
+		let mut removed = 0;
+		for (actor_id, address) in self.db().fetch_foreign_actors().await? {
+			if self.is_actor_pinned(&address).await {
+				continue;
+			}
+			removed += self
+				.db()
+				.prune_actor(actor_id, self.retention.policy_for(&address))
+				.await?;
+		}
+		if removed > 0 {
+			let blocks = self.db().prune_orphans().await?;
+			info!(
+				"Pruned {} objects and {} blocks of other actors.",
+				removed, blocks
+			);
+		}
+		Ok(())
+	}
+
+	/// The timestamp before which the objects of the actor don't need to be
+	/// synchronized, because they would be pruned anyway.
+	pub async fn retention_min_created(
+		&self, address: &ActorAddress, actor_id: i64,
+	) -> db::Result<u64> {
+		if self.is_actor_pinned(address).await || self.db().has_identity(actor_id).await? {
+			return Ok(0);
+		}
+		Ok(self.retention.policy_for(address).min_created())
+	}
+
+	/// Removes the data of the actors of which the grace period has passed.
+	async fn purge_abandoned_actors(&self, grace_period_days: u32) -> db::Result<()> {
+		let abandoned_before =
+			current_timestamp() as i64 - grace_period_days as i64 * 24 * 3600 * 1000;
+		for (actor_id, address) in self.db().fetch_abandoned_actors(abandoned_before).await? {
+			// The actor may have been followed again in the meantime
+			if self.is_actor_pinned(&address).await
+				|| self.db().is_actor_in_use(&address).await?
+				|| self.get_actor_node(&address.as_id()).await.is_some()
+			{
+				self.db().keep_actor(actor_id).await?;
+				continue;
+			}
+
+			let removed = self.db().purge_actor(actor_id).await?;
+			info!(
+				"Removed {} objects of unfollowed actor {}.",
+				removed, &address
+			);
+		}
+		Ok(())
+	}
+}
+
+pub fn maintain_retention(node: Arc<OverlayNode>, grace_period_days: Option<u32>) {
+	let grace_period_days = grace_period_days.unwrap_or(GRACE_PERIOD_DEFAULT);
+	node.tasks().spawn(
+		"actor data purger",
+		keep_purging(node.clone(), grace_period_days),
+	);
+	if node.retention.is_limited() {
+		node.tasks()
+			.spawn("actor data pruner", keep_pruning(node.clone()));
+	}
+}
+
+async fn keep_purging(node: Arc<OverlayNode>, grace_period_days: u32) {
+	loop {
+		if let Err(e) = node.purge_abandoned_actors(grace_period_days).await {
+			node.db().observe_error(&e);
+			error!("Unable to remove data of unfollowed actors: {}", e);
+		}
+
+		if !node.sleep_while_running(PURGE_INTERVAL).await {
+			break;
+		}
+	}
+}
+
+async fn keep_pruning(node: Arc<OverlayNode>) {
+	loop {
+		if let Err(e) = node.prune_actors().await {
+			node.db().observe_error(&e);
+			error!("Unable to prune data of other actors: {}", e);
+		}
+
+		if !node.sleep_while_running(PRUNE_INTERVAL).await {
+			break;
+		}
+	}
+}
@@ -0,0 +1,179 @@
+//! Decides what this node re-serves to other nodes.
+//!
+//! By default, every block that this node has is served to anyone in the
+//! network of the actor that asks for it. The seeding policy can narrow that
+//! down to the blocks of our own identities, or to those of the actors that we
+//! follow as well. On top of that, the number of peers that are served blocks
+//! at the same time can be limited with upload slots, and the rate at which the
+//! blocks of a single file are served can be capped. Requests that aren't
+//! served are answered as if we didn't have the block, so that the requester
+//! looks for it elsewhere.
+
+use std::{
+	collections::HashMap,
+	sync::Mutex as StdMutex,
+	time::{Duration, Instant},
+};
+
+use log::*;
+
+use crate::{
+	common::IdType,
+	config::Config,
+	core::NodeAddress,
+	db::BLOCK_SIZE,
+};
+
+
+/// A peer keeps its upload slot until it hasn't requested a block for this
+/// long.
+const SLOT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Once the bandwidth of this many files is being tracked, the files that have
+/// their full allowance again are forgotten about.
+const TRACKED_FILES_LIMIT: usize = 1000;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedingPolicy {
+	/// Only serve the blocks of our own identities.
+	Own,
+	/// Serve the blocks of our own identities and the actors that we follow.
+	Following,
+	/// Serve every block that we have.
+	All,
+}
+
+pub struct Seeder {
+	pub policy: SeedingPolicy,
+	/// The maximum number of peers to serve blocks to at the same time, or zero
+	/// if unlimited.
+	upload_slots: usize,
+	/// The peers that hold an upload slot, and when they last requested a block.
+	slots: StdMutex<HashMap<NodeAddress, Instant>>,
+	/// The number of bytes per second that the blocks of a single file may be
+	/// served at, or zero if unlimited.
+	file_rate: f64,
+	file_allowances: StdMutex<HashMap<IdType, Allowance>>,
+}
+
+#[derive(Clone, Copy)]
+struct Allowance {
+	bytes: f64,
+	updated: Instant,
+}
+
+
+impl SeedingPolicy {
+	fn from_config(config: &Config) -> Self {
+		match config.seeding_policy.as_deref() {
+			None | Some("all") => Self::All,
+			Some("following") => Self::Following,
+			Some("own") => Self::Own,
+			Some(other) => {
+				error!(
+					"Unknown seeding policy \"{}\", serving everything instead.",
+					other
+				);
+				Self::All
+			}
+		}
+	}
+}
+
+impl Seeder {
+	pub fn new(config: &Config) -> Self {
+		Self {
+			policy: SeedingPolicy::from_config(config),
+			upload_slots: config.upload_slots.unwrap_or(0),
+			slots: StdMutex::new(HashMap::new()),
+			file_rate: config.upload_rate_per_file.unwrap_or(0) as f64 * 1024.0,
+			file_allowances: StdMutex::new(HashMap::new()),
+		}
+	}
+
+	/// The most bytes that a file can have saved up. At least one full block,
+	/// otherwise the largest blocks could never be served.
+	fn file_burst(&self) -> f64 { self.file_rate.max(BLOCK_SIZE as f64) }
+
+	/// Takes an upload slot for the peer, or renews the one that it already
+	/// has. Returns whether the peer may be served.
+	pub fn take_slot(&self, peer: &NodeAddress) -> bool {
+		if self.upload_slots == 0 {
+			return true;
+		}
+
+		let now = Instant::now();
+		let mut slots = self.slots.lock().unwrap();
+		if !slots.contains_key(peer) {
+			slots.retain(|_, last| now.duration_since(*last) < SLOT_IDLE_TIMEOUT);
+			if slots.len() >= self.upload_slots {
+				return false;
+			}
+		}
+		slots.insert(peer.clone(), now);
+		true
+	}
+
+	/// Takes the given number of bytes from the allowance of the file. Returns
+	/// whether there was enough allowance left.
+	pub fn take_bandwidth(&self, file_hash: &IdType, size: u64) -> bool {
+		if self.file_rate == 0.0 {
+			return true;
+		}
+
+		let now = Instant::now();
+		let burst = self.file_burst();
+		let mut allowances = self.file_allowances.lock().unwrap();
+		if allowances.len() >= TRACKED_FILES_LIMIT && !allowances.contains_key(file_hash) {
+			allowances.retain(|_, a| self.allowance_at(a, now) < burst);
+		}
+
+		let allowance = allowances.entry(file_hash.clone()).or_insert(Allowance {
+			bytes: burst,
+			updated: now,
+		});
+		allowance.bytes = self.allowance_at(allowance, now);
+		allowance.updated = now;
+		if allowance.bytes < size as f64 {
+			return false;
+		}
+		allowance.bytes -= size as f64;
+		true
+	}
+
+	fn allowance_at(&self, allowance: &Allowance, now: Instant) -> f64 {
+		let elapsed = now.duration_since(allowance.updated).as_secs_f64();
+		(allowance.bytes + elapsed * self.file_rate).min(self.file_burst())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test;
+
+	#[test]
+	fn test_seeder() {
+		let mut config = Config::default();
+		config.seeding_policy = Some("following".to_string());
+		config.upload_slots = Some(1);
+		config.upload_rate_per_file = Some(1024);
+		let seeder = Seeder::new(&config);
+		assert_eq!(seeder.policy, SeedingPolicy::Following);
+
+		let mut rng = test::initialize_rng();
+		let peer = NodeAddress::V1(IdType::random(&mut rng));
+		let other_peer = NodeAddress::V1(IdType::random(&mut rng));
+		assert!(seeder.take_slot(&peer));
+		assert!(seeder.take_slot(&peer));
+		assert!(!seeder.take_slot(&other_peer));
+
+		// A file can be served a full block at once, but not two
+		let file_hash = IdType::random(&mut rng);
+		let other_file_hash = IdType::random(&mut rng);
+		assert!(seeder.take_bandwidth(&file_hash, BLOCK_SIZE as _));
+		assert!(!seeder.take_bandwidth(&file_hash, BLOCK_SIZE as _));
+		assert!(seeder.take_bandwidth(&other_file_hash, BLOCK_SIZE as _));
+	}
+}
@@ -0,0 +1,110 @@
+//! Records the traffic of the node into the database every few minutes, so
+//! that it can be looked back on per hour. The traffic with each other node is
+//! recorded per day. Whatever is older than the configured number of days is
+//! removed again.
+
+use std::{sync::Arc, time::Duration};
+
+use log::*;
+
+use super::OverlayNode;
+use crate::{common::current_timestamp, entity::network_stats, net::sstp::TrafficStats};
+
+
+const RECORD_INTERVAL: Duration = Duration::from_secs(300);
+/// The number of days the statistics are kept by default.
+const RETENTION_DEFAULT: u32 = 30;
+
+
+/// The counters of the network stack at some point in time.
+#[derive(Default)]
+struct Snapshot {
+	traffic: TrafficStats,
+	relayed_sessions: u64,
+	relayed_bytes: u64,
+	find_node_lookups: u64,
+	find_value_lookups: u64,
+}
+
+
+impl Snapshot {
+	async fn take(node: &OverlayNode) -> Self {
+		let relay = node.relay_stats().await;
+		let network = node.network_stats();
+		Self {
+			traffic: node.base.packet_server.traffic_stats(),
+			relayed_sessions: relay.opened_sessions,
+			relayed_bytes: relay.relayed_bytes,
+			find_node_lookups: network.find_node.completed,
+			find_value_lookups: network.find_value.completed,
+		}
+	}
+
+	/// How much the counters went up since the previous snapshot.
+	fn increase_since(&self, previous: &Self) -> network_stats::Model {
+		let increase = |current: u64, previous: u64| current.saturating_sub(previous) as i64;
+		network_stats::Model {
+			udp_bytes_in: increase(self.traffic.udp_bytes_in, previous.traffic.udp_bytes_in),
+			udp_bytes_out: increase(self.traffic.udp_bytes_out, previous.traffic.udp_bytes_out),
+			tcp_bytes_in: increase(self.traffic.tcp_bytes_in, previous.traffic.tcp_bytes_in),
+			tcp_bytes_out: increase(self.traffic.tcp_bytes_out, previous.traffic.tcp_bytes_out),
+			opened_connections: increase(
+				self.traffic.opened_connections,
+				previous.traffic.opened_connections,
+			),
+			relayed_sessions: increase(self.relayed_sessions, previous.relayed_sessions),
+			relayed_bytes: increase(self.relayed_bytes, previous.relayed_bytes),
+			find_node_lookups: increase(self.find_node_lookups, previous.find_node_lookups),
+			find_value_lookups: increase(self.find_value_lookups, previous.find_value_lookups),
+			..Default::default()
+		}
+	}
+}
+
+
+pub fn maintain_stats_history(node: Arc<OverlayNode>, retention_days: Option<u32>) {
+	let retention_days = retention_days.unwrap_or(RETENTION_DEFAULT);
+	if retention_days == 0 {
+		return;
+	}
+	node.tasks().spawn(
+		"network stats recorder",
+		keep_recording(node.clone(), retention_days),
+	);
+}
+
+async fn keep_recording(node: Arc<OverlayNode>, retention_days: u32) {
+	// The counters start at zero when the node starts, except for the traffic
+	// per node, which only starts being counted now
+	let mut previous = Snapshot::default();
+	node.base.packet_server.take_peer_traffic();
+	while node.sleep_while_running(RECORD_INTERVAL).await {
+		let current = Snapshot::take(&node).await;
+		let mut stats = current.increase_since(&previous);
+		let now = (current_timestamp() / 1000) as i64;
+		stats.hour = now - now % 3600;
+		stats.max_sessions = node.base.packet_server.session_count().await.0 as _;
+		// If recording fails, the increases are recorded the next time instead
+		if let Err(e) = node.db().add_network_stats(&stats).await {
+			node.db().observe_error(&e);
+			error!("Unable to record network statistics: {}", e);
+			continue;
+		}
+		previous = current;
+
+		// The traffic per node is simply lost if it can't be recorded
+		let peer_traffic = node.base.packet_server.take_peer_traffic();
+		let day = now - now % (24 * 3600);
+		if let Err(e) = node.db().add_peer_traffic(day, &peer_traffic).await {
+			error!("Unable to record the traffic per node: {}", e);
+		}
+
+		let before = stats.hour - retention_days as i64 * 24 * 3600;
+		if let Err(e) = node.db().prune_network_stats(before).await {
+			error!("Unable to remove old network statistics: {}", e);
+		}
+		if let Err(e) = node.db().prune_peer_traffic(before).await {
+			error!("Unable to remove the old traffic per node: {}", e);
+		}
+	}
+}
@@ -0,0 +1,267 @@
+//! Opt-in telemetry, to give the people researching the network an idea of
+//! what it looks like.
+//!
+//! Only if `telemetry` is enabled, the node sends a report to the configured
+//! telemetry node once a day. The report only contains a few coarse counters:
+//! the minor version of Stonenet, the openness of the node on each IP version,
+//! and an estimate of the size of the network that is rounded down to a power
+//! of two. No addresses, actors or any other content are included in it. The
+//! report that would be sent can always be previewed through the API.
+//!
+//! A node that has `telemetry_collector` enabled accepts these reports and
+//! keeps the latest one of each node, so that it can tell the distribution of
+//! those counters over the network.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Arc, Mutex as StdMutex},
+	time::{Duration, Instant},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{OverlayNode, OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_REQUEST};
+use crate::{
+	core::NodeAddress,
+	net::{binserde, sstp, IpAvailability, Openness},
+	serde_limit::{LimString, Limit32},
+};
+
+
+/// The time to wait before the first report, so that the routing table has
+/// been filled a bit.
+const FIRST_REPORT_DELAY: Duration = Duration::from_secs(600);
+const REPORT_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+/// Reports that haven't been renewed within this time are forgotten.
+const REPORT_EXPIRY: Duration = Duration::from_secs(2 * 24 * 3600);
+/// The maximum number of nodes a collector keeps a report of.
+const MAX_REPORTS: usize = 100_000;
+
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TelemetryReport {
+	/// The version of Stonenet, without the patch number.
+	pub version: LimString<Limit32>,
+	/// An estimate of the number of nodes on the network, rounded down to a
+	/// power of two.
+	pub node_count_estimate: Option<u64>,
+	pub ipv4_openness: Option<Openness>,
+	pub ipv6_openness: Option<Openness>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TelemetryReportResponse {
+	pub accepted: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TelemetryStats {
+	/// The number of nodes that have reported recently.
+	pub reporters: usize,
+	pub versions: BTreeMap<String, u64>,
+	pub node_count_estimates: BTreeMap<u64, u64>,
+	pub ipv4_openness: BTreeMap<String, u64>,
+	pub ipv6_openness: BTreeMap<String, u64>,
+}
+
+/// Keeps the reports that other nodes have sent to us.
+pub struct TelemetryCollector {
+	reports: StdMutex<HashMap<NodeAddress, (Instant, TelemetryReport)>>,
+}
+
+
+impl TelemetryCollector {
+	pub fn new() -> Self {
+		Self {
+			reports: StdMutex::new(HashMap::new()),
+		}
+	}
+
+	/// Remembers the report of the given node, replacing the one it sent
+	/// before. Returns false if there is no more room for it.
+	pub fn collect(&self, node_address: &NodeAddress, report: TelemetryReport) -> bool {
+		let mut reports = self.reports.lock().unwrap();
+		if reports.len() >= MAX_REPORTS && !reports.contains_key(node_address) {
+			reports.retain(|_, (received, _)| received.elapsed() < REPORT_EXPIRY);
+			if reports.len() >= MAX_REPORTS {
+				return false;
+			}
+		}
+		reports.insert(node_address.clone(), (Instant::now(), report));
+		true
+	}
+
+	pub fn stats(&self) -> TelemetryStats {
+		fn openness_key(openness: &Option<Openness>) -> String {
+			openness
+				.map(|o| o.to_string())
+				.unwrap_or_else(|| "none".to_string())
+		}
+
+		let mut stats = TelemetryStats::default();
+		let reports = self.reports.lock().unwrap();
+		for (received, report) in reports.values() {
+			if received.elapsed() >= REPORT_EXPIRY {
+				continue;
+			}
+
+			stats.reporters += 1;
+			*stats
+				.versions
+				.entry(report.version.to_string())
+				.or_default() += 1;
+			if let Some(estimate) = report.node_count_estimate {
+				*stats.node_count_estimates.entry(estimate).or_default() += 1;
+			}
+			*stats
+				.ipv4_openness
+				.entry(openness_key(&report.ipv4_openness))
+				.or_default() += 1;
+			*stats
+				.ipv6_openness
+				.entry(openness_key(&report.ipv6_openness))
+				.or_default() += 1;
+		}
+		stats
+	}
+}
+
+/// Estimates the number of nodes on the network from the number of nodes in
+/// each bucket of the routing table. A bucket at index `i` covers 1/2^(i+1)
+/// of the ID space, so if it isn't full, it should hold about that fraction
+/// of all nodes. The median of the estimates of those buckets is taken.
+fn estimate_node_count(buckets: &[(usize, usize)], bucket_size: usize) -> Option<u64> {
+	let mut estimates: Vec<u64> = buckets
+		.iter()
+		.filter(|(index, count)| *count > 0 && *count < bucket_size && *index < 63)
+		.map(|(index, count)| (*count as u64) << (index + 1))
+		.collect();
+	if estimates.is_empty() {
+		return None;
+	}
+	estimates.sort();
+	let median = estimates[estimates.len() / 2];
+	// Round it down to a power of two, so that it doesn't say much about our
+	// own routing table.
+	Some(1 << (63 - median.leading_zeros()))
+}
+
+/// The best openness that is configured or detected for an IP version.
+fn ip_openness(availability: Option<&IpAvailability>) -> Option<Openness> {
+	let availability = availability?;
+	[&availability.udp, &availability.tcp]
+		.into_iter()
+		.flatten()
+		.map(|entry| entry.openness)
+		.reduce(|a, b| if b > a { b } else { a })
+}
+
+pub fn maintain_telemetry(node: Arc<OverlayNode>, telemetry_node: NodeAddress) {
+	node.tasks().spawn(
+		"telemetry reporter",
+		keep_reporting_telemetry(node.clone(), telemetry_node),
+	);
+}
+
+async fn keep_reporting_telemetry(node: Arc<OverlayNode>, telemetry_node: NodeAddress) {
+	let mut delay = FIRST_REPORT_DELAY;
+	while node.sleep_while_running(delay).await {
+		let report = prepare_report(&node).await;
+		match report_telemetry(&node, &telemetry_node, &report).await {
+			None => warn!("Unable to send telemetry report to {}.", &telemetry_node),
+			Some(false) => warn!("Telemetry report not accepted by {}.", &telemetry_node),
+			Some(true) => {}
+		}
+		delay = REPORT_INTERVAL;
+	}
+}
+
+/// Puts together the report that is sent to the telemetry node.
+pub async fn prepare_report(node: &OverlayNode) -> TelemetryReport {
+	let buckets: Vec<(usize, usize)> = node
+		.routing_table()
+		.await
+		.into_iter()
+		.map(|b| (b.index, b.entries.iter().filter(|e| !e.replacement).count()))
+		.collect();
+	let contact_info = node.contact_info();
+	TelemetryReport {
+		version: format!(
+			"{}.{}",
+			env!("CARGO_PKG_VERSION_MAJOR"),
+			env!("CARGO_PKG_VERSION_MINOR")
+		)
+		.into(),
+		node_count_estimate: estimate_node_count(&buckets, node.base.bucket_size),
+		ipv4_openness: ip_openness(contact_info.ipv4.as_ref().map(|e| &e.availability)),
+		ipv6_openness: ip_openness(contact_info.ipv6.as_ref().map(|e| &e.availability)),
+	}
+}
+
+async fn report_telemetry(
+	node: &OverlayNode, telemetry_node: &NodeAddress, report: &TelemetryReport,
+) -> Option<bool> {
+	let contact_info = node.find_node(telemetry_node).await?;
+	let raw_request = binserde::serialize(report).unwrap();
+	let (raw_response, c) = node
+		.base
+		.exchange(
+			&contact_info,
+			OVERLAY_MESSAGE_TYPE_TELEMETRY_REPORT_REQUEST,
+			&raw_request,
+		)
+		.await?;
+	let their_node_info = c.their_node_info().clone();
+	drop(c);
+	let result: sstp::Result<_> = binserde::deserialize_sstp(&raw_response);
+	let response: TelemetryReportResponse = node
+		.base
+		.handle_connection_issue(result, &their_node_info)
+		.await?;
+	Some(response.accepted)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_estimate_node_count() {
+		// Full buckets are ignored, as they only give a lower bound.
+		assert_eq!(estimate_node_count(&[(0, 4), (1, 4)], 4), None);
+		// 2 << 7, 3 << 8 and 3 << 9 make a median of 768, rounded down to 512.
+		assert_eq!(
+			estimate_node_count(&[(0, 4), (6, 2), (7, 3), (8, 3)], 4),
+			Some(512)
+		);
+	}
+
+	#[test]
+	fn test_telemetry_collector() {
+		let mut rng = test::initialize_rng();
+		let collector = TelemetryCollector::new();
+		let report = |version: &str, openness| TelemetryReport {
+			version: version.into(),
+			node_count_estimate: Some(1024),
+			ipv4_openness: openness,
+			ipv6_openness: None,
+		};
+
+		let address = NodeAddress::V1(IdType::random(&mut rng));
+		assert!(collector.collect(&address, report("0.6", None)));
+		assert!(collector.collect(&address, report("0.7", Some(Openness::Punchable))));
+		let other_address = NodeAddress::V1(IdType::random(&mut rng));
+		assert!(collector.collect(&other_address, report("0.7", None)));
+
+		let stats = collector.stats();
+		assert_eq!(stats.reporters, 2);
+		assert_eq!(stats.versions.get("0.6"), None);
+		assert_eq!(stats.versions.get("0.7"), Some(&2));
+		assert_eq!(stats.node_count_estimates.get(&1024), Some(&2));
+		assert_eq!(stats.ipv4_openness.get("punchable"), Some(&1));
+		assert_eq!(stats.ipv6_openness.get("none"), Some(&2));
+	}
+}
@@ -2,7 +2,7 @@ use std::{sync::Arc, time::Duration};
 
 use log::error;
 use sea_orm::{prelude::*, ActiveValue::*, QueryOrder};
-use tokio::{spawn, time::sleep};
+use tokio::time::sleep;
 
 use super::{
 	current_timestamp, message::ListTrustedNodesResult, IdType, NodeAddress, NodeContactInfo,
@@ -24,7 +24,10 @@ fn generate_hash(list: &[(NodeAddress, u8)]) -> IdType {
 	IdType::hash(&data)
 }
 
-pub fn maintain_trust_web(node: Arc<OverlayNode>) { spawn(keep_updating_trust_web(node)); }
+pub fn maintain_trust_web(node: Arc<OverlayNode>) {
+	node.tasks()
+		.spawn("trust web updater", keep_updating_trust_web(node.clone()));
+}
 
 async fn keep_updating_trust_web(node: Arc<OverlayNode>) {
 	while node.base.is_running() {
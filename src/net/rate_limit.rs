@@ -0,0 +1,261 @@
+//! Limits the rate at which other peers can make requests to us.
+//!
+//! Every peer has a bucket of tokens that refills at a fixed rate, and each
+//! request takes one token out of it. Requests for data, like the blocks of a
+//! file, only take a part of a token, and keep-alive requests none at all.
+//! Requests that arrive while the bucket is empty are dropped. Peers are
+//! tracked both by their node ID and by their IP address, because node IDs are
+//! cheap to come by. The web interface uses the same limiter for the requests
+//! made to it, by IP address only.
+
+use std::{
+	collections::HashMap,
+	hash::Hash,
+	net::IpAddr,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+	time::Instant,
+};
+
+use serde::Serialize;
+
+use super::{
+	actor::{ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST, ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST},
+	node::NETWORK_MESSAGE_TYPE_FIND_VALUE_REQUEST,
+	overlay::OVERLAY_MESSAGE_TYPE_KEEP_ALIVE_REQUEST,
+};
+use crate::{config::Config, core::NodeAddress};
+
+
+const REQUEST_RATE_DEFAULT: u32 = 20;
+const REQUEST_BURST_DEFAULT: u32 = 100;
+/// Once this many peers are being tracked, the ones with a full bucket are
+/// forgotten about again.
+const TRACKED_PEERS_LIMIT: usize = 10000;
+const TRACKED_PEERS_LIMIT_LOW_MEMORY: usize = 1000;
+/// The part of a token that a request for data takes. Downloading a file takes
+/// a request for every block of it, which would empty a bucket in no time
+/// otherwise.
+const DATA_REQUEST_COST: f64 = 0.1;
+
+
+pub struct RateLimiter {
+	/// The number of tokens that are added to each bucket per second, or zero if
+	/// rate limiting is disabled.
+	rate: f64,
+	burst: f64,
+	tracked_peers_limit: usize,
+	node_buckets: Mutex<HashMap<NodeAddress, Bucket>>,
+	ip_buckets: Mutex<HashMap<IpAddr, Bucket>>,
+	accepted: AtomicU64,
+	rejected: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RateLimitStats {
+	pub accepted: u64,
+	pub rejected: u64,
+	pub tracked_nodes: usize,
+	pub tracked_ips: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+
+impl Bucket {
+	/// The number of tokens in the bucket at the given time.
+	fn tokens_at(&self, now: Instant, rate: f64, burst: f64) -> f64 {
+		let elapsed = now.duration_since(self.updated).as_secs_f64();
+		(self.tokens + elapsed * rate).min(burst)
+	}
+}
+
+impl RateLimiter {
+	pub fn new(config: &Config) -> Self {
+		Self::with_limits(
+			config,
+			config.request_rate_limit.unwrap_or(REQUEST_RATE_DEFAULT),
+			config.request_rate_burst.unwrap_or(REQUEST_BURST_DEFAULT),
+		)
+	}
+
+	/// Creates a limiter with the given rate and burst instead of the ones of
+	/// the network.
+	pub fn with_limits(config: &Config, rate: u32, burst: u32) -> Self {
+		let tracked_peers_limit = if config.low_memory.unwrap_or(false) {
+			TRACKED_PEERS_LIMIT_LOW_MEMORY
+		} else {
+			TRACKED_PEERS_LIMIT
+		};
+		Self {
+			rate: rate as f64,
+			burst: burst.max(1) as f64,
+			tracked_peers_limit,
+			node_buckets: Mutex::new(HashMap::new()),
+			ip_buckets: Mutex::new(HashMap::new()),
+			accepted: AtomicU64::new(0),
+			rejected: AtomicU64::new(0),
+		}
+	}
+
+	/// The most nodes, and the most IP addresses, that are kept track of.
+	pub fn tracked_peers_limit(&self) -> usize { self.tracked_peers_limit }
+
+	/// Takes the tokens for the request from the given peer, and returns
+	/// whether the request may be processed.
+	pub fn allow(&self, node_id: &NodeAddress, ip: &IpAddr, request: &[u8]) -> bool {
+		if self.rate == 0.0 {
+			return true;
+		}
+		let cost = request_cost(request);
+
+		let now = Instant::now();
+		let mut node_buckets = self.node_buckets.lock().unwrap();
+		let mut ip_buckets = self.ip_buckets.lock().unwrap();
+		let node_tokens = self.tokens(&mut node_buckets, node_id, now);
+		let ip_tokens = self.tokens(&mut ip_buckets, ip, now);
+		let allowed = node_tokens >= cost && ip_tokens >= cost;
+		if allowed {
+			node_buckets.get_mut(node_id).unwrap().tokens -= cost;
+			ip_buckets.get_mut(ip).unwrap().tokens -= cost;
+			self.accepted.fetch_add(1, Ordering::Relaxed);
+		} else {
+			self.rejected.fetch_add(1, Ordering::Relaxed);
+		}
+		allowed
+	}
+
+	/// Takes the given number of tokens for a request from the IP address, and
+	/// returns whether the request may be processed.
+	pub fn allow_ip(&self, ip: &IpAddr, cost: u32) -> bool {
+		if self.rate == 0.0 {
+			return true;
+		}
+
+		let mut ip_buckets = self.ip_buckets.lock().unwrap();
+		let tokens = self.tokens(&mut ip_buckets, ip, Instant::now());
+		// Requests that cost more than a whole burst still go through on a full
+		// bucket
+		let cost = (cost as f64).min(self.burst);
+		let allowed = tokens >= cost;
+		if allowed {
+			ip_buckets.get_mut(ip).unwrap().tokens -= cost;
+			self.accepted.fetch_add(1, Ordering::Relaxed);
+		} else {
+			self.rejected.fetch_add(1, Ordering::Relaxed);
+		}
+		allowed
+	}
+
+	pub fn stats(&self) -> RateLimitStats {
+		RateLimitStats {
+			accepted: self.accepted.load(Ordering::Relaxed),
+			rejected: self.rejected.load(Ordering::Relaxed),
+			tracked_nodes: self.node_buckets.lock().unwrap().len(),
+			tracked_ips: self.ip_buckets.lock().unwrap().len(),
+		}
+	}
+
+	/// Refills the bucket of the given peer up until now, and returns the number
+	/// of tokens that are in it.
+	fn tokens<K>(&self, buckets: &mut HashMap<K, Bucket>, key: &K, now: Instant) -> f64
+	where
+		K: Clone + Eq + Hash,
+	{
+		if buckets.len() >= self.tracked_peers_limit && !buckets.contains_key(key) {
+			buckets.retain(|_, b| b.tokens_at(now, self.rate, self.burst) < self.burst);
+		}
+
+		let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+			tokens: self.burst,
+			updated: now,
+		});
+		bucket.tokens = bucket.tokens_at(now, self.rate, self.burst);
+		bucket.updated = now;
+		bucket.tokens
+	}
+}
+
+/// The number of tokens that the request takes, depending on its message type.
+fn request_cost(request: &[u8]) -> f64 {
+	let message_type = match request.first() {
+		Some(t) => *t,
+		None => return 1.0,
+	};
+	if message_type == OVERLAY_MESSAGE_TYPE_KEEP_ALIVE_REQUEST {
+		return 0.0;
+	}
+	// Requests on actor networks have the highest bit set
+	match message_type ^ 0x80 {
+		NETWORK_MESSAGE_TYPE_FIND_VALUE_REQUEST
+		| ACTOR_MESSAGE_TYPE_BLOCK_MAP_REQUEST
+		| ACTOR_MESSAGE_TYPE_FIND_PARITY_REQUEST => DATA_REQUEST_COST,
+		_ => 1.0,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::{thread::sleep, time::Duration};
+
+	use super::*;
+	use crate::{common::IdType, net::overlay::OVERLAY_MESSAGE_TYPE_FIND_ACTOR_REQUEST, test};
+
+	#[test]
+	fn test_rate_limit() {
+		let mut config = Config::default();
+		config.request_rate_limit = Some(10);
+		config.request_rate_burst = Some(5);
+		let limiter = RateLimiter::new(&config);
+		let mut rng = test::initialize_rng();
+		let node_id = NodeAddress::V1(IdType::random(&mut rng));
+		let other_node_id = NodeAddress::V1(IdType::random(&mut rng));
+		let ip: IpAddr = "192.168.1.10".parse().unwrap();
+		let other_ip: IpAddr = "192.168.1.11".parse().unwrap();
+
+		let request = [OVERLAY_MESSAGE_TYPE_FIND_ACTOR_REQUEST];
+		for _ in 0..5 {
+			assert!(limiter.allow(&node_id, &ip, &request));
+		}
+		assert!(!limiter.allow(&node_id, &ip, &request));
+		// Another node ID from the same IP address is limited as well
+		assert!(!limiter.allow(&other_node_id, &ip, &request));
+		assert!(limiter.allow(&other_node_id, &other_ip, &request));
+
+		sleep(Duration::from_millis(200));
+		assert!(limiter.allow(&node_id, &ip, &request));
+
+		let stats = limiter.stats();
+		assert_eq!(stats.accepted, 7);
+		assert_eq!(stats.rejected, 2);
+		assert_eq!(stats.tracked_nodes, 2);
+		assert_eq!(stats.tracked_ips, 2);
+	}
+	#[test]
+	fn test_request_cost() {
+		let mut config = Config::default();
+		config.request_rate_limit = Some(1);
+		config.request_rate_burst = Some(1);
+		let limiter = RateLimiter::new(&config);
+		let mut rng = test::initialize_rng();
+		let node_id = NodeAddress::V1(IdType::random(&mut rng));
+		let ip: IpAddr = "192.168.1.10".parse().unwrap();
+
+		let find_block = [NETWORK_MESSAGE_TYPE_FIND_VALUE_REQUEST | 0x80];
+		for _ in 0..9 {
+			assert!(limiter.allow(&node_id, &ip, &find_block));
+		}
+		assert!(!limiter.allow(&node_id, &ip, &[OVERLAY_MESSAGE_TYPE_FIND_ACTOR_REQUEST]));
+		// Keep-alive requests always go through
+		for _ in 0..10 {
+			assert!(limiter.allow(&node_id, &ip, &[OVERLAY_MESSAGE_TYPE_KEEP_ALIVE_REQUEST]));
+		}
+	}
+}
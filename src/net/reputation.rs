@@ -0,0 +1,224 @@
+//! Remembers how well other nodes have been behaving towards us.
+//!
+//! Every node gets a score that goes up when it has been helpful, and down when
+//! it has been giving us problems. The scores slowly decay back to zero, so
+//! that nodes get a new chance after a while, and so that old merits don't
+//! count forever. The scores are kept in memory, and are written to the
+//! database periodically. Scores that haven't changed in a long time have
+//! decayed to practically nothing, and are forgotten.
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Mutex,
+};
+
+use chrono::Utc;
+use sea_orm::{sea_query::OnConflict, ColumnTrait, EntityTrait, NotSet, QueryFilter, Set};
+
+use crate::{
+	core::NodeAddress,
+	db::{self, Database, PersistenceHandle},
+	entity::node_reputation,
+};
+
+
+/// The time it takes for a score to be halved, in milliseconds.
+const DECAY_HALF_LIFE: f64 = 24.0 * 3600.0 * 1000.0;
+/// Nodes with a score below this value are not contacted, and their hello
+/// packets are ignored.
+const DISTRUST_THRESHOLD: f64 = -10.0;
+/// Scores are kept within this distance from zero, so that a node that has
+/// been offline for a while doesn't stay distrusted for too long.
+const SCORE_LIMIT: f64 = 20.0;
+/// The time after which an unchanged score is forgotten, in milliseconds. By
+/// then, even the highest score has decayed to less than a hundredth.
+const EXPIRY: i64 = 14 * 24 * 3600 * 1000;
+pub const HELPFUL_REWARD: f64 = 1.0;
+pub const PROBLEMATIC_PENALTY: f64 = -2.0;
+
+
+pub struct Reputations {
+	scores: Mutex<HashMap<NodeAddress, Reputation>>,
+	/// The nodes of which the score has changed since the last flush.
+	dirty: Mutex<HashSet<NodeAddress>>,
+}
+
+#[derive(Clone, Copy)]
+struct Reputation {
+	score: f64,
+	updated: i64,
+}
+
+
+impl Reputation {
+	/// The score, after decaying it up until the given time.
+	fn score_at(&self, now: i64) -> f64 {
+		let elapsed = (now - self.updated).max(0) as f64;
+		self.score * 0.5f64.powf(elapsed / DECAY_HALF_LIFE)
+	}
+}
+
+impl Reputations {
+	pub fn new() -> Self {
+		Self {
+			scores: Mutex::new(HashMap::new()),
+			dirty: Mutex::new(HashSet::new()),
+		}
+	}
+
+	/// Changes the score of the node by the given amount.
+	pub fn adjust(&self, address: &NodeAddress, amount: f64) {
+		let now = Utc::now().timestamp_millis();
+		{
+			let mut scores = self.scores.lock().unwrap();
+			let entry = scores.entry(address.clone()).or_insert(Reputation {
+				score: 0.0,
+				updated: now,
+			});
+			*entry = Reputation {
+				score: (entry.score_at(now) + amount).clamp(-SCORE_LIMIT, SCORE_LIMIT),
+				updated: now,
+			};
+		}
+		self.dirty.lock().unwrap().insert(address.clone());
+	}
+
+	/// Writes all changed scores to the database, and forgets about the ones
+	/// that have expired.
+	pub async fn flush(&self, db: &Database) -> db::Result<()> {
+		let expired_before = Utc::now().timestamp_millis() - EXPIRY;
+		let changed: Vec<(NodeAddress, Reputation)> = {
+			let mut dirty = self.dirty.lock().unwrap();
+			let mut scores = self.scores.lock().unwrap();
+			scores.retain(|_, r| r.updated >= expired_before);
+			dirty
+				.drain()
+				.filter_map(|a| scores.get(&a).map(|r| (a, *r)))
+				.collect()
+		};
+
+		let tx = db.transaction().await?;
+		for (address, reputation) in changed {
+			let model = node_reputation::ActiveModel {
+				id: NotSet,
+				address: Set(address),
+				score: Set(reputation.score),
+				updated: Set(reputation.updated),
+			};
+			node_reputation::Entity::insert(model)
+				.on_conflict(
+					OnConflict::column(node_reputation::Column::Address)
+						.update_columns([
+							node_reputation::Column::Score,
+							node_reputation::Column::Updated,
+						])
+						.to_owned(),
+				)
+				.exec(tx.inner())
+				.await?;
+		}
+		node_reputation::Entity::delete_many()
+			.filter(node_reputation::Column::Updated.lt(expired_before))
+			.exec(tx.inner())
+			.await?;
+		tx.commit().await?;
+		Ok(())
+	}
+
+	pub fn is_distrusted(&self, address: &NodeAddress) -> bool {
+		self.score(address) < DISTRUST_THRESHOLD
+	}
+
+	/// Loads all scores from the database that haven't expired yet.
+	pub async fn load(&self, db: &Database) -> db::Result<()> {
+		let expired_before = Utc::now().timestamp_millis() - EXPIRY;
+		let records = node_reputation::Entity::find()
+			.filter(node_reputation::Column::Updated.gte(expired_before))
+			.all(db.inner())
+			.await?;
+		let mut scores = self.scores.lock().unwrap();
+		for record in records {
+			scores.insert(
+				record.address,
+				Reputation {
+					score: record.score,
+					updated: record.updated,
+				},
+			);
+		}
+		Ok(())
+	}
+
+	/// The current score of the node, which is zero for unknown nodes.
+	pub fn score(&self, address: &NodeAddress) -> f64 {
+		let now = Utc::now().timestamp_millis();
+		self.scores
+			.lock()
+			.unwrap()
+			.get(address)
+			.map(|r| r.score_at(now))
+			.unwrap_or(0.0)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_decay() {
+		let reputation = Reputation {
+			score: -8.0,
+			updated: 0,
+		};
+		assert_eq!(reputation.score_at(0), -8.0);
+		assert_eq!(reputation.score_at(DECAY_HALF_LIFE as i64), -4.0);
+		assert_eq!(reputation.score_at(2 * DECAY_HALF_LIFE as i64), -2.0);
+	}
+
+	#[tokio::test]
+	async fn test_persistence() {
+		let db = test::load_database("reputation").await;
+		let mut rng = test::initialize_rng();
+		let address = NodeAddress::V1(IdType::random(&mut rng));
+
+		let reputations = Reputations::new();
+		for _ in 0..6 {
+			reputations.adjust(&address, PROBLEMATIC_PENALTY);
+		}
+		assert!(reputations.is_distrusted(&address));
+		reputations.flush(&db).await.unwrap();
+
+		let reloaded = Reputations::new();
+		reloaded.load(&db).await.unwrap();
+		assert!(reloaded.is_distrusted(&address));
+		assert!(reloaded.score(&address) > -12.0);
+	}
+
+	#[tokio::test]
+	async fn test_expiry() {
+		let db = test::load_database("reputation_expiry").await;
+		let mut rng = test::initialize_rng();
+		let address = NodeAddress::V1(IdType::random(&mut rng));
+		let model = node_reputation::ActiveModel {
+			id: NotSet,
+			address: Set(address.clone()),
+			score: Set(-SCORE_LIMIT),
+			updated: Set(0),
+		};
+		node_reputation::Entity::insert(model)
+			.exec(db.inner())
+			.await
+			.unwrap();
+
+		// An expired score is neither loaded nor kept in the database
+		let reputations = Reputations::new();
+		reputations.load(&db).await.unwrap();
+		assert_eq!(reputations.score(&address), 0.0);
+		reputations.flush(&db).await.unwrap();
+		let records = node_reputation::Entity::find().all(db.inner()).await.unwrap();
+		assert_eq!(records.len(), 0);
+	}
+}
@@ -112,7 +112,7 @@ where
 	V: Into<SocketAddr>,
 {
 	inner: tokio::net::TcpListener,
-	addr: UnsafeSync<V>,
+	_phantom: PhantomData<UnsafeSendSync<V>>,
 }
 pub struct TcpSocket<V>
 where
@@ -163,6 +163,13 @@ fn udp_max_packet_length<V>() -> usize {
 }
 
 
+impl<V> UdpServer<V>
+where
+	V: Into<SocketAddr>,
+{
+	pub fn local_addr(&self) -> io::Result<SocketAddr> { self.inner.local_addr() }
+}
+
 #[async_trait]
 impl<V> LinkServer for UdpServer<V>
 where
@@ -284,6 +291,8 @@ where
 			tokio::net::TcpSocket::new_v4()
 		}
 	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> { self.inner.local_addr() }
 }
 
 #[async_trait]
@@ -299,10 +308,10 @@ where
 		inner.set_reuseaddr(true)?;
 		#[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
 		inner.set_reuseport(true)?;
-		inner.bind(addr.clone().into())?;
+		inner.bind(addr.into())?;
 		Ok(Self {
 			inner: inner.listen(TCP_BACKLOG)?,
-			addr: UnsafeSync::new(addr),
+			_phantom: PhantomData,
 		})
 	}
 
@@ -319,7 +328,9 @@ where
 		inner.set_reuseaddr(true)?;
 		#[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
 		inner.set_reuseport(true)?;
-		inner.bind(self.addr.as_ref().clone().into())?;
+		// Connect from the port that we listen on, which may have been picked by
+		// the OS
+		inner.bind(self.local_addr()?)?;
 
 		select! {
 			result = inner.connect(addr.into()) => {
@@ -15,6 +15,7 @@
 //! after every window.
 
 
+mod nat;
 pub(super) mod server;
 mod transporter;
 
@@ -37,16 +38,22 @@ use futures::StreamExt;
 use generic_array::{typenum::*, GenericArray};
 use hmac::*;
 use log::*;
+use nat::SimulatedNat;
 use once_cell::sync::OnceCell;
 use rand::{rngs::OsRng, RngCore};
-pub use server::{MessageProcessorResult, Server};
+pub use server::{
+	MessageProcessorResult, PeerTraffic, RelayStats, Server, SessionInfo, TrafficStats,
+};
 use sha3::{Digest, Sha3_256};
 use tokio::{self, spawn, time::sleep};
 use transporter::*;
 use x25519_dalek as x25519;
 
 use super::{
+	banlist::Banlist,
 	binserde,
+	rate_limit::RateLimiter,
+	reputation::Reputations,
 	socket::{
 		ConnectionBasedLinkServer, ConnectionLessLinkServer, LinkServer, LinkSocket,
 		LinkSocketReceiver, LinkSocketSender, TcpServer, UdpServer,
@@ -56,6 +63,7 @@ use crate::{
 	config::Config,
 	identity::{self, *},
 	net::*,
+	task::TaskRegistry,
 	trace::{self, Traceable, Traced},
 };
 
@@ -69,6 +77,12 @@ pub(super) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
 /// The minimum timeout time that will be waited on a crucial packet before
 /// retrying.
 pub const MAXIMUM_RETRY_TIMEOUT: Duration = Duration::from_millis(500);
+/// The version of the protocol that we speak. It is exchanged in the hello
+/// handshake, so that we don't send anything to a node that it doesn't
+/// understand. Nodes that don't tell their version speak version 0, which
+/// doesn't require a proof-of-work for the node ID yet. Since version 2, a node
+/// that is too busy to handle a request can say so with a busy response.
+pub const PROTOCOL_VERSION: u16 = 2;
 
 pub struct Connection {
 	transporter: TransporterHandle,
@@ -77,6 +91,8 @@ pub struct Connection {
 	keep_alive_timeout: Duration,
 	peer_address: SocketAddr,
 	peer_node_info: NodeContactInfo,
+	/// The protocol version that the other side speaks, if it is known.
+	protocol_version: Option<u16>,
 	dest_session_id: u16,
 	local_session_id: u16, // our session ID
 }
@@ -93,6 +109,8 @@ pub enum Error {
 
 	BothSending,
 	BothReceiving,
+	/// The other side was too busy to handle the request.
+	Busy,
 	/// The connection has already been closed. Either by the Connection or
 	/// Server.
 	ConnectionClosed,
@@ -105,6 +123,8 @@ pub enum Error {
 	/// A different node ID has been responded with than was expected. This
 	/// could indicate a MitM attack.
 	InvalidNodeId,
+	/// The node ID of the other side didn't come with a valid proof-of-work.
+	InvalidProofOfWork,
 	InvalidResponseMessageType((u8, u8)),
 	InvalidSessionAddress(SocketAddr),
 	InvalidSessionId(u16),
@@ -168,6 +188,7 @@ impl fmt::Display for Error {
 			Self::InvalidPublicKey => write!(f, "invalid public key"),
 			Self::InvalidMessageType(mt) => write!(f, "invalid message type: {}", mt),
 			Self::InvalidNodeId => write!(f, "invalid node ID"),
+			Self::InvalidProofOfWork => write!(f, "invalid node ID proof-of-work"),
 			Self::InvalidResponseMessageType((mt, ex)) => write!(
 				f,
 				"expected message type {} from response but got {}",
@@ -187,6 +208,7 @@ impl fmt::Display for Error {
 			Self::Timeout(timeout) => write!(f, "timeout of {:?} exceeded", timeout),
 			Self::BothReceiving => write!(f, "both sides are in receiving mode"),
 			Self::BothSending => write!(f, "both sides are in sending mode"),
+			Self::Busy => write!(f, "the other side is too busy"),
 		}
 	}
 }
@@ -202,6 +224,8 @@ impl Error {
 			// good reasons.
 			Self::ConnectionClosed => true,
 			Self::OutOfSessions => true,
+			// A node that sheds load is still behaving well
+			Self::Busy => true,
 			_ => false,
 		}
 	}
@@ -223,6 +247,15 @@ impl Connection {
 	#[allow(dead_code)]
 	pub fn close_async(self) { self.transporter.close_async(); }
 
+	fn count_transferred_bytes(&self, bytes: usize, incoming: bool) {
+		self.server.count_transferred_bytes(
+			bytes,
+			self.transporter.is_connection_based(),
+			incoming,
+			&self.peer_node_info.address,
+		);
+	}
+
 	pub fn contact_option(&self) -> ContactOption {
 		ContactOption {
 			target: self.peer_address.clone(),
@@ -242,6 +275,10 @@ impl Connection {
 	#[allow(dead_code)]
 	pub fn peer_address(&self) -> &SocketAddr { &self.peer_address }
 
+	/// The protocol version that the other side speaks. It is only unknown if
+	/// the connection is relayed, because relayed handshakes don't include it.
+	pub fn protocol_version(&self) -> Option<u16> { self.protocol_version }
+
 	pub async fn receive(&mut self) -> Result<Vec<u8>> {
 		if let Some((message_size_result, mut stream)) = self.transporter.receive().await {
 			let message_size = if let Some(m) = message_size_result {
@@ -254,6 +291,7 @@ impl Connection {
 			while let Some(result) = stream.next().await {
 				buffer.extend(result?);
 			}
+			self.count_transferred_bytes(buffer.len(), true);
 			Ok(buffer)
 		} else {
 			trace::err(Error::ConnectionClosed)
@@ -261,6 +299,7 @@ impl Connection {
 	}
 
 	pub async fn send(&mut self, message: Vec<u8>) -> Result<()> {
+		self.count_transferred_bytes(message.len(), false);
 		self.transporter
 			.send(message)
 			.await
@@ -268,11 +307,16 @@ impl Connection {
 		Ok(())
 	}
 
+	pub(crate) fn set_protocol_version(&mut self, version: u16) {
+		self.protocol_version = Some(version);
+	}
+
 	pub(super) fn socket_sender(&self) -> Arc<dyn LinkSocketSender> {
 		self.transporter.socket_sender.clone()
 	}
 
 	pub fn send_async(&mut self, message: Vec<u8>) -> Result<()> {
+		self.count_transferred_bytes(message.len(), false);
 		match self.transporter.send_async(message) {
 			None => trace::err(Error::ConnectionClosed),
 			Some(()) => Ok(()),
@@ -292,6 +336,7 @@ impl Connection {
 			while let Some(result) = stream.next().await {
 				buffer.extend(result?);
 			}
+			self.count_transferred_bytes(buffer.len(), true);
 			Ok(buffer)
 		} else {
 			trace::err(Error::ConnectionClosed)
@@ -366,21 +411,14 @@ mod tests {
 	/// Sent and receive a bunch of messages.
 	async fn test_connection(use_udp: bool) {
 		let mut rng = test::initialize_rng();
-		let ip = Ipv4Addr::new(127, 0, 0, 1);
-		let master_addr = SocketAddr::V4(SocketAddrV4::new(ip, 10000));
 		let mut master_config = Config::default();
 		master_config.ipv4_address = Some("127.0.0.1".to_string());
 		if use_udp {
-			master_config.ipv4_udp_port = Some(10000);
-		} else {
-			master_config.ipv4_tcp_port = Some(10000);
-		}
-		let mut slave_config = master_config.clone();
-		if use_udp {
-			slave_config.ipv4_udp_port = Some(10001);
+			master_config.ipv4_udp_port = Some(0);
 		} else {
-			slave_config.ipv4_tcp_port = Some(10001);
+			master_config.ipv4_tcp_port = Some(0);
 		}
+		let slave_config = master_config.clone();
 		let stop_flag = Arc::new(AtomicBool::new(false));
 		let master_private_key = NodePrivateKey::generate_with_rng(&mut rng);
 		let master_node_id = master_private_key.public().generate_address();
@@ -468,6 +506,7 @@ mod tests {
 		master.set_next_session_id(100).await;
 		slave.set_next_session_id(200).await;
 
+		let master_addr = ipv4_addr(&master, !use_udp);
 		let (mut connection, first_response) = slave
 			.clone()
 			.connect(
@@ -496,19 +535,13 @@ mod tests {
 	}
 
 	#[tokio::test]
-	// Sent and receive a message through a relay
+	// Sent and receive a message through a relay, between two nodes that can't
+	// reach each other directly
 	async fn test_relaying() {
 		let mut rng = test::initialize_rng();
-		let mut relay_config = Config::default();
-		relay_config.ipv4_address = Some("127.0.0.1".to_string());
-		relay_config.ipv4_udp_port = Some(10002);
-		let mut node1_config = relay_config.clone();
-		node1_config.ipv4_udp_port = Some(10003);
-		let mut node2_config = relay_config.clone();
-		node2_config.ipv4_udp_port = Some(10004);
-		let ip = Ipv4Addr::new(127, 0, 0, 1);
-		let relay_addr = SocketAddr::V4(SocketAddrV4::new(ip, 10002));
-		let node2_addr = SocketAddr::V4(SocketAddrV4::new(ip, 10004));
+		let relay_config = simulated_config(Openness::Bidirectional);
+		let node1_config = simulated_config(Openness::Unidirectional);
+		let node2_config = node1_config.clone();
 		let stop_flag = Arc::new(AtomicBool::new(false));
 		let relay_private_key = NodePrivateKey::generate();
 		let relay_node_id = relay_private_key.public().generate_address();
@@ -593,6 +626,16 @@ mod tests {
 		relay.set_next_session_id(100).await;
 		node1.set_next_session_id(200).await;
 		node2.set_next_session_id(300).await;
+		let relay_addr = ipv4_addr(&relay, false);
+		let node2_addr = ipv4_addr(&node2, false);
+
+		// The NAT device of node 2 only lets the relay node through after node 2
+		// has contacted it
+		let (mut connection, _) = node2
+			.connect(&ContactOption::new_udp(relay_addr), Some(&relay_node_id), None)
+			.await
+			.expect("unable to connect to relay node");
+		connection.close().await.unwrap();
 
 		// Receive relayed message
 		let mut connection = node1
@@ -614,4 +657,336 @@ mod tests {
 			.expect("unable to receive message");
 		assert_eq!(received_message, response, "relayed message got corrupted");
 	}
+
+	async fn bind_server(
+		stop_flag: &Arc<AtomicBool>, config: &Config, private_key: NodePrivateKey,
+	) -> (Arc<sstp::Server>, NodeAddress) {
+		let node_id = private_key.public().generate_address();
+		let server = sstp::Server::bind(
+			stop_flag.clone(),
+			config,
+			node_id.clone(),
+			private_key,
+			DEFAULT_TIMEOUT,
+		)
+		.await
+		.expect("unable to bind server");
+		(server, node_id)
+	}
+
+	/// The address that the server listens on with IPv4.
+	fn ipv4_addr(server: &sstp::Server, use_tcp: bool) -> SocketAddr {
+		let entry = server.our_contact_info().ipv4.expect("no IPv4 contact info");
+		let transport = if use_tcp { entry.availability.tcp } else { entry.availability.udp };
+		SocketAddr::new(entry.addr.into(), transport.expect("no port").port)
+	}
+
+	/// A config for a node on IPv4 and UDP, that acts as if it is behind a NAT
+	/// device of the given openness.
+	fn simulated_config(openness: Openness) -> Config {
+		let mut config = Config::default();
+		config.ipv4_address = Some("127.0.0.1".to_string());
+		config.ipv4_udp_port = Some(0);
+		config.ipv4_udp_openness = Some(format!("{:?}", openness));
+		config.simulate_openness = Some(true);
+		config
+	}
+
+	/// Builds the contact info of a node that is reachable at the given IP
+	/// versions and transports, each with their own openness.
+	fn contact_info_with(options: &[(bool, bool, Openness)], port: u16) -> ContactInfo {
+		let mut config = Config::default();
+		for (use_ipv6, use_tcp, openness) in options {
+			let openness = Some(format!("{:?}", openness));
+			match (use_ipv6, use_tcp) {
+				(false, false) => {
+					config.ipv4_udp_port = Some(port);
+					config.ipv4_udp_openness = openness;
+				}
+				(false, true) => {
+					config.ipv4_tcp_port = Some(port);
+					config.ipv4_tcp_openness = openness;
+				}
+				(true, false) => {
+					config.ipv6_udp_port = Some(port);
+					config.ipv6_udp_openness = openness;
+				}
+				(true, true) => {
+					config.ipv6_tcp_port = Some(port);
+					config.ipv6_tcp_openness = openness;
+				}
+			}
+			if *use_ipv6 {
+				config.ipv6_address = Some("::1".to_string());
+			} else {
+				config.ipv4_address = Some("127.0.0.1".to_string());
+			}
+		}
+		ContactInfo::from_config(&config)
+	}
+
+	/// Only keeps the contact info for the given IP version and transport.
+	fn only_option(contact_info: &ContactInfo, use_ipv6: bool, use_tcp: bool) -> ContactInfo {
+		let mut contact_info = contact_info.clone();
+		if use_ipv6 {
+			contact_info.ipv4 = None;
+		} else {
+			contact_info.ipv6 = None;
+		}
+		let availabilities = [
+			contact_info.ipv4.as_mut().map(|e| &mut e.availability),
+			contact_info.ipv6.as_mut().map(|e| &mut e.availability),
+		];
+		for availability in availabilities.into_iter().flatten() {
+			if use_tcp {
+				availability.udp = None;
+			} else {
+				availability.tcp = None;
+			}
+		}
+		contact_info
+	}
+
+	fn dual_stack_config() -> Config {
+		let mut config = Config::default();
+		config.ipv4_address = Some("127.0.0.1".to_string());
+		config.ipv4_udp_port = Some(0);
+		config.ipv4_tcp_port = Some(0);
+		config.ipv6_address = Some("::1".to_string());
+		config.ipv6_udp_port = Some(0);
+		config.ipv6_tcp_port = Some(0);
+		config
+	}
+
+	/// Tries to connect to the target, and returns whether that worked.
+	async fn try_connect(
+		client: &Arc<sstp::Server>, target: &sstp::Server, target_node_id: &NodeAddress,
+	) -> bool {
+		let option = ContactOption::new_udp(ipv4_addr(target, false));
+		let request = b"hello";
+		let result = client
+			.connect_with_timeout(
+				Arc::new(AtomicBool::new(false)),
+				&option,
+				Some(target_node_id),
+				Some(&request[..]),
+				Duration::from_secs(1),
+			)
+			.await;
+		match result {
+			Ok((mut connection, response)) => {
+				assert_eq!(response.as_deref(), Some(&request[..]));
+				connection.close().await.unwrap();
+				true
+			}
+			Err(_) => false,
+		}
+	}
+
+	#[tokio::test]
+	/// Checks which contact option is picked for every combination of openness,
+	/// transport and IP version, and that a connection can be established over
+	/// each of them.
+	async fn test_contact_option_matrix() {
+		use Openness::*;
+
+		// Not every machine has IPv6 on its loopback interface
+		if std::net::UdpSocket::bind("[::1]:0").is_err() {
+			warn!("Skipping test because IPv6 is not available.");
+			return;
+		}
+
+		let mut rng = test::initialize_rng();
+		let stop_flag = Arc::new(AtomicBool::new(false));
+		let (client, _) = bind_server(
+			&stop_flag,
+			&dual_stack_config(),
+			NodePrivateKey::generate_with_rng(&mut rng),
+		)
+		.await;
+		let (target, target_node_id) = bind_server(
+			&stop_flag,
+			&dual_stack_config(),
+			NodePrivateKey::generate_with_rng(&mut rng),
+		)
+		.await;
+
+		// A target that is only reachable in one way, should be contacted in
+		// that way, whatever its openness.
+		for openness in [Bidirectional, Punchable, Unidirectional] {
+			for use_ipv6 in [false, true] {
+				for use_tcp in [false, true] {
+					let contact_info = contact_info_with(&[(use_ipv6, use_tcp, openness)], 10011);
+					let (option, picked_openness) = client
+						.pick_contact_option(&contact_info)
+						.expect("no contact option picked");
+					let case = format!("{:?} ipv6={} tcp={}", openness, use_ipv6, use_tcp);
+					assert_eq!(option.target.is_ipv6(), use_ipv6, "{}", case);
+					assert_eq!(option.use_tcp, use_tcp, "{}", case);
+					assert_eq!(option.target.port(), 10011, "{}", case);
+					assert_eq!(picked_openness, openness, "{}", case);
+				}
+			}
+		}
+
+		// If the target is reachable in multiple ways, the most open option is
+		// preferred, and otherwise IPv6 over IPv4, and UDP over TCP.
+		let cases = [
+			(
+				vec![
+					(false, false, Punchable),
+					(false, true, Bidirectional),
+					(true, false, Unidirectional),
+				],
+				(false, true, Bidirectional),
+			),
+			(
+				vec![
+					(false, false, Unidirectional),
+					(true, true, Punchable),
+					(true, false, Unidirectional),
+				],
+				(true, true, Punchable),
+			),
+			(
+				vec![(false, false, Bidirectional), (true, false, Bidirectional)],
+				(true, false, Bidirectional),
+			),
+			(
+				vec![
+					(false, true, Unidirectional),
+					(false, false, Unidirectional),
+				],
+				(false, false, Unidirectional),
+			),
+			(
+				vec![
+					(false, false, Unidirectional),
+					(false, true, Unidirectional),
+					(true, false, Unidirectional),
+					(true, true, Unidirectional),
+				],
+				(true, false, Unidirectional),
+			),
+		];
+		for (options, (use_ipv6, use_tcp, openness)) in cases {
+			let contact_info = contact_info_with(&options, 10011);
+			let (option, picked_openness) = client
+				.pick_contact_option(&contact_info)
+				.expect("no contact option picked");
+			assert_eq!(option.target.is_ipv6(), use_ipv6, "{:?}", options);
+			assert_eq!(option.use_tcp, use_tcp, "{:?}", options);
+			assert_eq!(picked_openness, openness, "{:?}", options);
+		}
+
+		// Options that we have no socket for, can't be picked.
+		let mut ipv4_udp_config = Config::default();
+		ipv4_udp_config.ipv4_address = Some("127.0.0.1".to_string());
+		ipv4_udp_config.ipv4_udp_port = Some(0);
+		let private_key = NodePrivateKey::generate_with_rng(&mut rng);
+		let (ipv4_udp_client, _) = bind_server(&stop_flag, &ipv4_udp_config, private_key).await;
+		let contact_info = contact_info_with(
+			&[
+				(true, false, Bidirectional),
+				(false, true, Bidirectional),
+				(false, false, Unidirectional),
+			],
+			10011,
+		);
+		let (option, openness) = ipv4_udp_client.pick_contact_option(&contact_info).unwrap();
+		assert!(!option.target.is_ipv6() && !option.use_tcp);
+		assert_eq!(openness, Unidirectional);
+		let contact_info = contact_info_with(&[(true, true, Bidirectional)], 10011);
+		assert!(ipv4_udp_client.pick_contact_option(&contact_info).is_none());
+
+		// Establish a connection over every option
+		target.listen(
+			|request, _, _| Box::pin(async move { Some((request, None)) }),
+			|result, _| {
+				Box::pin(async move {
+					result.expect("message error");
+				})
+			},
+		);
+		target.spawn();
+		client.spawn();
+		let target_contact_info = target.our_contact_info();
+		for use_ipv6 in [false, true] {
+			for use_tcp in [false, true] {
+				let contact_info = only_option(&target_contact_info, use_ipv6, use_tcp);
+				let (option, _) = client.pick_contact_option(&contact_info).unwrap();
+				let mut message = vec![0u8; 1000];
+				rng.fill_bytes(&mut message);
+				let (mut connection, response) = client
+					.clone()
+					.connect(&option, Some(&target_node_id), Some(&message))
+					.await
+					.expect("unable to connect");
+				assert_eq!(response, Some(message), "ipv6={} tcp={}", use_ipv6, use_tcp);
+				connection.close().await.unwrap();
+			}
+		}
+		stop_flag.store(true, Ordering::Relaxed);
+	}
+
+	#[tokio::test]
+	/// Checks that nodes behind a simulated NAT device can only be reached in
+	/// the ways that their openness allows.
+	async fn test_nat_traversal() {
+		use Openness::*;
+
+		let mut rng = test::initialize_rng();
+		let stop_flag = Arc::new(AtomicBool::new(false));
+		let (client, client_node_id) = bind_server(
+			&stop_flag,
+			&simulated_config(Bidirectional),
+			NodePrivateKey::generate_with_rng(&mut rng),
+		)
+		.await;
+		let (punchable, punchable_node_id) = bind_server(
+			&stop_flag,
+			&simulated_config(Punchable),
+			NodePrivateKey::generate_with_rng(&mut rng),
+		)
+		.await;
+		let (unidirectional, unidirectional_node_id) = bind_server(
+			&stop_flag,
+			&simulated_config(Unidirectional),
+			NodePrivateKey::generate_with_rng(&mut rng),
+		)
+		.await;
+		for server in [&client, &punchable, &unidirectional] {
+			server.listen(
+				|request, _, _| Box::pin(async move { Some((request, None)) }),
+				|result, _| {
+					Box::pin(async move {
+						result.expect("message error");
+					})
+				},
+			);
+			server.spawn();
+		}
+
+		// Only a bidirectional node can be reached directly
+		assert!(!try_connect(&client, &punchable, &punchable_node_id).await);
+		assert!(!try_connect(&client, &unidirectional, &unidirectional_node_id).await);
+
+		// A punched hole only lets us through a punchable NAT device
+		let client_option = ContactOption::new_udp(ipv4_addr(&client, false));
+		punchable
+			.send_punch_hole_packet(&client_option)
+			.await
+			.expect("unable to punch hole");
+		unidirectional
+			.send_punch_hole_packet(&client_option)
+			.await
+			.expect("unable to punch hole");
+		assert!(try_connect(&client, &punchable, &punchable_node_id).await);
+		assert!(!try_connect(&client, &unidirectional, &unidirectional_node_id).await);
+
+		// A unidirectional node can still connect to us instead, which is what a
+		// reversed connection does
+		assert!(try_connect(&unidirectional, &client, &client_node_id).await);
+		stop_flag.store(true, Ordering::Relaxed);
+	}
 }
@@ -0,0 +1,135 @@
+//! Simulates the NAT device in front of a node, by dropping the packets that it
+//! wouldn't let through.
+//!
+//! Nodes on the same machine can always reach each other, whatever openness
+//! they have been configured with. So without this, a direct connection always
+//! works, and the other ways to get through a NAT device, like hole punching
+//! and relaying, can't be tested on a single machine. With the
+//! `simulate_openness` option, every socket only receives the packets that it
+//! would receive at its openness:
+//! * A bidirectional socket receives everything.
+//! * A punchable socket only receives packets from the addresses that it has
+//!   sent something to.
+//! * A unidirectional socket only receives packets from the addresses that it
+//!   has sent something to, other than a punch hole packet. Its NAT device maps
+//!   every connection to another port, so a punched hole never opens the port
+//!   that other nodes know of.
+
+use std::{
+	collections::HashSet,
+	io,
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use super::server::PACKET_TYPE_PUNCH_HOLE;
+use crate::{
+	config::Config,
+	net::{socket::LinkSocketSender, Openness},
+};
+
+
+pub(super) struct SimulatedNat {
+	openness: Openness,
+	/// The addresses that packets are let through from.
+	contacted: Mutex<HashSet<SocketAddr>>,
+}
+
+/// Sends the packets of a socket through the simulated NAT device, so that it
+/// knows where they went.
+struct SimulatedNatSender {
+	inner: Arc<dyn LinkSocketSender>,
+	nat: Arc<SimulatedNat>,
+	target: SocketAddr,
+}
+
+
+impl SimulatedNat {
+	/// Only simulates a NAT device if the config asks for it.
+	pub fn for_config(config: &Config, openness: Openness) -> Option<Arc<Self>> {
+		if config.simulate_openness.unwrap_or(false) {
+			Some(Arc::new(Self::new(openness)))
+		} else {
+			None
+		}
+	}
+
+	fn new(openness: Openness) -> Self {
+		Self {
+			openness,
+			contacted: Mutex::new(HashSet::new()),
+		}
+	}
+
+	/// Whether a packet from the given address gets through.
+	pub fn admits(&self, sender: &SocketAddr) -> bool {
+		self.openness == Openness::Bidirectional || self.contacted.lock().unwrap().contains(sender)
+	}
+
+	fn record_outgoing(&self, target: &SocketAddr, packet: &[u8]) {
+		let is_punch = packet.first() == Some(&PACKET_TYPE_PUNCH_HOLE);
+		if is_punch && self.openness == Openness::Unidirectional {
+			return;
+		}
+		self.contacted.lock().unwrap().insert(*target);
+	}
+
+	/// Lets the packets that are sent to the target go through the NAT device.
+	pub fn wrap_sender(
+		self: &Arc<Self>, sender: Arc<dyn LinkSocketSender>, target: SocketAddr,
+	) -> Arc<dyn LinkSocketSender> {
+		Arc::new(SimulatedNatSender {
+			inner: sender,
+			nat: self.clone(),
+			target,
+		})
+	}
+}
+
+#[async_trait]
+impl LinkSocketSender for SimulatedNatSender {
+	async fn close(&self) -> io::Result<()> { self.inner.close().await }
+
+	fn max_packet_length(&self) -> usize { self.inner.max_packet_length() }
+
+	fn is_connection_based(&self) -> bool { self.inner.is_connection_based() }
+
+	async fn send(&self, message: &[u8]) -> io::Result<()> {
+		self.nat.record_outgoing(&self.target, message);
+		self.inner.send(message).await
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_simulated_nat() {
+		let peer: SocketAddr = "127.0.0.1:10000".parse().unwrap();
+		let other: SocketAddr = "127.0.0.1:10001".parse().unwrap();
+		let punch = [PACKET_TYPE_PUNCH_HOLE];
+		let packet = [0u8, 1, 2, 3];
+
+		let nat = SimulatedNat::new(Openness::Bidirectional);
+		assert!(nat.admits(&peer));
+
+		// A punched hole only opens a punchable NAT device
+		let nat = SimulatedNat::new(Openness::Punchable);
+		assert!(!nat.admits(&peer));
+		nat.record_outgoing(&peer, &punch);
+		assert!(nat.admits(&peer));
+		assert!(!nat.admits(&other));
+		let nat = SimulatedNat::new(Openness::Unidirectional);
+		nat.record_outgoing(&peer, &punch);
+		assert!(!nat.admits(&peer));
+
+		// Anything else opens both of them
+		nat.record_outgoing(&peer, &packet);
+		assert!(nat.admits(&peer));
+		assert!(!nat.admits(&other));
+	}
+}
@@ -1,4 +1,8 @@
-use std::{future::Future, pin::Pin, sync::Mutex as StdMutex};
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::{atomic::AtomicU64, Mutex as StdMutex},
+};
 
 use futures::{future::BoxFuture, FutureExt};
 use tokio::{
@@ -7,16 +11,22 @@ use tokio::{
 };
 
 use super::*;
-use crate::trace::Mutex;
+use crate::{limited_store::LimitedMap, trace::Mutex};
 
 
 const DEFAULT_KEEP_ALIVE_IDLE_TIME: Duration = Duration::from_secs(120);
+/// The maximum number of sessions that are kept open at the same time in the
+/// low-memory profile. Otherwise, the only limit is the number of session IDs.
+const SESSIONS_LIMIT_LOW_MEMORY: usize = 256;
+/// The most packets that are sent or received in one window in the low-memory
+/// profile, which limits how much of a message is buffered at once.
+const MAX_WINDOW_SIZE_LOW_MEMORY: u16 = 64;
 
 const PACKET_TYPE_HELLO: u8 = 0;
 const PACKET_TYPE_HELLO_ACK: u8 = 1;
 const PACKET_TYPE_HELLO_ACK_ACK: u8 = 2;
 pub(super) const PACKET_TYPE_CRYPTED: u8 = 3;
-const PACKET_TYPE_PUNCH_HOLE: u8 = 4;
+pub(super) const PACKET_TYPE_PUNCH_HOLE: u8 = 4;
 const PACKET_TYPE_RELAY_HELLO: u8 = 5;
 const PACKET_TYPE_RELAY_HELLO_ACK: u8 = 6;
 const PACKET_TYPE_RELAY_HELLO_RELAY_ACK: u8 = 7;
@@ -24,6 +34,14 @@ const PACKET_TYPE_RELAY_HELLO_ACK_ACK: u8 = 8;
 const PACKET_TYPE_RELAYED_HELLO: u8 = 9;
 const PACKET_TYPE_RELAYED_HELLO_ACK: u8 = 10;
 const PACKET_TYPE_RELAYED_HELLO_ACK_ACK: u8 = 11;
+const PACKET_TYPE_VERSIONED_HELLO: u8 = 12;
+const PACKET_TYPE_VERSIONED_HELLO_ACK: u8 = 13;
+
+/// The maximum number of nodes of which the protocol version is remembered.
+const PEER_VERSIONS_LIMIT: usize = 1000;
+/// How long a node that has used the legacy handshake is assumed to speak
+/// protocol version 0, before a versioned hello packet is tried on it again.
+const LEGACY_PEER_RECHECK_INTERVAL: Duration = Duration::from_secs(3600);
 
 
 pub type MessageProcessor = dyn Fn(
@@ -158,6 +176,17 @@ struct HelloPacketBody {
 	contact_info: ContactInfo,
 }
 
+/// Follows the body of a versioned hello or hello-ack packet, and is signed
+/// together with it. Nodes of protocol version 0 don't know the versioned
+/// packets and ignore them, so they are still sent the legacy packets, which
+/// don't have this.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct HandshakeExtension {
+	protocol_version: u16,
+	/// Proves that work has been done to generate the node ID of the sender.
+	proof_nonce: u64,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct HelloPacketHeader {
 	pub node_public_key: identity::NodePublicKey,
@@ -172,6 +201,7 @@ pub struct HelloResult {
 	dest_session_id: u16,
 	dh_public_key: x25519::PublicKey,
 	opt_response: Option<Vec<u8>>,
+	protocol_version: u16,
 }
 type HelloSender = mpsc::Sender<HelloResult>;
 
@@ -181,16 +211,89 @@ type HelloSender = mpsc::Sender<HelloResult>;
 /// forward them to the corresponding receiver to be processed.
 pub struct Server {
 	stop_flag: Arc<AtomicBool>,
+	pub(crate) banlist: Banlist,
+	pub(crate) rate_limiter: RateLimiter,
+	pub(crate) reputations: Reputations,
+	pub(crate) tasks: TaskRegistry,
 	sockets: SocketCollection,
 	our_contact_info: StdMutex<ContactInfo>,
 	pub(super) sessions: Mutex<Sessions>,
 	node_id: NodeAddress,
 	private_key: identity::NodePrivateKey,
+	proof_nonce: u64,
+	/// The protocol version that other nodes have been seen speaking, and
+	/// when.
+	peer_versions: StdMutex<LimitedMap<NodeAddress, (u16, Instant)>>,
 	default_timeout: Duration,
+	/// The most packets that a window can grow to.
+	max_window_size: u16,
+	relay_metrics: RelayMetrics,
+	traffic_metrics: TrafficMetrics,
+	/// The traffic per node, since it has last been taken. It is only counted
+	/// once it has been taken for the first time.
+	peer_traffic: StdMutex<Option<HashMap<NodeAddress, PeerTraffic>>>,
 	// TODO: Remove pub in following line:
 	pub message_processors: OnceCell<(Box<MessageProcessor>, Box<MessageFinishProcessor>)>,
 }
 
+/// Keeps count of the traffic that we relay for other nodes.
+#[derive(Default)]
+struct RelayMetrics {
+	opened_sessions: AtomicU64,
+	packets: AtomicU64,
+	bytes: AtomicU64,
+}
+
+/// Keeps count of the bytes of messages that are sent and received over our
+/// own connections, and of the connections themselves.
+#[derive(Default)]
+struct TrafficMetrics {
+	udp_bytes_in: AtomicU64,
+	udp_bytes_out: AtomicU64,
+	tcp_bytes_in: AtomicU64,
+	tcp_bytes_out: AtomicU64,
+	opened_connections: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TrafficStats {
+	pub udp_bytes_in: u64,
+	pub udp_bytes_out: u64,
+	pub tcp_bytes_in: u64,
+	pub tcp_bytes_out: u64,
+	pub opened_connections: u64,
+}
+
+/// The bytes of messages that have been exchanged with one other node.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerTraffic {
+	pub bytes_in: u64,
+	pub bytes_out: u64,
+}
+
+/// A snapshot of an open session, to show what the node is connected with.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionInfo {
+	pub id: u16,
+	/// The other node, if it has identified itself already.
+	pub node_address: Option<String>,
+	/// Whether we relay the session for two other nodes.
+	pub is_relayed: bool,
+	/// The node that relays the session for us, if any.
+	pub relay_node: Option<String>,
+	/// The number of seconds since anything has been received.
+	pub idle_seconds: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RelayStats {
+	/// The number of sessions that are being relayed at the moment.
+	pub active_sessions: usize,
+	pub opened_sessions: u64,
+	pub relayed_packets: u64,
+	pub relayed_bytes: u64,
+}
+
 pub(super) struct SessionData {
 	hello_ack_channel: Option<Sender<()>>,
 	their_node_id: Option<NodeAddress>,
@@ -232,6 +335,7 @@ struct SessionTransportDataRelay {
 pub(super) struct Sessions {
 	pub(super) map: HashMap<u16, Arc<Mutex<SessionData>>>,
 	next_id: u16,
+	limit: usize,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -277,6 +381,8 @@ where
 {
 	inner: S,
 	openness: Openness,
+	/// Only set if the openness is simulated.
+	nat: Option<Arc<SimulatedNat>>,
 }
 
 
@@ -288,15 +394,39 @@ impl Server {
 		stop_flag: Arc<AtomicBool>, config: &Config, node_id: NodeAddress,
 		private_key: NodePrivateKey, default_timeout: Duration,
 	) -> StdResult<Arc<Self>, SocketBindError> {
-		let contact_info = ContactInfo::from_config(config);
+		let sockets = SocketCollection::bind(config).await?;
+		let mut contact_info = ContactInfo::from_config(config);
+		sockets.fill_in_ports(&mut contact_info)?;
+		let proof_nonce = private_key.public().generate_proof_of_work();
+		let low_memory = config.low_memory.unwrap_or(false);
+		let sessions_limit = if low_memory {
+			SESSIONS_LIMIT_LOW_MEMORY
+		} else {
+			0x10000
+		};
+		let max_window_size = if low_memory {
+			MAX_WINDOW_SIZE_LOW_MEMORY
+		} else {
+			MAX_WINDOW_SIZE
+		};
 		Ok(Arc::new(Self {
 			stop_flag,
-			sockets: SocketCollection::bind(config).await?,
+			banlist: Banlist::new(config),
+			rate_limiter: RateLimiter::new(config),
+			reputations: Reputations::new(),
+			tasks: TaskRegistry::new(),
+			sockets,
 			our_contact_info: StdMutex::new(contact_info),
-			sessions: Mutex::new(Sessions::new()),
+			sessions: Mutex::new(Sessions::new(sessions_limit)),
 			node_id,
 			private_key,
+			proof_nonce,
+			peer_versions: StdMutex::new(LimitedMap::new(PEER_VERSIONS_LIMIT)),
 			default_timeout,
+			max_window_size,
+			relay_metrics: RelayMetrics::default(),
+			traffic_metrics: TrafficMetrics::default(),
+			peer_traffic: StdMutex::new(None),
 			message_processors: OnceCell::new(),
 		}))
 	}
@@ -328,6 +458,46 @@ impl Server {
 		}
 	}
 
+	pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+		let sessions = self.sessions.lock().await;
+		let mut list = Vec::with_capacity(sessions.map.len());
+		for (id, session_mutex) in sessions.map.iter() {
+			let session = session_mutex.lock().await;
+			let idle_seconds = session
+				.last_activity
+				.lock()
+				.unwrap()
+				.elapsed()
+				.map(|d| d.as_secs())
+				.unwrap_or_default();
+			let (node_address, is_relayed, relay_node) = match &session.transport_data {
+				SessionTransportData::Direct(data) => (
+					session.their_node_id.as_ref().map(|a| a.to_string()),
+					false,
+					data.relay_node_id.as_ref().map(|a| a.to_string()),
+				),
+				SessionTransportData::Relay(data) =>
+					(Some(data.target_node_id.to_string()), true, None),
+			};
+			list.push(SessionInfo {
+				id: *id,
+				node_address,
+				is_relayed,
+				relay_node,
+				idle_seconds,
+			});
+		}
+		list.sort_by_key(|s| s.id);
+		list
+	}
+
+	/// The number of open sessions, and the number of sessions that can be
+	/// open at most.
+	pub async fn session_count(&self) -> (usize, usize) {
+		let sessions = self.sessions.lock().await;
+		(sessions.map.len(), sessions.limit)
+	}
+
 	pub async fn complete_outgoing_relay(
 		self: &Arc<Server>, sender: Arc<dyn LinkSocketSender>,
 		initiation_data: RelayInitiationInfo, establish_info: HelloResult,
@@ -357,14 +527,20 @@ impl Server {
 			initiation_data.dh_private_key,
 			establish_info.dh_public_key,
 			initiation_data.packet_receiver,
+			self.max_window_size,
 		);
-		let transporter_handle = transporter.spawn();
+		let transporter_handle = transporter.spawn(&self.tasks);
 
+		self.traffic_metrics
+			.opened_connections
+			.fetch_add(1, Ordering::Relaxed);
 		Ok(Box::new(Connection {
 			transporter: transporter_handle,
 			server: self.clone(),
 			keep_alive_timeout: DEFAULT_KEEP_ALIVE_IDLE_TIME,
 			peer_address: target_addr,
+			// Relayed handshakes don't tell the version
+			protocol_version: self.peer_version(target_node_id),
 			peer_node_info: NodeContactInfo {
 				address: establish_info.node_id,
 				contact_info: establish_info.contact_info,
@@ -376,10 +552,11 @@ impl Server {
 
 	/// Fills the packet data into the given buffer.
 	/// Returns whether the request was able to be included into the hello
-	/// packet or not.
+	/// packet or not. The request is left out if the packet would become longer
+	/// than `max_len` with it.
 	fn compose_hello_packet(
 		&self, max_len: usize, private_key: &x25519::StaticSecret, session_id: u16,
-		request: Option<&[u8]>,
+		request: Option<&[u8]>, versioned: bool,
 	) -> (Vec<u8>, bool) {
 		let dh_public_key = x25519::PublicKey::from(private_key);
 		let body = HelloPacketBody {
@@ -387,23 +564,31 @@ impl Server {
 			session_id,
 			contact_info: self.our_contact_info(),
 		};
+		let extension = versioned.then(|| self.handshake_extension());
 
 		let body_offset = 1 + 96;
-		let request_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		let extension_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		let request_offset = extension_offset
+			+ extension
+				.as_ref()
+				.map(|e| binserde::serialized_size(e).unwrap())
+				.unwrap_or(0);
+		let request = request.filter(|b| request_offset + b.len() <= max_len);
+		let packet_type = if versioned { PACKET_TYPE_VERSIONED_HELLO } else { PACKET_TYPE_HELLO };
 		let mut buffer =
-			vec![PACKET_TYPE_HELLO; request_offset + request.map(|b| b.len()).unwrap_or(0)];
+			vec![packet_type; request_offset + request.map(|b| b.len()).unwrap_or(0)];
 
 		// Sign request
 		binserde::serialize_into(&mut buffer[body_offset..], &body).unwrap();
+		if let Some(e) = &extension {
+			binserde::serialize_into(&mut buffer[extension_offset..], e).unwrap();
+		}
 
 		// The request can't be encrypted yet because we don't have the public key yet.
 		let mut request_included = false;
 		if let Some(request_buffer) = request {
-			if request_offset + request_buffer.len() < max_len {
-				let request_offset = body_offset + binserde::serialized_size(&body).unwrap();
-				buffer[request_offset..].copy_from_slice(request_buffer);
-				request_included = true;
-			}
+			buffer[request_offset..].copy_from_slice(request_buffer);
+			request_included = true;
 		}
 
 		// Sign the body with the request together
@@ -460,19 +645,40 @@ impl Server {
 			.await
 			.ok_or(Error::OutOfSessions)?;
 
-		// Wait for the hello response to arrive while we keep sending hello packets
-		let started = SystemTime::now();
-		let sleep_time = min(timeout / 4, MAXIMUM_RETRY_TIMEOUT);
-		let (hello_packet, hello_request_included) = self.new_hello_packet(
+		// Nodes of protocol version 0 ignore versioned hello packets, so unless we
+		// know which version the other side speaks, a legacy hello packet is sent
+		// every other time. Both of them either include the request or not.
+		let (versioned_hello, hello_request_included) = self.new_hello_packet(
 			sender.max_packet_length(),
 			&dh_private_key,
 			local_session_id,
 			request,
+			true,
 		);
+		let (legacy_hello, _) = self.new_hello_packet(
+			sender.max_packet_length(),
+			&dh_private_key,
+			local_session_id,
+			request.filter(|_| hello_request_included),
+			false,
+		);
+		let hello_packets = match node_id.and_then(|id| self.peer_version(id)) {
+			None => vec![versioned_hello, legacy_hello],
+			Some(0) => vec![legacy_hello],
+			Some(_) => vec![versioned_hello],
+		};
+
+		// Wait for the hello response to arrive while we keep sending hello packets
+		let started = SystemTime::now();
+		let sleep_time = min(timeout / 4, MAXIMUM_RETRY_TIMEOUT);
+		let mut attempt = 0;
 		while !stop_flag.load(Ordering::Relaxed)
 			&& SystemTime::now().duration_since(started).unwrap() < timeout
 		{
-			sender.send(&hello_packet).await?;
+			sender
+				.send(&hello_packets[attempt % hello_packets.len()])
+				.await?;
+			attempt += 1;
 
 			tokio::select! {
 				result = hello_receiver.recv() => {
@@ -507,14 +713,16 @@ impl Server {
 						timeout,
 						dh_private_key,
 						establish_info.dh_public_key,
-						packet_receiver
+						packet_receiver,
+						self.max_window_size,
 					);
-					let transporter_handle = transporter.spawn();
+					let transporter_handle = transporter.spawn(&self.tasks);
 
 					if !target.use_tcp {
 						self.send_hello_ack_ack_packet(&*sender, establish_info.dest_session_id).await?;
 					}
-					return Ok((Box::new(Connection {
+					self.traffic_metrics.opened_connections.fetch_add(1, Ordering::Relaxed);
+					let mut connection = Box::new(Connection {
 						transporter: transporter_handle,
 						server: self.clone(),
 						keep_alive_timeout: DEFAULT_KEEP_ALIVE_IDLE_TIME,
@@ -523,9 +731,20 @@ impl Server {
 							address: establish_info.node_id,
 							contact_info: establish_info.contact_info,
 						},
+						protocol_version: Some(establish_info.protocol_version),
 						dest_session_id: establish_info.dest_session_id,
 						local_session_id,
-					}), establish_info.opt_response));
+					});
+
+					// A request that didn't fit into the hello packet is sent as the first
+					// message on the connection instead, so its response will arrive on it
+					// as well.
+					if let Some(request_buffer) = request {
+						if !hello_request_included {
+							connection.send_async(request_buffer.to_vec())?;
+						}
+					}
+					return Ok((connection, establish_info.opt_response));
 				},
 				_ = sleep(sleep_time) => {}
 			}
@@ -540,6 +759,13 @@ impl Server {
 		}
 	}
 
+	fn handshake_extension(&self) -> HandshakeExtension {
+		HandshakeExtension {
+			protocol_version: PROTOCOL_VERSION,
+			proof_nonce: self.proof_nonce,
+		}
+	}
+
 	/// Gives the connection away to be start listening on it for requests
 	pub async fn handle_connection(
 		self: &Arc<Self>, connection: Box<Connection>, timeout: Option<Duration>,
@@ -621,17 +847,19 @@ impl Server {
 
 	fn new_hello_packet(
 		&self, max_len: usize, private_key: &x25519::StaticSecret, my_session_id: u16,
-		request: Option<&[u8]>,
+		request: Option<&[u8]>, versioned: bool,
 	) -> (Vec<u8>, bool) {
 		let (buffer, request_included) =
-			self.compose_hello_packet(max_len, private_key, my_session_id, request);
+			self.compose_hello_packet(max_len, private_key, my_session_id, request, versioned);
 		debug_assert!(buffer.len() <= max_len);
 		(buffer, request_included)
 	}
 
+	/// Composes the hello-ack packet, which is versioned if the hello packet
+	/// that it answers was.
 	fn new_hello_ack_packet(
 		&self, max_len: usize, dh_public_key: x25519::PublicKey, our_session_id: u16,
-		their_session_id: u16, addr: &SocketAddr, response: Option<&[u8]>,
+		their_session_id: u16, addr: &SocketAddr, response: Option<&[u8]>, versioned: bool,
 	) -> (Vec<u8>, bool) {
 		let contact_info = self.our_contact_info();
 		let body = HelloAckPacketBody {
@@ -641,13 +869,25 @@ impl Server {
 			contact_info: contact_info.clone(),
 			link_address: addr.clone().into(),
 		};
+		let extension = versioned.then(|| self.handshake_extension());
 
 		let body_offset = 1 + 96;
-		let response_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		let extension_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		let response_offset = extension_offset
+			+ extension
+				.as_ref()
+				.map(|e| binserde::serialized_size(e).unwrap())
+				.unwrap_or(0);
+		// A response that doesn't fit is sent over the transporter afterwards
+		let response = response.filter(|b| response_offset + b.len() <= max_len);
 		let packet_len = response_offset + response.map(|b| b.len()).unwrap_or(0);
-		debug_assert!(packet_len <= max_len);
-		let mut buffer = vec![PACKET_TYPE_HELLO_ACK; packet_len];
+		let packet_type =
+			if versioned { PACKET_TYPE_VERSIONED_HELLO_ACK } else { PACKET_TYPE_HELLO_ACK };
+		let mut buffer = vec![packet_type; packet_len];
 		binserde::serialize_into(&mut buffer[body_offset..], &body).unwrap();
+		if let Some(e) = &extension {
+			binserde::serialize_into(&mut buffer[extension_offset..], e).unwrap();
+		}
 
 		let response_included = if let Some(response_buffer) = response {
 			buffer[response_offset..].copy_from_slice(response_buffer);
@@ -684,8 +924,9 @@ impl Server {
 
 		let body_offset = 1 + 96;
 		let response_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		// A response that doesn't fit is sent over the transporter afterwards
+		let response = response.filter(|b| response_offset + b.len() <= max_len);
 		let packet_len = response_offset + response.map(|b| b.len()).unwrap_or(0);
-		debug_assert!(packet_len <= max_len);
 		let mut buffer = vec![PACKET_TYPE_RELAYED_HELLO_ACK; packet_len];
 		binserde::serialize_into(&mut buffer[body_offset..], &body).unwrap();
 
@@ -737,6 +978,9 @@ impl Server {
 			Some(id) => id,
 		};
 		sessions.map.insert(session_id, session_data.clone());
+		self.relay_metrics
+			.opened_sessions
+			.fetch_add(1, Ordering::Relaxed);
 		return Ok((session_id, session_data));
 	}
 
@@ -829,7 +1073,61 @@ impl Server {
 
 	pub fn our_contact_info(&self) -> ContactInfo { self.our_contact_info.lock().unwrap().clone() }
 
-	fn parse_hello_packet(buffer: &[u8]) -> Result<(HelloPacket, Option<&[u8]>)> {
+	/// Counts the bytes of a message that has been sent or received over one of
+	/// our own connections.
+	pub(super) fn count_transferred_bytes(
+		&self, bytes: usize, use_tcp: bool, incoming: bool, peer: &NodeAddress,
+	) {
+		let counter = match (use_tcp, incoming) {
+			(false, true) => &self.traffic_metrics.udp_bytes_in,
+			(false, false) => &self.traffic_metrics.udp_bytes_out,
+			(true, true) => &self.traffic_metrics.tcp_bytes_in,
+			(true, false) => &self.traffic_metrics.tcp_bytes_out,
+		};
+		counter.fetch_add(bytes as _, Ordering::Relaxed);
+
+		if let Some(peer_traffic) = self.peer_traffic.lock().unwrap().as_mut() {
+			let traffic = peer_traffic.entry(peer.clone()).or_default();
+			if incoming {
+				traffic.bytes_in += bytes as u64;
+			} else {
+				traffic.bytes_out += bytes as u64;
+			}
+		}
+	}
+
+	/// Takes the traffic per node that has been counted so far, and starts
+	/// counting from zero again. Nothing is counted until this has been called
+	/// once, so that it doesn't pile up if nobody takes it.
+	pub fn take_peer_traffic(&self) -> HashMap<NodeAddress, PeerTraffic> {
+		self.peer_traffic
+			.lock()
+			.unwrap()
+			.replace(HashMap::new())
+			.unwrap_or_default()
+	}
+
+	/// The number of bytes of messages that have been sent and received over
+	/// our own connections.
+	pub fn transferred_bytes(&self) -> u64 {
+		let stats = self.traffic_stats();
+		stats.udp_bytes_in + stats.udp_bytes_out + stats.tcp_bytes_in + stats.tcp_bytes_out
+	}
+
+	pub fn traffic_stats(&self) -> TrafficStats {
+		let metrics = &self.traffic_metrics;
+		TrafficStats {
+			udp_bytes_in: metrics.udp_bytes_in.load(Ordering::Relaxed),
+			udp_bytes_out: metrics.udp_bytes_out.load(Ordering::Relaxed),
+			tcp_bytes_in: metrics.tcp_bytes_in.load(Ordering::Relaxed),
+			tcp_bytes_out: metrics.tcp_bytes_out.load(Ordering::Relaxed),
+			opened_connections: metrics.opened_connections.load(Ordering::Relaxed),
+		}
+	}
+
+	fn parse_hello_packet(
+		buffer: &[u8], versioned: bool,
+	) -> Result<(HelloPacket, Option<HandshakeExtension>, Option<&[u8]>)> {
 		let header: HelloPacketHeader = binserde::deserialize_with_trailing(buffer)?;
 
 		// Verify that the signature is correct
@@ -843,15 +1141,35 @@ impl Server {
 
 		// Parse the remainder of the hello packet
 		let body: HelloPacketBody = binserde::deserialize_with_trailing(&buffer[body_offset..])?;
+		let mut request_offset = body_offset + binserde::serialized_size(&body).unwrap();
+		let extension = if versioned {
+			let extension: HandshakeExtension =
+				binserde::deserialize_with_trailing(&buffer[request_offset..])?;
+			request_offset += binserde::serialized_size(&extension).unwrap();
+			Some(extension)
+		} else {
+			None
+		};
 
-		let request_offset = body_offset + binserde::serialized_size(&body).unwrap();
 		let request = if request_offset < buffer.len() {
 			Some(&buffer[request_offset..])
 		} else {
 			None
 		};
 
-		Ok((HelloPacket { header, body }, request))
+		Ok((HelloPacket { header, body }, extension, request))
+	}
+
+	/// The protocol version that the given node has been seen speaking, if it
+	/// is known.
+	pub fn peer_version(&self, node_id: &NodeAddress) -> Option<u16> {
+		let versions = self.peer_versions.lock().unwrap();
+		let (version, seen) = versions.find(node_id)?;
+		// The node may have been upgraded in the meantime
+		if *version == 0 && seen.elapsed() >= LEGACY_PEER_RECHECK_INTERVAL {
+			return None;
+		}
+		Some(*version)
 	}
 
 	pub fn pick_contact_option(&self, target: &ContactInfo) -> Option<(ContactOption, Openness)> {
@@ -878,7 +1196,7 @@ impl Server {
 					SessionTransportData::Relay(data) =>
 						if sender == &data.source_addr {
 							if let Some(target_socket) = &data.target_sender {
-								Self::relay_crypted_packet(
+								self.relay_crypted_packet(
 									target_socket,
 									data.target_session_id,
 									&buffer[2..],
@@ -893,7 +1211,7 @@ impl Server {
 								false
 							}
 						} else if sender == &data.target_addr {
-							Self::relay_crypted_packet(
+							self.relay_crypted_packet(
 								&data.source_sender,
 								data.source_session_id,
 								&buffer[2..],
@@ -982,6 +1300,7 @@ impl Server {
 				dest_session_id: relay_session_id,
 				dh_public_key: packet.body.base.dh_public_key,
 				opt_response: None,
+				protocol_version: 0,
 			})
 			.await
 			.is_err()
@@ -1015,6 +1334,7 @@ impl Server {
 			packet.header.relayer_session_id,
 			packet.body.base.session_id,
 			packet.header.base.node_public_key,
+			None,
 			packet.body.base.dh_public_key,
 			packet.body.base.contact_info,
 			None,
@@ -1107,6 +1427,11 @@ impl Server {
 		&self, buffer: Vec<u8>, contact: ContactOption, node_id: &NodeAddress,
 		contact_info: &ContactInfo,
 	) -> Option<(Vec<u8>, Option<Box<dyn MessageWorkToDo>>)> {
+		if !self.rate_limiter.allow(node_id, &contact.target.ip(), &buffer) {
+			trace!("Dropping request from {}, it exceeds the rate limit.", node_id);
+			return None;
+		}
+
 		let node_info = NodeContactInfo {
 			address: node_id.clone(),
 			contact_info: contact_info.clone(),
@@ -1243,7 +1568,8 @@ impl Server {
 	async fn _process_hello_packet(
 		self: &Arc<Self>, sender: Arc<dyn LinkSocketSender>, contact: &ContactOption,
 		dest_session_id: u16, encrypt_session_id: u16, public_key: NodePublicKey,
-		dh_public_key: x25519::PublicKey, contact_info: ContactInfo, opt_request: Option<&[u8]>,
+		extension: Option<HandshakeExtension>, dh_public_key: x25519::PublicKey,
+		contact_info: ContactInfo, opt_request: Option<&[u8]>,
 		relayer_public_key: Option<NodePublicKey>,
 		new_packet: impl FnOnce(
 			usize,
@@ -1255,7 +1581,28 @@ impl Server {
 			Option<&[u8]>,
 		) -> (Vec<u8>, bool),
 	) -> Result<()> {
+		if let Some(e) = &extension {
+			if !public_key.verify_proof_of_work(e.proof_nonce) {
+				return trace::err(Error::InvalidProofOfWork);
+			}
+		}
 		let their_node_id = public_key.generate_address();
+		if self.banlist.is_node_banned(&their_node_id) {
+			trace!("Ignoring hello packet from banned node {}.", their_node_id);
+			return Ok(());
+		}
+		if self.reputations.is_distrusted(&their_node_id) {
+			trace!("Ignoring hello packet from distrusted node {}.", their_node_id);
+			return Ok(());
+		}
+		// Relayed hello packets don't tell the version, so they say nothing about it
+		let protocol_version = if relayer_public_key.is_none() {
+			let version = extension.map(|e| e.protocol_version).unwrap_or(0);
+			self.record_peer_version(&their_node_id, version);
+			Some(version)
+		} else {
+			self.peer_version(&their_node_id)
+		};
 		let alive_flag = Arc::new(AtomicBool::new(true));
 		let (packet_sender, packet_receiver) = mpsc::unbounded_channel();
 		let (our_session_id, is_new, session) = self
@@ -1354,8 +1701,9 @@ impl Server {
 			dh_private_key,
 			dh_public_key,
 			packet_receiver,
+			self.max_window_size,
 		);
-		let transporter_handle = transporter.spawn();
+		let transporter_handle = transporter.spawn(&self.tasks);
 
 		// Send hello-ack packet back after the session has been set up, and wait until
 		// it has been received by the other side.
@@ -1383,12 +1731,16 @@ impl Server {
 			address: their_node_id,
 			contact_info,
 		};
+		self.traffic_metrics
+			.opened_connections
+			.fetch_add(1, Ordering::Relaxed);
 		let connection = Box::new(Connection {
 			transporter: transporter_handle.clone(),
 			server: self.clone(),
 			keep_alive_timeout: self.default_timeout,
 			peer_address: contact.target.clone(),
 			peer_node_info: peer_node_info.clone(),
+			protocol_version,
 			dest_session_id,
 			local_session_id: our_session_id,
 		});
@@ -1429,8 +1781,13 @@ impl Server {
 
 	async fn process_hello_packet(
 		self: &Arc<Self>, sender: Arc<dyn LinkSocketSender>, addr: &ContactOption, buffer: &[u8],
+		versioned: bool,
 	) -> Result<()> {
-		let (hello, first_request_opt) = Self::parse_hello_packet(buffer)?;
+		if self.banlist.is_ip_banned(&addr.target.ip()) {
+			trace!("Ignoring hello packet from banned IP address {}.", addr);
+			return Ok(());
+		}
+		let (hello, extension, first_request_opt) = Self::parse_hello_packet(buffer, versioned)?;
 
 		let mut their_contact_info = hello.body.contact_info.clone();
 		their_contact_info.update(&addr.target, addr.use_tcp);
@@ -1441,6 +1798,7 @@ impl Server {
 			hello.body.session_id,
 			hello.body.session_id,
 			hello.header.node_public_key,
+			extension,
 			hello.body.dh_public_key,
 			hello.body.contact_info,
 			first_request_opt,
@@ -1453,6 +1811,7 @@ impl Server {
 					dest_session_id,
 					addr,
 					response,
+					versioned,
 				)
 			},
 		)
@@ -1461,12 +1820,20 @@ impl Server {
 
 	async fn process_hello_ack_packet(
 		&self, link_socket: &Arc<dyn LinkSocketSender>, sender: &SocketAddr,
-		connection_based: bool, buffer: &[u8],
+		connection_based: bool, buffer: &[u8], versioned: bool,
 	) -> Result<()> {
 		let body_offset = 96;
 		let packet: HelloAckPacket = binserde::deserialize_with_trailing(buffer)?;
 		debug_assert!(sender.is_ipv4() == packet.body.link_address.is_ipv4());
-		let response_offset = binserde::serialized_size(&packet).unwrap();
+		let mut response_offset = binserde::serialized_size(&packet).unwrap();
+		let extension = if versioned {
+			let extension: HandshakeExtension =
+				binserde::deserialize_with_trailing(&buffer[response_offset..])?;
+			response_offset += binserde::serialized_size(&extension).unwrap();
+			Some(extension)
+		} else {
+			None
+		};
 
 		// Get some info from the session the packet is directed to
 		let our_session_id = packet.body.source_session_id;
@@ -1493,6 +1860,11 @@ impl Server {
 				&packet.header.signature,
 				&buffer[body_offset..],
 			)?;
+			if let Some(e) = &extension {
+				if !packet.header.node_public_key.verify_proof_of_work(e.proof_nonce) {
+					return trace::err(Error::InvalidProofOfWork);
+				}
+			}
 
 			// Update our own contact info
 			self.our_contact_info
@@ -1518,6 +1890,9 @@ impl Server {
 			}
 		};
 
+		let protocol_version = extension.map(|e| e.protocol_version).unwrap_or(0);
+		self.record_peer_version(&their_node_id, protocol_version);
+
 		// Send the hello-ack-ack packet to the other side
 		let their_session_id = packet.body.target_session_id;
 		if !connection_based {
@@ -1541,6 +1916,7 @@ impl Server {
 					encrypt_session_id: their_session_id,
 					dh_public_key: packet.body.dh_public_key,
 					opt_response,
+					protocol_version,
 				})
 				.await
 				.is_err()
@@ -1682,7 +2058,7 @@ impl Server {
 		let buffer = &packet[1..];
 		match message_type {
 			PACKET_TYPE_HELLO =>
-				self.process_hello_packet(link_socket, contact, buffer)
+				self.process_hello_packet(link_socket, contact, buffer, false)
 					.await,
 			PACKET_TYPE_HELLO_ACK =>
 				self.process_hello_ack_packet(
@@ -1690,6 +2066,7 @@ impl Server {
 					&contact.target,
 					link_socket.is_connection_based(),
 					buffer,
+					false,
 				)
 				.await,
 			PACKET_TYPE_HELLO_ACK_ACK => self.process_hello_ack_ack_packet(&buffer).await,
@@ -1713,6 +2090,18 @@ impl Server {
 					.await,
 			PACKET_TYPE_RELAYED_HELLO_ACK_ACK =>
 				self.process_relayed_hello_ack_ack_packet(&buffer).await,
+			PACKET_TYPE_VERSIONED_HELLO =>
+				self.process_hello_packet(link_socket, contact, buffer, true)
+					.await,
+			PACKET_TYPE_VERSIONED_HELLO_ACK =>
+				self.process_hello_ack_packet(
+					&link_socket,
+					&contact.target,
+					link_socket.is_connection_based(),
+					buffer,
+					true,
+				)
+				.await,
 			// Hole punching packets don't need to be responded to. They don't have any data other
 			// than the message type anyway.
 			PACKET_TYPE_PUNCH_HOLE => Ok(()),
@@ -1720,6 +2109,19 @@ impl Server {
 		}
 	}
 
+	pub(crate) fn record_peer_version(&self, node_id: &NodeAddress, version: u16) {
+		let mut versions = self.peer_versions.lock().unwrap();
+		// A node that doesn't know our version yet sends legacy hello packets as
+		// well, which doesn't mean that it has been downgraded
+		if let Some((known, _)) = versions.find(node_id) {
+			if version < *known {
+				return;
+			}
+		}
+		versions.remove(node_id);
+		versions.insert(node_id.clone(), (version, Instant::now()));
+	}
+
 	/// Opens a relay connection to another node, through a relay node. This
 	/// only works if the relay node has relaying enabled.
 	/// The only use this has is for when the target node is able to receive
@@ -1801,14 +2203,42 @@ impl Server {
 	}
 
 	async fn relay_crypted_packet(
-		sender: &Arc<dyn LinkSocketSender>, new_session_id: u16, buffer: &[u8],
+		&self, sender: &Arc<dyn LinkSocketSender>, new_session_id: u16, buffer: &[u8],
 	) -> io::Result<()> {
 		let mut new_buffer = Vec::with_capacity(3 + buffer.len());
 		new_buffer.push(PACKET_TYPE_CRYPTED);
 		new_buffer.extend(new_session_id.to_le_bytes());
 		new_buffer.extend(buffer);
 
-		sender.send(&new_buffer).await
+		sender.send(&new_buffer).await?;
+		self.relay_metrics.packets.fetch_add(1, Ordering::Relaxed);
+		self.relay_metrics
+			.bytes
+			.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// The most packets that a window can grow to.
+	pub fn max_window_size(&self) -> u16 { self.max_window_size }
+
+	/// The most sessions that are kept open at the same time.
+	pub async fn sessions_limit(&self) -> usize { self.sessions.lock().await.limit }
+
+	/// Statistics about the sessions that we relay for other nodes.
+	pub async fn relay_stats(&self) -> RelayStats {
+		let sessions: Vec<_> = self.sessions.lock().await.map.values().cloned().collect();
+		let mut active_sessions = 0;
+		for session in sessions {
+			if let SessionTransportData::Relay(_) = &session.lock().await.transport_data {
+				active_sessions += 1;
+			}
+		}
+		RelayStats {
+			active_sessions,
+			opened_sessions: self.relay_metrics.opened_sessions.load(Ordering::Relaxed),
+			relayed_packets: self.relay_metrics.packets.load(Ordering::Relaxed),
+			relayed_bytes: self.relay_metrics.bytes.load(Ordering::Relaxed),
+		}
 	}
 
 	async fn send_hello_ack_ack_packet(
@@ -1961,8 +2391,10 @@ impl Server {
 		self.clone().spawn_garbage_collector();
 
 		let this = self.clone();
-		self.sockets
-			.spawn_servers(self.stop_flag.clone(), move |sender, contact, packet| {
+		self.sockets.spawn_servers(
+			&self.tasks,
+			self.stop_flag.clone(),
+			move |sender, contact, packet| {
 				let this2 = this.clone();
 				let sender2 = sender.clone();
 				let contact2 = contact.clone();
@@ -1976,11 +2408,22 @@ impl Server {
 							Error::ConnectionClosed => {}
 							Error::Timeout(timeout) =>
 								warn!("Timeout ({:?}) with {}.", timeout, &contact2),
+							Error::InvalidSignature => {
+								warn!("Invalid signature received from {}.", &contact2);
+								if this2.banlist.record_invalid_signature(contact2.target.ip()) {
+									warn!(
+										"Temporarily banned {} for sending too many invalid \
+										 signatures.",
+										contact2.target.ip()
+									);
+								}
+							}
 							_ => warn!("SSTP I/O error with {}: {:?}", &contact2, e),
 						},
 					}
 				});
-			});
+			},
+		);
 	}
 
 	/// Gives the connection away to be start listening on it for requests
@@ -1995,9 +2438,9 @@ impl Server {
 
 	/// Starts garbage collecting the unresponded requests.
 	pub fn spawn_garbage_collector(self: Arc<Self>) {
-		tokio::task::spawn(async move {
-			let this = self.clone();
-			while !self.stop_flag.load(Ordering::Relaxed) {
+		let this = self.clone();
+		self.tasks.spawn("sstp session garbage collector", async move {
+			while !this.stop_flag.load(Ordering::Relaxed) {
 				sleep(DEFAULT_TIMEOUT).await;
 				this.clean_sessions().await;
 			}
@@ -2005,7 +2448,8 @@ impl Server {
 	}
 
 	fn verify_hello_ack_packet_raw(
-		node_id: &NodeAddress, public_key: &NodePublicKey, signature: &NodeSignature, buffer: &[u8],
+		node_id: &NodeAddress, public_key: &NodePublicKey, signature: &NodeSignature,
+		buffer: &[u8],
 	) -> Result<()> {
 		// Verify node ID
 		if &public_key.generate_address_v1() != node_id.as_id().as_ref() {
@@ -2070,45 +2514,49 @@ impl SocketCollection {
 
 			// Parse UDPv4 configuration
 			if let Some(port) = config.ipv4_udp_port {
+				let openness = config
+					.ipv4_udp_openness
+					.as_ref()
+					.map(|s| match Openness::from_str(s) {
+						Ok(o) => o,
+						Err(_) => {
+							error!(
+								"Unable to parse UDPv4 openness \"{}\" from config file. \
+								 Assuming unidirectional.",
+								s
+							);
+							Openness::Unidirectional
+						}
+					})
+					.unwrap_or(Openness::Unidirectional);
 				servers.udp = Some(Arc::new(SstpSocketServer {
 					inner: UdpServer::bind(SocketAddrV4::new(addr, port)).await?,
-					openness: config
-						.ipv4_udp_openness
-						.as_ref()
-						.map(|s| match Openness::from_str(s) {
-							Ok(o) => o,
-							Err(_) => {
-								error!(
-									"Unable to parse UDPv4 openness \"{}\" from config file. \
-									 Assuming unidirectional.",
-									s
-								);
-								Openness::Unidirectional
-							}
-						})
-						.unwrap_or(Openness::Unidirectional),
+					openness,
+					nat: SimulatedNat::for_config(config, openness),
 				}));
 			}
 
 			// Parse TCPv4 configuration
 			if let Some(port) = config.ipv4_tcp_port {
+				let openness = config
+					.ipv4_tcp_openness
+					.as_ref()
+					.map(|s| match Openness::from_str(s) {
+						Ok(o) => o,
+						Err(_) => {
+							error!(
+								"Unable to parse TCPv4 openness \"{}\" from config file. \
+								 Assuming unidirectional.",
+								s
+							);
+							Openness::Unidirectional
+						}
+					})
+					.unwrap_or(Openness::Unidirectional);
 				servers.tcp = Some(Arc::new(SstpSocketServer {
 					inner: TcpServer::bind(SocketAddrV4::new(addr, port)).await?,
-					openness: config
-						.ipv4_tcp_openness
-						.as_ref()
-						.map(|s| match Openness::from_str(s) {
-							Ok(o) => o,
-							Err(_) => {
-								error!(
-									"Unable to parse TCPv4 openness \"{}\" from config file. \
-									 Assuming unidirectional.",
-									s
-								);
-								Openness::Unidirectional
-							}
-						})
-						.unwrap_or(Openness::Unidirectional),
+					openness,
+					nat: SimulatedNat::for_config(config, openness),
 				}));
 			}
 
@@ -2123,45 +2571,49 @@ impl SocketCollection {
 
 			// Parse UDPv6 configuration
 			if let Some(port) = config.ipv6_udp_port {
+				let openness = config
+					.ipv6_udp_openness
+					.as_ref()
+					.map(|s| match Openness::from_str(&s) {
+						Ok(o) => o,
+						Err(_) => {
+							error!(
+								"Unable to parse UDPv6 openness \"{}\" from config file. \
+								 Assuming unidirectional.",
+								s
+							);
+							Openness::Unidirectional
+						}
+					})
+					.unwrap_or(Openness::Unidirectional);
 				servers.udp = Some(Arc::new(SstpSocketServer {
 					inner: UdpServer::bind(SocketAddrV6::new(addr, port, 0, 0)).await?,
-					openness: config
-						.ipv6_udp_openness
-						.as_ref()
-						.map(|s| match Openness::from_str(&s) {
-							Ok(o) => o,
-							Err(_) => {
-								error!(
-									"Unable to parse UDPv6 openness \"{}\" from config file. \
-									 Assuming unidirectional.",
-									s
-								);
-								Openness::Unidirectional
-							}
-						})
-						.unwrap_or(Openness::Unidirectional),
+					openness,
+					nat: SimulatedNat::for_config(config, openness),
 				}));
 			}
 
 			// Parse TCPv6 configuration
 			if let Some(port) = config.ipv6_tcp_port {
+				let openness = config
+					.ipv6_tcp_openness
+					.as_ref()
+					.map(|s| match Openness::from_str(&s) {
+						Ok(o) => o,
+						Err(_) => {
+							error!(
+								"Unable to parse TCPv6 openness \"{}\" from config file. \
+								 Assuming unidirectional.",
+								s
+							);
+							Openness::Unidirectional
+						}
+					})
+					.unwrap_or(Openness::Unidirectional);
 				servers.tcp = Some(Arc::new(SstpSocketServer {
 					inner: TcpServer::bind(SocketAddrV6::new(addr, port, 0, 0)).await?,
-					openness: config
-						.ipv6_tcp_openness
-						.as_ref()
-						.map(|s| match Openness::from_str(&s) {
-							Ok(o) => o,
-							Err(_) => {
-								error!(
-									"Unable to parse TCPv6 openness \"{}\" from config file. \
-									 Assuming unidirectional.",
-									s
-								);
-								Openness::Unidirectional
-							}
-						})
-						.unwrap_or(Openness::Unidirectional),
+					openness,
+					nat: SimulatedNat::for_config(config, openness),
 				}));
 			}
 
@@ -2171,10 +2623,22 @@ impl SocketCollection {
 		Ok(this)
 	}
 
+	/// Puts the ports that the OS has picked for us into the contact info, for
+	/// the sockets that have been configured with port 0.
+	fn fill_in_ports(&self, contact_info: &mut ContactInfo) -> io::Result<()> {
+		if let (Some(servers), Some(entry)) = (&self.ipv4, &mut contact_info.ipv4) {
+			servers.fill_in_ports(&mut entry.availability)?;
+		}
+		if let (Some(servers), Some(entry)) = (&self.ipv6, &mut contact_info.ipv6) {
+			servers.fill_in_ports(&mut entry.availability)?;
+		}
+		Ok(())
+	}
+
 	/// This spawns all the loops that wait for incomming packets and
 	/// connections.
 	fn spawn_servers(
-		&self, stop_flag: Arc<AtomicBool>,
+		&self, tasks: &TaskRegistry, stop_flag: Arc<AtomicBool>,
 		on_packet: impl Fn(Arc<dyn LinkSocketSender>, &ContactOption, &[u8]) + Send + Sync + 'static,
 	) {
 		let on_packet2 = Arc::new(on_packet);
@@ -2185,13 +2649,13 @@ impl SocketCollection {
 					None => {}
 					Some(socket_server) => socket_server
 						.clone()
-						.spawn_connection_less(stop_flag.clone(), on_packet2.clone()),
+						.spawn_connection_less(tasks, stop_flag.clone(), on_packet2.clone()),
 				}
 				match &socket_servers.tcp {
 					None => {}
 					Some(socket_server) => socket_server
 						.clone()
-						.spawn_connection_based(stop_flag.clone(), on_packet2.clone()),
+						.spawn_connection_based(tasks, stop_flag.clone(), on_packet2.clone()),
 				}
 			}
 		}
@@ -2202,13 +2666,13 @@ impl SocketCollection {
 					None => {}
 					Some(socket_server) => socket_server
 						.clone()
-						.spawn_connection_less(stop_flag.clone(), on_packet2.clone()),
+						.spawn_connection_less(tasks, stop_flag.clone(), on_packet2.clone()),
 				}
 				match &socket_servers.tcp {
 					None => {}
 					Some(socket_server) => socket_server
 						.clone()
-						.spawn_connection_based(stop_flag, on_packet2),
+						.spawn_connection_based(tasks, stop_flag, on_packet2),
 				}
 			}
 		}
@@ -2251,15 +2715,20 @@ impl Sessions {
 		None
 	}
 
-	pub fn new() -> Self {
+	pub fn new(limit: usize) -> Self {
 		Self {
 			map: HashMap::new(),
 			next_id: 0,
+			limit,
 		}
 	}
 
-	/// Returns a new unused session ID, or None if all session ID's are taken.
+	/// Returns a new unused session ID, or None if all session ID's are taken,
+	/// or if the session limit has been reached.
 	pub fn next_id(&mut self) -> Option<u16> {
+		if self.map.len() >= self.limit {
+			return None;
+		}
 		let mut i = 0u16;
 		while self.map.contains_key(&self.next_id) {
 			self.next_id = self.next_id.wrapping_add(1);
@@ -2523,7 +2992,8 @@ impl SocketCollection {
 							None => {}
 							Some(server) => {
 								let (tx, rx) = server.inner.connect(a.clone())?.split();
-								return Ok((Arc::new(tx), Box::new(rx)));
+								let tx = server.wrap_sender(Arc::new(tx), contact.target);
+								return Ok((tx, Box::new(rx)));
 							}
 						}
 					} else {
@@ -2532,7 +3002,8 @@ impl SocketCollection {
 							Some(server) => {
 								let (tx, rx) =
 									server.inner.connect(a.clone(), timeout).await?.split();
-								return Ok((Arc::new(tx), Box::new(rx)));
+								let tx = server.wrap_sender(Arc::new(tx), contact.target);
+								return Ok((tx, Box::new(rx)));
 							}
 						}
 					},
@@ -2545,7 +3016,8 @@ impl SocketCollection {
 							None => {}
 							Some(server) => {
 								let (tx, rx) = server.inner.connect(a.clone())?.split();
-								return Ok((Arc::new(tx), Box::new(rx)));
+								let tx = server.wrap_sender(Arc::new(tx), contact.target);
+								return Ok((tx, Box::new(rx)));
 							}
 						}
 					} else {
@@ -2554,7 +3026,8 @@ impl SocketCollection {
 							Some(server) => {
 								let (tx, rx) =
 									server.inner.connect(a.clone(), timeout).await?.split();
-								return Ok((Arc::new(tx), Box::new(rx)));
+								let tx = server.wrap_sender(Arc::new(tx), contact.target);
+								return Ok((tx, Box::new(rx)));
 							}
 						}
 					},
@@ -2581,13 +3054,38 @@ impl SocketCollection {
 	}
 }
 
+impl<S> SstpSocketServer<S>
+where
+	S: LinkServer,
+{
+	/// Whether a packet from the given address gets through the simulated NAT
+	/// device, if any.
+	fn admits(&self, sender: &SocketAddr) -> bool {
+		match &self.nat {
+			Some(nat) => nat.admits(sender),
+			None => true,
+		}
+	}
+
+	fn wrap_sender(
+		&self, sender: Arc<dyn LinkSocketSender>, target: SocketAddr,
+	) -> Arc<dyn LinkSocketSender> {
+		match &self.nat {
+			Some(nat) => nat.wrap_sender(sender, target),
+			None => sender,
+		}
+	}
+}
+
 impl<S> SstpSocketServer<S>
 where
 	S: ConnectionLessLinkServer + 'static,
 {
-	fn spawn_connection_less(self: Arc<Self>, stop_flag: Arc<AtomicBool>, on_packet: OnPacket) {
+	fn spawn_connection_less(
+		self: Arc<Self>, tasks: &TaskRegistry, stop_flag: Arc<AtomicBool>, on_packet: OnPacket,
+	) {
 		let this = self.clone();
-		spawn(async move {
+		tasks.spawn("connection-less socket server", async move {
 			while !stop_flag.load(Ordering::Relaxed) {
 				match this.inner.listen().await {
 					Err(e) => match e.kind() {
@@ -2596,13 +3094,16 @@ where
 					},
 					Ok((packet, addr)) => {
 						let addr2: SocketAddr = addr.clone().into();
+						if !this.admits(&addr2) {
+							continue;
+						}
 						let contact = ContactOption::new(addr2, false);
 						let (sender, _) = this
 							.inner
 							.connect(addr.try_into().unwrap())
 							.expect("no error expected")
 							.split();
-						on_packet(Arc::new(sender), &contact, &packet);
+						on_packet(this.wrap_sender(Arc::new(sender), addr2), &contact, &packet);
 					}
 				}
 			}
@@ -2614,10 +3115,12 @@ impl<S> SstpSocketServer<S>
 where
 	S: ConnectionBasedLinkServer + 'static,
 {
-	fn spawn_connection_based(self: Arc<Self>, stop_flag: Arc<AtomicBool>, on_packet: OnPacket) {
+	fn spawn_connection_based(
+		self: Arc<Self>, tasks: &TaskRegistry, stop_flag: Arc<AtomicBool>, on_packet: OnPacket,
+	) {
 		// Spawn the loop that accepts connections
 		let this = self.clone();
-		spawn(async move {
+		tasks.spawn("connection-based socket server", async move {
 			while !stop_flag.load(Ordering::Relaxed) {
 				match this.inner.accept(Duration::from_secs(1)).await {
 					Err(e) => match e.kind() {
@@ -2627,15 +3130,21 @@ where
 					Ok(result) => match result {
 						None => return,
 						Some((socket, addr)) => {
+							let addr: SocketAddr = addr.into();
+							// Dropping the socket refuses the connection
+							if !this.admits(&addr) {
+								continue;
+							}
 							let stop_flag2 = stop_flag.clone();
 							let (sender, receiver) = socket.split();
+							let sender = this.wrap_sender(Arc::new(sender), addr);
 							let on_packet2 = on_packet.clone();
 							spawn(async move {
 								Server::serve_connection_based_socket(
 									stop_flag2,
-									Arc::new(sender),
+									sender,
 									Box::new(receiver),
-									addr.into(),
+									addr,
 									on_packet2,
 								)
 								.await;
@@ -2648,6 +3157,25 @@ where
 	}
 }
 
+impl<V> SstpSocketServers<V>
+where
+	V: Into<SocketAddr> + Send + Clone + 'static,
+{
+	fn fill_in_ports(&self, availability: &mut IpAvailability) -> io::Result<()> {
+		if let (Some(server), Some(entry)) = (&self.udp, &mut availability.udp) {
+			if entry.port == 0 {
+				entry.port = server.inner.local_addr()?.port();
+			}
+		}
+		if let (Some(server), Some(entry)) = (&self.tcp, &mut availability.tcp) {
+			if entry.port == 0 {
+				entry.port = server.inner.local_addr()?.port();
+			}
+		}
+		Ok(())
+	}
+}
+
 impl<V> Default for SstpSocketServers<V>
 where
 	V: Into<SocketAddr> + Send + Clone,
@@ -2700,6 +3228,7 @@ impl From<RelayHelloAckPacket> for HelloResult {
 			dest_session_id: other.body.relayer_session_id,
 			dh_public_key: other.body.base.dh_public_key,
 			opt_response: None,
+			protocol_version: 0,
 		}
 	}
 }
@@ -2751,9 +3280,23 @@ async fn handle_connection_loop(
 					);
 					return;
 				};
+				let contact = connection.contact_option();
+				// Only the request itself is dropped, the connection stays usable
+				if !server.rate_limiter.allow(
+					&connection.their_node_info().address,
+					&contact.target.ip(),
+					&message,
+				) {
+					trace!(
+						"Dropping request from {}, it exceeds the rate limit.",
+						connection.their_node_info().address
+					);
+					result = Some(connection);
+					continue;
+				}
 				if let Some((response, opt_todo)) = processor(
 					message,
-					connection.contact_option(),
+					contact,
 					connection.their_node_info().clone(),
 				)
 				.await
@@ -2788,3 +3331,227 @@ async fn handle_connection_loop(
 		}
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use rand::CryptoRng;
+
+	use super::*;
+	use crate::{common::IdType, config::Config, test};
+
+	/// Binds a server to a UDP port that the OS picks, so that tests don't get
+	/// in each other's way.
+	async fn bind_server(rng: &mut (impl CryptoRng + RngCore)) -> Arc<Server> {
+		let mut config = Config::default();
+		config.ipv4_address = Some("127.0.0.1".to_string());
+		config.ipv4_udp_port = Some(0);
+		let private_key = NodePrivateKey::generate_with_rng(rng);
+		let node_id = private_key.public().generate_address();
+		Server::bind(
+			Arc::new(AtomicBool::new(false)),
+			&config,
+			node_id,
+			private_key,
+			DEFAULT_TIMEOUT,
+		)
+		.await
+		.expect("unable to bind server")
+	}
+
+	/// The address of the server, as it tells it to other nodes.
+	fn local_addr(server: &Server) -> SocketAddr {
+		let entry = server.our_contact_info().ipv4.expect("no IPv4 contact info");
+		let port = entry.availability.udp.expect("no UDP port").port;
+		SocketAddr::new(entry.addr.into(), port)
+	}
+
+	#[tokio::test]
+	async fn test_fill_in_ports() {
+		let mut rng = test::initialize_rng();
+		let server = bind_server(&mut rng).await;
+
+		// The port that the OS picked is the one in the contact info
+		let servers = server.sockets.ipv4.as_ref().unwrap();
+		let bound_addr = servers.udp.as_ref().unwrap().inner.local_addr().unwrap();
+		assert_ne!(bound_addr.port(), 0);
+		assert_eq!(local_addr(&server), bound_addr);
+	}
+
+	#[tokio::test]
+	async fn test_hello_packet_size() {
+		let server = bind_server(&mut test::initialize_rng()).await;
+		let private_key = x25519::StaticSecret::random_from_rng(OsRng);
+		let max_len = 1000;
+		let (packet, included) = server.compose_hello_packet(max_len, &private_key, 1, None, true);
+		assert!(!included);
+		let overhead = packet.len();
+
+		// A request that makes the packet exactly as long as allowed still fits
+		let request = vec![1u8; max_len - overhead];
+		let (packet, included) =
+			server.compose_hello_packet(max_len, &private_key, 1, Some(&request), true);
+		assert!(included);
+		assert_eq!(packet.len(), max_len);
+		assert_eq!(&packet[overhead..], &request[..]);
+
+		let request = vec![1u8; max_len - overhead - 1];
+		let (packet, included) =
+			server.compose_hello_packet(max_len, &private_key, 1, Some(&request), true);
+		assert!(included);
+		assert_eq!(packet.len(), max_len - 1);
+
+		// A request that doesn't fit is left out completely
+		let request = vec![1u8; max_len - overhead + 1];
+		let (packet, included) =
+			server.compose_hello_packet(max_len, &private_key, 1, Some(&request), true);
+		assert!(!included);
+		assert_eq!(packet.len(), overhead);
+	}
+
+	#[tokio::test]
+	async fn test_hello_ack_packet_size() {
+		let mut rng = test::initialize_rng();
+		let server = bind_server(&mut rng).await;
+		let peer = bind_server(&mut rng).await;
+		let private_key = x25519::StaticSecret::random_from_rng(OsRng);
+		let public_key = x25519::PublicKey::from(&private_key);
+		let addr = local_addr(&peer);
+		let max_len = 1000;
+		let (packet, included) =
+			server.new_hello_ack_packet(max_len, public_key, 1, 2, &addr, None, true);
+		assert!(!included);
+		let overhead = packet.len();
+
+		let response = vec![1u8; max_len - overhead];
+		let (packet, included) =
+			server.new_hello_ack_packet(max_len, public_key, 1, 2, &addr, Some(&response), true);
+		assert!(included);
+		assert_eq!(packet.len(), max_len);
+		assert_eq!(&packet[overhead..], &response[..]);
+
+		let response = vec![1u8; max_len - overhead - 1];
+		let (packet, included) =
+			server.new_hello_ack_packet(max_len, public_key, 1, 2, &addr, Some(&response), true);
+		assert!(included);
+		assert_eq!(packet.len(), max_len - 1);
+
+		// A response that doesn't fit has to be sent over the transporter instead
+		let response = vec![1u8; max_len - overhead + 1];
+		let (packet, included) =
+			server.new_hello_ack_packet(max_len, public_key, 1, 2, &addr, Some(&response), true);
+		assert!(!included);
+		assert_eq!(packet.len(), overhead);
+	}
+
+	#[tokio::test]
+	async fn test_transporter_fallback() {
+		let mut rng = test::initialize_rng();
+		let client = bind_server(&mut rng).await;
+		let target = bind_server(&mut rng).await;
+		target.listen(
+			|request, _, _| Box::pin(async move { Some((request, None)) }),
+			|result, _| {
+				Box::pin(async move {
+					result.expect("message error");
+				})
+			},
+		);
+		target.spawn();
+		client.spawn();
+
+		// Neither the request nor the response fit into a hello or hello-ack
+		// packet, so both are sent over the transporter
+		let mut request = vec![0u8; 10000];
+		rng.fill_bytes(&mut request);
+		let option = ContactOption::new(local_addr(&target), false);
+		let (mut connection, response) = client
+			.connect(&option, Some(&target.node_id), Some(&request))
+			.await
+			.expect("unable to connect");
+		assert!(response.is_none());
+		let response = connection.receive().await.expect("no response received");
+		assert_eq!(response, request);
+		connection.close().await.unwrap();
+
+		client.stop_flag.store(true, Ordering::Relaxed);
+		target.stop_flag.store(true, Ordering::Relaxed);
+	}
+
+	#[tokio::test]
+	async fn test_legacy_hello_packet() {
+		let mut rng = test::initialize_rng();
+		let server = bind_server(&mut rng).await;
+		let private_key = x25519::StaticSecret::random_from_rng(OsRng);
+		let request = vec![1u8; 10];
+
+		// The legacy hello packet is still what nodes of version 0 expect
+		let (packet, _) =
+			server.compose_hello_packet(1000, &private_key, 1, Some(&request), false);
+		assert_eq!(packet[0], PACKET_TYPE_HELLO);
+		let (_, extension, parsed_request) =
+			Server::parse_hello_packet(&packet[1..], false).unwrap();
+		assert!(extension.is_none());
+		assert_eq!(parsed_request, Some(&request[..]));
+
+		let (packet, _) = server.compose_hello_packet(1000, &private_key, 1, Some(&request), true);
+		assert_eq!(packet[0], PACKET_TYPE_VERSIONED_HELLO);
+		let (_, extension, parsed_request) =
+			Server::parse_hello_packet(&packet[1..], true).unwrap();
+		let extension = extension.unwrap();
+		assert_eq!(extension.protocol_version, PROTOCOL_VERSION);
+		assert!(server
+			.private_key
+			.public()
+			.verify_proof_of_work(extension.proof_nonce));
+		assert_eq!(parsed_request, Some(&request[..]));
+
+		// A legacy hello from a node that has spoken a later version before
+		// doesn't downgrade it
+		let peer = NodeAddress::V1(IdType::random(&mut rng));
+		server.record_peer_version(&peer, 1);
+		server.record_peer_version(&peer, 0);
+		assert_eq!(server.peer_version(&peer), Some(1));
+	}
+
+	#[tokio::test]
+	async fn test_versioned_handshake() {
+		let mut rng = test::initialize_rng();
+		let client = bind_server(&mut rng).await;
+		let target = bind_server(&mut rng).await;
+		target.listen(
+			|request, _, _| Box::pin(async move { Some((request, None)) }),
+			|result, _| {
+				Box::pin(async move {
+					result.expect("message error");
+				})
+			},
+		);
+		target.spawn();
+		client.spawn();
+
+		let option = ContactOption::new(local_addr(&target), false);
+		let (mut connection, _) = client
+			.connect(&option, Some(&target.node_id), None)
+			.await
+			.expect("unable to connect");
+		assert_eq!(connection.protocol_version(), Some(PROTOCOL_VERSION));
+		assert_eq!(client.peer_version(&target.node_id), Some(PROTOCOL_VERSION));
+		assert_eq!(target.peer_version(&client.node_id), Some(PROTOCOL_VERSION));
+		connection.close().await.unwrap();
+
+		// A node that is known to speak version 0 is only sent legacy hello
+		// packets, which are answered as well
+		client.peer_versions.lock().unwrap().remove(&target.node_id);
+		client.record_peer_version(&target.node_id, 0);
+		let (mut connection, _) = client
+			.connect(&option, Some(&target.node_id), None)
+			.await
+			.expect("unable to connect");
+		assert_eq!(connection.protocol_version(), Some(0));
+		connection.close().await.unwrap();
+
+		client.stop_flag.store(true, Ordering::Relaxed);
+		target.stop_flag.store(true, Ordering::Relaxed);
+	}
+}
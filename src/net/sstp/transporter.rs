@@ -17,6 +17,8 @@ const CRYPTED_PACKET_TYPE_CLOSE: u8 = 3;
 const CRYPTED_PACKET_TYPE_CLOSE_ACK: u8 = 4;
 const FIRST_WINDOW_HEADER_SIZE: usize = WINDOW_HEADER_SIZE + MESSAGE_HEADER_SIZE;
 const INITIAL_WINDOW_SIZE: u16 = 16;
+/// The most packets that a window can grow to, unless limited further.
+pub(super) const MAX_WINDOW_SIZE: u16 = 0xFFFF;
 const MESSAGE_HEADER_SIZE: usize = 4;
 const WINDOW_HEADER_SIZE: usize = 34;
 
@@ -116,6 +118,7 @@ enum TransporterTask {
 struct WindowInfo {
 	size: u16,
 	starting: bool,
+	max_size: u16,
 }
 
 
@@ -268,6 +271,7 @@ impl Transporter {
 		their_session_id: u16, socket_sender: Arc<dyn LinkSocketSender>, node_id: NodeAddress,
 		peer_node_id: NodeAddress, timeout: Duration, private_key: x25519::StaticSecret,
 		public_key: x25519::PublicKey, receiver: UnboundedReceiver<CryptedPacket>,
+		max_window_size: u16,
 	) -> Self {
 		Self {
 			inner: TransporterInner::new(
@@ -279,6 +283,7 @@ impl Transporter {
 				node_id,
 				peer_node_id,
 				timeout,
+				max_window_size,
 			),
 			alive_flag,
 			key_state_manager: KeyStateManager::new(private_key, public_key, INITIAL_WINDOW_SIZE),
@@ -533,7 +538,7 @@ impl Transporter {
 	}
 
 	/// Runs the transporter in the background.
-	pub fn spawn(self) -> TransporterHandle {
+	pub fn spawn(self, tasks: &TaskRegistry) -> TransporterHandle {
 		// Packets will be queued by the underlying socket implementation, so no reason
 		// to have an unbounded (or large) channel buffer.
 		// The task channel is unbounded, because otherwise if the run loop has exitted
@@ -546,7 +551,7 @@ impl Transporter {
 			is_connection_based: self.inner.socket_sender.is_connection_based(),
 			socket_sender: self.inner.socket_sender.clone(),
 		};
-		spawn(self.run(rx));
+		tasks.spawn("sstp transporter", self.run(rx));
 		handle
 	}
 }
@@ -705,7 +710,7 @@ impl TransporterInner {
 		encrypt_session_id: u16, our_session_id: u16, their_session_id: u16,
 		socket_sender: Arc<dyn LinkSocketSender>,
 		packet_receiver: UnboundedReceiver<CryptedPacket>, node_id: NodeAddress,
-		peer_node_id: NodeAddress, timeout: Duration,
+		peer_node_id: NodeAddress, timeout: Duration, max_window_size: u16,
 	) -> Self {
 		Self {
 			close_received: false,
@@ -726,9 +731,9 @@ impl TransporterInner {
 			peer_node_id,
 			previous_window_size: 0,
 			packet_receiver,
-			receive_window: WindowInfo::default(),
+			receive_window: WindowInfo::new(max_window_size),
 			requested_window_size: 0,
-			send_window: WindowInfo::default(),
+			send_window: WindowInfo::new(max_window_size),
 			socket_sender,
 			dest_session_id: their_session_id,
 			timeout,
@@ -1879,7 +1884,7 @@ impl WindowInfo {
 	}
 
 	pub fn increase_window_size(&self) -> u16 {
-		if self.starting {
+		let size = if self.starting {
 			if self.size < 0x8000 {
 				self.size << 1
 			} else {
@@ -1891,15 +1896,15 @@ impl WindowInfo {
 			} else {
 				0xFFFF
 			}
-		}
+		};
+		size.min(self.max_size)
 	}
-}
 
-impl Default for WindowInfo {
-	fn default() -> Self {
+	fn new(max_size: u16) -> Self {
 		Self {
 			starting: true,
-			size: INITIAL_WINDOW_SIZE,
+			size: INITIAL_WINDOW_SIZE.min(max_size),
+			max_size,
 		}
 	}
 }
@@ -1911,6 +1916,18 @@ mod tests {
 	use super::*;
 	use crate::test;
 
+	#[test]
+	fn test_window_size_limit() {
+		let mut window = WindowInfo::new(64);
+		while window.size < 64 {
+			window.size = window.increase_window_size();
+		}
+		assert_eq!(window.size, 64);
+		window.starting = false;
+		assert_eq!(window.increase_window_size(), 64);
+		assert_eq!(WindowInfo::new(MAX_WINDOW_SIZE).increase_window_size(), 32);
+	}
+
 	#[test]
 	fn test_encryption() {
 		let mut rng = test::initialize_rng();
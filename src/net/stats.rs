@@ -0,0 +1,259 @@
+//! Collects statistics about the lookups that this node does on the network,
+//! and about the exchanges with other nodes that they are made of.
+//!
+//! The metrics are shared by the overlay node and all actor nodes, and only
+//! consist of atomic counters, so that recording them is cheap.
+
+use std::{
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+use serde::Serialize;
+
+use super::node::ContactStrategyMethod;
+
+
+/// The upper bounds of the buckets of the hop count histograms.
+const HOPS_BOUNDS: &[u64] = &[0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32];
+/// The upper bounds of the buckets of the lookup time histograms, in
+/// milliseconds.
+const TIME_BOUNDS: &[u64] = &[10, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 20000, 60000];
+
+
+pub struct NetworkMetrics {
+	find_node: LookupMetrics,
+	find_value: LookupMetrics,
+	exchanges: ExchangeMetrics,
+	/// The exchange metrics for each contact strategy, indexed by
+	/// `ContactStrategyMethod::to_byte`.
+	strategies: [ExchangeMetrics; 4],
+}
+
+struct LookupMetrics {
+	completed: AtomicU64,
+	succeeded: AtomicU64,
+	hops: Histogram,
+	time: Histogram,
+}
+
+#[derive(Default)]
+struct ExchangeMetrics {
+	attempts: AtomicU64,
+	failures: AtomicU64,
+	timeouts: AtomicU64,
+}
+
+struct Histogram {
+	bounds: &'static [u64],
+	/// One counter for each bound, and one for everything above the last bound.
+	counts: Vec<AtomicU64>,
+	sum: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LookupKind {
+	FindNode,
+	FindValue,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkStats {
+	pub find_node: LookupStats,
+	pub find_value: LookupStats,
+	pub exchanges: ExchangeStats,
+	pub direct: ExchangeStats,
+	pub hole_punch: ExchangeStats,
+	pub reversed: ExchangeStats,
+	pub relay: ExchangeStats,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LookupStats {
+	pub completed: u64,
+	pub succeeded: u64,
+	/// The number of hops that each lookup took.
+	pub hops: HistogramStats,
+	/// The time it took for each lookup to finish, in milliseconds. For value
+	/// lookups that succeeded, this is the time-to-value.
+	pub time: HistogramStats,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExchangeStats {
+	pub attempts: u64,
+	pub failures: u64,
+	pub timeouts: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HistogramStats {
+	/// The upper bounds (inclusive) of the buckets.
+	pub bounds: Vec<u64>,
+	/// The number of samples in each bucket. The last one counts the samples
+	/// that exceeded the last bound.
+	pub counts: Vec<u64>,
+	pub count: u64,
+	pub sum: u64,
+}
+
+
+impl NetworkMetrics {
+	pub fn new() -> Self {
+		Self {
+			find_node: LookupMetrics::new(),
+			find_value: LookupMetrics::new(),
+			exchanges: ExchangeMetrics::default(),
+			strategies: Default::default(),
+		}
+	}
+
+	fn lookup(&self, kind: LookupKind) -> &LookupMetrics {
+		match kind {
+			LookupKind::FindNode => &self.find_node,
+			LookupKind::FindValue => &self.find_value,
+		}
+	}
+
+	/// Records an exchange with another node, and whether it succeeded.
+	pub fn record_exchange(&self, succeeded: bool, timed_out: bool) {
+		self.exchanges.record(succeeded, timed_out);
+	}
+
+	/// Records a finished lookup, with the number of hops that it took to get to
+	/// the node that answered it, or that it went on without getting there.
+	pub fn record_lookup(&self, kind: LookupKind, succeeded: bool, hops: usize, time: Duration) {
+		let metrics = self.lookup(kind);
+		metrics.completed.fetch_add(1, Ordering::Relaxed);
+		if succeeded {
+			metrics.succeeded.fetch_add(1, Ordering::Relaxed);
+		}
+		metrics.hops.record(hops as u64);
+		metrics.time.record(time.as_millis() as u64);
+	}
+
+	/// Records an attempt to reach a node with the given contact strategy.
+	pub fn record_strategy(
+		&self, method: &ContactStrategyMethod, succeeded: bool, timed_out: bool,
+	) {
+		self.strategies[method.to_byte() as usize].record(succeeded, timed_out);
+	}
+
+	pub fn stats(&self) -> NetworkStats {
+		NetworkStats {
+			find_node: self.find_node.stats(),
+			find_value: self.find_value.stats(),
+			exchanges: self.exchanges.stats(),
+			direct: self.strategies[0].stats(),
+			hole_punch: self.strategies[1].stats(),
+			reversed: self.strategies[2].stats(),
+			relay: self.strategies[3].stats(),
+		}
+	}
+}
+
+impl Default for NetworkMetrics {
+	fn default() -> Self { Self::new() }
+}
+
+impl LookupMetrics {
+	fn new() -> Self {
+		Self {
+			completed: AtomicU64::new(0),
+			succeeded: AtomicU64::new(0),
+			hops: Histogram::new(HOPS_BOUNDS),
+			time: Histogram::new(TIME_BOUNDS),
+		}
+	}
+
+	fn stats(&self) -> LookupStats {
+		LookupStats {
+			completed: self.completed.load(Ordering::Relaxed),
+			succeeded: self.succeeded.load(Ordering::Relaxed),
+			hops: self.hops.stats(),
+			time: self.time.stats(),
+		}
+	}
+}
+
+impl ExchangeMetrics {
+	fn record(&self, succeeded: bool, timed_out: bool) {
+		self.attempts.fetch_add(1, Ordering::Relaxed);
+		if !succeeded {
+			self.failures.fetch_add(1, Ordering::Relaxed);
+			if timed_out {
+				self.timeouts.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+	}
+
+	fn stats(&self) -> ExchangeStats {
+		ExchangeStats {
+			attempts: self.attempts.load(Ordering::Relaxed),
+			failures: self.failures.load(Ordering::Relaxed),
+			timeouts: self.timeouts.load(Ordering::Relaxed),
+		}
+	}
+}
+
+impl Histogram {
+	fn new(bounds: &'static [u64]) -> Self {
+		Self {
+			bounds,
+			counts: (0..(bounds.len() + 1)).map(|_| AtomicU64::new(0)).collect(),
+			sum: AtomicU64::new(0),
+		}
+	}
+
+	fn record(&self, sample: u64) {
+		let index = self
+			.bounds
+			.iter()
+			.position(|b| sample <= *b)
+			.unwrap_or(self.bounds.len());
+		self.counts[index].fetch_add(1, Ordering::Relaxed);
+		self.sum.fetch_add(sample, Ordering::Relaxed);
+	}
+
+	fn stats(&self) -> HistogramStats {
+		let counts: Vec<u64> = self
+			.counts
+			.iter()
+			.map(|c| c.load(Ordering::Relaxed))
+			.collect();
+		HistogramStats {
+			bounds: self.bounds.to_vec(),
+			count: counts.iter().sum(),
+			counts,
+			sum: self.sum.load(Ordering::Relaxed),
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_network_metrics() {
+		let metrics = NetworkMetrics::new();
+		metrics.record_lookup(LookupKind::FindValue, true, 3, Duration::from_millis(120));
+		metrics.record_lookup(LookupKind::FindValue, false, 40, Duration::from_secs(90));
+		metrics.record_strategy(&ContactStrategyMethod::Relay, false, true);
+		metrics.record_strategy(&ContactStrategyMethod::Direct, true, false);
+
+		let stats = metrics.stats();
+		assert_eq!(stats.find_node.completed, 0);
+		assert_eq!(stats.find_value.completed, 2);
+		assert_eq!(stats.find_value.succeeded, 1);
+		assert_eq!(stats.find_value.hops.counts[3], 1);
+		assert_eq!(stats.find_value.hops.counts[HOPS_BOUNDS.len()], 1);
+		assert_eq!(stats.find_value.hops.sum, 43);
+		assert_eq!(stats.find_value.time.counts[3], 1);
+		assert_eq!(stats.find_value.time.count, 2);
+		assert_eq!(stats.relay.timeouts, 1);
+		assert_eq!(stats.direct.attempts, 1);
+		assert_eq!(stats.direct.failures, 0);
+	}
+}
@@ -0,0 +1,101 @@
+//! Remembers the values that have recently been found on the network, so that
+//! looking up the same value again doesn't require another network search.
+
+use std::time::{Duration, Instant};
+
+use super::NodeContactInfo;
+use crate::{common::*, limited_store::*};
+
+
+/// The maximum number of values to remember per network.
+pub const VALUE_CACHE_CAPACITY: usize = 1000;
+pub const VALUE_CACHE_CAPACITY_LOW_MEMORY: usize = 100;
+/// Values that are larger than this are not cached, so that the cache can't
+/// grow too large. This leaves out blocks, but those are stored in the
+/// database anyway.
+const VALUE_CACHE_MAX_VALUE_SIZE: usize = 0x10000;
+/// The time after which a cached value is not used anymore, so that the nodes
+/// that are advertised along with it don't become too outdated.
+const VALUE_CACHE_TTL: Duration = Duration::from_secs(600);
+
+
+pub struct ValueCache {
+	map: LimitedMap<(u8, IdType), CachedValue>,
+}
+
+struct CachedValue {
+	value: Vec<u8>,
+	/// The node that the value was obtained from.
+	peer: NodeContactInfo,
+	found: Instant,
+}
+
+
+impl ValueCache {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			map: LimitedMap::new(capacity),
+		}
+	}
+
+	/// Returns the cached value, and the node it was obtained from, if it is
+	/// still fresh.
+	pub fn get(&mut self, value_type: u8, id: &IdType) -> Option<(Vec<u8>, NodeContactInfo)> {
+		let key = (value_type, id.clone());
+		let entry = self.map.promote(&key)?;
+		if entry.found.elapsed() < VALUE_CACHE_TTL {
+			Some((entry.value.clone(), entry.peer.clone()))
+		} else {
+			self.map.remove(&key);
+			None
+		}
+	}
+
+	pub fn insert(&mut self, value_type: u8, id: &IdType, value: &[u8], peer: &NodeContactInfo) {
+		if value.len() > VALUE_CACHE_MAX_VALUE_SIZE {
+			return;
+		}
+
+		let key = (value_type, id.clone());
+		self.map.remove(&key);
+		self.map.insert(
+			key,
+			CachedValue {
+				value: value.to_vec(),
+				peer: peer.clone(),
+				found: Instant::now(),
+			},
+		);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{core::NodeAddress, net::ContactInfo, test};
+
+	#[test]
+	fn test_value_cache() {
+		let mut rng = test::initialize_rng();
+		let peer = NodeContactInfo {
+			address: NodeAddress::V1(IdType::random(&mut rng)),
+			contact_info: ContactInfo::default(),
+		};
+		let ids: Vec<IdType> = (0..3).map(|_| IdType::random(&mut rng)).collect();
+
+		let mut cache = ValueCache::new(2);
+		cache.insert(0, &ids[0], b"first", &peer);
+		cache.insert(0, &ids[1], b"second", &peer);
+		assert!(cache.get(1, &ids[0]).is_none(), "value type not used as key");
+		// Using the first value should make the second one the first to go
+		assert_eq!(cache.get(0, &ids[0]).unwrap().0, b"first");
+		cache.insert(0, &ids[2], b"third", &peer);
+		assert!(cache.get(0, &ids[1]).is_none());
+		assert_eq!(cache.get(0, &ids[0]).unwrap().0, b"first");
+		assert_eq!(cache.get(0, &ids[2]).unwrap().0, b"third");
+
+		cache.insert(0, &ids[0], &vec![0u8; VALUE_CACHE_MAX_VALUE_SIZE + 1], &peer);
+		assert_eq!(cache.get(0, &ids[0]).unwrap().0, b"first");
+	}
+}
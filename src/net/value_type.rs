@@ -0,0 +1,130 @@
+//! The registry of the types of values that can be looked up on the network.
+//!
+//! Each node interface has its own registry, in which a handler is registered
+//! for every value type that it knows. The handler loads the value when
+//! another node asks for it, and verifies the value when it is found on the
+//! network. New kinds of values can therefore be supported by registering
+//! another handler, without changing the lookup code itself.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use super::message::SignedValue;
+use crate::{common::IdType, db};
+
+
+#[async_trait]
+pub trait ValueHandler<C>: Send + Sync
+where
+	C: ?Sized,
+{
+	/// Loads the value with the given ID, serialized, if we have it.
+	async fn find(&self, context: &C, id: &IdType) -> db::Result<Option<Vec<u8>>>;
+
+	/// Whether the value can't be verified by its ID alone. These values are
+	/// wrapped in a `SignedValue` by the node that stores them, and their
+	/// signature is checked with `verify_signature`.
+	fn is_signed(&self) -> bool { false }
+
+	/// Verifies a value that has been found on the network. For signed
+	/// values, this is the data inside the `SignedValue`.
+	fn verify(&self, context: &C, id: &IdType, data: &[u8]) -> bool;
+
+	/// Verifies a value against the signature and publication time of its
+	/// original publisher.
+	fn verify_signature(&self, _context: &C, _id: &IdType, _value: &SignedValue) -> bool { false }
+}
+
+/// Maps value types to the handlers that know them.
+pub struct ValueTypeRegistry<C>
+where
+	C: ?Sized,
+{
+	handlers: HashMap<u8, Box<dyn ValueHandler<C>>>,
+}
+
+
+impl<C> ValueTypeRegistry<C>
+where
+	C: ?Sized,
+{
+	pub fn new() -> Self {
+		Self {
+			handlers: HashMap::new(),
+		}
+	}
+
+	pub fn get(&self, value_type: u8) -> Option<&dyn ValueHandler<C>> {
+		self.handlers.get(&value_type).map(|h| h.as_ref())
+	}
+
+	/// Registers the handler for the given value type.
+	///
+	/// # Panics
+	/// If a handler has already been registered for the value type.
+	pub fn register(&mut self, value_type: u8, handler: impl ValueHandler<C> + 'static) {
+		let old = self.handlers.insert(value_type, Box::new(handler));
+		assert!(
+			old.is_none(),
+			"value type {} has been registered twice",
+			value_type
+		);
+	}
+
+	/// Like `register`, but returns the registry for chaining.
+	pub fn with(mut self, value_type: u8, handler: impl ValueHandler<C> + 'static) -> Self {
+		self.register(value_type, handler);
+		self
+	}
+}
+
+impl<C> Default for ValueTypeRegistry<C>
+where
+	C: ?Sized,
+{
+	fn default() -> Self { Self::new() }
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct Store(Vec<u8>);
+
+	struct HashHandler;
+
+	#[async_trait]
+	impl ValueHandler<Store> for HashHandler {
+		async fn find(&self, context: &Store, id: &IdType) -> db::Result<Option<Vec<u8>>> {
+			if &IdType::hash(&context.0) == id {
+				Ok(Some(context.0.clone()))
+			} else {
+				Ok(None)
+			}
+		}
+
+		fn verify(&self, _context: &Store, id: &IdType, data: &[u8]) -> bool {
+			&IdType::hash(data) == id
+		}
+	}
+
+	#[tokio::test]
+	async fn test_value_type_registry() {
+		let registry = ValueTypeRegistry::new().with(1, HashHandler);
+		let store = Store(b"value".to_vec());
+		let id = IdType::hash(&store.0);
+
+		assert!(registry.get(0).is_none());
+		let handler = registry.get(1).unwrap();
+		assert!(!handler.is_signed());
+		let value = handler.find(&store, &id).await.unwrap().unwrap();
+		assert!(handler.verify(&store, &id, &value));
+		assert!(!handler.verify(&store, &id, b"other value"));
+	}
+
+	#[test]
+	#[should_panic]
+	fn test_register_twice() { ValueTypeRegistry::new().with(1, HashHandler).with(1, HashHandler); }
+}
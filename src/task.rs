@@ -0,0 +1,134 @@
+//! Keeps track of the long-lived tasks that are running in the background.
+//!
+//! Every task is registered with a name, so that it can be seen what the node
+//! is busy with, and so that shutting down the node can wait for all of them to
+//! finish. Tasks that don't finish within the grace period are aborted.
+
+use std::{future::Future, sync::Mutex, time::Duration};
+
+use chrono::Utc;
+use log::*;
+use serde::Serialize;
+use tokio::{
+	spawn,
+	task::JoinHandle,
+	time::{sleep, Instant},
+};
+
+
+/// How often it is checked whether all tasks have finished during shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+
+#[derive(Default)]
+pub struct TaskRegistry {
+	tasks: Mutex<Vec<RegisteredTask>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskInfo {
+	pub name: String,
+	/// The time the task was started at, in milliseconds since the epoch.
+	pub started: i64,
+}
+
+struct RegisteredTask {
+	info: TaskInfo,
+	handle: JoinHandle<()>,
+}
+
+
+impl TaskRegistry {
+	pub fn new() -> Self { Self::default() }
+
+	/// Lists the tasks that are still running.
+	pub fn list(&self) -> Vec<TaskInfo> {
+		let mut tasks = self.tasks.lock().unwrap();
+		tasks.retain(|t| !t.handle.is_finished());
+		tasks.iter().map(|t| t.info.clone()).collect()
+	}
+
+	/// Waits for all tasks to finish, but no longer than the given grace
+	/// period. The tasks that are still running after that are aborted.
+	/// Returns the number of tasks that had to be aborted.
+	pub async fn shutdown(&self, grace_period: Duration) -> usize {
+		let deadline = Instant::now() + grace_period;
+		loop {
+			if self.list().is_empty() {
+				return 0;
+			}
+			if Instant::now() >= deadline {
+				break;
+			}
+			sleep(SHUTDOWN_POLL_INTERVAL).await;
+		}
+
+		let tasks: Vec<RegisteredTask> = self.tasks.lock().unwrap().drain(..).collect();
+		let mut aborted = 0;
+		for task in tasks {
+			if !task.handle.is_finished() {
+				warn!(
+					"Task \"{}\" didn't finish in time, aborting it.",
+					task.info.name
+				);
+				task.handle.abort();
+				aborted += 1;
+			}
+		}
+		aborted
+	}
+
+	/// Spawns the future as a new task, and registers it under the given name.
+	pub fn spawn<F>(&self, name: impl Into<String>, future: F)
+	where
+		F: Future<Output = ()> + Send + 'static,
+	{
+		let handle = spawn(future);
+		let mut tasks = self.tasks.lock().unwrap();
+		// Forget about the tasks that have finished already, so that short-lived
+		// tasks don't pile up.
+		tasks.retain(|t| !t.handle.is_finished());
+		tasks.push(RegisteredTask {
+			info: TaskInfo {
+				name: name.into(),
+				started: Utc::now().timestamp_millis(),
+			},
+			handle,
+		});
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		future::pending,
+		sync::{
+			atomic::{AtomicBool, Ordering},
+			Arc,
+		},
+	};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_shutdown() {
+		let registry = TaskRegistry::new();
+		let stop_flag = Arc::new(AtomicBool::new(false));
+		let stop_flag2 = stop_flag.clone();
+		registry.spawn("cooperative", async move {
+			while !stop_flag2.load(Ordering::Relaxed) {
+				sleep(Duration::from_millis(10)).await;
+			}
+		});
+		registry.spawn("wedged", pending());
+
+		let names: Vec<String> = registry.list().into_iter().map(|t| t.name).collect();
+		assert_eq!(names, vec!["cooperative", "wedged"]);
+
+		stop_flag.store(true, Ordering::Relaxed);
+		let aborted = registry.shutdown(Duration::from_millis(200)).await;
+		assert_eq!(aborted, 1);
+		assert_eq!(registry.list().len(), 0);
+	}
+}
@@ -49,6 +49,25 @@ pub async fn load_database(filename: &str) -> Database {
 	db
 }
 
+/// A config for a node on the loopback interface, on a port picked by the OS,
+/// that acts as if it is behind a NAT device of the given openness.
+pub fn simulated_config(openness: &str, bootstrap_nodes: Vec<String>) -> Config {
+	let mut config = Config::default();
+	config.bootstrap_nodes = bootstrap_nodes;
+	config.ipv4_address = Some("127.0.0.1".to_string());
+	config.ipv4_udp_port = Some(0);
+	config.ipv4_udp_openness = Some(openness.to_string());
+	config.simulate_openness = Some(true);
+	config
+}
+
+/// The address that other nodes can use to bootstrap from the test node.
+pub fn bootstrap_address(node: &Api) -> String {
+	let entry = node.node.contact_info().ipv4.expect("no IPv4 contact info");
+	let port = entry.availability.udp.expect("no UDP port").port;
+	format!("{}:{}", entry.addr, port)
+}
+
 /// Sets up a node usable for testing.
 pub async fn load_test_node(
 	stop_flag: Arc<AtomicBool>, rng: &mut (impl CryptoRng + RngCore), config: &Config,
@@ -58,14 +77,10 @@ pub async fn load_test_node(
 
 	let private_key = NodePrivateKey::generate_with_rng(rng);
 	let node_id = private_key.public().generate_address();
-	info!(
-		"Node {} runs on port {}.",
-		node_id,
-		config.ipv4_udp_port.expect("no port in config")
-	);
 	let node = OverlayNode::start(stop_flag.clone(), &config, node_id, private_key, db.clone())
 		.await
 		.expect("unable to start node");
+	info!("Node {} runs at {}.", node.node_id(), node.contact_info());
 
 	node.join_network(stop_flag).await;
 
@@ -1,8 +1,53 @@
-use std::{io, path::Path};
+use std::{
+	io,
+	path::Path,
+	sync::atomic::{AtomicBool, Ordering},
+	time::Duration,
+};
 
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{
+	fs::File,
+	io::AsyncReadExt,
+	runtime::{Handle, RuntimeFlavor},
+	select,
+	time::sleep,
+};
 
 
+/// How often a sleeping task checks whether it is being stopped.
+const STOP_FLAG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// Runs the given blocking closure without holding up the other tasks.
+///
+/// On the multi-threaded runtime, the other tasks are moved off of the current
+/// thread first. This isn't possible on the current-thread runtime, so there
+/// the closure simply blocks the runtime while it runs.
+pub fn block_in_place<F, R>(f: F) -> R
+where
+	F: FnOnce() -> R,
+{
+	match Handle::try_current().map(|h| h.runtime_flavor()) {
+		Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+		_ => f(),
+	}
+}
+
+/// Sleeps for the given duration, but wakes up early if the stop flag is set
+/// in the meantime. Returns false if the task should stop.
+pub async fn sleep_unless_stopped(stop_flag: &AtomicBool, duration: Duration) -> bool {
+	let stopped = async {
+		while !stop_flag.load(Ordering::Relaxed) {
+			sleep(STOP_FLAG_POLL_INTERVAL).await;
+		}
+	};
+	select! {
+		() = stopped => {}
+		() = sleep(duration) => {}
+	}
+	!stop_flag.load(Ordering::Relaxed)
+}
+
 /// Read all the content of a file into a string
 pub async fn read_text_file(path: impl AsRef<Path>) -> io::Result<String> {
 	let mut file = File::open(path).await?;
@@ -10,3 +55,29 @@ pub async fn read_text_file(path: impl AsRef<Path>) -> io::Result<String> {
 	file.read_to_string(&mut content).await?;
 	Ok(content)
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test(flavor = "current_thread")]
+	async fn test_block_in_place_current_thread() { assert_eq!(block_in_place(|| 1 + 1), 2); }
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_block_in_place_multi_thread() { assert_eq!(block_in_place(|| 1 + 1), 2); }
+
+	#[tokio::test]
+	async fn test_sleep_unless_stopped() {
+		let stop_flag = AtomicBool::new(false);
+		assert!(sleep_unless_stopped(&stop_flag, Duration::from_millis(10)).await);
+
+		stop_flag.store(true, Ordering::Relaxed);
+		let result = tokio::time::timeout(
+			Duration::from_secs(1),
+			sleep_unless_stopped(&stop_flag, Duration::from_secs(3600)),
+		)
+		.await;
+		assert_eq!(result, Ok(false));
+	}
+}
@@ -1,16 +1,28 @@
+#[cfg(feature = "web")]
 pub mod activity_pub;
 pub mod consolidated_feed;
+#[cfg(feature = "web")]
+pub mod identicon;
 pub mod info;
+#[cfg(feature = "web")]
 pub mod json;
+#[cfg(feature = "web")]
 pub mod server;
+#[cfg(feature = "web")]
+pub mod share_link;
+#[cfg(feature = "web")]
 pub mod webfinger;
 
 
+#[cfg(feature = "web")]
 use std::borrow::Cow;
 
+#[cfg(feature = "web")]
 use server::{AppState, ServerInfo};
+#[cfg(feature = "web")]
 use tokio::sync::Mutex;
 
+#[cfg(feature = "web")]
 use crate::{
 	api::Api,
 	config::Config,
@@ -18,6 +30,8 @@ use crate::{
 };
 
 
+/// The errors of the ActivityPub federation, which is part of the web server.
+#[cfg(feature = "web")]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("database error: {0}")]
@@ -32,6 +46,7 @@ pub enum Error {
 	UnexpectedBehavior(Cow<'static, str>, Cow<'static, str>),
 }
 
+#[cfg(feature = "web")]
 pub struct Global {
 	pub config: Config,
 	pub state: Mutex<AppState>,
@@ -39,12 +54,15 @@ pub struct Global {
 	pub api: Api,
 }
 
+#[cfg(feature = "web")]
 pub type Result<T> = trace::Result<T, Error>;
 
+#[cfg(feature = "web")]
 impl crate::db::Error {
 	fn to_web(self) -> Traced<Error> { Traced::capture(Error::Database(self)) }
 }
 
+#[cfg(feature = "web")]
 impl Traced<crate::db::Error> {
 	#[cfg(debug_assertions)]
 	fn to_web(self) -> Traced<Error> {
@@ -12,12 +12,11 @@ use std::{
 	time::Duration,
 };
 
-use axum::http::HeaderMap;
 use base64::prelude::*;
 use chrono::{SecondsFormat, TimeZone, Utc};
 use lazy_static::lazy_static;
 use log::*;
-use reqwest::Url;
+use reqwest::{header::HeaderMap, Url};
 use rsa::{
 	pkcs1v15::{SigningKey, VerifyingKey},
 	pkcs8::{DecodePrivateKey, DecodePublicKey},
@@ -31,25 +30,28 @@ use sea_orm::{
 	NotSet, Order, QueryOrder, QuerySelect, QueryTrait, Set, Statement,
 };
 use serde::{Serialize, Serializer};
-use tokio::{spawn, time::sleep};
+use tokio::time::sleep;
 use zeroize::Zeroizing;
 
 use super::{
 	consolidated_feed::ConsolidatedObjectType,
 	info::{
-		human_readable_duration, FileInfo, ObjectInfo, ObjectPayloadInfo, PossiblyKnownFileHeader,
-		PostMessageInfo, PostObjectInfo,
+		human_readable_duration, FileInfo, ObjectInfo, ObjectPayloadInfo, PollObjectInfo,
+		PossiblyKnownFileHeader, PostMessageInfo, PostObjectInfo,
 	},
 	json::{expect_object, expect_string, expect_url},
-	server::translate_special_mime_types2,
-	webfinger, Global,
+	webfinger,
 };
+#[cfg(feature = "web")]
+use super::Global;
 use crate::{
 	common::{current_timestamp, IdType},
 	config::Config,
 	core::{ActorAddress, Address, FileHeader, OBJECT_TYPE_PROFILE},
 	db::{self, Database, PersistenceHandle},
 	entity::{self, *},
+	media::MediaMetadata,
+	task::TaskRegistry,
 	web::{self, Error, Result},
 };
 
@@ -81,6 +83,8 @@ pub struct ActivityNoteObject {
 	pub content: String,
 	pub mediaType: String,
 	pub published: DateTime,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub updated: Option<DateTime>,
 	pub attachment: Vec<AttachmentObject>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub inReplyTo: Option<String>,
@@ -108,6 +112,43 @@ pub struct ActivityProfileObjectDescribes {
 pub struct ActivityProfileObjectType;
 pub struct ActivityProfileObjectDescribesType;
 
+/// A poll, of which the options are either in `oneOf` or `anyOf`, depending on
+/// whether more than one of them can be chosen.
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct ActivityQuestionObject {
+	id: String,
+	r#type: &'static str,
+	content: String,
+	published: DateTime,
+	endTime: DateTime,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	oneOf: Option<Vec<ActivityQuestionOption>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	anyOf: Option<Vec<ActivityQuestionOption>>,
+}
+
+#[derive(Serialize)]
+pub struct ActivityQuestionOption {
+	r#type: &'static str,
+	name: String,
+	replies: ActivityQuestionReplies,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct ActivityQuestionReplies {
+	r#type: &'static str,
+	totalItems: u64,
+}
+
+/// What is left of a note after it has been deleted.
+#[derive(Serialize)]
+pub struct ActivityTombstoneObject {
+	id: String,
+	r#type: &'static str,
+}
+
 #[derive(PartialEq)]
 pub enum ActivitySendState {
 	Send,
@@ -166,6 +207,9 @@ pub struct Activity<'a> {
 pub enum ActivityType {
 	Announce,
 	Create,
+	Delete,
+	Like,
+	Update,
 }
 
 #[allow(non_snake_case)]
@@ -175,6 +219,15 @@ pub struct AttachmentObject {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub mediaType: Option<String>,
 	pub url: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub width: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub height: Option<u32>,
+	/// The play time, as an `xsd:duration`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub blurhash: Option<String>,
 }
 
 pub enum AttachmentObjectType {
@@ -349,64 +402,75 @@ async fn collect_activities(db: &Database, json: &serde_json::Value, url: &str)
 	Ok(Some((activity, reply_urls)))
 }*/
 
+/// Composes the note of a post, along with the URLs of the actors it replies
+/// to. Returns `None` if the message of the post isn't known yet.
+async fn compose_note(
+	db: &Database, url_base: &str, object: &ObjectInfo, post: &PostObjectInfo,
+) -> Result<Option<(serde_json::Value, Vec<Url>)>> {
+	let message = match &post.message {
+		Some(m) => m,
+		None => return Ok(None),
+	};
+	let mut reply_webfingers = Vec::new();
+	let ap_object_id = format!("{}/object/{}/activity-pub", &object.actor_url, &object.id);
+
+	let note_json = if message.mime_type == "application/activity+json" {
+		let mut json = serde_json::Value::from_str(&message.body)
+			.map_err(|e| Error::Deserialization(e, "parsing activity object".into()))?;
+		let json_object = json.as_object_mut().unwrap();
+		// Add the id property, because it couldn't have been added upon creation
+		json_object.insert("id".to_string(), serde_json::Value::String(ap_object_id));
+
+		// Scan the content for mentioned webfingers
+		let media_type = if let Some(v) = json_object.get("mediaType") {
+			expect_string(v, &|| "parsing the mediaType property".into())?.as_str()
+		} else {
+			"text/html"
+		};
+		if media_type.starts_with("text/") {
+			if let Some(content_val) = json_object.get("content") {
+				let content = expect_string(content_val, &|| "parsing content property".into())?;
+				reply_webfingers = webfinger::find_from_content(&content);
+			}
+		}
+
+		json
+	} else {
+		// Parse the text body to find any metions of webfingers
+		if message.mime_type.starts_with("text/") {
+			reply_webfingers = webfinger::find_from_content(&message.body);
+		}
+
+		let mut note = ActivityNoteObject::new2(
+			&object.actor_url,
+			&object.id,
+			object.created,
+			message.mime_type.clone(),
+			message.body.clone(),
+			&post.attachments,
+		);
+		note.updated = post.edited.map(|e| DateTime(e));
+		if let Some(irt) = &post.in_reply_to {
+			note.inReplyTo = Some(format!(
+				"{}/actor/{}/object/{}/activity-pub",
+				url_base, &irt.actor_address, &irt.id
+			));
+		}
+		serde_json::to_value(note).unwrap()
+	};
+
+	let reply_urls = actor::resolve_urls_from_webfingers(db, &reply_webfingers).await;
+	Ok(Some((note_json, reply_urls)))
+}
+
 pub async fn compose_activity_from_object_info(
 	db: &Database, url_base: &str, object: &ObjectInfo,
 ) -> Result<Option<(serde_json::Value, Vec<Url>)>> {
 	debug_assert_eq!(object.consolidated_type, ConsolidatedObjectType::Stonenet);
 
 	let activity_opt = match &object.payload {
-		ObjectPayloadInfo::Post(post) => {
-			if let Some(message) = &post.message {
-				let mut reply_webfingers = Vec::new();
-				let ap_object_id =
-					format!("{}/object/{}/activity-pub", &object.actor_url, &object.id);
-
-				let activity_object_json = if message.mime_type == "application/activity+json" {
-					let mut json = serde_json::Value::from_str(&message.body)
-						.map_err(|e| Error::Deserialization(e, "parsing activity object".into()))?;
-					let json_object = json.as_object_mut().unwrap();
-					// Add the id property, because it couldn't have been added upon creation
-					json_object.insert("id".to_string(), serde_json::Value::String(ap_object_id));
-
-					// Scan the content for mentioned webfingers
-					let media_type = if let Some(v) = json_object.get("mediaType") {
-						expect_string(v, &|| "parsing the mediaType property".into())?.as_str()
-					} else {
-						"text/html"
-					};
-					if media_type.starts_with("text/") {
-						if let Some(content_val) = json_object.get("content") {
-							let content =
-								expect_string(content_val, &|| "parsing content property".into())?;
-							reply_webfingers = webfinger::find_from_content(&content);
-						}
-					}
-
-					json
-				} else {
-					// Parse the text body to find any metions of webfingers
-					if message.mime_type.starts_with("text/") {
-						reply_webfingers = webfinger::find_from_content(&message.body);
-					}
-
-					let mut note = ActivityNoteObject::new2(
-						&object.actor_url,
-						&object.id,
-						object.created,
-						message.mime_type.clone(),
-						message.body.clone(),
-						&post.attachments,
-					);
-					if let Some(irt) = &post.in_reply_to {
-						note.inReplyTo = Some(format!(
-							"{}/actor/{}/object/{}/activity-pub",
-							url_base, &irt.actor_address, &irt.id
-						));
-					}
-					serde_json::to_value(note).unwrap()
-				};
-
-				let reply_urls = actor::resolve_urls_from_webfingers(db, &reply_webfingers).await;
+		ObjectPayloadInfo::Post(post) =>
+			if let Some((note_json, reply_urls)) = compose_note(db, url_base, object, post).await? {
 				let reply_url_strings: Vec<String> =
 					reply_urls.iter().map(|i| i.to_string()).collect();
 				let cc_list: Vec<&str> = reply_url_strings.iter().map(|i| i.as_str()).collect();
@@ -416,13 +480,12 @@ pub async fn compose_activity_from_object_info(
 					&object.id,
 					&cc_list,
 					object.created,
-					activity_object_json,
+					note_json,
 				);
 				Some((serde_json::to_value(activity).unwrap(), reply_urls))
 			} else {
 				None
-			}
-		}
+			},
 		ObjectPayloadInfo::Share(share) =>
 			if let Some(post) = &share.original_post {
 				let target_object_id = format!(
@@ -460,6 +523,83 @@ pub async fn compose_activity_from_object_info(
 			);
 			Some((serde_json::to_value(activity).unwrap(), Vec::new()))
 		}
+		// The fediverse has no notion of our keys
+		ObjectPayloadInfo::KeyRotation(_) => None,
+		// The note of the post is replaced by its latest version
+		ObjectPayloadInfo::Edit(edit) => {
+			let original =
+				web::info::find_object_info(db, url_base, &edit.actor_address, &edit.object_hash)
+					.await
+					.map_err(|e| e.to_web())?;
+			let note = match &original {
+				Some(o) => match &o.payload {
+					ObjectPayloadInfo::Post(post) => compose_note(db, url_base, o, post).await?,
+					_ => None,
+				},
+				None => None,
+			};
+			if let Some((note_json, reply_urls)) = note {
+				let activity = Activity::new(
+					ActivityType::Update,
+					&object.actor_url,
+					&object.id,
+					&[],
+					object.created,
+					note_json,
+				);
+				Some((serde_json::to_value(activity).unwrap(), reply_urls))
+			} else {
+				None
+			}
+		}
+		// Only a tombstone is left of the note
+		ObjectPayloadInfo::Delete(delete) => {
+			let tombstone = ActivityTombstoneObject {
+				id: format!("{}/object/{}/activity-pub", &object.actor_url, &delete.object_hash),
+				r#type: "Tombstone",
+			};
+			let activity = Activity::new(
+				ActivityType::Delete,
+				&object.actor_url,
+				&object.id,
+				&[],
+				object.created,
+				serde_json::to_value(tombstone).unwrap(),
+			);
+			Some((serde_json::to_value(activity).unwrap(), Vec::new()))
+		}
+		ObjectPayloadInfo::Reaction(reaction) => {
+			let target_object_id = format!(
+				"{}/actor/{}/object/{}/activity-pub",
+				url_base, &reaction.actor_address, &reaction.object_hash
+			);
+			let activity = Activity::new(
+				ActivityType::Like,
+				&object.actor_url,
+				&object.id,
+				&[],
+				object.created,
+				serde_json::Value::String(target_object_id),
+			);
+			Some((serde_json::to_value(activity).unwrap(), Vec::new()))
+		}
+		ObjectPayloadInfo::Poll(poll) => {
+			let question =
+				ActivityQuestionObject::new(&object.actor_url, &object.id, object.created, poll);
+			let activity = Activity::new(
+				ActivityType::Create,
+				&object.actor_url,
+				&object.id,
+				&[],
+				object.created,
+				serde_json::to_value(question).unwrap(),
+			);
+			Some((serde_json::to_value(activity).unwrap(), Vec::new()))
+		}
+		// Votes of the fediverse are replies to the question, which can't be
+		// composed from our votes without knowing their poll
+		ObjectPayloadInfo::Vote(_) => None,
+		ObjectPayloadInfo::FollowerList(_) => None,
 	};
 	Ok(activity_opt)
 }
@@ -590,6 +730,9 @@ pub async fn load_object_info(db: &Database, id: i64) -> Result<Option<ObjectInf
 				sequence: 0,
 				message: Some(message),
 				attachments,
+				edited: None,
+				deleted: false,
+				reactions: Vec::new(),
 			}),
 		})
 	} else {
@@ -618,7 +761,9 @@ async fn loop_box_polls(
 	}
 }
 
-pub fn maintain_outbox_polls(stop_flag: Arc<AtomicBool>, db: Database, config: &Config) {
+pub fn maintain_outbox_polls(
+	stop_flag: Arc<AtomicBool>, tasks: &TaskRegistry, db: Database, config: &Config,
+) {
 	let inbox_info = if let Some(inbox_server) = &config.activity_pub_inbox_server {
 		if let Some(address_string) = &config.activity_pub_inbox_actor {
 			match Address::from_str(address_string)
@@ -645,7 +790,10 @@ pub fn maintain_outbox_polls(stop_flag: Arc<AtomicBool>, db: Database, config: &
 		None
 	};
 
-	spawn(loop_box_polls(stop_flag, db, inbox_info));
+	tasks.spawn(
+		"ActivityPub outbox poller",
+		loop_box_polls(stop_flag, db, inbox_info),
+	);
 }
 
 pub fn parse_account_name(resource: &str) -> Option<&str> {
@@ -704,6 +852,7 @@ pub fn parse_post_object(raw_json: &str) -> Result<(PostMessageInfo, Vec<FileInf
 		PostMessageInfo {
 			mime_type: "text/html".to_string(),
 			body: content.clone(),
+			html: None,
 		},
 		attachment_infos,
 	))
@@ -734,10 +883,28 @@ fn parse_post_object_attachment(json: &serde_json::Value) -> Result<FileInfo> {
 		))?;
 	};
 
-	Ok(FileInfo {
-		url: url.into(),
-		mime_type,
-	})
+	// The metadata is optional, so it is simply left out if it doesn't make sense
+	let dimension = |name| {
+		json.get(name)
+			.and_then(|v| v.as_u64())
+			.and_then(|v| u32::try_from(v).ok())
+	};
+	let metadata = MediaMetadata {
+		width: dimension("width"),
+		height: dimension("height"),
+		duration: None,
+		blurhash: json
+			.get("blurhash")
+			.and_then(|v| v.as_str())
+			.map(|s| s.to_string()),
+	};
+	let metadata = if metadata == MediaMetadata::default() {
+		None
+	} else {
+		Some(metadata)
+	};
+
+	Ok(FileInfo::new(url.into(), mime_type, metadata))
 }
 
 pub async fn poll_box(db: &Database, box_url: &str) -> Result<()> {
@@ -829,6 +996,7 @@ async fn poll_outboxes(stop_flag: Arc<AtomicBool>, db: &Database) -> db::Result<
 	Ok(())
 }
 
+#[cfg(feature = "web")]
 /// Puts the activity in the send queue for each following server, that will be
 /// processed somewhere in the future
 async fn populate_send_queue_from_new_object(
@@ -878,6 +1046,7 @@ async fn populate_send_queue_from_new_object(
 	Ok(())
 }
 
+#[cfg(feature = "web")]
 pub async fn populate_send_queue_from_new_objects(g: &Global, limit: u64) -> Result<()> {
 	let objects = object::Entity::find()
 		.filter(object::Column::PublishedOnFediverse.eq(false))
@@ -916,6 +1085,7 @@ pub async fn populate_send_queue_from_new_objects(g: &Global, limit: u64) -> Res
 	Ok(())
 }
 
+#[cfg(feature = "web")]
 /// Attempts to send the given send-queue activity.
 pub async fn process_next_send_queue_item(
 	g: &Global, record: activity_pub_send_queue::Model, private_key: Arc<Zeroizing<String>>,
@@ -1018,6 +1188,7 @@ pub async fn process_next_send_queue_item(
 	Ok(())
 }
 
+#[cfg(feature = "web")]
 /// Queues the given ActivitySteams object to be sent to the recipient at
 /// somewhere in the future.
 /// If `recipient_path` is `None`, the activity will be send to the server's
@@ -1066,6 +1237,7 @@ pub async fn queue_activity(
 	}
 }
 
+#[cfg(feature = "web")]
 async fn send_activity(
 	g: &Global, actor_address: &ActorAddress, recipient_server: &str, recipient_path: Option<&str>,
 	activity: String, private_key: &str,
@@ -1272,6 +1444,28 @@ pub async fn store_object(
 	Ok(object_id)
 }
 
+/// Attemps to translate a post message into one of a mime type that can be
+/// understood by the frontend.
+pub fn translate_special_mime_types2(mime_type: &str, body: &str) -> Option<PostMessageInfo> {
+	let result = match mime_type {
+		"application/activity+json" => translate_activitystreams_object(body),
+		"application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"" =>
+			translate_activitystreams_object(body),
+		_ => return None,
+	};
+
+	Some(match result {
+		Ok(r) => r,
+		Err(e) => PostMessageInfo {
+			// Abuse the mime_type field to signify an error has occured while trying to translate
+			// the message
+			mime_type: "error".to_string(),
+			body: e,
+			html: None,
+		},
+	})
+}
+
 pub fn translate_activitystreams_object(content: &str) -> StdResult<PostMessageInfo, String> {
 	let json = match serde_json::Value::from_str(content) {
 		Ok(r) => r,
@@ -1303,6 +1497,7 @@ pub fn translate_activitystreams_object(content: &str) -> StdResult<PostMessageI
 				return Ok(PostMessageInfo {
 					mime_type: content_mime_type.to_string(),
 					body: s.clone(),
+					html: None,
 				});
 			}
 			_ => return Err("Property content is not a string".into()),
@@ -1333,16 +1528,39 @@ impl Serialize for ActivityNoteObjectType {
 	}
 }
 
+impl AttachmentObject {
+	fn new(
+		r#type: AttachmentObjectType, mime_type: Option<String>, url: String,
+		metadata: Option<&MediaMetadata>,
+	) -> Self {
+		Self {
+			r#type,
+			mediaType: mime_type,
+			url,
+			width: metadata.and_then(|m| m.width),
+			height: metadata.and_then(|m| m.height),
+			duration: metadata
+				.and_then(|m| m.duration)
+				.map(|d| format!("PT{}.{:03}S", d / 1000, d % 1000)),
+			blurhash: metadata.and_then(|m| m.blurhash.clone()),
+		}
+	}
+}
+
 impl ActivityNoteObject {
 	fn attachments(
-		url_base: &str, actor_address: &ActorAddress, attachments: &[(&str, IdType)],
+		url_base: &str, actor_address: &ActorAddress,
+		attachments: &[(&str, IdType, Option<MediaMetadata>)],
 	) -> Vec<AttachmentObject> {
 		attachments
 			.iter()
-			.map(|(mime_type, hash)| AttachmentObject {
-				mediaType: Some(mime_type.to_string()),
-				r#type: AttachmentObjectType::from_mime_type(mime_type),
-				url: web::info::file_url(url_base, actor_address, &hash),
+			.map(|(mime_type, hash, metadata)| {
+				AttachmentObject::new(
+					AttachmentObjectType::from_mime_type(mime_type),
+					Some(mime_type.to_string()),
+					web::info::file_url(url_base, actor_address, &hash),
+					metadata.as_ref(),
+				)
 			})
 			.collect()
 	}
@@ -1350,15 +1568,16 @@ impl ActivityNoteObject {
 	fn attachments2(attachments: &[FileInfo]) -> Vec<AttachmentObject> {
 		let mut results = Vec::with_capacity(attachments.len());
 		for attachment in attachments {
-			let attachment_object = AttachmentObject {
-				r#type: attachment
+			let attachment_object = AttachmentObject::new(
+				attachment
 					.mime_type
 					.as_ref()
 					.map(|mt| AttachmentObjectType::from_mime_type(mt))
 					.unwrap_or(AttachmentObjectType::Document),
-				mediaType: attachment.mime_type.clone(),
-				url: attachment.url.clone(),
-			}; // TODO: Remove the above two clones
+				attachment.mime_type.clone(),
+				attachment.url.clone(),
+				attachment.metadata.as_ref(),
+			); // TODO: Remove the above two clones
 			results.push(attachment_object);
 		}
 		results
@@ -1366,7 +1585,7 @@ impl ActivityNoteObject {
 
 	pub fn create_markdown_note(
 		url_base: &str, actor_address: &ActorAddress, content: String,
-		attachments: &[(&str, IdType)],
+		attachments: &[(&str, IdType, Option<MediaMetadata>)],
 	) -> Self {
 		Self {
 			id: None,
@@ -1383,7 +1602,8 @@ impl ActivityNoteObject {
 	/// object hash, to construct the id property from.
 	pub fn new(
 		url_base: &str, actor_address: &ActorAddress, object_hash: &str, created: u64,
-		mime_type: String, content: String, attachments: &[(&str, IdType)],
+		mime_type: String, content: String,
+		attachments: &[(&str, IdType, Option<MediaMetadata>)],
 	) -> Self {
 		Self {
 			id: Some(format!(
@@ -1394,6 +1614,7 @@ impl ActivityNoteObject {
 			content,
 			mediaType: mime_type,
 			published: DateTime(created),
+			updated: None,
 			attachment: Self::attachments(url_base, actor_address, attachments),
 			inReplyTo: None,
 		}
@@ -1411,12 +1632,44 @@ impl ActivityNoteObject {
 			content,
 			mediaType: mime_type,
 			published: DateTime(created),
+			updated: None,
 			attachment: Self::attachments2(attachments),
 			inReplyTo: None,
 		}
 	}
 }
 
+impl ActivityQuestionObject {
+	fn new(actor_url: &str, object_hash: &str, created: u64, poll: &PollObjectInfo) -> Self {
+		let options: Vec<_> = poll
+			.options
+			.iter()
+			.map(|o| ActivityQuestionOption {
+				r#type: "Note",
+				name: o.text.clone(),
+				replies: ActivityQuestionReplies {
+					r#type: "Collection",
+					totalItems: o.votes,
+				},
+			})
+			.collect();
+		let (one_of, any_of) = if poll.multiple_choice {
+			(None, Some(options))
+		} else {
+			(Some(options), None)
+		};
+		Self {
+			id: format!("{}/object/{}/activity-pub", actor_url, object_hash),
+			r#type: "Question",
+			content: poll.question.clone(),
+			published: DateTime(created),
+			endTime: DateTime(poll.closes),
+			oneOf: one_of,
+			anyOf: any_of,
+		}
+	}
+}
+
 impl ActivityProfileObject {
 	fn new(
 		actor_url: &str, object_hash: &str, created: u64, name: String, summary: Option<String>,
@@ -1511,6 +1764,9 @@ impl Serialize for ActivityType {
 		match self {
 			Self::Announce => serializer.serialize_str("Announce"),
 			Self::Create => serializer.serialize_str("Create"),
+			Self::Delete => serializer.serialize_str("Delete"),
+			Self::Like => serializer.serialize_str("Like"),
+			Self::Update => serializer.serialize_str("Update"),
 		}
 	}
 }
@@ -1,14 +1,25 @@
 use std::collections::HashMap;
 
+#[cfg(feature = "web")]
 use log::warn;
+#[cfg(feature = "web")]
+use sea_orm::Condition;
 use sea_orm::{prelude::*, QueryOrder, QuerySelect, QueryTrait};
 use serde::Serialize;
 
-use super::Error;
+#[cfg(feature = "web")]
+use super::{
+	info::{FeedCursor, FeedPage, ObjectInfo},
+	Error, Result,
+};
 use crate::{
 	db::{self, Database, PersistenceHandle},
 	entity::*,
-	web::{self, info::ObjectInfo, Result},
+};
+#[cfg(feature = "web")]
+use crate::{
+	db::{block_list, local_user},
+	web,
 };
 
 
@@ -19,10 +30,52 @@ pub enum ConsolidatedObjectType {
 }
 
 
+#[cfg(feature = "web")]
 pub async fn load_consolidated_feed(
 	db: &Database, url_base: &str, count: u64, offset: u64,
 ) -> Result<Vec<ObjectInfo>> {
-	let consolidated = consolidated_object::Entity::find()
+	Ok(query_consolidated_feed(db, url_base, count, offset, None)
+		.await?
+		.objects)
+}
+
+#[cfg(feature = "web")]
+/// Loads the objects of the consolidated feed that come after the cursor, or
+/// the latest ones if no cursor is given.
+pub async fn load_consolidated_feed_page(
+	db: &Database, url_base: &str, count: u64, after: Option<FeedCursor>,
+) -> Result<FeedPage> {
+	query_consolidated_feed(db, url_base, count, 0, after).await
+}
+
+#[cfg(feature = "web")]
+async fn query_consolidated_feed(
+	db: &Database, url_base: &str, count: u64, offset: u64, after: Option<FeedCursor>,
+) -> Result<FeedPage> {
+	// Every local user only sees the actors that it follows
+	let mut query = consolidated_object::Entity::find().filter(local_user::feed_condition());
+	// The objects of the actors that the active identity has muted are left out
+	if let Some(actor_id) = db
+		.load_active_actor_id()
+		.await
+		.map_err(|e| e.to_web())?
+	{
+		query = query.filter(block_list::unmuted_condition(actor_id));
+	}
+	// The latest batches come first, but the objects within a batch are in the
+	// order they were added
+	if let Some(cursor) = after {
+		query = query.filter(
+			Condition::any()
+				.add(consolidated_object::Column::Batch.lt(cursor.position))
+				.add(
+					consolidated_object::Column::Batch
+						.eq(cursor.position)
+						.and(consolidated_object::Column::Id.gt(cursor.id)),
+				),
+		);
+	}
+	let consolidated = query
 		.order_by_desc(consolidated_object::Column::Batch)
 		.order_by_asc(consolidated_object::Column::Id)
 		.limit(count)
@@ -31,6 +84,13 @@ pub async fn load_consolidated_feed(
 		.await
 		.map_err(|e| db::Error::from(e).to_web())?;
 
+	let next = match consolidated.last() {
+		Some(last) if consolidated.len() as u64 == count => Some(FeedCursor {
+			position: last.batch,
+			id: last.id,
+		}),
+		_ => None,
+	};
 	let mut objects = Vec::with_capacity(consolidated.len());
 	for consolidated_object in consolidated {
 		let object_opt = if consolidated_object.r#type == 0 {
@@ -56,7 +116,7 @@ pub async fn load_consolidated_feed(
 			objects.push(o);
 		}
 	}
-	Ok(objects)
+	Ok(FeedPage { objects, next })
 }
 
 pub async fn load_next_unconsolidated_activity_pub_objects(
@@ -0,0 +1,144 @@
+//! Generates identicons: simple avatars that are derived from an actor's
+//! address, and are used for actors that haven't set an avatar themselves.
+//!
+//! An identicon is a grid of 5 by 5 cells that is mirrored horizontally, in a
+//! color that is taken from the address as well. They are encoded as PNG
+//! images, because not all ActivityPub servers accept SVG images as an icon.
+//! Once generated, they are kept as derived files next to the database.
+
+use std::{
+	io::{self, Cursor},
+	path::PathBuf,
+};
+
+use image::{ImageOutputFormat, Rgb, RgbImage};
+use log::*;
+use tokio::fs;
+
+use crate::{core::ActorAddress, db::Database};
+
+
+const GRID_SIZE: usize = 5;
+const CELL_SIZE: usize = 40;
+const PADDING: usize = 20;
+/// The width and height of an identicon, in pixels.
+pub const IMAGE_SIZE: usize = GRID_SIZE * CELL_SIZE + 2 * PADDING;
+const BACKGROUND_COLOR: [u8; 3] = [240, 240, 240];
+
+
+/// Generates the identicon of the given actor as a PNG image.
+pub fn generate(address: &ActorAddress) -> Vec<u8> {
+	let id = address.as_id();
+	let bytes = id.as_bytes();
+
+	// Which cells are filled in. Only the left three columns are derived from
+	// the address, the other two mirror them.
+	let mut cells = [[false; GRID_SIZE]; GRID_SIZE];
+	for row in 0..GRID_SIZE {
+		for column in 0..((GRID_SIZE + 1) / 2) {
+			let filled = bytes[row * 3 + column] & 1 == 1;
+			cells[row][column] = filled;
+			cells[row][GRID_SIZE - 1 - column] = filled;
+		}
+	}
+	let color = color_from_hue(u16::from_le_bytes([bytes[30], bytes[31]]) % 360);
+
+	let image = RgbImage::from_fn(IMAGE_SIZE as u32, IMAGE_SIZE as u32, |x, y| {
+		if is_filled(&cells, x as usize, y as usize) {
+			Rgb(color)
+		} else {
+			Rgb(BACKGROUND_COLOR)
+		}
+	});
+	let mut png = Vec::new();
+	image
+		.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+		.expect("unable to encode identicon");
+	png
+}
+
+/// The directory in which the generated identicons are kept.
+fn cache_dir(db: &Database) -> PathBuf {
+	let mut dir = db.derived_files_dir();
+	dir.push("identicons");
+	dir
+}
+
+/// Loads the identicon of the given actor, generating it if it hasn't been
+/// generated before.
+pub async fn load(db: &Database, address: &ActorAddress) -> io::Result<Vec<u8>> {
+	let dir = cache_dir(db);
+	let path = dir.join(format!("{}.png", address));
+	match fs::read(&path).await {
+		Ok(data) => return Ok(data),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => return Err(e),
+	}
+
+	let data = generate(address);
+	// Not being able to cache the identicon isn't a problem, it just gets
+	// generated again next time.
+	let result = match fs::create_dir_all(&dir).await {
+		Ok(()) => fs::write(&path, &data).await,
+		Err(e) => Err(e),
+	};
+	if let Err(e) = result {
+		warn!("Unable to cache identicon at {}: {}", path.display(), e);
+	}
+	Ok(data)
+}
+
+fn color_from_hue(hue: u16) -> [u8; 3] {
+	// The saturation and lightness are fixed at 60% and 50%, which gives colors
+	// that stand out against the light background.
+	let chroma = 0.6;
+	let h = hue as f32 / 60.0;
+	let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+	let (r, g, b) = match h as u8 {
+		0 => (chroma, x, 0.0),
+		1 => (x, chroma, 0.0),
+		2 => (0.0, chroma, x),
+		3 => (0.0, x, chroma),
+		4 => (x, 0.0, chroma),
+		_ => (chroma, 0.0, x),
+	};
+	let m = 0.5 - chroma / 2.0;
+	[r, g, b].map(|c| ((c + m) * 255.0).round() as u8)
+}
+
+fn is_filled(cells: &[[bool; GRID_SIZE]; GRID_SIZE], x: usize, y: usize) -> bool {
+	let grid_end = PADDING + GRID_SIZE * CELL_SIZE;
+	if x < PADDING || y < PADDING || x >= grid_end || y >= grid_end {
+		return false;
+	}
+	cells[(y - PADDING) / CELL_SIZE][(x - PADDING) / CELL_SIZE]
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_identicon() {
+		let mut rng = test::initialize_rng();
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let other_address = ActorAddress::V1(IdType::random(&mut rng));
+
+		let png = generate(&address);
+		assert_eq!(png, generate(&address));
+		assert_ne!(png, generate(&other_address));
+
+		// The image decodes, and its grid is mirrored within the padding
+		let image = image::load_from_memory(&png).unwrap().to_rgb8();
+		assert_eq!(image.dimensions(), (IMAGE_SIZE as u32, IMAGE_SIZE as u32));
+		assert_eq!(image.get_pixel(0, 0), &Rgb(BACKGROUND_COLOR));
+		for y in 0..IMAGE_SIZE as u32 {
+			for x in 0..IMAGE_SIZE as u32 {
+				let mirrored = IMAGE_SIZE as u32 - 1 - x;
+				assert_eq!(image.get_pixel(x, y), image.get_pixel(mirrored, y));
+			}
+		}
+	}
+}
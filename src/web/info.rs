@@ -1,28 +1,47 @@
+use std::{fmt, io::Cursor, str::FromStr};
+
 use ::serde::Serialize;
+use base64::prelude::*;
 use chrono::TimeDelta;
+use image::{ImageOutputFormat, RgbaImage};
 use sea_orm::{
 	prelude::*,
 	sea_query::{Alias, IntoCondition, Query},
 	Condition, DatabaseBackend, JoinType, Order, QueryOrder, QuerySelect, QueryTrait, Statement,
 };
+#[cfg(feature = "web")]
+use sea_orm::sea_query::SelectStatement;
 
 use super::consolidated_feed::ConsolidatedObjectType;
 use crate::{
 	common::{current_timestamp, IdType},
 	compression::decompress,
 	core::{
-		ActorAddress, CompressionType, FileHeader, OBJECT_TYPE_POST, OBJECT_TYPE_PROFILE,
-		OBJECT_TYPE_SHARE,
+		ActorAddress, CompressionType, FileHeader, OBJECT_TYPE_DELETE, OBJECT_TYPE_EDIT,
+		OBJECT_TYPE_FOLLOWER_LIST, OBJECT_TYPE_KEY_ROTATION, OBJECT_TYPE_POLL, OBJECT_TYPE_POST,
+		OBJECT_TYPE_PROFILE, OBJECT_TYPE_REACTION, OBJECT_TYPE_SHARE, OBJECT_TYPE_VOTE,
 	},
 	db::{Database, Error, PersistenceHandle, Result},
 	entity::*,
+	media::{self, MediaMetadata},
 };
 
 
+/// The size of the longest side of the placeholders of images, in pixels. They
+/// are stretched out anyway, so they don't need to be any larger.
+const PLACEHOLDER_SIZE: u32 = 32;
+
+
 #[derive(Clone, Debug, Serialize)]
 pub struct FileInfo {
 	pub url: String,
 	pub mime_type: Option<String>,
+	pub metadata: Option<MediaMetadata>,
+	/// A blurry version of the image to show while it is being loaded, as a
+	/// data URL.
+	pub placeholder_url: Option<String>,
+	/// A smaller version of the image to show in the feeds.
+	pub thumbnail_url: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -45,6 +64,89 @@ pub enum ObjectPayloadInfo {
 	Profile(ProfileObjectInfo),
 	Post(PostObjectInfo),
 	Share(ShareObjectInfo),
+	KeyRotation(KeyRotationObjectInfo),
+	Edit(EditObjectInfo),
+	Delete(DeleteObjectInfo),
+	Reaction(ReactionObjectInfo),
+	Poll(PollObjectInfo),
+	Vote(VoteObjectInfo),
+	FollowerList(FollowerListObjectInfo),
+}
+
+#[derive(Debug, Serialize)]
+pub struct PollObjectInfo {
+	pub question: String,
+	pub options: Vec<PollOptionInfo>,
+	pub multiple_choice: bool,
+	pub closes: u64,
+	pub closed: bool,
+	/// How long the poll is still open for, or an empty string once it closed.
+	pub time_left: String,
+	/// The number of actors that have voted, as far as we know.
+	pub voters: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PollOptionInfo {
+	pub index: usize,
+	pub text: String,
+	pub votes: u64,
+	/// The share of the voters that has chosen the option, from 0 to 100.
+	pub percentage: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FollowerListObjectInfo {
+	/// The actors that have been approved as followers, with their URLs.
+	pub followers: Vec<ApprovedFollowerInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApprovedFollowerInfo {
+	pub address: String,
+	pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoteObjectInfo {
+	/// The hash of the poll that has been voted in.
+	pub object_hash: IdType,
+	pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteObjectInfo {
+	#[serde(skip)]
+	pub actor_address: ActorAddress,
+	/// The hash of the post that has been deleted.
+	pub object_hash: IdType,
+	pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EditObjectInfo {
+	#[serde(skip)]
+	pub actor_address: ActorAddress,
+	/// The hash of the post that has been edited.
+	pub object_hash: IdType,
+	pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionObjectInfo {
+	#[serde(skip)]
+	pub actor_address: ActorAddress,
+	/// The hash of the post that has been reacted to.
+	pub object_hash: IdType,
+	pub emoji: String,
+	pub url: String,
+}
+
+/// How often a post has been reacted to with an emoji.
+#[derive(Debug, Serialize)]
+pub struct ReactionInfo {
+	pub emoji: String,
+	pub count: u64,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -53,24 +155,51 @@ pub enum PossiblyKnownFileHeader {
 	Known(FileHeader),
 }
 
+#[derive(Debug, Serialize)]
+pub struct KeyRotationObjectInfo {
+	/// A hash of the new public key, to compare with out of band.
+	pub fingerprint: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct PostObjectInfo {
 	pub in_reply_to: Option<TargetedPostInfo>,
 	pub sequence: u64,
 	pub message: Option<PostMessageInfo>,
 	pub attachments: Vec<FileInfo>,
+	/// When the post has last been edited, if it has been.
+	pub edited: Option<u64>,
+	/// Whether the post has been deleted, in which case only a tombstone is
+	/// left of it.
+	pub deleted: bool,
+	pub reactions: Vec<ReactionInfo>,
+}
+
+#[cfg(any(feature = "web", test))]
+/// One of the versions of a post, as it was before or after an edit.
+#[derive(Debug, Serialize)]
+pub struct PostVersionInfo {
+	pub created: u64,
+	pub created_ago: String,
+	pub message: Option<PostMessageInfo>,
+	pub attachments: Vec<FileInfo>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct PostMessageInfo {
 	pub mime_type: String,
+	/// The message as it is stored in the post.
 	pub body: String,
+	/// The message as sanitized HTML, if it can be shown as such.
+	pub html: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ProfileObjectInfo {
 	pub actor: TargetedActorInfo,
 	pub description: Option<String>,
+	/// Whether the actor only hands out its objects to its approved followers.
+	pub followers_only: bool,
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -83,10 +212,28 @@ pub struct TargetedActorInfo {
 	pub address: String,
 	pub url: String,
 	pub name: String,
+	/// The name the user has given the actor locally, if any. This is private,
+	/// so it is only filled in for the private interface.
+	pub petname: Option<String>,
 	pub avatar_url: Option<String>,
 	pub wallpaper_url: Option<String>,
 }
 
+/// Where the next page of a feed starts. It consists of the value that the
+/// feed is ordered by, and the ID of the row, to order the objects that have
+/// the same value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeedCursor {
+	pub position: i64,
+	pub id: i64,
+}
+
+pub struct FeedPage {
+	pub objects: Vec<ObjectInfo>,
+	/// Where the next page starts, if there may be one.
+	pub next: Option<FeedCursor>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TargetedPostInfo {
 	pub id: String,
@@ -98,7 +245,30 @@ pub struct TargetedPostInfo {
 }
 
 
+impl fmt::Display for FeedCursor {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}.{}", self.position, self.id)
+	}
+}
+
+impl FromStr for FeedCursor {
+	type Err = String;
+
+	fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+		let (position, id) = string
+			.split_once('.')
+			.ok_or_else(|| format!("invalid cursor: {}", string))?;
+		Ok(Self {
+			position: position
+				.parse()
+				.map_err(|_| format!("invalid cursor: {}", string))?,
+			id: id.parse().map_err(|_| format!("invalid cursor: {}", string))?,
+		})
+	}
+}
+
 impl ObjectInfo {
+	#[cfg(feature = "web")]
 	pub fn type_title(&self) -> String {
 		match &self.payload {
 			ObjectPayloadInfo::Profile(_) => "Profile update".to_string(),
@@ -121,6 +291,13 @@ impl ObjectInfo {
 
 				format!("Post shared by {}", &self.actor_name)
 			}
+			ObjectPayloadInfo::KeyRotation(_) => "Key rotation".to_string(),
+			ObjectPayloadInfo::Edit(_) => format!("Post edited by {}", &self.actor_name),
+			ObjectPayloadInfo::Delete(_) => format!("Post deleted by {}", &self.actor_name),
+			ObjectPayloadInfo::Reaction(_) => format!("Reaction by {}", &self.actor_name),
+			ObjectPayloadInfo::Poll(_) => format!("Poll by {}", &self.actor_name),
+			ObjectPayloadInfo::Vote(_) => format!("Vote by {}", &self.actor_name),
+			ObjectPayloadInfo::FollowerList(_) => format!("Followers of {}", &self.actor_name),
 		}
 	}
 }
@@ -128,9 +305,10 @@ impl ObjectInfo {
 impl ObjectPayloadInfo {
 	// Returns true if the payload contains enough information to at least show the
 	// main content.
+	#[cfg(feature = "web")]
 	pub fn has_main_content(&self) -> bool {
 		match self {
-			Self::Post(post) => post.message.is_some(),
+			Self::Post(post) => post.message.is_some() || post.deleted,
 			Self::Share(share) =>
 				if let Some(op) = &share.original_post {
 					op.message.is_some() && op.actor_name.is_some()
@@ -138,9 +316,17 @@ impl ObjectPayloadInfo {
 					false
 				},
 			Self::Profile(profile) => profile.description.is_some(),
+			Self::KeyRotation(_)
+			| Self::Edit(_)
+			| Self::Delete(_)
+			| Self::Reaction(_)
+			| Self::Poll(_)
+			| Self::Vote(_)
+			| Self::FollowerList(_) => true,
 		}
 	}
 
+	#[cfg(feature = "web")]
 	pub fn to_text(&self) -> String {
 		match self {
 			// TODO: Based on the mime type, attempt to remove any code from the text
@@ -160,6 +346,13 @@ impl ObjectPayloadInfo {
 					"[Post not synchronized yet]".to_string()
 				},
 			Self::Profile(_) => "[Profile updated]".to_string(),
+			Self::KeyRotation(_) => "[Key rotated]".to_string(),
+			Self::Edit(_) => "[Post edited]".to_string(),
+			Self::Delete(_) => "[Post deleted]".to_string(),
+			Self::Reaction(reaction) => format!("[Reacted with {}]", &reaction.emoji),
+			Self::Poll(poll) => poll.question.clone(),
+			Self::Vote(_) => "[Voted in a poll]".to_string(),
+			Self::FollowerList(_) => "[Followers approved]".to_string(),
 		}
 	}
 }
@@ -175,6 +368,7 @@ impl PossiblyKnownFileHeader {
 }
 
 /*impl PostMessageInfo {
+	#[cfg(feature = "web")]
 	pub fn new_html(html: String) -> Self {
 		Self {
 			mime_type: "text/html".to_string(),
@@ -184,14 +378,95 @@ impl PossiblyKnownFileHeader {
 }*/
 
 
+impl FileInfo {
+	pub fn new(url: String, mime_type: Option<String>, metadata: Option<MediaMetadata>) -> Self {
+		let placeholder_url = metadata.as_ref().and_then(placeholder_url);
+		Self {
+			url,
+			mime_type,
+			metadata,
+			placeholder_url,
+			thumbnail_url: None,
+		}
+	}
+
+	/// Like `new`, but for a file that is stored on Stonenet, which can have a
+	/// thumbnail.
+	pub fn new_stored(
+		url_base: &str, actor_address: &ActorAddress, hash: &IdType, mime_type: Option<String>,
+		metadata: Option<MediaMetadata>,
+	) -> Self {
+		let mut info = Self::new(file_url(url_base, actor_address, hash), mime_type, metadata);
+		let has_thumbnail = info
+			.mime_type
+			.as_deref()
+			.map(media::has_thumbnail)
+			.unwrap_or(false);
+		let is_large = match &info.metadata {
+			Some(MediaMetadata {
+				width: Some(width),
+				height: Some(height),
+				..
+			}) => *width > media::THUMBNAIL_SIZE || *height > media::THUMBNAIL_SIZE,
+			// If the image turns out to be small, it is served as its own thumbnail
+			_ => true,
+		};
+		if has_thumbnail && is_large {
+			info.thumbnail_url = Some(format!("{}/thumb", &info.url));
+		}
+		info
+	}
+}
+
+
 pub fn actor_url(url_base: &str, actor_address: &ActorAddress) -> String {
 	format!("{}/actor/{}", url_base, actor_address)
 }
 
+/// The URL of the actor's avatar, or of its identicon if it hasn't got one.
+pub fn avatar_url(
+	url_base: &str, actor_address: &ActorAddress, avatar: Option<&IdType>,
+) -> String {
+	match avatar {
+		Some(hash) => file_url(url_base, actor_address, hash),
+		None => identicon_url(url_base, actor_address),
+	}
+}
+
+/// The URL of the identicon that the web interface generates for the actor.
+pub fn identicon_url(url_base: &str, actor_address: &ActorAddress) -> String {
+	format!("{}/actor/{}/identicon", url_base, actor_address)
+}
+
 pub fn file_url(url_base: &str, actor_address: &ActorAddress, hash: &IdType) -> String {
 	format!("{}/actor/{}/file/{}", url_base, actor_address, hash)
 }
 
+/// Renders the blurhash of the media as a small PNG image, with the same
+/// aspect ratio as the media itself.
+fn placeholder_url(metadata: &MediaMetadata) -> Option<String> {
+	let blurhash = metadata.blurhash.as_ref()?;
+	let (width, height) = match (metadata.width, metadata.height) {
+		(Some(w), Some(h)) if w > 0 && h > 0 => {
+			let scale = |a: u32, b: u32| (PLACEHOLDER_SIZE as u64 * a as u64 / b as u64).max(1);
+			if w >= h {
+				(PLACEHOLDER_SIZE, scale(h, w) as u32)
+			} else {
+				(scale(w, h) as u32, PLACEHOLDER_SIZE)
+			}
+		}
+		_ => (PLACEHOLDER_SIZE, PLACEHOLDER_SIZE),
+	};
+
+	let pixels = blurhash::decode(blurhash, width, height, 1.0).ok()?;
+	let image = RgbaImage::from_raw(width, height, pixels)?;
+	let mut png = Vec::new();
+	image
+		.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+		.ok()?;
+	Some(format!("data:image/png;base64,{}", BASE64_STANDARD.encode(&png)))
+}
+
 pub async fn find_object_info(
 	db: &Database, url_base: &str, actor_address: &ActorAddress, hash: &IdType,
 ) -> Result<Option<ObjectInfo>> {
@@ -222,6 +497,90 @@ pub async fn find_object_info(
 	}
 }
 
+#[cfg(feature = "web")]
+/// Builds the info of a post that hasn't been published yet, so that it can be
+/// shown exactly like it would show up in a feed. The attachments need to have
+/// URLs that work without the post being stored.
+pub async fn preview_post_info(
+	db: &Database, url_base: &str, actor_address: &ActorAddress, message: PostMessageInfo,
+	attachments: Vec<FileInfo>, in_reply_to: Option<&(ActorAddress, IdType)>,
+) -> Result<ObjectInfo> {
+	let actor_id = actor::Entity::find()
+		.select_only()
+		.column(actor::Column::Id)
+		.filter(actor::Column::Address.eq(actor_address))
+		.into_tuple::<i64>()
+		.one(db.inner())
+		.await?;
+	let ((actor_name, actor_avatar), sequence) = match actor_id {
+		Some(id) => (
+			db.find_profile_limited(id).await?,
+			db.find_next_object_sequence(id).await?,
+		),
+		None => ((None, None), 0),
+	};
+	let in_reply_to = match in_reply_to {
+		Some((irt_actor_address, irt_hash)) =>
+			find_targeted_post_info(db, url_base, irt_actor_address, irt_hash).await?,
+		None => None,
+	};
+
+	let now = current_timestamp();
+	Ok(ObjectInfo {
+		consolidated_type: ConsolidatedObjectType::Stonenet,
+		created: now,
+		found: now,
+		found_ago: human_readable_duration_from_timestamp(now),
+		actor_address: Some(actor_address.to_string()),
+		actor_name: actor_name.unwrap_or(actor_address.to_string()),
+		payload: ObjectPayloadInfo::Post(PostObjectInfo {
+			in_reply_to,
+			sequence,
+			message: Some(message),
+			attachments,
+			edited: None,
+			deleted: false,
+			reactions: Vec::new(),
+		}),
+		url: String::new(),
+		id: String::new(),
+		actor_url: actor_url(url_base, actor_address),
+		actor_avatar_url: Some(avatar_url(url_base, actor_address, actor_avatar.as_ref())),
+	})
+}
+
+#[cfg(feature = "web")]
+/// Finds the post that is being replied to.
+async fn find_targeted_post_info(
+	db: &Database, url_base: &str, actor_address: &ActorAddress, hash: &IdType,
+) -> Result<Option<TargetedPostInfo>> {
+	let record = object::Entity::find()
+		.inner_join(actor::Entity)
+		.filter(actor::Column::Address.eq(actor_address))
+		.filter(object::Column::Hash.eq(hash))
+		.one(db.inner())
+		.await?;
+	let object = match record {
+		Some(o) => o,
+		None => return Ok(None),
+	};
+
+	let (actor_name, actor_avatar) = db.find_profile_limited(object.actor_id).await?;
+	let message_opt = find_post_object_info_files(db, url_base, actor_address, object.id).await?;
+	Ok(Some(TargetedPostInfo {
+		id: hash.to_string(),
+		actor_address: actor_address.to_string(),
+		actor_name,
+		actor_avatar_url: Some(avatar_url(url_base, actor_address, actor_avatar.as_ref())),
+		message: message_opt.clone().map(|(mt, b, _)| PostMessageInfo {
+			mime_type: mt,
+			body: b,
+			html: None,
+		}),
+		attachments: message_opt.map(|(_, _, a)| a).unwrap_or(Vec::new()),
+	}))
+}
+
 pub async fn find_profile_info(
 	db: &Database, url_base: &str, actor_address: &ActorAddress,
 ) -> Result<Option<ProfileObjectInfo>> {
@@ -231,7 +590,7 @@ pub async fn find_profile_info(
 			db.inner().get_database_backend(),
 			r#"
 		SELECT i.address, po.name, po.avatar_file_hash, po.wallpaper_file_hash, df.id,
-			df.compression_type, df.plain_hash, df.block_count
+			df.compression_type, df.plain_hash, df.block_count, po.followers_only
 		FROM profile_object AS po
 		LEFT JOIN object AS o ON po.object_id = o.id
 		LEFT JOIN actor AS i ON o.actor_id = i.id
@@ -250,6 +609,7 @@ pub async fn find_profile_info(
 	})
 }
 
+#[cfg(feature = "web")]
 pub async fn find_profile_info2(
 	db: &Database, url_base: &str, actor_id: i64,
 ) -> Result<Option<ProfileObjectInfo>> {
@@ -259,7 +619,7 @@ pub async fn find_profile_info2(
 			db.inner().get_database_backend(),
 			r#"
 		SELECT i.address, po.name, po.avatar_file_hash, po.wallpaper_file_hash, df.id,
-			df.compression_type, df.plain_hash, df.block_count
+			df.compression_type, df.plain_hash, df.block_count, po.followers_only
 		FROM profile_object AS po
 		LEFT JOIN object AS o ON po.object_id = o.id
 		LEFT JOIN actor AS i ON o.actor_id = i.id
@@ -286,7 +646,7 @@ async fn find_profile_object_info(
 			db.inner().get_database_backend(),
 			r#"
 		SELECT i.address, po.name, po.avatar_file_hash, po.wallpaper_file_hash, df.id,
-			   df.compression_type, df.plain_hash, df.block_count
+			   df.compression_type, df.plain_hash, df.block_count, po.followers_only
 		FROM profile_object AS po
 		LEFT JOIN object AS o ON po.object_id = o.id
 		LEFT JOIN actor AS i ON o.actor_id = i.id
@@ -303,6 +663,192 @@ async fn find_profile_object_info(
 	})
 }
 
+async fn find_edit_object_info(
+	db: &Database, url_base: &str, object_id: i64,
+) -> Result<Option<EditObjectInfo>> {
+	let result = edit_object::Entity::find_by_id(object_id)
+		.find_also_related(object::Entity)
+		.one(db.inner())
+		.await?;
+	let (record, object) = match result {
+		Some((r, Some(o))) => (r, o),
+		_ => return Ok(None),
+	};
+	let actor = match actor::Entity::find_by_id(object.actor_id)
+		.one(db.inner())
+		.await?
+	{
+		Some(a) => a,
+		None => return Ok(None),
+	};
+	Ok(Some(EditObjectInfo {
+		url: object_url(url_base, &actor.address, &record.edited_object_hash),
+		actor_address: actor.address,
+		object_hash: record.edited_object_hash,
+	}))
+}
+
+async fn find_delete_object_info(
+	db: &Database, url_base: &str, object_id: i64,
+) -> Result<Option<DeleteObjectInfo>> {
+	let result = delete_object::Entity::find_by_id(object_id)
+		.find_also_related(object::Entity)
+		.one(db.inner())
+		.await?;
+	let (record, object) = match result {
+		Some((r, Some(o))) => (r, o),
+		_ => return Ok(None),
+	};
+	let actor = match actor::Entity::find_by_id(object.actor_id)
+		.one(db.inner())
+		.await?
+	{
+		Some(a) => a,
+		None => return Ok(None),
+	};
+	Ok(Some(DeleteObjectInfo {
+		url: object_url(url_base, &actor.address, &record.deleted_object_hash),
+		actor_address: actor.address,
+		object_hash: record.deleted_object_hash,
+	}))
+}
+
+async fn find_reaction_object_info(
+	db: &Database, url_base: &str, object_id: i64,
+) -> Result<Option<ReactionObjectInfo>> {
+	let result = reaction_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?;
+	Ok(result.map(|r| ReactionObjectInfo {
+		url: object_url(url_base, &r.actor_address, &r.object_hash),
+		actor_address: r.actor_address,
+		object_hash: r.object_hash,
+		emoji: r.emoji,
+	}))
+}
+
+async fn find_poll_object_info(db: &Database, object_id: i64) -> Result<Option<PollObjectInfo>> {
+	let object = match object::Entity::find_by_id(object_id)
+		.find_also_related(actor::Entity)
+		.one(db.inner())
+		.await?
+	{
+		Some((o, Some(a))) => (o, a),
+		_ => return Ok(None),
+	};
+	let tally = match db.load_poll_tally(&object.1.address, &object.0.hash).await? {
+		Some(t) => t,
+		None => return Ok(None),
+	};
+	let options = tally
+		.options
+		.into_iter()
+		.zip(tally.votes)
+		.enumerate()
+		.map(|(index, (text, votes))| PollOptionInfo {
+			index,
+			text,
+			votes,
+			percentage: if tally.voters > 0 {
+				votes * 100 / tally.voters
+			} else {
+				0
+			},
+		})
+		.collect();
+	let now = current_timestamp();
+	let time_left = if now < tally.closes {
+		human_readable_duration(&TimeDelta::try_milliseconds((tally.closes - now) as _).unwrap())
+	} else {
+		String::new()
+	};
+	Ok(Some(PollObjectInfo {
+		question: tally.question,
+		options,
+		multiple_choice: tally.multiple_choice,
+		closes: tally.closes,
+		closed: now > tally.closes,
+		time_left,
+		voters: tally.voters,
+	}))
+}
+
+async fn find_vote_object_info(
+	db: &Database, url_base: &str, object_id: i64,
+) -> Result<Option<VoteObjectInfo>> {
+	let result = vote_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?;
+	Ok(result.map(|r| VoteObjectInfo {
+		url: object_url(url_base, &r.actor_address, &r.object_hash),
+		object_hash: r.object_hash,
+	}))
+}
+
+async fn find_follower_list_object_info(
+	db: &Database, url_base: &str, object_id: i64,
+) -> Result<FollowerListObjectInfo> {
+	let results = follower_list_entry::Entity::find()
+		.filter(follower_list_entry::Column::ObjectId.eq(object_id))
+		.order_by_asc(follower_list_entry::Column::Sequence)
+		.all(db.inner())
+		.await?;
+	Ok(FollowerListObjectInfo {
+		followers: results
+			.into_iter()
+			.map(|r| ApprovedFollowerInfo {
+				url: actor_url(url_base, &r.actor_address),
+				address: r.actor_address.to_string(),
+			})
+			.collect(),
+	})
+}
+
+/// Finds the edits of the post with the given hash, the latest one last. Only
+/// the edits of the actor that published the post count.
+async fn find_post_edits(
+	db: &Database, actor_id: i64, object_hash: &IdType,
+) -> Result<Vec<(i64, u64)>> {
+	let results = edit_object::Entity::find()
+		.select_only()
+		.column(edit_object::Column::ObjectId)
+		.column(object::Column::Created)
+		.inner_join(object::Entity)
+		.filter(object::Column::ActorId.eq(actor_id))
+		.filter(edit_object::Column::EditedObjectHash.eq(object_hash))
+		.order_by_asc(object::Column::Sequence)
+		.into_tuple::<(i64, i64)>()
+		.all(db.inner())
+		.await?;
+	Ok(results
+		.into_iter()
+		.map(|(id, created)| (id, created as u64))
+		.collect())
+}
+
+/// Finds the object ID of the latest edit of the post, along with the time it
+/// was made, if the post has been edited at all.
+async fn find_latest_edit(db: &Database, object_id: i64) -> Result<Option<(i64, u64)>> {
+	let object = match object::Entity::find_by_id(object_id).one(db.inner()).await? {
+		Some(o) if o.r#type == OBJECT_TYPE_POST => o,
+		_ => return Ok(None),
+	};
+	Ok(find_post_edits(db, object.actor_id, &object.hash)
+		.await?
+		.pop())
+}
+
+async fn find_key_rotation_object_info(
+	db: &Database, object_id: i64,
+) -> Result<Option<KeyRotationObjectInfo>> {
+	let result = key_rotation_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?;
+	Ok(result.map(|r| KeyRotationObjectInfo {
+		fingerprint: IdType::hash(&r.new_public_key).to_string(),
+	}))
+}
+
 async fn find_share_object_info(
 	db: &Database, url_base: &str, object_id: i64,
 ) -> Result<Option<ShareObjectInfo>> {
@@ -343,9 +889,16 @@ async fn find_share_object_info(
 					id: object_hash.to_string(),
 					actor_address: actor_address.to_string(),
 					actor_name,
-					actor_avatar_url: actor_avatar
-						.map(|hash| file_url(url_base, &actor_address, &hash)),
-					message: Some(PostMessageInfo { mime_type, body }),
+					actor_avatar_url: Some(avatar_url(
+						url_base,
+						&actor_address,
+						actor_avatar.as_ref(),
+					)),
+					message: Some(PostMessageInfo {
+						mime_type,
+						body,
+						html: None,
+					}),
 					attachments,
 				})
 			} else {
@@ -353,8 +906,11 @@ async fn find_share_object_info(
 					id: object_hash.to_string(),
 					actor_address: actor_address.to_string(),
 					actor_name,
-					actor_avatar_url: actor_avatar
-						.map(|hash| file_url(url_base, &actor_address, &hash)),
+					actor_avatar_url: Some(avatar_url(
+						url_base,
+						&actor_address,
+						actor_avatar.as_ref(),
+					)),
 					message: None,
 					attachments: Vec::new(),
 				})
@@ -368,10 +924,22 @@ async fn find_share_object_info(
 	}
 }
 
-/// Finds the mime-type, text content & attachments for the given post
-/// object respectively.
+/// Finds the mime-type, text content & attachments of the latest version of
+/// the given post object respectively.
 async fn find_post_object_info_files(
 	db: &Database, url_base: &str, actor_address: &ActorAddress, object_id: i64,
+) -> Result<Option<(String, String, Vec<FileInfo>)>> {
+	let version_id = match find_latest_edit(db, object_id).await? {
+		Some((edit_id, _)) => edit_id,
+		None => object_id,
+	};
+	find_post_version_files(db, url_base, actor_address, version_id).await
+}
+
+/// Finds the mime-type, text content & attachments of the post or edit object
+/// with the given ID.
+async fn find_post_version_files(
+	db: &Database, url_base: &str, actor_address: &ActorAddress, object_id: i64,
 ) -> Result<Option<(String, String, Vec<FileInfo>)>> {
 	fn file_query<F>(backend: DatabaseBackend, object_id: i64, condition: F) -> Statement
 	where
@@ -391,13 +959,18 @@ async fn find_post_object_info_files(
 			.build(backend)
 	}
 
-	let query = post_object::Entity::find()
-		.filter(post_object::Column::ObjectId.eq(object_id))
-		.build(db.inner().get_database_backend());
-	let result = db.inner().query_one(query).await?;
+	let file_count = match post_object::Entity::find_by_id(object_id)
+		.one(db.inner())
+		.await?
+	{
+		Some(r) => Some(r.file_count),
+		None => edit_object::Entity::find_by_id(object_id)
+			.one(db.inner())
+			.await?
+			.map(|r| r.file_count),
+	};
 
-	if let Some(r) = result {
-		let file_count: i64 = r.try_get_by("file_count")?;
+	if let Some(file_count) = file_count {
 
 		let query = file_query(
 			db.inner().get_database_backend(),
@@ -438,10 +1011,14 @@ async fn find_post_object_info_files(
 					for row in results {
 						let hash: IdType = row.try_get_by("hash")?;
 						let mime_type_opt: Option<String> = row.try_get_by("mime_type")?;
-						attachments.push(FileInfo {
-							url: format!("{}/actor/{}/file/{}", url_base, actor_address, hash),
-							mime_type: mime_type_opt,
-						});
+						let metadata = db.load_file_metadata(&hash).await?;
+						attachments.push(FileInfo::new_stored(
+							url_base,
+							actor_address,
+							&hash,
+							mime_type_opt,
+							metadata,
+						));
 					}
 
 					// TODO: remove unwrap
@@ -501,11 +1078,15 @@ async fn find_post_object_info(
 					id: object_hash.to_string(),
 					actor_address: irt_actor_address.to_string(),
 					actor_name: irt_actor_name,
-					actor_avatar_url: irt_actor_avatar_id
-						.map(|hash| file_url(url_base, &irt_actor_address, &hash)),
+					actor_avatar_url: Some(avatar_url(
+						url_base,
+						&irt_actor_address,
+						irt_actor_avatar_id.as_ref(),
+					)),
 					message: irt_message_opt.clone().map(|(mt, b, _)| PostMessageInfo {
 						mime_type: mt,
 						body: b,
+						html: None,
 					}),
 					attachments: irt_message_opt.map(|(_, _, a)| a).unwrap_or(Vec::new()),
 				})
@@ -513,22 +1094,79 @@ async fn find_post_object_info(
 		};
 
 		let actor_address = actor_address_opt.unwrap();
-		let message_opt =
-			find_post_object_info_files(db, url_base, &actor_address, object_id).await?;
+		let edited = find_latest_edit(db, object_id)
+			.await?
+			.map(|(_, created)| created);
+		let deleted = db.is_tombstone(object_id).await?;
+		let reactions = db
+			.load_reactions(&actor_address, &object_hash)
+			.await?
+			.into_iter()
+			.map(|(emoji, count)| ReactionInfo { emoji, count })
+			.collect();
+		let message_opt = if deleted {
+			None
+		} else {
+			find_post_object_info_files(db, url_base, &actor_address, object_id).await?
+		};
 		Ok(Some(PostObjectInfo {
 			in_reply_to,
 			sequence: object_sequence as _,
 			message: message_opt.as_ref().map(|(mt, b, _)| PostMessageInfo {
 				mime_type: mt.clone(),
 				body: b.clone(),
+				html: None,
 			}),
 			attachments: message_opt.map(|(_, _, a)| a).unwrap_or(Vec::new()),
+			edited,
+			deleted,
+			reactions,
 		}))
 	} else {
 		Ok(None)
 	}
 }
 
+#[cfg(any(feature = "web", test))]
+/// Loads all versions of the post, the latest one first. Returns `None` if the
+/// actor has no such post, or if it has been deleted.
+pub async fn load_post_history(
+	db: &Database, url_base: &str, actor_address: &ActorAddress, hash: &IdType,
+) -> Result<Option<Vec<PostVersionInfo>>> {
+	let object = match object::Entity::find()
+		.inner_join(actor::Entity)
+		.filter(actor::Column::Address.eq(actor_address))
+		.filter(object::Column::Hash.eq(hash))
+		.filter(object::Column::Type.eq(OBJECT_TYPE_POST))
+		.one(db.inner())
+		.await?
+	{
+		Some(o) => o,
+		None => return Ok(None),
+	};
+	if db.is_tombstone(object.id).await? {
+		return Ok(None);
+	}
+
+	let mut versions = vec![(object.id, object.created as u64)];
+	versions.extend(find_post_edits(db, object.actor_id, hash).await?);
+	let mut history = Vec::with_capacity(versions.len());
+	for (object_id, created) in versions.into_iter().rev() {
+		let files = find_post_version_files(db, url_base, actor_address, object_id).await?;
+		history.push(PostVersionInfo {
+			created,
+			created_ago: human_readable_duration_from_timestamp(created),
+			message: files.as_ref().map(|(mt, b, _)| PostMessageInfo {
+				mime_type: mt.clone(),
+				body: b.clone(),
+				html: None,
+			}),
+			attachments: files.map(|(_, _, a)| a).unwrap_or(Vec::new()),
+		});
+	}
+	Ok(Some(history))
+}
+
 pub fn human_readable_duration(duration: &TimeDelta) -> String {
 	if duration.num_weeks() > 0 {
 		let weeks = duration.num_weeks();
@@ -574,14 +1212,38 @@ fn human_readable_duration_from_timestamp(timestamp: u64) -> String {
 	human_readable_duration(&duration)
 }
 
+#[cfg(feature = "web")]
 pub async fn load_actor_feed(
 	db: &Database, url_base: &str, actor: &ActorAddress, limit: u64, offset: u64,
 ) -> Result<Vec<ObjectInfo>> {
-	let query = Query::select()
+	let query = actor_feed_query(actor).limit(limit).offset(offset).take();
+	Ok(load_actor_feed_objects(db, url_base, actor, &query, limit)
+		.await?
+		.objects)
+}
+
+#[cfg(feature = "web")]
+/// Loads the objects of the actor that come after the cursor, or the latest
+/// ones if no cursor is given.
+pub async fn load_actor_feed_page(
+	db: &Database, url_base: &str, actor: &ActorAddress, limit: u64, after: Option<FeedCursor>,
+) -> Result<FeedPage> {
+	let mut query = actor_feed_query(actor);
+	if let Some(cursor) = after {
+		query.and_where(Expr::col((object::Entity, object::Column::Sequence)).lt(cursor.position));
+	}
+	let query = query.limit(limit).take();
+	load_actor_feed_objects(db, url_base, actor, &query, limit).await
+}
+
+#[cfg(feature = "web")]
+fn actor_feed_query(actor: &ActorAddress) -> SelectStatement {
+	Query::select()
 		.column((object::Entity, object::Column::Id))
 		.column((object::Entity, object::Column::Type))
 		.column(object::Column::ActorId)
 		.column(object::Column::Hash)
+		.column(object::Column::Sequence)
 		.column(object::Column::Created)
 		.column(object::Column::Found)
 		.from(object::Entity)
@@ -592,11 +1254,14 @@ pub async fn load_actor_feed(
 		)
 		.and_where(actor::Column::Address.eq(actor))
 		.order_by(object::Column::Sequence, Order::Desc)
-		.limit(limit)
-		.offset(offset)
-		.take();
-	let stat = db.backend().build(&query);
+		.take()
+}
 
+#[cfg(feature = "web")]
+async fn load_actor_feed_objects(
+	db: &Database, url_base: &str, actor: &ActorAddress, query: &SelectStatement, limit: u64,
+) -> Result<FeedPage> {
+	let stat = db.backend().build(query);
 	let results = db.inner().query_all(stat).await?;
 	let mut objects = Vec::with_capacity(limit as _);
 	for result in &results {
@@ -604,12 +1269,35 @@ pub async fn load_actor_feed(
 			objects.push(object);
 		}
 	}
-	Ok(objects)
+	let next = match results.last() {
+		Some(last) if results.len() as u64 == limit => Some(FeedCursor {
+			position: last.try_get_by("sequence")?,
+			id: last.try_get_by("id")?,
+		}),
+		_ => None,
+	};
+	Ok(FeedPage { objects, next })
 }
 
 pub async fn load_home_feed(
 	db: &Database, limit: u64, offset: u64, track: impl Iterator<Item = &ActorAddress> + Send,
 ) -> Result<Vec<ObjectInfo>> {
+	Ok(query_home_feed(db, limit, offset, None, track).await?.objects)
+}
+
+/// Loads the objects of the home feed that come after the cursor, or the
+/// latest ones if no cursor is given.
+pub async fn load_home_feed_page(
+	db: &Database, limit: u64, after: Option<FeedCursor>,
+	track: impl Iterator<Item = &ActorAddress> + Send,
+) -> Result<FeedPage> {
+	query_home_feed(db, limit, 0, after, track).await
+}
+
+async fn query_home_feed(
+	db: &Database, limit: u64, offset: u64, after: Option<FeedCursor>,
+	track: impl Iterator<Item = &ActorAddress> + Send,
+) -> Result<FeedPage> {
 	// Build up the part of the query that includes the id's to track additionally
 	let cap = track.size_hint().1.unwrap_or(track.size_hint().0);
 	let mut tuples: Vec<&ActorAddress> = Vec::with_capacity(cap);
@@ -652,7 +1340,19 @@ pub async fn load_home_feed(
 					),
 				),
 		)
+		.apply_if(after, |query, cursor| {
+			query.filter(
+				Condition::any()
+					.add(object::Column::Found.lt(cursor.position))
+					.add(
+						object::Column::Found
+							.eq(cursor.position)
+							.and(object::Column::Id.lt(cursor.id)),
+					),
+			)
+		})
 		.order_by_desc(object::Column::Found)
+		.order_by_desc(object::Column::Id)
 		.offset(offset)
 		.limit(limit)
 		.build(db.backend());
@@ -671,9 +1371,17 @@ pub async fn load_home_feed(
 			}
 		}
 	}
-	Ok(objects)
+	let next = match results.last() {
+		Some(last) if results.len() as u64 == limit => Some(FeedCursor {
+			position: last.try_get_by("found")?,
+			id: last.try_get_by("id")?,
+		}),
+		_ => None,
+	};
+	Ok(FeedPage { objects, next })
 }
 
+#[cfg(feature = "web")]
 pub async fn load_object_info(
 	db: &Database, url_base: &str, hash: &IdType,
 ) -> Result<Option<ObjectInfo>> {
@@ -704,11 +1412,32 @@ pub async fn load_object_payload_info(
 		OBJECT_TYPE_PROFILE => find_profile_object_info(db, url_base, object_id)
 			.await?
 			.map(|r| ObjectPayloadInfo::Profile(r)),
+		OBJECT_TYPE_KEY_ROTATION => find_key_rotation_object_info(db, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::KeyRotation(r)),
+		OBJECT_TYPE_EDIT => find_edit_object_info(db, url_base, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::Edit(r)),
+		OBJECT_TYPE_DELETE => find_delete_object_info(db, url_base, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::Delete(r)),
+		OBJECT_TYPE_REACTION => find_reaction_object_info(db, url_base, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::Reaction(r)),
+		OBJECT_TYPE_POLL => find_poll_object_info(db, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::Poll(r)),
+		OBJECT_TYPE_VOTE => find_vote_object_info(db, url_base, object_id)
+			.await?
+			.map(|r| ObjectPayloadInfo::Vote(r)),
+		OBJECT_TYPE_FOLLOWER_LIST => Some(ObjectPayloadInfo::FollowerList(
+			find_follower_list_object_info(db, url_base, object_id).await?,
+		)),
 		other => panic!("unknown object type: {}", other),
 	})
 }
 
-fn object_url(url_base: &str, actor_address: &ActorAddress, hash: &IdType) -> String {
+pub fn object_url(url_base: &str, actor_address: &ActorAddress, hash: &IdType) -> String {
 	format!("{}/actor/{}/object/{}", url_base, actor_address, hash)
 }
 
@@ -740,8 +1469,7 @@ async fn _load_object_info(
 				url: object_url(url_base, &actor_address, &object.hash),
 				id: object.hash.to_string(),
 				actor_url: actor_url(url_base, &actor_address),
-				actor_avatar_url: actor_avatar
-					.map(|hash| file_url(url_base, &actor_address, &hash)),
+				actor_avatar_url: Some(avatar_url(url_base, &actor_address, actor_avatar.as_ref())),
 			})
 		} else {
 			None
@@ -777,7 +1505,7 @@ async fn _load_object_info_from_result(
 			url: object_url(url_base, actor_address, &hash),
 			id: hash.to_string(),
 			actor_url: actor_url(url_base, actor_address),
-			actor_avatar_url: actor_avatar.map(|hash| file_url(url_base, actor_address, &hash)),
+			actor_avatar_url: Some(avatar_url(url_base, actor_address, actor_avatar.as_ref())),
 		}))
 	} else {
 		Ok(None)
@@ -795,6 +1523,7 @@ async fn _parse_profile_info(
 	let description_compression_type: Option<u8> = result.try_get_by_index(5)?;
 	let description_plain_hash: Option<IdType> = result.try_get_by_index(6)?;
 	let description_block_count: Option<i64> = result.try_get_by_index(7)?;
+	let followers_only: bool = result.try_get_by_index(8)?;
 
 	let description = if let Some(file_id) = description_id {
 		let data = db
@@ -819,9 +1548,11 @@ async fn _parse_profile_info(
 			address: actor_address.to_string(),
 			url: actor_url(url_base, &actor_address),
 			name: actor_name,
-			avatar_url: avatar_id.map(|id| file_url(url_base, &actor_address, &id)),
+			petname: None,
+			avatar_url: Some(avatar_url(url_base, &actor_address, avatar_id.as_ref())),
 			wallpaper_url: wallpaper_id.map(|id| file_url(url_base, &actor_address, &id)),
 		},
 		description: description.map(|b| String::from_utf8_lossy(&b).to_string()),
+		followers_only,
 	}))
 }
@@ -1,18 +1,48 @@
 mod activity_pub;
 mod actor;
+mod admin;
+mod api_v1;
+mod auth;
+mod banlist;
+mod bookmark;
 pub mod common;
+mod conversation;
+mod csrf;
+mod draft;
+mod follow;
 mod identity;
+mod journal;
+mod locale;
+mod markdown;
+mod petname;
+mod poll;
+mod qr_code;
+mod rate_limit;
+mod stats;
+mod tag;
+mod theme;
+mod unlock;
+mod upload;
+mod verify;
 
 
 use std::{
+	collections::HashMap,
 	net::*,
+	path::Path,
 	str::FromStr,
 	sync::{atomic::*, Arc},
 	time::Duration,
 };
 
 use ::serde::*;
-use axum::{body::Body, extract::*, response::Response, routing::get, Router};
+use axum::{
+	body::Body, extract::*, middleware::from_fn_with_state, response::Response, routing::get,
+	Router,
+};
+use chrono::DateTime;
+use log::*;
+use rand::rngs::OsRng;
 #[cfg(debug_assertions)]
 use rss::validation::Validate;
 use rss::{ChannelBuilder, ItemBuilder};
@@ -20,21 +50,28 @@ use tera::{Context, Tera};
 use tokio::{sync::Mutex, time::sleep};
 use tower_http::services::ServeDir;
 
-use self::common::*;
+use self::{auth::Auth, common::*};
 use super::{
-	consolidated_feed::load_consolidated_feed,
-	info::{ObjectInfo, ObjectPayloadInfo, PostMessageInfo},
+	activity_pub::translate_special_mime_types2,
+	consolidated_feed::load_consolidated_feed_page,
+	info::{FeedCursor, ObjectInfo, ObjectPayloadInfo, PostMessageInfo},
 	Global,
 };
 use crate::{
-	api::Api,
+	api::{vanity::VanitySearch, Api},
 	common::*,
 	config::Config,
 	core::*,
-	db::{self, Database},
+	db::{self, local_user, Database},
+	naming,
 };
 
 
+/// The number of objects that are loaded into the feeds at a time.
+const FEED_PAGE_SIZE: u64 = 5;
+const SEARCH_PAGE_SIZE: u64 = 20;
+
+
 #[derive(Clone, Serialize)]
 pub struct IdentityData {
 	label: String,
@@ -50,6 +87,13 @@ pub struct AppState {
 pub struct ServerGlobal {
 	pub base: Arc<Global>,
 	pub template_engine: Tera,
+	pub auth: Auth,
+	pub locales: Arc<locale::Catalogs>,
+	/// Only set for the web interface, which is open to the public.
+	pub limits: Option<rate_limit::WebLimits>,
+	/// The searches for vanity addresses that have been started through the
+	/// API, by the label of the identity that they are for.
+	pub vanity_searches: Mutex<HashMap<String, Arc<VanitySearch>>>,
 }
 
 #[derive(Clone, Serialize)]
@@ -62,13 +106,16 @@ pub struct ServerInfo {
 
 
 impl AppState {
+	/// Loads the identities of the current local user, or of the owner of the
+	/// node.
 	pub async fn load(db: &Database) -> db::Result<Self> {
-		let identities = db.perform(|c| c.fetch_my_identities())?;
+		let user_id = local_user::current_user();
+		let identities = db
+			.perform(move |c| c.fetch_user_identities(user_id))
+			.await?;
 
 		Ok(Self {
-			active_identity: identities
-				.get(0)
-				.map(|(label, address, ..)| (label.clone(), address.clone())),
+			active_identity: db.load_active_identity().await?,
 			identities: identities
 				.into_iter()
 				.map(|(label, address, ..)| IdentityData {
@@ -84,6 +131,35 @@ pub async fn serve(
 	stop_flag: Arc<AtomicBool>, port: u16, _workers: Option<usize>, api: Api,
 	server_info: ServerInfo, config: Config,
 ) -> db::Result<()> {
+	let auth = match Auth::from_config(&config) {
+		Ok(a) => a,
+		Err(e) => {
+			error!("Unable to load the interface password, not serving on port {}: {}", port, e);
+			return Ok(());
+		}
+	};
+	let theme = theme::theme_dir(&config);
+	let mut template_engine = match theme::load_templates("templates/**/*.tera", theme.as_deref())
+	{
+		Ok(t) => t,
+		Err(e) => {
+			error!("Unable to load the templates, not serving on port {}: {}", port, e);
+			return Ok(());
+		}
+	};
+	let locales = match locale::Catalogs::load(Path::new("locales")) {
+		Ok(c) => Arc::new(c),
+		Err(e) => {
+			error!("Unable to load the translations, not serving on port {}: {}", port, e);
+			return Ok(());
+		}
+	};
+	locale::register(&mut template_engine, locales.clone());
+	let limits = if server_info.is_exposed {
+		Some(rate_limit::WebLimits::from_config(&config))
+	} else {
+		None
+	};
 	let global = Arc::new(ServerGlobal {
 		base: Arc::new(Global {
 			state: Mutex::new(AppState::load(&api.db).await?),
@@ -91,7 +167,11 @@ pub async fn serve(
 			server_info,
 			config,
 		}),
-		template_engine: Tera::new("templates/**/*.tera").unwrap(),
+		template_engine,
+		auth,
+		locales,
+		limits,
+		vanity_searches: Mutex::new(HashMap::new()),
 	});
 
 	// TODO: Only turn this on via a config option that is off by default.
@@ -100,6 +180,7 @@ pub async fn serve(
 	} else {
 		super::activity_pub::maintain_outbox_polls(
 			stop_flag.clone(),
+			global.base.api.node.tasks(),
 			global.base.api.db.clone(),
 			&global.base.config,
 		);
@@ -112,16 +193,44 @@ pub async fn serve(
 	};
 	let addr = SocketAddrV4::new(ip, port);
 
-	let app = Router::new()
+	let mut app = Router::new()
 		.route("/", get(home).post(home_post))
-		.nest_service("/static", ServeDir::new("static"))
+		.nest_service(
+			"/static",
+			ServeDir::new(theme::static_dir(theme.as_deref())).fallback(ServeDir::new("static")),
+		)
 		.nest("/activity-pub", activity_pub::router(global.clone()))
+		.merge(auth::router(global.clone()))
 		.nest("/actor", actor::router(global.clone()))
+		.nest("/admin", admin::router(global.clone()))
+		.nest("/api/v1", api_v1::router(global.clone()))
+		.nest("/banlist", banlist::router(global.clone()))
+		.nest("/bookmark", bookmark::router(global.clone()))
+		.nest("/conversation", conversation::router(global.clone()))
+		.nest("/draft", draft::router(global.clone()))
+		.nest("/follow", follow::router(global.clone()))
 		.nest("/identity", identity::router(global.clone()))
+		.nest("/journal", journal::router(global.clone()))
+		.nest("/language", locale::router(global.clone()))
+		.nest("/petname", petname::router(global.clone()))
+		.nest("/poll", poll::router(global.clone()))
 		.route("/rss", get(rss_feed))
 		.route("/search", get(search))
+		.nest("/stats", stats::router(global.clone()))
+		.nest("/tag", tag::router(global.clone()))
+		.nest("/unlock", unlock::router(global.clone()))
+		.nest("/upload", upload::router(global.clone()))
+		.nest("/verify", verify::router(global.clone()))
 		.route("/.well-known/webfinger", get(activity_pub::webfinger))
 		.route("/.well-known/x-nodeinfo2", get(activity_pub::nodeinfo))
+		.layer(from_fn_with_state(global.clone(), auth::require_login))
+		.layer(from_fn_with_state(global.clone(), locale::select_language))
+		.layer(from_fn_with_state(global.clone(), csrf::protect));
+	if let Some(limits) = &global.limits {
+		app = app.layer(DefaultBodyLimit::max(limits.request_size_limit));
+	}
+	let app = app
+		.layer(from_fn_with_state(global.clone(), rate_limit::limit))
 		.with_state(global);
 
 	let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
@@ -142,42 +251,76 @@ pub async fn serve(
 #[derive(Default, Deserialize)]
 struct PaginationQuery {
 	page: Option<u64>,
+	/// The cursor of the page of a feed to show, or none for the first page.
+	after: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PostQuery {
+	/// Shows what the post would look like, instead of publishing it.
+	preview: Option<bool>,
+	/// Saves the post as a draft, instead of publishing it.
+	draft: Option<bool>,
 }
 
 
 async fn home(
 	State(g): State<Arc<ServerGlobal>>, Query(query): Query<PaginationQuery>,
 ) -> Response {
-	let p = query.page.unwrap_or(0);
-	let start = p * 5;
+	let after = match parse_cursor(&query) {
+		Ok(c) => c,
+		Err(r) => return r,
+	};
 
-	let mut objects: Vec<ObjectInfo> = if g.base.server_info.is_exposed {
-		match g.base.api.load_home_feed(5, start).await {
+	let mut page = if g.base.server_info.is_exposed {
+		match g.base.api.load_home_feed_page(FEED_PAGE_SIZE, after).await {
 			Ok(f) => f,
 			Err(e) => return server_error_response(e, "unable to fetch home feed"),
 		}
 	// In your own local UI, view the consolidated home feed
 	} else {
-		if p == 0 {
+		if after.is_none() {
 			if let Err(e) = g.base.api.update_consolidated_feed().await {
 				return server_error_response(e, "unable to update consolidated feed");
 			}
 		}
-		match load_consolidated_feed(&g.base.api.db, &g.base.server_info.url_base, 5, start).await {
+		let url_base = &g.base.server_info.url_base;
+		match load_consolidated_feed_page(&g.base.api.db, url_base, FEED_PAGE_SIZE, after).await {
 			Ok(f) => f,
 			Err(e) => return server_error_response(e, "unable to fetch home feed"),
 		}
 	};
 
-	translate_special_mime_types_for_objects(&mut objects);
+	translate_special_mime_types_for_objects(&mut page.objects);
 
 	let mut context = Context::new();
-	context.insert("objects", &objects);
-	context.insert("page", &p);
+	context.insert("objects", &page.objects);
+	context.insert("is_first_page", &after.is_none());
+	context.insert("next_cursor", &page.next.map(|c| c.to_string()));
 	g.render("home.html.tera", context).await
 }
 
-async fn home_post(State(g): State<Arc<ServerGlobal>>, form: Multipart) -> Response {
+/// Parses the cursor of the page of a feed that is asked for, if any.
+fn parse_cursor(query: &PaginationQuery) -> Result<Option<FeedCursor>, Response> {
+	match &query.after {
+		None => Ok(None),
+		Some(after) => match after.parse::<FeedCursor>() {
+			Ok(c) => Ok(Some(c)),
+			Err(message) => Err(error_response(400, message)),
+		},
+	}
+}
+
+async fn home_post(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<PostQuery>, form: Multipart,
+) -> Response {
+	if query.preview.unwrap_or(false) {
+		return render_preview(&g, form, None).await;
+	}
+	if query.draft.unwrap_or(false) {
+		return draft::save_new_draft(&g, form, None).await;
+	}
+
 	match post_message(&g.base, form, None).await {
 		Ok(r) => r,
 		Err(e) => return e,
@@ -229,9 +372,22 @@ async fn rss_feed(State(g): State<Arc<ServerGlobal>>) -> Response {
 #[derive(Deserialize)]
 struct SearchQuery {
 	query: String,
+	page: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct SearchResultInfo {
+	url: String,
+	kind: &'static str,
+	actor_url: String,
+	actor_address: String,
+	created: String,
+	snippet: String,
 }
 
-async fn search(Query(query): Query<SearchQuery>) -> Response {
+async fn search(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<SearchQuery>,
+) -> Response {
 	if let Some(first_char) = query.query.chars().next() {
 		if first_char == '@' {
 			return Response::builder()
@@ -241,24 +397,128 @@ async fn search(Query(query): Query<SearchQuery>) -> Response {
 				.unwrap();
 		}
 	}
-
-	match Address::from_str(&query.query) {
-		Err(e) => server_error_response(e, "Invalid address"),
-		Ok(address) => Response::builder()
+	// A hashtag leads to the posts with that tag
+	if let Some(tag) = query.query.strip_prefix('#').and_then(db::tag::normalize_tag) {
+		let encoded: String = tag
+			.bytes()
+			.map(|b| {
+				if b.is_ascii_alphanumeric() || b == b'_' {
+					(b as char).to_string()
+				} else {
+					format!("%{:02X}", b)
+				}
+			})
+			.collect();
+		return Response::builder()
 			.status(303)
-			.header("Location", format!("/actor/{}", address))
+			.header("Location", format!("/tag/{}", encoded))
 			.body(Body::empty())
-			.unwrap(),
+			.unwrap();
 	}
+
+	// Addresses and names lead straight to the actor, anything else is searched
+	// for in the posts and profiles
+	let use_petnames = !g.base.server_info.is_exposed;
+	let use_dns = g.base.config.resolve_dns_names.unwrap_or(false);
+	match naming::resolve(&g.base.api.db, &query.query, use_petnames, use_dns).await {
+		Ok(Some(address)) =>
+			return Response::builder()
+				.status(303)
+				.header("Location", format!("/actor/{}", address))
+				.body(Body::empty())
+				.unwrap(),
+		Ok(None) => {}
+		Err(naming::Error::Database(e)) =>
+			return server_error_response(e, "Unable to resolve name"),
+		Err(_) => {}
+	}
+
+	// The public doesn't get to search through what it isn't allowed to see
+	let include_private = !g.base.server_info.is_exposed;
+	let page = query.page.unwrap_or(0);
+	let hits = match g
+		.base
+		.api
+		.search(
+			&query.query,
+			include_private,
+			SEARCH_PAGE_SIZE,
+			page * SEARCH_PAGE_SIZE,
+		)
+		.await
+	{
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to search"),
+	};
+	let results: Vec<_> = hits
+		.into_iter()
+		.map(|hit| {
+			let actor_url = format!("/actor/{}", hit.actor_address);
+			let (url, kind) = if hit.object_type == OBJECT_TYPE_PROFILE {
+				(actor_url.clone(), "Profile")
+			} else {
+				(format!("{}/object/{}", actor_url, hit.object_hash), "Post")
+			};
+			SearchResultInfo {
+				url,
+				kind,
+				actor_url,
+				actor_address: hit.actor_address.to_string(),
+				created: match DateTime::from_timestamp_millis(hit.created as _) {
+					Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+					None => String::new(),
+				},
+				snippet: hit.snippet,
+			}
+		})
+		.collect();
+
+	let mut context = Context::new();
+	context.insert("query", &query.query);
+	context.insert("page", &page);
+	context.insert("has_next_page", &(results.len() as u64 == SEARCH_PAGE_SIZE));
+	context.insert("results", &results);
+	g.render("search.html.tera", context).await
 }
 
 
 impl ServerGlobal {
+	/// Loads the identities again, after one of them has been added, changed
+	/// or selected.
+	/// The state of local users isn't kept, as it is loaded for every page.
+	pub async fn reload_identities(&self) -> db::Result<()> {
+		if local_user::current_user().is_none() {
+			let state = AppState::load(&self.base.api.db).await?;
+			*self.base.state.lock().await = state;
+		}
+		Ok(())
+	}
+
 	pub async fn render(&self, template_name: &str, context: Context) -> Response {
 		let mut complete_context = Context::new();
-		let state = self.base.state.lock().await.clone();
+		let state = if local_user::current_user().is_some() {
+			match AppState::load(&self.base.api.db).await {
+				Ok(s) => s,
+				Err(e) => return server_error_response(e, "Unable to load identities"),
+			}
+		} else {
+			self.base.state.lock().await.clone()
+		};
 		complete_context.insert("app", &state);
 		complete_context.insert("server", &self.base.server_info);
+		complete_context.insert("database", &self.base.api.database_status());
+		complete_context.insert("keys_locked", &self.base.api.db.keyring().is_locked());
+		complete_context.insert(
+			"login_required",
+			&(self.auth.is_enabled() || local_user::current_user().is_some()),
+		);
+		// The login page is shown to anybody, so it doesn't show the identities
+		complete_context.insert("logged_out", &false);
+		complete_context.insert("csrf_token", &csrf::current_token());
+		complete_context.insert("language", &locale::current_language());
+		complete_context.insert("languages", &self.locales.languages());
+		// Forms send this key along, so that resubmitting them has no effect
+		complete_context.insert("idempotency_key", &IdType::random(&mut OsRng).to_string());
 		complete_context.extend(context);
 
 		match self
@@ -277,31 +537,26 @@ impl ServerGlobal {
 	}
 }
 
-/// Attemps to translate a post message into one of a mime type that can be
-/// understood by the frontend.
-pub fn translate_special_mime_types(post: &PostMessageInfo) -> Option<PostMessageInfo> {
-	translate_special_mime_types2(&post.mime_type, &post.body)
+/// Renders the post in the form the way it would appear in a feed, without
+/// publishing it.
+async fn render_preview(
+	g: &ServerGlobal, form: Multipart, in_reply_to: Option<(ActorAddress, IdType)>,
+) -> Response {
+	let mut object = match preview_message(&g.base, form, in_reply_to).await {
+		Ok(o) => o,
+		Err(e) => return e,
+	};
+	translate_special_mime_types_for_object(&mut object);
+
+	let mut context = Context::new();
+	context.insert("object", &object);
+	g.render("preview.html.tera", context).await
 }
 
 /// Attemps to translate a post message into one of a mime type that can be
 /// understood by the frontend.
-pub fn translate_special_mime_types2(mime_type: &str, body: &str) -> Option<PostMessageInfo> {
-	let result = match mime_type {
-		"application/activity+json" => super::activity_pub::translate_activitystreams_object(body),
-		"application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"" =>
-			super::activity_pub::translate_activitystreams_object(body),
-		_ => return None,
-	};
-
-	Some(match result {
-		Ok(r) => r,
-		Err(e) => PostMessageInfo {
-			// Abuse the mime_type field to signify an error has occured while trying to translate
-			// the message
-			mime_type: "error".to_string(),
-			body: e,
-		},
-	})
+pub fn translate_special_mime_types(post: &PostMessageInfo) -> Option<PostMessageInfo> {
+	translate_special_mime_types2(&post.mime_type, &post.body)
 }
 
 fn translate_special_mime_types_for_object(object: &mut ObjectInfo) {
@@ -309,30 +564,57 @@ fn translate_special_mime_types_for_object(object: &mut ObjectInfo) {
 		ObjectPayloadInfo::Post(post) => {
 			if let Some(irt) = &mut post.in_reply_to {
 				if let Some(message) = &mut irt.message {
-					if let Some(new) = translate_special_mime_types(message) {
-						*message = new;
-					}
+					translate_message(message);
 				}
 			}
 
 			if let Some(message) = &mut post.message {
-				if let Some(new) = translate_special_mime_types(message) {
-					*message = new;
-				}
+				translate_message(message);
 			}
 		}
 		ObjectPayloadInfo::Share(share) =>
 			if let Some(post) = &mut share.original_post {
 				if let Some(message) = &mut post.message {
-					if let Some(new) = translate_special_mime_types(message) {
-						*message = new;
-					}
+					translate_message(message);
 				}
 			},
 		_ => {}
 	}
 }
 
+pub fn translate_message(message: &mut PostMessageInfo) {
+	if let Some(new) = translate_special_mime_types(message) {
+		*message = new;
+	}
+	match message.mime_type.as_str() {
+		"text/markdown" => {
+			message.body = link_mentions(&message.body);
+			message.html = Some(markdown::render_markdown(&message.body));
+		}
+		"text/html" => message.html = Some(markdown::sanitize_html(&message.body)),
+		_ => {}
+	}
+}
+
+/// Turns the mentions of actors in the markdown into links to the actors.
+fn link_mentions(markdown: &str) -> String {
+	let mut linked = String::with_capacity(markdown.len());
+	let mut last_end = 0;
+	for (range, word) in db::mention::find_mention_words(markdown) {
+		// Leave the mentions alone that are already the text of a link
+		if markdown[..range.start].ends_with('[') {
+			continue;
+		}
+		if let Ok(Address::Actor(address)) = Address::from_str(word) {
+			linked.push_str(&markdown[last_end..range.start]);
+			linked.push_str(&format!("[@{}](/actor/{})", word, address));
+			last_end = range.end;
+		}
+	}
+	linked.push_str(&markdown[last_end..]);
+	linked
+}
+
 fn translate_special_mime_types_for_objects(objects: &mut [ObjectInfo]) {
 	for object in objects {
 		translate_special_mime_types_for_object(object);
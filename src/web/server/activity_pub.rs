@@ -13,6 +13,7 @@ use std::{
 use axum::{
 	body::Body,
 	extract::{Query, State},
+	http::{header, HeaderMap, HeaderValue},
 	response::Response,
 	routing::*,
 	*,
@@ -35,8 +36,9 @@ use super::{
 use crate::{
 	db::{self, PersistenceHandle},
 	entity::*,
+	media,
 	trace::Traceable,
-	util::read_text_file,
+	util::{block_in_place, read_text_file},
 	web::{
 		self,
 		activity_pub::{
@@ -331,10 +333,12 @@ async fn activity_pub_actor_get(
 			address: address.clone(),
 			url: url.to_string(),
 			name,
+			petname: None,
 			avatar_url: None,
 			wallpaper_url: None,
 		},
 		description: summary,
+		followers_only: false,
 	};
 
 	let mut context = Context::new();
@@ -346,6 +350,48 @@ async fn activity_pub_actor_get(
 	g.render("activity_pub/actor.html.tera", context).await
 }
 
+/// Whether the client would rather have the ActivityStreams document of a page
+/// than the page itself, which is what other ActivityPub servers ask for when
+/// they look up a link.
+pub fn wants_activity_json(headers: &HeaderMap) -> bool {
+	let accept = match headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) {
+		Some(a) => a,
+		None => return false,
+	};
+	let mut html_quality = 0.0f32;
+	let mut json_quality = 0.0f32;
+	for range in accept.split(',') {
+		let mut params = range.split(';').map(|p| p.trim());
+		let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+		let params: Vec<_> = params.collect();
+		let quality = params
+			.iter()
+			.find_map(|p| p.strip_prefix("q="))
+			.and_then(|q| q.parse().ok())
+			.unwrap_or(1.0);
+		let is_activity_json = media_type == "application/activity+json"
+			|| (media_type == "application/ld+json"
+				&& params
+					.iter()
+					.any(|p| p.starts_with("profile=") && p.contains("activitystreams")));
+		if is_activity_json {
+			json_quality = json_quality.max(quality);
+		} else if media_type == "text/html" {
+			html_quality = html_quality.max(quality);
+		}
+	}
+	json_quality > 0.0 && json_quality >= html_quality
+}
+
+/// Marks the response as depending on the `Accept` header, so that caches
+/// don't hand out pages to ActivityPub servers, or the other way around.
+pub fn vary_on_accept(mut response: Response) -> Response {
+	response
+		.headers_mut()
+		.insert(header::VARY, HeaderValue::from_static("Accept"));
+	response
+}
+
 fn activity_pub_response(mut json: serde_json::Value, context: &[&str]) -> Response {
 	// Insert context into json object
 	json.as_object_mut()
@@ -436,7 +482,24 @@ pub async fn actor_inbox_post(
 		return error_response(406, "JSON object too big.");
 	}
 
-	let object_json = serde_json::Value::from_str(&body).unwrap();
+	let object_json = match serde_json::Value::from_str(&body) {
+		Ok(j) => j,
+		Err(e) => return error_response(400, format!("Invalid JSON: {}", e)),
+	};
+	// Activities of the actors that have been blocked are dropped silently
+	if let Some(serde_json::Value::String(sender)) = object_json.get("actor") {
+		match g
+			.base
+			.api
+			.db
+			.is_activity_pub_actor_blocked(actor.id, sender)
+			.await
+		{
+			Ok(false) => {}
+			Ok(true) => return Response::builder().status(202).body(Body::empty()).unwrap(),
+			Err(e) => return server_error_response(e, "Unable to load block list"),
+		}
+	}
 	let result = if let Some(object_type) = object_json.get("type") {
 		let type_string = match object_type {
 			serde_json::Value::String(s) => s,
@@ -719,7 +782,10 @@ pub async fn init(stop_flag: Arc<AtomicBool>, global: Arc<Global>) {
 
 	if let Some(p) = &global.config.activity_pub_private_key {
 		let private_key_path = p.clone();
-		spawn(loop_send_queue(stop_flag, global, private_key_path));
+		global.api.node.tasks().spawn(
+			"ActivityPub send queue",
+			loop_send_queue(stop_flag, global.clone(), private_key_path),
+		);
 	}
 }
 
@@ -947,30 +1013,21 @@ async fn object_post(
 	State(g): State<Arc<ServerGlobal>>, Path(object_id): Path<i64>, multipart: Multipart,
 ) -> Response {
 	// Load active identity and its private key
-	let identity = g
-		.base
-		.state
-		.lock()
-		.await
-		.active_identity
-		.as_ref()
-		.unwrap()
-		.1
-		.clone();
-	let private_key = match g.base.api.db.perform(|c| c.fetch_my_identity(&identity)) {
-		Ok(r) =>
-			if let Some((_, pk)) = r {
-				pk
-			} else {
-				return server_error_response2("unable to load identity");
-			},
-		Err(e) => return server_error_response(e, "unable to load identity"),
+	let (actor_address, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+
+	// Parse the request data before the transaction starts, because completed
+	// uploads are loaded from the database
+	let (message, attachment_datas, _) = match parse_post_message(&g.base, multipart).await {
+		Ok(r) => r,
+		Err(e) => return e,
 	};
 
 	// Load the AP object
 	// TODO: Remove .unwrap():
 	let tx = g.base.api.db.transaction().await.unwrap();
-	let actor_address = g.base.state.lock().await.active_identity.clone().unwrap().1;
 	let ap_object = match activity_pub_object::Entity::find_by_id(object_id)
 		.one(tx.inner())
 		.await
@@ -984,18 +1041,23 @@ async fn object_post(
 		Err(e) => return server_error_response(e, "Unable to load object"),
 	};
 
-	// Parse the request data, and pre-create the attachments so that we can deduce
-	// the URLs they will have
-	let (message, attachment_datas) = match parse_post_message(multipart).await {
-		Ok(r) => r,
-		Err(e) => return e,
-	};
-	let mut attachments = Vec::with_capacity(attachment_datas.len());
+	// Pre-create the attachments so that we can deduce the URLs they will have
+	let mut file_hashes = Vec::with_capacity(attachment_datas.len());
 	for attachment_data in &attachment_datas {
 		let (_, file_hash, _) = tx.create_file(&attachment_data).await.unwrap();
-		attachments.push((attachment_data.mime_type.as_str(), file_hash));
+		file_hashes.push(file_hash);
 	}
 	tx.commit().await.unwrap();
+	let attachments: Vec<_> = block_in_place(|| {
+		attachment_datas
+			.iter()
+			.zip(file_hashes)
+			.map(|(data, hash)| {
+				let metadata = media::extract_metadata(data.mime_type.as_str(), &data.data);
+				(data.mime_type.as_str(), hash, metadata)
+			})
+			.collect()
+	});
 
 	// Construct the Note object
 	let mut note = ActivityNoteObject::create_markdown_note(
@@ -1013,7 +1075,7 @@ async fn object_post(
 		.api
 		.publish_post(
 			&actor_address,
-			&private_key,
+			&*signer,
 			"application/activity+json",
 			&activity_object_json.to_string(),
 			Vec::new(),
@@ -1062,26 +1124,30 @@ pub async fn webfinger(
 		};
 
 		let webfinger = match &address {
-			Address::Actor(actor_address) => match g.base.api.db.connect_old() {
-				Err(e) => return server_error_response(e, "DB issue"),
-				Ok(c) => {
-					let result = match c.fetch_identity(actor_address) {
-						Err(e) => return server_error_response(e, "DB issue"),
-						Ok(r) => r,
-					};
+			Address::Actor(actor_address) => {
+				let actor_address = actor_address.clone();
+				let result = match g
+					.base
+					.api
+					.db
+					.perform(move |c| c.fetch_identity(&actor_address))
+					.await
+				{
+					Err(e) => return server_error_response(e, "DB issue"),
+					Ok(r) => r,
+				};
 
-					if result.is_some() {
-						WebFingerDocument::new(
-							&g.base.server_info.federation_domain,
-							&g.base.server_info.url_base,
-							"actor",
-							&address,
-						)
-					} else {
-						return not_found_error_response("actor doesn't exist");
-					}
+				if result.is_some() {
+					WebFingerDocument::new(
+						&g.base.server_info.federation_domain,
+						&g.base.server_info.url_base,
+						"actor",
+						&address,
+					)
+				} else {
+					return not_found_error_response("actor doesn't exist");
 				}
-			},
+			}
 			Address::Node(_) => WebFingerDocument::new(
 				&g.base.server_info.federation_domain,
 				&g.base.server_info.url_base,
@@ -1095,3 +1161,27 @@ pub async fn webfinger(
 		server_error_response2("Missing parameter \"resource\".")
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_wants_activity_json() {
+		let accepts = |value: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(header::ACCEPT, value.parse().unwrap());
+			wants_activity_json(&headers)
+		};
+		assert!(accepts("application/activity+json"));
+		assert!(accepts(
+			"application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\""
+		));
+		assert!(!accepts("application/ld+json"));
+		assert!(!accepts("text/html,application/xhtml+xml,*/*;q=0.8"));
+		assert!(!accepts("text/html, application/activity+json;q=0.5"));
+		assert!(accepts("text/html;q=0.5, application/activity+json"));
+		assert!(!wants_activity_json(&HeaderMap::new()));
+	}
+}
@@ -6,6 +6,7 @@ use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use axum::{
 	extract::{Path, Query, Request, State},
+	http::HeaderMap,
 	middleware::{from_fn_with_state, Next},
 	response::Response,
 	routing::get,
@@ -16,33 +17,69 @@ use serde::Deserialize;
 use tera::Context;
 
 use super::{
-	activity_pub, error_response, server_error_response, server_error_response2,
+	activity_pub, common::load_active_identity, error_response, follow, json_response,
+	parse_cursor, qr_code, server_error_response, server_error_response2,
 	translate_special_mime_types_for_objects, ActorAddress, Address, PaginationQuery, ServerGlobal,
+	FEED_PAGE_SIZE,
 };
 use crate::{
-	db::PersistenceHandle,
+	db::{self, block_list::BlockTarget, PersistenceHandle},
+	domain,
 	entity::*,
-	web::info::{find_profile_info, load_actor_feed, ObjectInfo},
+	naming,
+	web::{
+		identicon,
+		info::{find_profile_info, load_actor_feed_page, FeedPage},
+	},
 };
 
 
 #[derive(Deserialize)]
 struct ActorActions {
 	follow: Option<String>,
+	/// Only synchronizes the latest number of posts of the followed actor.
+	object_limit: Option<String>,
+	/// Only synchronizes the posts of the last number of days.
+	max_age: Option<String>,
+	/// Asks the actor to approve the active identity as a follower, and then
+	/// follows it.
+	request_follow: Option<String>,
+	/// Sets the petname of the actor, or removes it if empty.
+	petname: Option<String>,
+	/// Checks whether the domain vouches for the actor.
+	domain: Option<String>,
+	/// Mutes the actor for the active identity if "mute", blocks it if
+	/// "block", or else unmutes and unblocks it.
+	block: Option<String>,
+	idempotency_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QrCodeQuery {
+	/// Whether to encode a follow link instead of only the address.
+	follow: Option<bool>,
 }
 
 
 pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
 	let mut actor_methods = get(actor_get);
+	let mut router = Router::new();
 	if !g.base.server_info.is_exposed {
 		actor_methods = actor_methods.post(actor_post);
+		// Estimating the availability takes a number of lookups, so it isn't
+		// offered to the public.
+		router = router
+			.route("/:actor-address/availability", get(availability_get))
+			.route("/:actor-address/status", get(status_get));
 	}
 
-	Router::new()
+	router
 		.route("/:actor-address", actor_methods)
 		.route("/:actor-address/activity-pub", get(activity_pub::actor_get))
 		.nest("/:actor-address/activity-pub", activity_pub::actor_router(g.clone()))
 		.nest("/:actor-address/file", file::router(g.clone()))
+		.route("/:actor-address/identicon", get(identicon_get))
+		.route("/:actor-address/qr-code", get(qr_code_get))
 		// A workaround for Mastodon's behavior:
 		.nest("/:actor-address/activity-pub/file", file::router(g.clone()))
 		.nest("/:actor-address/object", object::router(g.clone()))
@@ -84,8 +121,14 @@ async fn actor_middleware(
 
 async fn actor_get(
 	State(g): State<Arc<ServerGlobal>>, Extension(address): Extension<ActorAddress>,
-	Query(query): Query<PaginationQuery>,
+	Extension(actor): Extension<actor::Model>, Query(query): Query<PaginationQuery>,
+	headers: HeaderMap,
 ) -> Response {
+	if activity_pub::wants_activity_json(&headers) {
+		let response = activity_pub::actor_get(State(g), Extension(address)).await;
+		return activity_pub::vary_on_accept(response);
+	}
+
 	let result = if g.base.server_info.is_exposed {
 		find_profile_info(&g.base.api.db, &g.base.server_info.url_base, &address).await
 	} else {
@@ -94,50 +137,125 @@ async fn actor_get(
 			.find_profile_info(&g.base.server_info.url_base, &address)
 			.await
 	};
-	let profile = match result {
+	let mut profile = match result {
 		Ok(p) => p,
 		Err(e) => return server_error_response(e, "Unable to fetch profile"),
 	};
-	let is_following: bool = match g.base.api.is_following(&address) {
+	if !g.base.server_info.is_exposed {
+		if let Some(profile) = &mut profile {
+			profile.actor.petname = match naming::find_petname(&g.base.api.db, &address).await {
+				Ok(p) => p,
+				Err(e) => return server_error_response(e, "Unable to load petname"),
+			};
+		}
+	}
+	let is_following: bool = match g.base.api.is_following(&address).await {
 		Ok(f) => f,
 		Err(e) => return server_error_response(e, "Unable to fetch follow status"),
 	};
+	let sync_depth = match g.base.api.db.load_sync_depth(&address).await {
+		Ok(d) => d.unwrap_or_default(),
+		Err(e) => return server_error_response(e, "Unable to load sync depth"),
+	};
+	let domains = match domain::load_verified_domains(&g.base.api.db, &address).await {
+		Ok(d) => d,
+		Err(e) => return server_error_response(e, "Unable to load verified domains"),
+	};
 	// TODO: Check if public key is available, if so, following is still possible.
+	let block = if g.base.server_info.is_exposed {
+		None
+	} else {
+		match load_block(&g, &address).await {
+			Ok(b) => b,
+			Err(e) => return server_error_response(e, "Unable to load block list"),
+		}
+	};
 
-	let p = query.page.unwrap_or(0);
-	let start = p * 5;
-	let mut objects: Vec<ObjectInfo> = match load_actor_feed(
-		&g.base.api.db,
-		&g.base.server_info.url_base,
-		&address,
-		5,
-		start,
-	)
-	.await
-	{
-		Ok(f) => f,
-		Err(e) => return server_error_response(e, "unable to fetch home feed"),
+	// The objects of private and followers-only actors can only be seen with a
+	// share link
+	let hide_objects = if g.base.server_info.is_exposed {
+		match is_private_actor(&g, actor.id).await {
+			Ok(p) => p,
+			Err(e) => return server_error_response(e, "Unable to load identity"),
+		}
+	} else {
+		false
+	};
+
+	let after = match parse_cursor(&query) {
+		Ok(c) => c,
+		Err(r) => return r,
+	};
+	let mut page = if hide_objects {
+		FeedPage {
+			objects: Vec::new(),
+			next: None,
+		}
+	} else {
+		match load_actor_feed_page(
+			&g.base.api.db,
+			&g.base.server_info.url_base,
+			&address,
+			FEED_PAGE_SIZE,
+			after,
+		)
+		.await
+		{
+			Ok(f) => f,
+			Err(e) => return server_error_response(e, "unable to fetch home feed"),
+		}
 	};
 
-	translate_special_mime_types_for_objects(&mut objects);
+	translate_special_mime_types_for_objects(&mut page.objects);
 
 	let mut context = Context::new();
 	context.insert("address", &address.to_string());
 	context.insert("profile", &profile);
 	context.insert("is_following", &is_following);
-	context.insert("page", &p);
-	context.insert("objects", &objects);
-	g.render("actor.html.tera", context).await
+	context.insert("sync_object_limit", &sync_depth.object_limit);
+	context.insert("sync_max_age", &sync_depth.max_age);
+	context.insert("domains", &domains);
+	context.insert("is_muted", &block.is_some());
+	context.insert("is_blocked", &block.map(|b| b.blocked).unwrap_or(false));
+	context.insert("objects", &page.objects);
+	context.insert("next_cursor", &page.next.map(|c| c.to_string()));
+	activity_pub::vary_on_accept(g.render("actor.html.tera", context).await)
 }
 
 async fn actor_post(
 	State(g): State<Arc<ServerGlobal>>, Extension(address): Extension<ActorAddress>,
-	Form(form_data): Form<ActorActions>,
+	Extension(actor): Extension<actor::Model>, Form(mut form_data): Form<ActorActions>,
 ) -> Response {
+	if form_data.request_follow.is_some() {
+		let (identity, signer) = match load_active_identity(&g.base).await {
+			Ok(r) => r,
+			Err(response) => return response,
+		};
+		match g.base.api.request_follow(&identity, &*signer, &address).await {
+			Ok(true) => form_data.follow = Some("1".to_string()),
+			Ok(false) =>
+				return error_response(503, "Unable to reach the node of this actor right now"),
+			Err(e) => return server_error_response(e, "Unable to ask to follow this actor"),
+		}
+	}
+
 	if let Some(follow) = &form_data.follow {
 		// Follow
 		if follow == "1" {
-			match g.base.api.follow(&address, true).await {
+			let sync_depth = match follow::parse_sync_depth(
+				form_data.object_limit.as_deref(),
+				form_data.max_age.as_deref(),
+			) {
+				Ok(d) => d,
+				Err(r) => return r,
+			};
+			let follow = g.base.api.follow(&address, true, sync_depth);
+			match g
+				.base
+				.api
+				.perform_idempotent(form_data.idempotency_key.as_deref(), "follow", follow)
+				.await
+			{
 				Ok(success) =>
 					if !success {
 						return server_error_response2(
@@ -155,14 +273,128 @@ async fn actor_post(
 		}
 	}
 
+	if let Some(name) = &form_data.petname {
+		if name.trim().is_empty() {
+			if let Err(e) = naming::clear_petname(&g.base.api.db, &address).await {
+				return server_error_response(e, "Unable to remove petname");
+			}
+		} else {
+			if let Err(message) = naming::check_petname(name) {
+				return error_response(400, message);
+			}
+			match naming::set_petname(&g.base.api.db, &address, name).await {
+				Ok(Some(_)) => {}
+				Ok(None) => return error_response(409, naming::PETNAME_TAKEN_MESSAGE),
+				Err(e) => return server_error_response(e, "Unable to set petname"),
+			}
+		}
+	}
+
+	if let Some(name) = &form_data.domain {
+		match g.base.api.verify_domain(&address, name).await {
+			Ok(Some(true)) => {}
+			Ok(Some(false)) =>
+				return error_response(400, "The domain doesn't vouch for this actor"),
+			Ok(None) => return error_response(404, "Unable to find the public key of this actor"),
+			Err(domain::Error::Database(e)) =>
+				return server_error_response(e, "Unable to store domain verification"),
+			Err(e) => return error_response(400, format!("Unable to verify domain: {}", e)),
+		}
+	}
+
+	if let Some(action) = &form_data.block {
+		let identity = match load_active_identity(&g.base).await {
+			Ok((identity, _)) => identity,
+			Err(response) => return response,
+		};
+		let target = BlockTarget::Actor(address.clone());
+		let result = match action.as_str() {
+			"mute" => g.base.api.block_actor(&identity, &target, false).await,
+			"block" => g.base.api.block_actor(&identity, &target, true).await,
+			_ => g.base.api.unblock_actor(&identity, &target).await,
+		};
+		if let Err(e) = result {
+			return server_error_response(e, "Unable to update block list");
+		}
+	}
+
 	actor_get(
 		State(g),
 		Extension(address),
+		Extension(actor),
 		Query(PaginationQuery::default()),
 	)
 	.await
 }
 
+/// Loads the entry of the actor in the block list of the active identity, if it
+/// has been muted or blocked.
+async fn load_block(
+	g: &ServerGlobal, address: &ActorAddress,
+) -> db::Result<Option<actor_block::Model>> {
+	let actor_id = match g.base.api.db.load_active_actor_id().await? {
+		Some(id) => id,
+		None => return Ok(None),
+	};
+	Ok(actor_block::Entity::find()
+		.filter(actor_block::Column::ActorId.eq(actor_id))
+		.filter(actor_block::Column::ActorAddress.eq(address))
+		.one(g.base.api.db.inner())
+		.await?)
+}
+
+/// Estimates how many nodes currently take part in the network of the actor.
+async fn availability_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(address): Extension<ActorAddress>,
+) -> Response {
+	let availability = g.base.api.estimate_actor_availability(&address).await;
+	json_response(&availability, None)
+}
+
+/// Shows the state of the actor network, to help find out why the feed isn't
+/// updating.
+async fn status_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(address): Extension<ActorAddress>,
+) -> Response {
+	match g.base.api.actor_network_status(&address).await {
+		Ok(status) => json_response(&status, None),
+		Err(e) => server_error_response(e, "Unable to load actor network status"),
+	}
+}
+
+/// Serves the identicon of the actor, which is used as its avatar when it hasn't
+/// set one itself.
+async fn identicon_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(address): Extension<ActorAddress>,
+) -> Response {
+	match identicon::load(&g.base.api.db, &address).await {
+		Ok(data) => Response::builder()
+			.header("Content-Type", "image/png")
+			// An identicon never changes for the same address
+			.header("Cache-Control", "public, max-age=31536000, immutable")
+			.body(data.into())
+			.unwrap(),
+		Err(e) => server_error_response(e, "Unable to load identicon"),
+	}
+}
+
+/// Serves a QR code of the address of the actor, or of a link to follow it.
+async fn qr_code_get(
+	Extension(address): Extension<ActorAddress>, Query(query): Query<QrCodeQuery>,
+) -> Response {
+	let data = if query.follow.unwrap_or(false) {
+		qr_code::follow_link(&address)
+	} else {
+		address.to_string()
+	};
+	Response::builder()
+		.header("Content-Type", "image/svg+xml")
+		// The QR code never changes for the same address
+		.header("Cache-Control", "public, max-age=31536000, immutable")
+		.body(qr_code::render_svg(&data).into())
+		.unwrap()
+}
+
 pub fn parse_actor_address(string: &str) -> Result<ActorAddress, Response> {
 	let address = match Address::from_str(string) {
 		Ok(a) => a,
@@ -174,3 +406,27 @@ pub fn parse_actor_address(string: &str) -> Result<ActorAddress, Response> {
 	};
 	Ok(actor_address)
 }
+
+/// Whether the actor is one of our own identities that has been marked as
+/// private, or an actor that only hands out its objects to its approved
+/// followers.
+pub(super) async fn is_private_actor(g: &ServerGlobal, actor_id: i64) -> db::Result<bool> {
+	let count = identity::Entity::find()
+		.filter(identity::Column::ActorId.eq(actor_id))
+		.filter(identity::Column::IsPrivate.eq(true))
+		.count(g.base.api.db.inner())
+		.await?;
+	if count > 0 {
+		return Ok(true);
+	}
+	g.base.api.db.is_followers_only(actor_id).await
+}
+
+/// Whether the actor is one of our own identities.
+pub(super) async fn is_own_actor(g: &ServerGlobal, actor_id: i64) -> db::Result<bool> {
+	let count = identity::Entity::find()
+		.filter(identity::Column::ActorId.eq(actor_id))
+		.count(g.base.api.db.inner())
+		.await?;
+	Ok(count > 0)
+}
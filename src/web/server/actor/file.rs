@@ -3,24 +3,23 @@ use std::{collections::HashMap, sync::Arc};
 use axum::{
 	body::Body,
 	extract::{Path, Request, State},
+	http::HeaderMap,
 	middleware::{from_fn_with_state, Next},
 	response::Response,
 	routing::get,
 	Extension, RequestExt, Router,
 };
 
-use crate::{
-	api::PossibleFileStream,
-	web::server::{
-		server_error_response, server_error_response2, ActorAddress, CompressionType, IdType,
-		ServerGlobal,
-	},
+use crate::web::server::{
+	file_stream_response, range_header, server_error_response, server_error_response2,
+	ActorAddress, IdType, ServerGlobal,
 };
 
 
 pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
 	Router::new()
 		.route("/:file-hash", get(file_get))
+		.route("/:file-hash/thumb", get(thumbnail_get))
 		.route_layer(from_fn_with_state(g, file_middleware))
 }
 
@@ -32,7 +31,7 @@ async fn file_middleware(mut request: Request, next: Next) -> Response {
 		.0;
 	let hash_str = params.get("file-hash").unwrap();
 
-	match IdType::from_base58(&hash_str) {
+	match IdType::parse(&hash_str) {
 		Ok(id) => request.extensions_mut().insert(id),
 		Err(e) => return server_error_response(e, "This is not a valid hash string"),
 	};
@@ -53,27 +52,40 @@ async fn file_middleware(mut request: Request, next: Next) -> Response {
 
 async fn file_get(
 	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
-	Extension(file_hash): Extension<IdType>,
+	Extension(file_hash): Extension<IdType>, headers: HeaderMap,
 ) -> Response {
-	match g.base.api.stream_file(actor_address, file_hash).await {
-		Ok(x) => match x {
-			/*PossibleFileStream::Full(FileData { mime_type, data }) => {
-				let body = Body::from(data);
-				(mime_type, body)
-			}*/
-			PossibleFileStream::Stream((mime_type, compression_type, loader)) => {
-				let body = Body::from_stream(loader);
-				let mut response = Response::builder().header("Content-Type", mime_type);
-				match compression_type {
-					CompressionType::None => {}
-					CompressionType::Brotli => {
-						response = response.header("Content-Encoding", "bz");
-					}
-				}
-				response.body(body).unwrap()
-			}
-			PossibleFileStream::None => server_error_response2("File doesn't exist"),
-		},
+	let range = range_header(&headers);
+	match g.base.api.stream_file(actor_address, file_hash, range).await {
+		Ok(stream) => file_stream_response(stream)
+			.unwrap_or_else(|| server_error_response2("File doesn't exist")),
 		Err(e) => server_error_response(e, "database issue"),
 	}
 }
+
+/// Serves the thumbnail of the image, or the image itself if it doesn't need
+/// one.
+async fn thumbnail_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(file_hash): Extension<IdType>, headers: HeaderMap,
+) -> Response {
+	match g
+		.base
+		.api
+		.load_file_thumbnail(&actor_address, &file_hash)
+		.await
+	{
+		Ok(Some((mime_type, data))) => Response::builder()
+			.header("Content-Type", mime_type)
+			.body(Body::from(data))
+			.unwrap(),
+		Ok(None) =>
+			file_get(
+				State(g),
+				Extension(actor_address),
+				Extension(file_hash),
+				headers,
+			)
+			.await,
+		Err(e) => server_error_response(e, "Unable to load thumbnail"),
+	}
+}
@@ -2,42 +2,76 @@ use std::{collections::HashMap, sync::Arc};
 
 use axum::{
 	body::Body,
-	extract::{Multipart, Path, Request, State},
+	extract::{Multipart, Path, Query, Request, State},
+	http::HeaderMap,
 	middleware::{from_fn_with_state, Next},
 	response::Response,
 	routing::{get, post},
-	Extension, RequestExt, Router,
+	Extension, Form, RequestExt, Router,
 };
 use sea_orm::prelude::*;
+use serde::Deserialize;
 use tera::Context;
 
+use super::{is_own_actor, is_private_actor};
 use crate::{
 	common::*,
 	core::*,
 	db::PersistenceHandle,
-	entity::object,
+	entity::{actor, object},
 	web::{
-		info::find_object_info,
+		info::{find_object_info, load_post_history, ObjectPayloadInfo},
 		server::{
-			activity_pub, not_found_error_response, post_message, server_error_response,
-			server_error_response2, translate_special_mime_types_for_object, ServerGlobal,
+			activity_pub, draft::save_new_draft, error_response, load_active_identity,
+			not_found_error_response, parse_post_message, post_message, render_preview,
+			server_error_response, translate_message, translate_special_mime_types_for_object,
+			IdempotentForm, PostQuery, ServerGlobal,
 		},
+		share_link::ShareToken,
 	},
+	util,
 };
 
 
+#[derive(Deserialize)]
+struct ObjectQuery {
+	/// A share token that gives access to an object of a private identity.
+	share: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReactionForm {
+	emoji: String,
+	idempotency_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShareLinkForm {
+	/// The number of days the link stays valid.
+	days: u32,
+}
+
+
 pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
 	let mut object_methods = get(object_get);
 	if !g.base.server_info.is_exposed {
 		object_methods = object_methods.post(object_post);
 	}
 
-	let mut router = Router::new().route("/:hash", object_methods).route(
-		"/:hash/activity-pub",
-		get(activity_pub::object_get_stonenet),
-	);
+	let mut router = Router::new()
+		.route("/:hash", object_methods)
+		.route("/:hash/activity-pub", get(activity_pub::object_get_stonenet))
+		.route("/:hash/history", get(object_history));
 	if !g.base.server_info.is_exposed {
-		router = router.route("/:object-hash/share", post(object_share));
+		router = router
+			.route("/:hash/bookmark", post(object_bookmark))
+			.route("/:hash/delete", post(object_delete))
+			.route("/:hash/edit", get(object_edit_get).post(object_edit_post))
+			.route("/:hash/react", post(object_react))
+			.route("/:object-hash/share", post(object_share))
+			.route("/:hash/share-link", post(object_share_link))
+			.route("/:hash/unbookmark", post(object_unbookmark))
+			.route("/:hash/vote", post(object_vote));
 	}
 
 	router.route_layer(from_fn_with_state(g, object_middleware))
@@ -53,7 +87,7 @@ async fn object_middleware(
 		.0;
 	let hash_str = params.get("hash").unwrap();
 
-	match IdType::from_base58(&hash_str) {
+	match IdType::parse(&hash_str) {
 		Ok(id) => request.extensions_mut().insert(id),
 		Err(e) => return server_error_response(e, "This is not a valid hash string"),
 	};
@@ -76,13 +110,61 @@ async fn object_middleware(
 
 async fn object_get(
 	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
-	Extension(object_hash): Extension<IdType>,
+	Extension(actor): Extension<actor::Model>, Extension(object_hash): Extension<IdType>,
+	Query(query): Query<ObjectQuery>, headers: HeaderMap,
+) -> Response {
+	let is_private = match is_private_actor(&g, actor.id).await {
+		Ok(p) => p,
+		Err(e) => return server_error_response(e, "Unable to load identity"),
+	};
+	// Objects of private identities are only shown to the public with a valid
+	// share link
+	if g.base.server_info.is_exposed && is_private {
+		// Share links are signed with whatever key the identity has now
+		let public_key = match g.base.api.db.load_current_public_key(actor.id).await {
+			Ok(Some(k)) => k,
+			Ok(None) => return not_found_error_response("Object not found"),
+			Err(e) => return server_error_response(e, "Unable to load public key"),
+		};
+		let has_access = query
+			.share
+			.as_deref()
+			.and_then(ShareToken::from_base58)
+			.map(|t| t.verify(&public_key, &actor_address, &object_hash))
+			.unwrap_or(false);
+		if !has_access {
+			return not_found_error_response("Object not found");
+		}
+	}
+	if activity_pub::wants_activity_json(&headers) {
+		let response = activity_pub::object_get_stonenet(State(g), Path(object_hash)).await;
+		return activity_pub::vary_on_accept(response);
+	}
+
+	let mut context = Context::new();
+	context.insert("can_share_link", &(!g.base.server_info.is_exposed && is_private));
+	if !g.base.server_info.is_exposed {
+		match g.base.api.db.is_bookmarked(&actor_address, &object_hash).await {
+			Ok(b) => context.insert("is_bookmarked", &b),
+			Err(e) => return server_error_response(e, "Unable to load bookmark"),
+		}
+		match is_own_actor(&g, actor.id).await {
+			Ok(o) => context.insert("is_own", &o),
+			Err(e) => return server_error_response(e, "Unable to load identity"),
+		}
+	}
+	let response = render_object(&g, &actor_address, &object_hash, context).await;
+	activity_pub::vary_on_accept(response)
+}
+
+async fn render_object(
+	g: &ServerGlobal, actor_address: &ActorAddress, object_hash: &IdType, mut context: Context,
 ) -> Response {
 	let mut object_info = match find_object_info(
 		&g.base.api.db,
 		&g.base.server_info.url_base,
-		&actor_address,
-		&object_hash,
+		actor_address,
+		object_hash,
 	)
 	.await
 	{
@@ -97,16 +179,207 @@ async fn object_get(
 
 	translate_special_mime_types_for_object(&mut object_info);
 
+	context.insert("address", actor_address);
+	context.insert("object", &object_info);
+	g.render("actor/object.html.tera", context).await
+}
+
+async fn object_bookmark(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>,
+) -> Response {
+	if let Err(e) = g
+		.base
+		.api
+		.bookmark_object(&actor_address, &object_hash)
+		.await
+	{
+		return server_error_response(e, "unable to bookmark object");
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", "/bookmark")
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Deletes one of the posts of our identities.
+async fn object_delete(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>, Form(form): Form<IdempotentForm>,
+) -> Response {
+	let address = actor_address.clone();
+	let result = g
+		.base
+		.api
+		.db
+		.perform(move |c| c.fetch_my_identity(&address))
+		.await;
+	let signer = match result {
+		Ok(Some((_, s))) => s,
+		Ok(None) =>
+			return error_response(403, "Only posts of your own identities can be deleted"),
+		Err(e) => return server_error_response(e, "unable to load identity"),
+	};
+
+	let publish = g
+		.base
+		.api
+		.publish_delete(&actor_address, &*signer, &object_hash);
+	match g
+		.base
+		.api
+		.perform_idempotent(form.idempotency_key.as_deref(), "publish_delete", publish)
+		.await
+	{
+		Ok(Some(_)) => {}
+		Ok(None) => return not_found_error_response("Post not found"),
+		Err(e) => return server_error_response(e, "unable to publish deletion"),
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/actor/{}", actor_address))
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Shows the form to edit one of the posts of our identities.
+async fn object_edit_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>,
+) -> Response {
+	let object_info = match find_object_info(
+		&g.base.api.db,
+		&g.base.server_info.url_base,
+		&actor_address,
+		&object_hash,
+	)
+	.await
+	{
+		Ok(Some(r)) => r,
+		Ok(None) => return not_found_error_response("Object not found"),
+		Err(e) => return server_error_response(e, "Unable to load object"),
+	};
+	let post = match &object_info.payload {
+		ObjectPayloadInfo::Post(p) => p,
+		_ => return error_response(400, "Only posts can be edited"),
+	};
+
 	let mut context = Context::new();
 	context.insert("address", &actor_address);
 	context.insert("object", &object_info);
-	g.render("actor/object.html.tera", context).await
+	context.insert("message", post.message.as_ref().map(|m| m.body.as_str()).unwrap_or(""));
+	g.render("actor/edit.html.tera", context).await
 }
 
-async fn object_post(
+/// Publishes a new version of the post. Only the identity that published the
+/// post can edit it.
+async fn object_edit_post(
 	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
 	Extension(object_hash): Extension<IdType>, multipart: Multipart,
 ) -> Response {
+	let (message, attachments, idempotency_key) =
+		match parse_post_message(&g.base, multipart).await {
+			Ok(r) => r,
+			Err(response) => return response,
+		};
+	let address = actor_address.clone();
+	let result = g
+		.base
+		.api
+		.db
+		.perform(move |c| c.fetch_my_identity(&address))
+		.await;
+	let signer = match result {
+		Ok(Some((_, s))) => s,
+		Ok(None) => return error_response(403, "Only posts of your own identities can be edited"),
+		Err(e) => return server_error_response(e, "unable to load identity"),
+	};
+
+	// Without new attachments, the post keeps the ones it has
+	let attachments = if attachments.len() > 0 {
+		Some(&attachments[..])
+	} else {
+		None
+	};
+	let publish = g.base.api.publish_edit(
+		&actor_address,
+		&*signer,
+		&object_hash,
+		"text/markdown",
+		&message,
+		attachments,
+	);
+	match g
+		.base
+		.api
+		.perform_idempotent(idempotency_key.as_deref(), "publish_edit", publish)
+		.await
+	{
+		Ok(Some(_)) => {}
+		Ok(None) => return not_found_error_response("Post not found"),
+		Err(e) => return server_error_response(e, "unable to publish edit"),
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/actor/{}/object/{}", actor_address, object_hash))
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Shows all versions of the post.
+async fn object_history(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(actor): Extension<actor::Model>, Extension(object_hash): Extension<IdType>,
+) -> Response {
+	if g.base.server_info.is_exposed {
+		match is_private_actor(&g, actor.id).await {
+			Ok(false) => {}
+			Ok(true) => return not_found_error_response("Object not found"),
+			Err(e) => return server_error_response(e, "Unable to load identity"),
+		}
+	}
+
+	let mut versions = match load_post_history(
+		&g.base.api.db,
+		&g.base.server_info.url_base,
+		&actor_address,
+		&object_hash,
+	)
+	.await
+	{
+		Ok(Some(r)) => r,
+		Ok(None) => return not_found_error_response("Post not found"),
+		Err(e) => return server_error_response(e, "Unable to load post history"),
+	};
+	for version in &mut versions {
+		if let Some(message) = &mut version.message {
+			translate_message(message);
+		}
+	}
+
+	let mut context = Context::new();
+	context.insert("address", &actor_address);
+	context.insert("object_hash", &object_hash);
+	context.insert("versions", &versions);
+	g.render("actor/history.html.tera", context).await
+}
+
+async fn object_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>, Query(query): Query<PostQuery>,
+	multipart: Multipart,
+) -> Response {
+	if query.preview.unwrap_or(false) {
+		return render_preview(&g, multipart, Some((actor_address, object_hash))).await;
+	}
+	if query.draft.unwrap_or(false) {
+		return save_new_draft(&g, multipart, Some((actor_address, object_hash))).await;
+	}
+
 	if let Err(e) = post_message(&g.base, multipart, Some((actor_address, object_hash))).await {
 		return e;
 	}
@@ -120,44 +393,174 @@ async fn object_post(
 
 async fn object_share(
 	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
-	Extension(object_hash): Extension<IdType>,
+	Extension(object_hash): Extension<IdType>, Form(form): Form<IdempotentForm>,
 ) -> Response {
-	let identity = g
+	let (identity, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+
+	let share = ShareObject {
+		actor_address,
+		object_hash,
+	};
+	let publish = g.base.api.publish_share(&identity, &*signer, &share);
+	if let Err(e) = g
 		.base
-		.state
-		.lock()
+		.api
+		.perform_idempotent(form.idempotency_key.as_deref(), "publish_share", publish)
 		.await
-		.active_identity
-		.as_ref()
+	{
+		return server_error_response(e, "unable to publish share");
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", "/")
+		.body(Body::empty())
 		.unwrap()
-		.1
-		.clone();
-	let private_key = match g.base.api.db.perform(|c| c.fetch_my_identity(&identity)) {
+}
+
+/// Reacts to the object with the active identity.
+async fn object_react(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>, Form(form): Form<ReactionForm>,
+) -> Response {
+	let (identity, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+
+	let publish = g.base.api.publish_reaction(
+		&identity,
+		&*signer,
+		&actor_address,
+		&object_hash,
+		&form.emoji,
+	);
+	// Reacting again with the same emoji is simply ignored
+	if let Err(e) = g
+		.base
+		.api
+		.perform_idempotent(form.idempotency_key.as_deref(), "publish_reaction", publish)
+		.await
+	{
+		return server_error_response(e, "unable to publish reaction");
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/actor/{}/object/{}", actor_address, object_hash))
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Votes in the poll. The form is taken as a list of pairs, because every
+/// chosen option is sent as a separate `choice` field.
+async fn object_vote(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>, Form(fields): Form<Vec<(String, String)>>,
+) -> Response {
+	let mut choices = Vec::new();
+	let mut idempotency_key = None;
+	for (name, value) in fields {
+		match name.as_str() {
+			"choice" => match value.parse::<u8>() {
+				Ok(c) => choices.push(c),
+				Err(_) => return error_response(400, "Invalid choice"),
+			},
+			"idempotency_key" => idempotency_key = Some(value),
+			_ => {}
+		}
+	}
+	choices.sort();
+
+	let (identity, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+	let publish = g
+		.base
+		.api
+		.publish_vote(&identity, &*signer, &actor_address, &object_hash, &choices);
+	match g
+		.base
+		.api
+		.perform_idempotent(idempotency_key.as_deref(), "publish_vote", publish)
+		.await
+	{
+		Ok(Some(_)) => {}
+		Ok(None) => return error_response(400, "The poll is closed, or the vote is invalid"),
+		Err(e) => return server_error_response(e, "unable to publish vote"),
+	}
+
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/actor/{}/object/{}", actor_address, object_hash))
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Mints a link through which the object of a private identity can be read on
+/// the exposed web interface, until it expires.
+async fn object_share_link(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>, Form(form): Form<ShareLinkForm>,
+) -> Response {
+	let address = actor_address.clone();
+	let result = g
+		.base
+		.api
+		.db
+		.perform(move |c| c.fetch_my_identity(&address))
+		.await;
+	let signer = match result {
 		Ok(r) =>
-			if let Some((_, pk)) = r {
-				pk
+			if let Some((_, s)) = r {
+				s
 			} else {
-				return server_error_response2("unable to load identity");
+				return error_response(403, "Only objects of your own identities can be shared");
 			},
 		Err(e) => return server_error_response(e, "unable to load identity"),
 	};
 
-	let share = ShareObject {
+	let minted = util::block_in_place(|| {
+		ShareToken::mint(&*signer, &actor_address, &object_hash, form.days)
+	});
+	let token = match minted {
+		Ok(t) => t,
+		Err(e) => return server_error_response(e, "unable to sign share link"),
+	};
+	let share_link = format!(
+		"{}/actor/{}/object/{}?share={}",
+		g.base.config.web_url_base.as_deref().unwrap_or(""),
 		actor_address,
 		object_hash,
-	};
+		token.to_base58()
+	);
+
+	let mut context = Context::new();
+	context.insert("can_share_link", &true);
+	context.insert("share_link", &share_link);
+	render_object(&g, &actor_address, &object_hash, context).await
+}
+
+async fn object_unbookmark(
+	State(g): State<Arc<ServerGlobal>>, Extension(actor_address): Extension<ActorAddress>,
+	Extension(object_hash): Extension<IdType>,
+) -> Response {
 	if let Err(e) = g
 		.base
 		.api
-		.publish_share(&identity, &private_key, &share)
+		.unbookmark_object(&actor_address, &object_hash)
 		.await
 	{
-		return server_error_response(e, "unable to publish share");
+		return server_error_response(e, "unable to remove bookmark");
 	}
 
 	Response::builder()
 		.status(303)
-		.header("Location", "/")
+		.header("Location", "/bookmark")
 		.body(Body::empty())
 		.unwrap()
 }
@@ -0,0 +1,196 @@
+//! The admin dashboard, which gives an overview of what the node is busy with
+//! and whether anything is going wrong, and where the local users are managed.
+
+use std::sync::Arc;
+
+use axum::{body::Body, extract::*, response::Response, routing::*};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+
+use super::{error_response, json_response, server_error_response, ServerGlobal};
+use crate::{
+	db::{backlog::QueueBacklogs, health::DatabaseStatus},
+	error_log::LoggedError,
+	net::{
+		actor::status::ActorNetworkStatus, bucket::RoutingTableSummary,
+		overlay::archiver::ArchiveStats, sstp::SessionInfo,
+	},
+};
+
+
+/// The number of recent errors that are shown.
+const ERROR_LIMIT: usize = 50;
+
+
+#[derive(Serialize)]
+struct Overview {
+	sessions: Vec<SessionInfo>,
+	routing_table: RoutingTableSummary,
+	actor_networks: Vec<ActorNetworkInfo>,
+	storage: StorageInfo,
+	backlogs: QueueBacklogs,
+	database: DatabaseStatus,
+	recent_errors: Vec<LoggedError>,
+}
+
+#[derive(Serialize)]
+struct ActorNetworkInfo {
+	actor_address: String,
+	status: ActorNetworkStatus,
+}
+
+#[derive(Serialize)]
+struct StorageInfo {
+	/// The number of bytes of block data that are stored.
+	used: u64,
+	/// The `max_storage` quota in bytes, if one is configured.
+	quota: Option<u64>,
+	/// Only set on archive nodes.
+	archive: Option<ArchiveStats>,
+}
+
+#[derive(Serialize)]
+struct UserData {
+	id: i64,
+	name: String,
+	is_admin: bool,
+	created: String,
+}
+
+#[derive(Deserialize)]
+struct UserFormData {
+	/// Either "create" or "delete".
+	action: String,
+	id: Option<i64>,
+	name: Option<String>,
+	password: Option<String>,
+	is_admin: Option<String>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index))
+		.route("/overview", get(overview))
+		.route("/users", get(users).post(users_post))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let overview = match load_overview(&g).await {
+		Ok(o) => o,
+		Err(response) => return response,
+	};
+
+	let mut context = Context::new();
+	context.insert("overview", &overview);
+	g.render("admin.html.tera", context).await
+}
+
+/// The same as the dashboard, in JSON.
+async fn overview(State(g): State<Arc<ServerGlobal>>) -> Response {
+	match load_overview(&g).await {
+		Ok(overview) => json_response(&overview, None),
+		Err(response) => response,
+	}
+}
+
+async fn load_overview(g: &ServerGlobal) -> Result<Overview, Response> {
+	let api = &g.base.api;
+	let actor_networks = match api.joined_actor_networks().await {
+		Ok(n) => n
+			.into_iter()
+			.map(|(address, status)| ActorNetworkInfo {
+				actor_address: address.to_string(),
+				status,
+			})
+			.collect(),
+		Err(e) => return Err(server_error_response(e, "Unable to load the actor networks")),
+	};
+	let used = match api.storage_usage().await {
+		Ok(u) => u,
+		Err(e) => return Err(server_error_response(e, "Unable to load the storage usage")),
+	};
+	let backlogs = match api.queue_backlogs().await {
+		Ok(b) => b,
+		Err(e) => return Err(server_error_response(e, "Unable to load the queue backlogs")),
+	};
+
+	Ok(Overview {
+		sessions: api.sessions().await,
+		routing_table: api.routing_table_summary().await,
+		actor_networks,
+		storage: StorageInfo {
+			used,
+			quota: g.base.config.max_storage.map(|q| q * 1024 * 1024),
+			archive: api.archive_stats(),
+		},
+		backlogs,
+		database: api.database_status(),
+		recent_errors: api.recent_errors(ERROR_LIMIT),
+	})
+}
+
+async fn users(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let users: Vec<_> = match g.base.api.db.load_local_users().await {
+		Ok(u) => u
+			.into_iter()
+			.map(|u| UserData {
+				id: u.id,
+				name: u.name,
+				is_admin: u.is_admin,
+				created: match DateTime::from_timestamp_millis(u.created) {
+					Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+					None => String::new(),
+				},
+			})
+			.collect(),
+		Err(e) => return server_error_response(e, "Unable to load the local users"),
+	};
+
+	let mut context = Context::new();
+	context.insert("users", &users);
+	g.render("admin/users.html.tera", context).await
+}
+
+async fn users_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<UserFormData>,
+) -> Response {
+	let db = &g.base.api.db;
+	match form.action.as_str() {
+		"create" => {
+			let name = form.name.as_deref().unwrap_or_default().trim();
+			let password = form.password.as_deref().unwrap_or_default();
+			if name.is_empty() || password.is_empty() {
+				return error_response(400, "A name and a password are required");
+			}
+			match db
+				.create_local_user(name, password, form.is_admin.is_some())
+				.await
+			{
+				Ok(Some(_)) => {}
+				Ok(None) => return error_response(400, "The name is taken already"),
+				Err(e) => return server_error_response(e, "Unable to create the local user"),
+			}
+		}
+		"delete" => {
+			let id = match form.id {
+				Some(i) => i,
+				None => return error_response(400, "No user given"),
+			};
+			if let Err(e) = db.delete_local_user(id).await {
+				return server_error_response(e, "Unable to delete the local user");
+			}
+		}
+		_ => return error_response(400, "Unknown action"),
+	}
+	Response::builder()
+		.status(303)
+		.header("Location", "/admin/users")
+		.body(Body::empty())
+		.unwrap()
+}
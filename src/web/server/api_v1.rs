@@ -0,0 +1,1017 @@
+//! A JSON API for other clients than the web interface, like mobile apps.
+//!
+//! It mirrors what `Api` offers. Errors are always given in the same form,
+//! an object with the status code and a message, so that clients can handle
+//! them all the same way. Like the web interface, a node that is exposed to
+//! the public only offers the endpoints that read public data.
+
+use std::{
+	fmt::{Debug, Display},
+	sync::Arc,
+};
+
+use axum::{
+	body::Body,
+	extract::{rejection::JsonRejection, Path, Query, State},
+	http::{HeaderMap, StatusCode},
+	response::{
+		sse::{self, KeepAlive, Sse},
+		IntoResponse, Response,
+	},
+	routing::{get, post, put},
+	Json, Router,
+};
+use base64::prelude::*;
+use futures::stream;
+use log::*;
+use sea_orm::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{
+	actor::is_private_actor, auth, file_stream_response, json_response, range_header,
+	translate_special_mime_types_for_object, translate_special_mime_types_for_objects,
+	ServerGlobal,
+};
+use crate::{
+	api::vanity::{VanityProgress, VanitySearch},
+	common::IdType,
+	core::{ActorAddress, Address, FileData},
+	db::{self, block_list::BlockTarget, health::DatabaseStatus, PersistenceHandle, SyncDepth},
+	entity::{actor, identity},
+	net::{load::LoadStats, sstp::RelayStats, stats::NetworkStats, MemoryLimits},
+	web::{
+		consolidated_feed::load_consolidated_feed,
+		info::{
+			find_object_info, find_profile_info, find_profile_info2, load_actor_feed,
+			ProfileObjectInfo,
+		},
+	},
+};
+
+
+/// The number of objects that are given at once, if not specified.
+const DEFAULT_LIMIT: u64 = 20;
+/// The most objects that can be asked for at once.
+const MAX_LIMIT: u64 = 100;
+
+
+#[derive(Serialize)]
+struct ErrorBody {
+	status: u16,
+	error: String,
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+	limit: Option<u64>,
+	offset: Option<u64>,
+}
+
+/// How much of the history of an actor is synchronized when following it. All
+/// of it, if neither is given.
+#[derive(Deserialize)]
+struct SyncDepthQuery {
+	/// Only the latest number of objects.
+	object_limit: Option<u64>,
+	/// Only the objects that were created within the last number of days.
+	max_age: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct MentionQuery {
+	/// What has been typed after the `@` so far.
+	prefix: String,
+	limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct NodeStatus {
+	version: &'static str,
+	node_address: String,
+	is_exposed: bool,
+	network: NetworkStats,
+	load: LoadStats,
+	relay: RelayStats,
+	database: DatabaseStatus,
+	memory: MemoryLimits,
+}
+
+#[derive(Serialize)]
+struct IdentityInfo {
+	label: String,
+	address: String,
+	is_active: bool,
+}
+
+#[derive(Deserialize)]
+struct NewIdentity {
+	label: String,
+	name: String,
+}
+
+#[derive(Deserialize)]
+struct ProfileUpdate {
+	/// The new label of the identity, if it is to be relabeled.
+	label: Option<String>,
+	name: String,
+	/// The description in markdown. The current one is kept if not given.
+	description: Option<String>,
+	/// The current avatar is kept if not given.
+	avatar: Option<NewAttachment>,
+	/// The current wallpaper is kept if not given.
+	wallpaper: Option<NewAttachment>,
+	/// The current setting is kept if not given.
+	followers_only: Option<bool>,
+	/// Whether the posts are only shown on an exposed web interface with a
+	/// share link. The current setting is kept if not given.
+	is_private: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ActiveIdentity {
+	label: String,
+}
+
+#[derive(Deserialize)]
+struct NewVanitySearch {
+	label: String,
+	name: String,
+	/// What the address has to start with, like `stna1qpxyz`.
+	prefix: String,
+}
+
+#[derive(Serialize)]
+struct VanitySearchInfo {
+	label: String,
+	prefix: String,
+	progress: VanityProgress,
+}
+
+#[derive(Serialize)]
+struct ActorInfo {
+	address: String,
+	profile: Option<ProfileObjectInfo>,
+	is_following: bool,
+}
+
+#[derive(Serialize)]
+struct FollowInfo {
+	address: String,
+	actor_type: String,
+}
+
+#[derive(Serialize)]
+struct BlockInfo {
+	/// The actor address or the URL of the ActivityPub actor.
+	target: String,
+	blocked: bool,
+}
+
+#[derive(Deserialize)]
+struct Block {
+	/// Whether to block the actor, instead of only muting it.
+	blocked: bool,
+}
+
+#[derive(Deserialize)]
+struct Login {
+	/// The name of a local user, or left out for the owner of the node.
+	name: Option<String>,
+	password: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+	token: String,
+}
+
+#[derive(Deserialize)]
+struct NewPost {
+	message: String,
+	#[serde(default)]
+	tags: Vec<String>,
+	#[serde(default)]
+	attachments: Vec<NewAttachment>,
+	in_reply_to: Option<ObjectReference>,
+}
+
+#[derive(Deserialize)]
+struct NewAttachment {
+	mime_type: String,
+	/// The content of the file, encoded in base64.
+	data: String,
+}
+
+#[derive(Deserialize)]
+struct ObjectReference {
+	actor_address: String,
+	hash: String,
+}
+
+#[derive(Serialize)]
+struct PublishedObject {
+	actor_address: String,
+	hash: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	let mut router = Router::new()
+		.route("/actors/:address", get(actor_get))
+		.route("/actors/:address/objects", get(actor_objects_get))
+		.route("/actors/:address/objects/:hash", get(object_get))
+		.route("/actors/:address/files/:hash", get(file_get))
+		.route("/feed", get(feed_get))
+		.route("/login", post(login_post))
+		.route("/tags/:tag", get(tag_get));
+	if !g.base.server_info.is_exposed {
+		router = router
+			.route("/blocks", get(blocks_get))
+			.route("/blocks/:target", put(block_put).delete(block_delete))
+			.route("/events", get(events_get))
+			.route("/follows", get(follows_get))
+			.route("/follows/:address", put(follow_put).delete(follow_delete))
+			.route("/identities", get(identities_get).post(identities_post))
+			.route("/identities/active", put(active_identity_put))
+			.route("/identities/:label/profile", put(profile_put))
+			.route("/mentions", get(mentions_get))
+			.route("/node", get(node_get))
+			.route("/objects", post(objects_post))
+			.route("/vanity-searches", post(vanity_searches_post))
+			.route(
+				"/vanity-searches/:label",
+				get(vanity_search_get).delete(vanity_search_delete),
+			);
+	}
+	router.fallback(|| async { api_error(404, "Unknown endpoint") })
+}
+
+pub(super) fn api_error(status: u16, message: impl Into<String>) -> Response {
+	let body = ErrorBody {
+		status,
+		error: message.into(),
+	};
+	let mut response = json_response(&body, None);
+	*response.status_mut() = StatusCode::from_u16(status).unwrap();
+	response
+}
+
+fn api_server_error<E>(e: E, message: &str) -> Response
+where
+	E: Debug + Display,
+{
+	error!("{}: {:?}", message, e);
+	api_error(500, format!("{}: {}", message, e))
+}
+
+async fn load_active_address(g: &ServerGlobal) -> Result<ActorAddress, Response> {
+	match g.base.api.active_identity().await {
+		Ok(Some((_, address))) => Ok(address),
+		Ok(None) => Err(api_error(400, "Create an identity first")),
+		Err(e) => Err(api_server_error(e, "Unable to load active identity")),
+	}
+}
+
+fn parse_actor_address(string: &str) -> Result<ActorAddress, Response> {
+	match string.parse::<Address>() {
+		Ok(Address::Actor(a)) => Ok(a),
+		Ok(_) => Err(api_error(400, "Not an actor address")),
+		Err(e) => Err(api_error(400, format!("Invalid address: {}", e))),
+	}
+}
+
+fn decode_attachment(attachment: NewAttachment) -> Result<FileData, Response> {
+	match BASE64_STANDARD.decode(&attachment.data) {
+		Ok(data) if data.len() > 0 => Ok(FileData {
+			mime_type: attachment.mime_type.into(),
+			data,
+		}),
+		Ok(_) => Err(api_error(400, "Attachments can not be empty")),
+		Err(e) => Err(api_error(400, format!("Invalid attachment data: {}", e))),
+	}
+}
+
+fn parse_hash(string: &str) -> Result<IdType, Response> {
+	IdType::parse(string).map_err(|e| api_error(400, format!("Invalid hash: {}", e)))
+}
+
+/// The key given in the `Idempotency-Key` header, with which a retried request
+/// isn't performed twice.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, Response> {
+	match headers.get("Idempotency-Key") {
+		None => Ok(None),
+		Some(value) => match value.to_str() {
+			Ok(key) => Ok(Some(key.to_string())),
+			Err(_) => Err(api_error(400, "Invalid idempotency key")),
+		},
+	}
+}
+
+fn page(query: &PageQuery) -> (u64, u64) {
+	(
+		query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+		query.offset.unwrap_or(0),
+	)
+}
+
+/// Whether the objects of the actor are hidden from the public. Gives a 404
+/// response if the actor is unknown.
+async fn is_hidden_actor(g: &ServerGlobal, address: &ActorAddress) -> Result<bool, Response> {
+	let actor = match actor::Entity::find()
+		.filter(actor::Column::Address.eq(address))
+		.one(g.base.api.db.inner())
+		.await
+	{
+		Ok(Some(a)) => a,
+		Ok(None) => return Err(api_error(404, "Unknown actor")),
+		Err(e) => return Err(api_server_error(e, "Unable to load actor")),
+	};
+	if !g.base.server_info.is_exposed {
+		return Ok(false);
+	}
+	is_private_actor(g, actor.id)
+		.await
+		.map_err(|e| api_server_error(e, "Unable to load identity"))
+}
+
+async fn actor_get(State(g): State<Arc<ServerGlobal>>, Path(address): Path<String>) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	let url_base = &g.base.server_info.url_base;
+	let result = if g.base.server_info.is_exposed {
+		find_profile_info(&g.base.api.db, url_base, &address).await
+	} else {
+		g.base.api.find_profile_info(url_base, &address).await
+	};
+	let profile = match result {
+		Ok(p) => p,
+		Err(e) => return api_server_error(e, "Unable to load profile"),
+	};
+	let is_following = match g.base.api.is_following(&address).await {
+		Ok(f) => f,
+		Err(e) => return api_server_error(e, "Unable to load follow status"),
+	};
+
+	json_response(
+		&ActorInfo {
+			address: address.to_string(),
+			profile,
+			is_following,
+		},
+		None,
+	)
+}
+
+async fn actor_objects_get(
+	State(g): State<Arc<ServerGlobal>>, Path(address): Path<String>,
+	Query(query): Query<PageQuery>,
+) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	match is_hidden_actor(&g, &address).await {
+		Ok(true) => return json_response(&Vec::<()>::new(), None),
+		Ok(false) => {}
+		Err(r) => return r,
+	}
+
+	let (limit, offset) = page(&query);
+	let url_base = &g.base.server_info.url_base;
+	match load_actor_feed(&g.base.api.db, url_base, &address, limit, offset).await {
+		Ok(mut objects) => {
+			translate_special_mime_types_for_objects(&mut objects);
+			json_response(&objects, None)
+		}
+		Err(e) => api_server_error(e, "Unable to load objects"),
+	}
+}
+
+async fn object_get(
+	State(g): State<Arc<ServerGlobal>>, Path((address, hash)): Path<(String, String)>,
+) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	let hash = match parse_hash(&hash) {
+		Ok(h) => h,
+		Err(r) => return r,
+	};
+	match is_hidden_actor(&g, &address).await {
+		Ok(true) => return api_error(404, "Object not found"),
+		Ok(false) => {}
+		Err(r) => return r,
+	}
+
+	let url_base = &g.base.server_info.url_base;
+	match find_object_info(&g.base.api.db, url_base, &address, &hash).await {
+		Ok(Some(mut object)) => {
+			translate_special_mime_types_for_object(&mut object);
+			json_response(&object, None)
+		}
+		Ok(None) => api_error(404, "Object not found"),
+		Err(e) => api_server_error(e, "Unable to load object"),
+	}
+}
+
+/// Serves the file itself, rather than JSON. A `Range` header can be given to
+/// load only a part of it.
+async fn file_get(
+	State(g): State<Arc<ServerGlobal>>, Path((address, hash)): Path<(String, String)>,
+	headers: HeaderMap,
+) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	let hash = match parse_hash(&hash) {
+		Ok(h) => h,
+		Err(r) => return r,
+	};
+	match is_hidden_actor(&g, &address).await {
+		Ok(true) => return api_error(404, "File not found"),
+		Ok(false) => {}
+		Err(r) => return r,
+	}
+
+	match g.base.api.stream_file(address, hash, range_header(&headers)).await {
+		Ok(stream) =>
+			file_stream_response(stream).unwrap_or_else(|| api_error(404, "File not found")),
+		Err(e) => api_server_error(e, "Unable to load file"),
+	}
+}
+
+/// The home feed, which is the consolidated feed on a private node.
+async fn feed_get(State(g): State<Arc<ServerGlobal>>, Query(query): Query<PageQuery>) -> Response {
+	let (limit, offset) = page(&query);
+	let result = if g.base.server_info.is_exposed {
+		g.base.api.load_home_feed(limit, offset).await
+	} else {
+		if offset == 0 {
+			if let Err(e) = g.base.api.update_consolidated_feed().await {
+				return api_server_error(e, "Unable to update feed");
+			}
+		}
+		let url_base = &g.base.server_info.url_base;
+		load_consolidated_feed(&g.base.api.db, url_base, limit, offset).await
+	};
+	match result {
+		Ok(mut objects) => {
+			translate_special_mime_types_for_objects(&mut objects);
+			json_response(&objects, None)
+		}
+		Err(e) => api_server_error(e, "Unable to load feed"),
+	}
+}
+
+/// Lists the posts with the tag, the newest first.
+async fn tag_get(
+	State(g): State<Arc<ServerGlobal>>, Path(tag): Path<String>, Query(query): Query<PageQuery>,
+) -> Response {
+	let (limit, offset) = page(&query);
+	let include_private = !g.base.server_info.is_exposed;
+	let url_base = &g.base.server_info.url_base;
+	match g
+		.base
+		.api
+		.load_tagged_posts(&tag, include_private, url_base, limit, offset)
+		.await
+	{
+		Ok(mut objects) => {
+			translate_special_mime_types_for_objects(&mut objects);
+			json_response(&objects, None)
+		}
+		Err(e) => api_server_error(e, "Unable to load tagged posts"),
+	}
+}
+
+/// Starts a session, of which the token is to be given as a bearer token.
+async fn login_post(
+	State(g): State<Arc<ServerGlobal>>, body: Result<Json<Login>, JsonRejection>,
+) -> Response {
+	let Json(login) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	match auth::log_in(&g, login.name.as_deref(), &login.password).await {
+		Ok(Some(token)) => json_response(&Session { token }, None),
+		Ok(None) => api_error(401, "The name or password is wrong"),
+		Err(e) => api_server_error(e, "Unable to verify the password"),
+	}
+}
+
+/// Lists the actors that the active identity has muted or blocked.
+async fn blocks_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let identity = match load_active_address(&g).await {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	match g.base.api.load_actor_blocks(&identity).await {
+		Ok(blocks) => json_response(
+			&blocks
+				.iter()
+				.filter_map(|b| {
+					BlockTarget::from_model(b).map(|target| BlockInfo {
+						target: target.to_string(),
+						blocked: b.blocked,
+					})
+				})
+				.collect::<Vec<_>>(),
+			None,
+		),
+		Err(e) => api_server_error(e, "Unable to load block list"),
+	}
+}
+
+/// Mutes or blocks the actor, which is either an actor address or the URL of
+/// an ActivityPub actor, for the active identity.
+async fn block_put(
+	State(g): State<Arc<ServerGlobal>>, Path(target): Path<String>,
+	body: Result<Json<Block>, JsonRejection>,
+) -> Response {
+	let Json(block) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	let target = match target.parse::<BlockTarget>() {
+		Ok(t) => t,
+		Err(message) => return api_error(400, message),
+	};
+	let identity = match load_active_address(&g).await {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	match g.base.api.block_actor(&identity, &target, block.blocked).await {
+		Ok(_) => Response::builder().status(204).body(Body::empty()).unwrap(),
+		Err(e) => api_server_error(e, "Unable to update block list"),
+	}
+}
+
+async fn block_delete(
+	State(g): State<Arc<ServerGlobal>>, Path(target): Path<String>,
+) -> Response {
+	let target = match target.parse::<BlockTarget>() {
+		Ok(t) => t,
+		Err(message) => return api_error(400, message),
+	};
+	let identity = match load_active_address(&g).await {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	match g.base.api.unblock_actor(&identity, &target).await {
+		Ok(true) => Response::builder().status(204).body(Body::empty()).unwrap(),
+		Ok(false) => api_error(404, "This actor isn't muted or blocked"),
+		Err(e) => api_server_error(e, "Unable to update block list"),
+	}
+}
+
+/// Streams the events of the node as server-sent events, with each event
+/// given as JSON.
+async fn events_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let receiver = g.base.api.subscribe_events();
+	let events = stream::unfold(receiver, |mut receiver| async move {
+		loop {
+			match receiver.recv().await {
+				Ok(event) => {
+					let sse_event = sse::Event::default().json_data(&event);
+					return Some((sse_event, receiver));
+				}
+				// Missing a few events is not a problem for a user interface
+				Err(RecvError::Lagged(_)) => {}
+				Err(RecvError::Closed) => return None,
+			}
+		}
+	});
+	Sse::new(events)
+		.keep_alive(KeepAlive::default())
+		.into_response()
+}
+
+async fn follows_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	match g.base.api.fetch_follow_list().await {
+		Ok(list) => json_response(
+			&list
+				.into_iter()
+				.map(|(address, info)| FollowInfo {
+					address: address.to_string(),
+					actor_type: info.actor_type.to_string(),
+				})
+				.collect::<Vec<_>>(),
+			None,
+		),
+		Err(e) => api_server_error(e, "Unable to load follow list"),
+	}
+}
+
+/// Suggests the actors to mention, for autocompletion.
+async fn mentions_get(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<MentionQuery>,
+) -> Response {
+	let limit = query.limit.unwrap_or(10).min(MAX_LIMIT) as usize;
+	match g.base.api.suggest_mentions(&query.prefix, limit).await {
+		Ok(suggestions) => json_response(&suggestions, None),
+		Err(e) => api_server_error(e, "Unable to load mention suggestions"),
+	}
+}
+
+/// Follows the actor, optionally synchronizing only its recent history. An
+/// `Idempotency-Key` header can be given so that a retried request doesn't
+/// follow it twice.
+async fn follow_put(
+	State(g): State<Arc<ServerGlobal>>, Path(address): Path<String>,
+	Query(query): Query<SyncDepthQuery>, headers: HeaderMap,
+) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	let key = match idempotency_key(&headers) {
+		Ok(k) => k,
+		Err(r) => return r,
+	};
+	let sync_depth = SyncDepth {
+		object_limit: query.object_limit,
+		max_age: query.max_age,
+	};
+	let follow = g.base.api.follow(&address, true, sync_depth);
+	match g
+		.base
+		.api
+		.perform_idempotent(key.as_deref(), "follow", follow)
+		.await
+	{
+		Ok(true) => Response::builder().status(204).body(Body::empty()).unwrap(),
+		Ok(false) => api_error(404, "Unable to find the public key of this actor"),
+		Err(db::Error::InvalidIdempotencyKey) => api_error(400, "Invalid idempotency key"),
+		Err(e) => api_server_error(e, "Unable to follow actor"),
+	}
+}
+
+async fn follow_delete(
+	State(g): State<Arc<ServerGlobal>>, Path(address): Path<String>,
+) -> Response {
+	let address = match parse_actor_address(&address) {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	match g.base.api.unfollow(&address).await {
+		Ok(true) => Response::builder().status(204).body(Body::empty()).unwrap(),
+		Ok(false) => api_error(404, "Not following this actor"),
+		Err(e) => api_server_error(e, "Unable to unfollow actor"),
+	}
+}
+
+async fn identities_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let active = match g.base.api.active_identity().await {
+		Ok(a) => a.map(|(label, _)| label),
+		Err(e) => return api_server_error(e, "Unable to load active identity"),
+	};
+	match g.base.api.fetch_my_identities().await {
+		Ok(identities) => json_response(
+			&identities
+				.into_iter()
+				.map(|(label, address, ..)| IdentityInfo {
+					is_active: active.as_ref() == Some(&label),
+					label,
+					address: address.to_string(),
+				})
+				.collect::<Vec<_>>(),
+			None,
+		),
+		Err(e) => api_server_error(e, "Unable to load identities"),
+	}
+}
+
+async fn identities_post(
+	State(g): State<Arc<ServerGlobal>>, body: Result<Json<NewIdentity>, JsonRejection>,
+) -> Response {
+	let Json(new) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	if new.label.trim().is_empty() {
+		return api_error(400, "The label can not be empty");
+	}
+
+	match g
+		.base
+		.api
+		.create_identity(&new.label, &new.name, None, None, None)
+		.await
+	{
+		Ok((address, _)) => {
+			if let Err(e) = g.reload_identities().await {
+				return api_server_error(e, "Unable to load identities");
+			}
+			json_response(
+				&IdentityInfo {
+					label: new.label,
+					address: address.to_string(),
+					is_active: false,
+				},
+				None,
+			)
+		}
+		Err(e) => api_server_error(e, "Unable to create identity"),
+	}
+}
+
+async fn active_identity_put(
+	State(g): State<Arc<ServerGlobal>>, body: Result<Json<ActiveIdentity>, JsonRejection>,
+) -> Response {
+	let Json(active) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	match g.base.api.select_identity(&active.label).await {
+		Ok(Some(address)) => {
+			if let Err(e) = g.reload_identities().await {
+				return api_server_error(e, "Unable to load identities");
+			}
+			json_response(
+				&IdentityInfo {
+					label: active.label,
+					address: address.to_string(),
+					is_active: true,
+				},
+				None,
+			)
+		}
+		Ok(None) => api_error(404, "Unknown identity"),
+		Err(e) => api_server_error(e, "Unable to select identity"),
+	}
+}
+
+/// Starts searching for an address that starts with the given prefix, and
+/// creates the identity once one has been found. The search runs in the
+/// background, its progress can be followed at `/vanity-searches/:label`.
+async fn vanity_searches_post(
+	State(g): State<Arc<ServerGlobal>>, body: Result<Json<NewVanitySearch>, JsonRejection>,
+) -> Response {
+	let Json(new) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	if new.label.trim().is_empty() {
+		return api_error(400, "The label can not be empty");
+	}
+	let search = match VanitySearch::new(&new.prefix) {
+		Ok(s) => Arc::new(s),
+		Err(e) => return api_error(400, e.to_string()),
+	};
+
+	let mut searches = g.vanity_searches.lock().await;
+	if let Some(running) = searches.get(&new.label) {
+		if !running.is_finished() {
+			return api_error(409, "A search for this label is running already");
+		}
+	}
+	searches.insert(new.label.clone(), search.clone());
+	drop(searches);
+
+	let info = VanitySearchInfo {
+		label: new.label.clone(),
+		prefix: search.prefix().to_string(),
+		progress: search.progress(),
+	};
+	let g2 = g.clone();
+	g.base.api.node.tasks().spawn("vanity address search", async move {
+		match g2
+			.base
+			.api
+			.create_vanity_identity(&new.label, &new.name, &search)
+			.await
+		{
+			Ok(Some(_)) =>
+				if let Err(e) = g2.reload_identities().await {
+					error!("Unable to load identities: {:?}", e);
+				},
+			Ok(None) => {}
+			Err(e) => {
+				error!("Unable to create vanity identity: {:?}", e);
+				search.cancel();
+			}
+		}
+	});
+	json_response(&info, None)
+}
+
+async fn vanity_search_get(
+	State(g): State<Arc<ServerGlobal>>, Path(label): Path<String>,
+) -> Response {
+	match g.vanity_searches.lock().await.get(&label) {
+		Some(search) => json_response(
+			&VanitySearchInfo {
+				label,
+				prefix: search.prefix().to_string(),
+				progress: search.progress(),
+			},
+			None,
+		),
+		None => api_error(404, "No search for this label"),
+	}
+}
+
+/// Cancels the search if it is still running, and forgets about it.
+async fn vanity_search_delete(
+	State(g): State<Arc<ServerGlobal>>, Path(label): Path<String>,
+) -> Response {
+	match g.vanity_searches.lock().await.remove(&label) {
+		Some(search) => {
+			search.cancel();
+			Response::builder().status(204).body(Body::empty()).unwrap()
+		}
+		None => api_error(404, "No search for this label"),
+	}
+}
+
+/// Publishes a new profile for the identity with the given label.
+async fn profile_put(
+	State(g): State<Arc<ServerGlobal>>, Path(label): Path<String>,
+	body: Result<Json<ProfileUpdate>, JsonRejection>,
+) -> Response {
+	let Json(update) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	if update.name.trim().is_empty() {
+		return api_error(400, "The display name can not be empty");
+	}
+	let new_label = update.label.unwrap_or_else(|| label.clone());
+	if new_label.trim().is_empty() {
+		return api_error(400, "The label can not be empty");
+	}
+	let avatar = match update.avatar.map(decode_attachment).transpose() {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+	let wallpaper = match update.wallpaper.map(decode_attachment).transpose() {
+		Ok(w) => w,
+		Err(r) => return r,
+	};
+	let description = update.description.map(|d| FileData {
+		mime_type: "text/markdown".into(),
+		data: d.into_bytes(),
+	});
+
+	let identity = match identity::Entity::find_by_id(label.clone())
+		.one(g.base.api.db.inner())
+		.await
+	{
+		Ok(Some(i)) => i,
+		Ok(None) => return api_error(404, "Unknown identity"),
+		Err(e) => return api_server_error(e, "Unable to load identity"),
+	};
+	let followers_only = match update.followers_only {
+		Some(f) => f,
+		None => match find_profile_info2(
+			&g.base.api.db,
+			&g.base.server_info.url_base,
+			identity.actor_id,
+		)
+		.await
+		{
+			Ok(profile) => profile.map(|p| p.followers_only).unwrap_or(false),
+			Err(e) => return api_server_error(e, "Unable to load profile"),
+		},
+	};
+	let signer = match g.base.api.db.keyring().load_signer(&identity.private_key) {
+		Ok(s) => s,
+		Err(e) => return api_server_error(e, "Unable to load private key"),
+	};
+
+	let result = g
+		.base
+		.api
+		.update_profile(
+			&*signer,
+			identity.actor_id,
+			&label,
+			&new_label,
+			&update.name,
+			avatar,
+			wallpaper,
+			description,
+			followers_only,
+		)
+		.await;
+	let hash = match result {
+		Ok(h) => h,
+		Err(e) => return api_server_error(e, "Unable to update profile"),
+	};
+	if let Some(is_private) = update.is_private {
+		if let Err(e) = g
+			.base
+			.api
+			.db
+			.set_identity_private(identity.actor_id, is_private)
+			.await
+		{
+			return api_server_error(e, "Unable to update identity");
+		}
+	}
+	if let Err(e) = g.reload_identities().await {
+		return api_server_error(e, "Unable to load identities");
+	}
+	let address = match actor::Entity::find_by_id(identity.actor_id)
+		.one(g.base.api.db.inner())
+		.await
+	{
+		Ok(Some(a)) => a.address,
+		Ok(None) => return api_error(404, "Unknown identity"),
+		Err(e) => return api_server_error(e, "Unable to load identity"),
+	};
+	json_response(
+		&PublishedObject {
+			actor_address: address.to_string(),
+			hash: hash.to_string(),
+		},
+		None,
+	)
+}
+
+async fn node_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let api = &g.base.api;
+	json_response(
+		&NodeStatus {
+			version: env!("CARGO_PKG_VERSION"),
+			node_address: api.node.node_id().to_string(),
+			is_exposed: g.base.server_info.is_exposed,
+			network: api.network_stats(),
+			load: api.load_stats(),
+			relay: api.relay_stats().await,
+			database: api.database_status(),
+			memory: api.memory_limits().await,
+		},
+		None,
+	)
+}
+
+/// Publishes a post with the active identity. An `Idempotency-Key` header can
+/// be given so that a retried request doesn't publish the post twice.
+async fn objects_post(
+	State(g): State<Arc<ServerGlobal>>, headers: HeaderMap,
+	body: Result<Json<NewPost>, JsonRejection>,
+) -> Response {
+	let Json(post) = match body {
+		Ok(b) => b,
+		Err(e) => return api_error(400, e.body_text()),
+	};
+	let key = match idempotency_key(&headers) {
+		Ok(k) => k,
+		Err(r) => return r,
+	};
+	let mut attachments = Vec::with_capacity(post.attachments.len());
+	for attachment in post.attachments {
+		match decode_attachment(attachment) {
+			Ok(file) => attachments.push(file),
+			Err(r) => return r,
+		}
+	}
+	let in_reply_to = match post.in_reply_to {
+		Some(r) => match (parse_actor_address(&r.actor_address), parse_hash(&r.hash)) {
+			(Ok(address), Ok(hash)) => Some((address, hash)),
+			(Err(r), _) | (_, Err(r)) => return r,
+		},
+		None => None,
+	};
+
+	let (identity, signer) = match g.base.api.load_active_identity_key().await {
+		Ok(Some(r)) => r,
+		Ok(None) => return api_error(400, "Create an identity first"),
+		Err(e) => return api_server_error(e, "Unable to load identity"),
+	};
+	let publish = g.base.api.publish_post(
+		&identity,
+		&*signer,
+		"text/markdown",
+		&post.message,
+		post.tags,
+		&attachments,
+		in_reply_to,
+	);
+	match g
+		.base
+		.api
+		.perform_idempotent(key.as_deref(), "publish_post", publish)
+		.await
+	{
+		Ok(hash) => {
+			let mut response = json_response(
+				&PublishedObject {
+					actor_address: identity.to_string(),
+					hash: hash.to_string(),
+				},
+				None,
+			);
+			*response.status_mut() = StatusCode::CREATED;
+			response
+		}
+		Err(db::Error::InvalidIdempotencyKey) => api_error(400, "Invalid idempotency key"),
+		Err(e) => api_server_error(e, "Unable to publish post"),
+	}
+}
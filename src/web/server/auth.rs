@@ -0,0 +1,343 @@
+//! Login for the user interface and the web interface.
+//!
+//! If a password is configured, everything on the user interface requires a
+//! session, except for the login page and the static assets. The web interface
+//! is the public face of the node, so only what changes something requires a
+//! session there. A session is started by logging in with the password.
+//! Browsers keep the session in a cookie, other clients can send it along as a
+//! bearer token. Sessions are only kept in memory, so everybody has to log in
+//! again after the node has restarted.
+//!
+//! Local users log in with their name and their own password, and everything
+//! they do is done within their scope. Without a configured password, anyone
+//! can still act as the owner of the node, so one should be configured to keep
+//! the users apart.
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{self, Read},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use argon2::{
+	password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+	Argon2,
+};
+use axum::{
+	body::Body,
+	extract::{Request, State},
+	http::{header, HeaderMap, Method},
+	middleware::Next,
+	response::Response,
+	routing::*,
+	Form,
+};
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use tera::Context;
+
+use super::{
+	api_v1::api_error,
+	common::*,
+	csrf::{cookie, cookie_value},
+	ServerGlobal,
+};
+use crate::{
+	common::IdType,
+	config::Config,
+	db::{self, local_user},
+	util,
+};
+
+
+/// The paths that can be used without a session, on both interfaces.
+const PUBLIC_PATHS: &[&str] = &["/login", "/api/v1/login", "/language"];
+const SESSION_COOKIE: &str = "stonenet_session";
+/// The number of minutes a session lasts without being used, by default.
+const SESSION_TIMEOUT_DEFAULT: u64 = 7 * 24 * 60;
+
+
+pub struct Auth {
+	/// The Argon2 hash of the password, if one is configured.
+	password_hash: Option<String>,
+	sessions: Mutex<HashMap<String, Session>>,
+	session_timeout: Duration,
+}
+
+#[derive(Clone, Copy)]
+struct Session {
+	last_used: Instant,
+	/// The local user that is logged in, or `None` for the owner of the node.
+	user_id: Option<i64>,
+	is_admin: bool,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+	/// Left out or empty for the owner of the node.
+	name: Option<String>,
+	password: String,
+}
+
+
+impl Auth {
+	/// Reads the password from the file set in the config, and only keeps a
+	/// hash of it.
+	pub fn from_config(config: &Config) -> io::Result<Self> {
+		let password_hash = match &config.interface_password_file {
+			None => None,
+			Some(path) => {
+				let mut content = String::new();
+				File::open(path)?.read_to_string(&mut content)?;
+				let password = content.trim_end_matches(|c| c == '\r' || c == '\n');
+				if password.is_empty() {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"the interface password file is empty",
+					));
+				}
+				let salt = SaltString::generate(&mut OsRng);
+				let hash = Argon2::default()
+					.hash_password(password.as_bytes(), &salt)
+					.expect("unable to hash the interface password");
+				Some(hash.to_string())
+			}
+		};
+		Ok(Self {
+			password_hash,
+			sessions: Mutex::new(HashMap::new()),
+			session_timeout: Duration::from_secs(
+				config
+					.interface_session_timeout
+					.unwrap_or(SESSION_TIMEOUT_DEFAULT)
+					* 60,
+			),
+		})
+	}
+
+	pub fn is_enabled(&self) -> bool { self.password_hash.is_some() }
+
+	/// Starts a new session for the owner of the node and returns its token, if
+	/// the password is right.
+	pub fn log_in(&self, password: &str) -> Option<String> {
+		let hash = PasswordHash::new(self.password_hash.as_ref()?).ok()?;
+		// Verifying the password is made slow on purpose
+		util::block_in_place(|| {
+			Argon2::default()
+				.verify_password(password.as_bytes(), &hash)
+				.ok()
+		})?;
+
+		Some(self.start_session(None, true))
+	}
+
+	/// Starts a new session for the local user, or for the owner of the node
+	/// if no user is given, and returns its token.
+	fn start_session(&self, user_id: Option<i64>, is_admin: bool) -> String {
+		let token = IdType::random(&mut OsRng).to_string();
+		self.sessions.lock().unwrap().insert(
+			token.clone(),
+			Session {
+				last_used: Instant::now(),
+				user_id,
+				is_admin,
+			},
+		);
+		token
+	}
+
+	pub fn log_out(&self, token: &str) { self.sessions.lock().unwrap().remove(token); }
+
+	/// Returns the session if it is still going on, which keeps it going for a
+	/// while longer.
+	fn check_session(&self, token: &str) -> Option<Session> {
+		let mut sessions = self.sessions.lock().unwrap();
+		let now = Instant::now();
+		sessions.retain(|_, s| now.duration_since(s.last_used) < self.session_timeout);
+		let session = sessions.get_mut(token)?;
+		session.last_used = now;
+		Some(*session)
+	}
+}
+
+/// Logs in as the local user with the given name, or as the owner of the node
+/// if no name is given. Returns the token of the new session if the password is
+/// right.
+pub async fn log_in(
+	g: &ServerGlobal, name: Option<&str>, password: &str,
+) -> db::Result<Option<String>> {
+	let name = match name {
+		Some(n) if !n.is_empty() => n,
+		_ => return Ok(g.auth.log_in(password)),
+	};
+	Ok(g
+		.base
+		.api
+		.db
+		.verify_local_user(name, password)
+		.await?
+		.map(|user| g.auth.start_session(Some(user.id), user.is_admin)))
+}
+
+pub fn router(_g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	// Local users can log in even if no password is configured
+	Router::new()
+		.route("/login", get(login).post(login_post))
+		.route("/logout", post(logout_post))
+}
+
+/// Finds the session token in either the cookie or the authorization header.
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+	if let Some(value) = headers.get(header::AUTHORIZATION) {
+		return value.to_str().ok()?.strip_prefix("Bearer ");
+	}
+	cookie_value(headers, SESSION_COOKIE)
+}
+
+/// Whether the request can be made without a session. On the user interface,
+/// only logging in and loading the static assets can. On the web interface,
+/// anything that doesn't change something can, except for the admin dashboard.
+fn is_public(is_exposed: bool, method: &Method, path: &str) -> bool {
+	if PUBLIC_PATHS.contains(&path) || path.starts_with("/static/") {
+		return true;
+	}
+	if !is_exposed {
+		return false;
+	}
+	// Other ActivityPub servers need to be able to deliver to the inboxes
+	if path.ends_with("/activity-pub/inbox") {
+		return true;
+	}
+	matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) && !path.starts_with("/admin")
+}
+
+/// Requires a session for every request that isn't public. The admin dashboard
+/// always requires a session, of the owner or of a local user that is an
+/// admin. The requests of local users are handled within their scope.
+pub async fn require_login(
+	State(g): State<Arc<ServerGlobal>>, request: Request, next: Next,
+) -> Response {
+	let session = session_token(request.headers()).and_then(|t| g.auth.check_session(t));
+	let path = request.uri().path();
+	if path.starts_with("/admin") && session.map(|s| !s.is_admin).unwrap_or(false) {
+		return error_response(403, "Only admins can access this page");
+	}
+	let user_id = session.and_then(|s| s.user_id);
+	if !g.auth.is_enabled() || session.is_some() {
+		return local_user::scope_user(user_id, next.run(request)).await;
+	}
+	if is_public(g.base.server_info.is_exposed, request.method(), path) {
+		return next.run(request).await;
+	}
+
+	if path.starts_with("/api/") {
+		api_error(401, "Log in first")
+	} else {
+		redirect("/login")
+	}
+}
+
+fn redirect(location: &str) -> Response {
+	Response::builder()
+		.status(303)
+		.header("Location", location)
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn login(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let mut context = Context::new();
+	context.insert("logged_out", &true);
+	g.render("login.html.tera", context).await
+}
+
+async fn login_post(State(g): State<Arc<ServerGlobal>>, Form(form): Form<LoginForm>) -> Response {
+	match log_in(&g, form.name.as_deref(), &form.password).await {
+		Err(e) => server_error_response(e, "Unable to verify the password"),
+		Ok(Some(token)) => Response::builder()
+			.status(303)
+			.header("Location", "/")
+			.header(
+				header::SET_COOKIE,
+				cookie(
+					&g,
+					SESSION_COOKIE,
+					&token,
+					Some(g.auth.session_timeout.as_secs()),
+				),
+			)
+			.body(Body::empty())
+			.unwrap(),
+		Ok(None) => {
+			let mut context = Context::new();
+			context.insert("logged_out", &true);
+			context.insert("error", "The name or password is wrong.");
+			g.render("login.html.tera", context).await
+		}
+	}
+}
+
+async fn logout_post(State(g): State<Arc<ServerGlobal>>, headers: HeaderMap) -> Response {
+	if let Some(token) = session_token(&headers) {
+		g.auth.log_out(token);
+	}
+	Response::builder()
+		.status(303)
+		.header("Location", "/login")
+		.header(header::SET_COOKIE, cookie(&g, SESSION_COOKIE, "", Some(0)))
+		.body(Body::empty())
+		.unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sessions() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("password");
+		std::fs::write(&path, "secret\n").unwrap();
+		let mut config = Config::default();
+		config.interface_password_file = Some(path.to_string_lossy().into_owned());
+		let auth = Auth::from_config(&config).unwrap();
+		assert!(auth.is_enabled());
+
+		assert_eq!(auth.log_in("wrong"), None);
+		let token = auth.log_in("secret").unwrap();
+		assert_eq!(auth.check_session(&token).map(|s| s.user_id), Some(None));
+		assert!(auth.check_session("unknown").is_none());
+		let user_token = auth.start_session(Some(1), false);
+		assert_eq!(auth.check_session(&user_token).map(|s| s.user_id), Some(Some(1)));
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			header::COOKIE,
+			format!("theme=dark; {}={}", SESSION_COOKIE, token)
+				.parse()
+				.unwrap(),
+		);
+		assert_eq!(session_token(&headers), Some(token.as_str()));
+
+		auth.log_out(&token);
+		assert!(auth.check_session(&token).is_none());
+	}
+
+	#[test]
+	fn test_public_paths() {
+		assert!(is_public(false, &Method::GET, "/login"));
+		assert!(is_public(false, &Method::GET, "/static/style.css"));
+		assert!(!is_public(false, &Method::GET, "/"));
+		assert!(!is_public(false, &Method::GET, "/identity/me/mnemonic"));
+		assert!(!is_public(false, &Method::GET, "/api/v1/events"));
+		assert!(!is_public(false, &Method::POST, "/activity-pub/inbox"));
+
+		assert!(is_public(true, &Method::GET, "/"));
+		assert!(is_public(true, &Method::POST, "/activity-pub/inbox"));
+		assert!(!is_public(true, &Method::POST, "/"));
+		assert!(!is_public(true, &Method::GET, "/admin"));
+	}
+}
@@ -0,0 +1,96 @@
+//! The endpoints to manage the banlist with. They respond with JSON, so that
+//! they can be used by scripts as well.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{body::*, extract::*, response::Response, routing::*};
+use serde::{Deserialize, Serialize};
+
+use super::{
+	error_response, json_response, not_found_error_response, server_error_response, ServerGlobal,
+};
+use crate::{entity::peer_ban, net::banlist::BanTarget};
+
+
+#[derive(Serialize)]
+struct BanInfo {
+	id: i64,
+	target: String,
+	reason: String,
+	created: i64,
+}
+
+#[derive(Deserialize)]
+struct BanFormData {
+	/// Either a node address, or an IP address or subnet in CIDR notation.
+	target: String,
+	reason: Option<String>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index).post(index_post))
+		.route("/:id", delete(ban_delete))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	match g.base.api.load_peer_bans().await {
+		Ok(records) => json_response(
+			&records.into_iter().map(BanInfo::from).collect::<Vec<_>>(),
+			None,
+		),
+		Err(e) => server_error_response(e, "Unable to load banlist"),
+	}
+}
+
+async fn index_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<BanFormData>,
+) -> Response {
+	let target = match BanTarget::from_str(form.target.trim()) {
+		Ok(t) => t,
+		Err(e) => return error_response(400, e),
+	};
+
+	match g
+		.base
+		.api
+		.ban_peer(&target, form.reason.as_deref().unwrap_or(""))
+		.await
+	{
+		Ok(record) => json_response(&BanInfo::from(record), None),
+		Err(e) => server_error_response(e, "Unable to ban peer"),
+	}
+}
+
+async fn ban_delete(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	match g.base.api.unban_peer(id).await {
+		Ok(true) => Response::builder()
+			.status(204)
+			.body(Body::empty())
+			.unwrap(),
+		Ok(false) => not_found_error_response("Ban not found"),
+		Err(e) => server_error_response(e, "Unable to lift ban"),
+	}
+}
+
+
+impl From<peer_ban::Model> for BanInfo {
+	fn from(other: peer_ban::Model) -> Self {
+		let target = match (other.node_address, other.subnet) {
+			(Some(address), _) => address.to_string(),
+			(None, Some(subnet)) => subnet,
+			(None, None) => String::new(),
+		};
+		Self {
+			id: other.id,
+			target,
+			reason: other.reason,
+			created: other.created,
+		}
+	}
+}
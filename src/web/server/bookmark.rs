@@ -0,0 +1,54 @@
+//! The page that lists the objects that have been bookmarked. Objects are
+//! bookmarked from their own page, see the `object` module of `actor`.
+
+use std::sync::Arc;
+
+use axum::{extract::*, response::Response, routing::*};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{server_error_response, translate_special_mime_types_for_object, ServerGlobal};
+
+
+const PAGE_SIZE: u64 = 20;
+
+
+#[derive(Deserialize)]
+struct BookmarksQuery {
+	page: Option<u64>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new().route("/", get(index))
+}
+
+async fn index(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<BookmarksQuery>,
+) -> Response {
+	let page = query.page.unwrap_or(0);
+	let mut bookmarks = match g
+		.base
+		.api
+		.load_bookmarks(&g.base.server_info.url_base, PAGE_SIZE, page * PAGE_SIZE)
+		.await
+	{
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load bookmarks"),
+	};
+	for bookmark in &mut bookmarks {
+		if let Some(object) = &mut bookmark.object {
+			translate_special_mime_types_for_object(object);
+		}
+	}
+
+	let mut context = Context::new();
+	context.insert("has_next_page", &(bookmarks.len() as u64 == PAGE_SIZE));
+	context.insert("bookmarks", &bookmarks);
+	context.insert("page", &page);
+	g.render("bookmarks.html.tera", context).await
+}
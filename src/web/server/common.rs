@@ -3,20 +3,44 @@ use std::{
 	sync::Arc,
 };
 
-use axum::{body::Body, extract::Multipart, response::Response};
+use axum::{
+	body::Body,
+	extract::Multipart,
+	http::{header, HeaderMap},
+	response::Response,
+};
+use base64::prelude::*;
 use log::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use super::IdType;
 use crate::{
-	core::{ActorAddress, FileData},
-	web::Global,
+	api::{ByteRange, PossibleFileStream},
+	core::{ActorAddress, CompressionType, FileData},
+	identity::Signer,
+	web::{
+		info::{preview_post_info, FileInfo, ObjectInfo, PostMessageInfo},
+		Global,
+	},
 };
 
 
-pub async fn parse_post_message(mut form: Multipart) -> Result<(String, Vec<FileData>), Response> {
+/// A form that consists of nothing more than an optional idempotency key.
+#[derive(Deserialize)]
+pub struct IdempotentForm {
+	pub idempotency_key: Option<String>,
+}
+
+
+/// Parses the form of a new post, which consists of the message, its
+/// attachments and optionally an idempotency key. Attachments can also be
+/// given by the ID of an upload that has been completed.
+pub async fn parse_post_message(
+	g: &Global, mut form: Multipart,
+) -> Result<(String, Vec<FileData>, Option<String>), Response> {
 	let mut message = String::new();
 	let mut attachments = Vec::new();
+	let mut idempotency_key = None;
 
 	// Collect the form fields
 	while let Some(field) = form.next_field().await.unwrap() {
@@ -43,57 +67,106 @@ pub async fn parse_post_message(mut form: Multipart) -> Result<(String, Vec<File
 				} else {
 					warn!("Ignoring attachement due to missing content type.");
 				},
+			"uploads" => {
+				let data = field.bytes().await.unwrap();
+				let id = String::from_utf8_lossy(&data);
+				match g.api.db.load_upload_data(&id).await {
+					Ok(Some(attachment)) => attachments.push(attachment),
+					Ok(None) => return Err(error_response(400, "An upload hasn't been completed")),
+					Err(e) => return Err(server_error_response(e, "unable to load upload")),
+				}
+			}
+			"idempotency_key" => {
+				let data = field.bytes().await.unwrap();
+				idempotency_key = Some(String::from_utf8_lossy(&data).to_string());
+			}
+			// Already checked before the request got here
+			"csrf_token" => {}
 			other => warn!("Unrecognized form field: {}", other),
 		}
 	}
 
 	// TODO: Parse tags from post
-	Ok((message, attachments))
+	Ok((message, attachments, idempotency_key))
+}
+
+/// Loads the address and the signer of the identity that is published with.
+pub async fn load_active_identity(
+	g: &Global,
+) -> Result<(ActorAddress, Box<dyn Signer>), Response> {
+	match g.api.load_active_identity_key().await {
+		Ok(Some(r)) => Ok(r),
+		Ok(None) => Err(error_response(400, "Create an identity first")),
+		Err(e) => Err(server_error_response(e, "unable to load identity")),
+	}
 }
 
 pub async fn post_message(
 	g: &Arc<Global>, form: Multipart, in_reply_to: Option<(ActorAddress, IdType)>,
 ) -> Result<IdType, Response> {
 	// Parse request
-	let (message, attachments) = parse_post_message(form).await?;
-
-	// Load active identity and its private key
-	let identity = g
-		.state
-		.lock()
-		.await
-		.active_identity
-		.as_ref()
-		.unwrap()
-		.1
-		.clone();
-	let private_key = match g.api.db.perform(|c| c.fetch_my_identity(&identity)) {
-		Ok(r) =>
-			if let Some((_, pk)) = r {
-				pk
-			} else {
-				return Err(server_error_response2("unable to load identity"));
-			},
-		Err(e) => return Err(server_error_response(e, "unable to load identity")),
-	};
+	let (message, attachments, idempotency_key) = parse_post_message(g, form).await?;
+	let (identity, signer) = load_active_identity(g).await?;
 
 	// Publish post
+	let publish = g.api.publish_post(
+		&identity,
+		&*signer,
+		"text/markdown",
+		&message,
+		Vec::new(),
+		&attachments,
+		in_reply_to,
+	);
 	let hash = g
 		.api
-		.publish_post(
-			&identity,
-			&private_key,
-			"text/markdown",
-			&message,
-			Vec::new(),
-			&attachments,
-			in_reply_to,
-		)
+		.perform_idempotent(idempotency_key.as_deref(), "publish_post", publish)
 		.await
 		.map_err(|e| server_error_response(e, "unable to publish post"))?;
 	Ok(hash)
 }
 
+/// Builds the post from the form the same way as [`post_message`] does, but
+/// without signing or publishing it, so that it can be looked at first.
+pub async fn preview_message(
+	g: &Arc<Global>, form: Multipart, in_reply_to: Option<(ActorAddress, IdType)>,
+) -> Result<ObjectInfo, Response> {
+	let (message, attachments, _) = parse_post_message(g, form).await?;
+	let identity = match g.api.active_identity().await {
+		Ok(Some((_, address))) => address,
+		Ok(None) => return Err(error_response(400, "Create an identity first")),
+		Err(e) => return Err(server_error_response(e, "unable to load identity")),
+	};
+
+	// The attachments aren't stored anywhere yet, so embed them into the page
+	let attachments = attachments
+		.into_iter()
+		.map(|file| {
+			let mime_type: String = file.mime_type.into();
+			FileInfo::new(
+				format!("data:{};base64,{}", &mime_type, BASE64_STANDARD.encode(&file.data)),
+				Some(mime_type),
+				None,
+			)
+		})
+		.collect();
+	let message = PostMessageInfo {
+		mime_type: "text/markdown".to_string(),
+		body: message,
+		html: None,
+	};
+	preview_post_info(
+		&g.api.db,
+		&g.server_info.url_base,
+		&identity,
+		message,
+		attachments,
+		in_reply_to.as_ref(),
+	)
+	.await
+	.map_err(|e| server_error_response(e, "unable to preview post"))
+}
+
 pub fn json_response(json: &impl Serialize, content_type: Option<&str>) -> Response {
 	Response::builder()
 		.header("Content-Type", content_type.unwrap_or("application/json"))
@@ -103,6 +176,48 @@ pub fn json_response(json: &impl Serialize, content_type: Option<&str>) -> Respo
 		.unwrap()
 }
 
+/// Parses the `Range` header of the request, if it has one that can be served.
+pub fn range_header(headers: &HeaderMap) -> Option<ByteRange> {
+	headers
+		.get(header::RANGE)?
+		.to_str()
+		.ok()
+		.and_then(ByteRange::parse)
+}
+
+/// Serves the file that is being streamed, or the part of it that was asked
+/// for. Returns `None` if the file doesn't exist.
+pub fn file_stream_response(stream: PossibleFileStream) -> Option<Response> {
+	let response = match stream {
+		PossibleFileStream::None => return None,
+		PossibleFileStream::Stream((mime_type, compression_type, loader)) => {
+			let mut response = Response::builder().header(header::CONTENT_TYPE, mime_type);
+			match compression_type {
+				// Only files that aren't compressed can be served in parts
+				CompressionType::None => {
+					response = response.header(header::ACCEPT_RANGES, "bytes");
+				}
+				CompressionType::Brotli => {
+					response = response.header(header::CONTENT_ENCODING, "br");
+				}
+			}
+			response.body(Body::from_stream(loader))
+		}
+		PossibleFileStream::Partial((mime_type, (first, last), size, loader)) => Response::builder()
+			.status(206)
+			.header(header::CONTENT_TYPE, mime_type)
+			.header(header::ACCEPT_RANGES, "bytes")
+			.header(header::CONTENT_LENGTH, last - first + 1)
+			.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, size))
+			.body(Body::from_stream(loader)),
+		PossibleFileStream::Unsatisfiable(size) => Response::builder()
+			.status(416)
+			.header(header::CONTENT_RANGE, format!("bytes */{}", size))
+			.body(Body::empty()),
+	};
+	Some(response.unwrap())
+}
+
 pub fn error_response<S>(status_code: u16, message: S) -> Response
 where
 	S: Into<String>,
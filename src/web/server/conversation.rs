@@ -0,0 +1,118 @@
+//! The pages to have private conversations with other actors on. The messages
+//! are encrypted for the other actor, and are delivered to its own node only.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{body::Body, extract::*, response::Response, routing::*};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{
+	common::error_response, not_found_error_response, server_error_response, ServerGlobal,
+};
+use crate::core::Address;
+
+
+/// The number of latest messages that are shown of a conversation.
+const MESSAGE_LIMIT: u64 = 50;
+
+
+#[derive(Deserialize)]
+struct StartForm {
+	/// The address of the actor to start the conversation with.
+	peer: String,
+}
+
+#[derive(Deserialize)]
+struct MessageForm {
+	message: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index).post(index_post))
+		.route("/:id", get(conversation_get).post(conversation_post))
+}
+
+fn redirect(location: &str) -> Response {
+	Response::builder()
+		.status(303)
+		.header("Location", location)
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let conversations = match g
+		.base
+		.api
+		.load_conversations(&g.base.server_info.url_base)
+		.await
+	{
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load conversations"),
+	};
+
+	let mut context = Context::new();
+	context.insert("conversations", &conversations);
+	g.render("conversations.html.tera", context).await
+}
+
+async fn index_post(State(g): State<Arc<ServerGlobal>>, Form(form): Form<StartForm>) -> Response {
+	let peer = match Address::from_str(form.peer.trim()) {
+		Ok(Address::Actor(a)) => a,
+		Ok(_) => return error_response(400, "Not an actor address"),
+		Err(e) => return error_response(400, format!("Invalid address: {}", e)),
+	};
+	let identity = match g.base.api.active_identity().await {
+		Ok(Some((_, address))) => address,
+		Ok(None) => return error_response(400, "Create an identity first"),
+		Err(e) => return server_error_response(e, "Unable to load identity"),
+	};
+	if peer == identity {
+		return error_response(400, "Unable to start a conversation with yourself");
+	}
+
+	match g.base.api.start_conversation(&identity, &peer).await {
+		Ok(id) => redirect(&format!("/conversation/{}", id)),
+		Err(e) => server_error_response(e, "Unable to start conversation"),
+	}
+}
+
+async fn conversation_get(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	let (conversation, messages) = match g
+		.base
+		.api
+		.load_conversation(&g.base.server_info.url_base, id, MESSAGE_LIMIT)
+		.await
+	{
+		Ok(Some(r)) => r,
+		Ok(None) => return not_found_error_response("Conversation not found"),
+		Err(e) => return server_error_response(e, "Unable to load conversation"),
+	};
+
+	let mut context = Context::new();
+	context.insert("conversation", &conversation);
+	context.insert("messages", &messages);
+	g.render("conversation.html.tera", context).await
+}
+
+async fn conversation_post(
+	State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>, Form(form): Form<MessageForm>,
+) -> Response {
+	let message = form.message.trim();
+	if message.is_empty() {
+		return redirect(&format!("/conversation/{}", id));
+	}
+
+	match g.base.api.send_direct_message(id, message).await {
+		Ok(Some(_)) => redirect(&format!("/conversation/{}", id)),
+		Ok(None) => not_found_error_response("Conversation not found"),
+		Err(e) => server_error_response(e, "Unable to send message"),
+	}
+}
@@ -0,0 +1,219 @@
+//! Protection against other websites submitting forms to the interfaces.
+//!
+//! Every browser gets a random token in a cookie, which other websites can't
+//! read. The pages put that token in their forms, and any form that is posted
+//! without it is refused. Scripts send the token along in the `X-CSRF-Token`
+//! header instead.
+//!
+//! Only POST requests with a content type that a form can have need the token.
+//! Browsers don't let other websites send any other request without asking
+//! permission first, which is never given.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+	body::{self, Body},
+	extract::{FromRequest, Multipart, Request, State},
+	http::{header, HeaderMap, Method},
+	middleware::Next,
+	response::Response,
+	Form,
+};
+use rand::rngs::OsRng;
+
+use super::{common::error_response, rate_limit, ServerGlobal};
+use crate::common::IdType;
+
+
+const CSRF_COOKIE: &str = "stonenet_csrf";
+const CSRF_FIELD: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+
+
+tokio::task_local! {
+	/// The token of the request that is being handled.
+	static CSRF_TOKEN: String;
+}
+
+
+/// The token that needs to be put in the forms of the page that is being
+/// rendered.
+pub fn current_token() -> String { CSRF_TOKEN.try_with(|t| t.clone()).unwrap_or_default() }
+
+/// Formats a cookie that only the interface itself is able to read, and that
+/// browsers don't send along with requests that come from other websites.
+pub fn cookie(g: &ServerGlobal, name: &str, value: &str, max_age: Option<u64>) -> String {
+	let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", name, value);
+	if let Some(max_age) = max_age {
+		cookie += &format!("; Max-Age={}", max_age);
+	}
+	if g.base.server_info.url_base.starts_with("https://") {
+		cookie += "; Secure";
+	}
+	cookie
+}
+
+pub fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+	headers
+		.get_all(header::COOKIE)
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(';'))
+		.find_map(|cookie| {
+			let (n, value) = cookie.trim().split_once('=')?;
+			if n == name {
+				Some(value)
+			} else {
+				None
+			}
+		})
+}
+
+/// Refuses forms that don't contain the token of the browser, and gives the
+/// browser a token if it doesn't have one yet.
+pub async fn protect(State(g): State<Arc<ServerGlobal>>, request: Request, next: Next) -> Response {
+	let existing = cookie_value(request.headers(), CSRF_COOKIE).map(|t| t.to_string());
+	let request = if needs_token(&request) {
+		// The form is read up to the same size that the request may have
+		let limit = rate_limit::request_size_limit(&g.base.config);
+		let (submitted, request) = match find_submitted_token(request, limit).await {
+			Ok(r) => r,
+			Err(response) => return response,
+		};
+		if existing.is_none() || submitted != existing {
+			return error_response(
+				403,
+				"The form is missing its security token, reload the page and try again",
+			);
+		}
+		request
+	} else {
+		request
+	};
+
+	let token = existing
+		.clone()
+		.unwrap_or_else(|| IdType::random(&mut OsRng).to_string());
+	let mut response = CSRF_TOKEN.scope(token.clone(), next.run(request)).await;
+	if existing.is_none() {
+		if let Ok(value) = cookie(&g, CSRF_COOKIE, &token, None).parse() {
+			response.headers_mut().append(header::SET_COOKIE, value);
+		}
+	}
+	response
+}
+
+fn needs_token(request: &Request) -> bool {
+	if request.method() != Method::POST {
+		return false;
+	}
+	match request.headers().get(header::CONTENT_TYPE) {
+		None => true,
+		Some(value) => {
+			let content_type = value.to_str().unwrap_or("").to_ascii_lowercase();
+			content_type.starts_with("application/x-www-form-urlencoded")
+				|| content_type.starts_with("multipart/form-data")
+				|| content_type.starts_with("text/plain")
+		}
+	}
+}
+
+/// Finds the token in the header or in the form, and gives back the request
+/// as it was. Forms bigger than `limit` bytes are refused.
+async fn find_submitted_token(
+	request: Request, limit: usize,
+) -> Result<(Option<String>, Request), Response> {
+	if let Some(value) = request.headers().get(CSRF_HEADER) {
+		let token = value.to_str().ok().map(|t| t.to_string());
+		return Ok((token, request));
+	}
+
+	let (parts, body) = request.into_parts();
+	let bytes = match body::to_bytes(body, limit).await {
+		Ok(b) => b,
+		Err(_) => return Err(error_response(413, "The form is too large")),
+	};
+	let content_type = parts
+		.headers
+		.get(header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.unwrap_or("")
+		.to_string();
+	let copy = Request::builder()
+		.method(Method::POST)
+		.header(header::CONTENT_TYPE, &content_type)
+		.body(Body::from(bytes.clone()))
+		.unwrap();
+
+	let token = if content_type.starts_with("multipart/form-data") {
+		match Multipart::from_request(copy, &()).await {
+			Ok(mut form) => {
+				let mut token = None;
+				while let Ok(Some(field)) = form.next_field().await {
+					if field.name() == Some(CSRF_FIELD) {
+						token = field.text().await.ok();
+						break;
+					}
+				}
+				token
+			}
+			Err(_) => None,
+		}
+	} else {
+		match Form::<HashMap<String, String>>::from_request(copy, &()).await {
+			Ok(Form(mut fields)) => fields.remove(CSRF_FIELD),
+			Err(_) => None,
+		}
+	};
+	Ok((token, Request::from_parts(parts, Body::from(bytes))))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_find_submitted_token() {
+		let request = Request::builder()
+			.method(Method::POST)
+			.header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+			.body(Body::from("message=hello&csrf_token=abc"))
+			.unwrap();
+		assert!(needs_token(&request));
+		let (token, request) = find_submitted_token(request, 1024).await.unwrap();
+		assert_eq!(token.as_deref(), Some("abc"));
+		// The form can still be read afterwards
+		let Form(fields) = Form::<HashMap<String, String>>::from_request(request, &())
+			.await
+			.unwrap();
+		assert_eq!(fields.get("message").map(|m| m.as_str()), Some("hello"));
+
+		let request = Request::builder()
+			.method(Method::POST)
+			.header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+			.body(Body::from(concat!(
+				"--X\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n",
+				"abc\r\n--X--\r\n",
+			)))
+			.unwrap();
+		let (token, _) = find_submitted_token(request, 1024).await.unwrap();
+		assert_eq!(token.as_deref(), Some("abc"));
+
+		// Forms over the configured limit are refused
+		let request = Request::builder()
+			.method(Method::POST)
+			.header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+			.body(Body::from("message=hello&csrf_token=abc"))
+			.unwrap();
+		let response = find_submitted_token(request, 16).await.unwrap_err();
+		assert_eq!(response.status(), 413);
+
+		let request = Request::builder()
+			.method(Method::POST)
+			.header(header::CONTENT_TYPE, "application/json")
+			.body(Body::from("{}"))
+			.unwrap();
+		assert!(!needs_token(&request));
+	}
+}
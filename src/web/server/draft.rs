@@ -0,0 +1,201 @@
+//! The pages to save posts as drafts, to edit them later on, and to publish
+//! them eventually, either right away or at a scheduled time. Drafts are
+//! created from the post form of the home page, or of an object that is being
+//! replied to.
+
+use std::sync::Arc;
+
+use axum::{body::Body, extract::*, response::Response, routing::*};
+use chrono::{Local, NaiveDateTime};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{
+	common::{error_response, load_active_identity, parse_post_message},
+	not_found_error_response, server_error_response, ServerGlobal,
+};
+use crate::{
+	common::IdType,
+	core::ActorAddress,
+	db::PersistenceHandle,
+	web::info::object_url,
+};
+
+
+#[derive(Deserialize)]
+struct DraftQuery {
+	/// Publishes the draft after saving it.
+	publish: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ScheduleForm {
+	/// The local date and time, as given by a `datetime-local` input.
+	publish_at: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index))
+		.route("/:id", get(draft_get).post(draft_post))
+		.route("/:id/attachment/:hash", get(attachment_get))
+		.route("/:id/attachment/:hash/remove", post(attachment_remove))
+		.route("/:id/delete", post(draft_delete))
+		.route("/:id/schedule", post(draft_schedule))
+		.route("/:id/unschedule", post(draft_unschedule))
+}
+
+/// Saves the post form as a new draft, and redirects to its page.
+pub async fn save_new_draft(
+	g: &ServerGlobal, form: Multipart, in_reply_to: Option<(ActorAddress, IdType)>,
+) -> Response {
+	let (message, attachments, _) = match parse_post_message(&g.base, form).await {
+		Ok(r) => r,
+		Err(e) => return e,
+	};
+	match g
+		.base
+		.api
+		.db
+		.create_draft(&message, &attachments, in_reply_to.as_ref())
+		.await
+	{
+		Ok(id) => redirect(&format!("/draft/{}", id)),
+		Err(e) => server_error_response(e, "Unable to save draft"),
+	}
+}
+
+fn redirect(location: &str) -> Response {
+	Response::builder()
+		.status(303)
+		.header("Location", location)
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let drafts = match g.base.api.load_drafts(&g.base.server_info.url_base).await {
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load drafts"),
+	};
+
+	let mut context = Context::new();
+	context.insert("drafts", &drafts);
+	g.render("drafts.html.tera", context).await
+}
+
+async fn draft_get(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	let draft = match g.base.api.load_draft(&g.base.server_info.url_base, id).await {
+		Ok(Some(d)) => d,
+		Ok(None) => return not_found_error_response("Draft not found"),
+		Err(e) => return server_error_response(e, "Unable to load draft"),
+	};
+
+	let mut context = Context::new();
+	context.insert("draft", &draft);
+	g.render("draft.html.tera", context).await
+}
+
+async fn draft_post(
+	State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>, Query(query): Query<DraftQuery>,
+	form: Multipart,
+) -> Response {
+	let (message, attachments, _) = match parse_post_message(&g.base, form).await {
+		Ok(r) => r,
+		Err(e) => return e,
+	};
+	match g.base.api.db.update_draft(id, &message, &attachments).await {
+		Ok(true) => {}
+		Ok(false) => return not_found_error_response("Draft not found"),
+		Err(e) => return server_error_response(e, "Unable to save draft"),
+	}
+	if !query.publish.unwrap_or(false) {
+		return redirect(&format!("/draft/{}", id));
+	}
+
+	let (identity, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+	match g.base.api.publish_draft(id, &identity, &*signer).await {
+		Ok(Some(hash)) => redirect(&object_url("", &identity, &hash)),
+		Ok(None) => not_found_error_response("Draft not found"),
+		Err(e) => server_error_response(e, "Unable to publish draft"),
+	}
+}
+
+async fn draft_delete(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	match g.base.api.db.delete_draft(id).await {
+		Ok(true) => redirect("/draft"),
+		Ok(false) => not_found_error_response("Draft not found"),
+		Err(e) => server_error_response(e, "Unable to delete draft"),
+	}
+}
+
+async fn draft_schedule(
+	State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>, Form(form): Form<ScheduleForm>,
+) -> Response {
+	let publish_at = match NaiveDateTime::parse_from_str(&form.publish_at, "%Y-%m-%dT%H:%M")
+		.ok()
+		.and_then(|t| t.and_local_timezone(Local).earliest())
+	{
+		Some(t) => t.timestamp_millis(),
+		None => return error_response(400, "Invalid publishing time"),
+	};
+	let identity = match g.base.api.active_identity().await {
+		Ok(Some((_, address))) => address,
+		Ok(None) => return error_response(400, "Create an identity first"),
+		Err(e) => return server_error_response(e, "Unable to load identity"),
+	};
+
+	match g.base.api.db.schedule_draft(id, &identity, publish_at).await {
+		Ok(true) => redirect(&format!("/draft/{}", id)),
+		Ok(false) => not_found_error_response("Draft not found"),
+		Err(e) => server_error_response(e, "Unable to schedule draft"),
+	}
+}
+
+async fn draft_unschedule(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	match g.base.api.db.unschedule_draft(id).await {
+		Ok(_) => redirect(&format!("/draft/{}", id)),
+		Err(e) => server_error_response(e, "Unable to unschedule draft"),
+	}
+}
+
+async fn attachment_get(
+	State(g): State<Arc<ServerGlobal>>, Path((id, hash)): Path<(i64, IdType)>,
+) -> Response {
+	// Only serve the files that are actually attached to the draft
+	match g.base.api.db.load_draft(id).await {
+		Ok(Some((_, file_hashes))) =>
+			if !file_hashes.contains(&hash) {
+				return not_found_error_response("Attachment not found");
+			},
+		Ok(None) => return not_found_error_response("Draft not found"),
+		Err(e) => return server_error_response(e, "Unable to load draft"),
+	}
+
+	match g.base.api.db.load_file_data(&hash).await {
+		Ok(Some(file_data)) => Response::builder()
+			.header("Content-Type", file_data.mime_type.as_str())
+			.body(Body::from(file_data.data))
+			.unwrap(),
+		Ok(None) => not_found_error_response("Attachment not found"),
+		Err(e) => server_error_response(e, "Unable to load attachment"),
+	}
+}
+
+async fn attachment_remove(
+	State(g): State<Arc<ServerGlobal>>, Path((id, hash)): Path<(i64, IdType)>,
+) -> Response {
+	match g.base.api.db.remove_draft_attachment(id, &hash).await {
+		Ok(true) => redirect(&format!("/draft/{}", id)),
+		Ok(false) => not_found_error_response("Attachment not found"),
+		Err(e) => server_error_response(e, "Unable to remove attachment"),
+	}
+}
@@ -0,0 +1,100 @@
+//! The page to follow actors by their address, which can be pasted in or
+//! scanned from a QR code. See the `qr_code` module for the follow links that
+//! lead to it.
+
+use std::sync::Arc;
+
+use axum::{body::*, extract::*, response::Response, routing::*};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{error_response, qr_code, server_error_response, ServerGlobal};
+use crate::db::SyncDepth;
+
+
+#[derive(Deserialize)]
+struct FollowQuery {
+	/// The follow link or address to fill in, which is given by the browser when
+	/// the page is opened as the handler of follow links.
+	link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FollowFormData {
+	address: String,
+	object_limit: Option<String>,
+	max_age: Option<String>,
+	idempotency_key: Option<String>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new().route("/", get(index).post(index_post))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>, Query(query): Query<FollowQuery>) -> Response {
+	let address = query
+		.link
+		.as_deref()
+		.and_then(qr_code::parse_scanned)
+		.map(|a| a.to_string());
+
+	let mut context = Context::new();
+	context.insert("address", &address);
+	context.insert("scheme", qr_code::FOLLOW_LINK_SCHEME);
+	g.render("follow.html.tera", context).await
+}
+
+async fn index_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<FollowFormData>,
+) -> Response {
+	let address = match qr_code::parse_scanned(&form.address) {
+		Some(a) => a,
+		None => return error_response(400, "Not an actor address or follow link"),
+	};
+
+	let (object_limit, max_age) = (form.object_limit.as_deref(), form.max_age.as_deref());
+	let sync_depth = match parse_sync_depth(object_limit, max_age) {
+		Ok(d) => d,
+		Err(r) => return r,
+	};
+	let follow = g.base.api.follow(&address, true, sync_depth);
+	match g
+		.base
+		.api
+		.perform_idempotent(form.idempotency_key.as_deref(), "follow", follow)
+		.await
+	{
+		Ok(true) => {}
+		Ok(false) =>
+			return error_response(404, "Unable to follow this actor: couldn't find its public key"),
+		Err(e) => return server_error_response(e, "Unable to follow this actor"),
+	}
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/actor/{}", address))
+		.body(Body::empty())
+		.unwrap()
+}
+
+/// Parses the sync depth fields of a follow form. Fields that are left empty
+/// don't limit the synchronization.
+pub(super) fn parse_sync_depth(
+	object_limit: Option<&str>, max_age: Option<&str>,
+) -> Result<SyncDepth, Response> {
+	let parse = |field: Option<&str>| match field.map(str::trim) {
+		None | Some("") => Ok(None),
+		Some(value) => match value.parse::<u64>() {
+			Ok(n) => Ok(Some(n)),
+			Err(_) => Err(error_response(400, "Invalid number of posts or days")),
+		},
+	};
+	Ok(SyncDepth {
+		object_limit: parse(object_limit)?,
+		max_age: parse(max_age)?,
+	})
+}
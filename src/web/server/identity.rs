@@ -1,25 +1,32 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use axum::{
 	body::*,
 	extract::*,
+	http::{header, HeaderMap},
 	middleware::{from_fn_with_state, Next},
 	response::Response,
 	routing::*,
 	RequestExt,
 };
+use base58::{FromBase58, ToBase58};
+use base64::prelude::*;
+use chrono::DateTime;
 use log::*;
-use sea_orm::{prelude::*, QuerySelect, QueryTrait};
+use sea_orm::prelude::*;
 use serde::{Deserialize, Serialize};
 use tera::Context;
 
 use super::{
-	not_found_error_response, server_error_response, server_error_response2, FileData, ServerGlobal,
+	error_response, markdown::render_markdown, not_found_error_response, server_error_response,
+	server_error_response2, FileData, ServerGlobal,
 };
 use crate::{
-	db::{self, Database, PersistenceHandle},
+	core::{Address, DELEGATION_SCOPE_POST, DELEGATION_SCOPE_PROFILE, DELEGATION_SCOPE_SHARE},
+	db::{self, block_list::BlockTarget, local_user, PersistenceHandle},
+	domain,
 	entity::*,
-	identity::ActorPrivateKeyV1,
+	identity::ActorPublicKeyV1,
 	web::info::find_profile_info2,
 };
 
@@ -30,6 +37,83 @@ struct IdentityData {
 	address: String,
 }
 
+#[derive(Deserialize)]
+struct AddDeviceFormData {
+	label: String,
+	key: String,
+}
+
+#[derive(Deserialize)]
+struct ExternalFormData {
+	label: String,
+	name: String,
+	key: String,
+}
+
+#[derive(Deserialize)]
+struct DevicesFormData {
+	post: Option<String>,
+	share: Option<String>,
+	profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DomainFormData {
+	domain: String,
+}
+
+#[derive(Deserialize)]
+struct FollowRequestFormData {
+	id: i64,
+	accept: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProfileQuery {
+	/// Shows what the profile would look like, instead of publishing it.
+	preview: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct BlockFormData {
+	target: String,
+	/// Blocks the target if "block", mutes it if "mute", or else unmutes and
+	/// unblocks it.
+	action: String,
+}
+
+#[derive(Serialize)]
+struct BlockData {
+	target: String,
+	/// Only set for actors on the stonenet network.
+	url: Option<String>,
+	blocked: bool,
+	created: String,
+}
+
+#[derive(Serialize)]
+struct FollowRequestData {
+	id: i64,
+	address: String,
+	url: String,
+	created: String,
+	/// Whether the request has been accepted, or `None` if it hasn't been
+	/// decided on yet.
+	accepted: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct RestoreFormData {
+	label: String,
+	address: String,
+	mnemonic: String,
+}
+
+#[derive(Deserialize)]
+struct SignFormData {
+	message: String,
+}
+
 #[derive(Deserialize)]
 struct SelectFormData {
 	identity: String,
@@ -43,9 +127,19 @@ pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
 
 	Router::new()
 		.route("/:label", get(profile_get).post(profile_post))
+		.route("/:label/mnemonic", get(mnemonic_get))
+		.route("/:label/rotate-key", post(rotate_key_post))
+		.route("/:label/devices", get(devices_get).post(devices_post))
+		.route("/:label/sign", get(sign_get).post(sign_post))
+		.route("/:label/domain", get(domain_get).post(domain_post))
+		.route("/:label/followers", get(followers_get).post(followers_post))
+		.route("/:label/blocks", get(blocks_get).post(blocks_post))
 		.route_layer(from_fn_with_state(g, identity_middleware))
 		.route("/", get(index))
 		.route("/new", get(new).post(new_post))
+		.route("/restore", get(restore).post(restore_post))
+		.route("/add-device", get(add_device).post(add_device_post))
+		.route("/new-external", get(new_external).post(new_external_post))
 		.route("/select", post(select_post))
 }
 
@@ -59,8 +153,10 @@ async fn identity_middleware(
 		.0;
 	let label = params.get("label").unwrap();
 
+	// Local users can only reach their own identities
 	let identity_opt = match identity::Entity::find()
 		.filter(identity::Column::Label.eq(label))
+		.filter(local_user::identity_condition())
 		.one(g.base.api.db.inner())
 		.await
 	{
@@ -96,37 +192,78 @@ async fn profile_get(
 	let mut context = Context::new();
 	context.insert("label", &label);
 	context.insert("profile", &profile);
+	context.insert("is_private", &identity.is_private);
 	context.insert("is_following", &true);
 	g.render("identity/profile.html.tera", context).await
 }
 
 async fn profile_post(
 	State(g): State<Arc<ServerGlobal>>, Extension(old_label): Extension<String>,
-	Extension(identity): Extension<identity::Model>, multipart: Multipart,
+	Extension(identity): Extension<identity::Model>, Query(query): Query<ProfileQuery>,
+	multipart: Multipart,
 ) -> Response {
-	let (new_label, name, avatar, wallpaper, description) = parse_identity_form(multipart).await;
+	let (
+		new_label,
+		name,
+		avatar,
+		wallpaper,
+		description,
+		followers_only,
+		is_private,
+		idempotency_key,
+	) = parse_identity_form(multipart).await;
 	if name.len() == 0 {
 		return server_error_response2("Display name can not be empty");
 	}
+	if query.preview.unwrap_or(false) {
+		return render_profile_preview(
+			&g,
+			&identity,
+			&name,
+			avatar.as_ref(),
+			wallpaper.as_ref(),
+			description.as_ref(),
+		)
+		.await;
+	}
 
-	let private_key = ActorPrivateKeyV1::from_bytes(identity.private_key.try_into().unwrap());
+	let signer = match g.base.api.db.keyring().load_signer(&identity.private_key) {
+		Ok(s) => s,
+		Err(e) => return server_error_response(e, "Unable to load private key"),
+	};
+	let update = g.base.api.update_profile(
+		&*signer,
+		identity.actor_id,
+		&old_label,
+		&new_label,
+		&name,
+		avatar,
+		wallpaper,
+		description,
+		followers_only,
+	);
 	if let Err(e) = g
 		.base
 		.api
-		.update_profile(
-			&private_key,
-			identity.actor_id,
-			&old_label,
-			&new_label,
-			&name,
-			avatar,
-			wallpaper,
-			description,
-		)
+		.perform_idempotent(idempotency_key.as_deref(), "update_profile", update)
 		.await
 	{
 		return server_error_response(e, "Unable to update profile");
 	}
+	if is_private != identity.is_private {
+		if let Err(e) = g
+			.base
+			.api
+			.db
+			.set_identity_private(identity.actor_id, is_private)
+			.await
+		{
+			return server_error_response(e, "Unable to update identity");
+		}
+	}
+	if let Err(e) = g.reload_identities().await {
+		return server_error_response(e, "Unable to load identities");
+	}
 	Response::builder()
 		.status(303)
 		.header("Location", "/identity")
@@ -134,8 +271,50 @@ async fn profile_post(
 		.unwrap()
 }
 
+/// Renders the profile the way it would appear on the page of the actor, without
+/// publishing it. The files that haven't been uploaded are taken from the
+/// current profile.
+async fn render_profile_preview(
+	g: &ServerGlobal, identity: &identity::Model, name: &str, avatar: Option<&FileData>,
+	wallpaper: Option<&FileData>, description: Option<&FileData>,
+) -> Response {
+	let profile = match find_profile_info2(
+		&g.base.api.db,
+		&g.base.server_info.url_base,
+		identity.actor_id,
+	)
+	.await
+	{
+		Ok(p) => p,
+		Err(e) => return server_error_response(e, "Unable to fetch profile"),
+	};
+	let data_url = |file: &FileData| {
+		format!("data:{};base64,{}", file.mime_type.as_str(), BASE64_STANDARD.encode(&file.data))
+	};
+
+	let avatar_url = match avatar {
+		Some(file) => Some(data_url(file)),
+		None => profile.as_ref().and_then(|p| p.actor.avatar_url.clone()),
+	};
+	let wallpaper_url = match wallpaper {
+		Some(file) => Some(data_url(file)),
+		None => profile.as_ref().and_then(|p| p.actor.wallpaper_url.clone()),
+	};
+	let description = match description {
+		Some(file) => Some(String::from_utf8_lossy(&file.data).to_string()),
+		None => profile.and_then(|p| p.description),
+	};
+
+	let mut context = Context::new();
+	context.insert("name", name);
+	context.insert("avatar_url", &avatar_url);
+	context.insert("wallpaper_url", &wallpaper_url);
+	context.insert("description", &description.map(|d| render_markdown(&d)));
+	g.render("identity/preview.html.tera", context).await
+}
+
 async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
-	let identities = match g.base.api.fetch_my_identities() {
+	let identities = match g.base.api.fetch_my_identities().await {
 		Ok(i) => i,
 		Err(e) => return server_error_response(e, "unable to fetch identities:"),
 	};
@@ -145,7 +324,7 @@ async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
 			let (label, address, ..) = i;
 			IdentityData {
 				label: label.clone(),
-				address: address.to_string(),
+				address: address.to_bech32(),
 			}
 		})
 		.collect();
@@ -167,6 +346,9 @@ async fn parse_identity_form(
 	Option<FileData>,
 	Option<FileData>,
 	Option<FileData>,
+	bool,
+	bool,
+	Option<String>,
 ) {
 	// Collect all data from the multipart post request
 	let mut label_buf = Vec::new();
@@ -176,6 +358,9 @@ async fn parse_identity_form(
 	let mut wallpaper_buf = Vec::new();
 	let mut wallpaper_mime_type: Option<String> = None;
 	let mut description_buf = Vec::new();
+	let mut followers_only = false;
+	let mut is_private = false;
+	let mut idempotency_key = None;
 	while let Some(field) = multipart.next_field().await.unwrap() {
 		let name = field.name().unwrap().to_string();
 
@@ -191,6 +376,13 @@ async fn parse_identity_form(
 				wallpaper_buf = field.bytes().await.unwrap().to_vec();
 			}
 			"description" => description_buf = field.bytes().await.unwrap().to_vec(),
+			"followers_only" => followers_only = true,
+			"is_private" => is_private = true,
+			"idempotency_key" => {
+				let data = field.bytes().await.unwrap();
+				idempotency_key = Some(String::from_utf8_lossy(&data).to_string());
+			}
+			"csrf_token" => {}
 			other => warn!("Unrecognized profile form field: {}", other),
 		}
 	}
@@ -223,11 +415,20 @@ async fn parse_identity_form(
 		None
 	};
 
-	(label, name, avatar, wallpaper, description)
+	(
+		label,
+		name,
+		avatar,
+		wallpaper,
+		description,
+		followers_only,
+		is_private,
+		idempotency_key,
+	)
 }
 
 async fn new_post(State(g): State<Arc<ServerGlobal>>, multipart: Multipart) -> Response {
-	let (label, name, avatar, wallpaper, description) = parse_identity_form(multipart).await;
+	let (label, name, avatar, wallpaper, description, ..) = parse_identity_form(multipart).await;
 
 	// Create the identity
 	match g
@@ -242,17 +443,10 @@ async fn new_post(State(g): State<Arc<ServerGlobal>>, multipart: Multipart) -> R
 		)
 		.await
 	{
-		Ok((address, _)) => {
-			g.base
-				.state
-				.lock()
-				.await
-				.identities
-				.push(super::IdentityData {
-					label,
-					address: address.to_string(),
-				});
-
+		Ok(_) => {
+			if let Err(e) = g.reload_identities().await {
+				return server_error_response(e, "Unable to load identities");
+			}
 			Response::builder()
 				.status(303)
 				.header("Location", "/identity")
@@ -263,37 +457,427 @@ async fn new_post(State(g): State<Arc<ServerGlobal>>, multipart: Multipart) -> R
 	}
 }
 
-async fn find_actor_by_label(db: &Database, label: &str) -> db::Result<Option<actor::Model>> {
-	let r = actor::Entity::find()
-		.filter(
-			actor::Column::Id.in_subquery(
-				identity::Entity::find()
-					.select_only()
-					.column(identity::Column::ActorId)
-					.filter(identity::Column::Label.eq(label))
-					.into_query(),
-			),
-		)
-		.one(db.inner())
-		.await?;
-	Ok(r)
+async fn render_new_external(g: &ServerGlobal, mut context: Context) -> Response {
+	match g.base.api.db.keyring().list_external_keys() {
+		Ok(keys) => {
+			let keys: Vec<String> = keys.into_iter().map(|k| k.to_bytes().to_base58()).collect();
+			context.insert("keys", &keys);
+		}
+		Err(e) =>
+			if !context.contains_key("error") {
+				context.insert("error", &e.to_string());
+			},
+	}
+	g.render("identity/new_external.html.tera", context).await
 }
 
-async fn select_post(
-	State(g): State<Arc<ServerGlobal>>, Form(form): Form<SelectFormData>,
+async fn new_external(State(g): State<Arc<ServerGlobal>>) -> Response {
+	render_new_external(&g, Context::new()).await
+}
+
+async fn new_external_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<ExternalFormData>,
+) -> Response {
+	let error_context = |message: String| {
+		let mut context = Context::new();
+		context.insert("label", &form.label);
+		context.insert("name", &form.name);
+		context.insert("error", &message);
+		context
+	};
+	let public_key = match form.key.from_base58() {
+		Ok(buffer) if buffer.len() == 57 =>
+			ActorPublicKeyV1::from_bytes(*array_ref![buffer, 0, 57]).unwrap(),
+		_ => return render_new_external(&g, error_context("Invalid key.".into())).await,
+	};
+
+	match g
+		.base
+		.api
+		.create_external_identity(&form.label, &form.name, &public_key)
+		.await
+	{
+		Ok(_) => {
+			if let Err(e) = g.reload_identities().await {
+				return server_error_response(e, "Unable to load identities");
+			}
+			Response::builder()
+				.status(303)
+				.header("Location", "/identity")
+				.body(Body::empty())
+				.unwrap()
+		}
+		Err(e) => match &*e {
+			db::Error::Signer(_) => render_new_external(&g, error_context(e.to_string())).await,
+			_ => server_error_response(e, "Unable to create your new identity"),
+		},
+	}
+}
+
+async fn mnemonic_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
 ) -> Response {
-	match find_actor_by_label(&g.base.api.db, &form.identity).await {
-		Err(e) => server_error_response(e, "Unable to find selected identity"),
-		Ok(resultset) =>
-			if let Some(record) = resultset {
-				g.base.state.lock().await.active_identity = Some((form.identity, record.address));
-				Response::builder()
-					.status(303)
-					.header("Location", "/")
-					.body(Body::empty())
-					.unwrap()
-			} else {
-				server_error_response2("Unable to find selected identity")
+	let mnemonic = match g.base.api.export_identity_mnemonic(&label).await {
+		Ok(m) => m,
+		Err(e) => return server_error_response(e, "Unable to load mnemonic phrase"),
+	};
+
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert(
+		"words",
+		&mnemonic.map(|m| m.split(' ').map(|w| w.to_string()).collect::<Vec<_>>()),
+	);
+	g.render("identity/mnemonic.html.tera", context).await
+}
+
+async fn rotate_key_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+) -> Response {
+	if let Err(e) = g.base.api.rotate_identity_key(&label).await {
+		return server_error_response(e, "Unable to rotate key");
+	}
+	// The old words don't work anymore, so show the new ones right away
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/identity/{}/mnemonic", label))
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn devices_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("label", &label);
+	g.render("identity/devices.html.tera", context).await
+}
+
+async fn devices_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Form(form): Form<DevicesFormData>,
+) -> Response {
+	let mut scopes = 0;
+	if form.post.is_some() {
+		scopes |= DELEGATION_SCOPE_POST;
+	}
+	if form.share.is_some() {
+		scopes |= DELEGATION_SCOPE_SHARE;
+	}
+	if form.profile.is_some() {
+		scopes |= DELEGATION_SCOPE_PROFILE;
+	}
+
+	let mut context = Context::new();
+	context.insert("label", &label);
+	match g.base.api.issue_device_key(&label, scopes).await {
+		Ok(Some(key)) => context.insert("key", &key),
+		Ok(None) => return not_found_error_response("Unknown identity"),
+		Err(e) => match &*e {
+			db::Error::NotDelegated => context.insert("error", &e.to_string()),
+			_ => return server_error_response(e, "Unable to issue device key"),
+		},
+	}
+	g.render("identity/devices.html.tera", context).await
+}
+
+async fn sign_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("label", &label);
+	g.render("identity/sign.html.tera", context).await
+}
+
+async fn sign_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Form(form): Form<SignFormData>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert("message", &form.message);
+	match g.base.api.sign_message(&label, form.message.as_bytes()).await {
+		Ok(Some((address, signature))) => {
+			context.insert("address", &address.to_string());
+			context.insert("signature", &signature.as_bytes().to_base58());
+		}
+		Ok(None) => return not_found_error_response("Unknown identity"),
+		Err(e) => match &*e {
+			db::Error::NotDelegated | db::Error::Signer(_) =>
+				context.insert("error", &e.to_string()),
+			_ => return server_error_response(e, "Unable to sign message"),
+		},
+	}
+	g.render("identity/sign.html.tera", context).await
+}
+
+async fn domain_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert("path", domain::WELL_KNOWN_PATH);
+	g.render("identity/domain.html.tera", context).await
+}
+
+async fn domain_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Form(form): Form<DomainFormData>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert("path", domain::WELL_KNOWN_PATH);
+	context.insert("domain", &form.domain);
+	match g.base.api.compose_domain_document(&label, &form.domain).await {
+		Ok(Some(document)) => {
+			let name = domain::normalize_domain_name(&form.domain).unwrap();
+			context.insert("url", &format!("https://{}{}", name, domain::WELL_KNOWN_PATH));
+			context.insert("document", &serde_json::to_string_pretty(&document).unwrap());
+		}
+		Ok(None) => return not_found_error_response("Unknown identity"),
+		Err(domain::Error::Database(e)) => match &*e {
+			db::Error::NotDelegated | db::Error::Signer(_) =>
+				context.insert("error", &e.to_string()),
+			_ => return server_error_response(e, "Unable to sign domain"),
+		},
+		Err(e) => context.insert("error", &e.to_string()),
+	}
+	g.render("identity/domain.html.tera", context).await
+}
+
+async fn followers_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Extension(identity): Extension<identity::Model>,
+) -> Response {
+	let requests = match g.base.api.db.load_follow_requests(identity.actor_id).await {
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load follow requests"),
+	};
+	let requests: Vec<_> = requests
+		.into_iter()
+		.map(|r| FollowRequestData {
+			id: r.id,
+			url: format!("/actor/{}", r.requester_address),
+			address: r.requester_address.to_string(),
+			created: match DateTime::from_timestamp_millis(r.created) {
+				Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+				None => String::new(),
 			},
+			accepted: r.accepted,
+		})
+		.collect();
+
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert("requests", &requests);
+	g.render("identity/followers.html.tera", context).await
+}
+
+async fn followers_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Extension(identity): Extension<identity::Model>, Form(form): Form<FollowRequestFormData>,
+) -> Response {
+	let signer = match g.base.api.db.keyring().load_signer(&identity.private_key) {
+		Ok(s) => s,
+		Err(e) => return server_error_response(e, "Unable to load private key"),
+	};
+	let decide = g.base.api.decide_follow_request(
+		&*signer,
+		identity.actor_id,
+		form.id,
+		form.accept.is_some(),
+	);
+	match decide.await
+	{
+		Ok(Some(_)) => {}
+		Ok(None) => return not_found_error_response("Unknown follow request"),
+		Err(e) => return server_error_response(e, "Unable to decide on follow request"),
+	}
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/identity/{}/followers", label))
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn blocks_get(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Extension(identity): Extension<identity::Model>,
+) -> Response {
+	let blocks = match g.base.api.db.load_actor_blocks(identity.actor_id).await {
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load block list"),
+	};
+	let blocks: Vec<_> = blocks
+		.into_iter()
+		.map(|b| BlockData {
+			target: b
+				.actor_address
+				.as_ref()
+				.map(|a| a.to_string())
+				.or(b.activity_pub_actor_url.clone())
+				.unwrap_or_default(),
+			url: b.actor_address.as_ref().map(|a| format!("/actor/{}", a)),
+			blocked: b.blocked,
+			created: match DateTime::from_timestamp_millis(b.created) {
+				Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+				None => String::new(),
+			},
+		})
+		.collect();
+
+	let mut context = Context::new();
+	context.insert("label", &label);
+	context.insert("blocks", &blocks);
+	g.render("identity/blocks.html.tera", context).await
+}
+
+async fn blocks_post(
+	State(g): State<Arc<ServerGlobal>>, Extension(label): Extension<String>,
+	Extension(identity): Extension<identity::Model>, Form(form): Form<BlockFormData>,
+) -> Response {
+	let target = match BlockTarget::from_str(form.target.trim()) {
+		Ok(t) => t,
+		Err(message) => return error_response(400, message),
+	};
+	let address = match actor::Entity::find_by_id(identity.actor_id)
+		.one(g.base.api.db.inner())
+		.await
+	{
+		Ok(Some(a)) => a.address,
+		Ok(None) => return not_found_error_response("Unknown identity"),
+		Err(e) => return server_error_response(e, "Unable to load identity"),
+	};
+	let result = match form.action.as_str() {
+		"mute" => g.base.api.block_actor(&address, &target, false).await,
+		"block" => g.base.api.block_actor(&address, &target, true).await,
+		_ => g.base.api.unblock_actor(&address, &target).await,
+	};
+	if let Err(e) = result {
+		return server_error_response(e, "Unable to update block list");
+	}
+	Response::builder()
+		.status(303)
+		.header("Location", format!("/identity/{}/blocks", label))
+		.body(Body::empty())
+		.unwrap()
+}
+
+async fn add_device(State(g): State<Arc<ServerGlobal>>) -> Response {
+	g.render("identity/add_device.html.tera", Context::new()).await
+}
+
+async fn add_device_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<AddDeviceFormData>,
+) -> Response {
+	let error_context = |message: String| {
+		let mut context = Context::new();
+		context.insert("label", &form.label);
+		context.insert("error", &message);
+		context
+	};
+
+	match g.base.api.add_device_identity(&form.label, &form.key).await {
+		Ok(true) => {
+			if let Err(e) = g.reload_identities().await {
+				return server_error_response(e, "Unable to load identities");
+			}
+			Response::builder()
+				.status(303)
+				.header("Location", "/identity")
+				.body(Body::empty())
+				.unwrap()
+		}
+		Ok(false) => {
+			let context = error_context("The identity couldn't be found on the network.".into());
+			g.render("identity/add_device.html.tera", context).await
+		}
+		Err(e) => match &*e {
+			db::Error::InvalidDelegation => {
+				let context = error_context(e.to_string());
+				g.render("identity/add_device.html.tera", context).await
+			}
+			_ => server_error_response(e, "Unable to add identity"),
+		},
+	}
+}
+
+async fn restore(State(g): State<Arc<ServerGlobal>>) -> Response {
+	g.render("identity/restore.html.tera", Context::new()).await
+}
+
+async fn restore_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<RestoreFormData>,
+) -> Response {
+	let error_context = |message: String| {
+		let mut context = Context::new();
+		context.insert("label", &form.label);
+		context.insert("address", &form.address);
+		context.insert("error", &message);
+		context
+	};
+	let address = match Address::from_str(form.address.trim()) {
+		Ok(Address::Actor(a)) => a,
+		Ok(_) => {
+			let context = error_context("Not an actor address.".into());
+			return g.render("identity/restore.html.tera", context).await;
+		}
+		Err(e) => {
+			let context = error_context(format!("Invalid address: {}", e));
+			return g.render("identity/restore.html.tera", context).await;
+		}
+	};
+
+	match g
+		.base
+		.api
+		.restore_identity_from_mnemonic(&form.label, &address, &form.mnemonic)
+		.await
+	{
+		Ok(true) => {
+			if let Err(e) = g.reload_identities().await {
+				return server_error_response(e, "Unable to load identities");
+			}
+			Response::builder()
+				.status(303)
+				.header("Location", "/identity")
+				.body(Body::empty())
+				.unwrap()
+		}
+		Ok(false) => {
+			let context = error_context("The identity couldn't be found on the network.".into());
+			g.render("identity/restore.html.tera", context).await
+		}
+		Err(e) => match &*e {
+			db::Error::InvalidMnemonic(_) | db::Error::MnemonicMismatch(_) => {
+				let context = error_context(e.to_string());
+				g.render("identity/restore.html.tera", context).await
+			}
+			_ => server_error_response(e, "Unable to restore identity"),
+		},
+	}
+}
+
+async fn select_post(
+	State(g): State<Arc<ServerGlobal>>, headers: HeaderMap, Form(form): Form<SelectFormData>,
+) -> Response {
+	match g.base.api.select_identity(&form.identity).await {
+		Err(e) => server_error_response(e, "Unable to select identity"),
+		Ok(None) => not_found_error_response("Unknown identity"),
+		Ok(Some(_)) => {
+			if let Err(e) = g.reload_identities().await {
+				return server_error_response(e, "Unable to load identities");
+			}
+			// Stay on the same page, so that switching is quick
+			let location = headers
+				.get(header::REFERER)
+				.and_then(|v| v.to_str().ok())
+				.unwrap_or("/");
+			Response::builder()
+				.status(303)
+				.header("Location", location)
+				.body(Body::empty())
+				.unwrap()
+		}
 	}
 }
@@ -0,0 +1,105 @@
+//! The history page, which shows the journal of the actions that have been
+//! taken on this node, and lets the ones that can be reversed be undone.
+
+use std::sync::Arc;
+
+use axum::{body::*, extract::*, response::Response, routing::*};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+
+use super::{not_found_error_response, server_error_response, ServerGlobal};
+use crate::{db::journal::JournalAction, entity::journal_entry};
+
+
+const PAGE_SIZE: u64 = 50;
+
+
+#[derive(Serialize)]
+struct JournalEntryInfo {
+	id: i64,
+	action: &'static str,
+	subject: String,
+	/// The URL of the object that was published, if any.
+	object_url: Option<String>,
+	detail: Option<String>,
+	created: String,
+	undoable: bool,
+	undone: bool,
+}
+
+#[derive(Deserialize)]
+struct JournalQuery {
+	page: Option<u64>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index))
+		.route("/:id/undo", post(entry_undo))
+}
+
+async fn index(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<JournalQuery>,
+) -> Response {
+	let page = query.page.unwrap_or(0);
+	let records = match g
+		.base
+		.api
+		.db
+		.load_journal(PAGE_SIZE, page * PAGE_SIZE)
+		.await
+	{
+		Ok(r) => r,
+		Err(e) => return server_error_response(e, "Unable to load journal"),
+	};
+	let entries: Vec<_> = records.into_iter().map(JournalEntryInfo::from).collect();
+
+	let mut context = Context::new();
+	context.insert("has_next_page", &(entries.len() as u64 == PAGE_SIZE));
+	context.insert("entries", &entries);
+	context.insert("page", &page);
+	g.render("journal.html.tera", context).await
+}
+
+async fn entry_undo(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	match g.base.api.undo_activity(id).await {
+		Ok(true) => Response::builder()
+			.status(303)
+			.header("Location", "/journal")
+			.body(Body::empty())
+			.unwrap(),
+		Ok(false) => not_found_error_response("Journal entry not found, or it can't be undone"),
+		Err(e) => server_error_response(e, "Unable to undo action"),
+	}
+}
+
+
+impl From<journal_entry::Model> for JournalEntryInfo {
+	fn from(other: journal_entry::Model) -> Self {
+		let action = JournalAction::from_u8(other.action);
+		let object_url = other
+			.object_hash
+			.as_ref()
+			.map(|hash| format!("/actor/{}/object/{}", &other.subject, hash));
+		let created = match DateTime::from_timestamp_millis(other.created) {
+			Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+			None => String::new(),
+		};
+		Self {
+			id: other.id,
+			action: action.map(|a| a.title()).unwrap_or("Unknown"),
+			subject: other.subject,
+			object_url,
+			detail: other.detail,
+			created,
+			undoable: action.map(|a| a.is_undoable()).unwrap_or(false),
+			undone: other.undone.is_some(),
+		}
+	}
+}
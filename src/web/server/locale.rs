@@ -0,0 +1,272 @@
+//! Translations of the web interface.
+//!
+//! Every language has a catalog in the `locales` directory, named after its
+//! language tag, which maps message IDs to the text in that language. Texts can
+//! have placeholders like `{name}`, which are filled in with the arguments of
+//! the same name. Templates translate messages with the `t` function:
+//! `{{ t(key="log-in") }}`. Messages that a catalog doesn't have are taken from
+//! the English one.
+//!
+//! The language is the one that the browser has chosen on the language page, or
+//! else the first one of its `Accept-Language` header that we have a catalog of.
+
+use std::{collections::HashMap, fs, io, path::Path, sync::Arc};
+
+use axum::{
+	body::Body,
+	extract::{Request, State},
+	http::{header, HeaderMap},
+	middleware::Next,
+	response::Response,
+	routing::*,
+	Form,
+};
+use serde::{Deserialize, Serialize};
+use tera::{Function, Value};
+
+use super::{
+	common::*,
+	csrf::{cookie, cookie_value},
+	ServerGlobal,
+};
+
+
+const DEFAULT_LANGUAGE: &str = "en";
+const LANGUAGE_COOKIE: &str = "stonenet_language";
+/// How long the chosen language is remembered, in seconds.
+const LANGUAGE_COOKIE_AGE: u64 = 365 * 24 * 60 * 60;
+/// The message that every catalog has with the name of its language, in that
+/// language.
+const LANGUAGE_NAME_KEY: &str = "language-name";
+
+
+tokio::task_local! {
+	/// The language of the request that is being handled.
+	static LANGUAGE: String;
+}
+
+
+pub struct Catalogs {
+	catalogs: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Serialize)]
+pub struct LanguageInfo {
+	tag: String,
+	name: String,
+}
+
+/// The `t` function of the templates.
+struct Translate {
+	catalogs: Arc<Catalogs>,
+}
+
+#[derive(Deserialize)]
+struct LanguageFormData {
+	language: String,
+}
+
+
+impl Catalogs {
+	/// Loads all catalogs in the directory.
+	pub fn load(dir: &Path) -> io::Result<Self> {
+		let mut catalogs = HashMap::new();
+		for entry in fs::read_dir(dir)? {
+			let path = entry?.path();
+			if path.extension().map(|e| e != "toml").unwrap_or(true) {
+				continue;
+			}
+			let tag = match path.file_stem() {
+				Some(s) => s.to_string_lossy().to_string(),
+				None => continue,
+			};
+			let catalog = toml::from_str(&fs::read_to_string(&path)?).map_err(|e| {
+				io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("invalid catalog {}: {}", path.display(), e),
+				)
+			})?;
+			catalogs.insert(tag, catalog);
+		}
+		Ok(Self { catalogs })
+	}
+
+	/// The languages that there are catalogs of, sorted by their tag.
+	pub fn languages(&self) -> Vec<LanguageInfo> {
+		let mut languages: Vec<_> = self
+			.catalogs
+			.iter()
+			.map(|(tag, catalog)| LanguageInfo {
+				tag: tag.clone(),
+				name: catalog
+					.get(LANGUAGE_NAME_KEY)
+					.cloned()
+					.unwrap_or_else(|| tag.clone()),
+			})
+			.collect();
+		languages.sort_by(|a, b| a.tag.cmp(&b.tag));
+		languages
+	}
+
+	/// Picks the first language of the `Accept-Language` header that there is
+	/// a catalog of. A language tag with a region is also matched to the
+	/// catalog of just the language.
+	pub fn negotiate(&self, accept_language: &str) -> Option<String> {
+		let mut ranges: Vec<(&str, f32)> = accept_language
+			.split(',')
+			.filter_map(|range| {
+				let mut parts = range.split(';');
+				let tag = parts.next()?.trim();
+				let quality = parts
+					.find_map(|p| p.trim().strip_prefix("q="))
+					.and_then(|q| q.parse().ok())
+					.unwrap_or(1.0);
+				Some((tag, quality))
+			})
+			.filter(|(tag, quality)| !tag.is_empty() && *quality > 0.0)
+			.collect();
+		// A stable sort keeps the order of ranges with the same quality
+		ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+		ranges.into_iter().find_map(|(tag, _)| {
+			let tag = tag.to_ascii_lowercase();
+			if self.catalogs.contains_key(&tag) {
+				return Some(tag);
+			}
+			let primary = tag.split('-').next()?;
+			if self.catalogs.contains_key(primary) {
+				Some(primary.to_string())
+			} else {
+				None
+			}
+		})
+	}
+
+	/// Translates the message into the language, filling in the arguments.
+	/// Returns the ID of the message if no catalog has it.
+	pub fn translate(&self, language: &str, key: &str, args: &HashMap<String, Value>) -> String {
+		let text = [language, DEFAULT_LANGUAGE]
+			.iter()
+			.find_map(|l| self.catalogs.get(*l)?.get(key));
+		let mut text = match text {
+			Some(t) => t.clone(),
+			None => return key.to_string(),
+		};
+		for (name, value) in args {
+			let value = match value {
+				Value::String(s) => s.clone(),
+				other => other.to_string(),
+			};
+			text = text.replace(&format!("{{{}}}", name), &value);
+		}
+		text
+	}
+}
+
+impl Function for Translate {
+	fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+		let key = match args.get("key") {
+			Some(Value::String(k)) => k,
+			_ => return Err(tera::Error::msg("the t function needs a key")),
+		};
+		let text = self.catalogs.translate(&current_language(), key, args);
+		Ok(Value::String(text))
+	}
+}
+
+
+/// The language of the page that is being rendered.
+pub fn current_language() -> String {
+	LANGUAGE
+		.try_with(|l| l.clone())
+		.unwrap_or_else(|_| DEFAULT_LANGUAGE.to_string())
+}
+
+/// Makes the `t` function available to the templates.
+pub fn register(tera: &mut tera::Tera, catalogs: Arc<Catalogs>) {
+	tera.register_function("t", Translate { catalogs });
+}
+
+pub fn router(_g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	Router::new().route("/", post(language_post))
+}
+
+/// Handles the request in the language that the browser prefers.
+pub async fn select_language(
+	State(g): State<Arc<ServerGlobal>>, request: Request, next: Next,
+) -> Response {
+	let language = preferred_language(&g.locales, request.headers());
+	LANGUAGE.scope(language, next.run(request)).await
+}
+
+fn preferred_language(catalogs: &Catalogs, headers: &HeaderMap) -> String {
+	if let Some(chosen) = cookie_value(headers, LANGUAGE_COOKIE) {
+		if catalogs.catalogs.contains_key(chosen) {
+			return chosen.to_string();
+		}
+	}
+	headers
+		.get(header::ACCEPT_LANGUAGE)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| catalogs.negotiate(v))
+		.unwrap_or_else(|| DEFAULT_LANGUAGE.to_string())
+}
+
+/// Remembers the chosen language, and goes back to the page it was chosen on.
+async fn language_post(
+	State(g): State<Arc<ServerGlobal>>, headers: HeaderMap, Form(form): Form<LanguageFormData>,
+) -> Response {
+	if !g.locales.catalogs.contains_key(&form.language) {
+		return error_response(400, "Unknown language");
+	}
+	// Only go back to pages of the interface itself
+	let location = headers
+		.get(header::REFERER)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|r| r.strip_prefix(&g.base.server_info.url_base))
+		.filter(|path| path.starts_with('/'))
+		.unwrap_or("/")
+		.to_string();
+	Response::builder()
+		.status(303)
+		.header("Location", location)
+		.header(
+			header::SET_COOKIE,
+			cookie(&g, LANGUAGE_COOKIE, &form.language, Some(LANGUAGE_COOKIE_AGE)),
+		)
+		.body(Body::empty())
+		.unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_catalogs() {
+		let dir = tempfile::tempdir().unwrap();
+		fs::write(
+			dir.path().join("en.toml"),
+			"language-name = \"English\"\ngreeting = \"Hello, {name}!\"\nbye = \"Bye\"\n",
+		)
+		.unwrap();
+		fs::write(
+			dir.path().join("nl.toml"),
+			"language-name = \"Nederlands\"\ngreeting = \"Hallo, {name}!\"\n",
+		)
+		.unwrap();
+		let catalogs = Catalogs::load(dir.path()).unwrap();
+		assert_eq!(catalogs.languages().len(), 2);
+
+		assert_eq!(catalogs.negotiate("nl-BE, en;q=0.8").as_deref(), Some("nl"));
+		assert_eq!(catalogs.negotiate("de, en;q=0.5, nl;q=0.7").as_deref(), Some("nl"));
+		assert_eq!(catalogs.negotiate("de, fr;q=0.5"), None);
+
+		let mut args = HashMap::new();
+		args.insert("name".to_string(), Value::String("Stonenet".to_string()));
+		assert_eq!(catalogs.translate("nl", "greeting", &args), "Hallo, Stonenet!");
+		assert_eq!(catalogs.translate("nl", "bye", &args), "Bye");
+		assert_eq!(catalogs.translate("de", "unknown", &args), "unknown");
+	}
+}
@@ -0,0 +1,76 @@
+//! Turns the messages of posts into HTML that is safe to put in a page.
+//!
+//! Markdown is rendered on the server, so that posts can be read without
+//! scripts. Anything that could run code or change the page around it is
+//! removed from the result, as is done with the HTML that comes from
+//! ActivityPub servers.
+
+use std::{borrow::Cow, collections::HashSet};
+
+use ammonia::Builder;
+use lazy_static::lazy_static;
+use pulldown_cmark::{html, Options, Parser};
+
+
+lazy_static! {
+	static ref SANITIZER: Builder<'static> = {
+		let mut builder = Builder::default();
+		builder
+			.url_schemes(HashSet::from(["http", "https", "mailto"]))
+			.link_rel(Some("noopener noreferrer nofollow"))
+			.add_tag_attributes("code", &["class"])
+			// Only the classes that mark the language of a code block are kept
+			.attribute_filter(|element, attribute, value| {
+				if element == "code" && attribute == "class" {
+					value
+						.split_whitespace()
+						.find(|c| c.starts_with("language-"))
+						.map(|c| Cow::Owned(c.to_string()))
+				} else {
+					Some(Cow::Borrowed(value))
+				}
+			});
+		builder
+	};
+}
+
+
+/// Renders the Markdown into sanitized HTML.
+pub fn render_markdown(source: &str) -> String {
+	let options = Options::ENABLE_TABLES
+		| Options::ENABLE_STRIKETHROUGH
+		| Options::ENABLE_TASKLISTS
+		| Options::ENABLE_FOOTNOTES;
+	let mut rendered = String::with_capacity(source.len() * 3 / 2);
+	html::push_html(&mut rendered, Parser::new_ext(source, options));
+	sanitize_html(&rendered)
+}
+
+/// Removes everything from the HTML that isn't safe to show.
+pub fn sanitize_html(html: &str) -> String { SANITIZER.clean(html).to_string() }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_render_markdown() {
+		let html = render_markdown(concat!(
+			"# Title\n\nSome *text* and [a link](https://example.com).\n\n",
+			"```rust\nfn main() {}\n```\n\n",
+			"<script>alert(1)</script> [click](javascript:alert(1))",
+		));
+		assert!(html.contains("<h1>Title</h1>"));
+		assert!(html.contains("<em>text</em>"));
+		assert!(html.contains(
+			"<a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">a link</a>"
+		));
+		assert!(html.contains("<code class=\"language-rust\">fn main() {}"));
+		assert!(!html.contains("<script"));
+		assert!(!html.contains("javascript:"));
+
+		let html = sanitize_html("<p class=\"position-fixed\" onclick=\"x()\">Hi</p>");
+		assert_eq!(html, "<p>Hi</p>");
+	}
+}
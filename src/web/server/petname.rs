@@ -0,0 +1,132 @@
+//! The endpoints to manage petnames with, and to resolve names into actor
+//! addresses. They respond with JSON, so that they can be used by scripts as
+//! well.
+
+use std::sync::Arc;
+
+use axum::{body::*, extract::*, response::Response, routing::*};
+use serde::{Deserialize, Serialize};
+
+use super::{
+	error_response, json_response, not_found_error_response, server_error_response, ActorAddress,
+	ServerGlobal,
+};
+use crate::{entity::petname, naming};
+
+
+#[derive(Serialize)]
+struct PetnameInfo {
+	id: i64,
+	actor_address: String,
+	name: String,
+	created: i64,
+}
+
+#[derive(Deserialize)]
+struct PetnameFormData {
+	/// The actor to give the petname to. May be a petname or DNS name itself.
+	actor: String,
+	name: String,
+}
+
+#[derive(Deserialize)]
+struct ResolveQuery {
+	name: String,
+}
+
+#[derive(Serialize)]
+struct ResolveInfo {
+	actor_address: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index).post(index_post))
+		.route("/resolve", get(resolve))
+		.route("/:id", delete(petname_delete))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	match naming::load_petnames(&g.base.api.db).await {
+		Ok(records) => json_response(
+			&records.into_iter().map(PetnameInfo::from).collect::<Vec<_>>(),
+			None,
+		),
+		Err(e) => server_error_response(e, "Unable to load petnames"),
+	}
+}
+
+async fn index_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<PetnameFormData>,
+) -> Response {
+	if let Err(message) = naming::check_petname(&form.name) {
+		return error_response(400, message);
+	}
+	let address = match resolve_name(&g, &form.actor).await {
+		Ok(a) => a,
+		Err(r) => return r,
+	};
+
+	match naming::set_petname(&g.base.api.db, &address, &form.name).await {
+		Ok(Some(record)) => json_response(&PetnameInfo::from(record), None),
+		Ok(None) => error_response(409, naming::PETNAME_TAKEN_MESSAGE),
+		Err(e) => server_error_response(e, "Unable to set petname"),
+	}
+}
+
+async fn petname_delete(State(g): State<Arc<ServerGlobal>>, Path(id): Path<i64>) -> Response {
+	match naming::remove_petname(&g.base.api.db, id).await {
+		Ok(true) => Response::builder()
+			.status(204)
+			.body(Body::empty())
+			.unwrap(),
+		Ok(false) => not_found_error_response("Petname not found"),
+		Err(e) => server_error_response(e, "Unable to remove petname"),
+	}
+}
+
+async fn resolve(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<ResolveQuery>,
+) -> Response {
+	match resolve_name(&g, &query.name).await {
+		Ok(address) => json_response(
+			&ResolveInfo {
+				actor_address: address.to_string(),
+			},
+			None,
+		),
+		Err(r) => r,
+	}
+}
+
+/// Resolves an actor address, petname or DNS name into an actor address, or
+/// responds with the reason it couldn't.
+pub async fn resolve_name(g: &ServerGlobal, name: &str) -> Result<ActorAddress, Response> {
+	// Petnames are private to the user, so they're not used by the web interface
+	// that is exposed to the public.
+	let use_petnames = !g.base.server_info.is_exposed;
+	let use_dns = g.base.config.resolve_dns_names.unwrap_or(false);
+	match naming::resolve(&g.base.api.db, name, use_petnames, use_dns).await {
+		Ok(Some(address)) => Ok(address),
+		Ok(None) => Err(not_found_error_response("No actor is known by that name")),
+		Err(naming::Error::Database(e)) => Err(server_error_response(e, "Unable to resolve name")),
+		Err(e) => Err(error_response(400, e.to_string())),
+	}
+}
+
+
+impl From<petname::Model> for PetnameInfo {
+	fn from(other: petname::Model) -> Self {
+		Self {
+			id: other.id,
+			actor_address: other.actor_address.to_string(),
+			name: other.name,
+			created: other.created,
+		}
+	}
+}
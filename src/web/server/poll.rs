@@ -0,0 +1,93 @@
+//! The page to create a new poll with.
+
+use std::sync::Arc;
+
+use axum::{body::Body, extract::*, response::Response, routing::*};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{
+	common::{error_response, load_active_identity},
+	server_error_response, ServerGlobal,
+};
+use crate::{
+	common::current_timestamp,
+	db::poll::{MAX_POLL_OPTIONS, MIN_POLL_OPTIONS},
+	web::info::object_url,
+};
+
+
+#[derive(Deserialize)]
+struct PollForm {
+	question: String,
+	/// The options, one on every line.
+	options: String,
+	multiple_choice: Option<String>,
+	/// The number of days the poll stays open.
+	days: u32,
+	idempotency_key: Option<String>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new().route("/", get(poll_get).post(poll_post))
+}
+
+async fn poll_get(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let mut context = Context::new();
+	context.insert("min_options", &MIN_POLL_OPTIONS);
+	context.insert("max_options", &MAX_POLL_OPTIONS);
+	g.render("poll.html.tera", context).await
+}
+
+async fn poll_post(State(g): State<Arc<ServerGlobal>>, Form(form): Form<PollForm>) -> Response {
+	let options: Vec<String> = form
+		.options
+		.lines()
+		.map(|l| l.trim())
+		.filter(|l| l.len() > 0)
+		.map(|l| l.to_string())
+		.collect();
+	if form.days == 0 || form.days > 365 {
+		return error_response(400, "A poll can be open for 1 to 365 days");
+	}
+	let closes = current_timestamp() + form.days as u64 * 24 * 60 * 60 * 1000;
+
+	let (identity, signer) = match load_active_identity(&g.base).await {
+		Ok(r) => r,
+		Err(response) => return response,
+	};
+	let publish = g.base.api.publish_poll(
+		&identity,
+		&*signer,
+		form.question.trim(),
+		&options,
+		form.multiple_choice.is_some(),
+		closes,
+	);
+	match g
+		.base
+		.api
+		.perform_idempotent(form.idempotency_key.as_deref(), "publish_poll", publish)
+		.await
+	{
+		Ok(Some(hash)) => Response::builder()
+			.status(303)
+			.header("Location", object_url("", &identity, &hash))
+			.body(Body::empty())
+			.unwrap(),
+		Ok(None) => error_response(
+			400,
+			format!(
+				"A poll needs a question of at most 256 bytes, and {} to {} options of at most 64 \
+				 bytes",
+				MIN_POLL_OPTIONS, MAX_POLL_OPTIONS
+			),
+		),
+		Err(e) => server_error_response(e, "Unable to publish poll"),
+	}
+}
@@ -0,0 +1,70 @@
+//! QR codes of actor addresses, so that addresses can be exchanged between
+//! phones and desktops by scanning them.
+//!
+//! Besides the plain address, a QR code can hold a follow link with the
+//! `web+stonenet` scheme. Browsers that have registered the follow page of the
+//! web interface as the handler of that scheme will open it with the link.
+
+use std::str::FromStr;
+
+use qrcode::{render::svg, EcLevel, QrCode};
+
+use crate::core::{ActorAddress, Address};
+
+
+pub const FOLLOW_LINK_SCHEME: &str = "web+stonenet";
+/// The width and height of a rendered QR code, at the least, in pixels.
+const MIN_IMAGE_SIZE: u32 = 200;
+
+
+/// The link that leads to the follow page of whoever opens it.
+pub fn follow_link(address: &ActorAddress) -> String {
+	format!("{}:follow/{}", FOLLOW_LINK_SCHEME, address)
+}
+
+/// Finds the actor address in what has been scanned or pasted, which is either
+/// the address itself or a follow link.
+pub fn parse_scanned(text: &str) -> Option<ActorAddress> {
+	let text = text.trim();
+	let address = match text.strip_prefix(FOLLOW_LINK_SCHEME) {
+		Some(rest) => rest.strip_prefix(":follow/")?,
+		None => text,
+	};
+	match Address::from_str(address) {
+		Ok(Address::Actor(a)) => Some(a),
+		_ => None,
+	}
+}
+
+/// Renders the data as a QR code in an SVG image.
+pub fn render_svg(data: &str) -> String {
+	// An actor address or follow link always fits in a QR code
+	let code = QrCode::with_error_correction_level(data, EcLevel::M)
+		.expect("data too long for QR code");
+	code.render::<svg::Color>()
+		.min_dimensions(MIN_IMAGE_SIZE, MIN_IMAGE_SIZE)
+		.build()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::IdType, test};
+
+	#[test]
+	fn test_parse_scanned() {
+		let mut rng = test::initialize_rng();
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+
+		assert_eq!(parse_scanned(&address.to_string()), Some(address.clone()));
+		assert_eq!(parse_scanned(&follow_link(&address)), Some(address.clone()));
+		assert_eq!(
+			parse_scanned(&format!("  {}\n", follow_link(&address))),
+			Some(address.clone())
+		);
+		assert_eq!(parse_scanned(&format!("web+stonenet:share/{}", address)), None);
+		assert_eq!(parse_scanned("nonsense"), None);
+		assert!(render_svg(&follow_link(&address)).contains("<svg"));
+	}
+}
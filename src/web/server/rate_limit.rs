@@ -0,0 +1,159 @@
+//! Limits on the requests that anyone can make to the web interface, so that a
+//! public node can't be kept busy by a single client.
+//!
+//! Every IP address gets a bucket of tokens, from which each request takes
+//! some. Searching, downloading files and delivering to inboxes cost more than
+//! other requests. Requests with a body bigger than the configured size are
+//! refused as well. The web interface is served behind a reverse proxy, so the
+//! IP address is taken from the `X-Forwarded-For` or `X-Real-IP` header that
+//! the proxy has set, if the request comes from the local machine.
+
+use std::{
+	net::{IpAddr, SocketAddr},
+	sync::Arc,
+};
+
+use axum::{
+	extract::{ConnectInfo, Request, State},
+	http::{header, HeaderMap},
+	middleware::Next,
+	response::Response,
+};
+
+use super::{api_v1::api_error, common::*, ServerGlobal};
+use crate::{config::Config, net::rate_limit::RateLimiter};
+
+
+const RATE_LIMIT_DEFAULT: u32 = 10;
+const RATE_BURST_DEFAULT: u32 = 50;
+/// The biggest request body that is accepted by default, in KiB.
+const REQUEST_SIZE_LIMIT_DEFAULT: u64 = 1024;
+/// The number of tokens that the expensive requests take.
+const SEARCH_COST: u32 = 5;
+const DOWNLOAD_COST: u32 = 2;
+const INBOX_COST: u32 = 2;
+
+
+pub struct WebLimits {
+	limiter: RateLimiter,
+	/// The biggest request body that is accepted, in bytes.
+	pub request_size_limit: usize,
+}
+
+
+impl WebLimits {
+	pub fn from_config(config: &Config) -> Self {
+		Self {
+			limiter: RateLimiter::with_limits(
+				config,
+				config.web_rate_limit.unwrap_or(RATE_LIMIT_DEFAULT),
+				config.web_rate_burst.unwrap_or(RATE_BURST_DEFAULT),
+			),
+			request_size_limit: request_size_limit(config),
+		}
+	}
+}
+
+
+/// The biggest request body that is accepted according to the config, in
+/// bytes.
+pub fn request_size_limit(config: &Config) -> usize {
+	(config
+		.web_request_size_limit
+		.unwrap_or(REQUEST_SIZE_LIMIT_DEFAULT)
+		* 1024) as usize
+}
+
+
+/// Refuses requests that are too big, or that are made too often by the same
+/// IP address.
+pub async fn limit(
+	State(g): State<Arc<ServerGlobal>>, ConnectInfo(peer): ConnectInfo<SocketAddr>,
+	request: Request, next: Next,
+) -> Response {
+	let limits = match &g.limits {
+		Some(l) => l,
+		None => return next.run(request).await,
+	};
+	let path = request.uri().path();
+	let is_api = path.starts_with("/api/");
+
+	let content_length = request
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.parse::<usize>().ok());
+	if content_length.map(|l| l > limits.request_size_limit).unwrap_or(false) {
+		return if is_api {
+			api_error(413, "Request too big")
+		} else {
+			error_response(413, "Request too big")
+		};
+	}
+
+	let ip = client_ip(&peer, request.headers());
+	if !limits.limiter.allow_ip(&ip, request_cost(path)) {
+		let mut response = if is_api {
+			api_error(429, "Too many requests, try again later")
+		} else {
+			error_response(429, "Too many requests, try again later")
+		};
+		response
+			.headers_mut()
+			.insert(header::RETRY_AFTER, header::HeaderValue::from_static("1"));
+		return response;
+	}
+	next.run(request).await
+}
+
+/// The IP address of whoever made the request. Only a proxy on the same
+/// machine is trusted to tell it.
+fn client_ip(peer: &SocketAddr, headers: &HeaderMap) -> IpAddr {
+	if !peer.ip().is_loopback() {
+		return peer.ip();
+	}
+	// The proxy adds the address it got the request from at the end
+	let forwarded = headers
+		.get("x-forwarded-for")
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.rsplit(',').next())
+		.or_else(|| headers.get("x-real-ip").and_then(|v| v.to_str().ok()));
+	forwarded
+		.and_then(|v| v.trim().parse().ok())
+		.unwrap_or(peer.ip())
+}
+
+fn request_cost(path: &str) -> u32 {
+	if path == "/search" {
+		SEARCH_COST
+	} else if path.ends_with("/inbox") {
+		INBOX_COST
+	} else if path.contains("/file/") || path.contains("/files/") {
+		DOWNLOAD_COST
+	} else {
+		1
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_client_ip() {
+		let mut headers = HeaderMap::new();
+		headers.insert("x-forwarded-for", "10.0.0.1, 203.0.113.7".parse().unwrap());
+		let local: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+		let remote: SocketAddr = "198.51.100.2:1234".parse().unwrap();
+		assert_eq!(client_ip(&local, &headers), "203.0.113.7".parse::<IpAddr>().unwrap());
+		// Anyone else can't pretend to be someone else
+		assert_eq!(client_ip(&remote, &headers), remote.ip());
+		assert_eq!(client_ip(&local, &HeaderMap::new()), local.ip());
+
+		assert_eq!(request_cost("/search"), SEARCH_COST);
+		assert_eq!(request_cost("/actor/abc/activity-pub/inbox"), INBOX_COST);
+		assert_eq!(request_cost("/actor/abc/file/def"), DOWNLOAD_COST);
+		assert_eq!(request_cost("/"), 1);
+	}
+}
@@ -0,0 +1,132 @@
+//! The endpoints that report some statistics about the node, in JSON.
+
+use std::sync::Arc;
+
+use axum::{extract::*, response::Response, routing::*};
+use serde::{Deserialize, Serialize};
+
+use super::{json_response, server_error_response, ServerGlobal};
+use crate::{
+	db::health::DatabaseStatus,
+	net::{
+		load::LoadStats,
+		overlay::{
+			archiver::ArchiveStats,
+			telemetry::{TelemetryReport, TelemetryStats},
+		},
+		rate_limit::RateLimitStats,
+		sstp::RelayStats,
+		stats::NetworkStats,
+	},
+};
+
+
+#[derive(Serialize)]
+struct Stats {
+	network: NetworkStats,
+	rate_limit: RateLimitStats,
+	load: LoadStats,
+	relay: RelayStats,
+	/// Only set on archive nodes.
+	archive: Option<ArchiveStats>,
+	database: DatabaseStatus,
+}
+
+#[derive(Serialize)]
+struct Telemetry {
+	enabled: bool,
+	/// Exactly what is sent to the telemetry node.
+	report: TelemetryReport,
+	/// The statistics of the reports of other nodes, if this node collects
+	/// them.
+	collected: Option<TelemetryStats>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+	/// The number of hours to go back. A day by default.
+	hours: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PeersQuery {
+	/// The number of days to go back. A week by default.
+	days: Option<u32>,
+	/// The number of nodes to list. 20 by default.
+	limit: Option<u64>,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", get(index))
+		.route("/downloads", get(downloads))
+		.route("/history", get(history))
+		.route("/peers", get(peers))
+		.route("/routing-table", get(routing_table))
+		.route("/tasks", get(tasks))
+		.route("/telemetry", get(telemetry))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let stats = Stats {
+		network: g.base.api.network_stats(),
+		rate_limit: g.base.api.rate_limit_stats(),
+		load: g.base.api.load_stats(),
+		relay: g.base.api.relay_stats().await,
+		archive: g.base.api.archive_stats(),
+		database: g.base.api.database_status(),
+	};
+	json_response(&stats, None)
+}
+
+/// Shows the progress of the files that are being downloaded.
+async fn downloads(State(g): State<Arc<ServerGlobal>>) -> Response {
+	json_response(&g.base.api.list_downloads(), None)
+}
+
+/// Shows the traffic of the node per hour.
+async fn history(
+	State(g): State<Arc<ServerGlobal>>, Query(query): Query<HistoryQuery>,
+) -> Response {
+	let hours = query.hours.unwrap_or(24);
+	match g.base.api.network_stats_history(hours).await {
+		Ok(history) => json_response(&history, None),
+		Err(e) => server_error_response(e, "Unable to load network statistics"),
+	}
+}
+
+/// Lists the nodes with which the most traffic has been exchanged.
+async fn peers(State(g): State<Arc<ServerGlobal>>, Query(query): Query<PeersQuery>) -> Response {
+	let days = query.days.unwrap_or(7);
+	let limit = query.limit.unwrap_or(20);
+	match g.base.api.top_peers_by_traffic(days, limit).await {
+		Ok(peers) => json_response(&peers, None),
+		Err(e) => server_error_response(e, "Unable to load the traffic per node"),
+	}
+}
+
+/// Lists the nodes in our routing table, bucket by bucket, to help find out
+/// why something can't be found on the network.
+async fn routing_table(State(g): State<Arc<ServerGlobal>>) -> Response {
+	json_response(&g.base.api.routing_table().await, None)
+}
+
+async fn tasks(State(g): State<Arc<ServerGlobal>>) -> Response {
+	json_response(&g.base.api.list_tasks(), None)
+}
+
+/// Shows the telemetry report of this node, so that the user can see what is
+/// being shared before opting in.
+async fn telemetry(State(g): State<Arc<ServerGlobal>>) -> Response {
+	let telemetry = Telemetry {
+		enabled: g.base.config.telemetry.unwrap_or(false),
+		report: g.base.api.telemetry_preview().await,
+		collected: g.base.api.telemetry_stats(),
+	};
+	json_response(&telemetry, None)
+}
@@ -0,0 +1,50 @@
+//! The pages that list the posts with a certain hashtag.
+
+use std::sync::Arc;
+
+use axum::{extract::*, response::Response, routing::*};
+use tera::Context;
+
+use super::{
+	server_error_response, translate_special_mime_types_for_objects, PaginationQuery,
+	ServerGlobal,
+};
+
+
+const PAGE_SIZE: u64 = 5;
+
+
+pub fn router(_: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	Router::new().route("/:name", get(tag_get))
+}
+
+async fn tag_get(
+	State(g): State<Arc<ServerGlobal>>, Path(name): Path<String>,
+	Query(query): Query<PaginationQuery>,
+) -> Response {
+	let page = query.page.unwrap_or(0);
+	// The posts of private identities can only be seen with a share link
+	let include_private = !g.base.server_info.is_exposed;
+	let mut objects = match g
+		.base
+		.api
+		.load_tagged_posts(
+			&name,
+			include_private,
+			&g.base.server_info.url_base,
+			PAGE_SIZE,
+			page * PAGE_SIZE,
+		)
+		.await
+	{
+		Ok(o) => o,
+		Err(e) => return server_error_response(e, "Unable to load tagged posts"),
+	};
+	translate_special_mime_types_for_objects(&mut objects);
+
+	let mut context = Context::new();
+	context.insert("tag", name.strip_prefix('#').unwrap_or(&name));
+	context.insert("objects", &objects);
+	context.insert("page", &page);
+	g.render("tag.html.tera", context).await
+}
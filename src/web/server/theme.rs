@@ -0,0 +1,113 @@
+//! Themes that replace the templates and static assets of the web interface,
+//! so that a node can be branded without building it again.
+//!
+//! A theme is a directory in `themes/`, with a `templates` and a `static`
+//! directory laid out the same way as the built-in ones. Only the files that
+//! a theme has are replaced, the built-in ones are used for everything else.
+
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use tera::Tera;
+
+use crate::config::Config;
+
+
+const THEMES_DIR: &str = "themes";
+
+
+/// The directory of the theme that the config selects, if any.
+pub fn theme_dir(config: &Config) -> Option<PathBuf> {
+	config.theme.as_ref().map(|name| Path::new(THEMES_DIR).join(name))
+}
+
+/// Loads the built-in templates that match the glob, and then the templates of
+/// the theme in place of the ones with the same name.
+pub fn load_templates(glob: &str, theme: Option<&Path>) -> tera::Result<Tera> {
+	let mut tera = Tera::new(glob)?;
+	if let Some(dir) = theme {
+		let dir = dir.join("templates");
+		let mut files = Vec::new();
+		if let Err(e) = find_templates(&dir, &dir, &mut files) {
+			return Err(tera::Error::msg(format!(
+				"unable to read theme templates in {}: {}",
+				dir.display(),
+				e
+			)));
+		}
+		tera.add_template_files(files)?;
+	}
+	Ok(tera)
+}
+
+/// The directory that static assets are served from first, which is the one
+/// of the theme if it has one.
+pub fn static_dir(theme: Option<&Path>) -> PathBuf {
+	match theme {
+		Some(dir) if dir.join("static").is_dir() => dir.join("static"),
+		_ => PathBuf::from("static"),
+	}
+}
+
+/// Collects the template files in the directory, named by their path relative
+/// to the root, the way Tera names them.
+fn find_templates(
+	root: &Path, dir: &Path, files: &mut Vec<(PathBuf, Option<String>)>,
+) -> io::Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			find_templates(root, &path, files)?;
+		} else if path.extension().map(|e| e == "tera").unwrap_or(false) {
+			let name = path
+				.strip_prefix(root)
+				.unwrap()
+				.components()
+				.map(|c| c.as_os_str().to_string_lossy())
+				.collect::<Vec<_>>()
+				.join("/");
+			files.push((path, Some(name)));
+		}
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use tera::Context;
+
+	use super::*;
+
+	#[test]
+	fn test_load_templates() {
+		let dir = tempfile::tempdir().unwrap();
+		let builtin = dir.path().join("builtin");
+		let theme = dir.path().join("theme");
+		fs::create_dir_all(builtin.join("admin")).unwrap();
+		fs::create_dir_all(theme.join("templates/admin")).unwrap();
+		fs::write(builtin.join("base.tera"), "<b>{% block content %}{% endblock %}</b>").unwrap();
+		fs::write(
+			builtin.join("admin/users.html.tera"),
+			r#"{% extends "base.tera" %}{% block content %}users{% endblock %}"#,
+		)
+		.unwrap();
+		fs::write(
+			theme.join("templates/base.tera"),
+			"<i>{% block content %}{% endblock %}</i>",
+		)
+		.unwrap();
+
+		let glob = format!("{}/**/*.tera", builtin.display());
+		let tera = load_templates(&glob, Some(&theme)).unwrap();
+		let html = tera.render("admin/users.html.tera", &Context::new()).unwrap();
+		assert_eq!(html, "<i>users</i>");
+
+		let tera = load_templates(&glob, None).unwrap();
+		let html = tera.render("admin/users.html.tera", &Context::new()).unwrap();
+		assert_eq!(html, "<b>users</b>");
+		assert_eq!(static_dir(Some(&theme)), PathBuf::from("static"));
+	}
+}
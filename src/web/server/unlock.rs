@@ -0,0 +1,47 @@
+//! The page to unlock the private keys with, once they have been locked after
+//! not being used for a while.
+
+use std::sync::Arc;
+
+use axum::{body::*, extract::*, response::Response, routing::*};
+use serde::Deserialize;
+use tera::Context;
+
+use super::{server_error_response, ServerGlobal};
+
+
+#[derive(Deserialize)]
+struct UnlockForm {
+	passphrase: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new().route("/", get(index).post(index_post))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	g.render("unlock.html.tera", Context::new()).await
+}
+
+async fn index_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<UnlockForm>,
+) -> Response {
+	match g.base.api.db.unlock_keys(&form.passphrase).await {
+		Ok(true) => Response::builder()
+			.status(303)
+			.header("Location", "/")
+			.body(Body::empty())
+			.unwrap(),
+		Ok(false) => {
+			let mut context = Context::new();
+			context.insert("error", "The passphrase is wrong.");
+			g.render("unlock.html.tera", context).await
+		}
+		Err(e) => server_error_response(e, "Unable to unlock the private keys"),
+	}
+}
@@ -0,0 +1,154 @@
+//! Uploading attachments in chunks, so that large files can be uploaded over
+//! connections that break now and then.
+//!
+//! An upload is started with its size and MIME type, after which its chunks
+//! are sent in order. If a chunk doesn't make it, the upload can be looked up
+//! to see where to continue from. Once it is complete, its ID is put in the
+//! post form instead of the file itself.
+
+use std::sync::Arc;
+
+use axum::{
+	body::{Body, Bytes},
+	extract::{Path, Query, State},
+	response::Response,
+	routing::{get, post},
+	Json, Router,
+};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use super::{error_response, json_response, server_error_response, ServerGlobal};
+use crate::{
+	common::IdType,
+	db::upload::{ChunkOutcome, MAX_CHUNK_SIZE, MAX_UPLOAD_SIZE},
+};
+
+
+#[derive(Deserialize)]
+struct NewUpload {
+	mime_type: String,
+	size: u64,
+}
+
+#[derive(Deserialize)]
+struct ChunkQuery {
+	offset: u64,
+}
+
+#[derive(Serialize)]
+struct UploadStatus {
+	id: String,
+	mime_type: String,
+	size: u64,
+	received: u64,
+	/// The largest chunk that can be sent at once.
+	chunk_size: usize,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new()
+		.route("/", post(upload_post))
+		.route("/:id", get(upload_get).put(upload_put).delete(upload_delete))
+}
+
+/// Starts a new upload.
+async fn upload_post(
+	State(g): State<Arc<ServerGlobal>>, Json(upload): Json<NewUpload>,
+) -> Response {
+	if upload.size == 0 || upload.size > MAX_UPLOAD_SIZE {
+		return error_response(
+			413,
+			format!("Files can be at most {} bytes large", MAX_UPLOAD_SIZE),
+		);
+	}
+	if upload.mime_type.is_empty() {
+		return error_response(400, "The MIME type is missing");
+	}
+
+	let id = IdType::random(&mut OsRng).to_string();
+	if let Err(e) = g
+		.base
+		.api
+		.db
+		.create_upload(&id, &upload.mime_type, upload.size)
+		.await
+	{
+		return server_error_response(e, "Unable to start upload");
+	}
+	json_response(
+		&UploadStatus {
+			id,
+			mime_type: upload.mime_type,
+			size: upload.size,
+			received: 0,
+			chunk_size: MAX_CHUNK_SIZE,
+		},
+		None,
+	)
+}
+
+/// Tells how far the upload has come, so that it can be resumed.
+async fn upload_get(State(g): State<Arc<ServerGlobal>>, Path(id): Path<String>) -> Response {
+	match g.base.api.db.load_upload(&id).await {
+		Ok(Some(upload)) => json_response(
+			&UploadStatus {
+				id: upload.id,
+				mime_type: upload.mime_type,
+				size: upload.size as u64,
+				received: upload.received as u64,
+				chunk_size: MAX_CHUNK_SIZE,
+			},
+			None,
+		),
+		Ok(None) => error_response(404, "Upload not found"),
+		Err(e) => server_error_response(e, "Unable to load upload"),
+	}
+}
+
+/// Stores the next chunk of the upload.
+async fn upload_put(
+	State(g): State<Arc<ServerGlobal>>, Path(id): Path<String>, Query(query): Query<ChunkQuery>,
+	chunk: Bytes,
+) -> Response {
+	if chunk.len() > MAX_CHUNK_SIZE {
+		return error_response(
+			413,
+			format!("Chunks can be at most {} bytes large", MAX_CHUNK_SIZE),
+		);
+	}
+
+	let (status, received) = match g
+		.base
+		.api
+		.db
+		.store_upload_chunk(&id, query.offset, &chunk)
+		.await
+	{
+		Ok(ChunkOutcome::Stored(received)) => (200, received),
+		// The client can continue from where the upload actually is
+		Ok(ChunkOutcome::WrongOffset(received)) => (409, received),
+		Ok(ChunkOutcome::TooLarge) =>
+			return error_response(413, "The chunk goes past the end of the file"),
+		Ok(ChunkOutcome::NotFound) => return error_response(404, "Upload not found"),
+		Err(e) => return server_error_response(e, "Unable to store chunk"),
+	};
+	Response::builder()
+		.status(status)
+		.header("Content-Type", "application/json")
+		.body(Body::from(format!("{{\"received\":{}}}", received)))
+		.unwrap()
+}
+
+async fn upload_delete(State(g): State<Arc<ServerGlobal>>, Path(id): Path<String>) -> Response {
+	match g.base.api.db.delete_upload(&id).await {
+		Ok(true) => Response::builder().status(204).body(Body::empty()).unwrap(),
+		Ok(false) => error_response(404, "Upload not found"),
+		Err(e) => server_error_response(e, "Unable to remove upload"),
+	}
+}
@@ -0,0 +1,73 @@
+//! The page on which signed messages can be checked, to see whether they were
+//! signed by the actor that they claim to be from.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{extract::*, response::Response, routing::*};
+use base58::FromBase58;
+use serde::Deserialize;
+use tera::Context;
+
+use super::{server_error_response, ServerGlobal};
+use crate::{
+	core::Address,
+	identity::ActorSignatureV1,
+};
+
+
+#[derive(Deserialize)]
+struct VerifyFormData {
+	address: String,
+	message: String,
+	signature: String,
+}
+
+
+pub fn router(g: Arc<ServerGlobal>) -> Router<Arc<ServerGlobal>> {
+	if g.base.server_info.is_exposed {
+		return Router::new();
+	}
+
+	Router::new().route("/", get(index).post(index_post))
+}
+
+async fn index(State(g): State<Arc<ServerGlobal>>) -> Response {
+	g.render("verify.html.tera", Context::new()).await
+}
+
+async fn index_post(
+	State(g): State<Arc<ServerGlobal>>, Form(form): Form<VerifyFormData>,
+) -> Response {
+	let mut context = Context::new();
+	context.insert("address", &form.address);
+	context.insert("message", &form.message);
+	context.insert("signature", &form.signature);
+
+	let address = match Address::from_str(form.address.trim()) {
+		Ok(Address::Actor(a)) => a,
+		_ => {
+			context.insert("error", "Not an actor address.");
+			return g.render("verify.html.tera", context).await;
+		}
+	};
+	let signature = match form.signature.trim().from_base58() {
+		Ok(buffer) if buffer.len() == 114 =>
+			ActorSignatureV1::from_bytes(*array_ref![buffer, 0, 114]),
+		_ => {
+			context.insert("error", "Not a signature.");
+			return g.render("verify.html.tera", context).await;
+		}
+	};
+
+	match g
+		.base
+		.api
+		.verify_message(&address, form.message.as_bytes(), &signature)
+		.await
+	{
+		Ok(Some(is_valid)) => context.insert("is_valid", &is_valid),
+		Ok(None) => context.insert("error", "The actor couldn't be found on the network."),
+		Err(e) => return server_error_response(e, "Unable to verify message"),
+	}
+	g.render("verify.html.tera", context).await
+}
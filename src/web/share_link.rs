@@ -0,0 +1,116 @@
+//! Links that give temporary read access to a non-public object.
+//!
+//! The objects of private identities are not shown on the exposed web
+//! interface. To still let someone who isn't on Stonenet read one of them, the
+//! owner of the identity can mint a share token for the object. The token is
+//! signed by the identity and carries the moment it expires at, so the exposed
+//! web interface can check it without having to remember anything, and the
+//! object becomes unreachable again once the token has expired.
+
+use base58::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	common::*,
+	core::ActorAddress,
+	identity::{ActorPublicKeyV1, ActorSignatureV1, Signer, SignerError},
+	net::binserde,
+};
+
+
+/// The longest a share token can be valid for, in days.
+pub const SHARE_TOKEN_MAX_DAYS: u32 = 365;
+
+
+#[derive(Deserialize, Serialize)]
+pub struct ShareToken {
+	/// The timestamp at which the token stops being valid, in milliseconds.
+	pub expires: u64,
+	pub signature: ActorSignatureV1,
+}
+
+#[derive(Serialize)]
+struct ShareTokenSignData<'a> {
+	actor_address: &'a ActorAddress,
+	object_hash: &'a IdType,
+	expires: u64,
+}
+
+
+impl ShareToken {
+	/// Signs a token that gives access to the object for the given number of
+	/// days.
+	pub fn mint(
+		signer: &dyn Signer, actor_address: &ActorAddress, object_hash: &IdType, days: u32,
+	) -> Result<Self, SignerError> {
+		let days = days.min(SHARE_TOKEN_MAX_DAYS) as u64;
+		let expires = current_timestamp() + days * 24 * 3600 * 1000;
+		let sign_data = ShareTokenSignData {
+			actor_address,
+			object_hash,
+			expires,
+		};
+		let signature = signer.sign(&binserde::serialize(&sign_data).unwrap())?;
+		Ok(Self { expires, signature })
+	}
+
+	pub fn from_base58(string: &str) -> Option<Self> {
+		let buffer = string.from_base58().ok()?;
+		binserde::deserialize(&buffer).ok()
+	}
+
+	pub fn to_base58(&self) -> String { binserde::serialize(self).unwrap().to_base58() }
+
+	/// Whether the token has been signed for the object by the given actor, and
+	/// hasn't expired yet.
+	pub fn verify(
+		&self, public_key: &ActorPublicKeyV1, actor_address: &ActorAddress, object_hash: &IdType,
+	) -> bool {
+		if self.expires <= current_timestamp() {
+			return false;
+		}
+		let sign_data = ShareTokenSignData {
+			actor_address,
+			object_hash,
+			expires: self.expires,
+		};
+		public_key.verify(&binserde::serialize(&sign_data).unwrap(), &self.signature)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{identity::ActorPrivateKeyV1, test};
+
+	#[test]
+	fn test_share_token() {
+		let mut rng = test::initialize_rng();
+		let private_key = ActorPrivateKeyV1::generate_with_rng(&mut rng);
+		let public_key = private_key.public();
+		let address = ActorAddress::V1(IdType::random(&mut rng));
+		let object_hash = IdType::random(&mut rng);
+
+		let token = ShareToken::mint(&private_key, &address, &object_hash, 1).unwrap();
+		let token = ShareToken::from_base58(&token.to_base58()).unwrap();
+		assert!(token.verify(&public_key, &address, &object_hash));
+
+		// The token is only valid for the object that it was minted for
+		let other_hash = IdType::random(&mut rng);
+		assert!(!token.verify(&public_key, &address, &other_hash));
+		let other_key = ActorPrivateKeyV1::generate_with_rng(&mut rng).public();
+		assert!(!token.verify(&other_key, &address, &object_hash));
+
+		// Extending the expiry date invalidates the signature
+		let extended = ShareToken {
+			expires: token.expires + 1,
+			signature: token.signature.clone(),
+		};
+		assert!(!extended.verify(&public_key, &address, &object_hash));
+
+		let expired = ShareToken::mint(&private_key, &address, &object_hash, 0).unwrap();
+		assert!(!expired.verify(&public_key, &address, &object_hash));
+		assert!(ShareToken::from_base58("invalid").is_none());
+	}
+}
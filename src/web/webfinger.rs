@@ -1,10 +1,9 @@
 use std::{borrow::Cow, str::FromStr};
 
-use axum::http::HeaderMap;
 use email_address_parser::EmailAddress;
 use lazy_static::lazy_static;
 use log::*;
-use reqwest::Url;
+use reqwest::{header::HeaderMap, Url};
 
 use super::json::{expect_string, expect_url};
 use crate::web::{Error, Result};
@@ -0,0 +1,109 @@
+// Suggests actors to mention while typing an @ in the post editor, and
+// completes the mention with the address of the chosen actor.
+(function () {
+	let list = null
+	let lastPrefix = null
+
+	// Finds the word that starts with an @ right before the caret
+	function findMention(target) {
+		if (target instanceof HTMLTextAreaElement) {
+			let text = target.value.substring(0, target.selectionStart)
+			let match = /(?:^|[^\p{L}\p{N}_-])@([\p{L}\p{N}_-]*)$/u.exec(text)
+			if (!match)
+				return null
+			return {
+				prefix: match[1],
+				replace: value => {
+					let end = target.selectionStart
+					target.setRangeText(value, end - match[1].length - 1, end, 'end')
+					target.dispatchEvent(new Event('input', { bubbles: true }))
+				},
+			}
+		}
+
+		let selection = window.getSelection()
+		let node = selection.anchorNode
+		if (!node || node.nodeType != Node.TEXT_NODE)
+			return null
+		let offset = selection.anchorOffset
+		let match = /(?:^|[^\p{L}\p{N}_-])@([\p{L}\p{N}_-]*)$/u.exec(node.textContent.substring(0, offset))
+		if (!match)
+			return null
+		return {
+			prefix: match[1],
+			replace: value => {
+				// Inserting it as typed text lets the editor notice the change
+				let range = document.createRange()
+				range.setStart(node, offset - match[1].length - 1)
+				range.setEnd(node, offset)
+				selection.removeAllRanges()
+				selection.addRange(range)
+				document.execCommand('insertText', false, value)
+			},
+		}
+	}
+
+	function hide() {
+		if (list) {
+			list.remove()
+			list = null
+		}
+		lastPrefix = null
+	}
+
+	function show(container, mention, suggestions) {
+		hide()
+		if (suggestions.length == 0)
+			return
+		list = document.createElement('div')
+		list.className = 'list-group position-absolute shadow'
+		list.style.zIndex = 1000
+		for (let suggestion of suggestions) {
+			let item = document.createElement('button')
+			item.type = 'button'
+			item.className = 'list-group-item list-group-item-action text-truncate'
+			item.textContent = suggestion.petname || suggestion.name || suggestion.address
+			let address = document.createElement('div')
+			address.className = 'small text-muted text-truncate'
+			address.textContent = suggestion.address
+			item.appendChild(address)
+			// Keep the focus in the editor, so that the caret stays where it is
+			item.addEventListener('mousedown', event => event.preventDefault())
+			item.addEventListener('click', () => {
+				mention.replace('@' + suggestion.address + ' ')
+				hide()
+			})
+			list.appendChild(item)
+		}
+		container.appendChild(list)
+	}
+
+	function update(event) {
+		let container = event.target.closest('.card-body')
+		let mention = findMention(event.target)
+		if (!container || !mention || mention.prefix.length == 0) {
+			hide()
+			return
+		}
+		if (mention.prefix == lastPrefix)
+			return
+		lastPrefix = mention.prefix
+
+		fetch('/api/v1/mentions?limit=5&prefix=' + encodeURIComponent(mention.prefix))
+			.then(response => response.ok ? response.json() : [])
+			.then(suggestions => {
+				// Only show the suggestions for what is typed now
+				if (lastPrefix == mention.prefix)
+					show(container, mention, suggestions)
+			})
+	}
+
+	for (let editor of document.querySelectorAll('#editor, textarea.default-editor')) {
+		editor.addEventListener('input', update)
+		editor.addEventListener('keydown', event => {
+			if (event.key == 'Escape')
+				hide()
+		})
+		editor.addEventListener('focusout', hide)
+	}
+})()
@@ -0,0 +1,121 @@
+// Uploads the attachments of the post form in chunks before the form is
+// submitted, so that a broken connection doesn't mean starting over. The form
+// is then submitted with the IDs of the uploads instead of the files.
+(function () {
+	const RETRY_DELAY = 1000
+	const MAX_RETRY_DELAY = 30000
+
+	function sleep(ms) {
+		return new Promise(resolve => setTimeout(resolve, ms))
+	}
+
+	// Uploads that were interrupted are remembered, so that they can even be
+	// resumed after the page has been reloaded
+	function storageKey(file) {
+		return 'upload:' + file.name + ':' + file.size + ':' + file.lastModified
+	}
+
+	async function startUpload(file) {
+		let key = storageKey(file)
+		let id = localStorage.getItem(key)
+		if (id) {
+			let response = await fetch('/upload/' + id)
+			if (response.ok)
+				return await response.json()
+			localStorage.removeItem(key)
+		}
+
+		let response = await fetch('/upload', {
+			method: 'POST',
+			headers: { 'Content-Type': 'application/json' },
+			body: JSON.stringify({
+				mime_type: file.type || 'application/octet-stream',
+				size: file.size,
+			}),
+		})
+		if (!response.ok)
+			throw new Error(await response.text())
+		let upload = await response.json()
+		localStorage.setItem(key, upload.id)
+		return upload
+	}
+
+	async function upload(file, onProgress) {
+		let status = await startUpload(file)
+		let offset = status.received
+		let delay = RETRY_DELAY
+		onProgress(offset)
+		while (offset < file.size) {
+			let chunk = file.slice(offset, offset + status.chunk_size)
+			let response = null
+			try {
+				response = await fetch('/upload/' + status.id + '?offset=' + offset, {
+					method: 'PUT',
+					headers: { 'Content-Type': 'application/octet-stream' },
+					body: chunk,
+				})
+			} catch (e) {
+				// The connection broke, so try again in a while
+			}
+
+			if (response && (response.ok || response.status == 409)) {
+				offset = (await response.json()).received
+				delay = RETRY_DELAY
+				onProgress(offset)
+			} else if (response && response.status == 404) {
+				// The upload has expired, so it has to start over
+				localStorage.removeItem(storageKey(file))
+				status = await startUpload(file)
+				offset = 0
+			} else if (response && response.status < 500) {
+				throw new Error(await response.text())
+			} else {
+				await sleep(delay)
+				delay = Math.min(delay * 2, MAX_RETRY_DELAY)
+			}
+		}
+		return status.id
+	}
+
+	async function uploadAll(form, input, submitter) {
+		let files = Array.from(input.files)
+		let total = files.reduce((sum, file) => sum + file.size, 0)
+		let buttons = form.querySelectorAll('button[type=submit]')
+		buttons.forEach(button => button.disabled = true)
+		let progress = document.createElement('progress')
+		progress.className = 'w-100'
+		progress.max = total
+		progress.value = 0
+		input.after(progress)
+
+		try {
+			let done = 0
+			for (let file of files) {
+				let id = await upload(file, sent => progress.value = done + sent)
+				done += file.size
+				let hidden = document.createElement('input')
+				hidden.type = 'hidden'
+				hidden.name = 'uploads'
+				hidden.value = id
+				form.appendChild(hidden)
+			}
+			input.value = ''
+		} catch (e) {
+			alert('Unable to upload the attachments: ' + e.message)
+			return
+		} finally {
+			progress.remove()
+			buttons.forEach(button => button.disabled = false)
+		}
+		form.requestSubmit(submitter)
+	}
+
+	document.addEventListener('submit', event => {
+		let form = event.target
+		let input = form.querySelector('input[type=file][name=attachments]')
+		if (!input || input.files.length == 0)
+			return
+		event.preventDefault()
+		uploadAll(form, input, event.submitter)
+	})
+})()
@@ -0,0 +1,94 @@
+//! Checks that a node running with the low-memory profile stays within the
+//! memory target that is documented in doc/LOW_MEMORY, and that the profile
+//! actually lowers the limits on its caches and buffers.
+
+#![cfg(target_os = "linux")]
+
+use std::{
+	fs,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use stonenetd::{config::Config, test::*};
+
+
+/// The maximum resident set size of the test process, in KiB. This is a bit
+/// above the 23.2 MiB that a debug build has been measured to take.
+const RSS_TARGET: u64 = 28 * 1024;
+
+
+/// Reads the resident set size of this process, in KiB.
+fn resident_set_size() -> u64 {
+	let status = fs::read_to_string("/proc/self/status").expect("unable to read process status");
+	let line = status
+		.lines()
+		.find(|l| l.starts_with("VmRSS:"))
+		.expect("no VmRSS in process status");
+	line.split_whitespace()
+		.nth(1)
+		.and_then(|s| s.parse().ok())
+		.expect("invalid VmRSS in process status")
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_low_memory_rss() {
+	let mut rng = initialize_rng();
+	let stop_flag = Arc::new(AtomicBool::new(false));
+
+	let mut config1 = Config::default();
+	config1.ipv4_address = Some("127.0.0.1".to_string());
+	config1.ipv4_udp_port = Some(40000);
+	config1.ipv4_udp_openness = Some("bidirectional".to_string());
+	config1.low_memory = Some(true);
+	let mut config2 = config1.clone();
+	config2.ipv4_udp_port = Some(40001);
+	config2.bootstrap_nodes = vec!["127.0.0.1:40000".to_string()];
+
+	let node1 = load_test_node(stop_flag.clone(), &mut rng, &config1, "memory1").await;
+	let node2 = load_test_node(stop_flag.clone(), &mut rng, &config2, "memory2").await;
+	tokio::time::sleep(Duration::from_secs(1)).await;
+
+	let rss = resident_set_size();
+	stop_flag.store(true, Ordering::Relaxed);
+	node2.close().await;
+	node1.close().await;
+	assert!(
+		rss <= RSS_TARGET,
+		"resident set size of {} KiB exceeds the target of {} KiB",
+		rss,
+		RSS_TARGET
+	);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_low_memory_limits() {
+	let mut rng = initialize_rng();
+	let stop_flag = Arc::new(AtomicBool::new(false));
+
+	let mut config1 = Config::default();
+	config1.ipv4_address = Some("127.0.0.1".to_string());
+	config1.ipv4_udp_port = Some(40002);
+	config1.ipv4_udp_openness = Some("bidirectional".to_string());
+	let mut config2 = config1.clone();
+	config2.ipv4_udp_port = Some(40003);
+	config2.low_memory = Some(true);
+
+	let node1 = load_test_node(stop_flag.clone(), &mut rng, &config1, "memory_limits1").await;
+	let node2 = load_test_node(stop_flag.clone(), &mut rng, &config2, "memory_limits2").await;
+	let default = node1.memory_limits().await;
+	let low = node2.memory_limits().await;
+	stop_flag.store(true, Ordering::Relaxed);
+	node2.close().await;
+	node1.close().await;
+
+	assert!(low.sessions < default.sessions);
+	assert!(low.window_packets < default.window_packets);
+	assert!(low.attached_nodes < default.attached_nodes);
+	assert!(low.cached_values < default.cached_values);
+	assert!(low.bucket_replacements < default.bucket_replacements);
+	assert!(low.rate_limited_peers < default.rate_limited_peers);
+}
@@ -1,14 +1,23 @@
-use std::sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
+use std::{
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Duration,
 };
 
 use log::*;
 use rand::RngCore;
 use stonenetd::{
-	api::Api, config::Config, core::*, db::PersistenceHandle, net::*, test::*,
+	api::Api,
+	config::Config,
+	core::*,
+	db::{PersistenceHandle, SyncDepth},
+	net::*,
+	test::*,
 	web::info::ObjectPayloadInfo,
 };
+use tokio::time::{sleep, timeout};
 
 
 #[ctor::ctor]
@@ -38,6 +47,426 @@ async fn test_data_synchronizations_relaying() {
 	.await;
 }
 
+/// Lets a unidirectional node fetch the data of another unidirectional node,
+/// which is only possible through a relay node.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_relay_path() {
+	let mut rng = initialize_rng();
+
+	// The relay node and the bootstrap node need to outlive the other two
+	let stop_flag = Arc::new(AtomicBool::new(false));
+	let end_stop_flag = Arc::new(AtomicBool::new(false));
+	// The openness of the nodes is simulated, so that the publisher and the
+	// fetcher really can't reach each other directly
+	let bootstrap_config = simulated_config("bidirectional", Vec::new());
+	let bootstrap_node =
+		load_test_node(stop_flag.clone(), &mut rng, &bootstrap_config, "bootstrap").await;
+	let bootstrap_nodes = vec![bootstrap_address(&bootstrap_node)];
+	let mut relay_config = simulated_config("bidirectional", bootstrap_nodes.clone());
+	relay_config.relay_node = Some(true);
+	let publisher_config = simulated_config("unidirectional", bootstrap_nodes.clone());
+	let fetcher_config = simulated_config("unidirectional", bootstrap_nodes);
+	let relay_node = load_test_node(stop_flag.clone(), &mut rng, &relay_config, "relay").await;
+	let publisher = load_test_node(
+		end_stop_flag.clone(),
+		&mut rng,
+		&publisher_config,
+		"publisher",
+	)
+	.await;
+	let fetcher = load_test_node(end_stop_flag.clone(), &mut rng, &fetcher_config, "fetcher").await;
+
+	let relay_node_info = fetcher
+		.node
+		.find_node(relay_node.node.node_id())
+		.await
+		.expect("relay node not found");
+	fetcher.node.remember_relay_node(&relay_node_info).await;
+	let relay_stats_before = relay_node.relay_stats().await;
+
+	// Publish a profile with an avatar that spans many packets, and a post
+	let mut avatar_file_data = FileData {
+		mime_type: "image/png".into(),
+		data: vec![0u8; 100000],
+	};
+	rng.fill_bytes(&mut avatar_file_data.data);
+	let (actor_id, actor_info) = publisher
+		.create_identity("relayed", "Relayed", Some(&avatar_file_data), None, None)
+		.await
+		.expect("unable to create identity");
+	let _ = publisher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("unable to join actor network");
+	let (_, signer) = publisher
+		.fetch_my_identity(&actor_id)
+		.await
+		.expect("unable to load identity")
+		.expect("missing identity");
+	let message = "Sent through a relay";
+	let post_hash = publisher
+		.publish_post(
+			&actor_id,
+			&*signer,
+			"text/plain",
+			message,
+			Vec::new(),
+			&[],
+			None,
+		)
+		.await
+		.expect("unable to publish post");
+
+	// Everything needs to arrive intact, which means that the packets could be
+	// decrypted at the other end of the relay
+	let profile_info = fetcher
+		.find_profile_info("", &actor_id)
+		.await
+		.expect("unable to fetch profile object from node")
+		.expect("got empty profile object");
+	assert_eq!(profile_info.actor.name, "Relayed");
+	let actor_node = fetcher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("actor node not found");
+	let profile_object = fetcher
+		.db
+		.load_profile(&actor_id)
+		.await
+		.expect("unable to load profile")
+		.expect("unable to load profile");
+	let avatar = fetcher
+		.find_file_data(
+			Some(&actor_node),
+			&profile_object.avatar.expect("missing avatar ID"),
+		)
+		.await
+		.expect("unable to get avatar file")
+		.expect("unable to get avatar file");
+	assert_eq!(avatar.mime_type, avatar_file_data.mime_type);
+	assert_eq!(
+		avatar.data, avatar_file_data.data,
+		"avatar file data got corrupted"
+	);
+
+	let actor_found = fetcher
+		.follow(&actor_id, false, SyncDepth::default())
+		.await
+		.expect("unable to follow publisher");
+	assert!(actor_found, "actor not found");
+	actor_node.wait_for_synchronization().await;
+	let home_feed = fetcher
+		.load_home_feed(10, 0)
+		.await
+		.expect("unable to load home feed");
+	let post = home_feed
+		.iter()
+		.find(|o| o.id == post_hash.to_string())
+		.expect("post didn't come through");
+	match &post.payload {
+		ObjectPayloadInfo::Post(post) => assert_eq!(
+			post.message.clone().expect("message is missing").body,
+			message
+		),
+		_ => panic!("object is not a post"),
+	}
+
+	// The relay node should have done the work
+	let relay_stats = relay_node.relay_stats().await;
+	assert!(relay_stats.opened_sessions > relay_stats_before.opened_sessions);
+	assert!(relay_stats.relayed_packets > relay_stats_before.relayed_packets);
+	assert!(
+		relay_stats.relayed_bytes >= relay_stats_before.relayed_bytes + 100000,
+		"not all of the avatar has been relayed"
+	);
+	assert!(relay_stats.active_sessions > 0);
+
+	// Once both ends are gone, the relay node should clean up their sessions
+	end_stop_flag.store(true, Ordering::Relaxed);
+	publisher.close().await;
+	fetcher.close().await;
+	let cleaned_up = timeout(Duration::from_secs(30), async {
+		while relay_node.relay_stats().await.active_sessions > 0 {
+			sleep(Duration::from_millis(100)).await;
+		}
+	})
+	.await;
+	assert!(cleaned_up.is_ok(), "relayed sessions have not been cleaned up");
+
+	stop_flag.store(true, Ordering::Relaxed);
+	relay_node.close().await;
+	bootstrap_node.close().await;
+}
+
+/// Lets a node that starts following an actor only after it has rotated its
+/// key, synchronize the objects that are signed with the new key.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_synchronization_across_key_rotation() {
+	let mut rng = initialize_rng();
+
+	let stop_flag = Arc::new(AtomicBool::new(false));
+	let mut bootstrap_config = Config::default();
+	bootstrap_config.ipv4_address = Some("127.0.0.1".to_string());
+	bootstrap_config.ipv4_udp_port = Some(40000);
+	bootstrap_config.ipv4_udp_openness = Some("bidirectional".to_string());
+	let bootstrap_nodes = vec!["127.0.0.1:40000".to_string()];
+	let mut publisher_config = Config::default();
+	publisher_config.ipv4_address = Some("127.0.0.1".to_string());
+	publisher_config.ipv4_udp_port = Some(40001);
+	publisher_config.ipv4_udp_openness = Some("bidirectional".to_string());
+	publisher_config.bootstrap_nodes = bootstrap_nodes.clone();
+	let mut fetcher_config = Config::default();
+	fetcher_config.ipv4_address = Some("127.0.0.1".to_string());
+	fetcher_config.ipv4_udp_port = Some(40002);
+	fetcher_config.ipv4_udp_openness = Some("bidirectional".to_string());
+	fetcher_config.bootstrap_nodes = bootstrap_nodes;
+	let bootstrap_node = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&bootstrap_config,
+		"rotation_bootstrap",
+	)
+	.await;
+	let publisher = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&publisher_config,
+		"rotation_publisher",
+	)
+	.await;
+	let fetcher = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&fetcher_config,
+		"rotation_fetcher",
+	)
+	.await;
+
+	// Publish a post with the original key, and one with the new key
+	let (actor_id, actor_info) = publisher
+		.create_identity("rotated", "Rotated", None, None, None)
+		.await
+		.expect("unable to create identity");
+	let _ = publisher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("unable to join actor network");
+	let (_, old_signer) = publisher
+		.fetch_my_identity(&actor_id)
+		.await
+		.expect("unable to load identity")
+		.expect("missing identity");
+	let old_message = "Signed with the original key";
+	let old_post_hash = publisher
+		.publish_post(
+			&actor_id,
+			&*old_signer,
+			"text/plain",
+			old_message,
+			Vec::new(),
+			&[],
+			None,
+		)
+		.await
+		.expect("unable to publish post");
+	publisher
+		.rotate_identity_key("rotated")
+		.await
+		.expect("unable to rotate key")
+		.expect("missing identity");
+	let (_, new_signer) = publisher
+		.fetch_my_identity(&actor_id)
+		.await
+		.expect("unable to load identity")
+		.expect("missing identity");
+	assert_ne!(new_signer.public(), actor_info.public_key);
+	let new_message = "Signed with the new key";
+	let new_post_hash = publisher
+		.publish_post(
+			&actor_id,
+			&*new_signer,
+			"text/plain",
+			new_message,
+			Vec::new(),
+			&[],
+			None,
+		)
+		.await
+		.expect("unable to publish post");
+
+	// The fetcher only knows the original key, so it needs to learn about the
+	// rotation before it can accept the head object
+	let actor_node = fetcher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("actor node not found");
+	let actor_found = fetcher
+		.follow(&actor_id, false, SyncDepth::default())
+		.await
+		.expect("unable to follow publisher");
+	assert!(actor_found, "actor not found");
+	actor_node.wait_for_synchronization().await;
+
+	let home_feed = fetcher
+		.load_home_feed(10, 0)
+		.await
+		.expect("unable to load home feed");
+	let expected = [
+		(&old_post_hash, old_message),
+		(&new_post_hash, new_message),
+	];
+	for (hash, message) in expected {
+		let post = home_feed
+			.iter()
+			.find(|o| o.id == hash.to_string())
+			.expect("post didn't come through");
+		match &post.payload {
+			ObjectPayloadInfo::Post(post) => assert_eq!(
+				post.message.clone().expect("message is missing").body,
+				message
+			),
+			_ => panic!("object is not a post"),
+		}
+	}
+
+	stop_flag.store(true, Ordering::Relaxed);
+	publisher.close().await;
+	fetcher.close().await;
+	bootstrap_node.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_catching_up() {
+	let mut rng = initialize_rng();
+
+	let stop_flag = Arc::new(AtomicBool::new(false));
+	let bootstrap_config = simulated_config("bidirectional", Vec::new());
+	let bootstrap_node = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&bootstrap_config,
+		"catch_up_bootstrap",
+	)
+	.await;
+	let bootstrap_nodes = vec![bootstrap_address(&bootstrap_node)];
+	let publisher_config = simulated_config("bidirectional", bootstrap_nodes.clone());
+	let fetcher_config = simulated_config("bidirectional", bootstrap_nodes);
+	let publisher = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&publisher_config,
+		"catch_up_publisher",
+	)
+	.await;
+	let fetcher = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&fetcher_config,
+		"catch_up_fetcher",
+	)
+	.await;
+
+	// The publisher has a history of posts before the fetcher shows up
+	let (actor_id, actor_info) = publisher
+		.create_identity("catch-up", "Catch up", None, None, None)
+		.await
+		.expect("unable to create identity");
+	let _ = publisher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("unable to join actor network");
+	let (_, signer) = publisher
+		.fetch_my_identity(&actor_id)
+		.await
+		.expect("unable to load identity")
+		.expect("missing identity");
+	let mut post_hashes = Vec::new();
+	for i in 0..100 {
+		let hash = publisher
+			.publish_post(
+				&actor_id,
+				&*signer,
+				"text/plain",
+				&format!("Post number {}", i),
+				Vec::new(),
+				&[],
+				None,
+			)
+			.await
+			.expect("unable to publish post");
+		post_hashes.push(hash);
+	}
+
+	let actor_node = fetcher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("actor node not found");
+	let actor_found = fetcher
+		.follow(&actor_id, false, SyncDepth::default())
+		.await
+		.expect("unable to follow publisher");
+	assert!(actor_found, "actor not found");
+	actor_node.wait_for_synchronization().await;
+
+	let home_feed = fetcher
+		.load_home_feed(200, 0)
+		.await
+		.expect("unable to load home feed");
+	for hash in &post_hashes {
+		assert!(
+			home_feed.iter().any(|o| o.id == hash.to_string()),
+			"post {} didn't come through",
+			hash
+		);
+	}
+
+	// A node that follows the actor with a limited sync depth, only gets the
+	// latest posts, also from the log sync
+	let limited_fetcher = load_test_node(
+		stop_flag.clone(),
+		&mut rng,
+		&fetcher_config,
+		"catch_up_limited_fetcher",
+	)
+	.await;
+	let actor_node = limited_fetcher
+		.node
+		.join_actor_network(&actor_id, &actor_info)
+		.await
+		.expect("actor node not found");
+	let sync_depth = SyncDepth {
+		object_limit: Some(10),
+		max_age: None,
+	};
+	let actor_found = limited_fetcher
+		.follow(&actor_id, false, sync_depth)
+		.await
+		.expect("unable to follow publisher");
+	assert!(actor_found, "actor not found");
+	actor_node.wait_for_synchronization().await;
+
+	let home_feed = limited_fetcher
+		.load_home_feed(200, 0)
+		.await
+		.expect("unable to load home feed");
+	for (i, hash) in post_hashes.iter().enumerate() {
+		let came_through = home_feed.iter().any(|o| o.id == hash.to_string());
+		assert_eq!(came_through, i >= 90, "post number {}", i);
+	}
+
+	stop_flag.store(true, Ordering::Relaxed);
+	publisher.close().await;
+	fetcher.close().await;
+	limited_fetcher.close().await;
+	bootstrap_node.close().await;
+}
+
 #[cfg(test)]
 async fn test_data_synchronization(
 	next_port: &mut u16, node1_openness: Openness, node2_openness: Openness,
@@ -121,14 +550,15 @@ Hoi ik ben Kees!
 	let first_message = "First post!!!";
 	let second_message = "Second post!!!";
 	let third_message = "Third post!!!";
-	let (_, private_key) = node1
+	let (_, signer) = node1
 		.fetch_my_identity(&actor_id)
+		.await
 		.expect("unable to load identity")
 		.expect("missing identity");
 	let first_post_hash = node1
 		.publish_post(
 			&actor_id,
-			&private_key,
+			&*signer,
 			"text/plain",
 			first_message,
 			vec!["first".to_string()],
@@ -140,7 +570,7 @@ Hoi ik ben Kees!
 	let second_post_hash = node1
 		.publish_post(
 			&actor_id,
-			&private_key,
+			&*signer,
 			"text/plain",
 			second_message,
 			vec!["second".to_string()],
@@ -152,7 +582,7 @@ Hoi ik ben Kees!
 	let third_post_hash = node1
 		.publish_post(
 			&actor_id,
-			&private_key,
+			&*signer,
 			"text/plain",
 			third_message,
 			vec!["third".to_string()],
@@ -166,7 +596,7 @@ Hoi ik ben Kees!
 		object_hash: first_post_hash.clone(),
 	};
 	let _share_hash = node1
-		.publish_share(&actor_id, &private_key, &share_object)
+		.publish_share(&actor_id, &*signer, &share_object)
 		.await
 		.expect("unable to publish share object");
 
@@ -227,7 +657,7 @@ Hoi ik ben Kees!
 
 	// Download the posts
 	let actor_found = node2
-		.follow(&actor_id, false)
+		.follow(&actor_id, false, SyncDepth::default())
 		.await
 		.expect("unable to follow node 1");
 	assert!(actor_found, "actor not found");
@@ -240,7 +670,7 @@ Hoi ik ben Kees!
 	let fourth_post_hash = node1
 		.publish_post(
 			&actor_id,
-			&private_key,
+			&*signer,
 			"text/plain",
 			fourth_message,
 			vec!["fourth".to_string()],